@@ -1,58 +1,289 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
-use axum::{Router, extract::State, routing::get};
+use axum::{
+    Extension, Router,
+    extract::{Path, Query, State},
+    routing::get,
+};
 use rmcp::{
-    model::CallToolRequestParam, service::ServiceExt, transport::StreamableHttpClientTransport,
+    RoleClient,
+    model::CallToolRequestParam,
+    service::{Peer, RunningService, ServiceError, ServiceExt},
+    transport::StreamableHttpClientTransport,
 };
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
+use utoipa::ToSchema;
 
 use striem_common::prelude::*;
 
-use crate::{ApiState, alerts::fetch_alert};
+use crate::{ApiState, alerts::fetch_alert, auth::Principal, config::redact, error::ApiError};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Whether StrIEM holds a session with an action's MCP server
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    Connected,
+    /// Not connected yet, or the session was dropped; the next call connects
+    #[default]
+    Disconnected,
+    /// The last call failed; the server isn't tried again until the
+    /// retry interval has passed
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Action {
+    /// `server_alias/tool_name`
     pub id: String,
     pub title: String,
+    /// Alias of the MCP server offering the action
+    pub server: String,
+    #[serde(default)]
+    pub state: ConnectionState,
+    /// Why the server is unreachable, on the entry standing in for it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Seconds until an unreachable server is tried again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_secs: Option<u64>,
 }
 
-pub(crate) struct Mcp {
+#[derive(Default)]
+struct Status {
+    /// Tools from the last listing, cached for [`MCP_REFRESH_INTERVAL_SECS`]
+    tools: Vec<Action>,
+    refreshed: Option<Instant>,
+    connected: bool,
+    error: Option<String>,
+    retry_at: Option<Instant>,
+}
+
+/// One MCP server: a session kept open across calls, its tools, and when
+/// it may be tried again after a failure
+struct Server {
+    alias: String,
     url: String,
-    inner: RwLock<HashMap<String, Action>>,
-    last_update: RwLock<std::time::Instant>,
+    client: Mutex<Option<RunningService<RoleClient, ()>>>,
+    status: RwLock<Status>,
+}
+
+impl Server {
+    /// The open session, connecting first if there is none
+    async fn peer(&self) -> Result<Peer<RoleClient>> {
+        let mut client = self.client.lock().await;
+        if let Some(client) = client.as_ref() {
+            return Ok(client.peer().clone());
+        }
+        let transport = StreamableHttpClientTransport::from_uri(self.url.clone());
+        let session = ().serve(transport).await?;
+        let peer = session.peer().clone();
+        *client = Some(session);
+        Ok(peer)
+    }
+
+    /// Run `call` on the session within `timeout`. Unless the server
+    /// answered with an error, a failure drops the session and holds off
+    /// further calls for `retry`.
+    async fn call<T, F, Fut>(&self, timeout: Duration, retry: Duration, call: F) -> Result<T>
+    where
+        F: FnOnce(Peer<RoleClient>) -> Fut,
+        Fut: Future<Output = Result<T, ServiceError>>,
+    {
+        if let Some(wait) = self
+            .status
+            .read()
+            .await
+            .retry_at
+            .and_then(|at| at.checked_duration_since(Instant::now()))
+        {
+            anyhow::bail!(
+                "MCP server {} unavailable, retrying in {}s",
+                self.alias,
+                wait.as_secs() + 1
+            );
+        }
+
+        let result = tokio::time::timeout(timeout, async {
+            let peer = self.peer().await?;
+            Ok::<_, anyhow::Error>(call(peer).await)
+        })
+        .await;
+
+        let error = match result {
+            Ok(Ok(Ok(value))) => {
+                let mut status = self.status.write().await;
+                status.connected = true;
+                status.error = None;
+                status.retry_at = None;
+                return Ok(value);
+            }
+            Ok(Ok(Err(ServiceError::McpError(e)))) => {
+                return Err(anyhow::anyhow!("MCP server {}: {}", self.alias, e.message));
+            }
+            Ok(Ok(Err(e))) => e.into(),
+            Ok(Err(e)) => e,
+            Err(_) => anyhow::anyhow!("no response within {}s", timeout.as_secs()),
+        };
+
+        tracing::warn!(
+            "MCP server {} unreachable, retrying in {}s: {}",
+            self.alias,
+            retry.as_secs(),
+            error
+        );
+        self.client.lock().await.take();
+        let mut status = self.status.write().await;
+        *status = Status {
+            error: Some(error.to_string()),
+            retry_at: Some(Instant::now() + retry),
+            ..Default::default()
+        };
+        Err(error)
+    }
+
+    async fn refresh_if_stale(&self, timeout: Duration, retry: Duration) {
+        let stale = self
+            .status
+            .read()
+            .await
+            .refreshed
+            .is_none_or(|at| at.elapsed().as_secs() >= MCP_REFRESH_INTERVAL_SECS);
+        if !stale {
+            return;
+        }
+        if let Ok(result) = self
+            .call(
+                timeout,
+                retry,
+                |peer| async move { peer.list_tools(None).await },
+            )
+            .await
+        {
+            let mut status = self.status.write().await;
+            status.tools = result
+                .tools
+                .into_iter()
+                .map(|tool| Action {
+                    id: format!("{}/{}", self.alias, tool.name),
+                    title: tool.description.unwrap_or_default().to_string(),
+                    server: self.alias.clone(),
+                    state: ConnectionState::Connected,
+                    error: None,
+                    retry_after_secs: None,
+                })
+                .collect();
+            status.refreshed = Some(Instant::now());
+        }
+    }
+
+    /// The server's actions, or a single entry with its error
+    async fn actions(&self) -> Vec<Action> {
+        let status = self.status.read().await;
+        match &status.error {
+            Some(error) => vec![Action {
+                id: self.alias.clone(),
+                title: String::new(),
+                server: self.alias.clone(),
+                state: ConnectionState::Failed,
+                error: Some(error.clone()),
+                retry_after_secs: status
+                    .retry_at
+                    .and_then(|at| at.checked_duration_since(Instant::now()))
+                    .map(|wait| wait.as_secs() + 1),
+            }],
+            None => {
+                let state = match status.connected {
+                    true => ConnectionState::Connected,
+                    false => ConnectionState::Disconnected,
+                };
+                status
+                    .tools
+                    .iter()
+                    .map(|action| Action {
+                        state,
+                        ..action.clone()
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Tools of every configured MCP server. Sessions are opened on first use
+/// and kept; tools are cached for [`MCP_REFRESH_INTERVAL_SECS`]. A server
+/// that fails isn't tried again for a while, and no call waits on a server
+/// longer than the timeout, so listing actions never stalls on one.
+pub(crate) struct Mcp {
+    servers: Vec<Server>,
+    timeout: Duration,
+    retry: Duration,
 }
 
 impl Mcp {
-    pub fn new(url: String) -> Self {
+    /// `(alias, url)` of each server
+    pub fn new(servers: Vec<(String, String)>) -> Self {
         Self {
-            url,
-            inner: RwLock::new(HashMap::new()),
-            last_update: RwLock::new(
-                std::time::Instant::now()
-                    - std::time::Duration::from_secs(MCP_REFRESH_INTERVAL_SECS),
-            ),
+            servers: servers
+                .into_iter()
+                .map(|(alias, url)| Server {
+                    alias,
+                    url,
+                    client: Mutex::new(None),
+                    status: RwLock::new(Status::default()),
+                })
+                .collect(),
+            timeout: Duration::from_secs(DEFAULT_MCP_TIMEOUT_SECS),
+            retry: Duration::from_secs(DEFAULT_MCP_RETRY_SECS),
         }
     }
-    pub async fn get(&self, id: &str) -> Result<Option<Action>> {
-        let last_update = self.last_update.read().await;
-        if last_update.elapsed().as_secs() > MCP_REFRESH_INTERVAL_SECS {
-            drop(last_update);
-            self.refresh().await?;
-        }
-        let inner = self.inner.read().await;
-        Ok(inner.get(id).cloned())
+
+    /// Longest any one call to a server may take, connecting included
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How long a server that failed is left alone
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    fn server(&self, id: &str) -> Option<&Server> {
+        let (alias, _) = id.split_once('/')?;
+        self.servers.iter().find(|server| server.alias == alias)
+    }
+
+    pub async fn get(&self, id: &str) -> Option<Action> {
+        let server = self.server(id)?;
+        server.refresh_if_stale(self.timeout, self.retry).await;
+        server
+            .actions()
+            .await
+            .into_iter()
+            .find(|action| action.id == id && action.error.is_none())
     }
 
-    pub async fn list(&self) -> Result<Vec<Action>> {
-        let last_update = self.last_update.read().await;
-        if last_update.elapsed().as_secs() > 300 {
-            drop(last_update);
-            self.refresh().await?;
+    /// Every server's actions, then an error entry for each unreachable one
+    pub async fn list(&self) -> Vec<Action> {
+        futures_util::future::join_all(
+            self.servers
+                .iter()
+                .map(|server| server.refresh_if_stale(self.timeout, self.retry)),
+        )
+        .await;
+
+        let mut actions = Vec::new();
+        for server in &self.servers {
+            actions.extend(server.actions().await);
         }
-        let inner = self.inner.read().await;
-        Ok(inner.values().cloned().collect())
+        actions.sort_by(|a, b| (a.error.is_some(), &a.id).cmp(&(b.error.is_some(), &b.id)));
+        actions
     }
 
     pub async fn execute(
@@ -60,123 +291,284 @@ impl Mcp {
         id: &str,
         params: serde_json::Map<String, serde_json::Value>,
     ) -> Result<()> {
-        let action = self
-            .get(id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("Action with id {} not found", id))?;
-
-        log::info!("Executing action: {:?} with params: {:?}", action, params);
-        let transport = StreamableHttpClientTransport::from_uri(self.url.clone());
+        let not_found = || anyhow::anyhow!("Action with id {} not found", id);
+        let server = self.server(id).ok_or_else(not_found)?;
+        let Some(action) = self.get(id).await else {
+            if let Some(error) = &server.status.read().await.error {
+                anyhow::bail!("MCP server {} unavailable: {}", server.alias, error);
+            }
+            return Err(not_found());
+        };
+        let tool = &action.id[server.alias.len() + 1..];
 
-        let client = ().serve(transport).await?;
-        client
-            .call_tool(CallToolRequestParam {
-                name: action.id.clone().into(),
-                arguments: Some(params),
+        tracing::info!("Executing action: {:?} with params: {:?}", action, params);
+        server
+            .call(self.timeout, self.retry, |peer| async move {
+                peer.call_tool(CallToolRequestParam {
+                    name: tool.to_string().into(),
+                    arguments: Some(params),
+                })
+                .await
             })
             .await?;
 
         Ok(())
     }
+}
 
-    async fn refresh(&self) -> Result<()> {
-        let transport = StreamableHttpClientTransport::from_uri(self.url.clone());
-
-        let client = ().serve(transport).await?;
-        let actions = client
-            .list_tools(None)
-            .await?
-            .tools
-            .into_iter()
-            .map(|tool| {
-                (
-                    tool.name.to_string(),
-                    Action {
-                        id: tool.name.to_string(),
-                        title: tool.description.unwrap_or_default().to_string(),
-                    },
-                )
-            })
-            .collect::<HashMap<_, _>>();
-        let mut inner = self.inner.write().await;
-        inner.clear();
-        inner.extend(actions);
-        drop(inner);
-        self.last_update
-            .write()
-            .await
-            .clone_from(&std::time::Instant::now());
-        Ok(())
-    }
+/// Runs of an action, as kept in the audit log
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub(crate) struct ActionAudit {
+    pub id: String,
+    pub action_id: String,
+    pub alert_id: String,
+    /// Tool arguments as sent, secrets redacted
+    #[schema(value_type = Object)]
+    pub params: serde_json::Value,
+    /// Token that ran the action, when API auth is on, or the playbook id
+    pub principal: Option<String>,
+    /// `api` or `playbook`
+    pub triggered_by: String,
+    pub executed_at: String,
+    /// `null` while the action runs, or if it never returned
+    pub success: Option<bool>,
+    pub error: Option<String>,
+    pub duration_ms: Option<u64>,
 }
 
+const DEFAULT_AUDIT_LIMIT: usize = 100;
+const MAX_AUDIT_LIMIT: usize = 1000;
+
 pub fn create_router() -> Router<ApiState> {
     axum::Router::new()
         .route("/", get(get_actions))
-        .route("/{id}", get(get_action_by_id).post(execute_action_by_id))
+        .route("/audit", get(get_action_audit))
+        // ids are `server/tool`
+        .route(
+            "/{server}/{*tool}",
+            get(get_action_by_id).post(execute_action_by_id),
+        )
 }
 
-async fn get_actions(
-    State(state): State<ApiState>,
-) -> Result<axum::Json<Vec<Action>>, (axum::http::StatusCode, String)> {
-    if let Some(actions) = &state.actions {
-        Ok(axum::Json(actions.list().await.map_err(|e| {
-            (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
-        })?))
-    } else {
-        log::error!("no actions available");
-        Ok(axum::Json(Vec::new()))
+/// Response actions offered by the MCP servers
+#[utoipa::path(
+    get,
+    path = "/api/1/actions",
+    tag = "actions",
+    responses(
+        (status = 200, description = "Available actions with their server's connection state, and an entry with `error` for each unreachable server; empty without MCP servers", body = [Action]),
+    )
+)]
+pub(crate) async fn get_actions(State(state): State<ApiState>) -> axum::Json<Vec<Action>> {
+    match &state.actions {
+        Some(actions) => axum::Json(actions.list().await),
+        None => axum::Json(Vec::new()),
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/1/actions/{server}/{tool}",
+    tag = "actions",
+    params(
+        ("server" = String, Path, description = "Alias of the action's MCP server"),
+        ("tool" = String, Path, description = "MCP tool name"),
+    ),
+    responses(
+        (status = 200, description = "The action", body = Action),
+        (status = 404, description = "No such action, or its server is unreachable", body = crate::error::ErrorBody),
+    )
+)]
 pub(crate) async fn get_action_by_id(
     State(state): State<ApiState>,
-    axum::extract::Path(id): axum::extract::Path<String>,
-) -> Result<axum::Json<Action>, (axum::http::StatusCode, String)> {
-    let mcp = state.actions.as_ref().ok_or((
-        axum::http::StatusCode::NOT_FOUND,
-        format!("Action with id {} not found", id),
-    ))?;
+    Path((server, tool)): Path<(String, String)>,
+) -> Result<axum::Json<Action>, ApiError> {
+    let id = format!("{}/{}", server, tool);
+    let mcp = state.actions.as_ref().ok_or(ApiError::NotFound(format!(
+        "Action with id {} not found",
+        id
+    )))?;
 
     mcp.get(&id)
         .await
-        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
         .map(axum::Json)
-        .ok_or((
-            axum::http::StatusCode::NOT_FOUND,
-            format!("Action with id {} not found", id),
-        ))
+        .ok_or(ApiError::NotFound(format!(
+            "Action with id {} not found",
+            id
+        )))
 }
 
+/// Run an action on a finding. The body holds the tool's arguments plus
+/// `alert_id` (and optionally `file`); the finding is passed as `data`.
+/// Every run is written to the audit log before it starts and updated when
+/// it ends; if the log can't be written the action doesn't run.
+#[utoipa::path(
+    post,
+    path = "/api/1/actions/{server}/{tool}",
+    tag = "actions",
+    params(
+        ("server" = String, Path, description = "Alias of the action's MCP server"),
+        ("tool" = String, Path, description = "MCP tool name"),
+    ),
+    request_body(content = Object, description = "Tool arguments with `alert_id`"),
+    responses(
+        (status = 200, description = "The action ran"),
+        (status = 400, description = "Missing `alert_id`", body = crate::error::ErrorBody),
+        (status = 404, description = "No such action", body = crate::error::ErrorBody),
+        (status = 500, description = "Finding lookup, action or audit log failed", body = crate::error::ErrorBody),
+        (status = 503, description = "Database not initialized, so the action can't be audited", body = crate::error::ErrorBody),
+    )
+)]
 pub(crate) async fn execute_action_by_id(
     State(state): State<ApiState>,
-    axum::extract::Path(id): axum::extract::Path<String>,
-    axum::extract::Json(mut params): axum::extract::Json<
-        serde_json::Map<String, serde_json::Value>,
-    >,
-) -> Result<axum::Json<()>, (axum::http::StatusCode, String)> {
-    let mcp = state.actions.as_ref().ok_or((
-        axum::http::StatusCode::NOT_FOUND,
-        format!("action with id {} not found", id),
-    ))?;
-
-    let alert_id = params.get("alert_id").and_then(|v| v.as_str()).ok_or((
-        axum::http::StatusCode::BAD_REQUEST,
-        "missing alert_id parameter".to_string(),
-    ))?;
-
-    log::info!("{:?}", params);
-    let file = params.get("file").and_then(|v| v.as_str());
+    principal: Option<Extension<Principal>>,
+    Path((server, tool)): Path<(String, String)>,
+    axum::extract::Json(params): axum::extract::Json<serde_json::Map<String, serde_json::Value>>,
+) -> Result<axum::Json<()>, ApiError> {
+    let id = format!("{}/{}", server, tool);
+    let mcp = state.actions.as_ref().ok_or(ApiError::NotFound(format!(
+        "action with id {} not found",
+        id
+    )))?;
+    let Some(pool) = state.db.as_ref() else {
+        return Err(ApiError::Unavailable(
+            "database not initialized; actions can't be audited".to_string(),
+        ));
+    };
 
-    let alert = fetch_alert(alert_id, file, &state)
-        .await
-        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let alert_id = params
+        .get("alert_id")
+        .and_then(|v| v.as_str())
+        .ok_or(ApiError::BadRequest(
+            "missing alert_id parameter".to_string(),
+        ))?
+        .to_string();
 
-    params.entry("data").or_insert_with(|| alert);
+    let file = params.get("file").and_then(|v| v.as_str());
 
-    mcp.execute(&id, params)
+    let alert = fetch_alert(&alert_id, file, &state)
         .await
-        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .map_err(ApiError::internal)?;
+
+    run_audited(
+        pool,
+        mcp,
+        &id,
+        alert_id,
+        params,
+        alert,
+        Trigger::Api(principal.map(|Extension(p)| p)),
+    )
+    .await?;
 
     Ok(axum::Json(()))
 }
+
+/// What started an action run
+pub(crate) enum Trigger {
+    /// An API request, with its token when auth is on
+    Api(Option<Principal>),
+    /// The playbook with this id
+    Playbook(String),
+}
+
+/// Run action `id` on a finding with the finding as `data`, writing the run
+/// to the audit log before it starts and updating it when it ends. If the
+/// log can't be written the action doesn't run.
+pub(crate) async fn run_audited(
+    pool: &crate::Pool,
+    mcp: &Mcp,
+    id: &str,
+    alert_id: String,
+    mut params: serde_json::Map<String, serde_json::Value>,
+    finding: serde_json::Value,
+    trigger: Trigger,
+) -> Result<(), ApiError> {
+    let mut audited = serde_json::Value::Object(params.clone());
+    redact(&mut audited);
+    tracing::info!("running action {} on {}: {}", id, alert_id, audited);
+
+    params.entry("data").or_insert(finding);
+
+    let (principal, triggered_by) = match trigger {
+        Trigger::Api(principal) => (principal.map(|p| p.0), "api"),
+        Trigger::Playbook(id) => (Some(id), "playbook"),
+    };
+    let entry = ActionAudit {
+        id: uuid::Uuid::now_v7().to_string(),
+        action_id: id.to_string(),
+        alert_id,
+        params: audited,
+        principal,
+        triggered_by: triggered_by.to_string(),
+        executed_at: chrono::Utc::now().to_rfc3339(),
+        success: None,
+        error: None,
+        duration_ms: None,
+    };
+    {
+        let mut conn = pool.get().map_err(ApiError::database)?;
+        crate::persist::add_action_audit(&mut conn, &entry).map_err(ApiError::database)?;
+    }
+
+    // no connection is held while the server runs the tool, which may take
+    // up to the call timeout
+    let start = Instant::now();
+    let result = mcp.execute(id, params).await;
+    let error = result.as_ref().err().map(|e| e.to_string());
+    let mut conn = pool.get().map_err(ApiError::database)?;
+    crate::persist::finish_action_audit(
+        &mut conn,
+        &entry.id,
+        error.as_deref(),
+        start.elapsed().as_millis() as u64,
+    )
+    .map_err(ApiError::database)?;
+
+    result.map_err(ApiError::internal)
+}
+
+/// Audit log of action runs, most recent first
+#[utoipa::path(
+    get,
+    path = "/api/1/actions/audit",
+    tag = "actions",
+    params(
+        ("alert_id" = Option<String>, Query, description = "Runs on this finding"),
+        ("action_id" = Option<String>, Query, description = "Runs of this action"),
+        ("limit" = Option<usize>, Query, description = "Entries to return (default 100, at most 1000)"),
+    ),
+    responses(
+        (status = 200, description = "Action runs", body = [ActionAudit]),
+        (status = 400, description = "Invalid `limit`", body = crate::error::ErrorBody),
+        (status = 500, description = "Audit lookup failed", body = crate::error::ErrorBody),
+        (status = 503, description = "Database not initialized", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_action_audit(
+    State(state): State<ApiState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<axum::Json<Vec<ActionAudit>>, ApiError> {
+    let Some(pool) = state.db.as_ref() else {
+        return Err(ApiError::Unavailable(
+            "database not initialized".to_string(),
+        ));
+    };
+    let limit = match params.get("limit") {
+        Some(limit) => limit
+            .parse::<usize>()
+            .map_err(|e| ApiError::BadRequest(format!("invalid limit: {}", e)))?
+            .min(MAX_AUDIT_LIMIT),
+        None => DEFAULT_AUDIT_LIMIT,
+    };
+
+    let mut conn = pool.get().map_err(ApiError::database)?;
+    let entries = crate::persist::action_audit(
+        &mut conn,
+        params.get("alert_id").map(String::as_str),
+        params.get("action_id").map(String::as_str),
+        limit,
+    )
+    .map_err(ApiError::database)?;
+    Ok(axum::Json(entries))
+}
@@ -0,0 +1,163 @@
+//! Runtime administration.
+//!
+//! - GET /api/1/admin/log-level - The log filter in effect, and when a
+//!   temporary one reverts
+//! - PUT /api/1/admin/log-level - Replace the log filter, e.g.
+//!   `{"level": "debug"}` or `{"level": "info,striem_storage=debug"}`;
+//!   with `?ttl=300` the previous filter comes back after that many seconds
+//!
+//! Changes apply at once and aren't saved: a restart starts with the
+//! configured `logging` levels again. A change while another is waiting to
+//! revert keeps the original filter as the one to revert to.
+
+use std::sync::{LazyLock, Mutex};
+
+use axum::{
+    Extension, Json, Router,
+    extract::{Query, State},
+    routing::get,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use striem_common::logging;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{ApiState, auth::Principal, error::ApiError, trace};
+
+/// The revert of a temporary log filter, while it's pending
+struct Revert {
+    id: uuid::Uuid,
+    task: JoinHandle<()>,
+    to: String,
+    at: DateTime<Utc>,
+}
+
+static REVERT: LazyLock<Mutex<Option<Revert>>> = LazyLock::new(|| Mutex::new(None));
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct LogLevel {
+    /// Filter directives in effect
+    pub level: String,
+    /// Directives restored at `revert_at`, for a temporary filter
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revert_to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revert_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub(crate) struct SetLogLevel {
+    /// `error`, `warn`, `info`, `debug` or `trace`, optionally with levels
+    /// for modules (`info,striem_storage=debug`)
+    pub level: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub(crate) struct LogLevelParams {
+    /// Seconds until the previous filter is restored
+    ttl: Option<u64>,
+}
+
+/// The filter in effect and any pending revert
+fn current() -> Result<LogLevel, ApiError> {
+    let level = logging::filter()
+        .ok_or_else(|| ApiError::Unavailable("logging is not initialized".to_string()))?;
+    let revert = REVERT.lock().unwrap_or_else(|e| e.into_inner());
+    Ok(LogLevel {
+        level,
+        revert_to: revert.as_ref().map(|r| r.to.clone()),
+        revert_at: revert.as_ref().map(|r| r.at),
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/1/admin/log-level",
+    tag = "system",
+    responses(
+        (status = 200, description = "The log filter in effect", body = LogLevel),
+        (status = 503, description = "Logging was not initialized by this process", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_log_level(State(_): State<ApiState>) -> Result<Json<LogLevel>, ApiError> {
+    current().map(Json)
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/1/admin/log-level",
+    tag = "system",
+    params(LogLevelParams),
+    request_body = SetLogLevel,
+    responses(
+        (status = 200, description = "The log filter now in effect", body = LogLevel),
+        (status = 400, description = "Invalid filter or ttl", body = crate::error::ErrorBody),
+        (status = 503, description = "Logging was not initialized by this process", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn put_log_level(
+    State(_): State<ApiState>,
+    principal: Option<Extension<Principal>>,
+    Query(params): Query<LogLevelParams>,
+    Json(payload): Json<SetLogLevel>,
+) -> Result<Json<LogLevel>, ApiError> {
+    if params.ttl == Some(0) {
+        return Err(ApiError::BadRequest("ttl must be positive".to_string()));
+    }
+    if logging::filter().is_none() {
+        return Err(ApiError::Unavailable(
+            "logging is not initialized".to_string(),
+        ));
+    }
+    let level = payload.level.trim();
+
+    let mut revert = REVERT.lock().unwrap_or_else(|e| e.into_inner());
+    let previous = logging::set_filter(level).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    info!(
+        request_id = %trace::current().unwrap_or_default(),
+        principal = %principal.map(|Extension(Principal(p))| p).unwrap_or_default(),
+        "log filter changed from '{}' to '{}'",
+        previous,
+        level
+    );
+
+    let pending = revert.take();
+    if let Some(pending) = &pending {
+        pending.task.abort();
+    }
+    if let Some(ttl) = params.ttl {
+        let to = pending.map_or(previous, |p| p.to);
+        let id = uuid::Uuid::new_v4();
+        let task = tokio::spawn({
+            let to = to.clone();
+            async move {
+                tokio::time::sleep(std::time::Duration::from_secs(ttl)).await;
+                let mut revert = REVERT.lock().unwrap_or_else(|e| e.into_inner());
+                // replaced by a later change while waiting for the lock
+                if revert.as_ref().is_none_or(|r| r.id != id) {
+                    return;
+                }
+                match logging::set_filter(&to) {
+                    Ok(level) => info!("log filter '{}' expired, restored '{}'", level, to),
+                    Err(e) => error!("could not restore log filter '{}': {}", to, e),
+                }
+                *revert = None;
+            }
+        });
+        *revert = Some(Revert {
+            id,
+            task,
+            to,
+            at: Utc::now() + chrono::Duration::seconds(ttl as i64),
+        });
+    }
+    drop(revert);
+
+    current().map(Json)
+}
+
+pub fn create_router() -> Router<ApiState> {
+    Router::new().route("/log-level", get(get_log_level).put(put_log_level))
+}
@@ -1,20 +1,72 @@
 use anyhow::{Result, anyhow};
 use axum::{
+    Extension,
     extract::{Path, Query, State},
+    response::{
+        IntoResponse,
+        sse::{Event as SseEvent, KeepAlive, Sse},
+    },
     routing::get,
 };
 use chrono::{DateTime, Utc};
+use futures_util::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, path::PathBuf};
+use striem_common::{
+    event::{Event, tenant_label},
+    metrics,
+    severity::Severity,
+};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{ApiState, auth::Tenant, error::ApiError};
+
+/// Analyst triage state of a finding
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertStatus {
+    #[default]
+    Open,
+    Ack,
+    Closed,
+}
 
-use crate::ApiState;
+impl std::fmt::Display for AlertStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlertStatus::Open => write!(f, "open"),
+            AlertStatus::Ack => write!(f, "ack"),
+            AlertStatus::Closed => write!(f, "closed"),
+        }
+    }
+}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl std::str::FromStr for AlertStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "open" => Ok(AlertStatus::Open),
+            "ack" | "acknowledged" => Ok(AlertStatus::Ack),
+            "closed" => Ok(AlertStatus::Closed),
+            _ => Err(format!("invalid status: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Alert {
     pub id: String,
     pub time: String,
     pub severity: String,
     pub title: String,
+    /// Triage status; findings without a recorded status are `open`
+    #[serde(default)]
+    pub status: AlertStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assignee: Option<String>,
     #[serde(flatten)]
     pub extra: HashMap<String, serde_json::Value>,
 }
@@ -22,77 +74,553 @@ pub struct Alert {
 pub fn create_router() -> axum::Router<ApiState> {
     axum::Router::new()
         .route("/", get(get_alerts))
-        .route("/{id}", get(get_alert_by_id))
+        .route("/stream", get(stream_alerts))
+        .route("/summary", get(get_summary))
+        .route("/{id}", get(get_alert_by_id).patch(patch_alert))
+}
+
+impl Alert {
+    /// Build the list view of a detection finding as it leaves the detection
+    /// engine; returns `None` for anything that isn't a detection_finding.
+    pub(crate) fn from_finding(event: &Event) -> Option<Self> {
+        let data = &event.data;
+        if data.get("class_uid").and_then(|v| v.as_u64()) != Some(2004) {
+            return None;
+        }
+        let text = |v: &serde_json::Value| match v {
+            serde_json::Value::String(s) => s.clone(),
+            serde_json::Value::Null => String::new(),
+            v => v.to_string(),
+        };
+        Some(Alert {
+            id: data["metadata"]["uid"].as_str()?.to_string(),
+            time: text(&data["time"]),
+            severity: text(&data["severity"]),
+            title: text(&data["finding_info"]["title"]),
+            status: AlertStatus::Open,
+            assignee: None,
+            extra: HashMap::from([(
+                "observables".to_string(),
+                data.get("observables")
+                    .map(|o| serde_json::Value::from(o.to_string()))
+                    .unwrap_or_default(),
+            )]),
+        })
+    }
+}
+
+/// Interval between SSE keep-alive comments
+const STREAM_HEARTBEAT: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// Stream new detection findings as server-sent events.
+///
+/// Each finding is sent as an `alert` event with the same shape as the
+/// list endpoint; with `tenant`, or a token scoped to one, only findings for
+/// that tenant's sources are. Subscribers that fall behind the findings channel are
+/// disconnected rather than allowed to hold up the pipeline; clients are
+/// expected to reconnect and backfill from `GET /api/1/alerts`.
+#[utoipa::path(
+    get,
+    path = "/api/1/alerts/stream",
+    tag = "alerts",
+    params(("tenant" = Option<String>, Query, description = "Tenant of the findings' sources")),
+    responses(
+        (status = 200, description = "`alert` events, one per new finding", body = Alert, content_type = "text/event-stream"),
+        (status = 403, description = "`tenant` isn't the token's tenant", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn stream_alerts(
+    State(state): State<ApiState>,
+    tenant: Option<Extension<Tenant>>,
+    axum::extract::Query(params): axum::extract::Query<HashMap<String, String>>,
+) -> Result<Sse<impl Stream<Item = Result<SseEvent, axum::Error>>>, ApiError> {
+    let mut query = AlertQuery {
+        tenant: text_filter(&params, "tenant").map_err(ApiError::BadRequest)?,
+        ..Default::default()
+    };
+    query.scope(tenant.as_deref())?;
+    let rx = state.events.subscribe();
+
+    let alerts = futures_util::stream::unfold((rx, query.tenant), |(mut rx, tenant)| async move {
+        loop {
+            match rx.recv().await {
+                Ok(events) => {
+                    let alerts = events
+                        .iter()
+                        .filter(|event| tenant.is_none() || event.tenant() == tenant.as_deref())
+                        .filter_map(Alert::from_finding)
+                        .collect::<Vec<_>>();
+                    if !alerts.is_empty() {
+                        return Some((alerts, (rx, tenant)));
+                    }
+                }
+                Err(RecvError::Lagged(n)) => {
+                    metrics::increment(
+                        "striem_broadcast_lagged_total",
+                        &[("subscriber", "alert_stream")],
+                        n,
+                    );
+                    warn!(
+                        "alert stream subscriber lagged by {} batches, disconnecting",
+                        n
+                    );
+                    return None;
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+    .flat_map(|alerts| {
+        futures_util::stream::iter(
+            alerts
+                .into_iter()
+                .map(|alert| SseEvent::default().event("alert").json_data(alert)),
+        )
+    });
+
+    Ok(Sse::new(alerts).keep_alive(KeepAlive::new().interval(STREAM_HEARTBEAT)))
+}
+
+/// Page size used when the client doesn't ask for one
+const DEFAULT_LIMIT: usize = 10;
+/// Server-side cap on page size regardless of the requested `limit`
+const MAX_LIMIT: usize = 1000;
+
+/// Parsed and validated alert list parameters
+#[derive(Debug, Clone)]
+pub(crate) struct AlertQuery {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub limit: usize,
+    pub offset: usize,
+    /// Match any of these severities
+    pub severity: Vec<Severity>,
+    /// Case-insensitive substrings that must all appear in `finding_info.title`
+    pub title_contains: Vec<String>,
+    /// Substring matched against the serialized observables
+    pub observable: Option<String>,
+    /// Match any of these triage states
+    pub status: Vec<AlertStatus>,
+    /// Only findings for events of this tenant's sources
+    pub tenant: Option<String>,
+    /// Collapse results into one row per distinct combination of these fields
+    pub group_by: Vec<GroupBy>,
+}
+
+/// Fields alerts can be grouped on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GroupBy {
+    Title,
+    Severity,
+    Status,
+}
+
+impl GroupBy {
+    fn name(&self) -> &'static str {
+        match self {
+            GroupBy::Title => "title",
+            GroupBy::Severity => "severity",
+            GroupBy::Status => "status",
+        }
+    }
+
+    #[cfg(feature = "duckdb")]
+    fn column(&self) -> &'static str {
+        match self {
+            GroupBy::Title => "finding_info.title",
+            GroupBy::Severity => "severity",
+            GroupBy::Status => "COALESCE(s.status, 'open')",
+        }
+    }
+}
+
+impl std::str::FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "title" => Ok(GroupBy::Title),
+            "severity" => Ok(GroupBy::Severity),
+            "status" => Ok(GroupBy::Status),
+            _ => Err(format!("invalid group_by field: {}", s)),
+        }
+    }
+}
+
+impl Default for AlertQuery {
+    fn default() -> Self {
+        let end = Utc::now();
+        AlertQuery {
+            start: end - chrono::Duration::hours(24),
+            end,
+            limit: DEFAULT_LIMIT,
+            offset: 0,
+            severity: Vec::new(),
+            title_contains: Vec::new(),
+            observable: None,
+            status: Vec::new(),
+            tenant: None,
+            group_by: Vec::new(),
+        }
+    }
 }
 
-async fn get_alerts(
+/// Parse an optional non-empty text filter
+fn text_filter(params: &HashMap<String, String>, key: &str) -> Result<Option<String>, String> {
+    match params.get(key).map(|v| v.trim()) {
+        Some("") => Err(format!("{} must not be empty", key)),
+        Some(v) => Ok(Some(v.to_string())),
+        None => Ok(None),
+    }
+}
+
+impl AlertQuery {
+    pub(crate) fn from_params(params: &HashMap<String, String>) -> Result<Self, String> {
+        let mut query = AlertQuery::default();
+
+        if let Some(start) = params.get("start") {
+            query.start = DateTime::parse_from_rfc3339(start)
+                .map_err(|e| format!("invalid start: {}", e))?
+                .with_timezone(&Utc);
+        }
+        if let Some(end) = params.get("end") {
+            query.end = DateTime::parse_from_rfc3339(end)
+                .map_err(|e| format!("invalid end: {}", e))?
+                .with_timezone(&Utc);
+        }
+        if let Some(limit) = params.get("limit") {
+            query.limit = limit
+                .parse::<usize>()
+                .map_err(|e| format!("invalid limit: {}", e))?
+                .min(MAX_LIMIT);
+        }
+        if let Some(offset) = params.get("offset") {
+            query.offset = offset
+                .parse::<usize>()
+                .map_err(|e| format!("invalid offset: {}", e))?;
+        }
+        if let Some(severity) = params.get("severity") {
+            query.severity = severity
+                .split(',')
+                .map(|s| s.parse::<Severity>())
+                .collect::<Result<Vec<_>, _>>()?;
+        }
+        // `rule` is shorthand for `title_contains`; both apply when given
+        query.title_contains = ["rule", "title_contains"]
+            .into_iter()
+            .map(|k| text_filter(params, k))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .flatten()
+            .collect();
+        query.observable = text_filter(params, "observable")?;
+        if let Some(status) = params.get("status") {
+            query.status = status
+                .split(',')
+                .map(|s| s.parse::<AlertStatus>())
+                .collect::<Result<Vec<_>, _>>()?;
+        }
+        query.tenant = text_filter(params, "tenant")?;
+        if let Some(group_by) = params.get("group_by") {
+            for field in group_by.split(',') {
+                let field = field.parse::<GroupBy>()?;
+                if !query.group_by.contains(&field) {
+                    query.group_by.push(field);
+                }
+            }
+        }
+
+        Ok(query)
+    }
+
+    /// WHERE clause and its bound parameters; user input only ever reaches
+    /// DuckDB as a parameter
+    #[cfg(feature = "duckdb")]
+    fn conditions(&self) -> (String, Vec<Box<dyn duckdb::ToSql>>) {
+        let mut clauses = vec!["time >= ?".to_string(), "time <= ?".to_string()];
+        let mut params: Vec<Box<dyn duckdb::ToSql>> =
+            vec![Box::new(self.start), Box::new(self.end)];
+
+        if !self.severity.is_empty() {
+            clauses.push(format!(
+                "lower(severity) IN ({})",
+                vec!["?"; self.severity.len()].join(", ")
+            ));
+            params.extend(
+                self.severity
+                    .iter()
+                    .map(|s| Box::new(s.to_string()) as Box<dyn duckdb::ToSql>),
+            );
+        }
+        for title in &self.title_contains {
+            clauses.push("contains(lower(finding_info.title), lower(?))".to_string());
+            params.push(Box::new(title.clone()));
+        }
+        if let Some(observable) = &self.observable {
+            clauses.push("contains(CAST(observables AS VARCHAR), ?)".to_string());
+            params.push(Box::new(observable.clone()));
+        }
+        if !self.status.is_empty() {
+            clauses.push(format!(
+                "COALESCE(s.status, 'open') IN ({})",
+                vec!["?"; self.status.len()].join(", ")
+            ));
+            params.extend(
+                self.status
+                    .iter()
+                    .map(|s| Box::new(s.to_string()) as Box<dyn duckdb::ToSql>),
+            );
+        }
+
+        if let Some(tenant) = &self.tenant {
+            clauses.push("list_contains(metadata.labels, ?)".to_string());
+            params.push(Box::new(tenant_label(tenant)));
+        }
+
+        (clauses.join(" AND "), params)
+    }
+
+    /// Scope the query to the caller's tenant, if its token has one; asking
+    /// for another tenant is forbidden
+    pub(crate) fn scope(&mut self, tenant: Option<&Tenant>) -> Result<(), ApiError> {
+        let Some(Tenant(tenant)) = tenant else {
+            return Ok(());
+        };
+        if self.tenant.as_ref().is_some_and(|t| t != tenant) {
+            return Err(ApiError::Forbidden(
+                "token is scoped to another tenant".to_string(),
+            ));
+        }
+        self.tenant = Some(tenant.clone());
+        Ok(())
+    }
+}
+
+/// List findings in a time range, newest first.
+///
+/// # Query Parameters
+/// - `start`, `end`: RFC3339 bounds (default: last 24 hours)
+/// - `limit`: page size (default 10, capped at 1000)
+/// - `offset`: number of findings to skip
+/// - `severity`: comma-separated severities to include
+/// - `rule` / `title_contains`: case-insensitive substring of the finding title
+/// - `observable`: substring of the finding's observables
+/// - `status`: comma-separated triage states (`open`, `ack`, `closed`)
+/// - `tenant`: only findings for the sources of this tenant; tokens with a
+///   tenant claim only ever see their own
+/// - `group_by`: comma-separated `title`, `severity`, `status`; returns one row
+///   per group instead of individual alerts (see [`group_alerts`])
+/// - `envelope`: when `true`, respond with `{total, items}` instead of a bare array
+///
+/// The total row count for the filtered range is always returned in `X-Total-Count`.
+#[utoipa::path(
+    get,
+    path = "/api/1/alerts",
+    tag = "alerts",
+    params(
+        ("start" = Option<String>, Query, description = "RFC 3339 lower bound (default: 24 hours ago)"),
+        ("end" = Option<String>, Query, description = "RFC 3339 upper bound (default: now)"),
+        ("limit" = Option<usize>, Query, description = "Page size (default 10, at most 1000)"),
+        ("offset" = Option<usize>, Query, description = "Findings to skip"),
+        ("severity" = Option<String>, Query, description = "Comma-separated severities"),
+        ("title_contains" = Option<String>, Query, description = "Substring of the finding title; `rule` is an alias"),
+        ("observable" = Option<String>, Query, description = "Substring of the finding's observables"),
+        ("status" = Option<String>, Query, description = "Comma-separated triage states"),
+        ("tenant" = Option<String>, Query, description = "Tenant of the findings' sources"),
+        ("group_by" = Option<String>, Query, description = "Comma-separated `title`, `severity`, `status`"),
+        ("envelope" = Option<bool>, Query, description = "Respond with `{total, items}`"),
+    ),
+    responses(
+        (status = 200, description = "Findings, newest first", body = [Alert],
+            headers(("X-Total-Count" = usize, description = "Findings matching the filters"))),
+        (status = 400, description = "Invalid filter", body = crate::error::ErrorBody),
+        (status = 403, description = "`tenant` isn't the token's tenant", body = crate::error::ErrorBody),
+        (status = 500, description = "Query failed", body = crate::error::ErrorBody),
+        (status = 501, description = "Needs a build with DuckDB", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_alerts(
     State(state): State<ApiState>,
+    tenant: Option<Extension<Tenant>>,
     axum::extract::Query(params): axum::extract::Query<HashMap<String, String>>,
-) -> Result<axum::Json<Vec<Alert>>, (axum::http::StatusCode, String)> {
+) -> Result<axum::response::Response, ApiError> {
     let config = state.config.load();
 
-    let start = params
-        .get("start")
-        .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
-        .map(|dt| dt.with_timezone(&Utc))
-        .unwrap_or(Utc::now() - chrono::Duration::hours(24));
+    let mut query = AlertQuery::from_params(&params).map_err(ApiError::BadRequest)?;
+    query.scope(tenant.as_deref())?;
 
-    let end = params
-        .get("end")
-        .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
-        .map(|dt| dt.with_timezone(&Utc))
-        .unwrap_or(Utc::now());
+    let envelope = params.get("envelope").is_some_and(|e| e == "true");
 
-    let db = if let Some(pool) = &state.db {
-        pool.get()
-            .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-    } else {
-        return Ok(axum::Json(Vec::new()));
+    #[cfg(feature = "duckdb")]
+    let (alerts, total) = match (&state.db, config.storage.as_ref()) {
+        (Some(pool), Some(storage)) => {
+            let db = pool.get().map_err(ApiError::database)?;
+            if query.group_by.is_empty() {
+                query_alerts(
+                    &db,
+                    &storage.root_for("findings", "detection_finding"),
+                    &query,
+                )
+                .and_then(|(alerts, total)| Ok((serde_json::to_value(alerts)?, total)))
+            } else {
+                group_alerts(
+                    &db,
+                    &storage.root_for("findings", "detection_finding"),
+                    &query,
+                )
+                .map(|(groups, total)| (serde_json::Value::from(groups), total))
+            }
+            .map_err(ApiError::database)?
+        }
+        _ => (serde_json::Value::Array(Vec::new()), 0),
     };
-
-    let basepath = {
-        if let Some(path) = config.storage.as_ref().and_then(|s| Some(s.path.clone())) {
-            path.clone()
-        } else {
-            return Ok(axum::Json(Vec::new()));
+    // without DuckDB, findings are read straight from local Parquet files
+    #[cfg(not(feature = "duckdb"))]
+    let (alerts, total) = match config.storage.as_ref() {
+        Some(storage) if !query.group_by.is_empty() || storage.uri.is_some() => {
+            return Err(ApiError::not_built("duckdb"));
+        }
+        Some(storage) => {
+            let basepath = storage.root_for("findings", "detection_finding");
+            let (alerts, total) = tokio::task::spawn_blocking(move || {
+                crate::findings::read_alerts(&basepath, &query)
+            })
+            .await
+            .map_err(ApiError::internal)?
+            .map_err(ApiError::internal)?;
+            (
+                serde_json::to_value(alerts).map_err(ApiError::internal)?,
+                total,
+            )
         }
+        None => (serde_json::Value::Array(Vec::new()), 0),
     };
 
+    let total_header = [("X-Total-Count", total.to_string())];
+    Ok(if envelope {
+        (
+            total_header,
+            axum::Json(serde_json::json!({
+                "total": total,
+                "items": alerts,
+            })),
+        )
+            .into_response()
+    } else {
+        (total_header, axum::Json(alerts)).into_response()
+    })
+}
+
+/// Whether `file`, a findings file as listed in `_file`, is a plain
+/// relative path; an absolute one or one with `..` could name any file
+fn is_finding_file(file: &str) -> bool {
+    std::path::Path::new(file)
+        .components()
+        .all(|c| matches!(c, std::path::Component::Normal(_)))
+}
+
+/// The findings file `file` below `basepath`, see [`is_finding_file`]
+pub(crate) fn finding_file(basepath: &std::path::Path, file: &str) -> Result<PathBuf> {
+    if !is_finding_file(file) {
+        return Err(anyhow!("{} is not a findings file", file));
+    }
+    Ok(basepath.join(file))
+}
+
+#[cfg(feature = "duckdb")]
+/// `FROM ... WHERE ...` clause over the findings relevant to `query`, with
+/// the alert triage state joined on, and its bound parameters. `None` when
+/// there are no findings to read.
+fn findings_source(
+    basepath: &std::path::Path,
+    query: &AlertQuery,
+) -> Option<(String, Vec<Box<dyn duckdb::ToSql>>)> {
     let findings_path = basepath.join("findings/detection_finding");
+    // object store paths can't be listed locally; DuckDB globs them instead
+    let remote = basepath.to_string_lossy().contains("://");
 
-    if !findings_path.exists() {
-        return Ok(axum::Json(Vec::new()));
+    if !remote && !findings_path.exists() {
+        return None;
     }
 
-    let mut sql = r#"SELECT metadata.uid,
-                              time,
-                              finding_info.title,
-                              severity,
-                              observables,
-                              filename"#
-        .to_string();
+    // files by the time range the manifest records, or by file name without one
+    let listed = if remote {
+        None
+    } else {
+        striem_storage::manifest::files_for_range(&findings_path, query.start, query.end)
+    };
+    let files = match listed {
+        Some(files) if files.is_empty() => return None,
+        Some(files) => format!(
+            "[{}]",
+            files
+                .iter()
+                .map(|f| format!("'{}'", f.to_string_lossy().replace('\'', "''")))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        None => format!(
+            "\"{}\"",
+            findings_path.join("**/*.parquet").to_string_lossy()
+        ),
+    };
 
-    sql = format!(
-        "{} FROM read_parquet(\"{}\")",
-        sql,
-        findings_path.join("**/*.parquet").to_string_lossy()
-    );
+    let (conditions, params) = query.conditions();
+    // Triage state lives in the database, joined onto the immutable findings.
+    // Hive partition detection is off since flat and date-partitioned files
+    // can sit side by side
+    Some((
+        format!(
+            "FROM read_parquet({}, hive_partitioning = false) f \
+             LEFT JOIN alert_status s ON s.id = f.metadata.uid WHERE {}",
+            files, conditions
+        ),
+        params,
+    ))
+}
+
+#[cfg(feature = "duckdb")]
+/// Fetch one page of findings matching `query` along with the total
+/// number of matching findings.
+pub(crate) fn query_alerts(
+    db: &duckdb::Connection,
+    basepath: &std::path::Path,
+    query: &AlertQuery,
+) -> Result<(Vec<Alert>, u64)> {
+    let Some((source, mut params)) = findings_source(basepath, query) else {
+        return Ok((Vec::new(), 0));
+    };
+
+    let total = db.query_row(
+        &format!("SELECT count(*) {};", source),
+        duckdb::params_from_iter(params.iter()),
+        |row| row.get::<_, u64>(0),
+    )?;
 
-    sql = format!(
-        "{} WHERE time >= ? AND time <= ? ORDER BY time DESC LIMIT 10;",
-        sql
+    let sql = format!(
+        r#"SELECT metadata.uid,
+                  time,
+                  finding_info.title,
+                  severity,
+                  observables,
+                  filename,
+                  COALESCE(s.status, 'open'),
+                  s.assignee
+           {} ORDER BY time DESC, metadata.uid LIMIT ? OFFSET ?;"#,
+        source
     );
 
-    let mut query = db
-        .prepare(&sql)
-        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    params.push(Box::new(query.limit as u64));
+    params.push(Box::new(query.offset as u64));
 
-    let alerts = query
-        .query_map(duckdb::params![start, end], |row| {
+    let mut stmt = db.prepare(&sql)?;
+
+    let alerts = stmt
+        .query_map(duckdb::params_from_iter(params.iter()), |row| {
             let fname = &row.get::<_, String>(5)?;
 
             let fname = PathBuf::from(&fname)
-                .strip_prefix(&basepath)
+                .strip_prefix(basepath)
                 .and_then(|p| Ok(p.to_path_buf()))
                 .unwrap_or_else(|_| PathBuf::from(&fname));
 
@@ -101,6 +629,8 @@ async fn get_alerts(
                 time: row.get(1)?,
                 title: row.get(2)?,
                 severity: row.get(3)?,
+                status: row.get::<_, String>(6)?.parse().unwrap_or_default(),
+                assignee: row.get(7)?,
                 extra: HashMap::from([
                     (
                         "_file".to_string(),
@@ -113,23 +643,476 @@ async fn get_alerts(
                 ]),
             })
         })
-        .and_then(|r| r.collect::<Result<Vec<_>, _>>())
-        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .and_then(|r| r.collect::<Result<Vec<_>, _>>())?;
+
+    Ok((alerts, total))
+}
+
+#[cfg(feature = "duckdb")]
+/// Distinct observable values reported per alert group
+const GROUP_OBSERVABLES: usize = 10;
+
+#[cfg(feature = "duckdb")]
+/// Group findings matching `query` by its `group_by` fields.
+///
+/// Each group reports its `count`, `first_seen` and `last_seen` times, the
+/// most recent finding as `alert_id`, and up to ten distinct `observables`.
+/// Groups are ordered by most recent activity; `limit`/`offset` page over
+/// groups and the returned total is the number of groups.
+pub(crate) fn group_alerts(
+    db: &duckdb::Connection,
+    basepath: &std::path::Path,
+    query: &AlertQuery,
+) -> Result<(Vec<serde_json::Value>, u64)> {
+    let Some((source, mut params)) = findings_source(basepath, query) else {
+        return Ok((Vec::new(), 0));
+    };
+
+    let keys = query
+        .group_by
+        .iter()
+        .map(|g| format!("{} AS \"{}\"", g.column(), g.name()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let group = (1..=query.group_by.len())
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let total = db.query_row(
+        &format!(
+            "SELECT count(*) FROM (SELECT {} {} GROUP BY {});",
+            keys, source, group
+        ),
+        duckdb::params_from_iter(params.iter()),
+        |row| row.get::<_, u64>(0),
+    )?;
+
+    let sql = format!(
+        r#"SELECT {},
+                  count(*),
+                  CAST(min(time) AS VARCHAR),
+                  CAST(max(time) AS VARCHAR),
+                  arg_max(metadata.uid, time),
+                  CAST(to_json(list_slice(
+                      list(DISTINCT CAST(observables AS VARCHAR))
+                          FILTER (WHERE observables IS NOT NULL),
+                      1, {})) AS VARCHAR)
+           {} GROUP BY {} ORDER BY max(time) DESC, {} LIMIT ? OFFSET ?;"#,
+        keys, GROUP_OBSERVABLES, source, group, group
+    );
+
+    params.push(Box::new(query.limit as u64));
+    params.push(Box::new(query.offset as u64));
+
+    let fields = query.group_by.len();
+    let mut stmt = db.prepare(&sql)?;
+    let groups = stmt
+        .query_map(duckdb::params_from_iter(params.iter()), |row| {
+            let mut group = serde_json::Map::new();
+            for (i, field) in query.group_by.iter().enumerate() {
+                group.insert(
+                    field.name().to_string(),
+                    serde_json::Value::from(row.get::<_, Option<String>>(i)?),
+                );
+            }
+            let observables = row
+                .get::<_, Option<String>>(fields + 4)?
+                .and_then(|o| serde_json::from_str::<serde_json::Value>(&o).ok())
+                .unwrap_or_else(|| serde_json::Value::Array(Vec::new()));
+
+            Ok(serde_json::json!({
+                "group": group,
+                "count": row.get::<_, u64>(fields)?,
+                "first_seen": row.get::<_, String>(fields + 1)?,
+                "last_seen": row.get::<_, String>(fields + 2)?,
+                "alert_id": row.get::<_, String>(fields + 3)?,
+                "observables": observables,
+            }))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((groups, total))
+}
+
+/// Upper bound on the number of buckets a summary may span
+const MAX_SUMMARY_BUCKETS: i64 = 1000;
+/// Number of rules reported in `top_rules`
+#[cfg(feature = "duckdb")]
+const SUMMARY_TOP_RULES: usize = 10;
+
+/// Parse a bucket width like `30s`, `15m`, `1h`, or `1d` into seconds
+pub(crate) fn parse_bucket(bucket: &str) -> Result<i64, String> {
+    let bucket = bucket.trim();
+    let (value, unit) = bucket.split_at(bucket.len().saturating_sub(1));
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(format!("invalid bucket: {}", bucket)),
+    };
+    match value.parse::<i64>() {
+        Ok(v) if v > 0 => Ok(v * scale),
+        _ => Err(format!("invalid bucket: {}", bucket)),
+    }
+}
+
+/// Alert counts for dashboards.
+///
+/// # Query Parameters
+/// Accepts the same filters as the list endpoint, plus `bucket` (default `1h`).
+///
+/// # Response Format
+/// `series` holds one entry per bucket from `start` to `end`, including empty
+/// buckets, with counts per severity; `top_rules` lists the most frequent
+/// finding titles in the range.
+#[utoipa::path(
+    get,
+    path = "/api/1/alerts/summary",
+    tag = "alerts",
+    params(
+        ("bucket" = Option<String>, Query, description = "Bucket width such as `15m`, `1h` or `1d` (default `1h`)"),
+    ),
+    responses(
+        (status = 200, description = "`series` of counts per bucket and severity, and `top_rules`", body = Object),
+        (status = 400, description = "Invalid filter or bucket", body = crate::error::ErrorBody),
+        (status = 403, description = "`tenant` isn't the token's tenant", body = crate::error::ErrorBody),
+        (status = 500, description = "Query failed", body = crate::error::ErrorBody),
+        (status = 501, description = "Needs a build with DuckDB", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_summary(
+    State(state): State<ApiState>,
+    tenant: Option<Extension<Tenant>>,
+    axum::extract::Query(params): axum::extract::Query<HashMap<String, String>>,
+) -> Result<axum::Json<serde_json::Value>, ApiError> {
+    let mut query = AlertQuery::from_params(&params).map_err(ApiError::BadRequest)?;
+    query.scope(tenant.as_deref())?;
+    let bucket = parse_bucket(params.get("bucket").map(|b| b.as_str()).unwrap_or("1h"))
+        .map_err(ApiError::BadRequest)?;
+
+    let range = (query.end - query.start).num_seconds();
+    if range <= 0 {
+        return Err(ApiError::BadRequest("end must be after start".to_string()));
+    }
+    if range.div_ceil(bucket) > MAX_SUMMARY_BUCKETS {
+        return Err(ApiError::BadRequest(format!(
+            "bucket too small for range; at most {} buckets allowed",
+            MAX_SUMMARY_BUCKETS
+        )));
+    }
+
+    Ok(axum::Json(summary(&state, &query, bucket)?))
+}
+
+#[cfg(feature = "duckdb")]
+fn summary(
+    state: &ApiState,
+    query: &AlertQuery,
+    bucket: i64,
+) -> Result<serde_json::Value, ApiError> {
+    let config = state.config.load();
+    let db = match (&state.db, config.storage.as_ref()) {
+        (Some(pool), Some(storage)) => Some((
+            pool.get().map_err(ApiError::database)?,
+            storage.root_for("findings", "detection_finding"),
+        )),
+        _ => None,
+    };
+
+    Ok(match db {
+        Some((db, path)) => {
+            summarize_alerts(&db, &path, query, bucket).map_err(ApiError::database)?
+        }
+        None => summarize_alerts_empty(query, bucket),
+    })
+}
+
+#[cfg(not(feature = "duckdb"))]
+fn summary(
+    state: &ApiState,
+    query: &AlertQuery,
+    bucket: i64,
+) -> Result<serde_json::Value, ApiError> {
+    match state.config.load().storage.as_ref() {
+        Some(_) => Err(ApiError::not_built("duckdb")),
+        None => Ok(summarize_alerts_empty(query, bucket)),
+    }
+}
+
+/// Bucket start times covering `query`'s range
+fn summary_buckets(query: &AlertQuery, bucket: i64) -> Vec<DateTime<Utc>> {
+    let count = (query.end - query.start).num_seconds().div_ceil(bucket);
+    (0..count)
+        .map(|i| query.start + chrono::Duration::seconds(i * bucket))
+        .collect()
+}
+
+fn summarize_alerts_empty(query: &AlertQuery, bucket: i64) -> serde_json::Value {
+    serde_json::json!({
+        "start": query.start.to_rfc3339(),
+        "end": query.end.to_rfc3339(),
+        "bucket_secs": bucket,
+        "series": summary_buckets(query, bucket)
+            .into_iter()
+            .map(|t| serde_json::json!({"time": t.to_rfc3339(), "total": 0, "severity": {}}))
+            .collect::<Vec<_>>(),
+        "top_rules": [],
+    })
+}
+
+#[cfg(feature = "duckdb")]
+/// Aggregate findings matching `query` into per-severity counts per bucket
+/// of `bucket` seconds, plus the most frequent rules.
+pub(crate) fn summarize_alerts(
+    db: &duckdb::Connection,
+    basepath: &std::path::Path,
+    query: &AlertQuery,
+    bucket: i64,
+) -> Result<serde_json::Value> {
+    let Some((source, params)) = findings_source(basepath, query) else {
+        return Ok(summarize_alerts_empty(query, bucket));
+    };
+
+    // Buckets are anchored at `start` so they line up with the zero-filled series
+    let sql = format!(
+        r#"SELECT CAST(epoch(time_bucket(to_seconds(?::BIGINT),
+                                         CAST(time AS TIMESTAMP),
+                                         make_timestamp(?::BIGINT))) AS BIGINT) AS bucket,
+                  severity,
+                  count(*)
+           {} GROUP BY ALL;"#,
+        source
+    );
+    let mut bucket_params: Vec<Box<dyn duckdb::ToSql>> =
+        vec![Box::new(bucket), Box::new(query.start.timestamp_micros())];
+    bucket_params.extend(params);
+
+    let mut buckets = summary_buckets(query, bucket)
+        .into_iter()
+        .map(|t| (t, 0u64, serde_json::Map::new()))
+        .collect::<Vec<_>>();
+
+    let mut stmt = db.prepare(&sql)?;
+    let rows = stmt
+        .query_map(duckdb::params_from_iter(bucket_params.iter()), |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, u64>(2)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (time, severity, count) in rows {
+        let index = (time - query.start.timestamp()) / bucket;
+        if let Some((_, total, severities)) =
+            usize::try_from(index).ok().and_then(|i| buckets.get_mut(i))
+        {
+            *total += count;
+            severities.insert(
+                severity.unwrap_or_else(|| "Unknown".to_string()),
+                serde_json::Value::from(count),
+            );
+        }
+    }
+
+    let (_, params) = query.conditions();
+    let sql = format!(
+        "SELECT finding_info.title, count(*) AS n {} GROUP BY ALL ORDER BY n DESC, 1 LIMIT {};",
+        source, SUMMARY_TOP_RULES
+    );
+    let mut stmt = db.prepare(&sql)?;
+    let top_rules = stmt
+        .query_map(duckdb::params_from_iter(params.iter()), |row| {
+            Ok(serde_json::json!({
+                "title": row.get::<_, Option<String>>(0)?,
+                "count": row.get::<_, u64>(1)?,
+            }))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(serde_json::json!({
+        "start": query.start.to_rfc3339(),
+        "end": query.end.to_rfc3339(),
+        "bucket_secs": bucket,
+        "series": buckets
+            .into_iter()
+            .map(|(t, total, severity)| serde_json::json!({
+                "time": t.to_rfc3339(),
+                "total": total,
+                "severity": severity,
+            }))
+            .collect::<Vec<_>>(),
+        "top_rules": top_rules,
+    }))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct PatchAlertPayload {
+    status: Option<AlertStatus>,
+    assignee: Option<String>,
+    note: Option<String>,
+}
+
+/// Update the triage status, assignee, or note of a finding.
+///
+/// Fields omitted from the payload keep their current value. Only the
+/// `alert_status` table is written; the Parquet finding is never modified.
+#[utoipa::path(
+    patch,
+    path = "/api/1/alerts/{id}",
+    tag = "alerts",
+    params(
+        ("id" = String, Path, description = "Finding `metadata.uid`"),
+        ("f" = Option<String>, Query, description = "Parquet file holding the finding, if known"),
+    ),
+    request_body = PatchAlertPayload,
+    responses(
+        (status = 200, description = "The finding's triage state", body = Object),
+        (status = 404, description = "No such finding, or it's another tenant's", body = crate::error::ErrorBody),
+        (status = 500, description = "Lookup or update failed", body = crate::error::ErrorBody),
+        (status = 501, description = "Needs a build with DuckDB", body = crate::error::ErrorBody),
+        (status = 503, description = "Database not initialized", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn patch_alert(
+    State(state): State<ApiState>,
+    tenant: Option<Extension<Tenant>>,
+    Path(id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    axum::extract::Json(payload): axum::extract::Json<PatchAlertPayload>,
+) -> Result<axum::Json<serde_json::Value>, ApiError> {
+    #[cfg(not(feature = "duckdb"))]
+    {
+        let _ = (state, tenant, id, params, payload);
+        Err(ApiError::not_built("duckdb"))
+    }
+    #[cfg(feature = "duckdb")]
+    triage(state, tenant.map(|Extension(t)| t), id, params, payload).await
+}
+
+/// Record the triage state of a finding in the database
+#[cfg(feature = "duckdb")]
+async fn triage(
+    state: ApiState,
+    tenant: Option<Tenant>,
+    id: String,
+    params: HashMap<String, String>,
+    payload: PatchAlertPayload,
+) -> Result<axum::Json<serde_json::Value>, ApiError> {
+    let Some(pool) = state.db.as_ref() else {
+        return Err(ApiError::Unavailable(
+            "database not initialized".to_string(),
+        ));
+    };
+
+    // Only findings that exist, and are the tenant's, can be triaged
+    let fname = params.get("f").map(|s| s.trim()).filter(|f| !f.is_empty());
+    if let Some(file) = fname.filter(|f| !is_finding_file(f)) {
+        return Err(ApiError::BadRequest(format!(
+            "{} is not a findings file",
+            file
+        )));
+    }
+    let alert = fetch_alert(&id, fname, &state).await.map_err(|e| {
+        match e.downcast_ref::<duckdb::Error>() {
+            Some(duckdb::Error::QueryReturnedNoRows) => {
+                ApiError::NotFound(format!("Alert with id {} not found", id))
+            }
+            _ => ApiError::Database(e),
+        }
+    })?;
+    if !visible_to(&alert, tenant.as_ref()) {
+        return Err(ApiError::NotFound(format!(
+            "Alert with id {} not found",
+            id
+        )));
+    }
+
+    let mut conn = pool.get().map_err(ApiError::database)?;
+
+    let current = crate::persist::alert_status(&mut conn, &id).map_err(ApiError::database)?;
+    let (status, assignee, note) = match current {
+        Some((status, assignee, note, _)) => (status.parse().unwrap_or_default(), assignee, note),
+        None => (AlertStatus::Open, None, None),
+    };
+
+    let status = payload.status.unwrap_or(status);
+    let assignee = payload.assignee.or(assignee);
+    let note = payload.note.or(note);
+
+    crate::persist::set_alert_status(
+        &mut conn,
+        &id,
+        &status.to_string(),
+        assignee.as_deref(),
+        note.as_deref(),
+    )
+    .map_err(ApiError::database)?;
 
-    Ok(axum::Json(alerts))
+    let updated_at = crate::persist::alert_status(&mut conn, &id)
+        .map_err(ApiError::database)?
+        .map(|(_, _, _, updated_at)| updated_at);
+
+    Ok(axum::Json(serde_json::json!({
+        "id": id,
+        "status": status,
+        "assignee": assignee,
+        "note": note,
+        "updated_at": updated_at,
+    })))
 }
 
-async fn get_alert_by_id(
+/// A single finding in full
+#[utoipa::path(
+    get,
+    path = "/api/1/alerts/{id}",
+    tag = "alerts",
+    params(
+        ("id" = String, Path, description = "Finding `metadata.uid`"),
+        ("f" = Option<String>, Query, description = "Parquet file holding the finding, if known"),
+    ),
+    responses(
+        (status = 200, description = "The OCSF detection finding", body = Object),
+        (status = 404, description = "The finding is another tenant's", body = crate::error::ErrorBody),
+        (status = 500, description = "Lookup failed", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_alert_by_id(
     State(state): State<ApiState>,
+    tenant: Option<Extension<Tenant>>,
     Path(id): Path<String>,
     Query(params): Query<HashMap<String, String>>,
-) -> Result<axum::Json<serde_json::Value>, (axum::http::StatusCode, String)> {
-    let fname = params.get("f").map(|s| s.as_str());
-    Ok(axum::Json(fetch_alert(&id, fname, &state).await.map_err(
-        |e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
-    )?))
+) -> Result<axum::Json<serde_json::Value>, ApiError> {
+    let fname = params.get("f").map(|s| s.trim()).filter(|f| !f.is_empty());
+    if let Some(file) = fname.filter(|f| !is_finding_file(f)) {
+        return Err(ApiError::BadRequest(format!(
+            "{} is not a findings file",
+            file
+        )));
+    }
+    let alert = fetch_alert(&id, fname, &state)
+        .await
+        .map_err(ApiError::internal)?;
+    if !visible_to(&alert, tenant.as_ref().map(|Extension(t)| t)) {
+        return Err(ApiError::NotFound(format!("alert {} not found", id)));
+    }
+    Ok(axum::Json(alert))
 }
 
+/// Whether a caller scoped to `tenant`, if any, may see `alert`; another
+/// tenant's finding is as good as missing
+pub(crate) fn visible_to(alert: &serde_json::Value, tenant: Option<&Tenant>) -> bool {
+    tenant.is_none_or(|Tenant(tenant)| {
+        alert["metadata"]["labels"]
+            .as_array()
+            .is_some_and(|labels| labels.contains(&tenant_label(tenant).into()))
+    })
+}
+
+#[cfg(feature = "duckdb")]
 pub(crate) async fn fetch_alert(
     id: &str,
     fname: Option<&str>,
@@ -142,24 +1125,28 @@ pub(crate) async fn fetch_alert(
     if let Some(file) = fname
         && file.trim() != ""
     {
+        let basepath = config
+            .storage
+            .as_ref()
+            .map(|s| s.root_for("findings", "detection_finding"))
+            .ok_or_else(|| anyhow!("data path not set"))?;
         sql = format!(
-            "{} FROM read_parquet(\"{}/{}\")",
+            "{} FROM read_parquet(\"{}\", hive_partitioning = false)",
             sql,
-            config
-                .storage
-                .as_ref()
-                .map(|s| s.path.to_string_lossy().to_string())
-                .ok_or_else(|| anyhow!("data path not set"))?,
-            file.trim()
+            finding_file(&basepath, file.trim())?.to_string_lossy()
         );
     } else {
         sql = format!(
-            "{} FROM read_parquet(\"{}/findings/detection_finding/**/*.parquet\")",
+            "{} FROM read_parquet(\"{}/findings/detection_finding/**/*.parquet\", hive_partitioning = false)",
             sql,
             config
                 .storage
                 .as_ref()
-                .map(|s| s.path.to_string_lossy().to_string())
+                .map(|s| {
+                    s.root_for("findings", "detection_finding")
+                        .to_string_lossy()
+                        .to_string()
+                })
                 .ok_or_else(|| anyhow!("data path not set"))?
         );
     }
@@ -181,6 +1168,31 @@ pub(crate) async fn fetch_alert(
     Ok(q)
 }
 
+/// A finding in full, read straight from its Parquet file
+#[cfg(not(feature = "duckdb"))]
+pub(crate) async fn fetch_alert(
+    id: &str,
+    fname: Option<&str>,
+    state: &ApiState,
+) -> Result<serde_json::Value> {
+    let basepath = state
+        .config
+        .load()
+        .storage
+        .as_ref()
+        .map(|s| s.root_for("findings", "detection_finding"))
+        .ok_or_else(|| anyhow!("data path not set"))?;
+    let (id, fname) = (id.to_string(), fname.map(str::to_string));
+    let mut alert = tokio::task::spawn_blocking(move || {
+        crate::findings::find(&basepath, &id, fname.as_deref())
+    })
+    .await??;
+
+    strip_nulls(&mut alert);
+
+    Ok(alert)
+}
+
 fn strip_nulls(value: &mut serde_json::Value) {
     match value {
         serde_json::Value::Object(map) => {
@@ -0,0 +1,191 @@
+//! Bearer token authentication for the API.
+//!
+//! With `api.auth` configured, every request needs `Authorization: Bearer
+//! <token>` for a token whose role covers the route; the `/health` probes,
+//! the UI and the API description (see [`crate::openapi`]) are exempt.
+//! Routes require:
+//! - `admin`: `/api/1/destination`, `/api/1/storage`, `/api/1/config`,
+//!   `/api/1/admin` and `/vector/env`, which holds source secrets
+//! - `read`: GET and HEAD requests, and `/api/1/query` (SQL is POSTed)
+//! - `write`: everything else, i.e. changes to sources, detections, reference
+//!   lists, alerts, notifications and playbooks, and running actions
+//!
+//! A missing or unknown token gets 401, a token with too low a role 403;
+//! both carry a `WWW-Authenticate` challenge. Accepted requests carry a
+//! [`Principal`] naming the token for handlers that record who did what,
+//! and for a token with a tenant claim, its [`Tenant`], which scopes the
+//! caller's alerts, event tail and queries to that tenant's events. Routes
+//! that can't be scoped refuse such tokens with 403: rule replays and
+//! `/api/1/storage`, which read stored events of every tenant, sources and
+//! their Vector configuration, notifications and playbooks, which receive
+//! every tenant's findings, the action audit and live stats.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{
+        HeaderValue, Method,
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use striem_common::constant_time_eq;
+use striem_config::api::Role;
+
+use crate::error::ApiError;
+
+const ADMIN_ROUTES: &[&str] = &[
+    "/api/1/destination",
+    "/api/1/storage",
+    "/api/1/config",
+    "/api/1/admin",
+    "/vector/env",
+];
+const READ_ROUTES: &[&str] = &["/api/1/query"];
+/// Routes that can't be scoped to a tenant
+const UNSCOPED_ROUTES: &[&str] = &[
+    "/api/1/detections/replay",
+    "/api/1/storage",
+    "/api/1/sources",
+    "/api/1/vector",
+    "/vector",
+    "/api/1/notifications",
+    "/api/1/playbooks",
+    "/api/1/actions",
+    "/api/1/stats",
+];
+const PUBLIC_ROUTES: &[&str] = &[
+    "/health",
+    "/health/live",
+    "/health/ready",
+    "/api/1/openapi.json",
+    "/api/docs",
+];
+
+/// Accepted tokens and their roles, and the tenants of those with one
+pub(crate) struct Tokens(Vec<(String, Role)>, Vec<(String, String)>);
+
+impl Tokens {
+    pub(crate) fn new(tokens: Vec<(String, Role)>) -> Self {
+        Self(tokens, Vec::new())
+    }
+
+    pub(crate) fn with_tenants(mut self, tenants: Vec<(String, String)>) -> Self {
+        self.1 = tenants;
+        self
+    }
+
+    /// Tenant claim of `presented`, if it has one
+    fn tenant(&self, presented: &str) -> Option<Tenant> {
+        self.1
+            .iter()
+            .filter(|(token, _)| constant_time_eq(token.as_bytes(), presented.as_bytes()))
+            .map(|(_, tenant)| Tenant(tenant.clone()))
+            .next()
+    }
+
+    /// Role of `presented`, checked against every token in constant time
+    fn role(&self, presented: &str) -> Option<Role> {
+        self.0
+            .iter()
+            .filter(|(token, _)| constant_time_eq(token.as_bytes(), presented.as_bytes()))
+            .map(|(_, role)| *role)
+            .max()
+    }
+}
+
+/// Who made an authenticated request: the token's role and, for tokens
+/// long enough to spare them, its last characters
+#[derive(Debug, Clone)]
+pub(crate) struct Principal(pub String);
+
+impl Principal {
+    fn new(role: Role, token: &str) -> Self {
+        let role = format!("{:?}", role).to_lowercase();
+        match token.len() {
+            16.. => Principal(format!("{} token ...{}", role, &token[token.len() - 4..])),
+            _ => Principal(format!("{} token", role)),
+        }
+    }
+}
+
+/// Tenant the request's token is scoped to
+#[derive(Debug, Clone)]
+pub(crate) struct Tenant(pub String);
+
+/// `path` is `prefix` or below it
+fn under(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Role needed for `method` on `path`, or `None` if it is public
+pub(crate) fn required_role(method: &Method, path: &str) -> Option<Role> {
+    if PUBLIC_ROUTES.contains(&path) {
+        None
+    } else if ADMIN_ROUTES.iter().any(|p| under(path, p)) {
+        Some(Role::Admin)
+    } else if method == Method::GET
+        || method == Method::HEAD
+        || READ_ROUTES.iter().any(|p| under(path, p))
+    {
+        Some(Role::Read)
+    } else {
+        Some(Role::Write)
+    }
+}
+
+fn challenge(error: ApiError, challenge: &'static str) -> Response {
+    (
+        [(WWW_AUTHENTICATE, HeaderValue::from_static(challenge))],
+        error,
+    )
+        .into_response()
+}
+
+/// Middleware rejecting requests without a token for the route's role
+pub(crate) async fn require_token(
+    State(tokens): State<Arc<Tokens>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(required) = required_role(request.method(), request.uri().path()) else {
+        return next.run(request).await;
+    };
+    let presented = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    let role = presented.as_deref().and_then(|token| tokens.role(token));
+    match role {
+        Some(role) if role >= required => {
+            let principal = Principal::new(role, presented.as_deref().unwrap_or_default());
+            request.extensions_mut().insert(principal);
+            if let Some(tenant) = presented.as_deref().and_then(|token| tokens.tenant(token)) {
+                let path = request.uri().path();
+                if UNSCOPED_ROUTES.iter().any(|p| under(path, p)) {
+                    return challenge(
+                        ApiError::Forbidden(
+                            "route isn't available to tenant-scoped tokens".to_string(),
+                        ),
+                        "Bearer realm=\"striem\", error=\"insufficient_scope\"",
+                    );
+                }
+                request.extensions_mut().insert(tenant);
+            }
+            next.run(request).await
+        }
+        Some(_) => challenge(
+            ApiError::Forbidden("token lacks the role for this route".to_string()),
+            "Bearer realm=\"striem\", error=\"insufficient_scope\"",
+        ),
+        None => challenge(
+            ApiError::Unauthorized("missing or unknown bearer token".to_string()),
+            "Bearer realm=\"striem\"",
+        ),
+    }
+}
@@ -0,0 +1,232 @@
+//! Parquet compaction jobs.
+//!
+//! - POST /api/1/storage/compact - Compact a class, or every class, and
+//!   optionally only one day; returns the job
+//! - GET /api/1/storage/compact - Compaction jobs since startup; only the
+//!   last [`COMPACTION_JOBS_KEPT`] finished ones are kept
+//! - GET /api/1/storage/compact/:id - A job's progress, and its report once
+//!   it's done
+//!
+//! See [`striem_storage::compaction`] for what is merged and when. Requested
+//! and scheduled compactions run one at a time.
+
+use std::sync::{Arc, LazyLock, Mutex};
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::StatusCode,
+    routing::get,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use striem_common::prelude::COMPACTION_JOBS_KEPT;
+use striem_storage::{
+    compaction::{self, Progress, Report},
+    reader,
+};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{ApiState, error::ApiError, replay::JobStatus};
+
+static JOBS: LazyLock<RwLock<Vec<Arc<Job>>>> = LazyLock::new(|| RwLock::new(Vec::new()));
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct CompactRequest {
+    /// OCSF class name, e.g. `network_activity`; every class when unset
+    pub class: Option<String>,
+    /// Only the partitions of this day (UTC); every settled day when unset
+    pub date: Option<NaiveDate>,
+}
+
+/// A compaction started through the API
+#[derive(Debug)]
+struct Job {
+    id: String,
+    request: CompactRequest,
+    started_at: DateTime<Utc>,
+    progress: Progress,
+    outcome: Mutex<Option<(DateTime<Utc>, Result<Report, String>)>>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CompactionJob {
+    pub id: String,
+    pub status: JobStatus,
+    pub request: CompactRequest,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// `partitions`, `partitions_done`, `files_merged`, `files_written`,
+    /// `bytes_before` and `bytes_after`, so far or in total
+    #[schema(value_type = Object)]
+    pub report: Report,
+    pub error: Option<String>,
+}
+
+impl Job {
+    fn is_finished(&self) -> bool {
+        self.outcome
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_some()
+    }
+
+    fn view(&self) -> CompactionJob {
+        let outcome = self.outcome.lock().unwrap_or_else(|e| e.into_inner());
+        let (status, finished_at, report, error) = match outcome.as_ref() {
+            None => (JobStatus::Running, None, self.progress.report(), None),
+            Some((at, Ok(report))) => (JobStatus::Completed, Some(*at), report.clone(), None),
+            Some((at, Err(e))) => (
+                JobStatus::Failed,
+                Some(*at),
+                self.progress.report(),
+                Some(e.clone()),
+            ),
+        };
+        CompactionJob {
+            id: self.id.clone(),
+            status,
+            request: self.request.clone(),
+            started_at: self.started_at,
+            finished_at,
+            report,
+            error,
+        }
+    }
+}
+
+/// Add `job`, forgetting the oldest finished jobs past
+/// [`COMPACTION_JOBS_KEPT`]
+async fn track(job: Arc<Job>) {
+    let mut jobs = JOBS.write().await;
+    let finished = jobs.iter().filter(|job| job.is_finished()).count();
+    let mut excess = finished.saturating_sub(COMPACTION_JOBS_KEPT);
+    jobs.retain(|job| {
+        let forget = excess > 0 && job.is_finished();
+        excess -= forget as usize;
+        !forget
+    });
+    jobs.push(job);
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/1/storage/compact",
+    tag = "storage",
+    request_body = CompactRequest,
+    responses(
+        (status = 202, description = "The compaction was started", body = CompactionJob),
+        (status = 400, description = "Unknown class, or object storage", body = crate::error::ErrorBody),
+        (status = 409, description = "The day's files are still being written", body = crate::error::ErrorBody),
+        (status = 503, description = "Storage is not configured", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn start_compaction(
+    State(state): State<ApiState>,
+    Json(request): Json<CompactRequest>,
+) -> Result<(StatusCode, Json<CompactionJob>), ApiError> {
+    let storage = state
+        .config
+        .load()
+        .storage
+        .clone()
+        .ok_or_else(|| ApiError::Unavailable("storage is not configured".to_string()))?;
+    if storage.uri.is_some() {
+        return Err(ApiError::BadRequest(
+            "only local storage can be compacted".to_string(),
+        ));
+    }
+    if let Some(class) = &request.class {
+        reader::class_dir(&storage, class).map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    }
+    if let Some(date) = request.date
+        && !compaction::settled(date, Utc::now(), storage.compaction.min_age_secs)
+    {
+        return Err(ApiError::Conflict(format!(
+            "files are still being written for {}",
+            date
+        )));
+    }
+
+    let job = Arc::new(Job {
+        id: uuid::Uuid::now_v7().to_string(),
+        request,
+        started_at: Utc::now(),
+        progress: Progress::default(),
+        outcome: Mutex::new(None),
+    });
+    track(job.clone()).await;
+    info!("compaction {} started: {:?}", job.id, job.request);
+
+    tokio::spawn({
+        let job = job.clone();
+        async move {
+            let result = tokio::task::spawn_blocking({
+                let job = job.clone();
+                move || {
+                    compaction::compact(
+                        &storage,
+                        job.request.class.as_deref(),
+                        job.request.date,
+                        Utc::now(),
+                        &job.progress,
+                    )
+                }
+            })
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|r| r);
+            match &result {
+                Ok(report) => info!(
+                    "compaction {} finished: {} files merged into {}",
+                    job.id, report.files_merged, report.files_written
+                ),
+                Err(e) => warn!("compaction {} failed: {:#}", job.id, e),
+            }
+            *job.outcome.lock().unwrap_or_else(|e| e.into_inner()) =
+                Some((Utc::now(), result.map_err(|e| format!("{:#}", e))));
+        }
+    });
+
+    Ok((StatusCode::ACCEPTED, Json(job.view())))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/1/storage/compact",
+    tag = "storage",
+    responses((status = 200, description = "Compactions since startup, oldest first; older finished ones are forgotten", body = [CompactionJob]))
+)]
+pub(crate) async fn list_compactions(State(_): State<ApiState>) -> Json<Vec<CompactionJob>> {
+    Json(JOBS.read().await.iter().map(|job| job.view()).collect())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/1/storage/compact/{id}",
+    tag = "storage",
+    params(("id" = String, Path, description = "Compaction id")),
+    responses(
+        (status = 200, description = "The compaction", body = CompactionJob),
+        (status = 404, description = "No such compaction", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_compaction(
+    State(_): State<ApiState>,
+    Path(id): Path<String>,
+) -> Result<Json<CompactionJob>, ApiError> {
+    JOBS.read()
+        .await
+        .iter()
+        .find(|job| job.id == id)
+        .map(|job| Json(job.view()))
+        .ok_or_else(|| ApiError::NotFound(format!("Compaction {} not found", id)))
+}
+
+pub fn create_router() -> Router<ApiState> {
+    Router::new()
+        .route("/", get(list_compactions).post(start_compaction))
+        .route("/{id}", get(get_compaction))
+}
@@ -0,0 +1,221 @@
+//! Effective configuration.
+//!
+//! - GET /api/1/config - The running configuration, merged from files,
+//!   environment and defaults, and the files it was loaded from
+//! - PATCH /api/1/config - Merge a partial configuration into the running
+//!   one; applied through a config update, like `/api/1/destination`. The
+//!   result must pass [`StrIEMConfig::validate_deep`] for the patched
+//!   sections, except for binding listener addresses, and changes that need
+//!   a restart (see [`requires_restart`]) are refused. Only the keys sent
+//!   are saved to `striem.json`, so values from the environment stay there.
+//!
+//! Fields named like secrets (tokens, passwords, keys) and object store
+//! options read as `"[redacted]"`. Sending that value back keeps the
+//! current one.
+
+use std::path::PathBuf;
+
+use axum::{Json, Router, extract::State, routing::get};
+use serde_json::{Map, Value, json};
+use striem_common::SysMessage;
+use striem_config::{
+    StrIEMConfig, api, detections, input, output, reload::requires_restart, storage,
+    validate::ValidateOptions,
+};
+
+use crate::{ApiState, error::ApiError};
+
+pub(crate) const REDACTED: &str = "[redacted]";
+
+/// Field names holding secrets, matched case-insensitively as substrings
+const SECRET_FIELDS: &[&str] = &[
+    "token",
+    "secret",
+    "password",
+    "credential",
+    "access_key",
+    "api_key",
+    "private_key",
+    "account_key",
+    "sas_key",
+    "authorization",
+];
+
+fn is_secret(field: &str) -> bool {
+    let field = field.to_ascii_lowercase();
+    SECRET_FIELDS.iter().any(|s| field.contains(s))
+}
+
+/// Replace the values of secret fields, at any depth, and of every object
+/// store option, whose keys vary by store
+pub(crate) fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            if let Some(Value::Object(storage)) = map.get_mut("storage")
+                && let Some(Value::Object(options)) = storage.get_mut("options")
+            {
+                options
+                    .values_mut()
+                    .filter(|v| !v.is_null())
+                    .for_each(|v| *v = Value::String(REDACTED.to_string()));
+            }
+            for (k, v) in map.iter_mut() {
+                if is_secret(k) && !v.is_null() {
+                    *v = Value::String(REDACTED.to_string());
+                } else {
+                    redact(v);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// Merge `patch` into `current`: objects key by key, anything else is
+/// replaced. Redacted placeholders leave the current value alone.
+pub fn merge(current: &mut Value, patch: Value) {
+    match (current, patch) {
+        (Value::Object(current), Value::Object(patch)) => {
+            for (k, v) in patch {
+                match current.get_mut(&k) {
+                    Some(existing) => merge(existing, v),
+                    None => {
+                        current.insert(k, v);
+                    }
+                }
+            }
+        }
+        (_, Value::String(s)) if s == REDACTED => {}
+        (current, patch) => *current = patch,
+    }
+}
+
+/// Check a merged top-level section reads back as its config type
+pub(crate) fn check_section(key: &str, value: &Value) -> Result<(), String> {
+    let value = value.clone();
+    let result = match key {
+        "db" => serde_json::from_value::<Option<PathBuf>>(value).map(drop),
+        "detections" => {
+            serde_json::from_value::<Option<detections::DetectionsConfig>>(value).map(drop)
+        }
+        "input" => serde_json::from_value::<input::Listener>(value).map(drop),
+        "outputs" => serde_json::from_value::<Vec<output::Destination>>(value).map(drop),
+        "storage" => serde_json::from_value::<Option<storage::StorageConfig>>(value).map(drop),
+        "api" => serde_json::from_value::<api::ApiConfig>(value).map(drop),
+        "fqdn" => serde_json::from_value::<Option<String>>(value).map(drop),
+        _ => return Err(format!("unknown configuration section '{}'", key)),
+    };
+    result.map_err(|e| format!("invalid '{}': {}", key, e))
+}
+
+/// Check the running configuration with `update` applied, rejecting errors
+/// in the updated sections, and return it
+pub(crate) fn check_update(
+    current: &Value,
+    update: &Map<String, Value>,
+) -> Result<StrIEMConfig, ApiError> {
+    let mut merged = current.clone();
+    if let Value::Object(merged) = &mut merged {
+        merged.extend(update.clone());
+    }
+    let config = StrIEMConfig::from_json(&merged.to_string())
+        .map_err(|e| ApiError::BadRequest(format!("{:#}", e)))?;
+    let sections: Vec<&str> = update.keys().map(String::as_str).collect();
+    let report = config.validate_deep(ValidateOptions { bind: false });
+    let errors: Vec<String> = report.errors_in(&sections).map(|i| i.to_string()).collect();
+    if !errors.is_empty() {
+        return Err(ApiError::BadRequest(errors.join("; ")));
+    }
+    Ok(config)
+}
+
+/// The running configuration, redacted
+#[utoipa::path(
+    get,
+    path = "/api/1/config",
+    tag = "config",
+    responses(
+        (status = 200, description = "`{\"config\": {...}, \"files\": [...]}`", body = Object),
+        (status = 500, description = "The configuration could not be serialized", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_config(State(state): State<ApiState>) -> Result<Json<Value>, ApiError> {
+    let config = state.config.load();
+    let mut value = serde_json::to_value(&**config)?;
+    redact(&mut value);
+    Ok(Json(json!({
+        "config": value,
+        "files": config.files,
+    })))
+}
+
+/// Merge a partial configuration into the running one
+#[utoipa::path(
+    patch,
+    path = "/api/1/config",
+    tag = "config",
+    request_body(content = Object, description = "Top-level sections to change, e.g. `{\"api\": {\"docs\": {\"enabled\": true}}}`"),
+    responses(
+        (status = 200, description = "The updated sections, redacted", body = Object),
+        (status = 400, description = "Unknown section, invalid values, the result fails validation, or a change needs a restart", body = crate::error::ErrorBody),
+        (status = 500, description = "The update could not be applied", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn patch_config(
+    State(state): State<ApiState>,
+    Json(patch): Json<Map<String, Value>>,
+) -> Result<Json<Value>, ApiError> {
+    if patch.is_empty() {
+        return Err(ApiError::BadRequest(
+            "empty configuration patch".to_string(),
+        ));
+    }
+    let running = state.config.load();
+    let current = serde_json::to_value(&**running)?;
+
+    let mut update = Map::new();
+    for (key, value) in patch.clone() {
+        if key == "output" {
+            return Err(ApiError::BadRequest(
+                "'output' is read as part of 'outputs'; patch 'outputs' instead".to_string(),
+            ));
+        }
+        let mut section = current.get(&key).cloned().unwrap_or(Value::Null);
+        merge(&mut section, value);
+        check_section(&key, &section).map_err(ApiError::BadRequest)?;
+        update.insert(key, section);
+    }
+    let updated = check_update(&current, &update)?;
+
+    // listeners are bound once, so these would show without taking effect
+    let restart: Vec<String> = running
+        .changes(&updated)?
+        .into_iter()
+        .filter(|key| requires_restart(key))
+        .collect();
+    if !restart.is_empty() {
+        return Err(ApiError::BadRequest(format!(
+            "these need a restart: {}",
+            restart.join(", ")
+        )));
+    }
+
+    tracing::info!(
+        "updating configuration: {}",
+        update.keys().cloned().collect::<Vec<_>>().join(", ")
+    );
+    // only what was sent, merged into the saved configuration
+    state
+        .sys
+        .send(SysMessage::Update(Box::new(patch)))
+        .map_err(ApiError::internal)?;
+
+    let mut applied = Value::Object(update);
+    redact(&mut applied);
+    Ok(Json(applied))
+}
+
+pub fn create_router() -> Router<ApiState> {
+    Router::new().route("/", get(get_config).patch(patch_config))
+}
@@ -0,0 +1,177 @@
+//! Confirmation of destructive requests.
+//!
+//! With `api.require_confirmation`, the routes in [`CONFIRMED_ROUTES`] —
+//! removing a source, a rule or a reference list, replacing a list, and
+//! changing the destination or the configuration — aren't carried out on
+//! the first request. It gets 202 with a
+//! `confirmation_token` instead, and the same request repeated with
+//! `X-Confirm-Token: <token>` within [`CONFIRMATION_TTL_SECS`] goes
+//! through.
+//!
+//! A token is single-use and only confirms the request it was issued for:
+//! the same method, path, body and, with `api.auth`, the same caller.
+//! Tokens are kept in memory, so a restart voids them. Without the setting,
+//! requests pass straight through.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use arc_swap::ArcSwap;
+use axum::{
+    Json,
+    body::Body,
+    extract::{MatchedPath, Request, State},
+    http::{StatusCode, request::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use striem_common::prelude::CONFIRMATION_TTL_SECS;
+use striem_config::StrIEMConfig;
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::{auth::Principal, error::ApiError};
+
+pub(crate) const CONFIRM_HEADER: &str = "x-confirm-token";
+
+/// Method and route template of each request needing confirmation
+const CONFIRMED_ROUTES: &[(&str, &str)] = &[
+    ("DELETE", "/api/1/sources/{id}"),
+    ("DELETE", "/api/1/detections/{id}"),
+    ("DELETE", "/api/1/lists/{name}"),
+    ("PUT", "/api/1/lists/{name}"),
+    ("POST", "/api/1/destination"),
+    // can change `outputs` and `storage.path` as the destination does
+    ("PATCH", "/api/1/config"),
+];
+
+/// Largest body read to fingerprint a request
+const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+static CONFIRMATIONS: LazyLock<Confirmations> = LazyLock::new(Confirmations::default);
+
+/// Returned with 202 for a request that needs confirming
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct Confirmation {
+    /// Send as `X-Confirm-Token` with the same request to carry it out
+    pub confirmation_token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Tokens issued and not yet used, with the fingerprint of the request each
+/// confirms and when it expires
+#[derive(Default)]
+pub(crate) struct Confirmations(Mutex<HashMap<String, (String, Instant)>>);
+
+impl Confirmations {
+    /// A token confirming the request with `fingerprint` until `now + ttl`
+    pub(crate) fn issue(&self, fingerprint: String, now: Instant, ttl: Duration) -> String {
+        let mut pending = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        pending.retain(|_, (_, expires)| *expires > now);
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        pending.insert(token.clone(), (fingerprint, now + ttl));
+        token
+    }
+
+    /// Use up `token` for the request with `fingerprint`
+    pub(crate) fn redeem(
+        &self,
+        token: &str,
+        fingerprint: &str,
+        now: Instant,
+    ) -> Result<(), ApiError> {
+        let mut pending = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let Some((expected, expires)) = pending.remove(token) else {
+            return Err(ApiError::Forbidden(
+                "unknown or already used confirmation token".to_string(),
+            ));
+        };
+        if expires <= now {
+            return Err(ApiError::Gone(
+                "confirmation token expired; repeat the request without it for a new one"
+                    .to_string(),
+            ));
+        }
+        if expected != fingerprint {
+            return Err(ApiError::Forbidden(
+                "confirmation token was issued for a different request".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Whether `method` on `route` needs confirming
+pub(crate) fn confirmed(method: &str, route: &str) -> bool {
+    CONFIRMED_ROUTES
+        .iter()
+        .any(|(m, r)| *m == method && *r == route)
+}
+
+/// Hash of what a token confirms: the request line, the caller and the body
+fn fingerprint(parts: &Parts, body: &[u8]) -> String {
+    let mut hash = Sha256::new();
+    hash.update(parts.method.as_str());
+    hash.update([0]);
+    hash.update(parts.uri.to_string());
+    hash.update([0]);
+    if let Some(Principal(principal)) = parts.extensions.get::<Principal>() {
+        hash.update(principal);
+    }
+    hash.update([0]);
+    hash.update(body);
+    format!("{:x}", hash.finalize())
+}
+
+/// Middleware holding back destructive requests until they are confirmed;
+/// a route layer, so the matched route is known
+pub(crate) async fn require_confirmation(
+    State(config): State<Arc<ArcSwap<StrIEMConfig>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_default();
+    if !config.load().api.require_confirmation || !confirmed(request.method().as_str(), &route) {
+        return next.run(request).await;
+    }
+
+    let (parts, body) = request.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(e) => return ApiError::BadRequest(format!("unreadable body: {}", e)).into_response(),
+    };
+    let fingerprint = fingerprint(&parts, &body);
+    let presented = parts
+        .headers
+        .get(CONFIRM_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string());
+
+    let Some(token) = presented else {
+        let ttl = Duration::from_secs(CONFIRMATION_TTL_SECS);
+        let token = CONFIRMATIONS.issue(fingerprint, Instant::now(), ttl);
+        info!("{} {} awaits confirmation", parts.method, parts.uri.path());
+        let confirmation = Confirmation {
+            confirmation_token: token,
+            expires_at: Utc::now() + chrono::Duration::seconds(CONFIRMATION_TTL_SECS as i64),
+        };
+        return (StatusCode::ACCEPTED, Json(confirmation)).into_response();
+    };
+    match CONFIRMATIONS.redeem(&token, &fingerprint, Instant::now()) {
+        Ok(()) => {
+            info!("{} {} confirmed", parts.method, parts.uri.path());
+            next.run(Request::from_parts(parts, Body::from(body))).await
+        }
+        Err(e) => e.into_response(),
+    }
+}
@@ -0,0 +1,259 @@
+//! Server-side cursors for paging through large query results.
+//!
+//! Opening a cursor materializes the query into a DuckDB temp table on a
+//! pooled connection reserved for that cursor, so later pages are cheap
+//! reads of the temp table rather than a re-run of the original scan.
+//! Cursors are dropped when exhausted or after sitting idle; dropping one
+//! removes its table and returns the connection to the pool.
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    net::IpAddr,
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use arc_swap::ArcSwap;
+use striem_common::SysMessage;
+use striem_config::StrIEMConfig;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, error, info};
+
+use crate::error::ApiError;
+use crate::query::{QueryFormat, ScopedConnection, bind_params, check_read_only, write_batches};
+
+pub(crate) static CURSORS: LazyLock<Cursors> = LazyLock::new(Cursors::default);
+
+/// Upper bound on rows per page
+pub(crate) const MAX_PAGE_SIZE: usize = 10_000;
+
+/// Open cursors across all clients; each pins a pooled connection, so
+/// this stays below the pool size to leave room for other requests
+const MAX_OPEN_CURSORS: usize = 8;
+
+/// How often idle cursors are swept
+const REAP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A materialized query result and the read position within it
+pub(crate) struct Cursor {
+    conn: ScopedConnection,
+    table: String,
+    page_size: usize,
+    offset: usize,
+    total: usize,
+}
+
+impl Cursor {
+    /// Run `sql` into a temp table on `conn`.
+    pub(crate) fn open(
+        conn: ScopedConnection,
+        sql: &str,
+        params: &[serde_json::Value],
+        page_size: usize,
+    ) -> Result<Self, ApiError> {
+        check_read_only(sql).map_err(ApiError::Forbidden)?;
+        let params = bind_params(params).map_err(ApiError::BadRequest)?;
+        if page_size == 0 || page_size > MAX_PAGE_SIZE {
+            return Err(ApiError::BadRequest(format!(
+                "page_size must be between 1 and {}",
+                MAX_PAGE_SIZE
+            )));
+        }
+
+        let table = format!("cursor_{}", uuid::Uuid::now_v7().simple());
+        let sql = format!(
+            "CREATE TEMP TABLE {} AS {}",
+            table,
+            sql.trim().trim_end_matches(';')
+        );
+
+        let mut stmt = conn.prepare(&sql).map_err(ApiError::database)?;
+        let expected = stmt.parameter_count();
+        if expected != params.len() {
+            return Err(ApiError::BadRequest(format!(
+                "query has {} parameter placeholders but {} values were supplied",
+                expected,
+                params.len()
+            )));
+        }
+        stmt.execute(duckdb::params_from_iter(params))
+            .map_err(ApiError::database)?;
+        drop(stmt);
+
+        // from here on the table is dropped with the cursor, even on error
+        let mut cursor = Cursor {
+            conn,
+            table,
+            page_size,
+            offset: 0,
+            total: 0,
+        };
+        let total = cursor
+            .conn
+            .query_row(
+                &format!("SELECT count(*) FROM {}", cursor.table),
+                [],
+                |row| row.get::<_, i64>(0),
+            )
+            .map_err(ApiError::database)?;
+        cursor.total = total as usize;
+        Ok(cursor)
+    }
+
+    /// Rows in the full result
+    pub(crate) fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Rows already returned
+    pub(crate) fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub(crate) fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    pub(crate) fn exhausted(&self) -> bool {
+        self.offset >= self.total
+    }
+
+    /// Write the next page as a JSON array and advance.
+    ///
+    /// Pages come back in insertion order, which DuckDB preserves for the
+    /// temp table, so an `ORDER BY` in the original query carries through.
+    pub(crate) fn next_page<W: Write>(&mut self, out: &mut W) -> Result<(), ApiError> {
+        let sql = format!(
+            "SELECT * FROM {} LIMIT {} OFFSET {}",
+            self.table, self.page_size, self.offset
+        );
+        let mut stmt = self.conn.prepare(&sql).map_err(ApiError::database)?;
+        let batches = stmt.query_arrow([]).map_err(ApiError::database)?;
+        write_batches(batches, QueryFormat::Json, None, out)?;
+
+        self.offset = (self.offset + self.page_size).min(self.total);
+        Ok(())
+    }
+}
+
+impl Drop for Cursor {
+    fn drop(&mut self) {
+        // the connection goes back to the pool; don't leave the table behind
+        self.conn
+            .execute_batch(&format!("DROP TABLE IF EXISTS {};", self.table))
+            .inspect_err(|e| error!("failed to drop cursor table {}: {}", self.table, e))
+            .ok();
+    }
+}
+
+struct Entry {
+    client: IpAddr,
+    last_used: Instant,
+    cursor: Arc<Mutex<Cursor>>,
+}
+
+/// Registry of open cursors keyed by opaque token
+#[derive(Default)]
+pub(crate) struct Cursors {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl Cursors {
+    /// Register `cursor` for `client`, returning its token and shared handle.
+    ///
+    /// Fails with 429 when the client or the server is at its cursor limit.
+    pub(crate) fn insert(
+        &self,
+        client: IpAddr,
+        cursor: Cursor,
+        max_per_client: usize,
+    ) -> Result<(String, Arc<Mutex<Cursor>>), ApiError> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.values().filter(|e| e.client == client).count() >= max_per_client
+            || entries.len() >= MAX_OPEN_CURSORS
+        {
+            return Err(ApiError::TooManyRequests(
+                "too many open cursors".to_string(),
+            ));
+        }
+
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        let cursor = Arc::new(Mutex::new(cursor));
+        entries.insert(
+            token.clone(),
+            Entry {
+                client,
+                last_used: Instant::now(),
+                cursor: cursor.clone(),
+            },
+        );
+        Ok((token, cursor))
+    }
+
+    /// Look up a live cursor, refreshing its idle timer
+    pub(crate) fn get(&self, token: &str, idle: Duration) -> Option<Arc<Mutex<Cursor>>> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries
+            .get(token)
+            .is_some_and(|e| e.last_used.elapsed() > idle)
+        {
+            entries.remove(token);
+        }
+        entries.get_mut(token).map(|e| {
+            e.last_used = Instant::now();
+            e.cursor.clone()
+        })
+    }
+
+    pub(crate) fn remove(&self, token: &str) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(token);
+    }
+
+    /// Drop cursors idle for longer than `idle`
+    pub(crate) fn expire(&self, idle: Duration) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|token, e| {
+                let live = e.last_used.elapsed() <= idle;
+                if !live {
+                    debug!("query cursor {} expired", token);
+                }
+                live
+            });
+    }
+
+    pub(crate) fn clear(&self) {
+        self.entries
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+    }
+}
+
+/// Periodically drop idle cursors until shutdown.
+pub(crate) async fn reap(
+    config: Arc<ArcSwap<StrIEMConfig>>,
+    mut shutdown: broadcast::Receiver<SysMessage>,
+) {
+    let mut interval = tokio::time::interval(REAP_INTERVAL);
+    loop {
+        tokio::select! {
+            msg = shutdown.recv() => {
+                if matches!(msg, Ok(SysMessage::Shutdown) | Err(RecvError::Closed)) {
+                    info!("query cursor reaper shutting down...");
+                    CURSORS.clear();
+                    return;
+                }
+            },
+            _ = interval.tick() => {
+                let idle = Duration::from_secs(config.load().api.query.cursor_idle_secs);
+                CURSORS.expire(idle);
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! The DuckDB database behind the API.
+//!
+//! File access is restricted database-wide when the database is opened (see
+//! [`crate::initdb`]) and can't be widened afterwards. When the directories
+//! DuckDB may read change, e.g. when storage moves through
+//! `/api/1/destination`, the database is reopened with the new ones allowed.
+//! Open query cursors are dropped and connections in use are waited for
+//! first, so the database file is never open twice; checkouts fail rather
+//! than wait meanwhile. Without `db` the database file is kept in a
+//! temporary directory of its own, removed with the last handle, so it's
+//! reopened with its tables like any other.
+
+use std::{
+    sync::{Arc, RwLock, RwLockWriteGuard},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, anyhow};
+use striem_config::StrIEMConfig;
+use tempfile::TempDir;
+use tracing::info;
+
+use crate::cursor::CURSORS;
+
+pub(crate) type ConnectionPool = r2d2::Pool<duckdb::DuckdbConnectionManager>;
+pub(crate) type Connection = r2d2::PooledConnection<duckdb::DuckdbConnectionManager>;
+
+/// How long reopening waits for the connections in use
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Opened {
+    /// `None` while reopening, or if reopening failed
+    pool: Option<ConnectionPool>,
+    /// Directories the pool's database may read
+    allowed: Vec<String>,
+    reopening: bool,
+}
+
+impl Opened {
+    fn pool(&self) -> Result<&ConnectionPool> {
+        match (&self.pool, self.reopening) {
+            (Some(pool), _) => Ok(pool),
+            (None, true) => Err(anyhow!("the database is being reopened")),
+            (None, false) => Err(anyhow!("the database could not be reopened")),
+        }
+    }
+}
+
+/// Pool of connections to the current database
+#[derive(Clone)]
+pub(crate) struct Db {
+    opened: Arc<RwLock<Opened>>,
+    /// Directory of the database file when `db` isn't set
+    scratch: Option<Arc<TempDir>>,
+}
+
+impl Db {
+    pub(crate) fn new(pool: ConnectionPool, allowed: Vec<String>) -> Self {
+        Db {
+            opened: Arc::new(RwLock::new(Opened {
+                pool: Some(pool),
+                allowed,
+                reopening: false,
+            })),
+            scratch: None,
+        }
+    }
+
+    /// Keep the database file in `scratch` for as long as the pool lives
+    pub(crate) fn with_scratch(mut self, scratch: Option<Arc<TempDir>>) -> Self {
+        self.scratch = scratch;
+        self
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Opened> {
+        self.opened.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The current pool, for its statistics
+    pub(crate) fn pool(&self) -> Result<ConnectionPool> {
+        let opened = self.opened.read().unwrap_or_else(|e| e.into_inner());
+        opened.pool().cloned()
+    }
+
+    /// A connection, checked out before a reopen can start
+    pub(crate) fn get(&self) -> Result<Connection> {
+        let opened = self.opened.read().unwrap_or_else(|e| e.into_inner());
+        Ok(opened.pool()?.get()?)
+    }
+
+    pub(crate) fn get_timeout(&self, timeout: Duration) -> Result<Connection> {
+        let opened = self.opened.read().unwrap_or_else(|e| e.into_inner());
+        Ok(opened.pool()?.get_timeout(timeout)?)
+    }
+
+    /// Reopen the database if `config` allows other directories than it was
+    /// opened with, returning whether it was. Blocks until the connections
+    /// in use are returned, or fails after [`DRAIN_TIMEOUT`] leaving the
+    /// database as it was.
+    pub(crate) fn reopen(&self, config: &StrIEMConfig) -> Result<bool> {
+        let allowed = crate::allowed_directories(config);
+        let pool = {
+            let mut opened = self.write();
+            if opened.reopening {
+                return Err(anyhow!("the database is already being reopened"));
+            }
+            if opened.allowed == allowed && opened.pool.is_some() {
+                return Ok(false);
+            }
+            opened.reopening = true;
+            opened.pool.take()
+        };
+
+        CURSORS.clear();
+        if let Some(pool) = pool
+            && let Err((pool, e)) = drain(pool)
+        {
+            let mut opened = self.write();
+            opened.pool = Some(pool);
+            opened.reopening = false;
+            return Err(e);
+        }
+
+        let pool = crate::open_pool(config, self.scratch.as_deref().map(TempDir::path));
+        let mut opened = self.write();
+        opened.reopening = false;
+        opened.pool = pool?;
+        opened.allowed = allowed;
+        info!("database reopened for the new storage location");
+        Ok(true)
+    }
+}
+
+/// Wait for the connections of `pool` in use to be returned, then close
+/// it; gives it back if they aren't within [`DRAIN_TIMEOUT`]
+fn drain(pool: ConnectionPool) -> Result<(), (ConnectionPool, anyhow::Error)> {
+    let started = Instant::now();
+    loop {
+        let state = pool.state();
+        if state.connections == state.idle_connections {
+            break;
+        }
+        if started.elapsed() > DRAIN_TIMEOUT {
+            let e = anyhow!(
+                "{} database connections still in use after {}s",
+                state.connections - state.idle_connections,
+                DRAIN_TIMEOUT.as_secs()
+            );
+            return Err((pool, e));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    // the last handle on the old database, closing it
+    drop(pool);
+    Ok(())
+}
+
+impl From<ConnectionPool> for Db {
+    /// A pool opened outside [`crate::initdb`]
+    fn from(pool: ConnectionPool) -> Self {
+        Db::new(pool, Vec::new())
+    }
+}
+
+/// Directory for the database file when `db` isn't set, or `None` if there's
+/// no database at all
+pub(crate) fn scratch(config: &StrIEMConfig) -> Result<Option<Arc<TempDir>>> {
+    if config.db.is_some() || config.storage.is_none() {
+        return Ok(None);
+    }
+    let dir = tempfile::Builder::new().prefix("striem-db-").tempdir()?;
+    Ok(Some(Arc::new(dir)))
+}
@@ -2,29 +2,38 @@ use axum::{Json, extract::State, routing::post};
 use serde_json::{Map, Value, json};
 use std::path::PathBuf;
 
-use crate::ApiState;
+use crate::{ApiState, error::ApiError};
 
-async fn set_destination(
+/// Move Parquet storage to `path`; applied through a config update, after
+/// which alerts and queries read the new location (see [`crate::db`])
+#[utoipa::path(
+    post,
+    path = "/api/1/destination",
+    tag = "destination",
+    request_body(content = Object, description = "`{\"path\": \"/absolute/path\"}`"),
+    params(("X-Confirm-Token" = Option<String>, Header, description = "Token from the 202 response, with `api.require_confirmation`")),
+    responses(
+        (status = 200, description = "The new storage configuration", body = Object),
+        (status = 202, description = "Confirmation required; repeat with `X-Confirm-Token`", body = crate::confirm::Confirmation),
+        (status = 400, description = "Missing or nonexistent `path`", body = crate::error::ErrorBody),
+        (status = 500, description = "Storage isn't configured or the update failed", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn set_destination(
     State(state): State<ApiState>,
     Json(payload): Json<Map<String, Value>>,
-) -> Result<axum::Json<Value>, (axum::http::StatusCode, String)> {
+) -> Result<axum::Json<Value>, ApiError> {
     let dest_path = payload
         .get("path")
         .and_then(|v| v.as_str())
-        .ok_or_else(|| {
-            (
-                axum::http::StatusCode::BAD_REQUEST,
-                "missing 'path' in request body".to_string(),
-            )
-        })?;
+        .ok_or_else(|| ApiError::BadRequest("missing 'path' in request body".to_string()))?;
     if !PathBuf::from(dest_path).exists() {
-        return Err((
-            axum::http::StatusCode::BAD_REQUEST,
+        return Err(ApiError::BadRequest(
             "'path' must be an absolute path".to_string(),
         ));
     }
 
-    log::info!("updating storage destination to '{}'", dest_path);
+    tracing::info!("updating storage destination to '{}'", dest_path);
 
     let storage = state
         .config
@@ -32,12 +41,7 @@ async fn set_destination(
         .storage
         .as_ref()
         .and_then(|s| serde_json::to_value(s).ok())
-        .ok_or_else(|| {
-            (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                "no storage configuration found".to_string(),
-            )
-        })?
+        .ok_or_else(|| ApiError::internal("no storage configuration found"))?
         .as_object_mut()
         .map(|storage| {
             storage
@@ -45,27 +49,17 @@ async fn set_destination(
                 .and_modify(|e| *e = serde_json::value::Value::String(dest_path.to_string()));
             storage.clone()
         })
-        .ok_or_else(|| {
-            (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                "failed to parse current storage configuration".to_string(),
-            )
-        })?;
+        .ok_or_else(|| ApiError::internal("failed to parse current storage configuration"))?;
 
     state
         .sys
         .send(crate::SysMessage::Update(Box::new(
             json!({"storage": storage})
                 .as_object()
-                .ok_or_else(|| {
-                    (
-                        axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                        "failed to create storage update message".to_string(),
-                    )
-                })?
+                .ok_or_else(|| ApiError::internal("failed to create storage update message"))?
                 .clone(),
         )))
-        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        .map_err(ApiError::internal)?;
 
     Ok(axum::Json(storage.into()))
 }
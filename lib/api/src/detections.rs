@@ -2,36 +2,165 @@
 //!
 //! Provides CRUD operations for Sigma rules:
 //! - GET /api/1/detections - List all rules (summary view)
+//! - GET /api/1/detections/coverage - MITRE ATT&CK coverage summary
 //! - GET /api/1/detections/:id - Get full rule details
-//! - PATCH /api/1/detections/:id - Enable/disable rule
+//! - PATCH /api/1/detections/:id - Enable/disable rule, override severity level
+//! - PUT /api/1/detections/:id - Rewrite a rule in its file
+//! - DELETE /api/1/detections/:id - Remove a rule from its file
 //! - POST /api/1/detections - Upload new YAML rule
+//! - POST /api/1/detections/sync - Fetch git rule sources; see [`crate::rules`]
+//! - /api/1/detections/replay - Replay stored events; see [`crate::replay`]
 //!
 //! Rules are stored in-memory in SigmaCollection and persisted to disk.
+//! Uploaded rules are saved when the only rule source is a local directory.
 //! Changes affect running detection engine immediately via RwLock.
+//!
+//! Listed rules carry their `origin`, the file and pack they were loaded
+//! from (see [`crate::rules`]), which PUT and DELETE edit. Those refuse
+//! vendor rules, from git sources, without `?force=true`. A rewritten rule
+//! is reloaded at once; a removed one is disabled until restart.
+
+use std::{collections::BTreeMap, sync::Arc};
 
 use anyhow::Result;
-use axum::{extract::State, routing::get};
+use arc_swap::ArcSwapOption;
+use axum::{
+    extract::{Query, State},
+    routing::get,
+};
+use serde::{Deserialize, Deserializer};
+use striem_common::{
+    attack::{attack_tags, parse_technique, tactic},
+    detection::ocsf_rules,
+    severity::Severity,
+};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{ApiState, error::ApiError, rules::RuleOrigin};
+
+/// Computed ATT&CK coverage, cleared whenever the rule set changes
+pub(crate) type CoverageCache = Arc<ArcSwapOption<serde_json::Value>>;
+
+#[derive(Default, serde::Serialize)]
+struct CoverageEntry {
+    rule_count: usize,
+    enabled_count: usize,
+    rule_ids: Vec<String>,
+}
+
+impl CoverageEntry {
+    fn add(&mut self, id: &str, enabled: bool) {
+        self.rule_count += 1;
+        if enabled {
+            self.enabled_count += 1;
+        }
+        self.rule_ids.push(id.to_string());
+    }
+}
+
+/// Build the tactic → technique → coverage tree from serialized rules.
+///
+/// Techniques on rules without a tactic tag are filed under `unknown`;
+/// rules with no parseable technique tag land in `untagged`.
+pub(crate) fn coverage(rules: &[serde_json::Value]) -> serde_json::Value {
+    let mut tactics: BTreeMap<String, BTreeMap<String, CoverageEntry>> = BTreeMap::new();
+    let mut untagged = CoverageEntry::default();
+
+    for rule in rules {
+        let Some(id) = rule.get("id").and_then(|v| v.as_str()) else {
+            continue;
+        };
+        let enabled = rule
+            .get("enabled")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true);
+
+        let tags = rule
+            .get("tags")
+            .and_then(|v| v.as_array())
+            .map(|tags| attack_tags(tags.iter().filter_map(|t| t.as_str())))
+            .unwrap_or_default();
+
+        let techniques = tags
+            .iter()
+            .filter_map(|t| parse_technique(t))
+            .collect::<Vec<_>>();
+        if techniques.is_empty() {
+            untagged.add(id, enabled);
+            continue;
+        }
+
+        let mut rule_tactics = tags
+            .iter()
+            .filter(|t| tactic(t).is_some())
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        if rule_tactics.is_empty() {
+            rule_tactics.push("unknown");
+        }
+
+        for tactic in rule_tactics {
+            let tactic = tactics.entry(tactic.to_string()).or_default();
+            for technique in &techniques {
+                tactic
+                    .entry(technique.clone())
+                    .or_default()
+                    .add(id, enabled);
+            }
+        }
+    }
 
-use crate::ApiState;
+    serde_json::json!({
+        "tactics": tactics,
+        "untagged": untagged,
+    })
+}
+
+/// Replace the serialized YAML `level` with the effective level,
+/// keeping the YAML value as `original_level`, and add the rule's `origin`.
+fn apply_level(rule: &mut serde_json::Value, state: &ApiState) {
+    let Some(obj) = rule.as_object_mut() else {
+        return;
+    };
+    let id = obj.get("id").and_then(|v| v.as_str()).unwrap_or_default();
+    let original = obj.get("level").cloned().unwrap_or_default();
+    let origin = serde_json::json!(state.origins.load().get(id));
+    if let Some(level) = state.levels.load().get(id) {
+        obj.insert("level".to_string(), serde_json::json!(level.to_string()));
+    }
+    obj.insert("original_level".to_string(), original);
+    obj.insert("origin".to_string(), origin);
+}
 
 /// List all detection rules with summary information.
 ///
 /// # Response Format
-/// Returns array of rule summaries with: id, title, description, enabled, level, logsource.
+/// Returns array of rule summaries with: id, title, description, enabled, level, logsource
+/// and origin, `null` for a rule not loaded from a file.
 /// Full rule details (detection logic, tags, etc.) omitted for performance.
 ///
 /// # Error Handling
 /// Logs serialization errors but returns empty array rather than 500.
 /// This prevents one malformed rule from breaking the entire list view.
-async fn list_rules(
+#[utoipa::path(
+    get,
+    path = "/api/1/detections",
+    tag = "detections",
+    responses(
+        (status = 200, description = "Rule summaries", body = [Object]),
+        (status = 500, description = "Rules could not be serialized", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn list_rules(
     State(state): State<ApiState>,
-) -> Result<axum::Json<Vec<serde_json::Value>>, (axum::http::StatusCode, String)> {
+) -> Result<axum::Json<Vec<serde_json::Value>>, ApiError> {
     let rules = serde_json::to_value(&*state.detections.read().await)
-        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        .as_array()
+        .map_err(ApiError::internal)?
+        .as_array_mut()
         .map(|r| {
-            r.iter()
+            r.iter_mut()
                 .flat_map(|rule| {
+                    apply_level(rule, &state);
                     rule.as_object().and_then(|obj| {
                         Some(serde_json::json!({
                             "id": obj.get("id")?,
@@ -39,7 +168,9 @@ async fn list_rules(
                             "description": obj.get("description")?,
                             "enabled": obj.get("enabled")?.as_bool().unwrap_or(true),
                             "level": obj.get("level")?,
+                            "original_level": obj.get("original_level")?,
                             "logsource": obj.get("logsource")?,
+                            "origin": obj.get("origin")?,
                         }))
                     })
                 })
@@ -50,50 +181,147 @@ async fn list_rules(
     Ok(axum::Json(rules))
 }
 
-async fn get_rule(
+/// ATT&CK coverage summary for the loaded rule set.
+///
+/// The result is cached until rules are added, patched, or reloaded, so
+/// the UI can call this on every page load.
+#[utoipa::path(
+    get,
+    path = "/api/1/detections/coverage",
+    tag = "detections",
+    responses(
+        (status = 200, description = "Rule counts by tactic and technique", body = Object),
+        (status = 500, description = "Rules could not be serialized", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_coverage(
+    State(state): State<ApiState>,
+) -> Result<axum::Json<serde_json::Value>, ApiError> {
+    if let Some(cached) = state.coverage.load_full() {
+        return Ok(axum::Json((*cached).clone()));
+    }
+
+    let rules =
+        serde_json::to_value(&*state.detections.read().await).map_err(ApiError::internal)?;
+    let coverage = coverage(rules.as_array().map(Vec::as_slice).unwrap_or_default());
+    state.coverage.store(Some(Arc::new(coverage.clone())));
+
+    Ok(axum::Json(coverage))
+}
+
+/// A rule in full, with any severity override applied
+#[utoipa::path(
+    get,
+    path = "/api/1/detections/{id}",
+    tag = "detections",
+    params(("id" = String, Path, description = "Sigma rule id")),
+    responses(
+        (status = 200, description = "The Sigma rule", body = Object),
+        (status = 404, description = "No such rule", body = crate::error::ErrorBody),
+        (status = 500, description = "Rule could not be serialized", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_rule(
     State(state): State<ApiState>,
     axum::extract::Path(rule_id): axum::extract::Path<String>,
-) -> Result<axum::Json<serde_json::Value>, (axum::http::StatusCode, String)> {
+) -> Result<axum::Json<serde_json::Value>, ApiError> {
     let detections = state.detections.read().await;
-    let rule = detections.get(&rule_id).ok_or_else(|| {
-        (
-            axum::http::StatusCode::NOT_FOUND,
-            format!("Rule with id {} not found", rule_id),
-        )
-    })?;
+    let rule = detections
+        .get(&rule_id)
+        .ok_or_else(|| ApiError::NotFound(format!("Rule with id {} not found", rule_id)))?;
 
-    let rule_json = serde_json::to_value(rule)
-        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut rule_json = serde_json::to_value(rule).map_err(ApiError::internal)?;
+    apply_level(&mut rule_json, &state);
 
     Ok(axum::Json(rule_json))
 }
 
-#[derive(serde::Deserialize)]
-struct PatchRulePayload {
-    enabled: bool,
+/// Distinguishes an absent field (`None`) from an explicit `null` (`Some(None)`)
+fn explicit_null<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct PatchRulePayload {
+    enabled: Option<bool>,
+    /// Severity override; `null` clears the override and restores the YAML level
+    #[serde(default, deserialize_with = "explicit_null")]
+    #[schema(value_type = Option<String>)]
+    level: Option<Option<String>>,
 }
 
-async fn patch_rule(
+/// Enable or disable a rule, or override its severity
+#[utoipa::path(
+    patch,
+    path = "/api/1/detections/{id}",
+    tag = "detections",
+    params(("id" = String, Path, description = "Sigma rule id")),
+    request_body = PatchRulePayload,
+    responses(
+        (status = 200, description = "The updated rule", body = Object),
+        (status = 400, description = "Unknown severity level", body = crate::error::ErrorBody),
+        (status = 404, description = "No such rule", body = crate::error::ErrorBody),
+        (status = 500, description = "Update could not be saved", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn patch_rule(
     State(state): State<ApiState>,
     axum::extract::Path(rule_id): axum::extract::Path<String>,
     axum::extract::Json(payload): axum::extract::Json<PatchRulePayload>,
-) -> Result<axum::Json<serde_json::Value>, (axum::http::StatusCode, String)> {
+) -> Result<axum::Json<serde_json::Value>, ApiError> {
     let detections = state.detections.read().await;
-    let rule = detections.get(&rule_id).ok_or_else(|| {
-        (
-            axum::http::StatusCode::NOT_FOUND,
-            format!("Rule with id {} not found", rule_id),
-        )
-    })?;
+    let rule = detections
+        .get(&rule_id)
+        .ok_or_else(|| ApiError::NotFound(format!("Rule with id {} not found", rule_id)))?;
 
-    if payload.enabled {
-        rule.enable();
-    } else {
-        rule.disable();
+    let level = match payload.level {
+        Some(Some(ref level)) => Some(Some(
+            level.parse::<Severity>().map_err(ApiError::BadRequest)?,
+        )),
+        Some(None) => Some(None),
+        None => None,
+    };
+
+    match payload.enabled {
+        Some(true) => {
+            rule.enable();
+        }
+        Some(false) => {
+            rule.disable();
+        }
+        None => {}
     }
 
-    let rule_json = serde_json::to_value(rule)
-        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if let Some(level) = level {
+        let mut overrides = (**state.levels.load()).clone();
+        match level {
+            Some(level) => overrides.insert(rule_id.clone(), level),
+            None => overrides.remove(&rule_id),
+        };
+        state.levels.store(Arc::new(overrides));
+    }
+
+    let mut rule_json = serde_json::to_value(rule).map_err(ApiError::internal)?;
+
+    if let Some(db) = state.db.as_ref() {
+        let mut conn = db.get().map_err(ApiError::database)?;
+        let enabled = rule_json
+            .get("enabled")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true);
+        let level = state.levels.load().get(&rule_id).map(|l| l.to_string());
+        crate::persist::set_rule_state(&mut conn, &rule_id, enabled, level.as_deref())
+            .map_err(ApiError::database)?;
+    }
+
+    apply_level(&mut rule_json, &state);
+    if payload.enabled.is_some() {
+        state.coverage.store(None);
+    }
 
     Ok(axum::Json(rule_json))
 }
@@ -112,44 +340,185 @@ async fn patch_rule(
 /// # Side Effects
 /// Adds rule to in-memory collection (immediately available for detection)
 /// and persists to disk for reload on restart.
-async fn post_rule(
+#[utoipa::path(
+    post,
+    path = "/api/1/detections",
+    tag = "detections",
+    request_body(content = String, description = "Sigma rule YAML", content_type = "application/x-yaml"),
+    responses(
+        (status = 200, description = "Id of the new rule", body = String),
+        (status = 400, description = "Invalid rule YAML", body = crate::error::ErrorBody),
+        (status = 409, description = "A rule with this id exists", body = crate::error::ErrorBody),
+        (status = 500, description = "Rule could not be added or saved", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn post_rule(
     State(state): State<ApiState>,
     body: String,
-) -> Result<axum::Json<String>, (axum::http::StatusCode, String)> {
+) -> Result<axum::Json<String>, ApiError> {
     // Parse the YAML content
-    let rule: sigmars::SigmaRule = serde_yaml::from_str(&body).map_err(|e| {
-        (
-            axum::http::StatusCode::BAD_REQUEST,
-            format!("Invalid YAML: {}", e),
-        )
-    })?;
+    let rule: sigmars::SigmaRule = serde_yaml::from_str(&body)
+        .map_err(|e| ApiError::BadRequest(format!("Invalid YAML: {}", e)))?;
     let id = rule.id.clone();
     let mut detections = state.detections.write().await;
     if detections.get(&id).is_some() {
-        return Err((
-            axum::http::StatusCode::CONFLICT,
-            format!("Rule with id {} already exists", rule.id),
-        ));
+        return Err(ApiError::Conflict(format!(
+            "Rule with id {} already exists",
+            rule.id
+        )));
     }
-    detections
-        .add(rule)
-        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-    if let Some(striem_config::StringOrList::String(dir)) = &state.config.load().detections {
-        let path = format!("{}/{}.yaml", dir, id);
-        std::fs::write(&path, body).map_err(|e| {
-            (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to write rule to disk: {}", e),
-            )
-        })?;
+    detections.add(rule).map_err(ApiError::internal)?;
+    state.ocsf.store(Arc::new(ocsf_rules(&detections)));
+    drop(detections);
+    state.coverage.store(None);
+
+    if let Some(dir) = state
+        .config
+        .load()
+        .detections
+        .as_ref()
+        .and_then(|d| d.upload_dir())
+    {
+        let path = dir.join(format!("{}.yaml", id));
+        std::fs::write(&path, body)
+            .map_err(|e| ApiError::internal(format!("Failed to write rule to disk: {}", e)))?;
+        let mut origins = (**state.origins.load()).clone();
+        origins.insert(id.clone(), crate::rules::uploaded(&dir, &id));
+        state.origins.store(Arc::new(origins));
     }
 
     Ok(axum::Json(id))
 }
 
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub(crate) struct ChangeParams {
+    /// Change a vendor rule anyway; its next sync undoes the change
+    #[serde(default)]
+    force: bool,
+}
+
+/// The origin of rule `id`, if it may be changed
+async fn changeable(state: &ApiState, id: &str, force: bool) -> Result<RuleOrigin, ApiError> {
+    if state.detections.read().await.get(id).is_none() {
+        return Err(ApiError::NotFound(format!("Rule with id {} not found", id)));
+    }
+    let origin = state
+        .origins
+        .load()
+        .get(id)
+        .cloned()
+        .ok_or_else(|| ApiError::Conflict(format!("Rule {} was not loaded from a file", id)))?;
+    if origin.vendor && !force {
+        return Err(ApiError::Forbidden(format!(
+            "Rule {} is part of the vendor pack {}; pass force=true to change it",
+            id, origin.pack
+        )));
+    }
+    Ok(origin)
+}
+
+/// Rewrite a rule in the file it was loaded from.
+///
+/// Only the rule's own document is replaced, so the other rules of a
+/// collection file are kept. The running rule set is rebuilt with the new
+/// version.
+#[utoipa::path(
+    put,
+    path = "/api/1/detections/{id}",
+    tag = "detections",
+    params(("id" = String, Path, description = "Sigma rule id"), ChangeParams),
+    request_body(content = String, description = "Sigma rule YAML", content_type = "application/x-yaml"),
+    responses(
+        (status = 200, description = "The file the rule was written to", body = RuleOrigin),
+        (status = 400, description = "Invalid rule YAML, or a different id", body = crate::error::ErrorBody),
+        (status = 403, description = "A vendor rule, without `force`", body = crate::error::ErrorBody),
+        (status = 404, description = "No such rule", body = crate::error::ErrorBody),
+        (status = 409, description = "The rule has no file", body = crate::error::ErrorBody),
+        (status = 500, description = "Rule could not be written", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn put_rule(
+    State(state): State<ApiState>,
+    axum::extract::Path(rule_id): axum::extract::Path<String>,
+    Query(params): Query<ChangeParams>,
+    body: String,
+) -> Result<axum::Json<RuleOrigin>, ApiError> {
+    let rule: sigmars::SigmaRule = serde_yaml::from_str(&body)
+        .map_err(|e| ApiError::BadRequest(format!("Invalid YAML: {}", e)))?;
+    if rule.id != rule_id {
+        return Err(ApiError::BadRequest(format!(
+            "Rule id {} doesn't match {}",
+            rule.id, rule_id
+        )));
+    }
+    let origin = changeable(&state, &rule_id, params.force).await?;
+    let mut detections = state.detections.write().await;
+    let replaced = crate::rules::replaced(&detections, rule)
+        .await
+        .map_err(|e| ApiError::internal(format!("{:#}", e)))?;
+    crate::rules::rewrite_rule(&origin.path, &rule_id, Some(&body))
+        .map_err(|e| ApiError::internal(format!("{:#}", e)))?;
+    *detections = replaced;
+    state.ocsf.store(Arc::new(ocsf_rules(&detections)));
+    drop(detections);
+    state.coverage.store(None);
+
+    Ok(axum::Json(origin))
+}
+
+/// Remove a rule from the file it was loaded from, and disable it.
+///
+/// The other rules of a collection file are kept; a file left without
+/// rules is deleted.
+#[utoipa::path(
+    delete,
+    path = "/api/1/detections/{id}",
+    tag = "detections",
+    params(
+        ("id" = String, Path, description = "Sigma rule id"),
+        ChangeParams,
+        ("X-Confirm-Token" = Option<String>, Header, description = "Token from the 202 response, with `api.require_confirmation`"),
+    ),
+    responses(
+        (status = 200, description = "The rule was removed"),
+        (status = 202, description = "Confirmation required; repeat with `X-Confirm-Token`", body = crate::confirm::Confirmation),
+        (status = 403, description = "A vendor rule, without `force`", body = crate::error::ErrorBody),
+        (status = 404, description = "No such rule", body = crate::error::ErrorBody),
+        (status = 409, description = "The rule has no file", body = crate::error::ErrorBody),
+        (status = 500, description = "Rule could not be removed", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn delete_rule(
+    State(state): State<ApiState>,
+    axum::extract::Path(rule_id): axum::extract::Path<String>,
+    Query(params): Query<ChangeParams>,
+) -> Result<axum::Json<()>, ApiError> {
+    let origin = changeable(&state, &rule_id, params.force).await?;
+    crate::rules::rewrite_rule(&origin.path, &rule_id, None)
+        .map_err(|e| ApiError::internal(format!("{:#}", e)))?;
+
+    if let Some(rule) = state.detections.read().await.get(&rule_id) {
+        rule.disable();
+    }
+    let mut origins = (**state.origins.load()).clone();
+    origins.remove(&rule_id);
+    state.origins.store(Arc::new(origins));
+    state.coverage.store(None);
+
+    Ok(axum::Json(()))
+}
+
 pub fn create_router() -> axum::Router<ApiState> {
     axum::Router::new()
         .route("/", get(list_rules).post(post_rule))
-        .route("/{id}", get(get_rule).patch(patch_rule))
+        .route("/coverage", get(get_coverage))
+        .route("/sync", axum::routing::post(crate::rules::post_sync))
+        .nest("/replay", crate::replay::create_router())
+        .route(
+            "/{id}",
+            get(get_rule)
+                .patch(patch_rule)
+                .put(put_rule)
+                .delete(delete_rule),
+        )
 }
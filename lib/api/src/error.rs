@@ -0,0 +1,197 @@
+//! Errors returned by API handlers.
+//!
+//! Every error response has the JSON body
+//! `{"error": {"code", "message", "detail"?}}`. Client errors carry their
+//! message as is. `Database` and `Internal` errors are logged in full with a
+//! correlation id; the client only gets a generic message and that id in
+//! `detail.correlation_id`, so DuckDB errors and file paths don't leak.
+//! `detail.request_id` is the request's `X-Request-Id`.
+
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::{Value, json};
+use tracing::error;
+use utoipa::ToSchema;
+
+#[derive(Debug)]
+pub(crate) enum ApiError {
+    NotFound(String),
+    BadRequest(String),
+    Conflict(String),
+    Unauthorized(String),
+    Forbidden(String),
+    /// A cursor or other resource that has expired
+    Gone(String),
+    TooManyRequests(String),
+    /// A required component (database, storage) isn't available
+    Unavailable(String),
+    /// The endpoint needs a feature this build doesn't have
+    NotImplemented(String),
+    Database(anyhow::Error),
+    Internal(anyhow::Error),
+}
+
+/// Body of every error response
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct ErrorBody {
+    error: ErrorObject,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ErrorObject {
+    /// Stable, machine-readable error kind, e.g. `not_found`
+    code: &'static str,
+    message: String,
+    /// `request_id` of the request, and `correlation_id` of server-side
+    /// errors, to find them in the logs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<Value>,
+}
+
+impl ApiError {
+    /// An internal error from anything displayable
+    pub(crate) fn internal(e: impl std::fmt::Display) -> Self {
+        ApiError::Internal(anyhow::anyhow!("{}", e))
+    }
+
+    /// Refusal of an endpoint that needs the `feature` this build lacks
+    pub(crate) fn not_built(feature: &str) -> Self {
+        ApiError::NotImplemented(format!(
+            "this endpoint needs StrIEM built with the `{}` feature",
+            feature
+        ))
+    }
+
+    /// A database error from anything displayable
+    pub(crate) fn database(e: impl std::fmt::Display) -> Self {
+        ApiError::Database(anyhow::anyhow!("{}", e))
+    }
+
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::Gone(_) => StatusCode::GONE,
+            ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::NotImplemented(_) => StatusCode::NOT_IMPLEMENTED,
+            ApiError::Database(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub(crate) fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::Gone(_) => "gone",
+            ApiError::TooManyRequests(_) => "too_many_requests",
+            ApiError::Unavailable(_) => "unavailable",
+            ApiError::NotImplemented(_) => "not_implemented",
+            ApiError::Database(_) => "database_error",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// The response body, logging server-side errors. Inside a request the
+    /// body carries its id in `detail.request_id`.
+    pub(crate) fn body(&self) -> ErrorBody {
+        let request_id = crate::trace::current();
+        let mut detail = serde_json::Map::new();
+        if let Some(id) = &request_id {
+            detail.insert("request_id".to_string(), json!(id));
+        }
+        let message = match self {
+            ApiError::NotFound(m)
+            | ApiError::BadRequest(m)
+            | ApiError::Conflict(m)
+            | ApiError::Unauthorized(m)
+            | ApiError::Forbidden(m)
+            | ApiError::Gone(m)
+            | ApiError::TooManyRequests(m)
+            | ApiError::Unavailable(m)
+            | ApiError::NotImplemented(m) => m.clone(),
+            ApiError::Database(e) | ApiError::Internal(e) => {
+                let id = uuid::Uuid::now_v7();
+                error!(
+                    request_id = request_id.as_deref().unwrap_or("-"),
+                    "{} {}: {:#}",
+                    self.code(),
+                    id,
+                    e
+                );
+                detail.insert("correlation_id".to_string(), json!(id));
+                match self {
+                    ApiError::Database(_) => "database error",
+                    _ => "internal server error",
+                }
+                .to_string()
+            }
+        };
+        ErrorBody {
+            error: ErrorObject {
+                code: self.code(),
+                message,
+                detail: (!detail.is_empty()).then_some(Value::Object(detail)),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::NotFound(m)
+            | ApiError::BadRequest(m)
+            | ApiError::Conflict(m)
+            | ApiError::Unauthorized(m)
+            | ApiError::Forbidden(m)
+            | ApiError::Gone(m)
+            | ApiError::TooManyRequests(m)
+            | ApiError::Unavailable(m)
+            | ApiError::NotImplemented(m) => write!(f, "{}", m),
+            ApiError::Database(e) | ApiError::Internal(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status(), Json(self.body())).into_response()
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        ApiError::Internal(e)
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(e: serde_json::Error) -> Self {
+        ApiError::Internal(e.into())
+    }
+}
+
+#[cfg(feature = "duckdb")]
+impl From<duckdb::Error> for ApiError {
+    fn from(e: duckdb::Error) -> Self {
+        ApiError::Database(e.into())
+    }
+}
+
+#[cfg(any(feature = "duckdb", feature = "sqlite"))]
+impl From<r2d2::Error> for ApiError {
+    fn from(e: r2d2::Error) -> Self {
+        ApiError::Database(e.into())
+    }
+}
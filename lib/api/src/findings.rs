@@ -0,0 +1,150 @@
+//! Reading detection findings without DuckDB.
+//!
+//! Builds without the `duckdb` feature list alerts by reading the findings
+//! Parquet files directly: files are picked by the manifest, or by their
+//! UUIDv7 names, for the requested time range, only the columns an
+//! [`Alert`] needs are read, and filtering, ordering and paging happen in
+//! Rust. Triage state lives in the database, so every finding is `open`.
+//! Grouping, summaries and object storage still need DuckDB.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use striem_common::event::tenant_label;
+use striem_storage::reader;
+
+use crate::alerts::{Alert, AlertQuery, AlertStatus, finding_file};
+
+/// Columns read to list a finding
+const ALERT_COLUMNS: &[&str] = &[
+    "severity",
+    "observables",
+    "metadata.uid",
+    "metadata.labels",
+    "finding_info.title",
+];
+
+/// Findings files below `basepath` that may hold findings in the range
+fn files(basepath: &Path, start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<PathBuf> {
+    let findings_path = basepath.join("findings/detection_finding");
+    if !findings_path.exists() {
+        return Vec::new();
+    }
+    striem_storage::manifest::files_for_range(&findings_path, start, end).unwrap_or_default()
+}
+
+/// Whether the finding in `row` passes the filters of `query`
+fn matches(row: &Map<String, Value>, query: &AlertQuery) -> bool {
+    let text = |v: Option<&Value>| v.and_then(|v| v.as_str()).unwrap_or_default().to_string();
+
+    let severity = text(row.get("severity")).to_lowercase();
+    if !query.severity.is_empty() && !query.severity.iter().any(|s| s.to_string() == severity) {
+        return false;
+    }
+    let title = text(row.get("finding_info").and_then(|f| f.get("title"))).to_lowercase();
+    if !query
+        .title_contains
+        .iter()
+        .all(|t| title.contains(&t.to_lowercase()))
+    {
+        return false;
+    }
+    if let Some(observable) = &query.observable
+        && !row
+            .get("observables")
+            .is_some_and(|o| o.to_string().contains(observable.as_str()))
+    {
+        return false;
+    }
+    if !query.status.is_empty() && !query.status.contains(&AlertStatus::Open) {
+        return false;
+    }
+    if let Some(tenant) = &query.tenant {
+        let label = Value::from(tenant_label(tenant));
+        return row
+            .get("metadata")
+            .and_then(|m| m.get("labels"))
+            .and_then(|l| l.as_array())
+            .is_some_and(|labels| labels.contains(&label));
+    }
+    true
+}
+
+/// The list view of the finding in `row`, read from `file`
+fn alert(basepath: &Path, file: &Path, time: i64, row: &Map<String, Value>) -> Option<Alert> {
+    let text = |v: Option<&Value>| v.and_then(|v| v.as_str()).unwrap_or_default().to_string();
+    let fname = file.strip_prefix(basepath).unwrap_or(file);
+    Some(Alert {
+        id: text(row.get("metadata").and_then(|m| m.get("uid"))),
+        time: DateTime::from_timestamp_millis(time)?.to_rfc3339(),
+        severity: text(row.get("severity")),
+        title: text(row.get("finding_info").and_then(|f| f.get("title"))),
+        status: AlertStatus::Open,
+        assignee: None,
+        extra: [
+            ("_file".to_string(), Value::from(fname.to_string_lossy())),
+            (
+                "observables".to_string(),
+                row.get("observables")
+                    .map(|o| Value::from(o.to_string()))
+                    .unwrap_or_default(),
+            ),
+        ]
+        .into(),
+    })
+}
+
+/// One page of findings matching `query`, newest first, along with the
+/// total number of matching findings
+pub(crate) fn read_alerts(basepath: &Path, query: &AlertQuery) -> Result<(Vec<Alert>, u64)> {
+    let mut alerts = Vec::new();
+    for file in files(basepath, query.start, query.end) {
+        for (time, row) in reader::read_rows(&file, Some(ALERT_COLUMNS), query.start, query.end)? {
+            if matches(&row, query)
+                && let Some(alert) = alert(basepath, &file, time, &row)
+            {
+                alerts.push((time, alert));
+            }
+        }
+    }
+    alerts.sort_by(|(a, x), (b, y)| b.cmp(a).then_with(|| x.id.cmp(&y.id)));
+
+    let total = alerts.len() as u64;
+    Ok((
+        alerts
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit)
+            .map(|(_, alert)| alert)
+            .collect(),
+        total,
+    ))
+}
+
+/// The finding with `id` in full, from `fname` (relative to `basepath`, as
+/// listed in `_file`) if given, else from any findings file
+pub(crate) fn find(basepath: &Path, id: &str, fname: Option<&str>) -> Result<Value> {
+    let files = match fname.map(str::trim).filter(|f| !f.is_empty()) {
+        Some(file) => vec![finding_file(basepath, file)?],
+        None => files(basepath, DateTime::<Utc>::MIN_UTC, DateTime::<Utc>::MAX_UTC),
+    };
+    for file in files {
+        let rows = reader::read_rows(
+            &file,
+            None,
+            DateTime::<Utc>::MIN_UTC,
+            DateTime::<Utc>::MAX_UTC,
+        )?;
+        if let Some((_, row)) = rows.into_iter().find(|(_, row)| {
+            row.get("metadata")
+                .and_then(|m| m.get("uid"))
+                .and_then(|u| u.as_str())
+                == Some(id)
+        }) {
+            return Ok(Value::Object(row));
+        }
+    }
+    Err(anyhow!("alert {} not found", id))
+}
@@ -0,0 +1,274 @@
+//! Liveness and readiness probes.
+//!
+//! - GET /health/live - The process is up
+//! - GET /health/ready - StrIEM can take events: 200, or 503 with the
+//!   failing checks
+//! - GET /health - Same as `/health/live`, for existing probes
+//!
+//! Readiness checks that a database connection can be acquired, that every
+//! local storage base path is writable, that the input listener accepts
+//! connections, when this process runs it, and that detection rules are
+//! loaded, plus, with
+//! `api.health.ready_requires_events_within_secs`, that events are still
+//! arriving. Checks that don't apply to the configuration are left out.
+//! Each check gives up after [`HEALTH_CHECK_TIMEOUT_MS`], and the result is
+//! reused for [`HEALTH_READY_CACHE_MS`] so frequent probes stay cheap.
+
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{Json, extract::State, http::StatusCode};
+use chrono::Utc;
+use serde::Serialize;
+use striem_common::{metrics, prelude::*};
+use utoipa::ToSchema;
+
+use crate::ApiState;
+
+/// When the API started; ingestion is judged from here until the first event
+pub(crate) static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// Readiness of one API server: whether there's an input listener to check,
+/// and the last result
+#[derive(Debug, Default)]
+pub(crate) struct Health {
+    /// This process runs the input listener; the standalone API doesn't
+    listener: bool,
+    ready: Mutex<Option<(Instant, Readiness)>>,
+}
+
+impl Health {
+    pub(crate) fn new(listener: bool) -> Self {
+        Health {
+            listener,
+            ready: Mutex::new(None),
+        }
+    }
+}
+
+/// Outcome of one readiness check
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub(crate) struct Check {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Check {
+    fn ok() -> Self {
+        Check {
+            ok: true,
+            error: None,
+        }
+    }
+
+    fn failed(error: impl ToString) -> Self {
+        Check {
+            ok: false,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+impl<E: ToString> From<Result<(), E>> for Check {
+    fn from(result: Result<(), E>) -> Self {
+        match result {
+            Ok(()) => Check::ok(),
+            Err(e) => Check::failed(e),
+        }
+    }
+}
+
+/// Readiness and the checks it was decided by, by name
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub(crate) struct Readiness {
+    pub ready: bool,
+    #[schema(value_type = BTreeMap<String, Check>)]
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+/// Run `check` on a blocking thread, failing it if it takes too long
+async fn bounded<F>(check: F) -> Check
+where
+    F: FnOnce() -> Result<(), String> + Send + 'static,
+{
+    let timeout = Duration::from_millis(HEALTH_CHECK_TIMEOUT_MS);
+    match tokio::time::timeout(timeout, tokio::task::spawn_blocking(check)).await {
+        Ok(Ok(result)) => result.into(),
+        Ok(Err(e)) => Check::failed(e),
+        Err(_) => Check::failed(format!("no answer within {}ms", HEALTH_CHECK_TIMEOUT_MS)),
+    }
+}
+
+async fn database(pool: crate::Pool) -> Check {
+    bounded(move || {
+        let conn = pool
+            .get_timeout(Duration::from_millis(HEALTH_CHECK_TIMEOUT_MS))
+            .map_err(|e| e.to_string())?;
+        conn.execute_batch("SELECT 1").map_err(|e| e.to_string())
+    })
+    .await
+}
+
+/// Create and remove a probe file in each of `bases`
+async fn storage(bases: Vec<PathBuf>) -> Check {
+    bounded(move || {
+        for base in bases {
+            let probe = base.join(".striem-ready");
+            std::fs::create_dir_all(&base)
+                .and_then(|_| std::fs::write(&probe, b""))
+                .and_then(|_| std::fs::remove_file(&probe))
+                .map_err(|e| format!("{} is not writable: {}", base.display(), e))?;
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Connect to the input listener; an unspecified address is reached on
+/// loopback
+async fn listener(mut address: SocketAddr) -> Check {
+    if address.ip().is_unspecified() {
+        address.set_ip(match address.ip() {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        });
+    }
+    let timeout = Duration::from_millis(HEALTH_CHECK_TIMEOUT_MS);
+    match tokio::time::timeout(timeout, tokio::net::TcpStream::connect(address)).await {
+        Ok(Ok(_)) => Check::ok(),
+        Ok(Err(e)) => Check::failed(format!("{} is not accepting connections: {}", address, e)),
+        Err(_) => Check::failed(format!("{} did not answer", address)),
+    }
+}
+
+/// Whether an event arrived within `within`, or StrIEM started that recently
+fn events(within: Duration) -> Check {
+    let since = match metrics::value("striem_last_event_timestamp_seconds", &[]) {
+        Some(last) => {
+            Duration::from_secs_f64((Utc::now().timestamp_millis() as f64 / 1000.0 - last).max(0.0))
+        }
+        None => STARTED.elapsed(),
+    };
+    if since <= within {
+        Check::ok()
+    } else {
+        Check::failed(format!("no events for {}s", since.as_secs()))
+    }
+}
+
+/// Run every check that applies to the current configuration
+pub(crate) async fn readiness(state: &ApiState) -> Readiness {
+    let config = state.config.load_full();
+    let mut checks = BTreeMap::new();
+
+    let database = async {
+        match state.db.clone() {
+            Some(pool) => Some(database(pool).await),
+            None => None,
+        }
+    };
+    let bases = config
+        .storage
+        .as_ref()
+        .filter(|s| s.uri.is_none())
+        .map(|s| s.roots());
+    let storage = async {
+        match bases {
+            Some(bases) => Some(storage(bases).await),
+            None => None,
+        }
+    };
+    let listener = async {
+        match state.health.listener {
+            true => Some(listener(config.input.address()).await),
+            false => None,
+        }
+    };
+    let (database, storage, listener) = tokio::join!(database, storage, listener);
+    if let Some(database) = database {
+        checks.insert("database", database);
+    }
+    if let Some(storage) = storage {
+        checks.insert("storage", storage);
+    }
+    if let Some(listener) = listener {
+        checks.insert("listener", listener);
+    }
+
+    if config.detections.is_some() {
+        let rules = state.detections.read().await.len();
+        checks.insert(
+            "rules",
+            if rules > 0 {
+                Check::ok()
+            } else {
+                Check::failed("no detection rules loaded")
+            },
+        );
+    }
+    if let Some(secs) = config.api.health.ready_requires_events_within_secs {
+        checks.insert("events", events(Duration::from_secs(secs)));
+    }
+
+    Readiness {
+        ready: checks.values().all(|check| check.ok),
+        checks,
+    }
+}
+
+/// [`readiness`], reusing a result younger than [`HEALTH_READY_CACHE_MS`]
+async fn cached_readiness(state: &ApiState) -> Readiness {
+    let max_age = Duration::from_millis(HEALTH_READY_CACHE_MS);
+    if let Some((at, readiness)) = state
+        .health
+        .ready
+        .lock()
+        .ok()
+        .and_then(|cached| cached.clone())
+        && at.elapsed() < max_age
+    {
+        return readiness;
+    }
+    let readiness = readiness(state).await;
+    if let Ok(mut cached) = state.health.ready.lock() {
+        *cached = Some((Instant::now(), readiness.clone()));
+    }
+    readiness
+}
+
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "system",
+    security(()),
+    responses((status = 200, description = "The process is up"))
+)]
+pub(crate) async fn live() -> StatusCode {
+    StatusCode::OK
+}
+
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "system",
+    security(()),
+    responses(
+        (status = 200, description = "Every check passed", body = Readiness),
+        (status = 503, description = "A check failed; see `checks`", body = Readiness),
+    )
+)]
+pub(crate) async fn ready(State(state): State<ApiState>) -> (StatusCode, Json<Readiness>) {
+    let readiness = cached_readiness(&state).await;
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness))
+}
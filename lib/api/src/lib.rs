@@ -1,22 +1,58 @@
 mod actions;
+mod admin;
 mod alerts;
+mod auth;
+mod compaction;
+mod config;
+mod confirm;
+mod cursor;
+#[cfg(feature = "duckdb")]
+mod db;
 mod destination;
 mod detections;
+mod error;
 pub mod features;
+// built with DuckDB too, for its tests
+#[cfg_attr(feature = "duckdb", allow(dead_code))]
+mod findings;
+mod health;
+mod lists;
+mod notifications;
+mod openapi;
 mod persist;
+mod playbooks;
+mod provision;
 mod query;
+mod replay;
 mod routes;
+mod rules;
 mod server;
 mod sinks;
 mod sources;
+mod stats;
+mod storage;
+mod system;
+mod tail;
+mod tls;
+mod trace;
 mod vector;
+mod views;
+
+#[cfg(test)]
+mod tests;
 
 use arc_swap::ArcSwap;
-use log::error;
+use tracing::error;
 
 use axum::http::HeaderValue;
-pub use server::serve;
-use striem_common::SysMessage;
+pub use config::merge;
+pub use replay::{ReplayProgress, ReplayReport, ReplayRequest, replay, saved_overrides};
+pub use rules::{RuleOrigin, RuleOrigins, load_rules};
+pub use server::{Shared, serve};
+use striem_common::{
+    SysMessage, detection::OcsfRules, event::Event, lists::ReferenceLists, severity::LevelOverrides,
+};
+pub use vector::export_vector_config;
 
 use std::sync::Arc;
 use tokio::sync::RwLock;
@@ -27,7 +63,7 @@ use striem_config::StrIEMConfig;
 use actions::Mcp;
 
 #[cfg(feature = "duckdb")]
-pub(crate) type Pool = r2d2::Pool<duckdb::DuckdbConnectionManager>;
+pub(crate) type Pool = db::Db;
 #[cfg(all(feature = "sqlite", not(feature = "duckdb")))]
 pub(crate) type Pool = r2d2::Pool<sqlite::SqliteConnectionManager>;
 #[cfg(not(any(feature = "duckdb", feature = "sqlite")))]
@@ -36,106 +72,243 @@ pub(crate) type Pool = ();
 #[derive(Clone)]
 pub(crate) struct ApiState {
     pub detections: Arc<RwLock<SigmaCollection>>,
+    /// Ids of the rules written against OCSF fields
+    pub ocsf: OcsfRules,
+    pub levels: LevelOverrides,
+    /// File and pack of each rule loaded from a file
+    pub origins: rules::RuleOrigins,
+    pub lists: ReferenceLists,
+    pub coverage: detections::CoverageCache,
+    pub schema: query::SchemaCache,
     pub actions: Option<Arc<Mcp>>,
     pub db: Option<Pool>,
     pub features: HeaderValue,
     pub sys: tokio::sync::broadcast::Sender<SysMessage>,
+    /// Detection findings emitted by the detection engine
+    pub events: tokio::sync::broadcast::Sender<Arc<Vec<Event>>>,
+    /// Live event and finding rates
+    pub stats: Arc<stats::LiveStats>,
+    /// Recent received events, when this process receives them
+    pub tail: Option<Arc<tail::Tail>>,
+    /// Readiness probe state
+    pub health: Arc<health::Health>,
     pub config: Arc<ArcSwap<StrIEMConfig>>,
 }
 
+/// Quote `s` as a SQL string literal
+#[cfg(feature = "duckdb")]
+fn sql_string(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// `CREATE SECRET` statement giving DuckDB read access to the object store
+/// at `uri`, using explicit keys from `options` when present and the
+/// provider's credential chain otherwise
 #[cfg(feature = "duckdb")]
-pub(crate) fn initdb(config: &StrIEMConfig) -> Option<Pool> {
-    // Create DuckDB connection pool with metadata caching enabled
-    // Metadata cache significantly improves query performance on large Parquet datasets
-    // by avoiding repeated schema reads
-    let mut allowed = vec![
-        "'application_activity'".to_string(),
-        "'discovery'".to_string(),
-        "'findings'".to_string(),
-        "'identity_access_management'".to_string(),
-        "'iam'".to_string(),
-        "'network_activity'".to_string(),
-        "'remediation'".to_string(),
-        "'system_activity'".to_string(),
-        "'unmanned_systems'".to_string(),
+pub(crate) fn storage_secret_sql(
+    uri: &str,
+    options: &std::collections::HashMap<String, String>,
+) -> Option<String> {
+    let (kind, keys): (&str, &[(&str, &str)]) = match uri.split_once("://")?.0 {
+        "s3" | "s3a" => (
+            "s3",
+            &[
+                ("aws_access_key_id", "KEY_ID"),
+                ("aws_secret_access_key", "SECRET"),
+                ("aws_session_token", "SESSION_TOKEN"),
+                ("aws_region", "REGION"),
+                ("aws_endpoint", "ENDPOINT"),
+            ],
+        ),
+        "gs" | "gcs" => ("gcs", &[]),
+        "az" | "azure" | "abfs" | "abfss" => {
+            ("azure", &[("azure_storage_account_name", "ACCOUNT_NAME")])
+        }
+        _ => return None,
+    };
+
+    let mut params = vec![
+        format!("TYPE {}", kind),
+        format!("SCOPE {}", sql_string(uri)),
     ];
+    let explicit = options.contains_key("aws_access_key_id");
+    if !explicit {
+        params.push("PROVIDER credential_chain".to_string());
+    }
+    for (option, param) in keys {
+        if let Some(value) = options.get(*option) {
+            // the credential chain supplies its own keys
+            if explicit || matches!(*param, "REGION" | "ENDPOINT" | "ACCOUNT_NAME") {
+                params.push(format!("{} {}", param, sql_string(value)));
+            }
+        }
+    }
+    Some(format!(
+        "CREATE OR REPLACE SECRET striem_storage ({});",
+        params.join(", ")
+    ))
+}
+
+/// Directories DuckDB may read from besides storage and the database
+/// directory, as OCSF category names relative to the search path
+#[cfg(feature = "duckdb")]
+const ALLOWED_CATEGORIES: &[&str] = &[
+    "application_activity",
+    "discovery",
+    "findings",
+    "identity_access_management",
+    "iam",
+    "network_activity",
+    "remediation",
+    "system_activity",
+    "unmanned_systems",
+];
+
+/// `SET allowed_directories` for `dirs`, each quoted as its own literal
+#[cfg(feature = "duckdb")]
+pub(crate) fn allowed_directories_sql(dirs: &[String]) -> String {
+    format!(
+        "SET allowed_directories = [{}];",
+        dirs.iter()
+            .map(|d| sql_string(d))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+/// Restrict DuckDB file access to `allowed`.
+///
+/// External access has to stay enabled when storage is in an object store,
+/// since DuckDB reads it over HTTP; a secret for the store is set up instead.
+/// The settings are database-wide and fixed once external access is off, so
+/// they're only applied while it's still on.
+#[cfg(feature = "duckdb")]
+fn restrict_access(
+    conn: &duckdb::Connection,
+    allowed: &[String],
+    storage: Option<&striem_config::storage::StorageConfig>,
+) -> duckdb::Result<()> {
+    let external: bool = conn.query_row(
+        "SELECT current_setting('enable_external_access')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !external {
+        return Ok(());
+    }
+    conn.execute_batch(&allowed_directories_sql(allowed))?;
+    match storage.and_then(|s| Some((s.uri.as_deref()?, &s.options))) {
+        Some((uri, options)) => {
+            if let Some(sql) = storage_secret_sql(uri, options) {
+                conn.execute_batch(&sql)
+                    .inspect_err(|e| error!("failed to configure storage credentials: {}", e))?;
+            }
+            Ok(())
+        }
+        None => conn.execute_batch("SET enable_external_access = false;"),
+    }
+}
 
+/// Applies [`restrict_access`] to every connection the pool opens, and has
+/// it search the storage roots for relative file names
+#[cfg(feature = "duckdb")]
+#[derive(Debug)]
+pub(crate) struct RestrictAccess {
+    pub allowed: Vec<String>,
+    pub storage: Option<striem_config::storage::StorageConfig>,
+}
+
+#[cfg(feature = "duckdb")]
+impl r2d2::CustomizeConnection<duckdb::Connection, duckdb::Error> for RestrictAccess {
+    fn on_acquire(&self, conn: &mut duckdb::Connection) -> Result<(), duckdb::Error> {
+        restrict_access(conn, &self.allowed, self.storage.as_ref())?;
+        match &self.storage {
+            Some(storage) => query::set_search_path(conn, &storage.roots()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Directories DuckDB may read for `config`: the OCSF category directories,
+/// storage and the database directory
+#[cfg(feature = "duckdb")]
+pub(crate) fn allowed_directories(config: &StrIEMConfig) -> Vec<String> {
+    let mut allowed = ALLOWED_CATEGORIES
+        .iter()
+        .map(|c| c.to_string())
+        .collect::<Vec<_>>();
     if let Some(storage) = &config.storage {
-        allowed.push(format!("'{}'", &storage.path.to_string_lossy()));
+        allowed.push(storage.path.to_string_lossy().to_string());
+        allowed.extend(
+            storage
+                .overrides
+                .values()
+                .map(|path| path.to_string_lossy().to_string()),
+        );
+        if let Some(uri) = &storage.uri {
+            allowed.push(uri.clone());
+        }
     }
+    if let Some(dbpath) = &config.db {
+        allowed.push(dbpath.to_string_lossy().to_string());
+    }
+    allowed
+}
+
+/// DuckDB pool over the database in `config.db`, or in a temporary directory
+/// when only storage is configured. Fails if file access can't be restricted.
+#[cfg(feature = "duckdb")]
+pub(crate) fn initdb(config: &StrIEMConfig) -> anyhow::Result<Option<Pool>> {
+    let scratch = db::scratch(config)?;
+    let pool = open_pool(config, scratch.as_deref().map(tempfile::TempDir::path))?;
+    Ok(pool.map(|pool| db::Db::new(pool, allowed_directories(config)).with_scratch(scratch)))
+}
 
-    if let Some(ref dbpath) = config.db {
-        std::fs::create_dir_all(dbpath)
-            .map_err(anyhow::Error::from)
-            .and_then(|_| {
-                let path = dbpath.join("striem.db");
-
-                allowed.extend([format!("'{}'", dbpath.to_string_lossy())]);
-
-                let allowed_str = format!("[{}]", allowed.join(", "));
-
-                duckdb::DuckdbConnectionManager::file_with_flags(
-                    path,
-                    duckdb::Config::default()
-                        .enable_object_cache(true)
-                        .map_err(anyhow::Error::from)?,
-                )
-                .map_err(anyhow::Error::from)
-                .and_then(|db| {
-                    r2d2::Pool::builder()
-                        .build(db)
-                        .inspect(|pool| {
-                            pool.get()
-                                .map(|conn| {
-                                    conn.execute(
-                                        "SET allowed_directories = ?;
-                                             SET enable_external_access = false;",
-                                        duckdb::params![&allowed_str],
-                                    )
-                                })
-                                .ok();
-                        })
-                        .map_err(anyhow::Error::from)
-                })
-            })
-            .and_then(|pool| {
-                let mut conn = pool.get().map_err(anyhow::Error::from)?;
-                crate::persist::init(&mut conn)?;
-                Ok(pool)
-            })
-            .inspect_err(|e| {
-                error!("{}", e);
-            })
-            .ok()
+/// Open the database for `config` in `dir`, or in `config.db`; see [`initdb`]
+#[cfg(feature = "duckdb")]
+pub(crate) fn open_pool(
+    config: &StrIEMConfig,
+    dir: Option<&std::path::Path>,
+) -> anyhow::Result<Option<db::ConnectionPool>> {
+    use anyhow::Context;
+    use r2d2::{CustomizeConnection, ManageConnection};
+
+    // Metadata cache significantly improves query performance on large
+    // Parquet datasets by avoiding repeated schema reads
+    let flags = || duckdb::Config::default().enable_object_cache(true);
+    let dbdir = dir.or(config.db.as_deref());
+    let manager = if let Some(dbpath) = dbdir {
+        std::fs::create_dir_all(dbpath)?;
+        duckdb::DuckdbConnectionManager::file_with_flags(dbpath.join("striem.db"), flags()?)?
     } else if config.storage.is_some() {
-        let allowed_str = format!("[{}]", allowed.join(", "));
-        duckdb::DuckdbConnectionManager::memory_with_flags(
-            duckdb::Config::default().enable_object_cache(true).ok()?,
-        )
-        .map_err(anyhow::Error::from)
-        .and_then(|db| {
-            r2d2::Pool::builder()
-                .build(db)
-                .inspect(|pool| {
-                    pool.get()
-                        .map(|conn| {
-                            conn.execute(
-                                "SET allowed_directories = ?;
-                                    SET enable_external_access = false;",
-                                duckdb::params![&allowed_str],
-                            )
-                        })
-                        .ok();
-                })
-                .map_err(anyhow::Error::from)
-        })
-        .inspect_err(|e| {
-            error!("{}", e);
-        })
-        .ok()
+        duckdb::DuckdbConnectionManager::memory_with_flags(flags()?)?
     } else {
-        None
+        return Ok(None);
+    };
+
+    let mut allowed = allowed_directories(config);
+    if let Some(dbpath) = dbdir.map(|d| d.to_string_lossy().to_string())
+        && !allowed.contains(&dbpath)
+    {
+        allowed.push(dbpath);
     }
+    let restrict = RestrictAccess {
+        allowed,
+        storage: config.storage.clone(),
+    };
+    // fail now, rather than on a pool checkout later
+    let mut probe = manager.connect()?;
+    restrict
+        .on_acquire(&mut probe)
+        .context("failed to restrict DuckDB file access")?;
+    drop(probe);
+
+    let pool = r2d2::Pool::builder()
+        .connection_customizer(Box::new(restrict))
+        .build(manager)?;
+    let mut conn = pool.get()?;
+    crate::persist::init(&mut conn)?;
+    Ok(Some(pool))
 }
 
 #[cfg(all(feature = "sqlite", not(feature = "duckdb")))]
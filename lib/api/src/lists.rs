@@ -0,0 +1,295 @@
+//! Reference lists for detection rules (see [`striem_common::lists`]).
+//!
+//! - GET /api/1/lists - List names, types and sizes
+//! - POST /api/1/lists - Create a list
+//! - GET /api/1/lists/:name - Get a list with its items
+//! - PUT /api/1/lists/:name - Create or replace a list; a `text/plain` body
+//!   replaces the items, one per line, keeping the list's type or taking
+//!   `?type=`
+//! - DELETE /api/1/lists/:name - Remove a list
+//!
+//! Changes are matched against from the next event on.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, LazyLock},
+};
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{HeaderMap, header},
+    routing::get,
+};
+use serde::{Deserialize, Serialize};
+use striem_common::lists::{ListMatcher, ListType};
+use tokio::sync::RwLock;
+use tracing::warn;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{ApiState, error::ApiError};
+
+pub(crate) static LISTS: LazyLock<RwLock<BTreeMap<String, ReferenceList>>> =
+    LazyLock::new(|| RwLock::new(BTreeMap::new()));
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ReferenceList {
+    /// Letters, digits, `_` and `-`, so rules can name it in a field path
+    pub name: String,
+    #[serde(rename = "type", default)]
+    #[schema(value_type = String)]
+    pub kind: ListType,
+    pub items: Vec<String>,
+}
+
+/// A list without its items
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ListSummary {
+    pub name: String,
+    #[serde(rename = "type")]
+    #[schema(value_type = String)]
+    pub kind: ListType,
+    pub count: usize,
+}
+
+/// JSON body of a PUT; the type defaults to the list's current one
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ListUpdate {
+    #[serde(rename = "type")]
+    #[schema(value_type = Option<String>)]
+    pub kind: Option<ListType>,
+    pub items: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub(crate) struct ListParams {
+    /// Type of a list created by a `text/plain` PUT
+    #[serde(rename = "type")]
+    #[param(value_type = Option<String>)]
+    kind: Option<ListType>,
+}
+
+/// Trim the items, dropping blank ones, and compile them
+fn compile(list: &mut ReferenceList) -> Result<ListMatcher, ApiError> {
+    if list.name.is_empty()
+        || !list
+            .name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(ApiError::BadRequest(format!(
+            "invalid list name: {:?}",
+            list.name
+        )));
+    }
+    list.items = list
+        .items
+        .iter()
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect();
+    ListMatcher::new(list.kind, &list.items).map_err(ApiError::BadRequest)
+}
+
+/// Matchers for every list in `lists`, skipping any that don't compile
+pub(crate) fn matchers(lists: &BTreeMap<String, ReferenceList>) -> HashMap<String, ListMatcher> {
+    lists
+        .values()
+        .filter_map(|list| match ListMatcher::new(list.kind, &list.items) {
+            Ok(matcher) => Some((list.name.clone(), matcher)),
+            Err(e) => {
+                warn!("skipping reference list '{}': {}", list.name, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Save `list` and start matching against it
+async fn store(state: &ApiState, mut list: ReferenceList) -> Result<ReferenceList, ApiError> {
+    let matcher = compile(&mut list)?;
+    if let Some(db) = state.db.as_ref() {
+        let mut conn = db.get().map_err(ApiError::database)?;
+        crate::persist::set_list(&mut conn, &list).map_err(ApiError::database)?;
+    }
+    let mut matchers = (**state.lists.load()).clone();
+    matchers.insert(list.name.clone(), matcher);
+    state.lists.store(Arc::new(matchers));
+    Ok(list)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/1/lists",
+    tag = "lists",
+    responses((status = 200, description = "Reference lists", body = [ListSummary]))
+)]
+pub(crate) async fn list_lists(State(_): State<ApiState>) -> axum::Json<Vec<ListSummary>> {
+    axum::Json(
+        LISTS
+            .read()
+            .await
+            .values()
+            .map(|list| ListSummary {
+                name: list.name.clone(),
+                kind: list.kind,
+                count: list.items.len(),
+            })
+            .collect(),
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/1/lists/{name}",
+    tag = "lists",
+    params(("name" = String, Path, description = "List name")),
+    responses(
+        (status = 200, description = "The list", body = ReferenceList),
+        (status = 404, description = "No such list", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_list(
+    State(_): State<ApiState>,
+    Path(name): Path<String>,
+) -> Result<axum::Json<ReferenceList>, ApiError> {
+    LISTS
+        .read()
+        .await
+        .get(&name)
+        .cloned()
+        .map(axum::Json)
+        .ok_or_else(|| ApiError::NotFound(format!("List {} not found", name)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/1/lists",
+    tag = "lists",
+    request_body = ReferenceList,
+    responses(
+        (status = 200, description = "The new list", body = ReferenceList),
+        (status = 400, description = "Invalid name or item", body = crate::error::ErrorBody),
+        (status = 409, description = "A list with this name exists", body = crate::error::ErrorBody),
+        (status = 500, description = "List could not be saved", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn add_list(
+    State(state): State<ApiState>,
+    axum::extract::Json(list): axum::extract::Json<ReferenceList>,
+) -> Result<axum::Json<ReferenceList>, ApiError> {
+    let mut lists = LISTS.write().await;
+    if lists.contains_key(&list.name) {
+        return Err(ApiError::Conflict(format!(
+            "List {} already exists",
+            list.name
+        )));
+    }
+    let list = store(&state, list).await?;
+    lists.insert(list.name.clone(), list.clone());
+    Ok(axum::Json(list))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/1/lists/{name}",
+    tag = "lists",
+    params(("name" = String, Path, description = "List name"), ListParams),
+    request_body(
+        content = ListUpdate,
+        description = "JSON, or a `text/plain` body with one item per line (blank lines and lines starting with `#` are skipped)"
+    ),
+    responses(
+        (status = 200, description = "The list as saved", body = ReferenceList),
+        (status = 400, description = "Invalid name, body or item", body = crate::error::ErrorBody),
+        (status = 500, description = "List could not be saved", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn put_list(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+    Query(params): Query<ListParams>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<axum::Json<ReferenceList>, ApiError> {
+    let text = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/plain"));
+    let update = if text {
+        let body = std::str::from_utf8(&body)
+            .map_err(|e| ApiError::BadRequest(format!("invalid text body: {}", e)))?;
+        ListUpdate {
+            kind: params.kind,
+            items: body
+                .lines()
+                .filter(|line| !line.trim_start().starts_with('#'))
+                .map(str::to_string)
+                .collect(),
+        }
+    } else {
+        serde_json::from_slice(&body)
+            .map_err(|e| ApiError::BadRequest(format!("invalid list: {}", e)))?
+    };
+
+    let mut lists = LISTS.write().await;
+    let kind = update
+        .kind
+        .or_else(|| lists.get(&name).map(|list| list.kind))
+        .unwrap_or_default();
+    let list = store(
+        &state,
+        ReferenceList {
+            name: name.clone(),
+            kind,
+            items: update.items,
+        },
+    )
+    .await?;
+    lists.insert(name, list.clone());
+    Ok(axum::Json(list))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/1/lists/{name}",
+    tag = "lists",
+    params(
+        ("name" = String, Path, description = "List name"),
+        ("X-Confirm-Token" = Option<String>, Header, description = "Token from the 202 response, with `api.require_confirmation`"),
+    ),
+    responses(
+        (status = 200, description = "The list was removed"),
+        (status = 202, description = "Confirmation required; repeat with `X-Confirm-Token`", body = crate::confirm::Confirmation),
+        (status = 404, description = "No such list", body = crate::error::ErrorBody),
+        (status = 500, description = "List could not be removed", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn delete_list(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+) -> Result<axum::Json<()>, ApiError> {
+    let mut lists = LISTS.write().await;
+    if !lists.contains_key(&name) {
+        return Err(ApiError::NotFound(format!("List {} not found", name)));
+    }
+
+    if let Some(db) = state.db.as_ref() {
+        let mut conn = db.get().map_err(ApiError::database)?;
+        crate::persist::remove_list(&mut conn, &name).map_err(ApiError::database)?;
+    }
+
+    let mut matchers = (**state.lists.load()).clone();
+    matchers.remove(&name);
+    state.lists.store(Arc::new(matchers));
+    lists.remove(&name);
+    Ok(axum::Json(()))
+}
+
+pub fn create_router() -> axum::Router<ApiState> {
+    Router::new()
+        .route("/", get(list_lists).post(add_list))
+        .route("/{name}", get(get_list).put(put_list).delete(delete_list))
+}
@@ -1,23 +1,18 @@
 use std::sync::Arc;
 
-use striem_api::serve;
-use striem_common::SysMessage;
-use striem_config::{StrIEMConfig, StringOrList};
+use striem_api::{Shared, load_rules, serve};
+use striem_common::{SysMessage, logging};
+use striem_config::StrIEMConfig;
 use tokio::main;
 use tokio::sync::{RwLock, broadcast};
 
 #[main]
 async fn main() -> anyhow::Result<()> {
-    env_logger::init();
-
     let config = StrIEMConfig::new()?;
-    let rules = if let Some(StringOrList::String(dir)) = &config.detections {
-        dir.clone()
-    } else {
-        "./rules".to_string()
-    };
-    let detections = sigmars::SigmaCollection::new_from_dir(&rules)
-        .map_err(|e| anyhow::anyhow!("Failed to load Sigma rules: {}", e))?;
+    logging::init(&config.logging, None).map_err(|e| anyhow::anyhow!("{}", e))?;
+    let mut detections = sigmars::SigmaCollection::default();
+    let (_, origins) = load_rules(&config, &mut detections).await?;
+    let ocsf = striem_common::detection::ocsf_rules(&detections);
 
     let sys = broadcast::channel::<SysMessage>(1).0;
     let sender = sys.clone();
@@ -25,9 +20,20 @@ async fn main() -> anyhow::Result<()> {
         tokio::signal::ctrl_c().await.unwrap();
         sender.send(SysMessage::Shutdown).unwrap();
     });
+    // Standalone API has no detection engine and receives no events; the
+    // alert stream stays idle, and there's no event tail or live stats
+    let shared = Shared {
+        detections: Arc::new(RwLock::new(detections)),
+        ocsf: Arc::new(arc_swap::ArcSwap::from_pointee(ocsf)),
+        levels: Default::default(),
+        origins: Arc::new(arc_swap::ArcSwap::from_pointee(origins)),
+        lists: Default::default(),
+        findings: None,
+        received: None,
+    };
     serve(
         &Arc::new(arc_swap::ArcSwap::from_pointee(config)),
-        Arc::new(RwLock::new(detections)),
+        shared,
         sys,
     )
     .await
@@ -0,0 +1,367 @@
+//! Webhook notifications for new detection findings.
+//!
+//! Notification rules are managed through the API:
+//! - GET /api/1/notifications - List rules with delivery failure counts
+//! - POST /api/1/notifications - Create a rule
+//! - GET /api/1/notifications/:id - Get a rule
+//! - PUT /api/1/notifications/:id - Replace a rule
+//! - DELETE /api/1/notifications/:id - Remove a rule
+//!
+//! A background task subscribes to the detection findings broadcast and
+//! POSTs the [`Alert`] JSON of each matching finding to the rule's URL.
+//! Every delivery runs in its own task so slow or failing endpoints never
+//! hold up the detection pipeline.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, LazyLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::{Router, extract::State, routing::get};
+use serde::{Deserialize, Serialize};
+use striem_common::{SysMessage, event::Event, metrics, prelude::*, severity::Severity};
+use tokio::sync::{
+    RwLock,
+    broadcast::{self, error::RecvError},
+};
+use tracing::{debug, info, warn};
+use utoipa::ToSchema;
+
+use crate::{ApiState, alerts::Alert, error::ApiError};
+
+pub(crate) static NOTIFICATIONS: LazyLock<RwLock<Vec<NotificationRule>>> =
+    LazyLock::new(|| RwLock::new(Vec::new()));
+
+const DEFAULT_ENABLED: fn() -> bool = || true;
+
+/// User-supplied notification settings, persisted as JSON
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NotificationConfig {
+    pub name: String,
+
+    /// Only findings at or above this severity are delivered
+    #[schema(value_type = String)]
+    pub min_severity: Severity,
+
+    /// Restrict to findings from these Sigma rule ids (all rules when empty)
+    #[serde(default)]
+    pub rules: Vec<String>,
+
+    /// Webhook target
+    pub url: String,
+
+    /// Extra headers sent with each request (e.g. authorization)
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    #[serde(default = "DEFAULT_ENABLED")]
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct NotificationRule {
+    pub id: String,
+    #[serde(flatten)]
+    pub config: NotificationConfig,
+    /// Deliveries that failed after all retries since startup
+    #[serde(serialize_with = "serialize_counter")]
+    #[schema(value_type = u64)]
+    pub failures: Arc<AtomicU64>,
+}
+
+fn serialize_counter<S>(counter: &Arc<AtomicU64>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_u64(counter.load(Ordering::Relaxed))
+}
+
+impl NotificationRule {
+    pub fn new(id: String, config: NotificationConfig) -> Self {
+        Self {
+            id,
+            config,
+            failures: Default::default(),
+        }
+    }
+
+    /// Whether `finding` (raised by `rule_id` at `severity`) should be delivered
+    pub(crate) fn matches(&self, rule_id: Option<&str>, severity: Option<Severity>) -> bool {
+        self.config.enabled
+            && severity.is_some_and(|s| s >= self.config.min_severity)
+            && (self.config.rules.is_empty()
+                || rule_id.is_some_and(|id| self.config.rules.iter().any(|r| r == id)))
+    }
+}
+
+/// Severity of a finding from its OCSF `severity_id`, falling back to the caption
+pub(crate) fn finding_severity(finding: &Event) -> Option<Severity> {
+    finding
+        .data
+        .get("severity_id")
+        .and_then(|v| v.as_u64())
+        .and_then(Severity::from_id)
+        .or_else(|| {
+            finding
+                .data
+                .get("severity")
+                .and_then(|v| v.as_str())
+                .and_then(|s| s.parse().ok())
+        })
+}
+
+/// Sigma rule id recorded on the finding by the detection engine
+pub(crate) fn finding_rule(finding: &Event) -> Option<&str> {
+    finding.data["finding_info"]["analytic"]["uid"].as_str()
+}
+
+/// POST `alert` to the rule's URL, retrying with exponential backoff.
+async fn deliver(client: reqwest::Client, rule: NotificationRule, alert: Alert) {
+    let mut delay = Duration::from_secs(NOTIFICATION_RETRY_BASE_SECS);
+    for attempt in 1..=NOTIFICATION_ATTEMPTS {
+        let mut request = client.post(&rule.config.url).json(&alert);
+        for (k, v) in &rule.config.headers {
+            request = request.header(k, v);
+        }
+
+        match request.send().await.and_then(|r| r.error_for_status()) {
+            Ok(_) => {
+                debug!(
+                    "notification '{}' delivered alert {}",
+                    rule.config.name, alert.id
+                );
+                return;
+            }
+            Err(e) => {
+                warn!(
+                    "notification '{}' attempt {}/{} failed: {}",
+                    rule.config.name, attempt, NOTIFICATION_ATTEMPTS, e
+                );
+            }
+        }
+        if attempt < NOTIFICATION_ATTEMPTS {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+    rule.failures.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Dispatch matching findings to webhooks until shutdown.
+pub(crate) async fn run(
+    mut events: broadcast::Receiver<Arc<Vec<Event>>>,
+    mut shutdown: broadcast::Receiver<SysMessage>,
+) {
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(NOTIFICATION_TIMEOUT_SECS))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("failed to create notification client: {}", e);
+            return;
+        }
+    };
+
+    loop {
+        tokio::select! {
+            msg = shutdown.recv() => {
+                if matches!(msg, Ok(SysMessage::Shutdown) | Err(RecvError::Closed)) {
+                    info!("notification dispatcher shutting down...");
+                    return;
+                }
+            },
+            result = events.recv() => {
+                let batch = match result {
+                    Ok(batch) => batch,
+                    Err(RecvError::Lagged(n)) => {
+                        metrics::increment(
+                            "striem_broadcast_lagged_total",
+                            &[("subscriber", "notifications")],
+                            n,
+                        );
+                        warn!("notification dispatcher skipped {} finding batches", n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+
+                let rules = NOTIFICATIONS.read().await;
+                if rules.is_empty() {
+                    continue;
+                }
+                for finding in batch.iter() {
+                    let Some(alert) = Alert::from_finding(finding) else {
+                        continue;
+                    };
+                    let severity = finding_severity(finding);
+                    let rule_id = finding_rule(finding);
+                    for rule in rules.iter().filter(|r| r.matches(rule_id, severity)) {
+                        tokio::spawn(deliver(client.clone(), rule.clone(), alert.clone()));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/1/notifications",
+    tag = "notifications",
+    responses((status = 200, description = "Notification rules", body = [NotificationRule]))
+)]
+pub(crate) async fn list_notifications(
+    State(_): State<ApiState>,
+) -> axum::Json<Vec<NotificationRule>> {
+    axum::Json(NOTIFICATIONS.read().await.clone())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/1/notifications/{id}",
+    tag = "notifications",
+    params(("id" = String, Path, description = "Notification rule id")),
+    responses(
+        (status = 200, description = "The notification rule", body = NotificationRule),
+        (status = 404, description = "No such rule", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_notification(
+    State(_): State<ApiState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+) -> Result<axum::Json<NotificationRule>, ApiError> {
+    NOTIFICATIONS
+        .read()
+        .await
+        .iter()
+        .find(|n| n.id == id)
+        .cloned()
+        .map(axum::Json)
+        .ok_or_else(|| ApiError::NotFound(format!("Notification with id {} not found", id)))
+}
+
+fn validate(config: &NotificationConfig) -> Result<(), ApiError> {
+    reqwest::Url::parse(&config.url)
+        .ok()
+        .filter(|u| matches!(u.scheme(), "http" | "https"))
+        .ok_or_else(|| ApiError::BadRequest(format!("invalid webhook url: {}", config.url)))?;
+    for (k, v) in &config.headers {
+        if reqwest::header::HeaderName::from_bytes(k.as_bytes()).is_err()
+            || reqwest::header::HeaderValue::from_str(v).is_err()
+        {
+            return Err(ApiError::BadRequest(format!("invalid header: {}", k)));
+        }
+    }
+    Ok(())
+}
+
+fn persist(state: &ApiState, rule: &NotificationRule) -> Result<(), ApiError> {
+    if let Some(db) = state.db.as_ref() {
+        let mut conn = db.get().map_err(ApiError::database)?;
+        crate::persist::set_notification(&mut conn, &rule.id, &rule.config)
+            .map_err(ApiError::database)?;
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/1/notifications",
+    tag = "notifications",
+    request_body = NotificationConfig,
+    responses(
+        (status = 200, description = "The new rule", body = NotificationRule),
+        (status = 400, description = "Invalid URL or header", body = crate::error::ErrorBody),
+        (status = 500, description = "Rule could not be saved", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn add_notification(
+    State(state): State<ApiState>,
+    axum::extract::Json(config): axum::extract::Json<NotificationConfig>,
+) -> Result<axum::Json<NotificationRule>, ApiError> {
+    validate(&config)?;
+    let rule = NotificationRule::new(uuid::Uuid::now_v7().to_string(), config);
+    persist(&state, &rule)?;
+    NOTIFICATIONS.write().await.push(rule.clone());
+    Ok(axum::Json(rule))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/1/notifications/{id}",
+    tag = "notifications",
+    params(("id" = String, Path, description = "Notification rule id")),
+    request_body = NotificationConfig,
+    responses(
+        (status = 200, description = "The updated rule", body = NotificationRule),
+        (status = 400, description = "Invalid URL or header", body = crate::error::ErrorBody),
+        (status = 404, description = "No such rule", body = crate::error::ErrorBody),
+        (status = 500, description = "Rule could not be saved", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn update_notification(
+    State(state): State<ApiState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::Json(config): axum::extract::Json<NotificationConfig>,
+) -> Result<axum::Json<NotificationRule>, ApiError> {
+    validate(&config)?;
+    let mut rules = NOTIFICATIONS.write().await;
+    let rule = rules
+        .iter_mut()
+        .find(|n| n.id == id)
+        .ok_or_else(|| ApiError::NotFound(format!("Notification with id {} not found", id)))?;
+
+    let updated = NotificationRule {
+        config,
+        ..rule.clone()
+    };
+    persist(&state, &updated)?;
+    *rule = updated.clone();
+    Ok(axum::Json(updated))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/1/notifications/{id}",
+    tag = "notifications",
+    params(("id" = String, Path, description = "Notification rule id")),
+    responses(
+        (status = 200, description = "The rule was removed"),
+        (status = 404, description = "No such rule", body = crate::error::ErrorBody),
+        (status = 500, description = "Rule could not be removed", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn delete_notification(
+    State(state): State<ApiState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+) -> Result<axum::Json<()>, ApiError> {
+    let mut rules = NOTIFICATIONS.write().await;
+    let index = rules
+        .iter()
+        .position(|n| n.id == id)
+        .ok_or_else(|| ApiError::NotFound(format!("Notification with id {} not found", id)))?;
+
+    if let Some(db) = state.db.as_ref() {
+        let mut conn = db.get().map_err(ApiError::database)?;
+        crate::persist::remove_notification(&mut conn, &id).map_err(ApiError::database)?;
+    }
+
+    rules.remove(index);
+    Ok(axum::Json(()))
+}
+
+pub fn create_router() -> axum::Router<ApiState> {
+    Router::new()
+        .route("/", get(list_notifications).post(add_notification))
+        .route(
+            "/{id}",
+            get(get_notification)
+                .put(update_notification)
+                .delete(delete_notification),
+        )
+}
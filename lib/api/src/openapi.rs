@@ -0,0 +1,168 @@
+//! OpenAPI description of the API.
+//!
+//! - GET /api/1/openapi.json - The OpenAPI 3.1 document, generated from the
+//!   `#[utoipa::path]` annotations on each handler
+//! - GET /api/docs - Swagger UI for it, when `api.docs.enabled` is set
+//!
+//! Both are public even with `api.auth` configured; the UI asks for a token
+//! before trying requests. Swagger UI's assets load from a CDN.
+//!
+//! Errors share one JSON body, `ErrorBody`.
+
+use axum::{
+    Json,
+    extract::State,
+    response::{Html, IntoResponse, Response},
+};
+use utoipa::{
+    Modify, OpenApi,
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+};
+
+use crate::{
+    ApiState, actions, admin, alerts, compaction, config, destination, detections, error::ApiError,
+    health, lists, notifications, playbooks, provision, query, replay, routes, rules, sources,
+    stats, storage, system, tail, vector,
+};
+
+/// Bearer auth, applying to every operation when `api.auth` is configured
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "StrIEM API"),
+    paths(
+        routes::health,
+        health::live,
+        health::ready,
+        routes::metrics,
+        vector::get_vector_config,
+        vector::get_vector_env,
+        vector::get_vector_validation,
+        provision::get_provision_status,
+        alerts::get_alerts,
+        alerts::stream_alerts,
+        alerts::get_summary,
+        alerts::get_alert_by_id,
+        alerts::patch_alert,
+        sources::list_sources,
+        sources::get_source,
+        sources::add_source,
+        sources::delete_source,
+        sources::rotate_token,
+        detections::list_rules,
+        detections::post_rule,
+        detections::get_coverage,
+        detections::get_rule,
+        detections::patch_rule,
+        detections::put_rule,
+        detections::delete_rule,
+        rules::post_sync,
+        replay::start_replay,
+        replay::list_replays,
+        replay::get_replay,
+        replay::cancel_replay,
+        lists::list_lists,
+        lists::add_list,
+        lists::get_list,
+        lists::put_list,
+        lists::delete_list,
+        actions::get_actions,
+        actions::get_action_by_id,
+        actions::execute_action_by_id,
+        actions::get_action_audit,
+        notifications::list_notifications,
+        notifications::add_notification,
+        notifications::get_notification,
+        notifications::update_notification,
+        notifications::delete_notification,
+        playbooks::list_playbooks,
+        playbooks::add_playbook,
+        playbooks::get_playbook,
+        playbooks::update_playbook,
+        playbooks::delete_playbook,
+        query::post_query,
+        query::get_schema,
+        query::post_next,
+        destination::set_destination,
+        storage::convert_errors,
+        storage::get_schemas,
+        storage::get_files,
+        compaction::start_compaction,
+        compaction::list_compactions,
+        compaction::get_compaction,
+        config::get_config,
+        config::patch_config,
+        system::stream_events,
+        tail::get_tail,
+        tail::stream_tail,
+        stats::get_live,
+        admin::get_log_level,
+        admin::put_log_level,
+        openapi_json,
+    ),
+    modifiers(&BearerAuth),
+    security(("bearer" = [])),
+    tags(
+        (name = "alerts", description = "Detection findings and their triage"),
+        (name = "sources", description = "Log sources feeding Vector"),
+        (name = "detections", description = "Sigma rules"),
+        (name = "lists", description = "Reference lists for Sigma rules"),
+        (name = "actions", description = "Response actions from the MCP servers, and their audit log"),
+        (name = "notifications", description = "Webhooks for new findings"),
+        (name = "playbooks", description = "Actions run automatically on new findings"),
+        (name = "query", description = "SQL over stored events"),
+        (name = "destination", description = "Storage location"),
+        (name = "storage", description = "Storage diagnostics and compaction"),
+        (name = "config", description = "Running configuration"),
+        (name = "vector", description = "Generated Vector configuration"),
+        (name = "system", description = "Health, metrics, system events and this document"),
+    )
+)]
+pub(crate) struct ApiDoc;
+
+#[utoipa::path(
+    get,
+    path = "/api/1/openapi.json",
+    tag = "system",
+    security(()),
+    responses((status = 200, description = "This document", body = Object))
+)]
+pub(crate) async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+const SWAGGER_UI: &str = r##"<!doctype html>
+<html>
+  <head>
+    <meta charset="utf-8" />
+    <title>StrIEM API</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+  </head>
+  <body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+    <script>
+      SwaggerUIBundle({ url: "/api/1/openapi.json", dom_id: "#swagger-ui" });
+    </script>
+  </body>
+</html>
+"##;
+
+/// Swagger UI, or not found unless `api.docs.enabled` is set
+pub(crate) async fn docs(State(state): State<ApiState>) -> Response {
+    if !state.config.load().api.docs.enabled {
+        return ApiError::NotFound("API docs are disabled".to_string()).into_response();
+    }
+    Html(SWAGGER_UI).into_response()
+}
@@ -1,6 +1,12 @@
 #[cfg(feature = "duckdb")]
 pub mod duckdb {
-    use crate::sources::Source;
+    use crate::{
+        actions::ActionAudit,
+        lists::ReferenceList,
+        notifications::NotificationConfig,
+        playbooks::PlaybookConfig,
+        sources::{Source, tokens::SourceToken},
+    };
     use anyhow::Result;
     use duckdb::{DuckdbConnectionManager, params};
     use r2d2::PooledConnection;
@@ -12,8 +18,65 @@ pub mod duckdb {
             type TEXT,
             config JSON);"#;
 
+    const CREATE_RULE_STATE_SQL: &str = r#"CREATE TABLE IF NOT EXISTS rule_state (
+            id TEXT PRIMARY KEY,
+            enabled BOOLEAN,
+            level TEXT);"#;
+
+    /// Analyst triage state for findings; the findings themselves stay immutable in Parquet
+    const CREATE_ALERT_STATUS_SQL: &str = r#"CREATE TABLE IF NOT EXISTS alert_status (
+            id TEXT PRIMARY KEY,
+            status TEXT NOT NULL,
+            assignee TEXT,
+            note TEXT,
+            updated_at TIMESTAMPTZ NOT NULL);"#;
+
+    const CREATE_NOTIFICATIONS_SQL: &str = r#"CREATE TABLE IF NOT EXISTS notifications (
+            id TEXT PRIMARY KEY,
+            config JSON);"#;
+
+    const CREATE_PLAYBOOKS_SQL: &str = r#"CREATE TABLE IF NOT EXISTS playbooks (
+            id TEXT PRIMARY KEY,
+            config JSON);"#;
+
+    const CREATE_LISTS_SQL: &str = r#"CREATE TABLE IF NOT EXISTS reference_lists (
+            name TEXT PRIMARY KEY,
+            type TEXT NOT NULL,
+            items JSON);"#;
+
+    /// HEC tokens by their SHA-256; `expires_at` is NULL for a source's
+    /// current token. Vector is given the tokens themselves as
+    /// `valid_tokens`, so they're kept like source secrets.
+    const CREATE_SOURCE_TOKENS_SQL: &str = r#"CREATE TABLE IF NOT EXISTS source_tokens (
+            hash TEXT PRIMARY KEY,
+            source_id TEXT NOT NULL,
+            token TEXT NOT NULL,
+            created_at TIMESTAMPTZ NOT NULL,
+            expires_at TIMESTAMPTZ);"#;
+
+    /// Response actions run on findings; `success` stays NULL until the
+    /// action returns
+    const CREATE_ACTION_AUDIT_SQL: &str = r#"CREATE TABLE IF NOT EXISTS action_audit (
+            id UUID PRIMARY KEY,
+            action_id TEXT NOT NULL,
+            alert_id TEXT NOT NULL,
+            params JSON,
+            principal TEXT,
+            triggered_by TEXT NOT NULL,
+            executed_at TIMESTAMPTZ NOT NULL,
+            success BOOLEAN,
+            error TEXT,
+            duration_ms BIGINT);"#;
+
     pub fn init(db: &mut PooledConnection<DuckdbConnectionManager>) -> Result<()> {
         db.execute(CREATE_TABLE_SQL, [])?;
+        db.execute(CREATE_RULE_STATE_SQL, [])?;
+        db.execute(CREATE_ALERT_STATUS_SQL, [])?;
+        db.execute(CREATE_NOTIFICATIONS_SQL, [])?;
+        db.execute(CREATE_PLAYBOOKS_SQL, [])?;
+        db.execute(CREATE_ACTION_AUDIT_SQL, [])?;
+        db.execute(CREATE_LISTS_SQL, [])?;
+        db.execute(CREATE_SOURCE_TOKENS_SQL, [])?;
         Ok(())
     }
     pub fn add_source(
@@ -24,7 +87,13 @@ pub mod duckdb {
 
         let sourcetype = source.sourcetype().to_string();
         let id = source.id();
-        let config = source.config().serialize(serde_json::value::Serializer)?;
+        let mut config = source.config().serialize(serde_json::value::Serializer)?;
+        if let (Some(vrl), Some(map)) = (source.custom_vrl(), config.as_object_mut()) {
+            map.insert("custom_vrl".to_string(), Value::String(vrl.to_string()));
+        }
+        if let (Some(tenant), Some(map)) = (source.tenant(), config.as_object_mut()) {
+            map.insert("tenant".to_string(), Value::String(tenant.to_string()));
+        }
 
         db.prepare(sql)?
             .execute(params![&sourcetype, &id, &config])?;
@@ -57,6 +126,344 @@ pub mod duckdb {
             .collect::<Result<_, Box<dyn std::error::Error>>>()
             .map_err(|e| anyhow::anyhow!("Failed to fetch sources from database: {}", e))?
     }
+
+    pub fn add_source_token(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+        token: &SourceToken,
+    ) -> Result<()> {
+        let sql = "INSERT INTO source_tokens (hash, source_id, token, created_at, expires_at)
+                   VALUES (?, ?, ?, CAST(? AS TIMESTAMPTZ), CAST(? AS TIMESTAMPTZ))";
+        db.prepare(sql)?.execute(params![
+            &token.hash,
+            &token.source_id,
+            &token.token,
+            token.created_at.to_rfc3339(),
+            token.expires_at.map(|t| t.to_rfc3339()),
+        ])?;
+        Ok(())
+    }
+
+    /// Have the current tokens of a source expire at `expires_at`, and drop
+    /// its tokens that already have
+    pub fn expire_source_tokens(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+        source_id: &str,
+        expires_at: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        let sql = "DELETE FROM source_tokens WHERE source_id = ? AND expires_at <= now()";
+        db.prepare(sql)?.execute(params![source_id])?;
+        let sql = "UPDATE source_tokens SET expires_at = CAST(? AS TIMESTAMPTZ)
+                   WHERE source_id = ? AND expires_at IS NULL";
+        db.prepare(sql)?
+            .execute(params![expires_at.to_rfc3339(), source_id])?;
+        Ok(())
+    }
+
+    pub fn remove_source_tokens(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+        source_id: &str,
+    ) -> Result<()> {
+        let sql = "DELETE FROM source_tokens WHERE source_id = ?";
+        db.prepare(sql)?.execute(params![source_id])?;
+        Ok(())
+    }
+
+    /// Tokens that haven't expired
+    pub fn source_tokens(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+    ) -> Result<Vec<SourceToken>> {
+        let sql = "SELECT hash, source_id, token, epoch_ms(created_at), epoch_ms(expires_at)
+                   FROM source_tokens WHERE expires_at IS NULL OR expires_at > now()";
+
+        db.prepare(sql)?
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, Option<i64>>(4)?,
+                ))
+            })?
+            .map(|row| {
+                let (hash, source_id, token, created_at, expires_at) = row?;
+                let time = |ms| {
+                    chrono::DateTime::from_timestamp_millis(ms)
+                        .ok_or_else(|| anyhow::anyhow!("invalid token timestamp {}", ms))
+                };
+                Ok(SourceToken {
+                    hash,
+                    source_id,
+                    token,
+                    created_at: time(created_at)?,
+                    expires_at: expires_at.map(time).transpose()?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| anyhow::anyhow!("Failed to fetch source tokens from database: {}", e))
+    }
+
+    /// Persist the runtime state of a detection rule.
+    ///
+    /// `level` is the severity override; `None` means the rule's YAML level applies.
+    pub fn set_rule_state(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+        id: &str,
+        enabled: bool,
+        level: Option<&str>,
+    ) -> Result<()> {
+        let sql = "INSERT OR REPLACE INTO rule_state (id, enabled, level) VALUES (?, ?, ?)";
+        db.prepare(sql)?.execute(params![id, enabled, level])?;
+        Ok(())
+    }
+
+    pub fn rule_states(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+    ) -> Result<Vec<(String, bool, Option<String>)>> {
+        let sql = "SELECT id, enabled, level FROM rule_state";
+
+        db.prepare(sql)?
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<bool>>(1)?.unwrap_or(true),
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow::anyhow!("Failed to fetch rule state from database: {}", e))
+    }
+
+    /// Record the triage status of a finding, replacing any previous state
+    pub fn set_alert_status(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+        id: &str,
+        status: &str,
+        assignee: Option<&str>,
+        note: Option<&str>,
+    ) -> Result<()> {
+        let sql = "INSERT OR REPLACE INTO alert_status (id, status, assignee, note, updated_at)
+                   VALUES (?, ?, ?, ?, now())";
+        db.prepare(sql)?
+            .execute(params![id, status, assignee, note])?;
+        Ok(())
+    }
+
+    /// Triage state of a finding as `(status, assignee, note, updated_at)`
+    pub fn alert_status(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+        id: &str,
+    ) -> Result<Option<(String, Option<String>, Option<String>, String)>> {
+        let sql = "SELECT status, assignee, note, CAST(updated_at AS VARCHAR)
+                   FROM alert_status WHERE id = ?";
+
+        db.prepare(sql)?
+            .query_map(params![id], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?
+            .next()
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("Failed to fetch alert status from database: {}", e))
+    }
+
+    pub fn set_notification(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+        id: &str,
+        config: &NotificationConfig,
+    ) -> Result<()> {
+        let sql = "INSERT OR REPLACE INTO notifications (id, config) VALUES (?, ?)";
+        let config = serde_json::to_value(config)?;
+        db.prepare(sql)?.execute(params![id, &config])?;
+        Ok(())
+    }
+
+    pub fn remove_notification(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+        id: &str,
+    ) -> Result<()> {
+        let sql = "DELETE FROM notifications WHERE id = ?";
+        db.prepare(sql)?.execute(params![id])?;
+        Ok(())
+    }
+
+    pub fn notifications(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+    ) -> Result<Vec<(String, NotificationConfig)>> {
+        let sql = "SELECT id, config FROM notifications";
+
+        db.prepare(sql)?
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Value>(1)?))
+            })?
+            .map(|row| {
+                let (id, config) = row?;
+                Ok((id, serde_json::from_value(config)?))
+            })
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| anyhow::anyhow!("Failed to fetch notifications from database: {}", e))
+    }
+
+    pub fn set_playbook(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+        id: &str,
+        config: &PlaybookConfig,
+    ) -> Result<()> {
+        let sql = "INSERT OR REPLACE INTO playbooks (id, config) VALUES (?, ?)";
+        let config = serde_json::to_value(config)?;
+        db.prepare(sql)?.execute(params![id, &config])?;
+        Ok(())
+    }
+
+    pub fn remove_playbook(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+        id: &str,
+    ) -> Result<()> {
+        let sql = "DELETE FROM playbooks WHERE id = ?";
+        db.prepare(sql)?.execute(params![id])?;
+        Ok(())
+    }
+
+    pub fn playbooks(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+    ) -> Result<Vec<(String, PlaybookConfig)>> {
+        let sql = "SELECT id, config FROM playbooks";
+
+        db.prepare(sql)?
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Value>(1)?))
+            })?
+            .map(|row| {
+                let (id, config) = row?;
+                Ok((id, serde_json::from_value(config)?))
+            })
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| anyhow::anyhow!("Failed to fetch playbooks from database: {}", e))
+    }
+
+    pub fn set_list(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+        list: &ReferenceList,
+    ) -> Result<()> {
+        let sql = "INSERT OR REPLACE INTO reference_lists (name, type, items) VALUES (?, ?, ?)";
+        let kind = serde_json::to_value(list.kind)?;
+        let items = serde_json::to_value(&list.items)?;
+        db.prepare(sql)?
+            .execute(params![&list.name, kind.as_str(), &items])?;
+        Ok(())
+    }
+
+    pub fn remove_list(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+        name: &str,
+    ) -> Result<()> {
+        let sql = "DELETE FROM reference_lists WHERE name = ?";
+        db.prepare(sql)?.execute(params![name])?;
+        Ok(())
+    }
+
+    pub fn lists(db: &mut PooledConnection<DuckdbConnectionManager>) -> Result<Vec<ReferenceList>> {
+        let sql = "SELECT name, type, items FROM reference_lists";
+
+        db.prepare(sql)?
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Value>(2)?,
+                ))
+            })?
+            .map(|row| {
+                let (name, kind, items) = row?;
+                Ok(ReferenceList {
+                    name,
+                    kind: kind.parse().map_err(anyhow::Error::msg)?,
+                    items: serde_json::from_value(items)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| anyhow::anyhow!("Failed to fetch reference lists from database: {}", e))
+    }
+
+    /// Record that an action is about to run
+    pub fn add_action_audit(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+        entry: &ActionAudit,
+    ) -> Result<()> {
+        let sql = "INSERT INTO action_audit
+                   (id, action_id, alert_id, params, principal, triggered_by, executed_at,
+                    success, error, duration_ms)
+                   VALUES (?, ?, ?, ?, ?, ?, CAST(? AS TIMESTAMPTZ), ?, ?, ?)";
+        db.prepare(sql)?.execute(params![
+            &entry.id,
+            &entry.action_id,
+            &entry.alert_id,
+            &entry.params,
+            &entry.principal,
+            &entry.triggered_by,
+            &entry.executed_at,
+            &entry.success,
+            &entry.error,
+            entry.duration_ms.map(|ms| ms as i64),
+        ])?;
+        Ok(())
+    }
+
+    /// Record how an action ended
+    pub fn finish_action_audit(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+        id: &str,
+        error: Option<&str>,
+        duration_ms: u64,
+    ) -> Result<()> {
+        let sql = "UPDATE action_audit SET success = ?, error = ?, duration_ms = ? WHERE id = ?";
+        db.prepare(sql)?
+            .execute(params![error.is_none(), error, duration_ms as i64, id])?;
+        Ok(())
+    }
+
+    /// Most recent action runs first, optionally for one finding or action
+    pub fn action_audit(
+        db: &mut PooledConnection<DuckdbConnectionManager>,
+        alert_id: Option<&str>,
+        action_id: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<ActionAudit>> {
+        let mut sql = "SELECT CAST(id AS VARCHAR), action_id, alert_id, params, principal,
+                              triggered_by, CAST(executed_at AS VARCHAR), success, error,
+                              duration_ms
+                       FROM action_audit WHERE true"
+            .to_string();
+        let mut filters: Vec<&dyn duckdb::ToSql> = Vec::new();
+        if let Some(alert_id) = &alert_id {
+            sql.push_str(" AND alert_id = ?");
+            filters.push(alert_id);
+        }
+        if let Some(action_id) = &action_id {
+            sql.push_str(" AND action_id = ?");
+            filters.push(action_id);
+        }
+        let limit = limit as u64;
+        sql.push_str(" ORDER BY executed_at DESC LIMIT ?");
+        filters.push(&limit);
+
+        db.prepare(&sql)?
+            .query_map(filters.as_slice(), |row| {
+                Ok(ActionAudit {
+                    id: row.get(0)?,
+                    action_id: row.get(1)?,
+                    alert_id: row.get(2)?,
+                    params: row.get(3)?,
+                    principal: row.get(4)?,
+                    triggered_by: row.get(5)?,
+                    executed_at: row.get(6)?,
+                    success: row.get(7)?,
+                    error: row.get(8)?,
+                    duration_ms: row.get::<_, Option<i64>>(9)?.map(|ms| ms as u64),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow::anyhow!("Failed to fetch action audit from database: {}", e))
+    }
 }
 
 #[cfg(feature = "duckdb")]
@@ -0,0 +1,423 @@
+//! Automated response: run an action when a rule fires.
+//!
+//! Playbooks are managed through the API:
+//! - GET /api/1/playbooks - List playbooks
+//! - POST /api/1/playbooks - Create a playbook
+//! - GET /api/1/playbooks/:id - Get a playbook
+//! - PUT /api/1/playbooks/:id - Replace a playbook
+//! - DELETE /api/1/playbooks/:id - Remove a playbook
+//!
+//! A background task subscribes to the detection findings broadcast and,
+//! for each enabled playbook matching a finding, renders the playbook's
+//! parameters from the finding and runs its action on it. A playbook runs
+//! at most once per `cooldown_secs`, however often its rule fires. Runs are
+//! written to the action audit log with `triggered_by: playbook`; without a
+//! database nothing runs, since the runs couldn't be audited.
+
+use std::{
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{Router, extract::State, routing::get};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use striem_common::{SysMessage, event::Event, metrics, severity::Severity};
+use tokio::sync::{
+    RwLock,
+    broadcast::{self, error::RecvError},
+};
+use tracing::{debug, info, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    ApiState,
+    actions::{Trigger, run_audited},
+    error::ApiError,
+    notifications::{finding_rule, finding_severity},
+};
+
+pub(crate) static PLAYBOOKS: LazyLock<RwLock<Vec<Playbook>>> =
+    LazyLock::new(|| RwLock::new(Vec::new()));
+
+const DEFAULT_ENABLED: fn() -> bool = || true;
+
+/// User-supplied playbook settings, persisted as JSON
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PlaybookConfig {
+    pub name: String,
+
+    /// Sigma rule ids that trigger the playbook (any rule when empty)
+    #[serde(default)]
+    pub rules: Vec<String>,
+
+    /// Only findings at or above this severity trigger the playbook. At
+    /// least one of `rules` and `min_severity` is required.
+    #[serde(default)]
+    #[schema(value_type = Option<String>)]
+    pub min_severity: Option<Severity>,
+
+    /// Action to run, `server_alias/tool_name`
+    pub action: String,
+
+    /// Action arguments. Strings may reference fields of the finding as
+    /// `{{finding_info.title}}`; a string that is only a reference takes
+    /// the field's value as is. The finding itself is passed as `data`.
+    #[serde(default)]
+    #[schema(value_type = Object)]
+    pub params: Map<String, Value>,
+
+    #[serde(default = "DEFAULT_ENABLED")]
+    pub enabled: bool,
+
+    /// Seconds after a run during which the playbook doesn't run again
+    #[serde(default)]
+    pub cooldown_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Playbook {
+    pub id: String,
+    #[serde(flatten)]
+    pub config: PlaybookConfig,
+    /// When the action last started, for the cooldown
+    #[serde(skip)]
+    last_run: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Playbook {
+    pub fn new(id: String, config: PlaybookConfig) -> Self {
+        Self {
+            id,
+            config,
+            last_run: Default::default(),
+        }
+    }
+
+    /// Whether a finding raised by `rule_id` at `severity` triggers the playbook
+    pub(crate) fn matches(&self, rule_id: Option<&str>, severity: Option<Severity>) -> bool {
+        self.config.enabled
+            && (self.config.rules.is_empty()
+                || rule_id.is_some_and(|id| self.config.rules.iter().any(|r| r == id)))
+            && self
+                .config
+                .min_severity
+                .is_none_or(|min| severity.is_some_and(|s| s >= min))
+    }
+
+    /// Claim a run unless the last one is within the cooldown
+    pub(crate) fn try_start(&self, now: Instant) -> bool {
+        let mut last_run = self.last_run.lock().unwrap_or_else(|e| e.into_inner());
+        let cooldown = Duration::from_secs(self.config.cooldown_secs);
+        if last_run.is_some_and(|last| now.duration_since(last) < cooldown) {
+            return false;
+        }
+        *last_run = Some(now);
+        true
+    }
+}
+
+/// Value at a dotted `path` of `finding`; numeric segments index arrays
+fn lookup<'a>(finding: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(finding, |value, key| match value {
+        Value::Array(values) => values.get(key.parse::<usize>().ok()?),
+        value => value.get(key),
+    })
+}
+
+/// Fill the `{{field}}` references in `template` from `finding`. Missing
+/// fields render as an empty string, or `null` for a whole-value reference.
+pub(crate) fn render(template: &Value, finding: &Value) -> Value {
+    match template {
+        Value::String(s) => {
+            let trimmed = s.trim();
+            if let Some(path) = trimmed
+                .strip_prefix("{{")
+                .and_then(|rest| rest.strip_suffix("}}"))
+                .filter(|path| !path.contains("{{") && !path.contains("}}"))
+            {
+                return lookup(finding, path.trim()).cloned().unwrap_or(Value::Null);
+            }
+
+            let mut rendered = String::new();
+            let mut rest = s.as_str();
+            while let Some(start) = rest.find("{{") {
+                let Some(end) = rest[start..].find("}}") else {
+                    break;
+                };
+                rendered.push_str(&rest[..start]);
+                match lookup(finding, rest[start + 2..start + end].trim()) {
+                    Some(Value::String(v)) => rendered.push_str(v),
+                    Some(Value::Null) | None => {}
+                    Some(v) => rendered.push_str(&v.to_string()),
+                }
+                rest = &rest[start + end + 2..];
+            }
+            rendered.push_str(rest);
+            Value::String(rendered)
+        }
+        Value::Array(values) => Value::Array(values.iter().map(|v| render(v, finding)).collect()),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| (k.clone(), render(v, finding)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// Run the playbook's action on `finding`
+async fn respond(state: ApiState, playbook: Playbook, finding: Value) {
+    let (Some(mcp), Some(pool)) = (state.actions.as_ref(), state.db.as_ref()) else {
+        return;
+    };
+    let Some(alert_id) = finding["metadata"]["uid"].as_str().map(str::to_string) else {
+        return;
+    };
+    let params = match render(&Value::Object(playbook.config.params.clone()), &finding) {
+        Value::Object(params) => params,
+        _ => Map::new(),
+    };
+
+    info!(
+        "playbook '{}' running {} on {}",
+        playbook.config.name, playbook.config.action, alert_id
+    );
+    let outcome = match run_audited(
+        pool,
+        mcp,
+        &playbook.config.action,
+        alert_id,
+        params,
+        finding,
+        Trigger::Playbook(playbook.id.clone()),
+    )
+    .await
+    {
+        Ok(()) => "success",
+        Err(e) => {
+            warn!("playbook '{}' failed: {}", playbook.config.name, e);
+            "failure"
+        }
+    };
+    metrics::increment("striem_playbook_runs_total", &[("outcome", outcome)], 1);
+}
+
+/// Run the playbooks matching new findings until shutdown.
+pub(crate) async fn run(
+    state: ApiState,
+    mut events: broadcast::Receiver<Arc<Vec<Event>>>,
+    mut shutdown: broadcast::Receiver<SysMessage>,
+) {
+    loop {
+        tokio::select! {
+            msg = shutdown.recv() => {
+                if matches!(msg, Ok(SysMessage::Shutdown) | Err(RecvError::Closed)) {
+                    info!("playbook runner shutting down...");
+                    return;
+                }
+            },
+            result = events.recv() => {
+                let batch = match result {
+                    Ok(batch) => batch,
+                    Err(RecvError::Lagged(n)) => {
+                        metrics::increment(
+                            "striem_broadcast_lagged_total",
+                            &[("subscriber", "playbooks")],
+                            n,
+                        );
+                        warn!("playbook runner skipped {} finding batches", n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                };
+
+                let playbooks = PLAYBOOKS.read().await;
+                if playbooks.is_empty() {
+                    continue;
+                }
+                if state.actions.is_none() || state.db.is_none() {
+                    warn!("playbooks need MCP servers and a database to run; skipping");
+                    continue;
+                }
+                let now = Instant::now();
+                for finding in batch.iter() {
+                    let severity = finding_severity(finding);
+                    let rule_id = finding_rule(finding);
+                    for playbook in playbooks.iter().filter(|p| p.matches(rule_id, severity)) {
+                        if !playbook.try_start(now) {
+                            debug!("playbook '{}' cooling down", playbook.config.name);
+                            metrics::increment(
+                                "striem_playbook_runs_total",
+                                &[("outcome", "cooldown")],
+                                1,
+                            );
+                            continue;
+                        }
+                        tokio::spawn(respond(
+                            state.clone(),
+                            playbook.clone(),
+                            finding.data.clone(),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/1/playbooks",
+    tag = "playbooks",
+    responses((status = 200, description = "Playbooks", body = [Playbook]))
+)]
+pub(crate) async fn list_playbooks(State(_): State<ApiState>) -> axum::Json<Vec<Playbook>> {
+    axum::Json(PLAYBOOKS.read().await.clone())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/1/playbooks/{id}",
+    tag = "playbooks",
+    params(("id" = String, Path, description = "Playbook id")),
+    responses(
+        (status = 200, description = "The playbook", body = Playbook),
+        (status = 404, description = "No such playbook", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_playbook(
+    State(_): State<ApiState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+) -> Result<axum::Json<Playbook>, ApiError> {
+    PLAYBOOKS
+        .read()
+        .await
+        .iter()
+        .find(|p| p.id == id)
+        .cloned()
+        .map(axum::Json)
+        .ok_or_else(|| ApiError::NotFound(format!("Playbook with id {} not found", id)))
+}
+
+fn validate(config: &PlaybookConfig) -> Result<(), ApiError> {
+    if config.rules.is_empty() && config.min_severity.is_none() {
+        return Err(ApiError::BadRequest(
+            "a playbook needs rules or min_severity".to_string(),
+        ));
+    }
+    match config.action.split_once('/') {
+        Some((server, tool)) if !server.is_empty() && !tool.is_empty() => Ok(()),
+        _ => Err(ApiError::BadRequest(format!(
+            "invalid action id: {}; expected server/tool",
+            config.action
+        ))),
+    }
+}
+
+fn persist(state: &ApiState, playbook: &Playbook) -> Result<(), ApiError> {
+    if let Some(db) = state.db.as_ref() {
+        let mut conn = db.get().map_err(ApiError::database)?;
+        crate::persist::set_playbook(&mut conn, &playbook.id, &playbook.config)
+            .map_err(ApiError::database)?;
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/1/playbooks",
+    tag = "playbooks",
+    request_body = PlaybookConfig,
+    responses(
+        (status = 200, description = "The new playbook", body = Playbook),
+        (status = 400, description = "No rules or severity, or invalid action id", body = crate::error::ErrorBody),
+        (status = 500, description = "Playbook could not be saved", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn add_playbook(
+    State(state): State<ApiState>,
+    axum::extract::Json(config): axum::extract::Json<PlaybookConfig>,
+) -> Result<axum::Json<Playbook>, ApiError> {
+    validate(&config)?;
+    let playbook = Playbook::new(uuid::Uuid::now_v7().to_string(), config);
+    persist(&state, &playbook)?;
+    PLAYBOOKS.write().await.push(playbook.clone());
+    Ok(axum::Json(playbook))
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/1/playbooks/{id}",
+    tag = "playbooks",
+    params(("id" = String, Path, description = "Playbook id")),
+    request_body = PlaybookConfig,
+    responses(
+        (status = 200, description = "The updated playbook", body = Playbook),
+        (status = 400, description = "No rules or severity, or invalid action id", body = crate::error::ErrorBody),
+        (status = 404, description = "No such playbook", body = crate::error::ErrorBody),
+        (status = 500, description = "Playbook could not be saved", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn update_playbook(
+    State(state): State<ApiState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+    axum::extract::Json(config): axum::extract::Json<PlaybookConfig>,
+) -> Result<axum::Json<Playbook>, ApiError> {
+    validate(&config)?;
+    let mut playbooks = PLAYBOOKS.write().await;
+    let playbook = playbooks
+        .iter_mut()
+        .find(|p| p.id == id)
+        .ok_or_else(|| ApiError::NotFound(format!("Playbook with id {} not found", id)))?;
+
+    // the cooldown carries over
+    let updated = Playbook {
+        config,
+        ..playbook.clone()
+    };
+    persist(&state, &updated)?;
+    *playbook = updated.clone();
+    Ok(axum::Json(updated))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/1/playbooks/{id}",
+    tag = "playbooks",
+    params(("id" = String, Path, description = "Playbook id")),
+    responses(
+        (status = 200, description = "The playbook was removed"),
+        (status = 404, description = "No such playbook", body = crate::error::ErrorBody),
+        (status = 500, description = "Playbook could not be removed", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn delete_playbook(
+    State(state): State<ApiState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+) -> Result<axum::Json<()>, ApiError> {
+    let mut playbooks = PLAYBOOKS.write().await;
+    let index = playbooks
+        .iter()
+        .position(|p| p.id == id)
+        .ok_or_else(|| ApiError::NotFound(format!("Playbook with id {} not found", id)))?;
+
+    if let Some(db) = state.db.as_ref() {
+        let mut conn = db.get().map_err(ApiError::database)?;
+        crate::persist::remove_playbook(&mut conn, &id).map_err(ApiError::database)?;
+    }
+
+    playbooks.remove(index);
+    Ok(axum::Json(()))
+}
+
+pub fn create_router() -> axum::Router<ApiState> {
+    Router::new()
+        .route("/", get(list_playbooks).post(add_playbook))
+        .route(
+            "/{id}",
+            get(get_playbook)
+                .put(update_playbook)
+                .delete(delete_playbook),
+        )
+}
@@ -0,0 +1,287 @@
+//! Automatic Vector provisioning.
+//!
+//! - GET /api/1/vector/status - The last provisioning attempt
+//!
+//! With `output.vector.api` and `output.vector.config_path` set, each change
+//! to the sources or sinks writes the configuration served at `/vector` to
+//! `config_path` and has Vector reload it. Changes less than
+//! `provision_debounce_ms` apart are written once, so a bulk import reloads
+//! Vector once. The file's extension picks the format, as for
+//! [`VectorFormat::from_path`].
+//!
+//! The configuration is written to a temporary file beside `config_path`
+//! and renamed over it, so Vector never reads a partial file. With
+//! `pid_file`, Vector is then sent SIGHUP. Vector's API has no reload call,
+//! so without one Vector is expected to run with `--watch-config`, and its
+//! API's `/health` is checked once the file is written.
+//!
+//! A configuration with validation errors isn't written. Failures don't undo
+//! the change that triggered them: they're logged, counted in
+//! `striem_vector_provisions_total` and reported by the status endpoint, and
+//! the next change tries again.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use axum::{Json, Router, extract::State, routing::get};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use striem_common::{SysMessage, metrics, prelude::*};
+use striem_config::{StrIEMConfig, output::Destination};
+use tokio::sync::{
+    Notify, RwLock,
+    broadcast::{self, error::RecvError},
+};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    ApiState,
+    vector::{VectorFormat, assemble, validate},
+};
+
+static CHANGED: LazyLock<Notify> = LazyLock::new(Notify::new);
+
+static STATUS: LazyLock<RwLock<ProvisionStatus>> = LazyLock::new(Default::default);
+
+/// Provision Vector once the current burst of changes settles
+pub(crate) fn changed() {
+    CHANGED.notify_one();
+}
+
+/// How Vector is told to reload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Reload {
+    /// SIGHUP to the process in `pid_file`
+    Sighup,
+    /// Vector watches `config_path` itself
+    Watch,
+}
+
+/// The last provisioning attempt
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub(crate) struct ProvisionStatus {
+    /// Whether `output.vector.api` and `output.vector.config_path` are set
+    pub enabled: bool,
+    /// Changes are waiting to be written
+    pub pending: bool,
+    #[schema(value_type = Option<String>)]
+    pub last_attempt: Option<DateTime<Utc>>,
+    pub success: Option<bool>,
+    pub error: Option<String>,
+    #[schema(value_type = Option<String>)]
+    pub path: Option<PathBuf>,
+    pub reload: Option<Reload>,
+    /// Validation warnings for the written configuration
+    pub warnings: Vec<String>,
+}
+
+/// Where and how to provision, from the first Vector output
+struct Target {
+    path: PathBuf,
+    pid_file: Option<PathBuf>,
+    api: String,
+    debounce: Duration,
+}
+
+impl Target {
+    fn from_config(config: &StrIEMConfig) -> Option<Self> {
+        let vector = config.outputs.iter().find_map(|output| match output {
+            Destination::Vector(cfg) => Some(cfg),
+            _ => None,
+        })?;
+        Some(Target {
+            path: vector.config_path.clone()?,
+            pid_file: vector.pid_file.clone(),
+            api: vector.api.as_ref()?.url(),
+            debounce: Duration::from_millis(vector.provision_debounce_ms),
+        })
+    }
+
+    fn reload(&self) -> Reload {
+        match self.pid_file {
+            Some(_) => Reload::Sighup,
+            None => Reload::Watch,
+        }
+    }
+}
+
+/// Write `contents` to a temporary file beside `path` and rename it over
+/// `path`, keeping its permissions
+pub(crate) fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    let dir = path
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mut file = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("could not create a file in {}", dir.display()))?;
+    file.write_all(contents.as_bytes())?;
+    if let Ok(metadata) = std::fs::metadata(path) {
+        file.as_file().set_permissions(metadata.permissions())?;
+    }
+    file.as_file().sync_all()?;
+    file.persist(path)
+        .with_context(|| format!("could not replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sighup(pid_file: &Path) -> Result<()> {
+    let pid: libc::pid_t = std::fs::read_to_string(pid_file)
+        .with_context(|| format!("could not read {}", pid_file.display()))?
+        .trim()
+        .parse()
+        .with_context(|| format!("no PID in {}", pid_file.display()))?;
+    // SAFETY: kill() takes no pointers
+    if unsafe { libc::kill(pid, libc::SIGHUP) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("could not signal Vector (PID {})", pid));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sighup(_: &Path) -> Result<()> {
+    bail!("pid_file reloads are only supported on Unix")
+}
+
+/// Check Vector's API is up after it was left to reload on its own
+async fn check_health(api: &str) -> Result<()> {
+    let url = format!("{}/health", api.trim_end_matches('/'));
+    let response = reqwest::Client::new()
+        .get(&url)
+        .timeout(Duration::from_secs(VECTOR_PROVISION_HEALTH_TIMEOUT_SECS))
+        .send()
+        .await
+        .with_context(|| format!("Vector API at {} is unreachable", api))?;
+    if !response.status().is_success() {
+        bail!("Vector API at {} answered {}", api, response.status());
+    }
+    Ok(())
+}
+
+/// Write the configuration and reload Vector, returning validation warnings
+async fn apply(state: &ApiState, target: &Target) -> Result<Vec<String>> {
+    let (config, duplicates) = assemble(state).await.map_err(|e| anyhow!("{}", e))?;
+    let validation = validate(&config, &duplicates);
+    if !validation.errors.is_empty() {
+        bail!("configuration has errors: {}", validation.errors.join("; "));
+    }
+    let rendered = VectorFormat::from_path(&target.path)
+        .render(&config)
+        .map_err(|e| anyhow!("{}", e))?;
+
+    let path = target.path.clone();
+    tokio::task::spawn_blocking(move || write_atomic(&path, &rendered)).await??;
+
+    match &target.pid_file {
+        Some(pid_file) => sighup(pid_file)?,
+        None => check_health(&target.api).await?,
+    }
+    Ok(validation.warnings)
+}
+
+/// Provision Vector now, recording the outcome
+pub(crate) async fn provision(state: &ApiState) {
+    let Some(target) = Target::from_config(&state.config.load()) else {
+        *STATUS.write().await = ProvisionStatus::default();
+        return;
+    };
+
+    let result = apply(state, &target).await;
+    let mut status = ProvisionStatus {
+        enabled: true,
+        pending: false,
+        last_attempt: Some(Utc::now()),
+        path: Some(target.path.clone()),
+        reload: Some(target.reload()),
+        ..Default::default()
+    };
+    match result {
+        Ok(warnings) => {
+            info!("Vector configuration written to {}", target.path.display());
+            metrics::increment(
+                "striem_vector_provisions_total",
+                &[("outcome", "success")],
+                1,
+            );
+            status.success = Some(true);
+            status.warnings = warnings;
+        }
+        Err(e) => {
+            warn!("Vector provisioning failed: {:#}", e);
+            metrics::increment(
+                "striem_vector_provisions_total",
+                &[("outcome", "failure")],
+                1,
+            );
+            status.success = Some(false);
+            status.error = Some(format!("{:#}", e));
+        }
+    }
+    *STATUS.write().await = status;
+}
+
+/// Whether `msg` ends the provisioning task
+fn is_shutdown(msg: Result<SysMessage, RecvError>) -> bool {
+    matches!(msg, Ok(SysMessage::Shutdown) | Err(RecvError::Closed))
+}
+
+/// Provision Vector at startup and after each burst of changes
+pub(crate) async fn run(state: ApiState, mut shutdown: broadcast::Receiver<SysMessage>) {
+    // the sources loaded at startup may differ from what Vector last read
+    provision(&state).await;
+    loop {
+        tokio::select! {
+            msg = shutdown.recv() => {
+                if is_shutdown(msg) {
+                    info!("Vector provisioning shutting down...");
+                    return;
+                }
+                continue;
+            },
+            _ = CHANGED.notified() => {},
+        }
+
+        let debounce = Target::from_config(&state.config.load())
+            .map(|t| t.debounce)
+            .unwrap_or_default();
+        STATUS.write().await.pending = true;
+        loop {
+            tokio::select! {
+                msg = shutdown.recv() => {
+                    if is_shutdown(msg) {
+                        info!("Vector provisioning shutting down...");
+                        return;
+                    }
+                },
+                _ = CHANGED.notified() => {},
+                _ = tokio::time::sleep(debounce) => break,
+            }
+        }
+        provision(&state).await;
+    }
+}
+
+/// The last Vector provisioning attempt
+#[utoipa::path(
+    get,
+    path = "/api/1/vector/status",
+    tag = "vector",
+    responses(
+        (status = 200, description = "Outcome of the last attempt; `enabled: false` when provisioning isn't configured", body = ProvisionStatus),
+    )
+)]
+pub(crate) async fn get_provision_status(State(_): State<ApiState>) -> Json<ProvisionStatus> {
+    Json(STATUS.read().await.clone())
+}
+
+pub fn create_router() -> Router<ApiState> {
+    Router::new().route("/status", get(get_provision_status))
+}
@@ -1,63 +1,784 @@
+use std::{
+    io::Write,
+    net::SocketAddr,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
 use anyhow::Result;
-use arrow_json::writer::ArrayWriter;
-use axum::extract::State;
-use log::error;
+use arc_swap::ArcSwapOption;
+use arrow_json::LineDelimitedWriter;
+use axum::{
+    Extension,
+    body::Bytes,
+    extract::{ConnectInfo, State},
+    response::IntoResponse,
+};
+use duckdb::{
+    arrow::datatypes::{DataType, Schema},
+    types::{TimeUnit, Value},
+};
+use futures_util::StreamExt;
 use serde::Deserialize;
+use striem_common::event::tenant_label;
+use striem_storage::schema::ClassSchema;
+use tokio::sync::mpsc;
+use tracing::error;
+use utoipa::ToSchema;
 
-use crate::ApiState;
-
-static INTERNAL_SERVER_ERROR: fn() -> (axum::http::StatusCode, String) = || {
-    (
-        axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-        "internal server error".to_string(),
-    )
+use crate::{
+    ApiState,
+    auth::Tenant,
+    cursor::{CURSORS, Cursor},
+    error::ApiError,
+    sql_string,
+    views::{VIEWS, identifier},
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct QueryRequest {
     pub sql: String,
     #[serde(default = "default_limit")]
     pub limit: usize,
+    /// Values bound to the statement's `?` placeholders, in order
+    #[serde(default)]
+    #[schema(value_type = Vec<Object>)]
+    pub params: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub format: QueryFormat,
+    /// Wrap rows as `{columns, rows}` with the result column types
+    #[serde(default)]
+    pub include_schema: bool,
+    /// Page through the full result with a server-side cursor instead of
+    /// applying `limit`; pages are always JSON envelopes
+    pub page_size: Option<usize>,
 }
 
+#[derive(Deserialize, ToSchema)]
+pub struct NextRequest {
+    pub cursor: String,
+}
+
+/// Response encoding for query results
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryFormat {
+    /// A single JSON array of row objects
+    #[default]
+    Json,
+    /// One JSON row object per line, for large exports
+    Ndjson,
+}
+
+impl QueryFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            QueryFormat::Json => "application/json",
+            QueryFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+/// Size at which buffered output is handed to the response body
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks in flight between the query thread and the response body
+const STREAM_CHUNKS: usize = 4;
+
+type Chunk = Result<Bytes, ApiError>;
+
+/// `io::Write` adapter feeding response body chunks from the blocking query
+/// thread. Sends block when the client isn't keeping up, which bounds memory
+/// to a few chunks plus the current record batch.
+struct ChannelWriter {
+    tx: mpsc::Sender<Chunk>,
+    buf: Vec<u8>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= STREAM_CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::take(&mut self.buf));
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+    }
+}
+
+/// Column tree of the configured OCSF schemas, cleared on reload
+pub(crate) type SchemaCache = Arc<ArcSwapOption<serde_json::Value>>;
+
 fn default_limit() -> usize {
     10
 }
 
 pub fn create_router() -> axum::Router<ApiState> {
-    axum::Router::new().route("/", axum::routing::post(post_query))
+    axum::Router::new()
+        .route("/", axum::routing::post(post_query))
+        .route("/schema", axum::routing::get(get_schema))
+        .route("/next", axum::routing::post(post_next))
+}
+
+/// Build the category → class → columns tree served to the query UI, with
+/// the class's view when it's one of `views`
+pub(crate) fn schema_tree(classes: &[ClassSchema], views: &[String]) -> serde_json::Value {
+    let mut tree = serde_json::Map::new();
+    for class in classes {
+        let columns = class
+            .columns
+            .iter()
+            .map(|c| serde_json::json!({"name": c.name, "type": c.data_type}))
+            .collect::<Vec<_>>();
+
+        if let serde_json::Value::Object(category) = tree
+            .entry(class.category.clone())
+            .or_insert_with(|| serde_json::json!({}))
+        {
+            category.insert(
+                class.class.clone(),
+                serde_json::json!({
+                    "class_uid": class.class_uid,
+                    "view": views.contains(&class.class).then_some(&class.class),
+                    "columns": columns,
+                }),
+            );
+        }
+    }
+    serde_json::Value::Object(tree)
 }
 
-async fn post_query(
+/// Queryable tables and their columns, derived from the OCSF schema
+/// directory rather than the data files. Classes with stored data name the
+/// view they can be queried through, e.g. `SELECT * FROM authentication`.
+#[utoipa::path(
+    get,
+    path = "/api/1/query/schema",
+    tag = "query",
+    responses(
+        (status = 200, description = "Tables by category, with class_uid, view and columns", body = Object),
+        (status = 404, description = "No schema directory configured", body = crate::error::ErrorBody),
+        (status = 500, description = "Schema could not be read", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_schema(
     State(state): State<ApiState>,
-    axum::extract::Json(payload): axum::extract::Json<QueryRequest>,
-) -> Result<axum::Json<serde_json::Value>, (axum::http::StatusCode, String)> {
-    let conn = if let Some(pool) = &state.db {
-        pool.get().map_err(|e| {
-            error!("Database Connection Error: {}", e);
-            INTERNAL_SERVER_ERROR()
-        })?
-    } else {
-        return Err(INTERNAL_SERVER_ERROR());
-    };
+) -> Result<axum::Json<serde_json::Value>, ApiError> {
+    if let Some(cached) = state.schema.load_full() {
+        return Ok(axum::Json((*cached).clone()));
+    }
 
-    let data = state
+    let Some(schemapath) = state
         .config
         .load()
         .storage
         .as_ref()
-        .and_then(|s| Some(s.path.as_path().to_str().map(|s| s.to_string())?));
-    conn.execute(
-        "SET file_search_path = ?",
-        duckdb::params![data.as_deref().unwrap_or("")],
+        .map(|s| s.schema.clone())
+    else {
+        return Err(ApiError::NotFound("storage is not configured".to_string()));
+    };
+
+    let classes =
+        tokio::task::spawn_blocking(move || striem_storage::schema::load_schemas(&schemapath))
+            .await
+            .map_err(ApiError::internal)?
+            .map_err(ApiError::internal)?;
+
+    let views = crate::views::VIEWS.read().await.clone();
+    let tree = schema_tree(&classes, &views);
+    state.schema.store(Some(Arc::new(tree.clone())));
+    Ok(axum::Json(tree))
+}
+
+/// Search each of `paths` in turn for relative file names
+pub(crate) fn set_search_path(conn: &duckdb::Connection, paths: &[PathBuf]) -> duckdb::Result<()> {
+    let search = paths
+        .iter()
+        .map(|path| path.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(",");
+    conn.execute("SET file_search_path = ?", duckdb::params![search])
+        .map(|_| ())
+}
+
+/// Pooled connection for one request. The pool sets each connection's file
+/// search path to the storage roots when it opens it; a connection given
+/// another path has its previous one restored when the guard drops, so it
+/// never leaks to the next borrower. Likewise, tenant views shadowing the
+/// class views (see [`ScopedConnection::scope_to_tenant`]) are dropped.
+pub(crate) struct ScopedConnection {
+    conn: r2d2::PooledConnection<duckdb::DuckdbConnectionManager>,
+    /// Search path to restore on drop
+    restore: Option<String>,
+    /// Temp views to drop on drop
+    shadowed: Vec<String>,
+}
+
+impl ScopedConnection {
+    /// Connection searching the paths the pool set up
+    pub(crate) fn pooled(conn: r2d2::PooledConnection<duckdb::DuckdbConnectionManager>) -> Self {
+        ScopedConnection {
+            conn,
+            restore: None,
+            shadowed: Vec::new(),
+        }
+    }
+
+    pub(crate) fn new(
+        conn: r2d2::PooledConnection<duckdb::DuckdbConnectionManager>,
+        path: &Path,
+    ) -> Result<Self, ApiError> {
+        Self::with_search_path(conn, &[path.to_path_buf()])
+    }
+
+    /// Search each of `paths` in turn until the guard drops
+    pub(crate) fn with_search_path(
+        conn: r2d2::PooledConnection<duckdb::DuckdbConnectionManager>,
+        paths: &[PathBuf],
+    ) -> Result<Self, ApiError> {
+        let previous: String = conn
+            .query_row("SELECT current_setting('file_search_path')", [], |row| {
+                row.get(0)
+            })
+            .map_err(ApiError::database)?;
+        set_search_path(&conn, paths).map_err(ApiError::database)?;
+        Ok(ScopedConnection {
+            conn,
+            restore: Some(previous),
+            shadowed: Vec::new(),
+        })
+    }
+
+    /// Limit `sql` to `tenant`'s events: after checking it reads nothing but
+    /// the class views in `views` and its own CTEs, shadow each of those
+    /// views with a temp view keeping only rows labelled for `tenant`.
+    pub(crate) fn scope_to_tenant(
+        &mut self,
+        sql: &str,
+        tenant: &str,
+        views: &[String],
+    ) -> Result<(), ApiError> {
+        check_tenant_query(&self.conn, sql, views)?;
+
+        let catalog: String = self
+            .conn
+            .query_row("SELECT current_database()", [], |row| row.get(0))
+            .map_err(ApiError::database)?;
+        let label = sql_string(&tenant_label(tenant));
+        for view in views {
+            if !self.shadowed.contains(view) {
+                self.shadowed.push(view.clone());
+            }
+            self.conn
+                .execute_batch(&format!(
+                    "CREATE OR REPLACE TEMP VIEW {view} AS SELECT * FROM {catalog}.main.{view} \
+                     WHERE list_contains(metadata.labels, {label})",
+                    view = identifier(view),
+                    catalog = identifier(&catalog),
+                ))
+                .map_err(ApiError::database)?;
+        }
+        Ok(())
+    }
+}
+
+impl Deref for ScopedConnection {
+    type Target = duckdb::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn
+    }
+}
+
+impl Drop for ScopedConnection {
+    fn drop(&mut self) {
+        for view in &self.shadowed {
+            self.conn
+                .execute_batch(&format!("DROP VIEW IF EXISTS temp.{}", identifier(view)))
+                .inspect_err(|e| error!("Database Error: {}", e))
+                .ok();
+        }
+        if let Some(previous) = &self.restore {
+            self.conn
+                .execute("SET file_search_path = ?", duckdb::params![previous])
+                .inspect_err(|e| error!("Database Error: {}", e))
+                .ok();
+        }
+    }
+}
+
+/// Connection searching the storage root, then any `storage.overrides`
+/// paths. DuckDB resolves a relative glob in the first path it matches in,
+/// so a category split across paths needs absolute paths to read whole.
+fn connection(state: &ApiState) -> Result<ScopedConnection, ApiError> {
+    let Some(pool) = &state.db else {
+        return Err(ApiError::Unavailable(
+            "database not initialized".to_string(),
+        ));
+    };
+    if state.config.load().storage.is_none() {
+        return Err(ApiError::Conflict("no storage configured".to_string()));
+    }
+    let conn = pool.get().map_err(ApiError::database)?;
+    Ok(ScopedConnection::pooled(conn))
+}
+
+/// Run a read-only SQL query over stored events.
+///
+/// Rows are returned as JSON or NDJSON per `format`; with `page_size`, the
+/// first page is returned with a cursor for [`post_next`]. For a token with
+/// a tenant claim, the class views show only that tenant's events, and the
+/// query may read nothing else: no table functions such as `read_parquet`
+/// and no schema-qualified names.
+#[utoipa::path(
+    post,
+    path = "/api/1/query",
+    tag = "query",
+    request_body = QueryRequest,
+    responses(
+        (status = 200, description = "Rows, a `{columns, rows}` envelope, or the first page", body = [Object]),
+        (status = 400, description = "Invalid SQL or parameters", body = crate::error::ErrorBody),
+        (status = 403, description = "Statement isn't read-only, or reads outside the tenant's views", body = crate::error::ErrorBody),
+        (status = 409, description = "No storage configured", body = crate::error::ErrorBody),
+        (status = 429, description = "Too many open cursors for this client", body = crate::error::ErrorBody),
+        (status = 500, description = "Query failed", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn post_query(
+    State(state): State<ApiState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    tenant: Option<Extension<Tenant>>,
+    axum::extract::Json(payload): axum::extract::Json<QueryRequest>,
+) -> Result<axum::response::Response, ApiError> {
+    let mut conn = connection(&state)?;
+    if let Some(Extension(Tenant(tenant))) = &tenant {
+        let views = VIEWS.read().await.clone();
+        conn = tokio::task::spawn_blocking({
+            let sql = payload.sql.clone();
+            let tenant = tenant.clone();
+            move || conn.scope_to_tenant(&sql, &tenant, &views).map(|_| conn)
+        })
+        .await
+        .map_err(ApiError::internal)??;
+    }
+
+    if let Some(page_size) = payload.page_size {
+        let max_per_client = state.config.load().api.query.max_cursors_per_client;
+        let body = tokio::task::spawn_blocking(move || {
+            let cursor = Cursor::open(conn, &payload.sql, &payload.params, page_size)?;
+            first_page(cursor, addr, max_per_client)
+        })
+        .await
+        .map_err(ApiError::internal)??;
+        return Ok(json_response(body));
+    }
+
+    // DuckDB is synchronous; run the query on a blocking thread and stream
+    // its output through a bounded channel as it is produced
+    let (tx, mut rx) = mpsc::channel::<Chunk>(STREAM_CHUNKS);
+    let format = payload.format;
+    tokio::task::spawn_blocking(move || {
+        let mut out = ChannelWriter {
+            tx: tx.clone(),
+            buf: Vec::new(),
+        };
+        let result = write_query(
+            &conn,
+            &payload.sql,
+            &payload.params,
+            payload.limit,
+            format,
+            payload.include_schema,
+            &mut out,
+        )
+        .and_then(|_| out.flush().map_err(ApiError::internal));
+        if let Err(e) = result {
+            tx.blocking_send(Err(e)).ok();
+        }
+    });
+
+    // Errors raised before any output (bad SQL, parameters) become a normal
+    // error response; later failures can only abort the stream
+    let first = match rx.recv().await {
+        Some(Ok(chunk)) => chunk,
+        Some(Err(e)) => return Err(e),
+        None => Bytes::new(),
+    };
+
+    let rest = futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| {
+            let chunk = chunk.map_err(|(_, e)| std::io::Error::other(e));
+            (chunk, rx)
+        })
+    });
+    let body =
+        futures_util::stream::once(async move { Ok::<_, std::io::Error>(first) }).chain(rest);
+
+    Ok((
+        [(axum::http::header::CONTENT_TYPE, format.content_type())],
+        axum::body::Body::from_stream(body),
+    )
+        .into_response())
+}
+
+fn json_response(body: Vec<u8>) -> axum::response::Response {
+    (
+        [(axum::http::header::CONTENT_TYPE, "application/json")],
+        body,
     )
-    .map_err(|e| {
-        error!("Database Error: {}", e);
-        INTERNAL_SERVER_ERROR()
-    })?;
+        .into_response()
+}
+
+/// Write `{cursor, total, rows}` for the next page of `cursor`; `cursor` is
+/// null once the last page has been returned
+fn write_page<W: Write>(
+    cursor: &mut Cursor,
+    token: Option<&str>,
+    out: &mut W,
+) -> Result<(), ApiError> {
+    let remaining = cursor.offset() + cursor.page_size() < cursor.total();
+    let token = serde_json::to_string(&token.filter(|_| remaining)).map_err(ApiError::internal)?;
+    write!(
+        out,
+        r#"{{"cursor":{},"total":{},"rows":"#,
+        token,
+        cursor.total()
+    )
+    .map_err(ApiError::internal)?;
+    cursor.next_page(out)?;
+    out.write_all(b"}").map_err(ApiError::internal)
+}
+
+/// Serve the first page, registering the cursor if more remain
+pub(crate) fn first_page(
+    mut cursor: Cursor,
+    client: SocketAddr,
+    max_per_client: usize,
+) -> Result<Vec<u8>, ApiError> {
+    let mut out = Vec::new();
+    if cursor.total() <= cursor.page_size() {
+        write_page(&mut cursor, None, &mut out)?;
+        return Ok(out);
+    }
+
+    let (token, cursor) = CURSORS.insert(client.ip(), cursor, max_per_client)?;
+    let mut cursor = cursor.lock().unwrap_or_else(|e| e.into_inner());
+    write_page(&mut cursor, Some(&token), &mut out).inspect_err(|_| CURSORS.remove(&token))?;
+    Ok(out)
+}
+
+/// Fetch the next page of an open cursor; unknown or expired cursors are 410
+pub(crate) fn next_page(token: &str, idle: Duration) -> Result<Vec<u8>, ApiError> {
+    let cursor = CURSORS
+        .get(token, idle)
+        .ok_or_else(|| ApiError::Gone("cursor expired or unknown".to_string()))?;
+    let mut cursor = cursor.lock().unwrap_or_else(|e| e.into_inner());
+
+    if cursor.exhausted() {
+        CURSORS.remove(token);
+        return Err(ApiError::Gone("cursor expired or unknown".to_string()));
+    }
+
+    let mut out = Vec::new();
+    write_page(&mut cursor, Some(token), &mut out)?;
+    if cursor.exhausted() {
+        CURSORS.remove(token);
+    }
+    Ok(out)
+}
+
+/// Next page of a paged query
+#[utoipa::path(
+    post,
+    path = "/api/1/query/next",
+    tag = "query",
+    request_body = NextRequest,
+    responses(
+        (status = 200, description = "The page, with the cursor if more rows remain", body = Object),
+        (status = 410, description = "Cursor expired or exhausted", body = crate::error::ErrorBody),
+        (status = 500, description = "Query failed", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn post_next(
+    State(state): State<ApiState>,
+    axum::extract::Json(payload): axum::extract::Json<NextRequest>,
+) -> Result<axum::response::Response, ApiError> {
+    let idle = Duration::from_secs(state.config.load().api.query.cursor_idle_secs);
+    let body = tokio::task::spawn_blocking(move || next_page(&payload.cursor, idle))
+        .await
+        .map_err(ApiError::internal)??;
+    Ok(json_response(body))
+}
+
+/// Statements the query endpoint will run
+const ALLOWED_STATEMENTS: &[&str] = &["SELECT", "WITH", "DESCRIBE", "SHOW"];
+
+/// Keywords that modify the database, filesystem, or session; rejected
+/// anywhere in the statement so they can't hide inside a CTE
+const BLOCKED_KEYWORDS: &[&str] = &[
+    "ALTER",
+    "ATTACH",
+    "CALL",
+    "CHECKPOINT",
+    "COPY",
+    "CREATE",
+    "DELETE",
+    "DETACH",
+    "DROP",
+    "EXPORT",
+    "IMPORT",
+    "INSERT",
+    "INSTALL",
+    "LOAD",
+    "MERGE",
+    "PRAGMA",
+    "RESET",
+    "SET",
+    "TRUNCATE",
+    "UPDATE",
+    "USE",
+    "VACUUM",
+];
+
+/// Split SQL into statements of upper-cased bare words, skipping string
+/// literals, quoted identifiers, and comments.
+fn tokenize(sql: &str) -> Vec<Vec<String>> {
+    let mut statements = vec![Vec::new()];
+    let mut word = String::new();
+    let mut chars = sql.chars().peekable();
+
+    let flush = |word: &mut String, statements: &mut Vec<Vec<String>>| {
+        if !word.is_empty() {
+            if let Some(current) = statements.last_mut() {
+                current.push(word.to_uppercase());
+            }
+            word.clear();
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_alphanumeric() || c == '_' => word.push(c),
+            '\'' | '"' => {
+                flush(&mut word, &mut statements);
+                // doubled quotes are escapes and simply re-enter the literal
+                while let Some(next) = chars.next() {
+                    if next == c && chars.peek() != Some(&c) {
+                        break;
+                    } else if next == c {
+                        chars.next();
+                    }
+                }
+            }
+            '$' if chars.peek() == Some(&'$') => {
+                flush(&mut word, &mut statements);
+                chars.next();
+                while let Some(next) = chars.next() {
+                    if next == '$' && chars.peek() == Some(&'$') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                flush(&mut word, &mut statements);
+                for next in chars.by_ref() {
+                    if next == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                flush(&mut word, &mut statements);
+                chars.next();
+                while let Some(next) = chars.next() {
+                    if next == '*' && chars.peek() == Some(&'/') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            ';' => {
+                flush(&mut word, &mut statements);
+                statements.push(Vec::new());
+            }
+            _ => flush(&mut word, &mut statements),
+        }
+    }
+    flush(&mut word, &mut statements);
+
+    statements.retain(|s| !s.is_empty());
+    statements
+}
+
+/// Reject anything but a single read-only statement, naming the offending keyword.
+pub(crate) fn check_read_only(sql: &str) -> Result<(), String> {
+    let statements = tokenize(sql);
+    let statement = match statements.as_slice() {
+        [] => return Err("empty query".to_string()),
+        [statement] => statement,
+        _ => return Err("multiple statements are not allowed".to_string()),
+    };
+
+    if !ALLOWED_STATEMENTS.contains(&statement[0].as_str()) {
+        return Err(format!("{} statements are not allowed", statement[0]));
+    }
+    if let Some(keyword) = statement
+        .iter()
+        .find(|w| BLOCKED_KEYWORDS.contains(&w.as_str()))
+    {
+        return Err(format!("{} is not allowed in queries", keyword));
+    }
+    Ok(())
+}
+
+/// Check that `sql`, a tenant's query, reads only the class views in `views`
+/// and CTEs it defines. DuckDB parses the statement, and every table it
+/// names is checked, including those in subqueries; only SELECT statements
+/// can be checked, so nothing else is run.
+pub(crate) fn check_tenant_query(
+    conn: &duckdb::Connection,
+    sql: &str,
+    views: &[String],
+) -> Result<(), ApiError> {
+    check_read_only(sql).map_err(ApiError::Forbidden)?;
+    let tree: String = conn
+        .query_row(
+            "SELECT json_serialize_sql(?)",
+            duckdb::params![sql],
+            |row| row.get(0),
+        )
+        .map_err(ApiError::database)?;
+    let tree: serde_json::Value = serde_json::from_str(&tree).map_err(ApiError::internal)?;
+    if tree["error"].as_bool().unwrap_or(false) {
+        return Err(ApiError::Forbidden(format!(
+            "only SELECT queries are available to tenant-scoped tokens: {}",
+            tree["error_message"].as_str().unwrap_or_default()
+        )));
+    }
+
+    let mut ctes = Vec::new();
+    cte_names(&tree, &mut ctes);
+    check_tables(&tree, views, &ctes)
+}
+
+/// Collect the names of the CTEs defined anywhere in `node`
+fn cte_names(node: &serde_json::Value, names: &mut Vec<String>) {
+    match node {
+        serde_json::Value::Object(map) => {
+            if let Some(ctes) = map
+                .get("cte_map")
+                .and_then(|m| m.get("map"))
+                .and_then(|m| m.as_array())
+            {
+                names.extend(
+                    ctes.iter()
+                        .filter_map(|cte| cte["key"].as_str())
+                        .map(str::to_lowercase),
+                );
+            }
+            map.values().for_each(|v| cte_names(v, names));
+        }
+        serde_json::Value::Array(items) => items.iter().for_each(|v| cte_names(v, names)),
+        _ => {}
+    }
+}
+
+/// Refuse table functions and any table other than a view or CTE
+fn check_tables(
+    node: &serde_json::Value,
+    views: &[String],
+    ctes: &[String],
+) -> Result<(), ApiError> {
+    match node {
+        serde_json::Value::Object(map) => {
+            match map.get("type").and_then(|t| t.as_str()) {
+                Some("TABLE_FUNCTION") => {
+                    return Err(ApiError::Forbidden(
+                        "table functions are not available to tenant-scoped tokens".to_string(),
+                    ));
+                }
+                Some("BASE_TABLE") => {
+                    let qualified = ["catalog_name", "schema_name"].iter().any(|k| {
+                        map.get(*k)
+                            .and_then(|v| v.as_str())
+                            .is_some_and(|v| !v.is_empty())
+                    });
+                    let name = map
+                        .get("table_name")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or_default()
+                        .to_lowercase();
+                    let known =
+                        views.iter().any(|v| v.to_lowercase() == name) || ctes.contains(&name);
+                    if qualified || !known {
+                        return Err(ApiError::Forbidden(format!("{} is not a class view", name)));
+                    }
+                }
+                _ => {}
+            }
+            map.values().try_for_each(|v| check_tables(v, views, ctes))
+        }
+        serde_json::Value::Array(items) => {
+            items.iter().try_for_each(|v| check_tables(v, views, ctes))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Convert JSON query parameters to DuckDB values.
+///
+/// Strings that parse as RFC3339 are bound as TIMESTAMP; arrays and
+/// objects are rejected.
+pub(crate) fn bind_params(params: &[serde_json::Value]) -> Result<Vec<Value>, String> {
+    params
+        .iter()
+        .enumerate()
+        .map(|(i, param)| match param {
+            serde_json::Value::Null => Ok(Value::Null),
+            serde_json::Value::Bool(b) => Ok(Value::Boolean(*b)),
+            serde_json::Value::Number(n) => n
+                .as_i64()
+                .map(Value::BigInt)
+                .or_else(|| n.as_u64().map(Value::UBigInt))
+                .or_else(|| n.as_f64().map(Value::Double))
+                .ok_or_else(|| format!("parameter {} is not a representable number", i + 1)),
+            serde_json::Value::String(s) => Ok(chrono::DateTime::parse_from_rfc3339(s)
+                .map(|t| Value::Timestamp(TimeUnit::Microsecond, t.timestamp_micros()))
+                .unwrap_or_else(|_| Value::Text(s.clone()))),
+            _ => Err(format!(
+                "parameter {} must be a string, number, boolean, or null",
+                i + 1
+            )),
+        })
+        .collect()
+}
 
-    let sql = &payload.sql;
-    let limit = payload.limit;
+/// Run `sql` with bound `params`, writing rows to `out` one record batch at a time.
+///
+/// Nothing is written until the statement has been validated and prepared,
+/// so an error return with empty output means the query never ran.
+pub(crate) fn write_query<W: Write>(
+    conn: &duckdb::Connection,
+    sql: &str,
+    params: &[serde_json::Value],
+    limit: usize,
+    format: QueryFormat,
+    include_schema: bool,
+    out: &mut W,
+) -> Result<(), ApiError> {
+    check_read_only(sql).map_err(ApiError::Forbidden)?;
+    let params = bind_params(params).map_err(ApiError::BadRequest)?;
 
     let sql = if !sql.trim().to_lowercase().contains("limit") {
         format!("{} LIMIT {}", sql.trim_end_matches(';'), limit)
@@ -65,42 +786,138 @@ async fn post_query(
         sql.to_string()
     };
 
-    let mut stmt = conn.prepare(&sql).map_err(|_| {
-        (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            "SQL Error".to_string(),
-        )
-    })?;
-
-    let res = stmt
-        .query_arrow([])
-        .map_err(|_| {
-            (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                "SQL Error".to_string(),
-            )
-        })?
-        .collect::<Vec<_>>();
-
-    let buf = Vec::new();
-    let mut writer = ArrayWriter::new(buf);
-    let batch_refs: Vec<&_> = res.iter().collect();
-
-    writer.write_batches(&batch_refs).map_err(|e| {
-        error!("Arrow Error writing batches: {}", e);
-        INTERNAL_SERVER_ERROR()
-    })?;
-
-    writer.finish().map_err(|e| {
-        error!("Arrow Error: {}", e);
-        INTERNAL_SERVER_ERROR()
-    })?;
-
-    let out: serde_json::Value =
-        serde_json::from_reader(writer.into_inner().as_slice()).map_err(|e| {
-            error!("JSON Serialization Error: {}", e);
-            INTERNAL_SERVER_ERROR()
-        })?;
-
-    Ok(axum::Json(out))
+    let mut stmt = conn.prepare(&sql).map_err(ApiError::database)?;
+
+    let expected = stmt.parameter_count();
+    if expected != params.len() {
+        return Err(ApiError::BadRequest(format!(
+            "query has {} parameter placeholders but {} values were supplied",
+            expected,
+            params.len()
+        )));
+    }
+
+    // Run inside a transaction that is always rolled back, so anything that
+    // slips past the statement check leaves no trace in the database
+    conn.execute_batch("BEGIN TRANSACTION;")
+        .map_err(ApiError::database)?;
+    let result = stmt
+        .query_arrow(duckdb::params_from_iter(params))
+        .map_err(ApiError::database)
+        .and_then(|batches| {
+            let schema = include_schema.then(|| batches.get_schema());
+            write_batches(batches, format, schema.as_deref(), out)
+        });
+    conn.execute_batch("ROLLBACK;")
+        .inspect_err(|e| error!("Database Error: {}", e))
+        .ok();
+    result
+}
+
+/// Column type vocabulary exposed to clients
+pub(crate) fn column_type(data_type: &DataType) -> &'static str {
+    match data_type {
+        DataType::Boolean => "bool",
+        DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64 => "int",
+        DataType::Float16
+        | DataType::Float32
+        | DataType::Float64
+        | DataType::Decimal128(_, _)
+        | DataType::Decimal256(_, _) => "float",
+        DataType::Timestamp(_, _)
+        | DataType::Date32
+        | DataType::Date64
+        | DataType::Time32(_)
+        | DataType::Time64(_) => "timestamp",
+        DataType::Struct(_) | DataType::Map(_, _) => "struct",
+        DataType::List(_) | DataType::LargeList(_) | DataType::FixedSizeList(_, _) => "list",
+        _ => "string",
+    }
+}
+
+/// `[{name, type}]` for each column of `schema`
+fn columns(schema: &Schema) -> serde_json::Value {
+    schema
+        .fields()
+        .iter()
+        .map(|f| serde_json::json!({"name": f.name(), "type": column_type(f.data_type())}))
+        .collect()
+}
+
+/// Serialize record batches as they arrive, never holding more than one.
+///
+/// With a `schema`, JSON output becomes `{"columns": [...], "rows": [...]}`
+/// and NDJSON output starts with a `{"columns": [...]}` line.
+pub(crate) fn write_batches<W: Write>(
+    batches: impl Iterator<Item = duckdb::arrow::record_batch::RecordBatch>,
+    format: QueryFormat,
+    schema: Option<&Schema>,
+    out: &mut W,
+) -> Result<(), ApiError> {
+    let mut first = true;
+    match (format, schema) {
+        (QueryFormat::Json, None) => out.write_all(b"[").map_err(ApiError::internal)?,
+        (QueryFormat::Json, Some(schema)) => {
+            write!(out, r#"{{"columns":{},"rows":["#, columns(schema))
+                .map_err(ApiError::internal)?
+        }
+        (QueryFormat::Ndjson, Some(schema)) => {
+            writeln!(out, r#"{{"columns":{}}}"#, columns(schema)).map_err(ApiError::internal)?
+        }
+        (QueryFormat::Ndjson, None) => {}
+    }
+
+    let mut lines = Vec::new();
+    for batch in batches {
+        lines.clear();
+        {
+            let mut writer = LineDelimitedWriter::new(&mut lines);
+            writer
+                .write(&batch)
+                .and_then(|_| writer.finish())
+                .map_err(ApiError::internal)?;
+        }
+
+        match format {
+            QueryFormat::Ndjson => out.write_all(&lines).map_err(ApiError::internal)?,
+            // Rows are single-line JSON objects; join them into one array
+            QueryFormat::Json => {
+                for row in lines.split(|b| *b == b'\n').filter(|r| !r.is_empty()) {
+                    if !first {
+                        out.write_all(b",").map_err(ApiError::internal)?;
+                    }
+                    first = false;
+                    out.write_all(row).map_err(ApiError::internal)?;
+                }
+            }
+        }
+    }
+
+    if let QueryFormat::Json = format {
+        out.write_all(b"]").map_err(ApiError::internal)?;
+        if schema.is_some() {
+            out.write_all(b"}").map_err(ApiError::internal)?;
+        }
+    }
+    Ok(())
+}
+
+/// Run `sql` with bound `params`, returning rows as a JSON array.
+#[cfg(test)]
+pub(crate) fn execute_query(
+    conn: &duckdb::Connection,
+    sql: &str,
+    params: &[serde_json::Value],
+    limit: usize,
+) -> Result<serde_json::Value, ApiError> {
+    let mut out = Vec::new();
+    write_query(conn, sql, params, limit, QueryFormat::Json, false, &mut out)?;
+    serde_json::from_slice(&out).map_err(ApiError::internal)
 }
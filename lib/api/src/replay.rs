@@ -0,0 +1,519 @@
+//! Replays of stored events through detection rules.
+//!
+//! - POST /api/1/detections/replay - Start a replay, returning its job
+//! - GET /api/1/detections/replay - Replay jobs since startup
+//! - GET /api/1/detections/replay/:id - A job's progress, and its report
+//!   once it's done
+//! - DELETE /api/1/detections/replay/:id - Cancel a running job
+//!
+//! A replay reads the events of one OCSF class stored between `start` and
+//! `end` (see [`striem_storage::reader`]) and evaluates them against a rule
+//! collection of its own, loaded afresh from `detections` and restricted to
+//! the selected rules, or every rule when none are. Findings are written to
+//! storage with `replay` in their `metadata.labels`, or with `dry_run` only
+//! counted per rule. They never reach outputs, notifications or playbooks.
+//! `striem replay` runs the same from the command line, with the severity
+//! overrides and reference lists saved in the database.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+
+use anyhow::{Result, anyhow};
+use arc_swap::ArcSwap;
+use axum::{
+    Router,
+    extract::{Path, State},
+    http::StatusCode,
+    routing::get,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sigmars::{MemBackend, SigmaCollection};
+use striem_common::{
+    detection,
+    lists::ReferenceLists,
+    prelude::*,
+    severity::{LevelOverrides, Severity},
+};
+use striem_config::StrIEMConfig;
+use striem_storage::{ParquetBackend, reader};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{ApiState, error::ApiError};
+
+/// OCSF detection_finding
+const FINDING_CLASS_UID: u32 = 2004;
+
+static JOBS: LazyLock<RwLock<Vec<Arc<Job>>>> = LazyLock::new(|| RwLock::new(Vec::new()));
+
+const NOW: fn() -> DateTime<Utc> = Utc::now;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ReplayRequest {
+    /// OCSF class name, e.g. `authentication`
+    pub class: String,
+    pub start: DateTime<Utc>,
+    #[serde(default = "NOW")]
+    pub end: DateTime<Utc>,
+    /// Rule ids to evaluate; every rule when empty
+    #[serde(default)]
+    pub rules: Vec<String>,
+    /// Only count findings instead of storing them
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// How far a replay has got, updated as it runs
+#[derive(Debug, Default)]
+pub struct ReplayProgress {
+    files: AtomicU64,
+    files_read: AtomicU64,
+    events: AtomicU64,
+    findings: AtomicU64,
+    cancelled: AtomicBool,
+}
+
+impl ReplayProgress {
+    /// Stop the replay after the batch it's evaluating
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    fn snapshot(&self) -> ProgressSnapshot {
+        ProgressSnapshot {
+            files: self.files.load(Ordering::Relaxed),
+            files_read: self.files_read.load(Ordering::Relaxed),
+            events: self.events.load(Ordering::Relaxed),
+            findings: self.findings.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ProgressSnapshot {
+    /// Files to read
+    pub files: u64,
+    pub files_read: u64,
+    /// Events evaluated so far
+    pub events: u64,
+    pub findings: u64,
+}
+
+/// Findings of one rule
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RuleFindings {
+    pub id: String,
+    pub title: Option<String>,
+    pub findings: u64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ReplayReport {
+    pub files: u64,
+    pub events: u64,
+    pub findings: u64,
+    /// Findings stored; 0 for a dry run
+    pub written: u64,
+    /// Rules that matched, most findings first
+    pub rules: Vec<RuleFindings>,
+    /// Whether the replay was cancelled before reading every file
+    pub cancelled: bool,
+}
+
+/// A fresh collection of the rules in `detections`, with all but `selected`
+/// disabled unless it's empty, and the title of every rule by id
+async fn load_selected(
+    config: &StrIEMConfig,
+    selected: &[String],
+) -> Result<(SigmaCollection, HashMap<String, Option<String>>)> {
+    let mut rules = SigmaCollection::default();
+    crate::load_rules(config, &mut rules).await?;
+
+    let titles = serde_json::to_value(&rules)?
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|rule| {
+            let id = rule.get("id")?.as_str()?.to_string();
+            let title = rule.get("title").and_then(|t| t.as_str()).map(String::from);
+            Some((id, title))
+        })
+        .collect::<HashMap<_, _>>();
+    if let Some(unknown) = selected.iter().find(|id| !titles.contains_key(*id)) {
+        return Err(anyhow!("no rule with id {}", unknown));
+    }
+
+    rules.init(&mut MemBackend::new().await).await;
+    if !selected.is_empty() {
+        for id in titles.keys().filter(|id| !selected.contains(id)) {
+            if let Some(rule) = rules.get(id) {
+                rule.disable();
+            }
+        }
+    }
+    Ok((rules, titles))
+}
+
+/// Severity overrides and reference lists saved through the API, read the
+/// way the API server reads them at startup, for a replay run outside it
+pub fn saved_overrides(config: &StrIEMConfig) -> Result<(LevelOverrides, ReferenceLists)> {
+    let (levels, lists) = (LevelOverrides::default(), ReferenceLists::default());
+    let Some(db) = crate::initdb(config)? else {
+        return Ok((levels, lists));
+    };
+    let mut conn = db.get()?;
+    levels.store(Arc::new(
+        crate::persist::rule_states(&mut conn)?
+            .into_iter()
+            .filter_map(|(id, _, level)| Some((id, level?.parse::<Severity>().ok()?)))
+            .collect(),
+    ));
+    let references = crate::persist::lists(&mut conn)?
+        .into_iter()
+        .map(|list| (list.name.clone(), list))
+        .collect();
+    lists.store(Arc::new(crate::lists::matchers(&references)));
+    Ok((levels, lists))
+}
+
+/// Replay the stored events `request` selects, reporting to `progress` as
+/// it goes. `levels` and `lists` are read once, at the start.
+pub async fn replay(
+    config: &Arc<ArcSwap<StrIEMConfig>>,
+    request: &ReplayRequest,
+    levels: &LevelOverrides,
+    lists: &ReferenceLists,
+    progress: &ReplayProgress,
+) -> Result<ReplayReport> {
+    let loaded = config.load_full();
+    let storage = loaded
+        .storage
+        .as_ref()
+        .ok_or_else(|| anyhow!("storage is not configured"))?;
+    if request.end < request.start {
+        return Err(anyhow!("end is before start"));
+    }
+    let files = reader::class_files(storage, &request.class, request.start, request.end)?;
+    progress.files.store(files.len() as u64, Ordering::Relaxed);
+
+    let (rules, titles) = load_selected(&loaded, &request.rules).await?;
+    let ocsf = detection::ocsf_rules(&rules);
+    let (levels, lists) = (levels.load_full(), lists.load_full());
+
+    let writer = match request.dry_run {
+        true => None,
+        false => {
+            let writer = ParquetBackend::new(config)?
+                .take_writer(FINDING_CLASS_UID)
+                .ok_or_else(|| anyhow!("no detection_finding schema loaded"))?;
+            // opens the first file
+            writer.flush().await?;
+            Some(writer)
+        }
+    };
+
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    let mut written = 0;
+    for file in files {
+        if progress.is_cancelled() {
+            break;
+        }
+        let events = {
+            let (file, start, end) = (file.clone(), request.start, request.end);
+            tokio::task::spawn_blocking(move || reader::read_events(&file, start, end)).await?
+        };
+        let events = match events {
+            Ok(events) => events,
+            Err(e) => {
+                warn!("replay: skipping {}: {}", file.display(), e);
+                progress.files_read.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+        };
+
+        for batch in events.chunks(REPLAY_BATCH_SIZE) {
+            if progress.is_cancelled() {
+                break;
+            }
+            let mut findings = Vec::new();
+            for event in batch {
+                match detection::findings(&rules, &ocsf, &levels, &lists, event).await {
+                    Ok(matches) => {
+                        for (id, mut finding) in matches {
+                            *counts.entry(id).or_default() += 1;
+                            label(&mut finding.data);
+                            finding.metadata.insert("replay".to_string(), json!(true));
+                            findings.push(finding);
+                        }
+                    }
+                    Err(e) => warn!("replay: error applying detection rules: {}", e),
+                }
+            }
+            progress
+                .events
+                .fetch_add(batch.len() as u64, Ordering::Relaxed);
+            progress
+                .findings
+                .fetch_add(findings.len() as u64, Ordering::Relaxed);
+
+            if let Some(writer) = &writer
+                && !findings.is_empty()
+            {
+                let rows = findings.iter().map(|f| &f.data).collect::<Vec<_>>();
+                let rejected = writer.write_batch(&rows).await?;
+                written += (rows.len() - rejected.len()) as u64;
+            }
+        }
+        progress.files_read.fetch_add(1, Ordering::Relaxed);
+    }
+    if let Some(writer) = &writer {
+        writer.flush().await?;
+    }
+
+    let snapshot = progress.snapshot();
+    let mut rules = counts
+        .into_iter()
+        .map(|(id, findings)| RuleFindings {
+            title: titles.get(&id).cloned().flatten(),
+            id,
+            findings,
+        })
+        .collect::<Vec<_>>();
+    rules.sort_by(|a, b| b.findings.cmp(&a.findings).then_with(|| a.id.cmp(&b.id)));
+    Ok(ReplayReport {
+        files: snapshot.files_read,
+        events: snapshot.events,
+        findings: snapshot.findings,
+        written,
+        rules,
+        cancelled: progress.is_cancelled(),
+    })
+}
+
+/// Add `replay` to the finding's `metadata.labels`
+fn label(finding: &mut serde_json::Value) {
+    let labels = &mut finding["metadata"]["labels"];
+    match labels.as_array_mut() {
+        Some(labels) => labels.push(json!("replay")),
+        None => *labels = json!(["replay"]),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+/// A replay started through the API
+#[derive(Debug)]
+pub(crate) struct Job {
+    id: String,
+    request: ReplayRequest,
+    started_at: DateTime<Utc>,
+    progress: ReplayProgress,
+    outcome: Mutex<Option<(DateTime<Utc>, Result<ReplayReport, String>)>>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ReplayJob {
+    pub id: String,
+    pub status: JobStatus,
+    pub request: ReplayRequest,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub progress: ProgressSnapshot,
+    pub report: Option<ReplayReport>,
+    pub error: Option<String>,
+}
+
+impl Job {
+    fn is_finished(&self) -> bool {
+        self.outcome
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_some()
+    }
+
+    fn view(&self) -> ReplayJob {
+        let outcome = self.outcome.lock().unwrap_or_else(|e| e.into_inner());
+        let (status, finished_at, report, error) = match outcome.as_ref() {
+            None => (JobStatus::Running, None, None, None),
+            Some((at, Ok(report))) if report.cancelled => {
+                (JobStatus::Cancelled, Some(*at), Some(report.clone()), None)
+            }
+            Some((at, Ok(report))) => (JobStatus::Completed, Some(*at), Some(report.clone()), None),
+            Some((at, Err(e))) => (JobStatus::Failed, Some(*at), None, Some(e.clone())),
+        };
+        ReplayJob {
+            id: self.id.clone(),
+            status,
+            request: self.request.clone(),
+            started_at: self.started_at,
+            finished_at,
+            progress: self.progress.snapshot(),
+            report,
+            error,
+        }
+    }
+}
+
+/// Add `job`, forgetting the oldest finished jobs past [`REPLAY_JOBS_KEPT`]
+async fn track(job: Arc<Job>) {
+    let mut jobs = JOBS.write().await;
+    let finished = jobs.iter().filter(|job| job.is_finished()).count();
+    let mut excess = finished.saturating_sub(REPLAY_JOBS_KEPT);
+    jobs.retain(|job| {
+        let forget = excess > 0 && job.is_finished();
+        excess -= forget as usize;
+        !forget
+    });
+    jobs.push(job);
+}
+
+async fn find(id: &str) -> Result<Arc<Job>, ApiError> {
+    JOBS.read()
+        .await
+        .iter()
+        .find(|job| job.id == id)
+        .cloned()
+        .ok_or_else(|| ApiError::NotFound(format!("Replay {} not found", id)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/1/detections/replay",
+    tag = "detections",
+    request_body = ReplayRequest,
+    responses(
+        (status = 202, description = "The replay was started", body = ReplayJob),
+        (status = 400, description = "Unknown class or an empty time range", body = crate::error::ErrorBody),
+        (status = 503, description = "Storage is not configured", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn start_replay(
+    State(state): State<ApiState>,
+    axum::extract::Json(request): axum::extract::Json<ReplayRequest>,
+) -> Result<(StatusCode, axum::Json<ReplayJob>), ApiError> {
+    {
+        let config = state.config.load();
+        let storage = config
+            .storage
+            .as_ref()
+            .ok_or_else(|| ApiError::Unavailable("storage is not configured".to_string()))?;
+        reader::class_dir(storage, &request.class)
+            .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+    }
+    if request.end < request.start {
+        return Err(ApiError::BadRequest("end is before start".to_string()));
+    }
+
+    let job = Arc::new(Job {
+        id: uuid::Uuid::now_v7().to_string(),
+        request,
+        started_at: Utc::now(),
+        progress: ReplayProgress::default(),
+        outcome: Mutex::new(None),
+    });
+    track(job.clone()).await;
+    info!("replay {} started: {:?}", job.id, job.request);
+
+    tokio::spawn({
+        let job = job.clone();
+        async move {
+            let result = replay(
+                &state.config,
+                &job.request,
+                &state.levels,
+                &state.lists,
+                &job.progress,
+            )
+            .await;
+            match &result {
+                Ok(report) => info!(
+                    "replay {} finished: {} events, {} findings",
+                    job.id, report.events, report.findings
+                ),
+                Err(e) => warn!("replay {} failed: {:#}", job.id, e),
+            }
+            *job.outcome.lock().unwrap_or_else(|e| e.into_inner()) =
+                Some((Utc::now(), result.map_err(|e| format!("{:#}", e))));
+        }
+    });
+
+    Ok((StatusCode::ACCEPTED, axum::Json(job.view())))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/1/detections/replay",
+    tag = "detections",
+    responses((status = 200, description = "Replays since startup, oldest first", body = [ReplayJob]))
+)]
+pub(crate) async fn list_replays(State(_): State<ApiState>) -> axum::Json<Vec<ReplayJob>> {
+    axum::Json(JOBS.read().await.iter().map(|job| job.view()).collect())
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/1/detections/replay/{id}",
+    tag = "detections",
+    params(("id" = String, Path, description = "Replay id")),
+    responses(
+        (status = 200, description = "The replay", body = ReplayJob),
+        (status = 404, description = "No such replay", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_replay(
+    State(_): State<ApiState>,
+    Path(id): Path<String>,
+) -> Result<axum::Json<ReplayJob>, ApiError> {
+    Ok(axum::Json(find(&id).await?.view()))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/1/detections/replay/{id}",
+    tag = "detections",
+    params(("id" = String, Path, description = "Replay id")),
+    responses(
+        (status = 200, description = "The replay will stop after its current batch", body = ReplayJob),
+        (status = 404, description = "No such replay", body = crate::error::ErrorBody),
+        (status = 409, description = "The replay already finished", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn cancel_replay(
+    State(_): State<ApiState>,
+    Path(id): Path<String>,
+) -> Result<axum::Json<ReplayJob>, ApiError> {
+    let job = find(&id).await?;
+    if job.view().status != JobStatus::Running {
+        return Err(ApiError::Conflict(format!(
+            "Replay {} already finished",
+            id
+        )));
+    }
+    job.progress.cancel();
+    Ok(axum::Json(job.view()))
+}
+
+pub fn create_router() -> axum::Router<ApiState> {
+    Router::new()
+        .route("/", get(list_replays).post(start_replay))
+        .route("/{id}", get(get_replay).delete(cancel_replay))
+}
@@ -1,21 +1,120 @@
-use crate::{ApiState, actions, alerts, detections, sources, vector};
+use crate::{
+    ApiState, actions, alerts, detections, health, lists, notifications, playbooks, provision,
+    sources, vector,
+};
 
-use crate::query;
+use crate::{error::ApiError, openapi, query};
 
-use axum::{Router, http::StatusCode, routing::get};
+use std::time::Instant;
+
+use axum::{
+    Router,
+    extract::{MatchedPath, Request, State},
+    http::{StatusCode, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use striem_common::metrics;
 
 pub fn create_router() -> Router<ApiState> {
+    // ingestion staleness is measured from startup until the first event
+    std::sync::LazyLock::force(&health::STARTED);
     Router::new()
         .route("/health", get(health))
+        .route("/health/live", get(health::live))
+        .route("/health/ready", get(health::ready))
+        .route("/metrics", get(metrics))
+        .route("/api/1/openapi.json", get(openapi::openapi_json))
+        .route("/api/docs", get(openapi::docs))
         .nest("/vector", vector::create_router())
+        .nest("/api/1/vector", provision::create_router())
         .nest("/api/1/alerts", alerts::create_router())
         .nest("/api/1/sources", sources::create_router())
         .nest("/api/1/detections", detections::create_router())
+        .nest("/api/1/lists", lists::create_router())
         .nest("/api/1/actions", actions::create_router())
-        .nest("/api/1/query", query::create_router())
+        .nest("/api/1/notifications", notifications::create_router())
+        .nest("/api/1/playbooks", playbooks::create_router())
+        .nest("/api/1/query", query_router())
         .nest("/api/1/destination", crate::destination::create_router())
+        .nest("/api/1/storage", crate::storage::create_router())
+        .nest("/api/1/config", crate::config::create_router())
+        .nest("/api/1/events", crate::system::create_router())
+        .nest("/api/1/events/tail", crate::tail::create_router())
+        .nest("/api/1/stats", crate::stats::create_router())
+        .nest("/api/1/admin", crate::admin::create_router())
+        .route_layer(middleware::from_fn(request_metrics))
+}
+
+#[cfg(feature = "duckdb")]
+fn query_router() -> Router<ApiState> {
+    query::create_router()
+}
+
+/// Queries run on DuckDB; without it they answer 501 naming the feature
+#[cfg(not(feature = "duckdb"))]
+fn query_router() -> Router<ApiState> {
+    async fn not_built() -> ApiError {
+        ApiError::not_built("duckdb")
+    }
+    Router::new()
+        .route("/", axum::routing::post(not_built))
+        .route("/schema", get(not_built))
+        .route("/next", axum::routing::post(not_built))
+}
+
+/// Record each routed request's latency by method, route template and status
+async fn request_metrics(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_default();
+    let response = next.run(request).await;
+    metrics::observe(
+        "striem_api_request_duration_seconds",
+        &[
+            ("method", &method),
+            ("route", &route),
+            ("status", response.status().as_str()),
+        ],
+        start.elapsed().as_secs_f64(),
+    );
+    response
 }
 
-async fn health() -> StatusCode {
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "system",
+    security(()),
+    responses((status = 200, description = "The API is up; see `/health/ready` for dependencies"))
+)]
+pub(crate) async fn health() -> StatusCode {
     StatusCode::OK
 }
+
+/// Prometheus scrape endpoint; see [`striem_common::metrics`] for the metric
+/// names. Not found when `api.metrics.enabled` is off.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "system",
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text format", body = String, content_type = "text/plain"),
+        (status = 404, description = "Metrics are disabled"),
+    )
+)]
+pub(crate) async fn metrics(State(state): State<ApiState>) -> Response {
+    if !state.config.load().api.metrics.enabled {
+        return ApiError::NotFound("metrics are disabled".to_string()).into_response();
+    }
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics::render(),
+    )
+        .into_response()
+}
@@ -0,0 +1,501 @@
+//! Loading detection rules from their sources.
+//!
+//! - POST /api/1/detections/sync - Fetch git rule sources and apply changes
+//!
+//! Local directories are read as they are. Git sources (see
+//! [`DetectionSource`]) are cloned into `{db}/rules-cache/` with the `git`
+//! command, and fetched again at startup, on request, and every
+//! `detections.sync_interval_secs`. If a fetch fails at startup, the last
+//! clone is used.
+//!
+//! A sync adds the rules that appeared since the last checkout. Rules that
+//! disappeared are disabled, and rules that changed keep their loaded
+//! version; both are only reloaded on restart.
+//!
+//! The file each rule was loaded from is kept as its [`RuleOrigin`], with
+//! the pack it belongs to: the name of its `detections.paths` entry. Rules
+//! from git sources are vendor rules, which the API only changes on request
+//! since their next sync undoes the change.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use arc_swap::ArcSwap;
+use axum::{Json, extract::State};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sigmars::{MemBackend, SigmaCollection, SigmaRule};
+use striem_common::{SysMessage, detection::ocsf_rules};
+use striem_config::{StrIEMConfig, detections::DetectionSource};
+use tokio::sync::{
+    Mutex,
+    broadcast::{self, error::RecvError},
+};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{ApiState, error::ApiError};
+
+/// Held for a whole sync, so the interval's and `POST /detections/sync`
+/// never fetch into a clone or diff its rules at the same time
+static SYNCING: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
+
+/// Outcome of syncing one git source
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct SyncResult {
+    pub url: String,
+    /// Commit checked out after the sync
+    pub commit: Option<String>,
+    /// Ids of rules that appeared
+    pub added: Vec<String>,
+    /// Ids of rules that disappeared, now disabled
+    pub removed: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// Where a loaded rule came from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct RuleOrigin {
+    /// Absolute path of the rule file
+    #[schema(value_type = String)]
+    pub path: PathBuf,
+    /// Name of the `detections.paths` entry the rule came from
+    pub pack: String,
+    /// Whether that entry is a git source
+    pub vendor: bool,
+}
+
+/// Origin of each rule loaded from a file, by rule id
+pub type RuleOrigins = Arc<ArcSwap<HashMap<String, RuleOrigin>>>;
+
+fn db_dir(config: &StrIEMConfig) -> PathBuf {
+    config.db.clone().unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Run `git` with `args`, returning its trimmed stdout
+fn git(dir: Option<&Path>, args: &[&str]) -> Result<String> {
+    let mut command = Command::new("git");
+    if let Some(dir) = dir {
+        command.arg("-C").arg(dir);
+    }
+    let output = command
+        .args(args)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .context("could not run git")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Whether `source` has been cloned before
+fn is_cloned(source: &DetectionSource, db: &Path) -> bool {
+    source
+        .cache_dir(db)
+        .is_some_and(|dir| dir.join(".git").is_dir())
+}
+
+/// Clone or fetch `source` into its cache directory and check out its ref,
+/// returning the commit
+pub(crate) fn fetch(source: &DetectionSource, db: &Path) -> Result<String> {
+    let (DetectionSource::Git { url, reference, .. }, Some(dir)) = (source, source.cache_dir(db))
+    else {
+        bail!("{} is not a git source", source);
+    };
+    if is_cloned(source, db) {
+        git(
+            Some(&dir),
+            &["fetch", "--quiet", "--tags", "--prune", "origin"],
+        )?;
+    } else {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("could not create {}", dir.display()))?;
+        let target = dir.to_string_lossy();
+        git(
+            None,
+            &["clone", "--quiet", "--no-checkout", "--", url, &target],
+        )?;
+    }
+
+    // a branch name means the fetched remote branch, not a stale local one
+    let target = match reference {
+        Some(reference) => {
+            let remote = format!("origin/{}^{{commit}}", reference);
+            git(Some(&dir), &["rev-parse", "--verify", "--quiet", &remote])
+                .or_else(|_| {
+                    let local = format!("{}^{{commit}}", reference);
+                    git(Some(&dir), &["rev-parse", "--verify", "--quiet", &local])
+                })
+                .map_err(|_| anyhow!("{} has no ref {}", url, reference))?
+        }
+        None => "origin/HEAD".to_string(),
+    };
+    git(
+        Some(&dir),
+        &["checkout", "--quiet", "--force", "--detach", &target],
+    )?;
+    git(Some(&dir), &["rev-parse", "HEAD"])
+}
+
+/// Rule files under `dir`, recursively
+fn rule_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        if path.is_dir() && !name.starts_with('.') {
+            rule_files(&path, files);
+        } else if matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("yml" | "yaml")
+        ) {
+            files.push(path);
+        }
+    }
+}
+
+/// The rules in `dir` and the absolute paths of their files; files that
+/// don't parse as rules are skipped
+fn read_rules(dir: &Path) -> Vec<(PathBuf, SigmaRule)> {
+    let mut files = Vec::new();
+    rule_files(dir, &mut files);
+    files
+        .into_iter()
+        .filter_map(|file| Some((std::fs::read_to_string(&file).ok()?, file)))
+        .flat_map(|(s, file)| {
+            let file = std::fs::canonicalize(&file).unwrap_or(file);
+            serde_yaml::Deserializer::from_str(&s)
+                .filter_map(|document| SigmaRule::deserialize(document).ok())
+                .map(|rule| (file.clone(), rule))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Origin of a rule uploaded to `dir`, the upload directory
+pub(crate) fn uploaded(dir: &Path, id: &str) -> RuleOrigin {
+    let path = dir.join(format!("{}.yaml", id));
+    RuleOrigin {
+        path: std::fs::canonicalize(&path).unwrap_or(path),
+        pack: DetectionSource::Path(dir.to_path_buf()).pack(),
+        vendor: false,
+    }
+}
+
+/// The YAML documents of a rule file, split on `---` lines
+fn documents(content: &str) -> Vec<String> {
+    let mut documents = vec![String::new()];
+    for line in content.lines() {
+        if line.trim_end() == "---" {
+            documents.push(String::new());
+        } else if let Some(document) = documents.last_mut() {
+            document.push_str(line);
+            document.push('\n');
+        }
+    }
+    documents.retain(|document| !document.trim().is_empty());
+    documents
+}
+
+/// Replace the document of rule `id` in the file at `path` with
+/// `replacement`, or remove it with `None`. Other documents, such as the
+/// other rules of a collection, are kept; a file left empty is deleted.
+/// The file is replaced whole, so a crash never leaves it half written
+pub(crate) fn rewrite_rule(path: &Path, id: &str, replacement: Option<&str>) -> Result<()> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("could not read {}", path.display()))?;
+    let mut documents = documents(&content);
+    let index = documents
+        .iter()
+        .position(|document| {
+            serde_yaml::from_str::<serde_yaml::Value>(document)
+                .is_ok_and(|value| value.get("id").and_then(|v| v.as_str()) == Some(id))
+        })
+        .ok_or_else(|| anyhow!("{} no longer holds rule {}", path.display(), id))?;
+    match replacement {
+        Some(replacement) => documents[index] = format!("{}\n", replacement.trim_end()),
+        None => {
+            documents.remove(index);
+        }
+    }
+
+    if documents.is_empty() {
+        std::fs::remove_file(path).with_context(|| format!("could not remove {}", path.display()))
+    } else {
+        crate::provision::write_atomic(path, &documents.join("---\n"))
+    }
+}
+
+/// `detections` with its rule `rule.id` replaced by `rule`, each rule
+/// keeping whether it's enabled. sigmars can't replace a rule in place, so
+/// the collection is rebuilt from its rules
+pub(crate) async fn replaced(
+    detections: &SigmaCollection,
+    rule: SigmaRule,
+) -> Result<SigmaCollection> {
+    let Value::Array(rules) = serde_json::to_value(detections)? else {
+        bail!("the loaded rules could not be listed");
+    };
+    let mut rule = Some(rule);
+    let (mut replaced, mut disabled) = (SigmaCollection::default(), Vec::new());
+    for value in rules {
+        let id = value
+            .get("id")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string();
+        if value.get("enabled").and_then(|v| v.as_bool()) == Some(false) {
+            disabled.push(id.clone());
+        }
+        let next = match rule.take_if(|rule| rule.id == id) {
+            Some(rule) => rule,
+            None => serde_json::from_value(value)
+                .with_context(|| format!("could not reload rule {}", id))?,
+        };
+        replaced.add(next).map_err(|e| anyhow!(e.to_string()))?;
+    }
+    if let Some(rule) = rule {
+        bail!("rule {} is not loaded", rule.id);
+    }
+    replaced.init(&mut MemBackend::new().await).await;
+    for id in disabled {
+        if let Some(rule) = replaced.get(&id) {
+            rule.disable();
+        }
+    }
+    Ok(replaced)
+}
+
+/// Load the rules of every configured source into `detections`, fetching
+/// git sources first; returns the number loaded and where each came from.
+/// A rule found in more than one source keeps the first
+pub async fn load_rules(
+    config: &StrIEMConfig,
+    detections: &mut SigmaCollection,
+) -> Result<(usize, HashMap<String, RuleOrigin>)> {
+    let mut origins = HashMap::new();
+    let Some(sources) = config.detections.as_ref().map(|d| d.sources()) else {
+        warn!("No detection rules loaded");
+        return Ok((0, origins));
+    };
+    let db = db_dir(config);
+
+    let mut count = 0;
+    for source in sources {
+        if let DetectionSource::Git { url, .. } = &source {
+            let fetched = {
+                let (source, db) = (source.clone(), db.clone());
+                tokio::task::spawn_blocking(move || fetch(&source, &db)).await?
+            };
+            match fetched {
+                Ok(commit) => info!("... rules from {} at {}", url, commit),
+                Err(e) if is_cloned(&source, &db) => {
+                    warn!("could not fetch {}, using the last clone: {:#}", url, e)
+                }
+                Err(e) => return Err(e.context(format!("could not clone {}", url))),
+            }
+        }
+        let dir = source.rules_dir(&db);
+        info!("... loading Sigma detection rules from {}", dir.display());
+        let (pack, vendor) = (source.pack(), matches!(source, DetectionSource::Git { .. }));
+        for (path, rule) in read_rules(&dir) {
+            let id = rule.id.clone();
+            if detections.get(&id).is_some() {
+                continue;
+            }
+            match detections.add(rule) {
+                Ok(_) => count += 1,
+                Err(e) => {
+                    warn!("could not add rule {} from {}: {}", id, path.display(), e);
+                    continue;
+                }
+            }
+            let origin = RuleOrigin {
+                path,
+                pack: pack.clone(),
+                vendor,
+            };
+            origins.insert(id, origin);
+        }
+    }
+    Ok((count, origins))
+}
+
+/// Fetch `source` and apply the rules that appeared or disappeared
+async fn sync_source(state: &ApiState, source: DetectionSource, db: &Path) -> SyncResult {
+    let DetectionSource::Git { url, .. } = &source else {
+        return SyncResult::default();
+    };
+    let mut result = SyncResult {
+        url: url.clone(),
+        ..Default::default()
+    };
+
+    let dir = source.rules_dir(db);
+    let ids = |rules: &[(PathBuf, SigmaRule)]| {
+        rules
+            .iter()
+            .map(|(_, r)| r.id.clone())
+            .collect::<HashSet<_>>()
+    };
+    let before = {
+        let dir = dir.clone();
+        tokio::task::spawn_blocking(move || read_rules(&dir))
+            .await
+            .unwrap_or_default()
+    };
+    let fetched = {
+        let (source, db) = (source.clone(), db.to_path_buf());
+        tokio::task::spawn_blocking(move || fetch(&source, &db).map(|c| (c, read_rules(&dir))))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|r| r)
+    };
+    let (commit, after) = match fetched {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            warn!("could not sync rules from {}: {:#}", url, e);
+            result.error = Some(format!("{:#}", e));
+            return result;
+        }
+    };
+    result.commit = Some(commit);
+
+    let (old, new) = (ids(&before), ids(&after));
+    // rules found in an earlier source keep their origin there
+    let pack = source.pack();
+    let ours = |origin: &RuleOrigin| origin.vendor && origin.pack == pack;
+    let mut origins = (**state.origins.load()).clone();
+    for (path, rule) in &after {
+        if origins.get(&rule.id).is_none_or(ours) {
+            let origin = RuleOrigin {
+                path: path.clone(),
+                pack: pack.clone(),
+                vendor: true,
+            };
+            origins.insert(rule.id.clone(), origin);
+        }
+    }
+    for id in old.difference(&new) {
+        if origins.get(id).is_some_and(ours) {
+            origins.remove(id);
+        }
+    }
+    state.origins.store(Arc::new(origins));
+
+    let mut detections = state.detections.write().await;
+    for (_, rule) in after
+        .into_iter()
+        .filter(|(_, rule)| !old.contains(&rule.id))
+    {
+        let id = rule.id.clone();
+        // a rule removed by an earlier sync is still loaded, disabled
+        if let Some(existing) = detections.get(&id) {
+            existing.enable();
+            result.added.push(id);
+            continue;
+        }
+        match detections.add(rule) {
+            Ok(_) => result.added.push(id),
+            Err(e) => warn!("could not add rule {} from {}: {}", id, url, e),
+        }
+    }
+    if !result.added.is_empty() {
+        state.ocsf.store(Arc::new(ocsf_rules(&detections)));
+    }
+    for id in old.difference(&new) {
+        if let Some(rule) = detections.get(id) {
+            rule.disable();
+        }
+        result.removed.push(id.clone());
+    }
+    drop(detections);
+    result.added.sort();
+    result.removed.sort();
+    if !result.added.is_empty() || !result.removed.is_empty() {
+        state.coverage.store(None);
+    }
+
+    info!(
+        "rules from {} synced to {}: {} added, {} removed",
+        url,
+        result.commit.as_deref().unwrap_or_default(),
+        result.added.len(),
+        result.removed.len()
+    );
+    result
+}
+
+/// Sync every git source
+pub(crate) async fn sync(state: &ApiState) -> Vec<SyncResult> {
+    let config = state.config.load();
+    let Some(detections) = config.detections.as_ref() else {
+        return Vec::new();
+    };
+    let db = db_dir(&config);
+    let _syncing = SYNCING.lock().await;
+    let mut results = Vec::new();
+    for source in detections.sources() {
+        if matches!(source, DetectionSource::Git { .. }) {
+            results.push(sync_source(state, source, &db).await);
+        }
+    }
+    results
+}
+
+/// Sync git sources every `detections.sync_interval_secs`, until shutdown
+pub(crate) async fn run(state: ApiState, mut shutdown: broadcast::Receiver<SysMessage>) {
+    loop {
+        let interval = state
+            .config
+            .load()
+            .detections
+            .as_ref()
+            .and_then(|d| d.sync_interval_secs)
+            .filter(|secs| *secs > 0);
+        let Some(interval) = interval else {
+            return;
+        };
+        tokio::select! {
+            msg = shutdown.recv() => {
+                if matches!(msg, Ok(SysMessage::Shutdown) | Err(RecvError::Closed)) {
+                    info!("rule sync shutting down...");
+                    return;
+                }
+            },
+            _ = tokio::time::sleep(Duration::from_secs(interval)) => {
+                sync(&state).await;
+            },
+        }
+    }
+}
+
+/// Fetch git rule sources now
+#[utoipa::path(
+    post,
+    path = "/api/1/detections/sync",
+    tag = "detections",
+    responses(
+        (status = 200, description = "Outcome per git source; a failed source has `error` set", body = Vec<SyncResult>),
+    )
+)]
+pub(crate) async fn post_sync(
+    State(state): State<ApiState>,
+) -> Result<Json<Vec<SyncResult>>, ApiError> {
+    Ok(Json(sync(&state).await))
+}
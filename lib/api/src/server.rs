@@ -9,79 +9,196 @@
 //! # Architecture
 //! - Axum for HTTP routing and middleware
 //! - Tower HTTP for CORS and static file serving
+//! - Optional bearer token auth by role (see [`crate::auth`])
 //! - DuckDB connection pool for query execution
 //! - Shared state (Arc) for detection rules and configuration
 
-use std::sync::Arc;
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
 
 use anyhow::Result;
 use arc_swap::ArcSwap;
 use axum::http::HeaderValue;
-use axum::middleware;
-use log::{error, info};
+use axum::{Router, middleware};
+use axum_server::tls_rustls::RustlsConfig;
 use sigmars::SigmaCollection;
 use tokio::sync::RwLock;
 use tower_http::cors::CorsLayer;
 use tower_http::services::ServeDir;
+use tracing::{error, info};
 
 use striem_config::StrIEMConfig;
-use striem_config::StringOrList;
 
-use striem_common::SysMessage;
+use striem_common::{
+    SysMessage,
+    detection::OcsfRules,
+    event::Event,
+    lists::ReferenceLists,
+    severity::{LevelOverrides, Severity},
+};
 
 use crate::{
-    ApiState, actions::Mcp, features::feature_flag_middleware, initdb, persist,
-    routes::create_router, sources::SOURCES,
+    ApiState,
+    actions::Mcp,
+    auth::{Tokens, require_token},
+    features::feature_flag_middleware,
+    health::Health,
+    initdb,
+    lists::LISTS,
+    notifications::{self, NOTIFICATIONS, NotificationRule},
+    persist,
+    playbooks::{self, PLAYBOOKS, Playbook},
+    routes::create_router,
+    rules::RuleOrigins,
+    sources::{SOURCES, tokens::SOURCE_TOKENS},
+    stats::LiveStats,
+    tail::Tail,
 };
 
+/// State the API shares with the rest of the process: the detection
+/// engine's rules, and the channels it reads events from
+#[derive(Clone)]
+pub struct Shared {
+    pub detections: Arc<RwLock<SigmaCollection>>,
+    /// Ids of the rules written against OCSF fields
+    pub ocsf: OcsfRules,
+    pub levels: LevelOverrides,
+    /// File and pack each rule was loaded from
+    pub origins: RuleOrigins,
+    pub lists: ReferenceLists,
+    /// Detection findings, when the detection engine runs in this process
+    pub findings: Option<tokio::sync::broadcast::Sender<Arc<Vec<Event>>>>,
+    /// Received events, when this process runs the Vector server
+    pub received: Option<tokio::sync::broadcast::Sender<Arc<Vec<Event>>>>,
+}
+
 /// Initialize and run the API server.
 ///
 /// # Database Initialization
 /// Creates DuckDB connection pool if storage is configured.
 /// Uses file-backed DB if data_dir specified, otherwise in-memory.
 /// Enables parquet_metadata_cache for faster queries on large datasets.
+/// Every connection may only read storage, the database directory and the
+/// OCSF category directories; startup fails if that can't be enforced.
+///
+/// # Event Tail
+/// With [`Shared::received`], the Vector server's event channel, the most
+/// recent events are kept for `/api/1/events/tail`; see [`crate::tail`].
+///
+/// # Live Stats
+/// [`Shared::received`] and [`Shared::findings`] are counted for
+/// `/api/1/stats/live`; see [`crate::stats`]. Without `findings` the alert
+/// stream stays idle.
 ///
 /// # UI Serving
 /// Serves Next.js static export from binary path or configured ui.path.
 /// Redirects / to /ui for convenience.
 pub async fn serve(
     config: &Arc<ArcSwap<StrIEMConfig>>,
-    detections: Arc<RwLock<SigmaCollection>>,
+    shared: Shared,
     sys: tokio::sync::broadcast::Sender<SysMessage>,
 ) -> Result<()> {
+    let Shared {
+        detections,
+        ocsf,
+        levels,
+        origins,
+        lists,
+        findings,
+        received,
+    } = shared;
     let config_container = config.clone();
     let config = config.load();
 
     let mut features: Vec<String> = Vec::new();
 
     // Create DB connection pool
-    let db = initdb(&config).inspect(|_| {
+    let db = initdb(&config)?.inspect(|_| {
         #[cfg(feature = "duckdb")]
         features.push("duckdb".to_string());
     });
 
+    #[cfg(any(feature = "duckdb", feature = "sqlite"))]
+    if let Some(db) = db.clone() {
+        striem_common::metrics::register_collector(move || {
+            use striem_common::metrics::set;
+            let Ok(pool) = db.pool() else {
+                return;
+            };
+            let state = pool.state();
+            let idle = state.idle_connections as f64;
+            let active = (state.connections - state.idle_connections) as f64;
+            set("striem_api_db_connections", &[("state", "idle")], idle);
+            set("striem_api_db_connections", &[("state", "active")], active);
+            set("striem_api_db_max_connections", &[], pool.max_size() as f64);
+        });
+    }
+
     if let Some(db) = db.as_ref() {
         let mut conn = db
             .get()
             .map_err(|e| anyhow::anyhow!("Failed to get DB connection: {}", e))?;
         let mut sources = SOURCES.write().await;
         sources.append(&mut persist::sources(&mut conn).unwrap_or_default());
-    };
+        let tokens = persist::source_tokens(&mut conn).unwrap_or_default();
+        // rotated tokens still in their grace period leave Vector's
+        // configuration when they expire
+        tokens
+            .iter()
+            .filter_map(|t| t.expires_at)
+            .for_each(crate::sources::tokens::reprovision_at);
+        SOURCE_TOKENS.write().await.extend(tokens);
+
+        let mut notifications = NOTIFICATIONS.write().await;
+        notifications.extend(
+            persist::notifications(&mut conn)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(id, config)| NotificationRule::new(id, config)),
+        );
+        PLAYBOOKS.write().await.extend(
+            persist::playbooks(&mut conn)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(id, config)| Playbook::new(id, config)),
+        );
+        let mut references = LISTS.write().await;
+        references.extend(
+            persist::lists(&mut conn)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|list| (list.name.clone(), list)),
+        );
+        lists.store(Arc::new(crate::lists::matchers(&references)));
 
-    let actions = if let Some(mcp_config) = &config.api.mcp {
-        match &mcp_config.url {
-            StringOrList::String(url) => Some(Arc::new(Mcp::new(url.clone()))),
-            StringOrList::List(urls) if !urls.is_empty() => {
-                Some(Arc::new(Mcp::new(urls[0].clone())))
+        // Re-apply enabled/disabled state and severity overrides set via the API
+        let rules = detections.read().await;
+        let mut overrides = HashMap::new();
+        for (id, enabled, level) in persist::rule_states(&mut conn).unwrap_or_default() {
+            if let Some(rule) = rules.get(&id) {
+                if enabled {
+                    rule.enable();
+                } else {
+                    rule.disable();
+                }
+            }
+            if let Some(level) = level.and_then(|l| l.parse::<Severity>().ok()) {
+                overrides.insert(id, level);
             }
-            _ => None,
         }
-    } else {
-        None
-    }
-    .inspect(|_| {
-        features.push("mcp".to_string());
-    });
+        levels.store(Arc::new(overrides));
+    };
+
+    let actions = match config.api.mcp.as_ref().filter(|mcp| !mcp.url.is_empty()) {
+        Some(mcp) => {
+            features.push("mcp".to_string());
+            Some(Arc::new(
+                Mcp::new(mcp.servers().map_err(|e| anyhow::anyhow!(e))?)
+                    .with_timeout(Duration::from_secs(mcp.timeout_secs))
+                    .with_retry(Duration::from_secs(mcp.retry_secs)),
+            ))
+        }
+        None => None,
+    };
 
     let ui = config
         .api
@@ -100,16 +217,86 @@ pub async fn serve(
         })
         .filter(|p| p.exists());
 
+    let stats = LiveStats {
+        events: received.as_ref().map(|_| Default::default()),
+        findings: findings.as_ref().map(|_| Default::default()),
+    };
+    for (counter, channel, subscriber) in [
+        (&stats.events, &received, "live_stats_events"),
+        (&stats.findings, &findings, "live_stats_findings"),
+    ] {
+        if let (Some(counter), Some(channel)) = (counter, channel) {
+            tokio::spawn(crate::stats::count(
+                counter.clone(),
+                channel.subscribe(),
+                sys.subscribe(),
+                subscriber,
+            ));
+        }
+    }
+
     let state = ApiState {
         detections,
+        ocsf,
+        levels,
+        origins,
+        lists,
+        coverage: Default::default(),
+        schema: Default::default(),
         actions,
         db,
         config: config_container,
         sys: sys.clone(),
+        events: findings.unwrap_or_else(|| tokio::sync::broadcast::channel(64).0),
+        stats: Arc::new(stats),
+        health: Arc::new(Health::new(received.is_some())),
+        tail: received.map(|received| Arc::new(Tail::new(&config.api.tail, received))),
         features: HeaderValue::from_str(&features.join(","))?,
     };
 
-    let mut app = create_router()
+    let coverage = state.coverage.clone();
+    let schema = state.schema.clone();
+
+    // Webhook delivery runs off its own subscription so it never slows detection
+    tokio::spawn(notifications::run(
+        state.events.subscribe(),
+        sys.subscribe(),
+    ));
+    tokio::spawn(playbooks::run(
+        state.clone(),
+        state.events.subscribe(),
+        sys.subscribe(),
+    ));
+    tokio::spawn(crate::provision::run(state.clone(), sys.subscribe()));
+    tokio::spawn(crate::rules::run(state.clone(), sys.subscribe()));
+    tokio::spawn(crate::cursor::reap(state.config.clone(), sys.subscribe()));
+    if let Some(db) = state.db.clone() {
+        tokio::spawn(crate::views::run(
+            db,
+            state.config.clone(),
+            schema.clone(),
+            sys.subscribe(),
+        ));
+    }
+    if let Some(tail) = state.tail.clone() {
+        tokio::spawn(crate::tail::run(
+            tail,
+            state.config.clone(),
+            sys.subscribe(),
+        ));
+    }
+
+    // inside the auth layer, so tokens are only issued to accepted callers
+    let mut app = create_router().route_layer(middleware::from_fn_with_state(
+        state.config.clone(),
+        crate::confirm::require_confirmation,
+    ));
+    if let Some(auth) = &config.api.auth {
+        let tokens = Arc::new(Tokens::new(auth.load()?).with_tenants(auth.tenants()?));
+        app = app.layer(middleware::from_fn_with_state(tokens, require_token));
+    }
+    // the UI is added below, outside the auth layer
+    let mut app = app
         .layer(CorsLayer::permissive())
         .layer(middleware::from_fn_with_state(
             state.clone(),
@@ -129,28 +316,76 @@ pub async fn serve(
             );
     }
 
-    let listener = tokio::net::TcpListener::bind(&config.api.host.address()).await?;
+    // outermost, so rejected and UI requests are logged too
+    let app = app.layer(middleware::from_fn(crate::trace::trace_request));
+
+    // certificates are loaded before binding so bad files fail startup
+    let tls = config
+        .api
+        .tls
+        .as_ref()
+        .map(crate::tls::server_config)
+        .transpose()?;
+    let address = config.api.host.try_address()?;
+    let listener = std::net::TcpListener::bind(address)?;
 
-    log::info!(
-        "API server listening on http://{}",
-        config.api.host.address()
+    tracing::info!(
+        "API server listening on {}://{}",
+        if tls.is_some() { "https" } else { "http" },
+        address
     );
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(async move {
-            let mut rx = sys.subscribe();
-            loop {
-                match rx.recv().await {
-                    Ok(SysMessage::Shutdown) => break,
-                    Ok(_) => continue,
-                    Err(_) => {
-                        error!("system broadcast channel closed unexpectedly");
-                        break;
-                    }
+    let mut rx = sys.subscribe();
+    serve_router(app, listener, tls, async move {
+        loop {
+            match rx.recv().await {
+                Ok(SysMessage::Shutdown) => break,
+                // Rules and schemas may have changed on disk; recompute on next request
+                Ok(SysMessage::Reload) => {
+                    coverage.store(None);
+                    schema.store(None);
+                }
+                Ok(_) => continue,
+                Err(_) => {
+                    error!("system broadcast channel closed unexpectedly");
+                    break;
                 }
             }
-            info!("API shutting down...");
-        })
-        .await?;
+        }
+        info!("API shutting down...");
+    })
+    .await
+}
+
+/// Serve `app` on `listener`, over TLS if `tls` is set, until `shutdown`
+/// completes; in-flight requests are then allowed to finish.
+pub(crate) async fn serve_router(
+    app: Router,
+    listener: std::net::TcpListener,
+    tls: Option<rustls::ServerConfig>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    listener.set_nonblocking(true)?;
+    // peer addresses scope per-client limits such as query cursors
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    match tls {
+        Some(tls) => {
+            let handle = axum_server::Handle::new();
+            let stop = handle.clone();
+            tokio::spawn(async move {
+                shutdown.await;
+                stop.graceful_shutdown(None);
+            });
+            axum_server::from_tcp_rustls(listener, RustlsConfig::from_config(Arc::new(tls)))
+                .handle(handle)
+                .serve(app)
+                .await?;
+        }
+        None => {
+            axum::serve(tokio::net::TcpListener::from_std(listener)?, app)
+                .with_graceful_shutdown(shutdown)
+                .await?;
+        }
+    }
     Ok(())
 }
@@ -94,6 +94,8 @@ impl<'de> Deserialize<'de> for AwsCloudtrailConfig {
 pub struct AwsCloudtrail {
     pub(super) id: String,
     pub(super) config: AwsCloudtrailConfig,
+    pub(super) custom_vrl: Option<String>,
+    pub(super) tenant: Option<String>,
 }
 
 impl Source for AwsCloudtrail {
@@ -136,4 +138,16 @@ impl Source for AwsCloudtrail {
         )]);
         Some((transforms, pre_id))
     }
+
+    fn custom_vrl(&self) -> Option<&str> {
+        self.custom_vrl.as_deref()
+    }
+
+    fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    fn secret_fields(&self) -> &[&'static str] {
+        &["auth.secret_access_key", "auth.session_token"]
+    }
 }
@@ -0,0 +1,201 @@
+//! HTTP client settings of polling sources: an egress proxy and the TLS
+//! trust for the API polled.
+//!
+//! Sources take them as `proxy: {url, username, password}` and
+//! `tls: {ca_file, verify}`, and serialize them as Vector's `proxy` and
+//! `tls` blocks, which is also how they're persisted. Both shapes read back,
+//! so persisted sources load as they were registered. Proxy credentials go
+//! into the proxy URL, the only way Vector takes them; the URLs are listed
+//! among a source's secret fields as `proxy.http` and `proxy.https`.
+
+use serde::{Deserialize, Serialize, ser::SerializeMap};
+use tracing::warn;
+use url::Url;
+
+/// Proxy all of a source's requests go through
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyConfig {
+    /// `http://` or `https://` URL of the proxy
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// Decode `%XX` escapes in a URL component
+fn percent_decode(s: &str) -> String {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        let escaped = (s.as_bytes()[i] == b'%')
+            .then(|| s.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            None => {
+                bytes.push(s.as_bytes()[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+impl ProxyConfig {
+    /// Parse `url`, which must be an `http://` or `https://` URL
+    fn parse(url: &str) -> Result<Url, String> {
+        let parsed = Url::parse(url).map_err(|e| format!("proxy.url '{}': {}", url, e))?;
+        if !matches!(parsed.scheme(), "http" | "https") || parsed.host().is_none() {
+            return Err(format!(
+                "proxy.url '{}' must be an http:// or https:// URL",
+                url
+            ));
+        }
+        Ok(parsed)
+    }
+
+    /// The proxy URL with the credentials in it, as Vector takes it
+    pub fn vector_url(&self) -> String {
+        let Ok(mut url) = Self::parse(&self.url) else {
+            return self.url.clone();
+        };
+        if let Some(username) = &self.username {
+            url.set_username(username).ok();
+            url.set_password(self.password.as_deref()).ok();
+        }
+        url.to_string()
+    }
+
+    /// Split a Vector proxy URL back into the URL and its credentials
+    fn from_vector_url(url: &str) -> Result<Self, String> {
+        let mut parsed = Self::parse(url)?;
+        let username = Some(percent_decode(parsed.username())).filter(|u| !u.is_empty());
+        let password = parsed.password().map(percent_decode);
+        parsed.set_username("").ok();
+        parsed.set_password(None).ok();
+        Ok(ProxyConfig {
+            url: parsed.to_string(),
+            username,
+            password,
+        })
+    }
+}
+
+impl Serialize for ProxyConfig {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let url = self.vector_url();
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("enabled", &true)?;
+        map.serialize_entry("http", &url)?;
+        map.serialize_entry("https", &url)?;
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for ProxyConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Shape {
+            Settings {
+                url: String,
+                username: Option<String>,
+                password: Option<String>,
+            },
+            /// As serialized for Vector, and persisted
+            Vector {
+                https: Option<String>,
+                http: Option<String>,
+            },
+        }
+
+        let proxy = match Shape::deserialize(deserializer)? {
+            Shape::Settings {
+                url,
+                username,
+                password,
+            } => {
+                if password.is_some() && username.is_none() {
+                    return Err(serde::de::Error::custom(
+                        "proxy.password needs proxy.username",
+                    ));
+                }
+                ProxyConfig::parse(&url).map_err(serde::de::Error::custom)?;
+                ProxyConfig {
+                    url,
+                    username,
+                    password,
+                }
+            }
+            Shape::Vector { https, http } => {
+                let url = https
+                    .or(http)
+                    .ok_or_else(|| serde::de::Error::custom("proxy.url is required"))?;
+                ProxyConfig::from_vector_url(&url).map_err(serde::de::Error::custom)?
+            }
+        };
+        Ok(proxy)
+    }
+}
+
+/// Trust for the API a source polls
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct TlsConfig {
+    /// PEM file of the CA certificates to trust, e.g. an internal CA
+    pub ca_file: Option<String>,
+    /// Verify the server's certificate and hostname; on by default
+    #[serde(alias = "verify_certificate")]
+    pub verify: Option<bool>,
+}
+
+impl TlsConfig {
+    /// Warn about a `ca_file` that isn't there; it may be mounted for
+    /// Vector only
+    pub fn check(&self) {
+        if let Some(ca_file) = &self.ca_file
+            && !std::path::Path::new(ca_file).is_file()
+        {
+            warn!(
+                "tls.ca_file {} not found; Vector must be able to read it",
+                ca_file
+            );
+        }
+        if self.verify == Some(false) {
+            warn!("tls.verify is off; the polled API's certificate isn't checked");
+        }
+    }
+}
+
+impl Serialize for TlsConfig {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        if let Some(ca_file) = &self.ca_file {
+            map.serialize_entry("ca_file", ca_file)?;
+        }
+        if let Some(verify) = self.verify {
+            map.serialize_entry("verify_certificate", &verify)?;
+            map.serialize_entry("verify_hostname", &verify)?;
+        }
+        map.end()
+    }
+}
+
+/// `url` with its password, if any, replaced for display
+pub(crate) fn redact_url(url: &str) -> Option<String> {
+    let mut url = Url::parse(url).ok()?;
+    url.password()?;
+    url.set_password(Some("redacted")).ok()?;
+    Some(url.to_string())
+}
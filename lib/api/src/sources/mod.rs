@@ -1,5 +1,8 @@
 mod aws_cloudtrail;
+pub(crate) mod client;
 mod okta;
+pub(crate) mod tokens;
+pub(crate) mod windows_events;
 use std::{collections::BTreeMap, fmt::Display};
 
 use axum::{Router, extract::State};
@@ -7,20 +10,24 @@ use erased_serde as es;
 use serde::{Deserialize, Serialize, ser::SerializeMap};
 
 use serde_json::{Value, json};
+use striem_common::metrics;
 use tokio::sync::RwLock;
+use utoipa::ToSchema;
 
 use std::sync::LazyLock;
 
-use crate::ApiState;
+use crate::{ApiState, error::ApiError};
+use tokens::{SOURCE_TOKENS, SourceToken};
 
 pub(crate) static SOURCES: LazyLock<RwLock<Vec<Box<dyn Source>>>> =
     LazyLock::new(|| RwLock::new(Vec::new()));
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum SourceType {
     AwsCloudtrail,
     Okta,
+    WindowsEvents,
 }
 
 impl Display for SourceType {
@@ -28,6 +35,7 @@ impl Display for SourceType {
         match self {
             SourceType::AwsCloudtrail => write!(f, "aws_cloudtrail"),
             SourceType::Okta => write!(f, "okta"),
+            SourceType::WindowsEvents => write!(f, "windows_events"),
         }
     }
 }
@@ -38,11 +46,19 @@ pub enum Decoding {
     #[default]
     Json,
 }
+
+#[derive(Serialize, Clone, Default)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Framing {
+    #[default]
+    NewlineDelimited,
+}
 #[derive(Serialize, Clone, Default)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum TransformType {
     #[default]
     Remap,
+    Filter,
 }
 
 #[derive(Serialize, Default)]
@@ -54,6 +70,8 @@ pub struct Transform {
     source: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    condition: Option<String>,
 }
 
 /// A data source in StrIEM is defines it's own Sigma taxonomy
@@ -62,9 +80,15 @@ pub struct Transform {
 ///
 /// It is serialized to a Vector configuration as
 /// source-{sourcetype}_{id} in the `sources` section,
-/// with transforms to insert the Sigma taxonomy (as a metadata field)
-/// and OCSF normalization as logsource-{sourcetype}_{id}
-/// and ocsf-{sourcetype}_{id}
+/// with transforms to insert the Sigma taxonomy and tenant (as metadata
+/// fields) and OCSF normalization as logsource-{sourcetype}_{id}
+/// and ocsf-{sourcetype}_{id}. A source's custom VRL runs before both,
+/// as custom-{sourcetype}_{id}. A source attached to the HEC listener has
+/// no Vector source; source-{sourcetype}_{id} is instead a filter
+/// transform on `source-hec`, whose condition is the hashes of its valid
+/// tokens (see [`tokens`]). Likewise a source attached to the shared HTTP
+/// listener is a filter transform on `source-http` passing the events
+/// posted to its path.
 pub trait Source: Send + Sync {
     fn id(&self) -> String;
 
@@ -87,28 +111,243 @@ pub trait Source: Send + Sync {
         None
     }
 
+    /// VRL run after the Sigma taxonomy is set, for the parts of it that
+    /// differ between the source's events, e.g. the service of a Windows
+    /// event by its channel
+    fn logsource_vrl(&self) -> Option<String> {
+        None
+    }
+
     /// Vector source configuration
     fn config(&self) -> &dyn es::Serialize;
 
     fn preprocess_transforms(&self) -> Option<(BTreeMap<String, Transform>, String)> {
         None
     }
+
+    /// VRL run on the source's events ahead of the Sigma taxonomy and OCSF
+    /// remap, e.g. to rename a field
+    fn custom_vrl(&self) -> Option<&str> {
+        None
+    }
+
+    /// Dotted paths of the secret fields in [`Source::config`]; with
+    /// `api.vector_interpolate` they're served as environment references
+    fn secret_fields(&self) -> &[&'static str] {
+        &[]
+    }
+
+    /// Business unit the source's events belong to; it's set as their
+    /// `tenant` metadata and stored in their `metadata.labels`
+    fn tenant(&self) -> Option<&str> {
+        None
+    }
+
+    /// Whether the source's events arrive on the shared HEC listener, told
+    /// apart by their token, rather than on a Vector source of its own
+    fn hec(&self) -> bool {
+        false
+    }
+
+    /// Path on the shared HTTP listener the source's events are posted to,
+    /// when they arrive there rather than on a Vector source of its own
+    fn http_path(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Environment variable holding a source's secret field,
+/// `STRIEM_SECRET_{source_id}_{field}` in upper case with other characters
+/// replaced by `_`
+pub(crate) fn secret_var(source: &dyn Source, field: &str) -> String {
+    format!("STRIEM_SECRET_{}_{}", source.id(), field)
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect()
+}
+
+/// `(variable, value)` of each secret field set on `source`
+pub(crate) fn secrets(source: &dyn Source) -> Vec<(String, String)> {
+    let Ok(config) = serde_json::to_value(source.config()) else {
+        return Vec::new();
+    };
+    source
+        .secret_fields()
+        .iter()
+        .filter_map(|field| {
+            let value = field
+                .split('.')
+                .try_fold(&config, |value, key| value.get(key))?
+                .as_str()?;
+            Some((secret_var(source, field), value.to_string()))
+        })
+        .collect()
+}
+
+/// Apply `f` to the secret fields set in `source`'s Vector source
+/// configuration `table`, with their dotted paths
+fn for_each_secret(
+    source: &dyn Source,
+    table: &mut serde_json::Map<String, Value>,
+    mut f: impl FnMut(&str, &mut Value),
+) {
+    for field in source.secret_fields() {
+        let mut path = field.split('.').collect::<Vec<_>>();
+        let Some(last) = path.pop() else {
+            continue;
+        };
+        let parent = path.into_iter().try_fold(&mut *table, |table, key| {
+            table.get_mut(key).and_then(|v| v.as_object_mut())
+        });
+        if let Some(value) = parent.and_then(|t| t.get_mut(last))
+            && value.is_string()
+        {
+            f(field, value);
+        }
+    }
 }
 
+/// Replace the secret fields set in `source`'s Vector source configuration
+/// `table` with `${VAR}` references
+pub(crate) fn reference_secrets(source: &dyn Source, table: &mut serde_json::Map<String, Value>) {
+    for_each_secret(source, table, |field, value| {
+        *value = Value::String(format!("${{{}}}", secret_var(source, field)));
+    });
+}
+
+/// Redact the secret fields set in `source`'s Vector source configuration
+/// `table`: a URL only loses its password, anything else is replaced whole
+pub(crate) fn redact_secrets(source: &dyn Source, table: &mut serde_json::Map<String, Value>) {
+    for_each_secret(source, table, |_, value| {
+        let url = value.as_str().filter(|v| v.contains("://"));
+        *value = match url {
+            Some(url) => client::redact_url(url).map_or_else(|| value.clone(), Value::String),
+            None => Value::String(crate::config::REDACTED.to_string()),
+        };
+    });
+}
+
+/// Check a custom VRL program is worth handing to Vector: not empty, with
+/// its brackets balanced and its string literals closed. Vector compiles it
+/// properly when it loads the configuration.
+pub(crate) fn check_vrl(program: &str) -> Result<(), String> {
+    if program.trim().is_empty() {
+        return Err("custom_vrl is empty".to_string());
+    }
+    let mut open = Vec::new();
+    let mut chars = program.chars();
+    let mut prev = ' ';
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => {
+                // comment to the end of the line
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            // "string", and s'raw', r'regex' and t'timestamp' literals
+            '"' | '\'' if c == '"' || matches!(prev, 's' | 'r' | 't') => {
+                let mut closed = false;
+                while let Some(s) = chars.next() {
+                    match s {
+                        '\\' => {
+                            chars.next();
+                        }
+                        s if s == c => {
+                            closed = true;
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                if !closed {
+                    return Err("custom_vrl has an unterminated string".to_string());
+                }
+            }
+            '(' | '[' | '{' => open.push(c),
+            ')' | ']' | '}' => {
+                let expected = match c {
+                    ')' => '(',
+                    ']' => '[',
+                    _ => '{',
+                };
+                if open.pop() != Some(expected) {
+                    return Err(format!("custom_vrl has an unmatched '{}'", c));
+                }
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    match open.pop() {
+        Some(c) => Err(format!("custom_vrl has an unclosed '{}'", c)),
+        None => Ok(()),
+    }
+}
+
+/// Check a tenant name: letters, digits, `-`, `_` and `.` only
+pub(crate) fn check_tenant(tenant: &str) -> Result<(), String> {
+    if tenant.is_empty()
+        || !tenant
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err(format!(
+            "tenant '{}' must be letters, digits, '-', '_' or '.'",
+            tenant
+        ));
+    }
+    Ok(())
+}
+
+/// `(sourcetype, id, config)`. The config may carry the source's
+/// `custom_vrl` and `tenant` next to its Vector source settings, as it's
+/// persisted.
 pub type ExistingSource = (String, String, serde_json::Value);
 
 impl TryInto<Box<dyn Source>> for ExistingSource {
     type Error = anyhow::Error;
     fn try_into(self) -> Result<Box<dyn Source>, Self::Error> {
-        let (sourcetype, id, config) = self;
+        let (sourcetype, id, mut config) = self;
+        let custom_vrl = match config.as_object_mut().and_then(|c| c.remove("custom_vrl")) {
+            None | Some(Value::Null) => None,
+            Some(Value::String(vrl)) => {
+                check_vrl(&vrl).map_err(|e| anyhow::anyhow!(e))?;
+                Some(vrl)
+            }
+            Some(_) => Err(anyhow::anyhow!("custom_vrl must be a string"))?,
+        };
+        let tenant = match config.as_object_mut().and_then(|c| c.remove("tenant")) {
+            None | Some(Value::Null) => None,
+            Some(Value::String(tenant)) => {
+                check_tenant(&tenant).map_err(|e| anyhow::anyhow!(e))?;
+                Some(tenant)
+            }
+            Some(_) => Err(anyhow::anyhow!("tenant must be a string"))?,
+        };
         match sourcetype.as_str() {
             "aws_cloudtrail" => Ok(Box::new(aws_cloudtrail::AwsCloudtrail {
                 id,
                 config: serde_json::from_value(config).map_err(|e| anyhow::anyhow!(e))?,
+                custom_vrl,
+                tenant,
             })),
             "okta" => Ok(Box::new(okta::Okta {
                 id,
                 config: serde_json::from_value(config).map_err(|e| anyhow::anyhow!(e))?,
+                custom_vrl,
+                tenant,
+            })),
+            "windows_events" => Ok(Box::new(windows_events::WindowsEvents {
+                id,
+                config: serde_json::from_value(config).map_err(|e| anyhow::anyhow!(e))?,
+                custom_vrl,
+                tenant,
             })),
             _ => Err(anyhow::anyhow!("Unsupported source type: {}", sourcetype))?,
         }
@@ -137,19 +376,64 @@ impl Serialize for dyn Source {
         }
 
         let sigma = format!("%sigma = {}", serde_json::json!({"logsource": logsource}));
+        let tenant = self
+            .tenant()
+            .map(|t| format!("%tenant = {}\n", json!(t)))
+            .unwrap_or_default();
+        let sigma = match self.logsource_vrl() {
+            Some(vrl) => format!("{}\n{}", sigma, vrl),
+            None => sigma,
+        };
 
-        let mut map = serializer.serialize_map(Some(2))?;
-
-        map.serialize_entry(
-            "sources",
-            &BTreeMap::from([(source_id.clone(), &self.config())]),
-        )?;
+        let mut map = serializer.serialize_map(None)?;
 
-        let (mut transforms, final_id) = match self.preprocess_transforms() {
+        let (mut transforms, mut final_id) = match self.preprocess_transforms() {
             Some((transforms, final_id)) => (transforms, final_id),
             None => (BTreeMap::new(), source_id.clone()),
         };
 
+        if self.hec() {
+            // the condition is filled in with the source's tokens when the
+            // Vector configuration is assembled
+            transforms.insert(
+                source_id.clone(),
+                Transform {
+                    _type: TransformType::Filter,
+                    inputs: vec!["source-hec".to_string()],
+                    ..Default::default()
+                },
+            );
+        } else if let Some(path) = self.http_path() {
+            transforms.insert(
+                source_id.clone(),
+                Transform {
+                    _type: TransformType::Filter,
+                    inputs: vec!["source-http".to_string()],
+                    condition: Some(format!("%http_server.path == {}", json!(path))),
+                    ..Default::default()
+                },
+            );
+        } else {
+            map.serialize_entry(
+                "sources",
+                &BTreeMap::from([(source_id.clone(), &self.config())]),
+            )?;
+        }
+
+        if let Some(vrl) = self.custom_vrl() {
+            let custom_id = format!("custom-{}_{}", self.sourcetype().to_string(), self.id());
+            transforms.insert(
+                custom_id.clone(),
+                Transform {
+                    inputs: vec![final_id],
+                    source: Some(vrl.to_string()),
+                    file: None,
+                    ..Default::default()
+                },
+            );
+            final_id = custom_id;
+        }
+
         // This workaround is until Vector supports environment variable interpolation
         // in HTTP provider configuration
         let remaps_dir = if let Ok(dir) = std::env::var("STRIEM_REMAPS") {
@@ -164,7 +448,10 @@ impl Serialize for dyn Source {
                 logsource_id.clone(),
                 Transform {
                     inputs: vec![final_id],
-                    source: Some(format!("%source_id = \"{}\"\n{}\n", source_id, sigma)),
+                    source: Some(format!(
+                        "%source_id = \"{}\"\n{}\n{}",
+                        source_id, sigma, tenant
+                    )),
                     file: None,
                     ..Default::default()
                 },
@@ -192,110 +479,220 @@ impl Serialize for dyn Source {
     }
 }
 
-async fn list_sources(State(_): State<ApiState>) -> axum::Json<Vec<serde_json::Value>> {
+#[utoipa::path(
+    get,
+    path = "/api/1/sources",
+    tag = "sources",
+    responses((status = 200, description = "Sources as `{id, sourcetype, name, tenant, limited}`, `limited` counting events held (`delayed`) or refused (`rejected`) by `input.vector.limits` since startup", body = [Object]))
+)]
+pub(crate) async fn list_sources(State(_): State<ApiState>) -> axum::Json<Vec<serde_json::Value>> {
     let sources = SOURCES.read().await;
 
     axum::Json(
         sources
             .iter()
             .map(|source| {
+                let source_id =
+                    format!("source-{}_{}", source.sourcetype().to_string(), source.id());
+                let limited = |action: &str| {
+                    metrics::value(
+                        "striem_vector_server_limited_events_total",
+                        &[("source_id", &source_id), ("action", action)],
+                    )
+                    .unwrap_or_default() as u64
+                };
                 serde_json::json!({
                     "id": source.id(),
                     "sourcetype": source.sourcetype(),
                     "name": source.name(),
+                    "tenant": source.tenant(),
+                    "limited": {
+                        "delayed": limited("delayed"),
+                        "rejected": limited("rejected"),
+                    },
                 })
             })
             .collect(),
     )
 }
 
-async fn get_source(
+/// A source with its Vector source and transform configuration, secrets
+/// redacted
+#[utoipa::path(
+    get,
+    path = "/api/1/sources/{id}",
+    tag = "sources",
+    params(("id" = String, Path, description = "Source id")),
+    responses(
+        (status = 200, description = "The source", body = Object),
+        (status = 404, description = "No such source", body = crate::error::ErrorBody),
+        (status = 500, description = "Source could not be serialized", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_source(
     State(_): State<ApiState>,
     axum::extract::Path(id): axum::extract::Path<String>,
-) -> Result<axum::Json<serde_json::Value>, (axum::http::StatusCode, String)> {
+) -> Result<axum::Json<serde_json::Value>, ApiError> {
     let sources = SOURCES.read().await;
 
     let source = sources
         .iter()
         .find(|source| source.id() == id)
-        .ok_or_else(|| {
-            (
-                axum::http::StatusCode::NOT_FOUND,
-                format!("Source with id {} not found", id),
-            )
-        })?;
+        .ok_or_else(|| ApiError::NotFound(format!("Source with id {} not found", id)))?;
 
-    let source_json = serde_json::to_value(source)
-        .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut source_json = serde_json::to_value(source).map_err(ApiError::internal)?;
+    let source_id = format!("source-{}_{}", source.sourcetype(), source.id());
+    if let Some(table) = source_json["sources"][&source_id].as_object_mut() {
+        redact_secrets(source.as_ref(), table);
+    }
 
     Ok(axum::Json(source_json))
 }
 
-async fn delete_source(
+#[utoipa::path(
+    delete,
+    path = "/api/1/sources/{id}",
+    tag = "sources",
+    params(
+        ("id" = String, Path, description = "Source id"),
+        ("X-Confirm-Token" = Option<String>, Header, description = "Token from the 202 response, with `api.require_confirmation`"),
+    ),
+    responses(
+        (status = 200, description = "The source was removed"),
+        (status = 202, description = "Confirmation required; repeat with `X-Confirm-Token`", body = crate::confirm::Confirmation),
+        (status = 404, description = "No such source", body = crate::error::ErrorBody),
+        (status = 500, description = "Source could not be removed", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn delete_source(
     State(state): State<ApiState>,
     axum::extract::Path(id): axum::extract::Path<String>,
-) -> Result<axum::Json<()>, (axum::http::StatusCode, String)> {
+) -> Result<axum::Json<()>, ApiError> {
     let mut sources = SOURCES.write().await;
 
     let index = sources
         .iter()
         .position(|source| source.id() == id)
-        .ok_or_else(|| {
-            (
-                axum::http::StatusCode::NOT_FOUND,
-                format!("Source with id {} not found", id),
-            )
-        })?;
+        .ok_or_else(|| ApiError::NotFound(format!("Source with id {} not found", id)))?;
 
     if let Some(db) = state.db.as_ref() {
-        let mut conn = db
-            .get()
-            .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-        crate::persist::remove_source(&mut conn, &id)
-            .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        let mut conn = db.get().map_err(ApiError::database)?;
+        crate::persist::remove_source(&mut conn, &id).map_err(ApiError::database)?;
+        crate::persist::remove_source_tokens(&mut conn, &id).map_err(ApiError::database)?;
     };
 
     sources.remove(index);
+    SOURCE_TOKENS.write().await.retain(|t| t.source_id != id);
+    crate::provision::changed();
 
     Ok(axum::Json(()))
 }
 
-async fn add_source(
+/// Add a source of the type in the path; the body is its configuration
+#[utoipa::path(
+    post,
+    path = "/api/1/sources/{id}",
+    tag = "sources",
+    params(("id" = SourceType, Path, description = "Type of the new source")),
+    request_body(content = Object, description = "Source configuration for the type, optionally with `custom_vrl` to run ahead of the OCSF remap and the `tenant` its events belong to"),
+    responses(
+        (status = 200, description = "`{id: sourcetype}` of the new source, and for a HEC source its `token`, which isn't shown again", body = Object),
+        (status = 400, description = "Invalid configuration for the type, or invalid `custom_vrl` or `tenant`", body = crate::error::ErrorBody),
+        (status = 500, description = "Source could not be saved", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn add_source(
     State(state): State<ApiState>,
     axum::extract::Path(sourcetype): axum::extract::Path<SourceType>,
     axum::extract::Json(config): axum::extract::Json<Value>,
-) -> Result<axum::Json<Value>, (axum::http::StatusCode, String)> {
+) -> Result<axum::Json<Value>, ApiError> {
     let id = uuid::Uuid::now_v7().to_string();
 
-    let source: Box<dyn Source> = match sourcetype {
-        SourceType::AwsCloudtrail => {
-            let cfg = serde_json::from_value(config)
-                .map_err(|e| (axum::http::StatusCode::BAD_REQUEST, e.to_string()))?;
-            Box::new(aws_cloudtrail::AwsCloudtrail { id, config: cfg })
-        }
-        SourceType::Okta => {
-            let cfg = serde_json::from_value(config)
-                .map_err(|e| (axum::http::StatusCode::BAD_REQUEST, e.to_string()))?;
-            Box::new(okta::Okta { id, config: cfg })
-        }
-    };
+    let source: Box<dyn Source> = (sourcetype.to_string(), id, config)
+        .try_into()
+        .map_err(|e: anyhow::Error| ApiError::BadRequest(e.to_string()))?;
 
     let sourcetype = source.sourcetype();
     let id = source.id();
+    let token = source.hec().then(|| SourceToken::generate(&id));
 
     if let Some(db) = state.db.as_ref() {
-        let mut conn = db
-            .get()
-            .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-        crate::persist::add_source(&mut conn, &source)
-            .map_err(|e| (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        let mut conn = db.get().map_err(ApiError::database)?;
+        crate::persist::add_source(&mut conn, &source).map_err(ApiError::database)?;
+        if let Some(token) = &token {
+            crate::persist::add_source_token(&mut conn, token).map_err(ApiError::database)?;
+        }
     };
 
     let mut sources = SOURCES.write().await;
 
     sources.push(source);
+    let mut response = json!({ id: sourcetype });
+    if let Some(token) = token {
+        response["token"] = Value::String(token.token.clone());
+        SOURCE_TOKENS.write().await.push(token);
+    }
+    crate::provision::changed();
+
+    Ok(axum::Json(response))
+}
+
+/// Give a HEC source a new token. Its current token stays valid for
+/// `api.hec_token_grace_secs`.
+#[utoipa::path(
+    post,
+    path = "/api/1/sources/{id}/rotate-token",
+    tag = "sources",
+    params(("id" = String, Path, description = "Source id")),
+    responses(
+        (status = 200, description = "`{id, token, previous_expires_at}`; the token isn't shown again", body = Object),
+        (status = 400, description = "The source doesn't receive events over HEC", body = crate::error::ErrorBody),
+        (status = 404, description = "No such source", body = crate::error::ErrorBody),
+        (status = 500, description = "Token could not be saved", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn rotate_token(
+    State(state): State<ApiState>,
+    axum::extract::Path(id): axum::extract::Path<String>,
+) -> Result<axum::Json<Value>, ApiError> {
+    let sources = SOURCES.read().await;
+    let source = sources
+        .iter()
+        .find(|source| source.id() == id)
+        .ok_or_else(|| ApiError::NotFound(format!("Source with id {} not found", id)))?;
+    if !source.hec() {
+        return Err(ApiError::BadRequest(format!(
+            "Source {} doesn't receive events over HEC",
+            id
+        )));
+    }
+
+    let now = chrono::Utc::now();
+    let grace = chrono::Duration::seconds(state.config.load().api.hec_token_grace_secs as i64);
+    let stored = SourceToken::generate(&id);
+    let token = stored.token.clone();
+
+    let mut tokens = SOURCE_TOKENS.write().await;
+    if let Some(db) = state.db.as_ref() {
+        let mut conn = db.get().map_err(ApiError::database)?;
+        crate::persist::expire_source_tokens(&mut conn, &id, now + grace)
+            .map_err(ApiError::database)?;
+        crate::persist::add_source_token(&mut conn, &stored).map_err(ApiError::database)?;
+    }
+    tokens.retain(|t| t.valid_at(now));
+    let expires = tokens::expire(&mut tokens, &id, now, grace);
+    tokens.push(stored);
+
+    crate::provision::changed();
+    if grace > chrono::Duration::zero() {
+        tokens::reprovision_at(expires);
+    }
 
-    Ok(axum::Json(json!({ id: sourcetype })))
+    Ok(axum::Json(json!({
+        "id": id,
+        "token": token,
+        "previous_expires_at": expires,
+    })))
 }
 
 pub fn create_router() -> axum::Router<ApiState> {
@@ -307,4 +704,5 @@ pub fn create_router() -> axum::Router<ApiState> {
                 .delete(delete_source)
                 .post(add_source),
         )
+        .route("/{id}/rotate-token", axum::routing::post(rotate_token))
 }
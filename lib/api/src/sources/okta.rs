@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 
-use super::{Source, SourceType};
+use super::{
+    Source, SourceType,
+    client::{ProxyConfig, TlsConfig},
+};
 
 #[derive(Debug, Clone, Serialize)]
 pub struct OktaConfig {
@@ -11,6 +14,12 @@ pub struct OktaConfig {
     pub scrape_interval_secs: Option<u64>,
     pub scrape_timeout_secs: Option<u64>,
     pub since: Option<u64>,
+    /// Proxy the Okta API is reached through
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyConfig>,
+    /// Trust for the Okta API, e.g. an internal CA of an inspecting proxy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
 }
 
 impl<'de> Deserialize<'de> for OktaConfig {
@@ -25,9 +34,14 @@ impl<'de> Deserialize<'de> for OktaConfig {
             pub scrape_interval_secs: Option<u64>,
             pub scrape_timeout_secs: Option<u64>,
             pub since: Option<u64>,
+            pub proxy: Option<ProxyConfig>,
+            pub tls: Option<TlsConfig>,
         }
 
         let helper = OktaConfigHelper::deserialize(deserializer)?;
+        if let Some(tls) = &helper.tls {
+            tls.check();
+        }
         Ok(OktaConfig {
             _type: "okta".into(),
             domain: helper.domain,
@@ -35,6 +49,8 @@ impl<'de> Deserialize<'de> for OktaConfig {
             scrape_interval_secs: helper.scrape_interval_secs,
             scrape_timeout_secs: helper.scrape_timeout_secs,
             since: helper.since,
+            proxy: helper.proxy,
+            tls: helper.tls,
         })
     }
 }
@@ -42,6 +58,8 @@ impl<'de> Deserialize<'de> for OktaConfig {
 pub struct Okta {
     pub(super) id: String,
     pub(super) config: OktaConfig,
+    pub(super) custom_vrl: Option<String>,
+    pub(super) tenant: Option<String>,
 }
 
 impl Source for Okta {
@@ -68,4 +86,16 @@ impl Source for Okta {
     fn logsource_product(&self) -> Option<String> {
         Some("audit".to_string())
     }
+
+    fn custom_vrl(&self) -> Option<&str> {
+        self.custom_vrl.as_deref()
+    }
+
+    fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    fn secret_fields(&self) -> &[&'static str] {
+        &["token", "proxy.http", "proxy.https"]
+    }
 }
@@ -0,0 +1,147 @@
+//! HEC tokens of the sources whose events arrive on the shared HEC
+//! listener (`output.vector.hec`).
+//!
+//! Each such source gets a token when it's added, and a new one from
+//! `POST /api/1/sources/{id}/rotate-token`; either way the API returns the
+//! token once and never shows it again. Every valid token is `source-hec`'s
+//! `valid_tokens`, so the listener refuses any other token with a 401, and
+//! like source secrets they're `${VAR}` references to `/vector/env` with
+//! `api.vector_interpolate`. The source's filter transform then passes the
+//! HEC events whose token hashes to one of the source's own. A rotated token
+//! stays valid for `api.hec_token_grace_secs`, so senders can be moved over
+//! one at a time.
+
+use std::sync::LazyLock;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+
+pub(crate) static SOURCE_TOKENS: LazyLock<RwLock<Vec<SourceToken>>> =
+    LazyLock::new(|| RwLock::new(Vec::new()));
+
+/// A source's token
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct SourceToken {
+    pub source_id: String,
+    /// The token itself, only ever given to Vector
+    #[serde(skip)]
+    pub token: String,
+    /// Hex SHA-256 of the token
+    pub hash: String,
+    pub created_at: DateTime<Utc>,
+    /// When the token stops being valid; `None` for the current token
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl SourceToken {
+    /// A new token for `source_id`
+    pub(crate) fn generate(source_id: &str) -> Self {
+        let token = uuid::Uuid::new_v4().to_string();
+        SourceToken {
+            source_id: source_id.to_string(),
+            hash: hash(&token),
+            token,
+            created_at: Utc::now(),
+            expires_at: None,
+        }
+    }
+
+    /// Environment variable holding the token for Vector,
+    /// `STRIEM_HEC_TOKEN_` and the start of its hash in upper case
+    pub(crate) fn var(&self) -> String {
+        format!(
+            "STRIEM_HEC_TOKEN_{}",
+            self.hash.get(..16).unwrap_or(&self.hash)
+        )
+        .to_ascii_uppercase()
+    }
+
+    pub(crate) fn valid_at(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_none_or(|expires| expires > now)
+    }
+}
+
+/// Hex SHA-256 of `token`, as VRL's `sha2(token, variant: "SHA-256")`
+/// gives it
+pub(crate) fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Have the current tokens of `source_id` expire after `grace`, returning
+/// when they do
+pub(crate) fn expire(
+    tokens: &mut [SourceToken],
+    source_id: &str,
+    now: DateTime<Utc>,
+    grace: Duration,
+) -> DateTime<Utc> {
+    let expires = now + grace;
+    tokens
+        .iter_mut()
+        .filter(|t| t.source_id == source_id && t.expires_at.is_none())
+        .for_each(|t| t.expires_at = Some(expires));
+    expires
+}
+
+/// Hashes of the tokens `source_id` accepts now
+pub(crate) async fn valid_hashes(source_id: &str) -> Vec<String> {
+    let now = Utc::now();
+    SOURCE_TOKENS
+        .read()
+        .await
+        .iter()
+        .filter(|t| t.source_id == source_id && t.valid_at(now))
+        .map(|t| t.hash.clone())
+        .collect()
+}
+
+/// `source-hec`'s `valid_tokens`: every source's tokens that are valid now,
+/// as `${VAR}` references with `interpolate`
+pub(crate) async fn valid_tokens(interpolate: bool) -> Vec<String> {
+    let now = Utc::now();
+    SOURCE_TOKENS
+        .read()
+        .await
+        .iter()
+        .filter(|t| t.valid_at(now))
+        .map(|t| match interpolate {
+            true => format!("${{{}}}", t.var()),
+            false => t.token.clone(),
+        })
+        .collect()
+}
+
+/// `(variable, token)` of every token that's valid now, for `/vector/env`
+pub(crate) async fn token_vars() -> Vec<(String, String)> {
+    let now = Utc::now();
+    SOURCE_TOKENS
+        .read()
+        .await
+        .iter()
+        .filter(|t| t.valid_at(now))
+        .map(|t| (t.var(), t.token.clone()))
+        .collect()
+}
+
+/// VRL condition passing the HEC events sent with a token hashing to one of
+/// `hashes`
+pub(crate) fn condition(hashes: &[String]) -> String {
+    format!(
+        r#"includes({}, sha2(string(get_secret("splunk_hec_token")) ?? "", variant: "SHA-256"))"#,
+        serde_json::to_string(hashes).unwrap_or_else(|_| "[]".to_string())
+    )
+}
+
+/// Provision Vector again once a rotated token expires, so it's dropped
+/// from the configuration
+pub(crate) fn reprovision_at(at: DateTime<Utc>) {
+    let Ok(wait) = (at - Utc::now()).to_std() else {
+        return;
+    };
+    tokio::spawn(async move {
+        tokio::time::sleep(wait).await;
+        crate::provision::changed();
+    });
+}
@@ -0,0 +1,186 @@
+use serde::{Deserialize, Serialize};
+
+use erased_serde as es;
+use std::{collections::BTreeMap, net::SocketAddr};
+
+use super::{Decoding, Framing, Source, SourceType, Transform};
+
+/// Sigma services of the Windows event log channels SigmaHQ rules are
+/// written for, by lower-cased channel
+pub(crate) const CHANNEL_SERVICES: &[(&str, &str)] = &[
+    ("security", "security"),
+    ("system", "system"),
+    ("application", "application"),
+    ("microsoft-windows-sysmon/operational", "sysmon"),
+    ("microsoft-windows-powershell/operational", "powershell"),
+    ("windows powershell", "powershell-classic"),
+    (
+        "microsoft-windows-windows defender/operational",
+        "windefend",
+    ),
+    (
+        "microsoft-windows-taskscheduler/operational",
+        "taskscheduler",
+    ),
+];
+
+/// Splits a batch posted to the HTTP listener into its events, and flattens
+/// winlogbeat's envelope into the field names Sigma's Windows rules use:
+/// the `winlog.event_data` fields, `EventID`, `Channel`, `Computer` and so
+/// on. NXLog's JSON is already flat and passes through.
+const NORMALIZE_VRL: &str = r#"events = if is_array(.) {
+  .
+} else if is_string(.) {
+  map_values(compact(split(string!(.), "\n"))) -> |line| {
+    parse_json(strip_whitespace(string!(line))) ?? line
+  }
+} else {
+  [.]
+}
+. = map_values(array!(events)) -> |event| {
+  if is_object(event.winlog) {
+    normalized = merge(object(event.winlog.event_data) ?? {}, object(event.winlog.user_data) ?? {})
+    normalized.EventID = to_int(event.winlog.event_id) ?? event.winlog.event_id
+    normalized.Channel = event.winlog.channel
+    normalized.Computer = event.winlog.computer_name
+    normalized.Provider_Name = event.winlog.provider_name
+    normalized.EventRecordID = event.winlog.record_id
+    normalized.TimeCreated = event."@timestamp"
+    normalized.original = event.event.original
+    compact(normalized, string: false, object: false, array: false)
+  } else {
+    event
+  }
+}"#;
+
+#[derive(Serialize, Default)]
+struct Socket {
+    #[serde(rename = "type")]
+    _type: String,
+    mode: String,
+    decoding: Decoding,
+    framing: Framing,
+}
+
+/// Windows event logs shipped as JSON by winlogbeat or NXLog, posted to
+/// StrIEM's HTTP listener at `/windows/{id}`, or sent one event per line to
+/// a TCP listener of the source's own at `address`
+#[derive(Serialize, Default)]
+pub struct WindowsEventsConfig {
+    /// `ip:port` to listen for TCP connections on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(flatten)]
+    socket: Option<Socket>,
+}
+
+impl<'de> Deserialize<'de> for WindowsEventsConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        #[derive(Deserialize, Default)]
+        struct WindowsEventsConfigHelper {
+            pub address: Option<String>,
+        }
+
+        let helper = WindowsEventsConfigHelper::deserialize(deserializer)?;
+        if let Some(address) = &helper.address
+            && address.parse::<SocketAddr>().is_err()
+        {
+            return Err(serde::de::Error::custom(format!(
+                "address '{}' must be ip:port",
+                address
+            )));
+        }
+        Ok(WindowsEventsConfig {
+            socket: helper.address.as_ref().map(|_| Socket {
+                _type: "socket".to_string(),
+                mode: "tcp".to_string(),
+                ..Default::default()
+            }),
+            address: helper.address,
+        })
+    }
+}
+
+pub struct WindowsEvents {
+    pub(super) id: String,
+    pub(super) config: WindowsEventsConfig,
+    pub(super) custom_vrl: Option<String>,
+    pub(super) tenant: Option<String>,
+}
+
+impl Source for WindowsEvents {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    fn name(&self) -> String {
+        match (&self.config.address, self.http_path()) {
+            (Some(address), _) => format!("tcp://{}", address),
+            (None, Some(path)) => path,
+            (None, None) => self.sourcetype().to_string(),
+        }
+    }
+
+    fn sourcetype(&self) -> SourceType {
+        SourceType::WindowsEvents
+    }
+
+    fn config(&self) -> &dyn es::Serialize {
+        &self.config
+    }
+
+    fn logsource_product(&self) -> Option<String> {
+        Some("windows".to_string())
+    }
+
+    fn logsource_vrl(&self) -> Option<String> {
+        let branches = CHANNEL_SERVICES
+            .iter()
+            .map(|(channel, service)| {
+                format!(
+                    "if channel == {:?} {{\n  %sigma.logsource.service = {:?}\n}}",
+                    channel, service
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" else ");
+        Some(format!(
+            "channel = downcase(string(.Channel) ?? \"\")\n{}",
+            branches
+        ))
+    }
+
+    fn preprocess_transforms(&self) -> Option<(BTreeMap<String, Transform>, String)> {
+        let source_id = format!("source-{}_{}", self.sourcetype().to_string(), self.id());
+        let pre_id = format!("pre-{}_{}", self.sourcetype().to_string(), self.id());
+
+        let transforms = BTreeMap::from([(
+            pre_id.clone(),
+            Transform {
+                inputs: vec![source_id.clone()],
+                source: Some(NORMALIZE_VRL.to_string()),
+                file: None,
+                ..Default::default()
+            },
+        )]);
+        Some((transforms, pre_id))
+    }
+
+    fn custom_vrl(&self) -> Option<&str> {
+        self.custom_vrl.as_deref()
+    }
+
+    fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    fn http_path(&self) -> Option<String> {
+        self.config
+            .address
+            .is_none()
+            .then(|| format!("/windows/{}", self.id))
+    }
+}
@@ -0,0 +1,135 @@
+//! Live pipeline rates, so the dashboard shows activity before the first
+//! Parquet files rotate.
+//!
+//! - GET /api/1/stats/live - Events received and findings generated per
+//!   second, averaged over the last 1, 5 and 15 minutes
+//!
+//! Both are counted in process, off the Vector server's event channel and
+//! the detection engine's findings channel. A channel this process doesn't
+//! have, as with the standalone API, is reported as unavailable. Batches
+//! missed by a lagging counter aren't counted.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use axum::{Json, Router, extract::State, routing::get};
+use chrono::Utc;
+use serde_json::{Map, Value, json};
+use striem_common::{SysMessage, event::Event, metrics};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::ApiState;
+
+/// Rate windows reported, by name, in seconds
+const WINDOWS: [(&str, u64); 3] = [("1m", 60), ("5m", 300), ("15m", 900)];
+
+/// Counts per second over the longest window
+#[derive(Default)]
+pub(crate) struct Counter {
+    seconds: Mutex<VecDeque<(i64, u64)>>,
+}
+
+impl Counter {
+    /// Count `n` at `now`, in seconds since the epoch
+    pub(crate) fn add(&self, now: i64, n: u64) {
+        let mut seconds = self.seconds.lock().unwrap_or_else(|e| e.into_inner());
+        match seconds.back_mut() {
+            Some((second, count)) if *second == now => *count += n,
+            _ => seconds.push_back((now, n)),
+        }
+        let longest = WINDOWS[WINDOWS.len() - 1].1 as i64;
+        while seconds
+            .front()
+            .is_some_and(|(second, _)| now - second >= longest)
+        {
+            seconds.pop_front();
+        }
+    }
+
+    /// `{"1m", "5m", "15m"}` per second rates as of `now`
+    pub(crate) fn rates(&self, now: i64) -> Map<String, Value> {
+        let seconds = self.seconds.lock().unwrap_or_else(|e| e.into_inner());
+        WINDOWS
+            .iter()
+            .map(|(name, window)| {
+                let count = seconds
+                    .iter()
+                    .filter(|(second, _)| now - second < *window as i64)
+                    .map(|(_, count)| count)
+                    .sum::<u64>();
+                (name.to_string(), json!(count as f64 / *window as f64))
+            })
+            .collect()
+    }
+}
+
+/// Counters of the channels this process has
+#[derive(Default)]
+pub(crate) struct LiveStats {
+    pub events: Option<Arc<Counter>>,
+    pub findings: Option<Arc<Counter>>,
+}
+
+impl LiveStats {
+    /// `{events, findings}`, each `{available, 1m, 5m, 15m}`
+    pub(crate) fn report(&self, now: i64) -> Value {
+        let report = |counter: &Option<Arc<Counter>>| match counter {
+            Some(counter) => {
+                let mut rates = counter.rates(now);
+                rates.insert("available".to_string(), json!(true));
+                Value::Object(rates)
+            }
+            None => json!({"available": false}),
+        };
+        json!({
+            "events": report(&self.events),
+            "findings": report(&self.findings),
+        })
+    }
+}
+
+/// Count the events of each batch on `rx` until shutdown
+pub(crate) async fn count(
+    counter: Arc<Counter>,
+    mut rx: broadcast::Receiver<Arc<Vec<Event>>>,
+    mut sys: broadcast::Receiver<SysMessage>,
+    subscriber: &'static str,
+) {
+    loop {
+        tokio::select! {
+            received = rx.recv() => match received {
+                Ok(events) => counter.add(Utc::now().timestamp(), events.len() as u64),
+                Err(RecvError::Lagged(n)) => {
+                    metrics::increment(
+                        "striem_broadcast_lagged_total",
+                        &[("subscriber", subscriber)],
+                        n,
+                    );
+                }
+                Err(RecvError::Closed) => return,
+            },
+            message = sys.recv() => match message {
+                Ok(SysMessage::Shutdown) | Err(RecvError::Closed) => return,
+                _ => {}
+            },
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/1/stats/live",
+    tag = "system",
+    responses(
+        (status = 200, description = "`events` received and `findings` generated per second over `1m`, `5m` and `15m`; each is `{available: false}` when this process doesn't have it", body = Object),
+    )
+)]
+pub(crate) async fn get_live(State(state): State<ApiState>) -> Json<Value> {
+    Json(state.stats.report(Utc::now().timestamp()))
+}
+
+pub fn create_router() -> Router<ApiState> {
+    Router::new().route("/live", get(get_live))
+}
@@ -0,0 +1,238 @@
+//! Storage pipeline diagnostics.
+//!
+//! - GET /api/1/storage/convert_errors - Conversion problems since startup,
+//!   grouped by class, field and error kind, most frequent first
+//! - GET /api/1/storage/schemas - Classes with a schema, their field counts
+//!   and schema files, the OCSF version StrIEM was built for, and the
+//!   classes without a schema; `?class=` gives one class's field tree
+//! - GET /api/1/storage/files?class=&date= - A class's files with the write
+//!   statistics in their footers, optionally only those of one day
+//! - /api/1/storage/compact - Compaction jobs, see [`crate::compaction`]
+
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    routing::get,
+};
+use chrono::NaiveDate;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use striem_storage::{
+    manifest, reader,
+    schema::{ClassSchema, Column},
+    stats,
+};
+use tracing::warn;
+use utoipa::IntoParams;
+
+use crate::{ApiState, error::ApiError};
+
+#[utoipa::path(
+    get,
+    path = "/api/1/storage/convert_errors",
+    tag = "storage",
+    responses(
+        (status = 200, description = "Conversion problems by class, field and kind", body = [Object]),
+    )
+)]
+pub(crate) async fn convert_errors(State(_): State<ApiState>) -> Json<Value> {
+    Json(Value::Array(
+        striem_storage::convert_errors()
+            .into_iter()
+            .map(|e| {
+                json!({
+                    "class": e.class,
+                    "field": e.field,
+                    "kind": e.kind,
+                    "count": e.count,
+                    "last_message": e.last_message,
+                })
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub(crate) struct SchemaParams {
+    /// Class name, e.g. `authentication`, to get the fields of
+    class: Option<String>,
+}
+
+/// Fields nested under their parents, from columns named with dot notation
+/// and listed parents first
+pub(crate) fn field_tree(columns: &[Column]) -> Vec<Value> {
+    fn insert(fields: &mut Vec<Value>, path: &[&str], data_type: &str) {
+        let Some((name, rest)) = path.split_first() else {
+            return;
+        };
+        let position = fields.iter().position(|f| f["name"] == *name);
+        let field = match position {
+            Some(i) => &mut fields[i],
+            None => {
+                fields.push(json!({"name": name, "type": data_type}));
+                fields.last_mut().unwrap()
+            }
+        };
+        if !rest.is_empty()
+            && let Some(field) = field.as_object_mut()
+        {
+            let children = field
+                .entry("fields")
+                .or_insert_with(|| Value::Array(Vec::new()));
+            if let Some(children) = children.as_array_mut() {
+                insert(children, rest, data_type);
+            }
+        }
+    }
+
+    let mut fields = Vec::new();
+    for column in columns {
+        insert(
+            &mut fields,
+            &column.name.split('.').collect::<Vec<_>>(),
+            &column.data_type,
+        );
+    }
+    fields
+}
+
+fn summary(class: &ClassSchema) -> Map<String, Value> {
+    Map::from_iter([
+        ("class".to_string(), json!(class.class)),
+        ("class_uid".to_string(), json!(class.class_uid)),
+        ("category".to_string(), json!(class.category)),
+        ("fields".to_string(), json!(class.columns.len())),
+        ("schema_file".to_string(), json!(class.path)),
+    ])
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/1/storage/schemas",
+    tag = "storage",
+    params(SchemaParams),
+    responses(
+        (status = 200, description = "Loaded classes, or one class's fields", body = Object),
+        (status = 404, description = "Storage is not configured, or no schema for the class", body = crate::error::ErrorBody),
+        (status = 500, description = "Schemas could not be read", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_schemas(
+    State(state): State<ApiState>,
+    Query(params): Query<SchemaParams>,
+) -> Result<Json<Value>, ApiError> {
+    let Some(schemapath) = state
+        .config
+        .load()
+        .storage
+        .as_ref()
+        .map(|s| s.schema.clone())
+    else {
+        return Err(ApiError::NotFound("storage is not configured".to_string()));
+    };
+    let classes =
+        tokio::task::spawn_blocking(move || striem_storage::schema::load_schemas(&schemapath))
+            .await
+            .map_err(ApiError::internal)?
+            .map_err(ApiError::internal)?;
+
+    if let Some(name) = params.class {
+        let class = classes
+            .iter()
+            .find(|c| c.class == name)
+            .ok_or_else(|| ApiError::NotFound(format!("No schema for class {}", name)))?;
+        let mut detail = summary(class);
+        detail.insert(
+            "ocsf_version".to_string(),
+            json!(striem_storage::OCSF_VERSION),
+        );
+        detail.insert("fields".to_string(), json!(field_tree(&class.columns)));
+        return Ok(Json(Value::Object(detail)));
+    }
+
+    let mut summaries = classes.iter().map(summary).collect::<Vec<_>>();
+    summaries.sort_by(|a, b| a["class"].as_str().cmp(&b["class"].as_str()));
+    Ok(Json(json!({
+        "ocsf_version": striem_storage::OCSF_VERSION,
+        "classes": summaries,
+        "missing": striem_storage::schema::missing_classes(&classes),
+    })))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub(crate) struct FilesParams {
+    /// OCSF class name, e.g. `authentication`
+    class: String,
+    /// Only files holding events of this day (UTC), `YYYY-MM-DD`
+    date: Option<NaiveDate>,
+}
+
+/// A class's Parquet files, oldest first, with their size, row count and
+/// the write statistics in their footers: `events`, `mismatched_events`
+/// (stored with a mistyped value as null) and `min_time`/`max_time` in
+/// epoch milliseconds. `stats` is null for files written without them.
+#[utoipa::path(
+    get,
+    path = "/api/1/storage/files",
+    tag = "storage",
+    params(FilesParams),
+    responses(
+        (status = 200, description = "The class's files and their statistics", body = [Object]),
+        (status = 400, description = "Unknown class, or object storage", body = crate::error::ErrorBody),
+        (status = 503, description = "Storage is not configured", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_files(
+    State(state): State<ApiState>,
+    Query(params): Query<FilesParams>,
+) -> Result<Json<Value>, ApiError> {
+    let Some(storage) = state.config.load().storage.clone() else {
+        return Err(ApiError::Unavailable(
+            "storage is not configured".to_string(),
+        ));
+    };
+    let dir = reader::class_dir(&storage, &params.class)
+        .map_err(|e| ApiError::BadRequest(e.to_string()))?;
+
+    let files = tokio::task::spawn_blocking(move || {
+        if !dir.exists() {
+            return Vec::new();
+        }
+        let files = match params.date {
+            Some(date) => {
+                let start = date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+                let end = start + chrono::Duration::days(1) - chrono::Duration::milliseconds(1);
+                manifest::files_for_range(&dir, start, end).unwrap_or_default()
+            }
+            None => {
+                let mut files = Vec::new();
+                striem_storage::parquet_files(&dir, &mut files)
+                    .inspect_err(|e| warn!("failed to list {}: {}", dir.display(), e))
+                    .ok();
+                files
+            }
+        };
+        let mut files = files
+            .iter()
+            .filter_map(|path| {
+                stats::read(&dir, path)
+                    .inspect_err(|e| warn!("unreadable footer in {}: {}", path.display(), e))
+                    .ok()
+            })
+            .collect::<Vec<_>>();
+        files.sort_by(|a, b| a.file.cmp(&b.file));
+        files
+    })
+    .await
+    .map_err(ApiError::internal)?;
+
+    Ok(Json(json!(files)))
+}
+
+pub fn create_router() -> Router<ApiState> {
+    Router::new()
+        .route("/convert_errors", get(convert_errors))
+        .route("/schemas", get(get_schemas))
+        .route("/files", get(get_files))
+        .nest("/compact", crate::compaction::create_router())
+}
@@ -0,0 +1,131 @@
+//! System events for live status in the UI.
+//!
+//! - GET /api/1/events - Server-sent events: `update` with the changed
+//!   configuration sections (redacted), `reload`, `shutdown`, and a
+//!   `heartbeat` every [`HEARTBEAT`] with quick health stats
+//!
+//! The stream ends after `shutdown`. As with the alert stream, clients that
+//! fall behind the system channel are disconnected rather than holding it up.
+
+use std::time::Duration;
+
+use axum::{
+    Router,
+    extract::State,
+    response::sse::{Event as SseEvent, KeepAlive, Sse},
+    routing::get,
+};
+use chrono::{DateTime, Utc};
+use futures_util::Stream;
+use serde::Serialize;
+use serde_json::{Value, json};
+use striem_common::{SysMessage, metrics};
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    time::{Interval, MissedTickBehavior},
+};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{ApiState, config::redact};
+
+/// Interval between `heartbeat` events
+pub(crate) const HEARTBEAT: Duration = Duration::from_secs(10);
+
+/// Quick health stats sent with each `heartbeat`
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct Heartbeat {
+    /// Loaded Sigma rules
+    pub rules: usize,
+    /// When the last event was received, if any since startup
+    #[schema(value_type = Option<String>)]
+    pub last_event: Option<DateTime<Utc>>,
+    /// Free bytes on the volume holding Parquet storage, when it's local
+    pub storage_free_bytes: Option<u64>,
+}
+
+pub(crate) async fn heartbeat(state: &ApiState) -> Heartbeat {
+    let rules = state.detections.read().await.len();
+    let last_event = metrics::value("striem_last_event_timestamp_seconds", &[])
+        .and_then(|t| DateTime::from_timestamp_millis((t * 1000.0) as i64));
+    let storage_free_bytes = state
+        .config
+        .load()
+        .storage
+        .as_ref()
+        .filter(|s| s.uri.is_none())
+        .and_then(|s| fs4::available_space(&s.path).ok());
+    Heartbeat {
+        rules,
+        last_event,
+        storage_free_bytes,
+    }
+}
+
+/// The event for a system message
+fn system_event(message: SysMessage) -> Result<SseEvent, axum::Error> {
+    match message {
+        SysMessage::Update(update) => {
+            let mut update = Value::Object(*update);
+            redact(&mut update);
+            SseEvent::default().event("update").json_data(update)
+        }
+        SysMessage::Reload => SseEvent::default().event("reload").json_data(json!({})),
+        SysMessage::Shutdown => SseEvent::default().event("shutdown").json_data(json!({})),
+    }
+}
+
+type StreamState = (broadcast::Receiver<SysMessage>, Interval, ApiState);
+
+/// Stream system events and heartbeats as server-sent events
+#[utoipa::path(
+    get,
+    path = "/api/1/events",
+    tag = "system",
+    responses(
+        (status = 200, description = "`update`, `reload`, `shutdown` and `heartbeat` events", body = Heartbeat, content_type = "text/event-stream"),
+    )
+)]
+pub(crate) async fn stream_events(
+    State(state): State<ApiState>,
+) -> Sse<impl Stream<Item = Result<SseEvent, axum::Error>>> {
+    let rx = state.sys.subscribe();
+    let mut interval = tokio::time::interval(HEARTBEAT);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let events = futures_util::stream::unfold(
+        Some((rx, interval, state)),
+        |current: Option<StreamState>| async move {
+            let (mut rx, mut interval, state) = current?;
+            tokio::select! {
+                message = rx.recv() => match message {
+                    Ok(SysMessage::Shutdown) => Some((system_event(SysMessage::Shutdown), None)),
+                    Ok(message) => Some((system_event(message), Some((rx, interval, state)))),
+                    Err(RecvError::Lagged(n)) => {
+                        metrics::increment(
+                            "striem_broadcast_lagged_total",
+                            &[("subscriber", "system_stream")],
+                            n,
+                        );
+                        warn!("system event subscriber lagged by {} messages, disconnecting", n);
+                        None
+                    }
+                    Err(RecvError::Closed) => None,
+                },
+                _ = interval.tick() => {
+                    let beat = heartbeat(&state).await;
+                    Some((
+                        SseEvent::default().event("heartbeat").json_data(beat),
+                        Some((rx, interval, state)),
+                    ))
+                }
+            }
+        },
+    );
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+pub fn create_router() -> Router<ApiState> {
+    Router::new().route("/", get(stream_events))
+}
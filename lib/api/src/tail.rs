@@ -0,0 +1,345 @@
+//! Recent received events, for watching what a source sends while it's
+//! onboarded, without waiting for Parquet files to rotate.
+//!
+//! - GET /api/1/events/tail - The most recent events, oldest first,
+//!   optionally only those with a `source_id` or `class_uid`
+//! - GET /api/1/events/tail/stream - Server-sent `event`s as they arrive,
+//!   with the same filters
+//!
+//! A token with a tenant claim only sees the events of that tenant's
+//! sources.
+//!
+//! Events are kept as they leave the Vector server, before enrichment and
+//! storage, up to `api.tail.events` events and `api.tail.max_bytes`
+//! serialized bytes. With `api.tail.enabled: false` nothing is kept and both
+//! endpoints answer 404. As with the alert stream, stream clients that fall
+//! behind are disconnected.
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use arc_swap::ArcSwap;
+use axum::{
+    Extension, Json, Router,
+    extract::{Query, State},
+    response::sse::{Event as SseEvent, KeepAlive, Sse},
+    routing::get,
+};
+use futures_util::{Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::Value;
+use striem_common::{SysMessage, event::Event, metrics};
+use striem_config::{StrIEMConfig, api::TailConfig};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+use utoipa::IntoParams;
+
+use crate::{ApiState, auth::Tenant, error::ApiError};
+
+/// Events returned when the client doesn't ask for a number
+const DEFAULT_LIMIT: usize = 100;
+
+/// Interval between SSE keep-alive comments
+const STREAM_HEARTBEAT: std::time::Duration = std::time::Duration::from_secs(15);
+
+struct Entry {
+    tenant: Option<String>,
+    source_id: Option<String>,
+    class_uid: Option<u64>,
+    json: String,
+}
+
+struct Buffer {
+    entries: VecDeque<Entry>,
+    bytes: usize,
+    limits: TailConfig,
+}
+
+impl Buffer {
+    fn trim(&mut self) {
+        while self.entries.len() > self.limits.events || self.bytes > self.limits.max_bytes {
+            match self.entries.pop_front() {
+                Some(entry) => self.bytes -= entry.json.len(),
+                None => break,
+            }
+        }
+    }
+}
+
+/// Ring buffer of the most recent received events, capped in events and
+/// bytes
+pub(crate) struct Tail {
+    buffer: Mutex<Buffer>,
+    received: broadcast::Sender<Arc<Vec<Event>>>,
+}
+
+impl Tail {
+    pub(crate) fn new(config: &TailConfig, received: broadcast::Sender<Arc<Vec<Event>>>) -> Self {
+        Tail {
+            buffer: Mutex::new(Buffer {
+                entries: VecDeque::new(),
+                bytes: 0,
+                limits: config.clone(),
+            }),
+            received,
+        }
+    }
+
+    /// Apply new limits, dropping what no longer fits; disabling drops
+    /// everything
+    pub(crate) fn configure(&self, config: &TailConfig) {
+        let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        buffer.limits = config.clone();
+        if !config.enabled {
+            buffer.entries.clear();
+            buffer.bytes = 0;
+        }
+        buffer.trim();
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.buffer
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .limits
+            .enabled
+    }
+
+    /// Keep `events`, evicting the oldest. Only as many of the batch's last
+    /// events as could be kept are serialized.
+    pub(crate) fn push(&self, events: &[Event]) {
+        let (enabled, keep, max_bytes) = {
+            let buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+            let limits = &buffer.limits;
+            (limits.enabled, limits.events, limits.max_bytes)
+        };
+        if !enabled || keep == 0 {
+            return;
+        }
+
+        let entries = events[events.len().saturating_sub(keep)..]
+            .iter()
+            .filter_map(|event| {
+                let json = serde_json::to_string(event).ok()?;
+                Some(Entry {
+                    tenant: event.tenant().map(str::to_string),
+                    source_id: source_id(event).map(str::to_string),
+                    class_uid: class_uid(event),
+                    json,
+                })
+            })
+            .filter(|entry| entry.json.len() <= max_bytes)
+            .collect::<Vec<_>>();
+
+        let mut buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        for entry in entries {
+            buffer.bytes += entry.json.len();
+            buffer.entries.push_back(entry);
+        }
+        buffer.trim();
+    }
+
+    /// Up to `limit` of the most recent events matching `filter`, and of
+    /// `tenant` if given, oldest first
+    pub(crate) fn recent(
+        &self,
+        filter: &TailFilter,
+        tenant: Option<&str>,
+        limit: usize,
+    ) -> Vec<Value> {
+        let buffer = self.buffer.lock().unwrap_or_else(|e| e.into_inner());
+        let mut events = buffer
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| tenant.is_none_or(|t| entry.tenant.as_deref() == Some(t)))
+            .filter(|entry| filter.matches(entry.source_id.as_deref(), entry.class_uid))
+            .take(limit)
+            .filter_map(|entry| serde_json::from_str(&entry.json).ok())
+            .collect::<Vec<_>>();
+        events.reverse();
+        events
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Arc<Vec<Event>>> {
+        self.received.subscribe()
+    }
+}
+
+fn source_id(event: &Event) -> Option<&str> {
+    event.metadata.get("source_id").and_then(|s| s.as_str())
+}
+
+fn class_uid(event: &Event) -> Option<u64> {
+    event.data.get("class_uid").and_then(|c| c.as_u64())
+}
+
+/// Keep the tail filled from the received events until shutdown, applying
+/// `api.tail` again on reload
+pub(crate) async fn run(
+    tail: Arc<Tail>,
+    config: Arc<ArcSwap<StrIEMConfig>>,
+    mut sys: broadcast::Receiver<SysMessage>,
+) {
+    let mut rx = tail.subscribe();
+    loop {
+        tokio::select! {
+            received = rx.recv() => match received {
+                Ok(events) => tail.push(&events),
+                // only the most recent events are wanted anyway
+                Err(RecvError::Lagged(n)) => {
+                    metrics::increment(
+                        "striem_broadcast_lagged_total",
+                        &[("subscriber", "event_tail")],
+                        n,
+                    );
+                }
+                Err(RecvError::Closed) => return,
+            },
+            message = sys.recv() => match message {
+                Ok(SysMessage::Reload) => tail.configure(&config.load().api.tail),
+                Ok(SysMessage::Shutdown) | Err(RecvError::Closed) => return,
+                _ => {}
+            },
+        }
+    }
+}
+
+/// Which events to return
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub(crate) struct TailFilter {
+    /// Only events from this Vector source
+    pub source_id: Option<String>,
+    /// Only events of this OCSF class
+    pub class_uid: Option<u64>,
+}
+
+impl TailFilter {
+    fn matches(&self, source_id: Option<&str>, class_uid: Option<u64>) -> bool {
+        self.source_id
+            .as_deref()
+            .is_none_or(|wanted| source_id == Some(wanted))
+            && self
+                .class_uid
+                .is_none_or(|wanted| class_uid == Some(wanted))
+    }
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+pub(crate) struct TailParams {
+    /// Only events from this Vector source
+    source_id: Option<String>,
+    /// Only events of this OCSF class
+    class_uid: Option<u64>,
+    /// Events to return, at most; defaults to 100
+    limit: Option<usize>,
+}
+
+fn enabled_tail(state: &ApiState) -> Result<&Arc<Tail>, ApiError> {
+    let tail = state
+        .tail
+        .as_ref()
+        .ok_or_else(|| ApiError::NotFound("events aren't received by this process".to_string()))?;
+    if !tail.enabled() {
+        return Err(ApiError::NotFound("the event tail is disabled".to_string()));
+    }
+    Ok(tail)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/1/events/tail",
+    tag = "system",
+    params(TailParams),
+    responses(
+        (status = 200, description = "The most recent matching events, oldest first", body = [Object]),
+        (status = 404, description = "The event tail is disabled", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_tail(
+    State(state): State<ApiState>,
+    tenant: Option<Extension<Tenant>>,
+    Query(params): Query<TailParams>,
+) -> Result<Json<Value>, ApiError> {
+    let tail = enabled_tail(&state)?;
+    let filter = TailFilter {
+        source_id: params.source_id,
+        class_uid: params.class_uid,
+    };
+    let tenant = tenant.as_ref().map(|Extension(Tenant(t))| t.as_str());
+    Ok(Json(Value::Array(tail.recent(
+        &filter,
+        tenant,
+        params.limit.unwrap_or(DEFAULT_LIMIT),
+    ))))
+}
+
+/// Stream received events as server-sent events.
+///
+/// Each event is sent as an `event` event in its wire format. The stream
+/// ends when the tail is disabled.
+#[utoipa::path(
+    get,
+    path = "/api/1/events/tail/stream",
+    tag = "system",
+    params(TailFilter),
+    responses(
+        (status = 200, description = "`event` events, one per matching received event", body = Object, content_type = "text/event-stream"),
+        (status = 404, description = "The event tail is disabled", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn stream_tail(
+    State(state): State<ApiState>,
+    tenant: Option<Extension<Tenant>>,
+    Query(filter): Query<TailFilter>,
+) -> Result<Sse<impl Stream<Item = Result<SseEvent, axum::Error>>>, ApiError> {
+    let tail = enabled_tail(&state)?.clone();
+    let rx = tail.subscribe();
+    let tenant = tenant.map(|Extension(Tenant(t))| t);
+
+    let events = futures_util::stream::unfold(
+        (rx, tail, filter, tenant),
+        |(mut rx, tail, filter, tenant)| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(_) if !tail.enabled() => return None,
+                    Ok(events) => {
+                        let events = events
+                            .iter()
+                            .filter(|event| tenant.is_none() || event.tenant() == tenant.as_deref())
+                            .filter(|event| filter.matches(source_id(event), class_uid(event)))
+                            .map(|event| SseEvent::default().event("event").json_data(event))
+                            .collect::<Vec<_>>();
+                        if !events.is_empty() {
+                            return Some((events, (rx, tail, filter, tenant)));
+                        }
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        metrics::increment(
+                            "striem_broadcast_lagged_total",
+                            &[("subscriber", "event_tail_stream")],
+                            n,
+                        );
+                        warn!(
+                            "event tail subscriber lagged by {} batches, disconnecting",
+                            n
+                        );
+                        return None;
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        },
+    )
+    .flat_map(futures_util::stream::iter);
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::new().interval(STREAM_HEARTBEAT)))
+}
+
+pub fn create_router() -> Router<ApiState> {
+    Router::new()
+        .route("/", get(get_tail))
+        .route("/stream", get(stream_tail))
+}
@@ -0,0 +1,75 @@
+//! HTTPS for the API server.
+//!
+//! With `api.tls` configured the API is served over TLS (rustls) from PEM
+//! certificate and key files; with `client_ca` set, clients must also
+//! present a certificate signed by that CA. Files are loaded once at
+//! startup, and any problem with them aborts it.
+
+use std::{path::Path, sync::Arc};
+
+use anyhow::{Result, anyhow};
+use rustls::{
+    ConfigBuilder, RootCertStore, ServerConfig, WantsVerifier,
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    server::WebPkiClientVerifier,
+};
+use striem_config::api::TlsConfig;
+
+fn certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| anyhow!("failed to read certificates from {}: {}", path.display(), e))?;
+    if certs.is_empty() {
+        return Err(anyhow!("no certificates found in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+fn versions(provider: Arc<CryptoProvider>) -> Result<ConfigBuilder<ServerConfig, WantsVerifier>> {
+    ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| anyhow!("TLS provider unusable: {}", e))
+}
+
+/// rustls configuration for `tls`. Dependencies compile in both the ring
+/// and aws-lc-rs backends, so the provider is named here rather than left to
+/// a process-wide default that nothing installs.
+pub(crate) fn server_config(tls: &TlsConfig) -> Result<ServerConfig> {
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let certs = certificates(&tls.cert)?;
+    let key = PrivateKeyDer::from_pem_file(&tls.key).map_err(|e| {
+        anyhow!(
+            "failed to read private key from {}: {}",
+            tls.key.display(),
+            e
+        )
+    })?;
+
+    let builder = match &tls.client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in certificates(ca)? {
+                roots
+                    .add(cert)
+                    .map_err(|e| anyhow!("invalid client CA in {}: {}", ca.display(), e))?;
+            }
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                    .build()
+                    .map_err(|e| anyhow!("invalid client CA in {}: {}", ca.display(), e))?;
+            versions(provider)?.with_client_cert_verifier(verifier)
+        }
+        None => versions(provider)?.with_no_client_auth(),
+    };
+    let mut config = builder.with_single_cert(certs, key).map_err(|e| {
+        anyhow!(
+            "invalid certificate {} or key {}: {}",
+            tls.cert.display(),
+            tls.key.display(),
+            e
+        )
+    })?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
@@ -0,0 +1,72 @@
+//! Request ids and request logging.
+//!
+//! Every response carries an `X-Request-Id`: the client's own when it sent a
+//! usable one, otherwise a new UUID. Each request is logged with its method,
+//! path, status, latency and id, at info when the status isn't a success and
+//! at debug otherwise. Errors returned as [`ApiError`](crate::error::ApiError)
+//! include the id in `detail.request_id`.
+
+use std::time::Instant;
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tracing::{Instrument, debug, info, info_span};
+
+pub(crate) const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client-supplied id that is kept rather than replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Id of the request being handled, outside of a request `None`
+pub(crate) fn current() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// The client's id if it's printable and not too long, else a new one
+fn request_id(request: &Request) -> String {
+    request
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::now_v7().to_string())
+}
+
+/// Middleware tagging each request with an id and logging its outcome
+pub(crate) async fn trace_request(mut request: Request, next: Next) -> Response {
+    let id = request_id(&request);
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    // handlers and anything proxied see the same id as the client
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        request.headers_mut().insert(X_REQUEST_ID, value);
+    }
+
+    let span = info_span!("request", request_id = %id);
+    let start = Instant::now();
+    let mut response = REQUEST_ID
+        .scope(id.clone(), next.run(request))
+        .instrument(span)
+        .await;
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    let status = response.status();
+    if status.is_success() {
+        debug!(request_id = %id, status = status.as_u16(), latency_ms, "{} {}", method, path);
+    } else {
+        info!(request_id = %id, status = status.as_u16(), latency_ms, "{} {}", method, path);
+    }
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(X_REQUEST_ID, value);
+    }
+    response
+}
@@ -1,74 +1,236 @@
-use crate::{ApiState, sinks::SINKS, sources::SOURCES};
-use axum::{Router, extract::State, routing::get};
-use striem_config::output::Destination;
-use toml::{Table, toml};
+//! Vector configuration for the configured sources and outputs.
+//!
+//! - GET /vector - The configuration, as TOML by default; `?format=yaml`,
+//!   `?format=json` or an `Accept` header of either picks another format
+//! - GET /vector/env - Environment file for `api.vector_interpolate`
+//! - GET /vector/validate - Errors and warnings found in the configuration,
+//!   and the output of `vector validate` when `api.vector_bin` is set
+//!
+//! The configuration is built once as JSON and serialized in the requested
+//! format, so all three describe the same structure. It's checked after it's
+//! assembled: inputs that don't match a source or transform, component ids
+//! used twice, and fields a component type can't do without. `/vector` still
+//! serves a configuration with problems, listing them in `X-Config-Warnings`.
+//!
+//! [`export_vector_config`] builds the same TOML outside the API, for
+//! `striem export-vector-config`.
 
-async fn get_vector_config(
-    State(state): State<ApiState>,
-) -> Result<String, (axum::http::StatusCode, String)> {
-    let mut config = toml! {
-        [schema]
-        log_namespace = true
-    };
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    process::Stdio,
+    time::Duration,
+};
+
+use crate::{
+    ApiState,
+    error::ApiError,
+    sinks::SINKS,
+    sources::{
+        SOURCES, reference_secrets, secrets,
+        tokens::{condition, token_vars, valid_hashes, valid_tokens},
+    },
+};
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    http::{HeaderMap, HeaderValue, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use striem_config::{StrIEMConfig, output::Destination};
+use utoipa::ToSchema;
+
+/// Format of the generated configuration
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum VectorFormat {
+    #[default]
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl VectorFormat {
+    /// The format named by `?format=`, else the first known `Accept` type
+    fn negotiate(params: &HashMap<String, String>, headers: &HeaderMap) -> Result<Self, ApiError> {
+        if let Some(format) = params.get("format") {
+            return serde_json::from_value(Value::String(format.to_ascii_lowercase()))
+                .map_err(|_| ApiError::BadRequest(format!("unknown format: {}", format)));
+        }
+        let accept = headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        let format = accept
+            .split(',')
+            .map(|t| t.split(';').next().unwrap_or_default().trim())
+            .find_map(|t| match t {
+                "application/toml" => Some(VectorFormat::Toml),
+                "application/yaml" | "application/x-yaml" | "text/yaml" => Some(VectorFormat::Yaml),
+                "application/json" => Some(VectorFormat::Json),
+                _ => None,
+            });
+        Ok(format.unwrap_or_default())
+    }
+
+    /// The format for a file, by its extension; TOML unless YAML or JSON
+    pub(crate) fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => VectorFormat::Yaml,
+            Some("json") => VectorFormat::Json,
+            _ => VectorFormat::Toml,
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            VectorFormat::Toml => "application/toml",
+            VectorFormat::Yaml => "application/yaml",
+            VectorFormat::Json => "application/json",
+        }
+    }
+
+    /// `config` in this format
+    pub(crate) fn render(&self, config: &Value) -> Result<String, ApiError> {
+        match self {
+            VectorFormat::Toml => Ok(toml::Table::try_from(config)
+                .map_err(ApiError::internal)?
+                .to_string()),
+            VectorFormat::Yaml => serde_yaml::to_string(config).map_err(ApiError::internal),
+            VectorFormat::Json => Ok(serde_json::to_string_pretty(config)?),
+        }
+    }
+}
+
+/// Drop `null` fields, which TOML can't express and Vector treats as unset
+fn strip_nulls(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.retain(|_, v| !v.is_null());
+            map.values_mut().for_each(strip_nulls);
+        }
+        Value::Array(values) => values.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+/// How long `vector validate` may take
+const VECTOR_VALIDATE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Fields a component can't do without, by section and type. Alternatives
+/// are separated by `|`, nested fields by `.`.
+const REQUIRED_FIELDS: &[(&str, &str, &[&str])] = &[
+    ("sources", "vector", &["address"]),
+    ("sources", "http_server", &["address"]),
+    ("sources", "splunk_hec", &["address"]),
+    ("sources", "okta", &["domain", "token"]),
+    ("sources", "aws_s3", &["sqs.queue_url"]),
+    ("sources", "socket", &["address", "mode"]),
+    ("transforms", "remap", &["source|file"]),
+    ("transforms", "filter", &["condition"]),
+    ("sinks", "vector", &["address"]),
+    ("sinks", "http", &["uri"]),
+];
+
+/// Insert a component, noting its id if one was already there
+fn insert_component(
+    map: &mut Map<String, Value>,
+    id: String,
+    component: Value,
+    duplicates: &mut Vec<String>,
+) {
+    if map.contains_key(&id) {
+        duplicates.push(id.clone());
+    }
+    map.insert(id, component);
+}
+
+/// The Vector configuration for the configured sources and outputs. With
+/// `api.vector_interpolate`, source secrets and HEC tokens are `${VAR}`
+/// references to the variables listed by `/vector/env`.
+pub(crate) async fn vector_config(state: &ApiState) -> Result<Value, ApiError> {
+    Ok(assemble(state).await?.0)
+}
+
+/// The configuration, and ids that were inserted more than once while
+/// assembling it; later ones replace earlier ones
+pub(crate) async fn assemble(state: &ApiState) -> Result<(Value, Vec<String>), ApiError> {
+    let mut duplicates = Vec::new();
+    let mut config = Map::new();
+    config.insert("schema".to_string(), json!({"log_namespace": true}));
 
     let striemconfig = state.config.load();
 
-    let mut transforms = toml::Table::new();
+    let mut transforms = Map::new();
 
-    let mut sources = toml! {
-        // this ensures the ocsf-* wildcard input always has at least one producer
-        [ocsf-stdin]
-        type = "stdin"
-        decoding = { codec = "json" }
-        framing = { method = "newline_delimited" }
-    };
+    let mut sources = Map::new();
+    // this ensures the ocsf-* wildcard input always has at least one producer
+    sources.insert(
+        "ocsf-stdin".to_string(),
+        json!({
+            "type": "stdin",
+            "decoding": {"codec": "json"},
+            "framing": {"method": "newline_delimited"},
+        }),
+    );
 
     let fqdn = striemconfig
         .fqdn
         .clone()
         .unwrap_or_else(|| striemconfig.input.url());
 
-    let mut sinks = toml! {
-        [sink-striem]
-        type = "vector"
-        inputs = ["ocsf-*"]
-        address = fqdn
-    };
+    let mut sinks = Map::new();
+    sinks.insert(
+        "sink-striem".to_string(),
+        json!({
+            "type": "vector",
+            "inputs": ["ocsf-*"],
+            "address": fqdn,
+        }),
+    );
 
-    if let Some(Destination::Vector(ref cfg)) = striemconfig.output {
+    // Vector's own sources are configured from the first Vector output
+    let vector = striemconfig.outputs.iter().find_map(|output| match output {
+        Destination::Vector(cfg) => Some(cfg),
+        _ => None,
+    });
+    if let Some(cfg) = vector {
         if let Some(api) = &cfg.api {
-            let api_address = api.address().to_string();
-            let api_config = toml! {
-                [api]
-                enabled = true
-                address = api_address
-            };
-            config.extend(api_config);
+            config.insert(
+                "api".to_string(),
+                json!({
+                    "enabled": true,
+                    "address": api.try_address().map_err(ApiError::internal)?.to_string(),
+                }),
+            );
         }
 
-        let address = cfg.cfg.address().to_string();
-
         sources.insert(
             "source-striem".to_string(),
-            toml! {
-                type = "vector"
-                address = address
-                version = "2"
-            }
-            .into(),
+            json!({
+                "type": "vector",
+                "address": cfg.cfg.try_address().map_err(ApiError::internal)?.to_string(),
+                "version": "2",
+            }),
         );
 
-        // TODO: set valid_tokens based on the list of sources
-        if let Some(hec) = &cfg.hec {
-            let address = hec.address().to_string();
+        // Only the HEC sources' tokens are accepted; with none there's
+        // nothing to receive, so there's no listener to send to either
+        let tokens = valid_tokens(striemconfig.api.vector_interpolate).await;
+        if let Some(hec) = &cfg.hec
+            && !tokens.is_empty()
+        {
             sources.insert(
                 "source-hec".to_string(),
-                toml! {
-                    type = "splunk_hec"
-                    address = address
-                    store_hec_token = true
-                }
-                .into(),
+                json!({
+                    "type": "splunk_hec",
+                    "address": hec.try_address().map_err(ApiError::internal)?.to_string(),
+                    "store_hec_token": true,
+                    "valid_tokens": tokens,
+                }),
             );
         }
 
@@ -86,42 +248,53 @@ async fn get_vector_config(
             ]
             .join("\n");
 
-            let address = http.address().to_string();
-            sources.extend(toml! {
-                [source-http]
-                type = "http_server"
-                address = address
-                headers = ['*']
-                strict_path = false
-
-                [source-http.decoding]
-                codec = "vrl"
-                vrl = {"source" = vrl}
-            });
+            sources.insert(
+                "source-http".to_string(),
+                json!({
+                    "type": "http_server",
+                    "address": http.try_address().map_err(ApiError::internal)?.to_string(),
+                    "headers": ["*"],
+                    "strict_path": false,
+                    "decoding": {"codec": "vrl", "vrl": {"source": vrl}},
+                }),
+            );
         }
     }
 
-    SOURCES.read().await.iter().for_each(|source| {
-        Table::try_from(source)
-            .map(|t| {
-                if let Some(s) = t.get("sources").and_then(|s| s.as_table()) {
-                    sources.extend(s.clone());
+    let interpolate = striemconfig.api.vector_interpolate;
+    for source in SOURCES.read().await.iter() {
+        let Ok(Value::Object(mut t)) = serde_json::to_value(source) else {
+            continue;
+        };
+        if let Some(Value::Object(s)) = t.remove("sources") {
+            for (id, mut table) in s {
+                if interpolate && let Value::Object(table) = &mut table {
+                    reference_secrets(source.as_ref(), table);
                 }
-
-                if let Some(t) = t.get("transforms").and_then(|t| t.as_table()) {
-                    transforms.extend(t.clone());
+                insert_component(&mut sources, id, table, &mut duplicates);
+            }
+        }
+        if let Some(Value::Object(t)) = t.remove("transforms") {
+            let hec = source
+                .hec()
+                .then(|| format!("source-{}_{}", source.sourcetype(), source.id()));
+            for (id, mut table) in t {
+                if hec.as_ref() == Some(&id) {
+                    let hashes = valid_hashes(&source.id()).await;
+                    table["condition"] = Value::String(condition(&hashes));
                 }
-            })
-            .ok();
-    });
+                insert_component(&mut transforms, id, table, &mut duplicates);
+            }
+        }
+    }
 
-    SINKS.read().await.iter().for_each(|sink| {
-        Table::try_from(sink)
-            .map(|s| {
-                sinks.extend(s);
-            })
-            .ok();
-    });
+    for sink in SINKS.read().await.iter() {
+        if let Ok(Value::Object(s)) = serde_json::to_value(sink) {
+            for (id, table) in s {
+                insert_component(&mut sinks, id, table, &mut duplicates);
+            }
+        }
+    }
 
     if !sources.is_empty() {
         config.insert("sources".to_string(), sources.into());
@@ -133,9 +306,357 @@ async fn get_vector_config(
         config.insert("sinks".to_string(), sinks.into());
     }
 
-    Ok(config.to_string())
+    let mut config = Value::Object(config);
+    strip_nulls(&mut config);
+    Ok((config, duplicates))
+}
+
+/// Output of `vector validate`
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct VectorCheck {
+    pub success: bool,
+    /// Combined stdout and stderr
+    pub output: String,
+}
+
+/// Problems found in a generated configuration
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub(crate) struct Validation {
+    /// No errors, and `vector validate` passed if it ran
+    pub valid: bool,
+    /// Problems Vector won't start with
+    pub errors: Vec<String>,
+    /// Problems Vector starts with anyway, like a wildcard input with no match
+    pub warnings: Vec<String>,
+    /// `vector validate`, when `api.vector_bin` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vector: Option<VectorCheck>,
+}
+
+/// Whether `id` matches `pattern`, where `*` matches any run of characters
+fn wildcard_match(pattern: &str, id: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = id.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// Whether `field`, dotted for nested fields, is set to something non-empty
+fn has_field(component: &Value, field: &str) -> bool {
+    let value = field
+        .split('.')
+        .try_fold(component, |value, key| value.get(key));
+    match value {
+        None | Some(Value::Null) => false,
+        Some(Value::String(s)) => !s.is_empty(),
+        Some(_) => true,
+    }
+}
+
+/// Check an assembled configuration
+pub(crate) fn validate(config: &Value, duplicates: &[String]) -> Validation {
+    let mut validation = Validation::default();
+    for id in duplicates {
+        validation
+            .errors
+            .push(format!("component id '{}' is defined more than once", id));
+    }
+
+    let section = |name: &str| config.get(name).and_then(Value::as_object);
+    let mut seen = HashSet::new();
+    for name in ["sources", "transforms", "sinks"] {
+        for id in section(name).into_iter().flat_map(|s| s.keys()) {
+            if !seen.insert(id.as_str()) {
+                validation.errors.push(format!(
+                    "component id '{}' is used in more than one section",
+                    id
+                ));
+            }
+        }
+    }
+
+    // Only sources and transforms can be inputs
+    let producers: Vec<&str> = ["sources", "transforms"]
+        .into_iter()
+        .filter_map(section)
+        .flat_map(|s| s.keys().map(String::as_str))
+        .collect();
+
+    for name in ["sources", "transforms", "sinks"] {
+        let kind = name.trim_end_matches('s');
+        for (id, component) in section(name).into_iter().flatten() {
+            let Some(ty) = component.get("type").and_then(Value::as_str) else {
+                validation
+                    .errors
+                    .push(format!("{} '{}' has no type", kind, id));
+                continue;
+            };
+
+            let required = REQUIRED_FIELDS
+                .iter()
+                .filter(|(section, t, _)| *section == name && *t == ty)
+                .flat_map(|(_, _, fields)| fields.iter());
+            for field in required {
+                if !field.split('|').any(|f| has_field(component, f)) {
+                    validation.errors.push(format!(
+                        "{} '{}' ({}) is missing '{}'",
+                        kind,
+                        id,
+                        ty,
+                        field.replace('|', "' or '")
+                    ));
+                }
+            }
+
+            if name == "sources" {
+                continue;
+            }
+            let inputs: Vec<&str> = component
+                .get("inputs")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .collect();
+            if inputs.is_empty() {
+                validation
+                    .errors
+                    .push(format!("{} '{}' has no inputs", kind, id));
+            }
+            for input in inputs {
+                if input.contains('*') {
+                    if !producers
+                        .iter()
+                        .any(|p| *p != id && wildcard_match(input, p))
+                    {
+                        validation.warnings.push(format!(
+                            "{} '{}' input '{}' matches no source or transform",
+                            kind, id, input
+                        ));
+                    }
+                } else if input == id || !producers.contains(&input) {
+                    validation.errors.push(format!(
+                        "{} '{}' input '{}' is not a source or transform",
+                        kind, id, input
+                    ));
+                }
+            }
+        }
+    }
+
+    validation.valid = validation.errors.is_empty();
+    validation
+}
+
+/// Run `vector validate` on `config`, written to a temporary TOML file
+async fn vector_validate(bin: &Path, config: &Value) -> Result<VectorCheck, ApiError> {
+    let rendered = VectorFormat::Toml.render(config)?;
+    let file = tempfile::Builder::new()
+        .prefix("striem-vector-")
+        .suffix(".toml")
+        .tempfile()
+        .map_err(ApiError::internal)?;
+    tokio::fs::write(file.path(), rendered)
+        .await
+        .map_err(ApiError::internal)?;
+
+    let child = tokio::process::Command::new(bin)
+        .arg("validate")
+        .arg("--no-environment")
+        .arg(file.path())
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = match tokio::time::timeout(VECTOR_VALIDATE_TIMEOUT, child).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => {
+            return Err(ApiError::Unavailable(format!(
+                "could not run {}: {}",
+                bin.display(),
+                e
+            )));
+        }
+        Err(_) => {
+            return Err(ApiError::Unavailable(format!(
+                "{} validate timed out",
+                bin.display()
+            )));
+        }
+    };
+
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok(VectorCheck {
+        success: output.status.success(),
+        output: text,
+    })
+}
+
+/// `X-Config-Warnings` for a configuration's problems: errors and warnings
+/// joined by `; `, reduced to characters a header can carry
+fn warnings_header(validation: &Validation) -> Option<HeaderValue> {
+    let problems: Vec<&str> = validation
+        .errors
+        .iter()
+        .chain(&validation.warnings)
+        .map(String::as_str)
+        .collect();
+    if problems.is_empty() {
+        return None;
+    }
+    let value: String = problems
+        .join("; ")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() || c == ' ' {
+                c
+            } else {
+                '?'
+            }
+        })
+        .collect();
+    HeaderValue::from_str(&value).ok()
+}
+
+/// Vector configuration for the configured sources and outputs
+#[utoipa::path(
+    get,
+    path = "/vector",
+    tag = "vector",
+    params(("format" = Option<VectorFormat>, Query, description = "`toml` (default), `yaml` or `json`; without it the `Accept` header decides")),
+    responses(
+        (status = 200, description = "Vector configuration; problems found in it are listed in `X-Config-Warnings`", body = String, content_type = ["application/toml", "application/yaml", "application/json"]),
+        (status = 400, description = "Unknown format", body = crate::error::ErrorBody),
+        (status = 500, description = "Configuration could not be generated", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_vector_config(
+    State(state): State<ApiState>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let format = VectorFormat::negotiate(&params, &headers)?;
+    let (config, duplicates) = assemble(&state).await?;
+    let mut response = (
+        [(header::CONTENT_TYPE, format.content_type())],
+        format.render(&config)?,
+    )
+        .into_response();
+    if let Some(warnings) = warnings_header(&validate(&config, &duplicates)) {
+        response.headers_mut().insert("x-config-warnings", warnings);
+    }
+    Ok(response)
+}
+
+/// Check the generated Vector configuration
+#[utoipa::path(
+    get,
+    path = "/vector/validate",
+    tag = "vector",
+    responses(
+        (status = 200, description = "Errors and warnings, and `vector validate` output when `api.vector_bin` is set", body = Validation),
+        (status = 503, description = "`vector validate` could not be run", body = crate::error::ErrorBody),
+        (status = 500, description = "Configuration could not be generated", body = crate::error::ErrorBody),
+    )
+)]
+pub(crate) async fn get_vector_validation(
+    State(state): State<ApiState>,
+) -> Result<Json<Validation>, ApiError> {
+    let (config, duplicates) = assemble(&state).await?;
+    let mut validation = validate(&config, &duplicates);
+    if let Some(bin) = &state.config.load().api.vector_bin {
+        let check = vector_validate(bin, &config).await?;
+        validation.valid &= check.success;
+        validation.vector = Some(check);
+    }
+    Ok(Json(validation))
+}
+
+/// Environment file with the source secrets and HEC tokens referenced by
+/// `/vector`, one `VAR=value` per line; values spanning lines are left out
+#[utoipa::path(
+    get,
+    path = "/vector/env",
+    tag = "vector",
+    responses(
+        (status = 200, description = "Environment file for Vector", body = String, content_type = "text/plain"),
+    )
+)]
+pub(crate) async fn get_vector_env(State(_): State<ApiState>) -> impl IntoResponse {
+    let mut env = String::new();
+    for source in SOURCES.read().await.iter() {
+        for (var, value) in secrets(source.as_ref()) {
+            if value.contains(['\n', '\r']) {
+                tracing::warn!(
+                    "{} spans lines, leaving it out of the environment file",
+                    var
+                );
+                continue;
+            }
+            env.push_str(&format!("{}={}\n", var, value));
+        }
+    }
+    for (var, token) in token_vars().await {
+        env.push_str(&format!("{}={}\n", var, token));
+    }
+    ([(header::CONTENT_TYPE, "text/plain")], env)
+}
+
+/// The configuration served at `/vector` as TOML, for `config` and the
+/// sources saved in its database, without starting the API
+pub async fn export_vector_config(config: StrIEMConfig) -> anyhow::Result<String> {
+    let db = crate::initdb(&config)?;
+    if let Some(db) = db.as_ref() {
+        let mut conn = db
+            .get()
+            .map_err(|e| anyhow::anyhow!("Failed to get DB connection: {}", e))?;
+        SOURCES
+            .write()
+            .await
+            .append(&mut crate::persist::sources(&mut conn)?);
+    }
+
+    let state = ApiState {
+        detections: Default::default(),
+        ocsf: Default::default(),
+        levels: Default::default(),
+        origins: Default::default(),
+        lists: Default::default(),
+        coverage: Default::default(),
+        schema: Default::default(),
+        actions: None,
+        db,
+        features: HeaderValue::from_static(""),
+        sys: tokio::sync::broadcast::channel(1).0,
+        events: tokio::sync::broadcast::channel(1).0,
+        stats: Default::default(),
+        tail: None,
+        health: Default::default(),
+        config: std::sync::Arc::new(arc_swap::ArcSwap::from_pointee(config)),
+    };
+    let config = vector_config(&state)
+        .await
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    VectorFormat::Toml
+        .render(&config)
+        .map_err(|e| anyhow::anyhow!("{}", e))
 }
 
 pub fn create_router() -> axum::Router<ApiState> {
-    Router::new().route("/", get(get_vector_config))
+    Router::new()
+        .route("/", get(get_vector_config))
+        .route("/env", get(get_vector_env))
+        .route("/validate", get(get_vector_validation))
 }
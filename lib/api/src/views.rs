@@ -0,0 +1,156 @@
+//! A DuckDB view per stored OCSF class, so queries can name classes rather
+//! than Parquet globs: `SELECT * FROM authentication WHERE ...`.
+//!
+//! Views are (re)created at startup and on every reload, which covers a
+//! storage destination change through `/api/1/destination`; the database is
+//! reopened first when DuckDB may not read the new location yet (see
+//! [`crate::db`]). A class whose
+//! directory exists reads `{root}/{category}/{class}/**/*.parquet`; while
+//! the directory holds no files yet, its view is empty, with the columns of
+//! the class schema. Views of classes no longer stored are dropped. With
+//! object storage every class gets a view once it has files.
+
+use std::{
+    path::Path,
+    sync::{Arc, LazyLock},
+};
+
+use arc_swap::ArcSwap;
+use striem_common::SysMessage;
+use striem_config::{StrIEMConfig, storage::StorageConfig};
+use striem_storage::schema::ClassSchema;
+use tokio::sync::{RwLock, broadcast};
+use tracing::{debug, error, warn};
+
+use crate::{Pool, query::SchemaCache, sql_string};
+
+/// Names of the views currently defined
+pub(crate) static VIEWS: LazyLock<RwLock<Vec<String>>> = LazyLock::new(|| RwLock::new(Vec::new()));
+
+pub(crate) fn identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// `CREATE OR REPLACE VIEW` statement for `class`, over the Parquet files
+/// matching `glob`, or empty without one
+pub(crate) fn view_sql(class: &ClassSchema, glob: Option<&str>) -> String {
+    let query = match glob {
+        Some(glob) => format!(
+            "SELECT * FROM read_parquet({}, union_by_name = true)",
+            sql_string(glob)
+        ),
+        None => format!(
+            "SELECT {} WHERE false",
+            class
+                .fields
+                .iter()
+                .map(|f| format!("NULL::{} AS {}", f.data_type, identifier(&f.name)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    format!(
+        "CREATE OR REPLACE VIEW {} AS {};",
+        identifier(&class.class),
+        query
+    )
+}
+
+fn has_parquet(dir: &Path) -> bool {
+    let mut files = Vec::new();
+    striem_storage::parquet_files(dir, &mut files).is_ok() && !files.is_empty()
+}
+
+/// Define the view of every class in `classes` stored under `storage`,
+/// returning the names of the views defined
+pub(crate) fn refresh(
+    conn: &duckdb::Connection,
+    storage: &StorageConfig,
+    classes: &[ClassSchema],
+) -> Vec<String> {
+    let mut views = Vec::new();
+    for class in classes {
+        let root = storage.root_for(&class.category, &class.class);
+        let dir = root.join(&class.category).join(&class.class);
+        let glob = format!("{}/**/*.parquet", dir.to_string_lossy());
+
+        let defined = if storage.uri.is_some() {
+            // no cheap way to list an object store; a glob without
+            // matches fails
+            conn.execute_batch(&view_sql(class, Some(&glob)))
+                .inspect_err(|e| debug!("no view of {}: {}", class.class, e))
+                .is_ok()
+        } else if !dir.is_dir() || class.fields.is_empty() {
+            false
+        } else {
+            let glob = has_parquet(&dir).then_some(glob.as_str());
+            conn.execute_batch(&view_sql(class, glob))
+                .inspect_err(|e| warn!("failed to create view {}: {}", class.class, e))
+                .is_ok()
+        };
+
+        if defined {
+            views.push(class.class.clone());
+        } else if let Err(e) = conn.execute_batch(&format!(
+            "DROP VIEW IF EXISTS {};",
+            identifier(&class.class)
+        )) {
+            warn!("failed to drop view {}: {}", class.class, e);
+        }
+    }
+    views
+}
+
+/// Define the views for the current configuration
+async fn refresh_all(pool: &Pool, config: &StrIEMConfig) {
+    let Some(storage) = config.storage.clone() else {
+        VIEWS.write().await.clear();
+        return;
+    };
+    let pool = pool.clone();
+    let refreshed = tokio::task::spawn_blocking(move || -> anyhow::Result<Vec<String>> {
+        let classes = striem_storage::schema::load_schemas(&storage.schema)?;
+        let conn = pool.get()?;
+        Ok(refresh(&conn, &storage, &classes))
+    })
+    .await;
+
+    match refreshed {
+        Ok(Ok(views)) => *VIEWS.write().await = views,
+        Ok(Err(e)) => warn!("failed to refresh class views: {}", e),
+        Err(e) => warn!("failed to refresh class views: {}", e),
+    }
+}
+
+/// Reopen the database if storage moved, so DuckDB may read the new location
+async fn reopen(pool: &Pool, config: Arc<StrIEMConfig>) {
+    let pool = pool.clone();
+    match tokio::task::spawn_blocking(move || pool.reopen(&config)).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => error!("failed to reopen the database: {}", e),
+        Err(e) => error!("failed to reopen the database: {}", e),
+    }
+}
+
+/// Keep the class views current until shutdown, reopening the database
+/// when storage moves and clearing the cached query schema once they change
+pub(crate) async fn run(
+    pool: Pool,
+    config: Arc<ArcSwap<StrIEMConfig>>,
+    schema: SchemaCache,
+    mut sys: broadcast::Receiver<SysMessage>,
+) {
+    refresh_all(&pool, &config.load()).await;
+    schema.store(None);
+    loop {
+        match sys.recv().await {
+            Ok(SysMessage::Reload) => {
+                reopen(&pool, config.load_full()).await;
+                refresh_all(&pool, &config.load()).await;
+                schema.store(None);
+            }
+            Ok(SysMessage::Shutdown) | Err(broadcast::error::RecvError::Closed) => return,
+            _ => {}
+        }
+    }
+}
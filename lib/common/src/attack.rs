@@ -0,0 +1,98 @@
+//! MITRE ATT&CK tags of Sigma rules (`attack.<tactic>`, `attack.tNNNN`,
+//! `attack.tNNNN.NNN`), for coverage reports and the `attacks` of
+//! detection findings.
+
+use serde_json::{Value, json};
+
+/// Enterprise tactics: Sigma tag, ATT&CK id and name
+pub const TACTICS: &[(&str, &str, &str)] = &[
+    ("reconnaissance", "TA0043", "Reconnaissance"),
+    ("resource_development", "TA0042", "Resource Development"),
+    ("initial_access", "TA0001", "Initial Access"),
+    ("execution", "TA0002", "Execution"),
+    ("persistence", "TA0003", "Persistence"),
+    ("privilege_escalation", "TA0004", "Privilege Escalation"),
+    ("defense_evasion", "TA0005", "Defense Evasion"),
+    ("credential_access", "TA0006", "Credential Access"),
+    ("discovery", "TA0007", "Discovery"),
+    ("lateral_movement", "TA0008", "Lateral Movement"),
+    ("collection", "TA0009", "Collection"),
+    ("command_and_control", "TA0011", "Command and Control"),
+    ("exfiltration", "TA0010", "Exfiltration"),
+    ("impact", "TA0040", "Impact"),
+];
+
+/// Lowercased ATT&CK tags of a rule, without the `attack.` prefix
+pub fn attack_tags<'a>(tags: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    tags.into_iter()
+        .filter_map(|t| t.to_lowercase().strip_prefix("attack.").map(String::from))
+        .collect()
+}
+
+/// `(id, name)` of the tactic tagged `tag`, e.g. `initial_access`
+pub fn tactic(tag: &str) -> Option<(&'static str, &'static str)> {
+    TACTICS
+        .iter()
+        .find(|(t, _, _)| *t == tag)
+        .map(|(_, uid, name)| (*uid, *name))
+}
+
+/// Parse an ATT&CK technique id (`t1078`, `t1078.004`) into canonical form
+pub fn parse_technique(tag: &str) -> Option<String> {
+    let (base, sub) = match tag.split_once('.') {
+        Some((base, sub)) => (base, Some(sub)),
+        None => (tag, None),
+    };
+    let digits = base.strip_prefix('t')?;
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    match sub {
+        Some(sub) if sub.len() == 3 && sub.chars().all(|c| c.is_ascii_digit()) => {
+            Some(format!("T{}.{}", digits, sub))
+        }
+        Some(_) => None,
+        None => Some(format!("T{}", digits)),
+    }
+}
+
+/// OCSF `attacks` of a rule with `tags`: one per technique and tactic, with
+/// a sub-technique's parent as its `technique`. A technique without a
+/// tactic tag has no `tactic`, a tactic without a technique no `technique`.
+pub fn attacks<'a>(tags: impl IntoIterator<Item = &'a str>) -> Vec<Value> {
+    let tags = attack_tags(tags);
+    let tactics = tags
+        .iter()
+        .filter_map(|t| tactic(t))
+        .map(|(uid, name)| json!({"uid": uid, "name": name}))
+        .collect::<Vec<_>>();
+    let techniques = tags
+        .iter()
+        .filter_map(|t| parse_technique(t))
+        .map(|uid| match uid.split_once('.') {
+            Some((parent, _)) => json!({
+                "technique": {"uid": parent},
+                "sub_technique": {"uid": uid},
+            }),
+            None => json!({"technique": {"uid": uid}}),
+        })
+        .collect::<Vec<_>>();
+
+    match (tactics.is_empty(), techniques.is_empty()) {
+        (_, true) => tactics
+            .into_iter()
+            .map(|tactic| json!({"tactic": tactic}))
+            .collect(),
+        (true, false) => techniques,
+        (false, false) => tactics
+            .iter()
+            .flat_map(|tactic| {
+                techniques.iter().map(move |technique| {
+                    let mut attack = technique.clone();
+                    attack["tactic"] = tactic.clone();
+                    attack
+                })
+            })
+            .collect(),
+    }
+}
@@ -0,0 +1,295 @@
+//! Sigma rule evaluation, shared by the detection engine and replays of
+//! stored events.
+//!
+//! OCSF events (with `ocsf` metadata) are evaluated in their original
+//! vendor format when they carry `raw_data`, so vendor-specific rules keep
+//! working after normalization, and rules written for OCSF (logsource
+//! product `ocsf`) are evaluated against the normalized event as well.
+//! Each match becomes an OCSF detection_finding (class_uid 2004) correlated
+//! with the event, with the Vector agent it arrived from, if known, in
+//! `metadata.loggers`, and its source's tenant, if any, in
+//! `metadata.labels`. The rule's first reference, identity, ATT&CK tags and
+//! false positives are carried over to `finding_info` and `unmapped`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use arc_swap::ArcSwap;
+use chrono::Utc;
+use serde_json::{Value, json};
+use sigmars::SigmaCollection;
+
+use crate::{
+    attack,
+    event::{Event, add_logger, ingest_logger, label_tenant},
+    lists::{ListMatcher, list_matches},
+    severity::Severity,
+};
+
+/// (rule id, finding) pairs for one evaluated event
+pub type Matches = Vec<(String, Event)>;
+
+/// Logsource product of rules written against OCSF fields
+pub const OCSF_PRODUCT: &str = "ocsf";
+
+/// Whether the serialized Sigma `rule` is written against OCSF fields rather
+/// than a vendor format: its logsource product or taxonomy is `ocsf`
+pub fn is_ocsf_rule(rule: &Value) -> bool {
+    [&rule["logsource"]["product"], &rule["taxonomy"]]
+        .iter()
+        .any(|v| {
+            v.as_str()
+                .is_some_and(|v| v.eq_ignore_ascii_case(OCSF_PRODUCT))
+        })
+}
+
+/// Ids of the loaded rules written against OCSF fields, refreshed whenever
+/// rules are added or replaced
+pub type OcsfRules = Arc<ArcSwap<HashSet<String>>>;
+
+/// Ids of the rules in `rules` written against OCSF fields (see
+/// [`is_ocsf_rule`])
+pub fn ocsf_rules(rules: &SigmaCollection) -> HashSet<String> {
+    serde_json::to_value(rules)
+        .ok()
+        .and_then(|rules| match rules {
+            Value::Array(rules) => Some(rules),
+            _ => None,
+        })
+        .unwrap_or_default()
+        .iter()
+        .filter(|rule| is_ocsf_rule(rule))
+        .filter_map(|rule| Some(rule.get("id")?.as_str()?.to_string()))
+        .collect()
+}
+
+/// Rules a payload is evaluated against
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Taxonomy {
+    Any,
+    Vendor,
+    Ocsf,
+}
+
+/// Ids of the rules matching `data` under `logsource`, after tagging it with
+/// the reference `lists` it hits, and `metadata` with those hits if any
+async fn matching(
+    rules: &SigmaCollection,
+    lists: &HashMap<String, ListMatcher>,
+    data: &Value,
+    metadata: &HashMap<String, Value>,
+    logsource: sigmars::event::LogSource,
+) -> Result<(Vec<String>, Option<HashMap<String, Value>>), String> {
+    // Rules condition on `list_matches`; the event is only copied on a hit
+    let matched = list_matches(lists, data);
+    let (tagged, metadata_tagged) = if matched.is_empty() {
+        (None, None)
+    } else {
+        let matched = Value::Object(matched.into_iter().collect());
+        let mut metadata = metadata.clone();
+        metadata.insert("list_matches".to_string(), matched.clone());
+        let tagged = data.as_object().map(|data| {
+            let mut data = data.clone();
+            data.insert("list_matches".to_string(), matched);
+            Value::Object(data)
+        });
+        (tagged, Some(metadata))
+    };
+
+    let sigma_event = sigmars::event::RefEvent {
+        data: tagged.as_ref().unwrap_or(data),
+        metadata: metadata_tagged.as_ref().unwrap_or(metadata),
+        logsource,
+    };
+    let ids = rules
+        .get_matches_from_ref(&sigma_event)
+        .await
+        .map_err(|e| format!("error applying rules: {}", e))?
+        .iter()
+        .map(|id| id.to_string())
+        .collect();
+    Ok((ids, metadata_tagged))
+}
+
+/// Evaluate `event` against `rules`, building a detection finding for each
+/// match with any severity override in `levels` applied, after tagging it
+/// with the reference `lists` it hits.
+///
+/// OCSF events are evaluated twice: vendor rules against `raw_data` (or the
+/// normalized event without it) under the event's logsource, and the OCSF
+/// rules in `ocsf` (see [`ocsf_rules`]) against the normalized event under
+/// the `ocsf` product. A rule matching in both yields one finding.
+pub async fn findings(
+    rules: &SigmaCollection,
+    ocsf: &HashSet<String>,
+    levels: &HashMap<String, Severity>,
+    lists: &HashMap<String, ListMatcher>,
+    event: &Event,
+) -> Result<Matches, String> {
+    // Extract logsource for rule filtering (e.g., windows/sysmon, aws/cloudtrail)
+    let filter = event
+        .metadata
+        .get("logsource")
+        .map(|v| sigmars::event::LogSource::from(v.clone()))
+        .unwrap_or_default();
+
+    // For OCSF events, vendor rules see the raw_data field, so vendor-specific
+    // Sigma rules keep working post-normalization
+    let is_ocsf = event.metadata.contains_key("ocsf");
+    let raw_data = is_ocsf
+        .then(|| match event.data.get("raw_data") {
+            Some(Value::String(raw_data)) => serde_json::from_str::<Value>(raw_data).ok(),
+            _ => None,
+        })
+        .flatten();
+
+    let passes = if is_ocsf {
+        vec![
+            (
+                raw_data.as_ref().unwrap_or(&event.data),
+                filter,
+                Taxonomy::Vendor,
+            ),
+            (
+                &event.data,
+                sigmars::event::LogSource::from(json!({"product": OCSF_PRODUCT})),
+                Taxonomy::Ocsf,
+            ),
+        ]
+    } else {
+        vec![(&event.data, filter, Taxonomy::Any)]
+    };
+
+    // rule id and the metadata of the payload it matched, first match wins
+    let mut matched: Vec<(String, Option<HashMap<String, Value>>)> = Vec::new();
+    for (data, logsource, taxonomy) in passes {
+        let (ids, metadata) = matching(rules, lists, data, &event.metadata, logsource).await?;
+        for id in ids {
+            if matched.iter().any(|(m, _)| *m == id) {
+                continue;
+            }
+            let rule_is_ocsf = ocsf.contains(&id);
+            let wanted = match taxonomy {
+                Taxonomy::Any => true,
+                Taxonomy::Vendor => !rule_is_ocsf,
+                Taxonomy::Ocsf => rule_is_ocsf,
+            };
+            if wanted {
+                matched.push((id, metadata.clone()));
+            }
+        }
+    }
+
+    // Convert matching rules to OCSF detection_finding events
+    let detections = matched
+        .iter()
+        .filter_map(|(id, metadata)| Some((rules.get(id)?, metadata)))
+        .filter_map(|(d, metadata)| {
+            let metadata = metadata.as_ref().unwrap_or(&event.metadata);
+            // Establish correlation between detection and original event
+            // Uses OCSF metadata.uid if present, falls back to StrIEM's event ID
+            let correlation_uid = event
+                .data
+                .as_object()
+                .and_then(|v| v.get("metadata"))
+                .and_then(|v| v.as_object())
+                .and_then(|v| v.get("uid"))
+                .and_then(|v| v.as_str())
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| event.id.to_string());
+
+            let mut ocsf = Event::default();
+
+            // Convert Sigma detection to OCSF detection_finding (class_uid 2004)
+            let mut data: Value = d.into();
+            data["metadata"]["uid"] = json!(event.id.to_string());
+            data["metadata"]["correlation_uid"] = json!(correlation_uid);
+            // Record the originating rule so findings can be traced back to it
+            if data["finding_info"]["analytic"]["uid"].is_null() {
+                data["finding_info"]["analytic"]["uid"] = json!(d.id);
+            }
+            if let Ok(rule) = serde_json::to_value(d) {
+                describe_rule(&mut data, &rule);
+            }
+            data["metadata"]["product"] = json!({
+                "vendor_name": "StrIEM",
+                "product_name": "StrIEM"
+            });
+            if let Some(logger) = ingest_logger(metadata) {
+                add_logger(&mut data, logger);
+            }
+            if let Some(tenant) = event.tenant() {
+                label_tenant(&mut data, tenant);
+            }
+            // Severity overrides set via the API take precedence over the rule's YAML level
+            if let Some(level) = levels.get(&d.id) {
+                data["severity"] = json!(level.caption());
+                data["severity_id"] = json!(level.id());
+            }
+            // the finding's own time if the rule set one, else when it was found
+            ocsf.time = Event::time_of(&data).or_else(|| Some(Utc::now()));
+            ocsf.data = data;
+            ocsf.metadata
+                .extend(metadata.iter().map(|(k, v)| (k.clone(), v.clone())));
+            ocsf.metadata.extend([
+                ("ocsf".to_string(), json!(true)),
+                ("striem".to_string(), json!(true)),
+            ]);
+            if let Some(tenant) = event.tenant() {
+                ocsf.metadata.insert("tenant".to_string(), json!(tenant));
+            }
+            Some((d.id.clone(), ocsf))
+        })
+        .collect::<Vec<_>>();
+    Ok(detections)
+}
+
+/// Set `key` of `target` unless it's already set
+fn set_default(target: &mut Value, key: &str, value: Value) {
+    if target[key].is_null() {
+        target[key] = value;
+    }
+}
+
+/// Fill the finding `data` in from its serialized Sigma `rule`: the first
+/// reference as `finding_info.src_url`, the rule as `finding_info.analytic`,
+/// its ATT&CK tags as `finding_info.attacks` and its false positives as
+/// `unmapped.falsepositives`. What the rule's conversion already set is kept.
+fn describe_rule(data: &mut Value, rule: &Value) {
+    let info = &mut data["finding_info"];
+    if let Some(url) = rule["references"].get(0).and_then(|r| r.as_str()) {
+        set_default(info, "src_url", json!(url));
+    }
+
+    let analytic = &mut info["analytic"];
+    if let Some(title) = rule["title"].as_str() {
+        set_default(analytic, "name", json!(title));
+    }
+    // Sigma rules have no version; the last change dates them
+    if let Some(version) = rule["modified"].as_str().or(rule["date"].as_str()) {
+        set_default(analytic, "version", json!(version));
+    }
+    set_default(analytic, "type", json!("Rule"));
+    set_default(analytic, "type_id", json!(1));
+
+    let attacks = attack::attacks(
+        rule["tags"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|t| t.as_str()),
+    );
+    if !attacks.is_empty() {
+        set_default(info, "attacks", json!(attacks));
+    }
+
+    if let Some(falsepositives) = rule["falsepositives"].as_array().filter(|f| !f.is_empty()) {
+        set_default(
+            &mut data["unmapped"],
+            "falsepositives",
+            json!(falsepositives),
+        );
+    }
+}
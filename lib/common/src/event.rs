@@ -1,14 +1,40 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
 
-use serde_json::Value;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
 use sigmars::event::{Event as SigmaEvent, LogSource, RefEvent as SigmaRefEvent};
+use tokio::sync::oneshot;
 use uuid::Uuid;
 
-#[derive(Clone, Debug)]
+use crate::prelude::TENANT_LABEL_PREFIX;
+
+/// An event flowing through the pipeline.
+///
+/// Serializes as `{"id", "time", "data", "metadata"}`, with `time` as an
+/// RFC 3339 string or null; see [`Event::json_schema`]. The acknowledgement
+/// handle is process-local and never serialized.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Event {
     pub id: Uuid,
+    /// When the event happened, if known; see [`Event::time_of`]
+    #[serde(default)]
+    pub time: Option<DateTime<Utc>>,
     pub data: Value,
+    #[serde(default)]
     pub metadata: HashMap<String, Value>,
+    /// Completion handle of the received batch, when its sender waits for
+    /// the events to be stored
+    #[serde(skip)]
+    pub ack: Option<Arc<Ack>>,
 }
 impl Default for Event {
     fn default() -> Self {
@@ -16,15 +42,175 @@ impl Default for Event {
             id: Uuid::now_v7(),
             data: Value::default(),
             metadata: HashMap::default(),
+            time: None,
+            ack: None,
+        }
+    }
+}
+
+impl Event {
+    /// Parse an event in its wire format. `time` and `metadata` may be
+    /// omitted; unknown fields and mistyped values are rejected.
+    pub fn from_json_bytes(bytes: &[u8]) -> serde_json::Result<Self> {
+        serde_json::from_slice(bytes)
+    }
+
+    /// JSON Schema of the wire format
+    pub fn json_schema() -> Value {
+        json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "title": "Event",
+            "type": "object",
+            "properties": {
+                "id": {"type": "string", "format": "uuid"},
+                "time": {"type": ["string", "null"], "format": "date-time"},
+                "data": {},
+                "metadata": {"type": "object"}
+            },
+            "required": ["id", "data"],
+            "additionalProperties": false
+        })
+    }
+
+    /// The OCSF `time` field of `data`: epoch milliseconds or an RFC 3339
+    /// string
+    pub fn time_of(data: &Value) -> Option<DateTime<Utc>> {
+        match data.get("time")? {
+            Value::Number(ms) => DateTime::from_timestamp_millis(ms.as_i64()?),
+            Value::String(s) => DateTime::parse_from_rfc3339(s)
+                .ok()
+                .map(|t| t.with_timezone(&Utc)),
+            _ => None,
+        }
+    }
+
+    /// Tenant of the source the event came from: its `tenant` metadata, or
+    /// the tenant label in its OCSF `metadata.labels` once stored
+    pub fn tenant(&self) -> Option<&str> {
+        self.metadata
+            .get("tenant")
+            .and_then(|t| t.as_str())
+            .or_else(|| {
+                self.data["metadata"]["labels"]
+                    .as_array()?
+                    .iter()
+                    .find_map(|l| l.as_str()?.strip_prefix(TENANT_LABEL_PREFIX))
+            })
+    }
+}
+
+/// The `metadata.labels` entry naming `tenant`
+pub fn tenant_label(tenant: &str) -> String {
+    format!("{}{}", TENANT_LABEL_PREFIX, tenant)
+}
+
+/// Add `tenant`'s label to the OCSF `metadata.labels` of `data`, an object,
+/// unless it's there already
+pub fn label_tenant(data: &mut Value, tenant: &str) {
+    if !data.is_object() || data.get("metadata").is_some_and(|m| !m.is_object()) {
+        return;
+    }
+    let label = Value::String(tenant_label(tenant));
+    let labels = &mut data["metadata"]["labels"];
+    match labels.as_array_mut() {
+        Some(labels) if labels.contains(&label) => {}
+        Some(labels) => labels.push(label),
+        None => *labels = Value::Array(vec![label]),
+    }
+}
+
+/// OCSF logger for the Vector agent an event was received from, from its
+/// `ingest_peer` and `ingest_agent` metadata
+pub fn ingest_logger(metadata: &HashMap<String, Value>) -> Option<Value> {
+    let peer = metadata
+        .get("ingest_peer")
+        .and_then(|p| p.as_str())
+        .and_then(|p| p.parse::<SocketAddr>().ok());
+    let agent = metadata.get("ingest_agent").and_then(|a| a.as_str());
+    if peer.is_none() && agent.is_none() {
+        return None;
+    }
+    let mut logger = json!({"name": "vector"});
+    if let Some(peer) = peer {
+        logger["device"] = json!({"ip": peer.ip().to_string()});
+    }
+    if let Some(agent) = agent {
+        logger["uid"] = json!(agent);
+    }
+    Some(logger)
+}
+
+/// Add `logger` to the OCSF `metadata.loggers` of `data`, an object, unless
+/// it's there already
+pub fn add_logger(data: &mut Value, logger: Value) {
+    if !data.is_object() || data.get("metadata").is_some_and(|m| !m.is_object()) {
+        return;
+    }
+    let loggers = &mut data["metadata"]["loggers"];
+    match loggers.as_array_mut() {
+        Some(loggers) if loggers.contains(&logger) => {}
+        Some(loggers) => loggers.push(logger),
+        None => *loggers = Value::Array(vec![logger]),
+    }
+}
+
+/// Acknowledgement of a received batch, shared by its events.
+///
+/// Resolves `true` once every event has been stored, or `false` as soon as
+/// one couldn't be. If all handles are dropped first the receiver sees the
+/// channel close, which callers treat as a failure.
+#[derive(Debug)]
+pub struct Ack {
+    pending: AtomicUsize,
+    tx: Mutex<Option<oneshot::Sender<bool>>>,
+}
+
+impl Ack {
+    /// Handle for a batch of `events`, and the receiver of its outcome
+    pub fn new(events: usize) -> (Arc<Self>, oneshot::Receiver<bool>) {
+        let (tx, rx) = oneshot::channel();
+        let ack = Arc::new(Ack {
+            pending: AtomicUsize::new(events),
+            tx: Mutex::new(Some(tx)),
+        });
+        if events == 0 {
+            ack.resolve(true);
+        }
+        (ack, rx)
+    }
+
+    fn resolve(&self, stored: bool) {
+        if let Some(tx) = self.tx.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            tx.send(stored).ok();
         }
     }
+
+    /// Mark `events` of the batch as stored
+    pub fn complete(&self, events: usize) {
+        let before = self
+            .pending
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                Some(n.saturating_sub(events))
+            })
+            .unwrap_or_default();
+        if before > 0 && before <= events {
+            self.resolve(true);
+        }
+    }
+
+    /// Mark the batch as not stored
+    pub fn fail(&self) {
+        self.resolve(false);
+    }
 }
 impl From<Value> for Event {
     fn from(data: Value) -> Self {
         Event {
             id: Uuid::now_v7(),
+            time: Event::time_of(&data),
             data,
             metadata: HashMap::new(),
+            ack: None,
         }
     }
 }
@@ -33,8 +219,10 @@ impl From<(Value, HashMap<String, Value>)> for Event {
     fn from(data: (Value, HashMap<String, Value>)) -> Self {
         Event {
             id: Uuid::now_v7(),
+            time: Event::time_of(&data.0),
             data: data.0,
             metadata: data.1,
+            ack: None,
         }
     }
 }
@@ -45,6 +233,8 @@ impl From<&Value> for Event {
             id: Uuid::now_v7(),
             data: data.clone(),
             metadata: HashMap::new(),
+            time: Event::time_of(data),
+            ack: None,
         }
     }
 }
@@ -60,8 +250,10 @@ impl From<SigmaEvent> for Event {
 
         Event {
             id,
+            time: Event::time_of(&event.data),
             data: event.data,
             metadata: HashMap::new(),
+            ack: None,
         }
     }
 }
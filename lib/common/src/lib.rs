@@ -1,5 +1,11 @@
 use serde_json::{Map, Value};
+pub mod attack;
+pub mod detection;
 pub mod event;
+pub mod lists;
+pub mod logging;
+pub mod metrics;
+pub mod severity;
 
 pub mod prelude;
 
@@ -11,3 +17,11 @@ pub enum SysMessage {
     Reload,
     Shutdown,
 }
+
+/// Compare without stopping at the first difference; only the length leaks
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests;
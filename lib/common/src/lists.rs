@@ -0,0 +1,140 @@
+//! Reference lists (known-bad IPs, service accounts, ...) for detection rules.
+//!
+//! Before an event is evaluated, each of its string and number values is
+//! looked up in every list. The lists that hit are added to the evaluated
+//! data, and to the event metadata, as `list_matches`, mapping each list name
+//! to the first value found in it, so a rule can condition on a list with:
+//!
+//! ```yaml
+//! detection:
+//!   selection:
+//!     list_matches.known_bad_ips: '*'
+//! ```
+//!
+//! String lists match whole values, ignoring case as Sigma does; CIDR lists
+//! match addresses inside any of their ranges or plain addresses.
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap, HashSet},
+    net::IpAddr,
+    str::FromStr,
+    sync::Arc,
+};
+
+use arc_swap::ArcSwap;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Compiled reference lists by name.
+///
+/// Shared between the API (which edits them) and the detection engine
+/// (which matches events against them).
+pub type ReferenceLists = Arc<ArcSwap<HashMap<String, ListMatcher>>>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListType {
+    #[default]
+    String,
+    Cidr,
+}
+
+impl FromStr for ListType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "string" => Ok(ListType::String),
+            "cidr" => Ok(ListType::Cidr),
+            _ => Err(format!("invalid list type: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ListMatcher {
+    /// Lowercased items
+    String(HashSet<String>),
+    /// Single addresses are kept apart from ranges so they're a lookup
+    Cidr {
+        hosts: HashSet<IpAddr>,
+        nets: Vec<IpNet>,
+    },
+}
+
+impl ListMatcher {
+    /// Compile `items`, failing on the first CIDR item that isn't a range or
+    /// an address
+    pub fn new(kind: ListType, items: &[String]) -> Result<Self, String> {
+        match kind {
+            ListType::String => Ok(ListMatcher::String(
+                items.iter().map(|item| item.to_lowercase()).collect(),
+            )),
+            ListType::Cidr => {
+                let mut hosts = HashSet::new();
+                let mut nets = Vec::new();
+                for item in items {
+                    let item = item.trim();
+                    match (item.parse::<IpNet>(), item.parse::<IpAddr>()) {
+                        (Ok(net), _) if net.prefix_len() == net.max_prefix_len() => {
+                            hosts.insert(net.addr());
+                        }
+                        (Ok(net), _) => nets.push(net.trunc()),
+                        (_, Ok(addr)) => {
+                            hosts.insert(addr);
+                        }
+                        _ => return Err(format!("invalid CIDR range or address: {}", item)),
+                    }
+                }
+                Ok(ListMatcher::Cidr { hosts, nets })
+            }
+        }
+    }
+
+    pub fn matches(&self, value: &str) -> bool {
+        self.contains(&value.to_lowercase(), value.trim().parse().ok())
+    }
+
+    /// [`ListMatcher::matches`] for a value already lowercased and parsed
+    fn contains(&self, lowercase: &str, addr: Option<IpAddr>) -> bool {
+        match (self, addr) {
+            (ListMatcher::String(items), _) => items.contains(lowercase),
+            (ListMatcher::Cidr { hosts, nets }, Some(addr)) => {
+                hosts.contains(&addr) || nets.iter().any(|net| net.contains(&addr))
+            }
+            (ListMatcher::Cidr { .. }, None) => false,
+        }
+    }
+}
+
+/// Every list with a value of `data` in it, and the first such value
+pub fn list_matches(lists: &HashMap<String, ListMatcher>, data: &Value) -> BTreeMap<String, Value> {
+    let mut matched = BTreeMap::new();
+    if !lists.is_empty() {
+        collect(lists, data, &mut matched);
+    }
+    matched
+}
+
+fn collect(
+    lists: &HashMap<String, ListMatcher>,
+    value: &Value,
+    matched: &mut BTreeMap<String, Value>,
+) {
+    let s = match value {
+        Value::Object(map) => return map.values().for_each(|v| collect(lists, v, matched)),
+        Value::Array(values) => return values.iter().for_each(|v| collect(lists, v, matched)),
+        Value::String(s) => Cow::Borrowed(s.as_str()),
+        Value::Number(n) => Cow::Owned(n.to_string()),
+        Value::Bool(_) | Value::Null => return,
+    };
+    let lowercase = s.to_lowercase();
+    let addr = s.trim().parse::<IpAddr>().ok();
+    for (name, list) in lists {
+        if !matched.contains_key(name) && list.contains(&lowercase, addr) {
+            matched.insert(name.clone(), value.clone());
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Log output for the StrIEM binaries.
+//!
+//! [`init`] installs a `tracing` subscriber built from a [`LoggingConfig`],
+//! which also receives records from the `log` crate. Text is the
+//! human-readable format; JSON writes one object per line with `timestamp`,
+//! `level`, `target`, `message` and the event's fields, so StrIEM's own logs
+//! can be ingested without parsing.
+//!
+//! # Example
+//! ```yaml
+//! logging:
+//!   format: json
+//!   level: info
+//!   modules:
+//!     striem_storage: debug
+//!     tower_http: warn
+//!   file:
+//!     path: /var/log/striem/striem.log
+//!     max_bytes: 104857600
+//!     max_files: 5
+//! ```
+//!
+//! The filter is, in order of precedence: the filter passed to [`init`]
+//! (`--log-level`), `RUST_LOG`, then `level` and `modules`. It can be
+//! replaced while running with [`set_filter`], e.g. to turn on debug logging
+//! for a while.
+
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{
+    EnvFilter, Layer, Registry, fmt::writer::BoxMakeWriter, layer::SubscriberExt, reload,
+    util::SubscriberInitExt,
+};
+
+use crate::prelude::*;
+
+const LEVEL: fn() -> String = || DEFAULT_LOG_LEVEL.to_string();
+const MAX_BYTES: fn() -> u64 = || DEFAULT_LOG_FILE_MAX_BYTES;
+const MAX_FILES: fn() -> usize = || DEFAULT_LOG_FILE_MAX_FILES;
+
+/// The installed filter, once [`init`] has run
+static FILTER: OnceLock<ActiveFilter> = OnceLock::new();
+
+/// Handle to swap the filter of the installed subscriber, and the
+/// directives it was built from
+struct ActiveFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    directives: Mutex<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Log file, rotated by size
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogFileConfig {
+    pub path: PathBuf,
+    /// Size at which the file is rotated to `<path>.1`
+    #[serde(default = "MAX_BYTES")]
+    pub max_bytes: u64,
+    /// Rotated files kept, `<path>.1` being the newest
+    #[serde(default = "MAX_FILES")]
+    pub max_files: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Default level: `error`, `warn`, `info`, `debug` or `trace`
+    #[serde(default = "LEVEL")]
+    pub level: String,
+    /// Levels for modules, by target, e.g. `striem_storage: debug`
+    #[serde(default)]
+    pub modules: BTreeMap<String, String>,
+    /// Write to this file instead of stderr
+    pub file: Option<LogFileConfig>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            format: LogFormat::default(),
+            level: LEVEL(),
+            modules: BTreeMap::new(),
+            file: None,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// `level` and `modules` as an `EnvFilter` directive string
+    pub fn directives(&self) -> String {
+        std::iter::once(self.level.clone())
+            .chain(
+                self.modules
+                    .iter()
+                    .map(|(module, level)| format!("{}={}", module, level)),
+            )
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// A log file that's moved aside once it reaches `max_bytes`
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    pub fn open(config: &LogFileConfig) -> io::Result<Self> {
+        if let Some(dir) = config.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = Self::append(&config.path)?;
+        Ok(RotatingFile {
+            path: config.path.clone(),
+            size: file.metadata()?.len(),
+            file,
+            max_bytes: config.max_bytes,
+            max_files: config.max_files,
+        })
+    }
+
+    fn append(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    /// Shift `<path>.N` to `<path>.N+1`, dropping the oldest, and start a
+    /// new file
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated(n + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated(1))?;
+            self.file = Self::append(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Install the global subscriber for `config`; `filter`, when given,
+/// replaces both `RUST_LOG` and the configured levels
+pub fn init(
+    config: &LoggingConfig,
+    filter: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let directives = match filter {
+        Some(filter) => filter.to_string(),
+        None => std::env::var("RUST_LOG").unwrap_or_else(|_| config.directives()),
+    };
+    let (filter, handle) = reload::Layer::new(EnvFilter::try_new(&directives)?);
+
+    let (writer, ansi) = match &config.file {
+        Some(file) => (
+            BoxMakeWriter::new(Mutex::new(RotatingFile::open(file)?)),
+            false,
+        ),
+        None => (BoxMakeWriter::new(io::stderr), true),
+    };
+    let layer: Box<dyn Layer<Registry> + Send + Sync> = match config.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(ansi)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(false)
+            .with_writer(writer)
+            .boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(layer.with_filter(filter))
+        .try_init()?;
+    FILTER
+        .set(ActiveFilter {
+            handle,
+            directives: Mutex::new(directives),
+        })
+        .ok();
+    Ok(())
+}
+
+/// Directives of the filter in effect, `None` before [`init`]
+pub fn filter() -> Option<String> {
+    let active = FILTER.get()?;
+    Some(
+        active
+            .directives
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone(),
+    )
+}
+
+/// Replace the filter in effect with `directives`, e.g. `debug` or
+/// `info,striem_storage=debug`, returning the directives it replaced
+pub fn set_filter(directives: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let filter = EnvFilter::try_new(directives)?;
+    let active = FILTER.get().ok_or("logging is not initialized")?;
+    let mut current = active.directives.lock().unwrap_or_else(|e| e.into_inner());
+    active.handle.reload(filter)?;
+    // records from the `log` crate are capped at the level logging started with
+    log::set_max_level(match LevelFilter::current().into_level() {
+        None => log::LevelFilter::Off,
+        Some(tracing::Level::ERROR) => log::LevelFilter::Error,
+        Some(tracing::Level::WARN) => log::LevelFilter::Warn,
+        Some(tracing::Level::INFO) => log::LevelFilter::Info,
+        Some(tracing::Level::DEBUG) => log::LevelFilter::Debug,
+        Some(tracing::Level::TRACE) => log::LevelFilter::Trace,
+    });
+    Ok(std::mem::replace(&mut *current, directives.to_string()))
+}
@@ -0,0 +1,385 @@
+//! Process-wide metrics, rendered in the Prometheus text format.
+//!
+//! A deliberately small registry: counters, gauges and summaries (sum and count)
+//! keyed by metric name and label set. Every metric must be listed in
+//! [`METRICS`] with its help text so names stay stable and discoverable:
+//!
+//! - `striem_storage_events_total{class}` - events written to Parquet
+//! - `striem_storage_conversion_failures_total{class}` - events dropped
+//!   because they didn't fit the class schema
+//! - `striem_storage_conversion_errors_total{class,field,kind}` - values
+//!   nulled or events skipped during conversion, by field and error kind
+//! - `striem_storage_files_total{class}` - Parquet files finalized
+//! - `striem_storage_bytes_total{class}` - bytes in finalized files
+//! - `striem_storage_rotation_duration_seconds{class}` - time to finalize
+//!   a file on rotation
+//! - `striem_storage_queue_depth{class}` - batches waiting in a class's
+//!   write queue
+//! - `striem_storage_dropped_events_total{class}` - events dropped because
+//!   a class's write queue was full
+//! - `striem_storage_sampled_events_total{class}` - events not stored
+//!   because of `storage.sampling`
+//! - `striem_broadcast_lagged_total{subscriber}` - broadcast messages a
+//!   subscriber missed by falling behind
+//! - `striem_vector_client_reconnects_total` - reconnections to the
+//!   downstream Vector after a failed push
+//! - `striem_vector_client_dropped_batches_total` - batches not forwarded to
+//!   the downstream Vector
+//! - `striem_vector_client_events_total` - findings forwarded to the
+//!   downstream Vector
+//! - `striem_vector_server_events_total{type}` - events received from
+//!   Vector, by type (`log`, `metric`, `trace`)
+//! - `striem_vector_server_dropped_events_total{type}` - received events
+//!   that were not processed
+//! - `striem_vector_server_source_events_total{source_id}` - log events
+//!   received from Vector, by Vector source component
+//! - `striem_vector_server_limited_events_total{source_id,action}` - log
+//!   events over `input.vector.limits`, `delayed` or `rejected`
+//! - `striem_vector_server_subscribers` - receivers of the Vector server's
+//!   event channel
+//! - `striem_vector_server_queued_batches` - batches in the Vector server's
+//!   event channel not yet received by its slowest subscriber
+//! - `striem_http_output_events_total` - findings delivered to the HTTP
+//!   output
+//! - `striem_http_output_retries_total` - retried HTTP output requests
+//! - `striem_http_output_dropped_batches_total` - batches the HTTP output
+//!   gave up on
+//! - `striem_http_ingest_events_total` - events received by the HTTP
+//!   listener
+//! - `striem_http_ingest_rejected_requests_total{status}` - requests the
+//!   HTTP listener refused
+//! - `striem_last_event_timestamp_seconds` - Unix time the last event was
+//!   received, from Vector or the HTTP listener
+//! - `striem_detection_events_total` - events evaluated against the rules
+//! - `striem_detection_evaluation_duration_seconds` - time to evaluate one
+//!   event
+//! - `striem_detection_findings_total` - findings emitted, after
+//!   deduplication
+//! - `striem_api_request_duration_seconds{method,route,status}` - API
+//!   request latency
+//! - `striem_api_db_connections{state}` - DuckDB pool connections, `idle`
+//!   or `active`
+//! - `striem_api_db_max_connections` - DuckDB pool size
+//! - `striem_playbook_runs_total{outcome}` - actions started by playbooks,
+//!   by outcome (`success`, `failure`, `cooldown`)
+//! - `striem_vector_provisions_total{outcome}` - Vector configurations
+//!   written and reloaded, by outcome (`success`, `failure`)
+//!
+//! Values owned elsewhere can be sampled at scrape time with
+//! [`register_collector`].
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{LazyLock, Mutex},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Counter,
+    Gauge,
+    Summary,
+}
+
+/// Name, type and help text of every metric
+pub const METRICS: &[(&str, Kind, &str)] = &[
+    (
+        "striem_storage_events_total",
+        Kind::Counter,
+        "Events written to Parquet storage",
+    ),
+    (
+        "striem_storage_conversion_failures_total",
+        Kind::Counter,
+        "Events dropped because they could not be converted to the class schema",
+    ),
+    (
+        "striem_storage_conversion_errors_total",
+        Kind::Counter,
+        "Values nulled or events skipped while converting to the class schema",
+    ),
+    (
+        "striem_storage_files_total",
+        Kind::Counter,
+        "Parquet files finalized",
+    ),
+    (
+        "striem_storage_bytes_total",
+        Kind::Counter,
+        "Bytes in finalized Parquet files",
+    ),
+    (
+        "striem_storage_rotation_duration_seconds",
+        Kind::Summary,
+        "Time taken to finalize a Parquet file on rotation",
+    ),
+    (
+        "striem_storage_queue_depth",
+        Kind::Gauge,
+        "Batches waiting in a class's write queue",
+    ),
+    (
+        "striem_storage_dropped_events_total",
+        Kind::Counter,
+        "Events dropped because a class's write queue was full",
+    ),
+    (
+        "striem_storage_sampled_events_total",
+        Kind::Counter,
+        "Events not stored because of storage sampling",
+    ),
+    (
+        "striem_broadcast_lagged_total",
+        Kind::Counter,
+        "Broadcast messages missed by a subscriber that fell behind",
+    ),
+    (
+        "striem_vector_client_reconnects_total",
+        Kind::Counter,
+        "Reconnections to the downstream Vector after a failed push",
+    ),
+    (
+        "striem_vector_client_dropped_batches_total",
+        Kind::Counter,
+        "Batches not forwarded to the downstream Vector",
+    ),
+    (
+        "striem_vector_client_events_total",
+        Kind::Counter,
+        "Findings forwarded to the downstream Vector",
+    ),
+    (
+        "striem_vector_server_events_total",
+        Kind::Counter,
+        "Events received from Vector",
+    ),
+    (
+        "striem_vector_server_dropped_events_total",
+        Kind::Counter,
+        "Events received from Vector that were not processed",
+    ),
+    (
+        "striem_vector_server_source_events_total",
+        Kind::Counter,
+        "Log events received from Vector, by Vector source component",
+    ),
+    (
+        "striem_vector_server_limited_events_total",
+        Kind::Counter,
+        "Log events from Vector held or rejected by rate limits, by source and action",
+    ),
+    (
+        "striem_vector_server_subscribers",
+        Kind::Gauge,
+        "Receivers of the Vector server's event channel",
+    ),
+    (
+        "striem_vector_server_queued_batches",
+        Kind::Gauge,
+        "Batches in the Vector server's event channel not yet received by every subscriber",
+    ),
+    (
+        "striem_http_output_events_total",
+        Kind::Counter,
+        "Findings delivered to the HTTP output",
+    ),
+    (
+        "striem_http_output_retries_total",
+        Kind::Counter,
+        "HTTP output requests retried after a failure",
+    ),
+    (
+        "striem_http_output_dropped_batches_total",
+        Kind::Counter,
+        "Batches the HTTP output gave up delivering",
+    ),
+    (
+        "striem_http_ingest_events_total",
+        Kind::Counter,
+        "Events received by the HTTP listener",
+    ),
+    (
+        "striem_http_ingest_rejected_requests_total",
+        Kind::Counter,
+        "Requests refused by the HTTP listener",
+    ),
+    (
+        "striem_last_event_timestamp_seconds",
+        Kind::Gauge,
+        "Unix time the last event was received",
+    ),
+    (
+        "striem_detection_events_total",
+        Kind::Counter,
+        "Events evaluated against the detection rules",
+    ),
+    (
+        "striem_detection_evaluation_duration_seconds",
+        Kind::Summary,
+        "Time taken to evaluate one event against the detection rules",
+    ),
+    (
+        "striem_detection_findings_total",
+        Kind::Counter,
+        "Detection findings emitted, after deduplication",
+    ),
+    (
+        "striem_api_request_duration_seconds",
+        Kind::Summary,
+        "API request latency",
+    ),
+    (
+        "striem_api_db_connections",
+        Kind::Gauge,
+        "DuckDB pool connections, by state",
+    ),
+    (
+        "striem_api_db_max_connections",
+        Kind::Gauge,
+        "Maximum connections in the DuckDB pool",
+    ),
+    (
+        "striem_playbook_runs_total",
+        Kind::Counter,
+        "Playbook matches, by outcome; cooldown means the action was not run",
+    ),
+    (
+        "striem_vector_provisions_total",
+        Kind::Counter,
+        "Vector configurations written and reloaded, by outcome",
+    ),
+];
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Value {
+    sum: f64,
+    count: u64,
+}
+
+static REGISTRY: LazyLock<Mutex<BTreeMap<(&'static str, String), Value>>> =
+    LazyLock::new(Default::default);
+
+type Collector = Box<dyn Fn() + Send + Sync>;
+
+static COLLECTORS: LazyLock<Mutex<Vec<Collector>>> = LazyLock::new(Default::default);
+
+/// Run `collect` before every [`render`], typically to [`set`] gauges from
+/// state owned elsewhere.
+pub fn register_collector(collect: impl Fn() + Send + Sync + 'static) {
+    COLLECTORS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(Box::new(collect));
+}
+
+/// `{k="v",...}` with values escaped per the text format
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels = labels
+        .iter()
+        .map(|(k, v)| {
+            let v = v
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", k, v)
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{{{}}}", labels)
+}
+
+fn series<T>(
+    name: &'static str,
+    labels: &[(&str, &str)],
+    update: impl FnOnce(&mut Value) -> T,
+) -> T {
+    debug_assert!(
+        METRICS.iter().any(|(n, _, _)| *n == name),
+        "unregistered metric {}",
+        name
+    );
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    update(registry.entry((name, render_labels(labels))).or_default())
+}
+
+fn record(name: &'static str, labels: &[(&str, &str)], value: f64) {
+    series(name, labels, |entry| {
+        entry.sum += value;
+        entry.count += 1;
+    })
+}
+
+/// Add `n` to a counter
+pub fn increment(name: &'static str, labels: &[(&str, &str)], n: u64) {
+    record(name, labels, n as f64);
+}
+
+/// Set a gauge to `value`
+pub fn set(name: &'static str, labels: &[(&str, &str)], value: f64) {
+    series(name, labels, |entry| {
+        entry.sum = value;
+        entry.count = 1;
+    })
+}
+
+/// Set a gauge to the current Unix time
+pub fn set_now(name: &'static str, labels: &[(&str, &str)]) {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    set(name, labels, now.as_secs_f64());
+}
+
+/// Record one observation of a summary
+pub fn observe(name: &'static str, labels: &[(&str, &str)], value: f64) {
+    record(name, labels, value);
+}
+
+/// Current value of a counter or gauge, or the sum of a summary
+pub fn value(name: &'static str, labels: &[(&str, &str)]) -> Option<f64> {
+    REGISTRY
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&(name, render_labels(labels)))
+        .map(|v| v.sum)
+}
+
+/// All recorded metrics in the Prometheus text exposition format
+pub fn render() -> String {
+    for collect in COLLECTORS.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        collect();
+    }
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    let mut out = String::new();
+    for (name, kind, help) in METRICS {
+        let series = registry
+            .range((*name, String::new())..)
+            .take_while(|((n, _), _)| n == name);
+        let mut header = false;
+        for ((_, labels), value) in series {
+            if !header {
+                let kind = match kind {
+                    Kind::Counter => "counter",
+                    Kind::Gauge => "gauge",
+                    Kind::Summary => "summary",
+                };
+                writeln!(out, "# HELP {} {}", name, help).ok();
+                writeln!(out, "# TYPE {} {}", name, kind).ok();
+                header = true;
+            }
+            match kind {
+                Kind::Counter | Kind::Gauge => {
+                    writeln!(out, "{}{} {}", name, labels, value.sum).ok();
+                }
+                Kind::Summary => {
+                    writeln!(out, "{}_sum{} {}", name, labels, value.sum).ok();
+                    writeln!(out, "{}_count{} {}", name, labels, value.count).ok();
+                }
+            }
+        }
+    }
+    out
+}
@@ -4,6 +4,73 @@ pub const DEFAULT_VECTOR_HTTP_LISTEN_PORT: u16 = 6660;
 pub const DEFAULT_VECTOR_API_LISTEN_PORT: u16 = 6666;
 
 pub const DEFAULT_STRIEM_LISTEN_PORT: u16 = 9000;
+pub const DEFAULT_VECTOR_SERVER_CHANNEL_CAPACITY: usize = 256;
+pub const DEFAULT_VECTOR_SERVER_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+pub const DEFAULT_VECTOR_ACK_TIMEOUT_SECS: u64 = 30;
+pub const DEFAULT_INPUT_LIMIT_MAX_DELAY_MS: u64 = 1000;
+pub const DEFAULT_VECTOR_AGENT_ID_HEADER: &str = "x-striem-agent-id";
+pub const DEFAULT_VECTOR_PROVISION_DEBOUNCE_MS: u64 = 2000;
+pub const VECTOR_PROVISION_HEALTH_TIMEOUT_SECS: u64 = 5;
 
 pub const DEFAULT_API_LISTEN_PORT: u16 = 8080;
 pub const MCP_REFRESH_INTERVAL_SECS: u64 = 300;
+pub const DEFAULT_MCP_TIMEOUT_SECS: u64 = 5;
+pub const DEFAULT_MCP_RETRY_SECS: u64 = 30;
+
+pub const HEALTH_CHECK_TIMEOUT_MS: u64 = 100;
+pub const HEALTH_READY_CACHE_MS: u64 = 1000;
+
+pub const CONFIRMATION_TTL_SECS: u64 = 60;
+
+pub const NOTIFICATION_ATTEMPTS: u32 = 3;
+pub const NOTIFICATION_RETRY_BASE_SECS: u64 = 1;
+pub const NOTIFICATION_TIMEOUT_SECS: u64 = 10;
+
+pub const DEFAULT_QUERY_CURSOR_IDLE_SECS: u64 = 300;
+pub const DEFAULT_QUERY_MAX_CURSORS_PER_CLIENT: usize = 4;
+
+pub const DEFAULT_TAIL_EVENTS: usize = 500;
+pub const DEFAULT_TAIL_MAX_BYTES: usize = 16 * 1024 * 1024;
+
+pub const REPLAY_BATCH_SIZE: usize = 1024;
+/// Finished replay jobs listed before the oldest are forgotten
+pub const REPLAY_JOBS_KEPT: usize = 32;
+
+pub const CONFIG_WATCH_DEBOUNCE_MS: u64 = 500;
+
+pub const DEFAULT_SHUTDOWN_GRACE_SECS: u64 = 30;
+
+pub const GEOIP_RELOAD_CHECK_SECS: u64 = 30;
+
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+pub const DEFAULT_LOG_FILE_MAX_BYTES: u64 = 100 * 1024 * 1024;
+pub const DEFAULT_LOG_FILE_MAX_FILES: usize = 5;
+
+/// Prefix of the OCSF `metadata.labels` entry naming an event's tenant
+pub const TENANT_LABEL_PREFIX: &str = "tenant:";
+
+pub const DEFAULT_RETENTION_INTERVAL_SECS: u64 = 3600;
+pub const STORAGE_TEMP_DIR: &str = ".tmp";
+pub const STORAGE_ROTATION_INTERVAL_SECS: u64 = 300;
+pub const DEFAULT_STORAGE_ROTATION_JITTER_SECS: u64 = 30;
+pub const DEFAULT_COMPACTION_MIN_AGE_SECS: u64 = 3600;
+/// Finished compaction jobs listed before the oldest are forgotten
+pub const COMPACTION_JOBS_KEPT: usize = 32;
+
+pub const STORAGE_UPLOAD_ATTEMPTS: u32 = 3;
+pub const STORAGE_UPLOAD_RETRY_BASE_SECS: u64 = 1;
+
+pub const DEFAULT_STORAGE_QUEUE_CAPACITY: usize = 64;
+pub const DEFAULT_STORAGE_QUEUE_TIMEOUT_MS: u64 = 1000;
+
+pub const VECTOR_CLIENT_BUFFER_BATCHES: usize = 256;
+pub const VECTOR_CLIENT_RECONNECT_BASE_MS: u64 = 500;
+pub const VECTOR_CLIENT_RECONNECT_MAX_SECS: u64 = 30;
+
+pub const DEFAULT_HTTP_OUTPUT_MAX_RETRIES: u32 = 5;
+pub const DEFAULT_HTTP_OUTPUT_CONCURRENCY: usize = 4;
+pub const DEFAULT_HTTP_OUTPUT_TIMEOUT_SECS: u64 = 30;
+pub const HTTP_OUTPUT_RETRY_BASE_MS: u64 = 500;
+pub const HTTP_OUTPUT_RETRY_MAX_SECS: u64 = 30;
+
+pub const DEFAULT_HTTP_INGEST_MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
@@ -0,0 +1,83 @@
+//! Sigma rule levels and their OCSF severity equivalents.
+//!
+//! Sigma uses lowercase level names (`informational` .. `critical`) while
+//! OCSF findings carry both a `severity_id` and a `severity` caption.
+//! Variants are ordered so that `Severity::High > Severity::Medium`.
+
+use std::{collections::HashMap, fmt::Display, str::FromStr, sync::Arc};
+
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+
+/// Per-rule severity overrides keyed on Sigma rule id.
+///
+/// Shared between the API (which edits them) and the detection engine
+/// (which applies them to emitted findings).
+pub type LevelOverrides = Arc<ArcSwap<HashMap<String, Severity>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Informational = 1,
+    Low = 2,
+    Medium = 3,
+    High = 4,
+    Critical = 5,
+}
+
+impl Severity {
+    /// OCSF `severity_id`
+    pub fn id(&self) -> u8 {
+        *self as u8
+    }
+
+    /// OCSF `severity` caption
+    pub fn caption(&self) -> &'static str {
+        match self {
+            Severity::Informational => "Informational",
+            Severity::Low => "Low",
+            Severity::Medium => "Medium",
+            Severity::High => "High",
+            Severity::Critical => "Critical",
+        }
+    }
+
+    pub fn from_id(id: u64) -> Option<Self> {
+        match id {
+            1 => Some(Severity::Informational),
+            2 => Some(Severity::Low),
+            3 => Some(Severity::Medium),
+            4 => Some(Severity::High),
+            5 => Some(Severity::Critical),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Informational => write!(f, "informational"),
+            Severity::Low => write!(f, "low"),
+            Severity::Medium => write!(f, "medium"),
+            Severity::High => write!(f, "high"),
+            Severity::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+impl FromStr for Severity {
+    type Err = String;
+
+    /// Accepts Sigma level names and OCSF captions, case-insensitively
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "informational" | "info" => Ok(Severity::Informational),
+            "low" => Ok(Severity::Low),
+            "medium" => Ok(Severity::Medium),
+            "high" => Ok(Severity::High),
+            "critical" => Ok(Severity::Critical),
+            _ => Err(format!("invalid severity: {}", s)),
+        }
+    }
+}
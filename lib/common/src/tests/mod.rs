@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+
+use chrono::{TimeZone, Utc};
+use serde_json::{Map, Value, json};
+
+use crate::event::Event;
+
+/// Deterministic xorshift generator, enough to vary test values
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A random JSON value, nesting at most `depth` levels
+    fn value(&mut self, depth: u32) -> Value {
+        let kinds = if depth == 0 { 5 } else { 7 };
+        match self.next() % kinds {
+            0 => Value::Null,
+            1 => Value::Bool(self.next() % 2 == 0),
+            2 => json!(self.next() as i64),
+            3 => json!((self.next() % 10_000) as f64 / 8.0),
+            4 => Value::String(format!("s{}\n\"{}\"", self.next() % 100, self.next() % 7)),
+            5 => (0..self.next() % 4)
+                .map(|_| self.value(depth - 1))
+                .collect(),
+            _ => Value::Object(self.map(depth - 1)),
+        }
+    }
+
+    fn map(&mut self, depth: u32) -> Map<String, Value> {
+        (0..self.next() % 4)
+            .map(|i| (format!("k{}", i), self.value(depth)))
+            .collect()
+    }
+}
+
+#[test]
+fn event_serde_round_trips() {
+    let mut rng = Rng(0x5eed);
+    for i in 0..500 {
+        let event = Event {
+            time: (i % 3 != 0).then(|| {
+                Utc.timestamp_millis_opt(rng.next() as i64 % 4_000_000_000_000)
+                    .unwrap()
+            }),
+            // objects and everything else: strings, arrays, numbers, null
+            data: rng.value(3),
+            metadata: rng.map(3).into_iter().collect::<HashMap<_, _>>(),
+            ..Default::default()
+        };
+        let bytes = serde_json::to_vec(&event).unwrap();
+        let back = Event::from_json_bytes(&bytes).unwrap();
+        assert_eq!(back.id, event.id);
+        assert_eq!(back.time, event.time);
+        assert_eq!(back.data, event.data);
+        assert_eq!(back.metadata, event.metadata);
+        assert!(back.ack.is_none());
+    }
+}
+
+#[test]
+fn event_wire_format() {
+    let event = Event {
+        time: Some(Utc.timestamp_millis_opt(1_700_000_000_123).unwrap()),
+        metadata: HashMap::from([("source".to_string(), json!({"nested": [1, 2]}))]),
+        ..Event::from(json!("just a string"))
+    };
+    let wire = serde_json::to_value(&event).unwrap();
+    assert_eq!(
+        wire,
+        json!({
+            "id": event.id.to_string(),
+            "time": "2023-11-14T22:13:20.123Z",
+            "data": "just a string",
+            "metadata": {"source": {"nested": [1, 2]}},
+        })
+    );
+    let keys = wire
+        .as_object()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    assert_eq!(keys.len(), 4);
+
+    // time and metadata are optional
+    let minimal = Event::from_json_bytes(
+        br#"{"id": "0192f0c4-3b8a-7cc2-9a4e-5f1d2c3b4a59", "data": {"n": 1}}"#,
+    )
+    .unwrap();
+    assert_eq!(minimal.time, None);
+    assert!(minimal.metadata.is_empty());
+
+    // shape violations are rejected
+    for bad in [
+        r#"{"data": {}}"#,
+        r#"{"id": "not-a-uuid", "data": {}}"#,
+        r#"{"id": "0192f0c4-3b8a-7cc2-9a4e-5f1d2c3b4a59"}"#,
+        r#"{"id": "0192f0c4-3b8a-7cc2-9a4e-5f1d2c3b4a59", "data": {}, "metadata": []}"#,
+        r#"{"id": "0192f0c4-3b8a-7cc2-9a4e-5f1d2c3b4a59", "data": {}, "extra": 1}"#,
+        r#"[]"#,
+    ] {
+        assert!(Event::from_json_bytes(bad.as_bytes()).is_err(), "{}", bad);
+    }
+
+    let schema = Event::json_schema();
+    assert_eq!(schema["required"], json!(["id", "data"]));
+}
+
+#[test]
+fn logging_rotates_by_size() {
+    use std::io::Write;
+
+    use crate::logging::{LogFileConfig, LoggingConfig, RotatingFile};
+
+    let config: LoggingConfig = serde_yaml::from_str(
+        "format: json\nmodules:\n  striem_storage: debug\n  tower_http: warn\n",
+    )
+    .unwrap();
+    assert_eq!(
+        config.directives(),
+        "info,striem_storage=debug,tower_http=warn"
+    );
+
+    let dir = std::env::temp_dir().join(format!("striem-logging-{}", std::process::id()));
+    let path = dir.join("striem.log");
+    let mut file = RotatingFile::open(&LogFileConfig {
+        path: path.clone(),
+        max_bytes: 16,
+        max_files: 2,
+    })
+    .unwrap();
+    for line in [
+        "first line\n",
+        "second line\n",
+        "third line\n",
+        "fourth line\n",
+    ] {
+        file.write_all(line.as_bytes()).unwrap();
+    }
+    file.flush().unwrap();
+
+    let read = |p: &std::path::Path| std::fs::read_to_string(p).unwrap();
+    assert_eq!(read(&path), "fourth line\n");
+    assert_eq!(read(&dir.join("striem.log.1")), "third line\n");
+    assert_eq!(read(&dir.join("striem.log.2")), "second line\n");
+    assert!(!dir.join("striem.log.3").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn logging_filter_changes_at_runtime() {
+    use crate::logging::{self, LogFileConfig, LoggingConfig};
+
+    let dir = std::env::temp_dir().join(format!("striem-filter-{}", std::process::id()));
+    let path = dir.join("striem.log");
+    assert!(logging::set_filter("debug").is_err());
+
+    let config = LoggingConfig {
+        file: Some(LogFileConfig {
+            path: path.clone(),
+            max_bytes: 1 << 20,
+            max_files: 1,
+        }),
+        ..Default::default()
+    };
+    logging::init(&config, Some("info")).unwrap();
+    assert_eq!(logging::filter().as_deref(), Some("info"));
+
+    log::debug!("before the change");
+    assert!(logging::set_filter("striem=loud").is_err());
+    assert_eq!(logging::set_filter("debug").unwrap(), "info");
+    assert_eq!(logging::filter().as_deref(), Some("debug"));
+    log::debug!("after the change");
+    tracing::debug!("from tracing");
+
+    let logged = std::fs::read_to_string(&path).unwrap();
+    assert!(!logged.contains("before the change"));
+    assert!(logged.contains("after the change"));
+    assert!(logged.contains("from tracing"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn list_matches_tags_values_in_lists() {
+    use crate::lists::{ListMatcher, ListType, list_matches};
+
+    let lists = HashMap::from([
+        (
+            "bad_ips".to_string(),
+            ListMatcher::new(
+                ListType::Cidr,
+                &["203.0.113.0/24".to_string(), "2001:db8::1".to_string()],
+            )
+            .unwrap(),
+        ),
+        (
+            "service_accounts".to_string(),
+            ListMatcher::new(ListType::String, &["svc-backup".to_string()]).unwrap(),
+        ),
+        (
+            "ports".to_string(),
+            ListMatcher::new(ListType::String, &["4444".to_string()]).unwrap(),
+        ),
+    ]);
+    assert!(ListMatcher::new(ListType::Cidr, &["not-an-ip".to_string()]).is_err());
+
+    let event = json!({
+        "src_endpoint": {"ip": "198.51.100.1", "port": 4444},
+        "dst_endpoint": {"ip": "203.0.113.9"},
+        "actor": {"user": {"name": "SVC-Backup"}},
+        "observables": [{"value": "2001:db8::1"}],
+    });
+    let matched = list_matches(&lists, &event);
+    assert_eq!(matched.len(), 3);
+    assert_eq!(matched["service_accounts"], json!("SVC-Backup"));
+    assert_eq!(matched["ports"], json!(4444));
+    assert!(
+        matched["bad_ips"] == json!("203.0.113.9") || matched["bad_ips"] == json!("2001:db8::1")
+    );
+
+    assert!(list_matches(&lists, &json!({"user": "svc-backup2", "ip": "203.0.114.1"})).is_empty());
+    assert!(list_matches(&HashMap::new(), &event).is_empty());
+}
+
+#[test]
+fn tenant_is_labelled_once() {
+    use crate::event::label_tenant;
+
+    let mut event = Event::from(json!({"class_uid": 3002, "metadata": {"labels": ["x"]}}));
+    assert_eq!(event.tenant(), None);
+
+    label_tenant(&mut event.data, "emea");
+    label_tenant(&mut event.data, "emea");
+    assert_eq!(
+        event.data["metadata"]["labels"],
+        json!(["x", "tenant:emea"])
+    );
+    assert_eq!(event.tenant(), Some("emea"));
+
+    // metadata wins over the stored label
+    event.metadata.insert("tenant".to_string(), json!("apac"));
+    assert_eq!(event.tenant(), Some("apac"));
+
+    let mut data = json!({"class_uid": 3002});
+    label_tenant(&mut data, "apac");
+    assert_eq!(data["metadata"]["labels"], json!(["tenant:apac"]));
+    let mut data = json!({"metadata": "opaque"});
+    label_tenant(&mut data, "apac");
+    assert_eq!(data, json!({"metadata": "opaque"}));
+}
+
+#[tokio::test]
+async fn findings_describe_their_rule() {
+    use crate::detection::findings;
+
+    let mut rules = sigmars::SigmaCollection::default();
+    let rule: sigmars::SigmaRule = serde_yaml::from_str(
+        r#"
+title: Encoded PowerShell
+id: 00000000-0000-4000-8000-000000000001
+references:
+  - https://attack.mitre.org/techniques/T1059/001/
+  - https://example.com/second
+date: 2024-03-01
+modified: 2025-01-02
+tags:
+  - attack.execution
+  - attack.t1059.001
+  - attack.t1027
+logsource:
+  product: test
+detection:
+  selection:
+    user: admin
+  condition: selection
+falsepositives:
+  - Administrative scripts
+level: high
+"#,
+    )
+    .unwrap();
+    rules.add(rule).unwrap();
+    rules.init(&mut sigmars::MemBackend::new().await).await;
+
+    let event = Event::from((
+        json!({"user": "admin"}),
+        [("logsource".to_string(), json!({"product": "test"}))].into(),
+    ));
+    let ocsf = Default::default();
+    let matches = findings(&rules, &ocsf, &HashMap::new(), &HashMap::new(), &event)
+        .await
+        .unwrap();
+    assert_eq!(matches.len(), 1);
+    let data = &matches[0].1.data;
+
+    let info = &data["finding_info"];
+    assert_eq!(
+        info["src_url"],
+        json!("https://attack.mitre.org/techniques/T1059/001/")
+    );
+    assert_eq!(
+        info["analytic"]["uid"],
+        json!("00000000-0000-4000-8000-000000000001")
+    );
+    assert_eq!(info["analytic"]["name"], json!("Encoded PowerShell"));
+    assert_eq!(info["analytic"]["version"], json!("2025-01-02"));
+    assert_eq!(info["analytic"]["type_id"], json!(1));
+    assert_eq!(
+        info["attacks"],
+        json!([
+            {
+                "tactic": {"uid": "TA0002", "name": "Execution"},
+                "technique": {"uid": "T1059"},
+                "sub_technique": {"uid": "T1059.001"}
+            },
+            {
+                "tactic": {"uid": "TA0002", "name": "Execution"},
+                "technique": {"uid": "T1027"}
+            }
+        ])
+    );
+    assert_eq!(
+        data["unmapped"]["falsepositives"],
+        json!(["Administrative scripts"])
+    );
+}
+
+#[tokio::test]
+async fn vendor_and_ocsf_rules_match_the_same_event() {
+    use crate::detection::{findings, is_ocsf_rule, ocsf_rules};
+
+    let rule = |id: u32, product: &str, field: &str, value: &str| {
+        serde_yaml::from_str::<sigmars::SigmaRule>(&format!(
+            r#"
+title: rule {id}
+id: 00000000-0000-4000-8000-{id:012}
+logsource:
+  product: {product}
+detection:
+  selection:
+    {field}: {value}
+  condition: selection
+level: medium
+"#
+        ))
+        .unwrap()
+    };
+    let mut rules = sigmars::SigmaCollection::default();
+    rules
+        .add(rule(1, "okta", "eventType", "user.session.start"))
+        .unwrap();
+    rules
+        .add(rule(2, "ocsf", "activity_name", "Logon"))
+        .unwrap();
+    // matches the raw event, but written for OCSF
+    rules
+        .add(rule(3, "ocsf", "eventType", "user.session.start"))
+        .unwrap();
+    rules.init(&mut sigmars::MemBackend::new().await).await;
+
+    assert!(is_ocsf_rule(&json!({"logsource": {"product": "OCSF"}})));
+    assert!(is_ocsf_rule(&json!({"taxonomy": "ocsf", "logsource": {}})));
+    assert!(!is_ocsf_rule(&json!({"logsource": {"product": "okta"}})));
+    let ocsf = ocsf_rules(&rules);
+    let mut sorted = ocsf.iter().cloned().collect::<Vec<_>>();
+    sorted.sort();
+    assert_eq!(
+        sorted,
+        [
+            "00000000-0000-4000-8000-000000000002",
+            "00000000-0000-4000-8000-000000000003"
+        ]
+    );
+
+    let raw = json!({"eventType": "user.session.start"});
+    let event = |data: Value| {
+        Event::from((
+            data,
+            [
+                ("logsource".to_string(), json!({"product": "okta"})),
+                ("ocsf".to_string(), json!(true)),
+            ]
+            .into(),
+        ))
+    };
+    let ids = |matches: crate::detection::Matches| {
+        let mut ids = matches.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+        ids.sort();
+        ids
+    };
+
+    let normalized = json!({
+        "class_uid": 3002,
+        "activity_name": "Logon",
+        "raw_data": raw.to_string(),
+    });
+    let matches = findings(
+        &rules,
+        &ocsf,
+        &HashMap::new(),
+        &HashMap::new(),
+        &event(normalized),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        ids(matches),
+        [
+            "00000000-0000-4000-8000-000000000001",
+            "00000000-0000-4000-8000-000000000002"
+        ]
+    );
+
+    // without raw_data OCSF rules still match
+    let dropped = json!({"class_uid": 3002, "activity_name": "Logon"});
+    let matches = findings(
+        &rules,
+        &ocsf,
+        &HashMap::new(),
+        &HashMap::new(),
+        &event(dropped),
+    )
+    .await
+    .unwrap();
+    assert_eq!(ids(matches), ["00000000-0000-4000-8000-000000000002"]);
+}
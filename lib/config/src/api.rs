@@ -1,13 +1,88 @@
+use std::{path::PathBuf, str::FromStr};
+
+use anyhow::{Result, anyhow};
 use serde::{Deserialize, Serialize};
 
-use crate::{HostConfig, StringOrList};
+use crate::HostConfig;
 use striem_common::prelude::*;
 
 const TRUE: fn() -> bool = || true;
+const CURSOR_IDLE_SECS: fn() -> u64 = || DEFAULT_QUERY_CURSOR_IDLE_SECS;
+const MAX_CURSORS_PER_CLIENT: fn() -> usize = || DEFAULT_QUERY_MAX_CURSORS_PER_CLIENT;
+const TAIL_EVENTS: fn() -> usize = || DEFAULT_TAIL_EVENTS;
+const TAIL_MAX_BYTES: fn() -> usize = || DEFAULT_TAIL_MAX_BYTES;
+const MCP_TIMEOUT_SECS: fn() -> u64 = || DEFAULT_MCP_TIMEOUT_SECS;
+const MCP_RETRY_SECS: fn() -> u64 = || DEFAULT_MCP_RETRY_SECS;
+
+/// An MCP server offering response actions: a bare URL, or `{alias, url}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum McpServer {
+    Url(String),
+    Aliased { alias: String, url: String },
+}
 
+/// MCP servers (`api.mcp.url`), one or a list. Action ids are namespaced
+/// by server as `alias/tool`; a bare URL's alias is its host.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MCPConfig {
-    pub url: StringOrList,
+    #[serde(deserialize_with = "one_or_many")]
+    pub url: Vec<McpServer>,
+    /// Seconds any one call to a server, connecting included, may take
+    #[serde(default = "MCP_TIMEOUT_SECS")]
+    pub timeout_secs: u64,
+    /// Seconds to wait before retrying a server that failed
+    #[serde(default = "MCP_RETRY_SECS")]
+    pub retry_secs: u64,
+}
+
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<McpServer>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Helper {
+        One(McpServer),
+        Many(Vec<McpServer>),
+    }
+
+    Ok(match Helper::deserialize(deserializer)? {
+        Helper::One(server) => vec![server],
+        Helper::Many(servers) => servers,
+    })
+}
+
+impl MCPConfig {
+    /// `(alias, url)` of each server. Aliases can't contain `/`, and two
+    /// servers with the same alias, e.g. bare URLs on one host, are an
+    /// error: each needs an explicit `alias` then.
+    pub fn servers(&self) -> Result<Vec<(String, String)>, String> {
+        let mut seen = std::collections::HashSet::<String>::new();
+        self.url
+            .iter()
+            .map(|server| {
+                let (alias, url) = match server {
+                    McpServer::Url(url) => (
+                        url::Url::parse(url)
+                            .ok()
+                            .and_then(|u| u.host_str().map(str::to_string))
+                            .unwrap_or_else(|| "mcp".to_string()),
+                        url,
+                    ),
+                    McpServer::Aliased { alias, url } => (alias.clone(), url),
+                };
+                let alias = alias.replace('/', "-");
+                if !seen.insert(alias.clone()) {
+                    return Err(format!(
+                        "api.mcp servers share the alias '{}'; give them distinct aliases",
+                        alias
+                    ));
+                }
+                Ok((alias, url.clone()))
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
@@ -17,13 +92,279 @@ pub struct UIConfig {
     pub path: Option<String>,
 }
 
+/// Paged query cursors (`POST /api/1/query` with `page_size`)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QueryConfig {
+    /// Seconds a cursor may sit unused before it is dropped
+    #[serde(default = "CURSOR_IDLE_SECS")]
+    pub cursor_idle_secs: u64,
+    /// Open cursors allowed per client address
+    #[serde(default = "MAX_CURSORS_PER_CLIENT")]
+    pub max_cursors_per_client: usize,
+}
+
+impl Default for QueryConfig {
+    fn default() -> Self {
+        QueryConfig {
+            cursor_idle_secs: CURSOR_IDLE_SECS(),
+            max_cursors_per_client: MAX_CURSORS_PER_CLIENT(),
+        }
+    }
+}
+
+/// Access level of an API token; each role includes the ones before it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Queries, alerts and other reads
+    Read,
+    /// Changes to sources, detections, alerts and notifications
+    Write,
+    /// Output destinations and configuration
+    #[default]
+    Admin,
+}
+
+impl FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "read" => Ok(Role::Read),
+            "write" => Ok(Role::Write),
+            "admin" => Ok(Role::Admin),
+            _ => Err(anyhow!("unknown role '{}'", s)),
+        }
+    }
+}
+
+/// An API token: a bare string has the `admin` role
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ApiToken {
+    Token(String),
+    Tagged {
+        token: String,
+        #[serde(default)]
+        role: Role,
+        /// Tenant the token's alerts and event tail are scoped to; it can't query
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tenant: Option<String>,
+    },
+}
+
+impl ApiToken {
+    pub fn token(&self) -> &str {
+        match self {
+            ApiToken::Token(token) | ApiToken::Tagged { token, .. } => token,
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        match self {
+            ApiToken::Token(_) => Role::default(),
+            ApiToken::Tagged { role, .. } => *role,
+        }
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        match self {
+            ApiToken::Token(_) => None,
+            ApiToken::Tagged { tenant, .. } => tenant.as_deref(),
+        }
+    }
+}
+
+/// Bearer token authentication (`api.auth`); without it the API is open
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub tokens: Vec<ApiToken>,
+    /// File of tokens, one per line and optionally followed by a role and
+    /// a tenant; blank lines and lines starting with `#` are skipped
+    pub tokens_file: Option<PathBuf>,
+}
+
+impl AuthConfig {
+    /// Every configured token with its role and tenant, including those in
+    /// `tokens_file`. At least one token is required. Once any token has a
+    /// tenant every `read` and `write` token needs one, so only `admin`
+    /// tokens, which can't have one, see across tenants.
+    fn entries(&self) -> Result<Vec<(String, Role, Option<String>)>> {
+        let mut tokens = self
+            .tokens
+            .iter()
+            .map(|t| {
+                (
+                    t.token().to_string(),
+                    t.role(),
+                    t.tenant().map(str::to_string),
+                )
+            })
+            .collect::<Vec<_>>();
+        if let Some(path) = &self.tokens_file {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|e| anyhow!("failed to read {}: {}", path.display(), e))?;
+            for line in contents.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let mut parts = line.split_whitespace();
+                let token = parts.next().unwrap_or_default().to_string();
+                let role = match parts.next() {
+                    Some(role) => role.parse()?,
+                    None => Role::default(),
+                };
+                tokens.push((token, role, parts.next().map(str::to_string)));
+            }
+        }
+        if tokens.iter().any(|(t, _, _)| t.is_empty()) {
+            return Err(anyhow!("api.auth tokens must not be empty"));
+        }
+        if tokens.is_empty() {
+            return Err(anyhow!("api.auth requires at least one token"));
+        }
+        if tokens
+            .iter()
+            .any(|(_, role, tenant)| *role == Role::Admin && tenant.is_some())
+        {
+            return Err(anyhow!("api.auth admin tokens can't have a tenant"));
+        }
+        if tokens.iter().any(|(_, _, tenant)| tenant.is_some())
+            && tokens
+                .iter()
+                .any(|(_, role, tenant)| *role != Role::Admin && tenant.is_none())
+        {
+            return Err(anyhow!(
+                "api.auth read and write tokens need a tenant once any token has one"
+            ));
+        }
+        Ok(tokens)
+    }
+
+    /// Every configured token with its role, including those in
+    /// `tokens_file`. At least one token is required.
+    pub fn load(&self) -> Result<Vec<(String, Role)>> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .map(|(token, role, _)| (token, role))
+            .collect())
+    }
+
+    /// The tokens scoped to a tenant, with their tenant
+    pub fn tenants(&self) -> Result<Vec<(String, String)>> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .filter_map(|(token, _, tenant)| Some((token, tenant?)))
+            .collect())
+    }
+}
+
+/// HTTPS for the API (`api.tls`); PEM files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Require client certificates signed by this CA (mTLS)
+    pub client_ca: Option<PathBuf>,
+}
+
+/// Swagger UI at `/api/docs`
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct DocsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+/// Prometheus scrape endpoint (`GET /metrics`)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MetricsConfig {
+    #[serde(default = "TRUE")]
+    pub enabled: bool,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig { enabled: true }
+    }
+}
+
+/// Readiness checks (`GET /health/ready`)
+///
+/// # Example
+/// ```yaml
+/// api:
+///   health:
+///     ready_requires_events_within_secs: 3600
+/// ```
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct HealthConfig {
+    /// Report not ready when no event has arrived for this long, so a probe
+    /// can page when ingestion stops
+    pub ready_requires_events_within_secs: Option<u64>,
+}
+
+/// The most recent received events, kept in memory for
+/// `/api/1/events/tail`. Disable it where events mustn't be readable
+/// before they're stored.
+///
+/// ```yaml
+/// api:
+///   tail:
+///     events: 500
+///     max_bytes: 16777216
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TailConfig {
+    #[serde(default = "TRUE")]
+    pub enabled: bool,
+    /// Events kept
+    #[serde(default = "TAIL_EVENTS")]
+    pub events: usize,
+    /// Serialized bytes kept, however few events that is; larger events are
+    /// left out
+    #[serde(default = "TAIL_MAX_BYTES")]
+    pub max_bytes: usize,
+}
+
+impl Default for TailConfig {
+    fn default() -> Self {
+        TailConfig {
+            enabled: true,
+            events: TAIL_EVENTS(),
+            max_bytes: TAIL_MAX_BYTES(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Clone)]
 pub struct ApiConfig {
     pub enabled: bool,
     pub data: Option<String>,
     pub mcp: Option<MCPConfig>,
     pub ui: Option<UIConfig>,
+    /// Flattened, the same shape it's read in
+    #[serde(flatten)]
     pub host: HostConfig,
+    pub query: QueryConfig,
+    pub metrics: MetricsConfig,
+    pub docs: DocsConfig,
+    pub health: HealthConfig,
+    pub tail: TailConfig,
+    pub auth: Option<AuthConfig>,
+    pub tls: Option<TlsConfig>,
+    /// Serve source secrets to Vector as `${STRIEM_SECRET_<source>_<field>}`
+    /// references, with the values at `/vector/env`, rather than inline
+    pub vector_interpolate: bool,
+    /// `vector` binary that `/vector/validate` checks the configuration with
+    pub vector_bin: Option<PathBuf>,
+    /// Seconds a HEC source's previous token stays valid after it's rotated
+    pub hec_token_grace_secs: u64,
+    /// Destructive requests must be repeated with the confirmation token
+    /// the first one returns
+    pub require_confirmation: bool,
 }
 
 impl<'de> Deserialize<'de> for ApiConfig {
@@ -39,6 +380,25 @@ impl<'de> Deserialize<'de> for ApiConfig {
             data: Option<String>,
             mcp: Option<MCPConfig>,
             ui: Option<UIConfig>,
+            #[serde(default)]
+            query: QueryConfig,
+            #[serde(default)]
+            metrics: MetricsConfig,
+            #[serde(default)]
+            docs: DocsConfig,
+            #[serde(default)]
+            health: HealthConfig,
+            #[serde(default)]
+            tail: TailConfig,
+            auth: Option<AuthConfig>,
+            tls: Option<TlsConfig>,
+            #[serde(default)]
+            vector_interpolate: bool,
+            vector_bin: Option<PathBuf>,
+            #[serde(default)]
+            hec_token_grace_secs: u64,
+            #[serde(default)]
+            require_confirmation: bool,
         }
 
         let helper = Helper::deserialize(deserializer)?;
@@ -55,6 +415,17 @@ impl<'de> Deserialize<'de> for ApiConfig {
             data: helper.data,
             mcp: helper.mcp,
             ui: helper.ui,
+            query: helper.query,
+            metrics: helper.metrics,
+            docs: helper.docs,
+            health: helper.health,
+            tail: helper.tail,
+            auth: helper.auth,
+            tls: helper.tls,
+            vector_interpolate: helper.vector_interpolate,
+            vector_bin: helper.vector_bin,
+            hec_token_grace_secs: helper.hec_token_grace_secs,
+            require_confirmation: helper.require_confirmation,
         })
     }
 }
@@ -67,6 +438,17 @@ impl Default for ApiConfig {
             data: None,
             mcp: None,
             ui: Some(UIConfig::default()),
+            query: QueryConfig::default(),
+            metrics: MetricsConfig::default(),
+            docs: DocsConfig::default(),
+            health: HealthConfig::default(),
+            tail: TailConfig::default(),
+            auth: None,
+            tls: None,
+            vector_interpolate: false,
+            vector_bin: None,
+            hec_token_grace_secs: 0,
+            require_confirmation: false,
         }
     }
 }
@@ -0,0 +1,249 @@
+//! Detection engine configuration.
+//!
+//! Accepts the original shorthand (a single rule directory or a list of
+//! directories) as well as a full block with engine options:
+//!
+//! ```yaml
+//! detections:
+//!   paths:
+//!     - ./data/detections
+//!   dedup_window_secs: 60
+//!   dedup_fields: [src_endpoint.ip, actor.user.name]
+//!   dedup_rules:
+//!     5f1abf38-3f1d-4f3a-9f2a-4b1e3b1c7a11: 300
+//!   workers: 4
+//! ```
+//!
+//! A path may instead be a git repository, `git+<url>[@<ref>][#<subdir>]`,
+//! e.g. `git+https://github.com/SigmaHQ/sigma.git@r2024-11-10#rules/windows`.
+//! It's cloned into `{db}/rules-cache/` and rules are loaded from `subdir`
+//! of the checked out `ref` (the remote's default branch without one). The
+//! ref can't contain `/`. With `sync_interval_secs`, repositories are
+//! fetched again on that interval as well as at startup.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::StringOrList;
+
+const DEFAULT_DEDUP_MAX_ENTRIES: fn() -> usize = || 10_000;
+const DEFAULT_WORKERS: fn() -> usize = || {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(4)
+};
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct DetectionsConfig {
+    /// Location of top-level Sigma detection directories
+    pub paths: StringOrList,
+
+    /// Default findings deduplication window; repeats of the same rule
+    /// (and observable key) within the window are rolled up into one finding
+    pub dedup_window_secs: Option<u64>,
+
+    /// Per-rule deduplication windows, overriding `dedup_window_secs`
+    /// (a value of 0 disables deduplication for that rule)
+    pub dedup_rules: HashMap<String, u64>,
+
+    /// Event fields (dot notation) hashed into the deduplication key.
+    /// When empty, findings are keyed on rule id alone
+    pub dedup_fields: Vec<String>,
+
+    /// Upper bound on open deduplication windows; the oldest is evicted when full
+    pub dedup_max_entries: usize,
+
+    /// Tasks evaluating rules in parallel; 1 evaluates in the handler itself.
+    /// Defaults to the available CPUs, at most 4
+    pub workers: usize,
+
+    /// Fetch git rule sources this often; unset only fetches at startup and
+    /// on request
+    pub sync_interval_secs: Option<u64>,
+}
+
+/// Where detection rules are loaded from, one per entry of `paths`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DetectionSource {
+    /// A local rule directory
+    Path(PathBuf),
+    /// A git repository, cloned into the rule cache
+    Git {
+        url: String,
+        /// Branch, tag or commit to check out
+        reference: Option<String>,
+        /// Directory in the repository holding the rules
+        subdir: Option<String>,
+    },
+}
+
+/// FNV-1a, stable across builds so cache directories survive upgrades
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl DetectionSource {
+    pub fn parse(s: &str) -> Self {
+        let Some(rest) = s.strip_prefix("git+") else {
+            return DetectionSource::Path(PathBuf::from(s));
+        };
+        let (rest, subdir) = match rest.split_once('#') {
+            Some((rest, subdir)) if !subdir.is_empty() => (rest, Some(subdir.to_string())),
+            Some((rest, _)) => (rest, None),
+            None => (rest, None),
+        };
+        // an `@` followed by a `/` is user info, not a ref
+        let (url, reference) = match rest.rsplit_once('@') {
+            Some((url, reference)) if !reference.is_empty() && !reference.contains('/') => {
+                (url, Some(reference.to_string()))
+            }
+            _ => (rest, None),
+        };
+        DetectionSource::Git {
+            url: url.to_string(),
+            reference,
+            subdir,
+        }
+    }
+
+    /// Where a git source is cloned under `db`
+    pub fn cache_dir(&self, db: &Path) -> Option<PathBuf> {
+        match self {
+            DetectionSource::Path(_) => None,
+            DetectionSource::Git { url, .. } => {
+                Some(db.join("rules-cache").join(format!("{:016x}", fnv1a(url))))
+            }
+        }
+    }
+
+    /// Short name for the rules of this source: a git repository's name
+    /// (`sigma` for `git+https://github.com/SigmaHQ/sigma.git`) or a
+    /// directory's name
+    pub fn pack(&self) -> String {
+        match self {
+            DetectionSource::Path(path) => path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.display().to_string()),
+            DetectionSource::Git { url, .. } => {
+                let name = url.trim_end_matches('/').rsplit('/').next().unwrap_or(url);
+                name.strip_suffix(".git").unwrap_or(name).to_string()
+            }
+        }
+    }
+
+    /// The directory rules are loaded from
+    pub fn rules_dir(&self, db: &Path) -> PathBuf {
+        match self {
+            DetectionSource::Path(path) => path.clone(),
+            DetectionSource::Git { subdir, .. } => {
+                let dir = self.cache_dir(db).unwrap_or_default();
+                match subdir {
+                    Some(subdir) => dir.join(subdir),
+                    None => dir,
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for DetectionSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DetectionSource::Path(path) => write!(f, "{}", path.display()),
+            DetectionSource::Git { url, .. } => f.write_str(url),
+        }
+    }
+}
+
+impl From<StringOrList> for DetectionsConfig {
+    fn from(paths: StringOrList) -> Self {
+        DetectionsConfig {
+            paths,
+            dedup_window_secs: None,
+            dedup_rules: HashMap::new(),
+            dedup_fields: Vec::new(),
+            dedup_max_entries: DEFAULT_DEDUP_MAX_ENTRIES(),
+            workers: DEFAULT_WORKERS(),
+            sync_interval_secs: None,
+        }
+    }
+}
+
+impl DetectionsConfig {
+    /// The entries of `paths`, in order
+    pub fn sources(&self) -> Vec<DetectionSource> {
+        match &self.paths {
+            StringOrList::String(path) => vec![DetectionSource::parse(path)],
+            StringOrList::List(paths) => paths.iter().map(|p| DetectionSource::parse(p)).collect(),
+        }
+    }
+
+    /// The rule directory uploaded rules are saved to: the only source, if
+    /// it's local
+    pub fn upload_dir(&self) -> Option<PathBuf> {
+        match self.sources().as_slice() {
+            [DetectionSource::Path(path)] => Some(path.clone()),
+            _ => None,
+        }
+    }
+
+    /// Whether any rule has a non-zero deduplication window
+    pub fn dedup_enabled(&self) -> bool {
+        self.dedup_window_secs.is_some_and(|w| w > 0) || self.dedup_rules.values().any(|w| *w > 0)
+    }
+}
+
+impl<'de> Deserialize<'de> for DetectionsConfig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Helper {
+            Paths(StringOrList),
+            Full {
+                paths: StringOrList,
+                dedup_window_secs: Option<u64>,
+                #[serde(default)]
+                dedup_rules: HashMap<String, u64>,
+                #[serde(default)]
+                dedup_fields: Vec<String>,
+                #[serde(default = "DEFAULT_DEDUP_MAX_ENTRIES")]
+                dedup_max_entries: usize,
+                #[serde(default = "DEFAULT_WORKERS")]
+                workers: usize,
+                sync_interval_secs: Option<u64>,
+            },
+        }
+
+        Ok(match Helper::deserialize(deserializer)? {
+            Helper::Paths(paths) => paths.into(),
+            Helper::Full {
+                paths,
+                dedup_window_secs,
+                dedup_rules,
+                dedup_fields,
+                dedup_max_entries,
+                workers,
+                sync_interval_secs,
+            } => DetectionsConfig {
+                paths,
+                dedup_window_secs,
+                dedup_rules,
+                dedup_fields,
+                dedup_max_entries,
+                workers,
+                sync_interval_secs,
+            },
+        })
+    }
+}
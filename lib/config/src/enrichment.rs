@@ -0,0 +1,78 @@
+//! Event enrichment ahead of detection and storage.
+//!
+//! ```yaml
+//! enrichment:
+//!   geoip:
+//!     database: /var/lib/GeoIP/GeoLite2-City.mmdb
+//!   assets:
+//!     path: /etc/striem/assets.yaml
+//! ```
+//!
+//! Both look at the IP address fields matching `fields`, dotted paths whose
+//! segments may hold `*` wildcards, `*_endpoint.ip` by default. GeoIP adds a
+//! `location` next to each address; the assets file lists CIDR ranges whose
+//! `tags` are added next to each address inside them, more specific ranges
+//! overriding less specific ones. Fields already set are left alone.
+//!
+//! ```yaml
+//! - cidr: 10.0.0.0/8
+//!   tags:
+//!     network_zone: internal
+//! - cidr: 10.20.0.0/16
+//!   tags:
+//!     network_zone: pci
+//!     asset_criticality: high
+//! ```
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const IP_FIELDS: fn() -> Vec<String> = || vec!["*_endpoint.ip".to_string()];
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct EnrichmentConfig {
+    pub geoip: Option<GeoIpConfig>,
+    pub assets: Option<AssetsConfig>,
+}
+
+impl EnrichmentConfig {
+    pub fn is_empty(&self) -> bool {
+        self.geoip.is_none() && self.assets.is_none()
+    }
+}
+
+/// MaxMind GeoLite2 or GeoIP2 City lookups
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GeoIpConfig {
+    /// The `.mmdb` file, read again when it changes
+    pub database: PathBuf,
+    #[serde(default = "IP_FIELDS")]
+    pub fields: Vec<String>,
+}
+
+/// Tags for the addresses in CIDR ranges
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AssetsConfig {
+    /// YAML list of [`AssetRange`]s, read at startup and on reload
+    pub path: PathBuf,
+    #[serde(default = "IP_FIELDS")]
+    pub fields: Vec<String>,
+}
+
+impl AssetsConfig {
+    pub fn load(&self) -> Result<Vec<AssetRange>> {
+        let s = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("could not read {}", self.path.display()))?;
+        serde_yaml::from_str(&s).with_context(|| format!("invalid {}", self.path.display()))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AssetRange {
+    /// e.g. `10.0.0.0/8` or `fd00::/8`
+    pub cidr: String,
+    pub tags: BTreeMap<String, Value>,
+}
@@ -0,0 +1,113 @@
+//! Configuration from `STRIEM` environment variables.
+//!
+//! Nested keys are separated by double underscores, so keys with
+//! underscores in their names are reachable:
+//! `STRIEM__OUTPUT__VECTOR__HEC__ADDRESS` sets `output.vector.hec.address`
+//! and `STRIEM__STORAGE__RETENTION__INTERVAL_SECS` sets
+//! `storage.retention.interval_secs`.
+//!
+//! The single-underscore names used so far (`STRIEM_STORAGE_PATH`,
+//! `STRIEM_OUTPUT_VECTOR_URL`) are still read as aliases, splitting on every
+//! underscore as before. A double-underscore variable wins over an alias for
+//! the same key.
+//!
+//! Values of the keys in [`LIST_KEYS`] are comma-separated lists. Other
+//! values of `true`, `false` or an integer are read as such, since sections
+//! with flattened fields (`api`, `input`, `output`) don't convert strings.
+
+use config::{ConfigError, Map, Source, Value, ValueKind};
+
+const PREFIX: &str = "STRIEM";
+
+/// Keys read as comma-separated lists
+pub const LIST_KEYS: &[&str] = &[
+    "detections",
+    "detections.paths",
+    "detections.dedup_fields",
+    "input.http.tokens",
+    "storage.sort_by",
+    "storage.bloom_filter_columns",
+    "api.mcp.url",
+];
+
+/// A [`config::Source`] for `STRIEM` environment variables
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    /// Variables to read instead of the process environment
+    vars: Option<Vec<(String, String)>>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read `vars` rather than the process environment
+    pub fn with_vars<K: Into<String>, V: Into<String>>(
+        vars: impl IntoIterator<Item = (K, V)>,
+    ) -> Self {
+        Environment {
+            vars: Some(
+                vars.into_iter()
+                    .map(|(k, v)| (k.into(), v.into()))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// The configuration key for variable `name`, and whether it's an alias
+    fn key(name: &str) -> Option<(String, bool)> {
+        let rest = name.strip_prefix(PREFIX)?;
+        let (key, alias) = match rest.strip_prefix("__") {
+            Some(nested) => (nested.split("__").collect::<Vec<_>>().join("."), false),
+            None => (rest.strip_prefix('_')?.replace('_', "."), true),
+        };
+        if key.is_empty() || key.split('.').any(str::is_empty) {
+            return None;
+        }
+        Some((key.to_ascii_lowercase(), alias))
+    }
+}
+
+impl Source for Environment {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<Map<String, Value>, ConfigError> {
+        let vars = match &self.vars {
+            Some(vars) => vars.clone(),
+            None => std::env::vars().collect(),
+        };
+
+        let mut keyed: Vec<_> = vars
+            .iter()
+            .filter_map(|(name, value)| Some((Self::key(name)?, name, value)))
+            .collect();
+        // aliases first, so double-underscore variables replace them
+        keyed.sort_by_key(|((_, alias), _, _)| !alias);
+
+        let mut map = Map::new();
+        for ((key, _), name, value) in keyed {
+            let origin = format!("environment variable {}", name);
+            let kind = if LIST_KEYS.contains(&key.as_str()) {
+                ValueKind::Array(
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|v| !v.is_empty())
+                        .map(|v| Value::new(Some(&origin), v))
+                        .collect(),
+                )
+            } else if let Ok(b) = value.parse::<bool>() {
+                ValueKind::Boolean(b)
+            } else if let Ok(i) = value.parse::<i64>() {
+                ValueKind::I64(i)
+            } else {
+                ValueKind::String(value.clone())
+            };
+            map.insert(key, Value::new(Some(&origin), kind));
+        }
+        Ok(map)
+    }
+}
@@ -1,35 +1,209 @@
-use std::net::SocketAddr;
+use std::{collections::BTreeMap, net::SocketAddr};
 
 use serde::{Deserialize, Serialize};
 
 use striem_common::prelude::*;
 
-use crate::HostConfig;
+use crate::{AddressError, Compression, HostConfig};
+
+const CHANNEL_CAPACITY: fn() -> usize = || DEFAULT_VECTOR_SERVER_CHANNEL_CAPACITY;
+const MAX_DECODING_MESSAGE_SIZE: fn() -> usize = || DEFAULT_VECTOR_SERVER_MAX_MESSAGE_SIZE;
+const ACK_TIMEOUT_SECS: fn() -> u64 = || DEFAULT_VECTOR_ACK_TIMEOUT_SECS;
+const MAX_BODY_SIZE: fn() -> usize = || DEFAULT_HTTP_INGEST_MAX_BODY_SIZE;
+const MAX_DELAY_MS: fn() -> u64 = || DEFAULT_INPUT_LIMIT_MAX_DELAY_MS;
+const AGENT_ID_HEADER: fn() -> String = || DEFAULT_VECTOR_AGENT_ID_HEADER.to_string();
+
+/// Tuning for the gRPC server receiving events from Vector
+///
+/// # Example
+/// ```yaml
+/// input:
+///   vector:
+///     address: 0.0.0.0:9000
+///     server:
+///       channel_capacity: 1024
+///       max_decoding_message_size: 33554432
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct ServerOptions {
+    /// Batches buffered for detection and storage; a subscriber that falls
+    /// further behind misses batches
+    #[serde(default = "CHANNEL_CAPACITY")]
+    pub channel_capacity: usize,
+    /// Largest request Vector may send, in bytes
+    #[serde(default = "MAX_DECODING_MESSAGE_SIZE")]
+    pub max_decoding_message_size: usize,
+    /// Concurrent HTTP/2 streams per connection; unset leaves it unlimited
+    pub max_concurrent_streams: Option<u32>,
+    #[serde(default)]
+    pub tcp_nodelay: bool,
+    /// Pass metric events to metric subscribers instead of dropping them
+    #[serde(default)]
+    pub accept_metrics: bool,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions {
+            channel_capacity: CHANNEL_CAPACITY(),
+            max_decoding_message_size: MAX_DECODING_MESSAGE_SIZE(),
+            max_concurrent_streams: None,
+            tcp_nodelay: false,
+            accept_metrics: false,
+        }
+    }
+}
+
+/// What to do with a batch over its rate limit
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitPolicy {
+    /// Hold the batch until it fits, up to `max_delay_ms`, then reject it
+    #[default]
+    Delay,
+    /// Reject at once
+    Reject,
+}
+
+/// Event rate limits for the gRPC server, as token buckets refilled every
+/// second and holding at most one second's worth. A batch with any source
+/// over its cap, or over the overall cap, that isn't delayed gets
+/// RESOURCE_EXHAUSTED whole, so Vector backpressures its own sources.
+///
+/// # Example
+/// ```yaml
+/// input:
+///   vector:
+///     address: 0.0.0.0:9000
+///     limits:
+///       events_per_sec: 50000
+///       sources:
+///         source-okta_1: 1000
+///       policy: delay
+///       max_delay_ms: 1000
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct LimitsConfig {
+    /// Events per second across all sources
+    pub events_per_sec: Option<u64>,
+    /// Events per second by Vector `source_id`
+    #[serde(default)]
+    pub sources: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub policy: LimitPolicy,
+    /// Longest a batch is held under the `delay` policy
+    #[serde(default = "MAX_DELAY_MS")]
+    pub max_delay_ms: u64,
+}
+
+/// Identity of the Vector agent pushing a batch, read from a gRPC request
+/// header and recorded in each event's `ingest_agent` metadata
+///
+/// # Example
+/// ```yaml
+/// input:
+///   vector:
+///     address: 0.0.0.0:9000
+///     agent_id:
+///       header: x-striem-agent-id
+///       required: true
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct AgentIdConfig {
+    #[serde(default = "AGENT_ID_HEADER")]
+    pub header: String,
+    /// Refuse batches without the header with UNAUTHENTICATED
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// Vector gRPC listener: where to listen and how to serve
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VectorListenerConfig {
+    #[serde(flatten)]
+    pub cfg: HostConfig,
+    #[serde(default)]
+    pub server: ServerOptions,
+    /// Acknowledge a batch to Vector only once storage has written it, so
+    /// Vector keeps and retries batches that weren't stored
+    #[serde(default)]
+    pub acknowledgements: bool,
+    /// How long to wait for storage before failing a batch back to Vector
+    #[serde(default = "ACK_TIMEOUT_SECS")]
+    pub ack_timeout_secs: u64,
+    /// Rate limits on received events
+    pub limits: Option<LimitsConfig>,
+    /// Record, or require, the identity each agent sends
+    pub agent_id: Option<AgentIdConfig>,
+    /// Also accept requests in this encoding and compress responses with
+    /// it; gzip requests are always accepted
+    #[serde(default)]
+    pub compression: Compression,
+}
+
+/// HTTP ingest listener, accepting JSON, NDJSON and Splunk HEC events
+/// directly instead of through Vector
+///
+/// # Example
+/// ```yaml
+/// input:
+///   http:
+///     address: 0.0.0.0:8088
+///     tokens:
+///       - 5f0c7a3e-hec-token
+///     max_body_size: 10485760
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct HttpListenerConfig {
+    #[serde(flatten)]
+    pub cfg: HostConfig,
+    /// Accepted tokens, sent as `Authorization: Bearer <token>` or, for HEC,
+    /// `Authorization: Splunk <token>`. Empty accepts any request.
+    #[serde(default)]
+    pub tokens: Vec<String>,
+    /// Largest request body, in bytes, before and after decompression
+    #[serde(default = "MAX_BODY_SIZE")]
+    pub max_body_size: usize,
+}
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum Listener {
-    Vector(HostConfig),
-    Http(HostConfig),
+    Vector(VectorListenerConfig),
+    Http(HttpListenerConfig),
 }
 
 impl Default for Listener {
     fn default() -> Self {
-        Listener::Vector(HostConfig::default().set_port(DEFAULT_STRIEM_LISTEN_PORT))
+        Listener::Vector(VectorListenerConfig {
+            cfg: HostConfig::default().set_port(DEFAULT_STRIEM_LISTEN_PORT),
+            server: ServerOptions::default(),
+            acknowledgements: false,
+            ack_timeout_secs: ACK_TIMEOUT_SECS(),
+            limits: None,
+            agent_id: None,
+            compression: Compression::None,
+        })
     }
 }
 
 impl Listener {
     pub fn url(&self) -> String {
         match self {
-            Listener::Vector(vector) => vector.url(),
-            Listener::Http(cfg) => cfg.url(),
+            Listener::Vector(vector) => vector.cfg.url(),
+            Listener::Http(http) => http.cfg.url(),
         }
     }
     pub fn address(&self) -> SocketAddr {
         match self {
-            Listener::Vector(cfg) => cfg.address(),
-            Listener::Http(cfg) => cfg.address(),
+            Listener::Vector(vector) => vector.cfg.address(),
+            Listener::Http(http) => http.cfg.address(),
+        }
+    }
+    pub fn try_address(&self) -> Result<SocketAddr, AddressError> {
+        match self {
+            Listener::Vector(vector) => vector.cfg.try_address(),
+            Listener::Http(http) => http.cfg.try_address(),
         }
     }
 }
@@ -2,14 +2,15 @@
 //!
 //! Uses [Config](https://docs.rs/config/latest/config/index.html), supports loading from:
 //! - Configuration files (YAML, JSON, TOML)
-//! - Environment variables (STRIEM_ prefix)
+//! - Environment variables (STRIEM_ prefix, `__` between nested keys; see
+//!   [`env`])
 //! - Defaults
 //!
 //! Environment variables override file settings, enabling Docker/K8s deployments
 //! without rebuilding config files.
 
 use std::{
-    net::{Ipv4Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs},
     path::PathBuf,
 };
 use url::Url;
@@ -17,11 +18,17 @@ use url::Url;
 use anyhow::{Result, anyhow};
 use config::Config;
 use serde::{Deserialize, Serialize};
+use striem_common::{logging::LoggingConfig, prelude::DEFAULT_SHUTDOWN_GRACE_SECS};
 
 pub mod api;
+pub mod detections;
+pub mod enrichment;
+pub mod env;
 pub mod input;
 pub mod output;
+pub mod reload;
 pub mod storage;
+pub mod validate;
 
 mod tests;
 
@@ -33,11 +40,83 @@ pub enum StringOrList {
     List(Vec<String>),
 }
 
+/// URL scheme of the service at a [`HostConfig`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scheme {
+    #[default]
+    Http,
+    Https,
+    Grpc,
+    Grpcs,
+}
+
+impl std::fmt::Display for Scheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Scheme::Http => "http",
+            Scheme::Https => "https",
+            Scheme::Grpc => "grpc",
+            Scheme::Grpcs => "grpcs",
+        })
+    }
+}
+
+/// gRPC message compression between StrIEM and Vector
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+/// Why a [`HostConfig`] has no socket address
+#[derive(Debug)]
+pub enum AddressError {
+    /// The URL has no host, e.g. `unix:/path`
+    NoHost(Url),
+    /// The host name could not be resolved
+    Resolve { host: String, error: std::io::Error },
+    /// The host name resolved to no addresses
+    NoAddresses(String),
+}
+
+impl std::fmt::Display for AddressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AddressError::NoHost(url) => write!(f, "{} has no host", url),
+            AddressError::Resolve { host, error } => {
+                write!(f, "could not resolve {}: {}", host, error)
+            }
+            AddressError::NoAddresses(host) => write!(f, "{} resolved to no addresses", host),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AddressError::Resolve { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Where a service listens or is reached: a socket `address`, a `url`, or
+/// both. The `port` fills in one the address or URL leaves out.
+///
+/// A URL's host name is resolved when the address is needed, preferring
+/// IPv4 addresses unless `prefer_ipv6` is set. `scheme` is the scheme of
+/// URLs made from `address`; a configured `url` is used as is.
 #[derive(Debug, Serialize, Clone)]
 pub struct HostConfig {
     pub address: Option<SocketAddr>,
     pub url: Option<Url>,
     pub port: u16,
+    pub scheme: Option<Scheme>,
+    pub prefer_ipv6: bool,
 }
 
 impl Default for HostConfig {
@@ -46,6 +125,8 @@ impl Default for HostConfig {
             address: Some(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))),
             url: None,
             port: 0,
+            scheme: None,
+            prefer_ipv6: false,
         }
     }
 }
@@ -60,6 +141,9 @@ impl<'de> Deserialize<'de> for HostConfig {
             address: Option<SocketAddr>,
             url: Option<Url>,
             port: Option<u16>,
+            scheme: Option<Scheme>,
+            #[serde(default)]
+            prefer_ipv6: bool,
         }
 
         let helper = HostConfigHelper::deserialize(deserializer)?;
@@ -70,71 +154,90 @@ impl<'de> Deserialize<'de> for HostConfig {
             ));
         }
 
-        let port = if let Some(p) = helper.port {
-            p
-        } else if let Some(addr) = helper.address
-            && addr.port() != 0
-        {
-            addr.port()
-        } else if let Some(url) = &helper.url {
-            url.port().unwrap_or(0)
-        } else {
-            unreachable!()
-        };
+        // an address with port 0 leaves the port to the URL, if any, or to
+        // the OS when binding
+        let port = helper
+            .port
+            .or_else(|| helper.address.map(|addr| addr.port()).filter(|p| *p != 0))
+            .or_else(|| helper.url.as_ref().and_then(Url::port))
+            .unwrap_or(0);
 
         Ok(HostConfig {
             address: helper.address,
             url: helper.url,
             port,
+            scheme: helper.scheme,
+            prefer_ipv6: helper.prefer_ipv6,
         })
     }
 }
 
 impl HostConfig {
-    pub fn address(&self) -> SocketAddr {
-        if let Some(addr) = self.address {
+    /// The socket address, resolving the URL's host name if there's no
+    /// `address`
+    pub fn try_address(&self) -> Result<SocketAddr, AddressError> {
+        if let Some(mut addr) = self.address {
             if addr.port() == 0 {
-                let mut addr = addr;
                 addr.set_port(self.port);
-                addr
-            } else {
-                addr
-            }
-        } else {
-            match &self.url {
-                Some(url) => match url.host() {
-                    Some(url::Host::Domain(host)) => {
-                        if host == "localhost" {
-                            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, self.port))
-                        } else {
-                            host.parse::<SocketAddr>().unwrap_or_else(|_| {
-                                SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, self.port))
-                            })
-                        }
-                    }
-                    Some(url::Host::Ipv4(ip)) => SocketAddr::V4(SocketAddrV4::new(ip, self.port)),
-                    Some(url::Host::Ipv6(ip)) => {
-                        SocketAddr::V6(SocketAddrV6::new(ip, self.port, 0, 0))
-                    }
-                    None => SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, self.port)),
-                },
-                None => unreachable!(),
             }
+            return Ok(addr);
+        }
+        let Some(url) = &self.url else {
+            // deserialization requires one of address and url
+            return Ok(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::UNSPECIFIED,
+                self.port,
+            )));
+        };
+        match url.host() {
+            Some(url::Host::Ipv4(ip)) => Ok(SocketAddr::V4(SocketAddrV4::new(ip, self.port))),
+            Some(url::Host::Ipv6(ip)) => Ok(SocketAddr::V6(SocketAddrV6::new(ip, self.port, 0, 0))),
+            Some(url::Host::Domain(host)) => self.resolve(host),
+            None => Err(AddressError::NoHost(url.clone())),
         }
     }
 
+    /// `host` resolved, an address of the preferred family first
+    fn resolve(&self, host: &str) -> Result<SocketAddr, AddressError> {
+        let addrs: Vec<SocketAddr> = (host, self.port)
+            .to_socket_addrs()
+            .map_err(|error| AddressError::Resolve {
+                host: host.to_string(),
+                error,
+            })?
+            .collect();
+        addrs
+            .iter()
+            .find(|a| a.is_ipv6() == self.prefer_ipv6)
+            .or_else(|| addrs.first())
+            .copied()
+            .ok_or_else(|| AddressError::NoAddresses(host.to_string()))
+    }
+
+    /// The socket address, or the unspecified address on this port if the
+    /// URL's host can't be resolved; see [`HostConfig::try_address`]
+    pub fn address(&self) -> SocketAddr {
+        self.try_address().unwrap_or_else(|e| {
+            log::warn!("{}, using 0.0.0.0:{}", e, self.port);
+            SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, self.port))
+        })
+    }
+
+    /// The configured URL, or one made from `scheme` and the address. An
+    /// unspecified address is reached as `localhost`.
     pub fn url(&self) -> String {
-        match &self.url {
-            Some(url) => url.to_string(),
-            None => {
-                if self.address().ip().is_unspecified() {
-                    format!("http://localhost:{}", self.port)
-                } else {
-                    format!("http://{}", self.address())
-                }
-            }
+        if let Some(url) = &self.url {
+            return url.to_string();
+        }
+        let scheme = self.scheme.unwrap_or_default();
+        let addr = self.address();
+        if addr.ip().is_unspecified() {
+            format!("{}://localhost:{}", scheme, addr.port())
+        } else {
+            format!("{}://{}", scheme, addr)
         }
     }
+
     pub fn set_port(mut self, port: u16) -> Self {
         self.port = port;
         self
@@ -154,9 +257,8 @@ struct StrIEMConfigOptions {
     db: PathBuf,
 
     /// Location of top-level Sigma detection directory
-    /// (can be a list or single path)
-    #[serde(with = "serde_yaml::with::singleton_map")]
-    detections: Option<StringOrList>,
+    /// (can be a list or single path, or a block with engine options)
+    detections: Option<detections::DetectionsConfig>,
 
     /// Input listener configuration
     #[serde(with = "serde_yaml::with::singleton_map")]
@@ -166,31 +268,65 @@ struct StrIEMConfigOptions {
     #[serde(with = "serde_yaml::with::singleton_map")]
     output: Option<output::Destination>,
 
+    /// Further output destinations, each forwarded to independently
+    #[serde(with = "serde_yaml::with::singleton_map_recursive")]
+    outputs: Option<Vec<output::Destination>>,
+
     /// Storage backend configuration
     storage: Option<storage::StorageConfig>,
 
+    /// GeoIP and asset tags added to events before detection and storage
+    enrichment: Option<enrichment::EnrichmentConfig>,
+
     /// API server configuration
     api: Option<api::ApiConfig>,
 
     /// Fully qualified domain name for this StrIEM instance
     fqdn: Option<String>,
+
+    /// Reload when the configuration files change on disk
+    #[serde(default)]
+    watch_config: bool,
+
+    /// How long shutdown waits for received events to be evaluated, stored
+    /// and forwarded
+    shutdown_grace_secs: Option<u64>,
+
+    /// Log format, levels and destination
+    #[serde(default)]
+    logging: LoggingConfig,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Serialize, Clone)]
 pub struct StrIEMConfig {
     pub db: Option<PathBuf>,
 
-    pub detections: Option<StringOrList>,
+    pub detections: Option<detections::DetectionsConfig>,
 
     pub input: input::Listener,
 
-    pub output: Option<output::Destination>,
+    /// Destinations from `output` and `outputs`, in that order
+    pub outputs: Vec<output::Destination>,
 
     pub storage: Option<storage::StorageConfig>,
 
+    pub enrichment: Option<enrichment::EnrichmentConfig>,
+
     pub api: api::ApiConfig,
 
     pub fqdn: Option<String>,
+
+    /// Reload when a file in `files` changes; see [`reload`]
+    pub watch_config: bool,
+
+    /// Bound on the in-flight drain at shutdown
+    pub shutdown_grace_secs: u64,
+
+    pub logging: LoggingConfig,
+
+    /// Configuration files loaded, in order of precedence (lowest first)
+    #[serde(skip)]
+    pub files: Vec<PathBuf>,
 }
 
 impl From<StrIEMConfigOptions> for StrIEMConfig {
@@ -199,10 +335,21 @@ impl From<StrIEMConfigOptions> for StrIEMConfig {
             db: Some(val.db.clone()),
             detections: val.detections,
             input: val.input.unwrap_or_default(),
-            output: val.output,
+            outputs: val
+                .output
+                .into_iter()
+                .chain(val.outputs.into_iter().flatten())
+                .collect(),
             storage: val.storage,
+            enrichment: val.enrichment,
             api: val.api.unwrap_or_default(),
             fqdn: val.fqdn,
+            watch_config: val.watch_config,
+            shutdown_grace_secs: val
+                .shutdown_grace_secs
+                .unwrap_or(DEFAULT_SHUTDOWN_GRACE_SECS),
+            logging: val.logging,
+            files: Vec::new(),
         }
     }
 }
@@ -214,7 +361,7 @@ impl StrIEMConfig {
                 serde_json::to_string(&StrIEMConfigOptions::default())?.as_str(),
                 config::FileFormat::Json,
             ))
-            .add_source(config::Environment::with_prefix("STRIEM").separator("_"))
+            .add_source(env::Environment::new())
             .build()?;
 
         let config: StrIEMConfigOptions = builder.try_deserialize()?;
@@ -231,79 +378,82 @@ impl StrIEMConfig {
                 config::FileFormat::Json,
             ))
             .add_source(config::File::with_name(file))
-            .add_source(config::Environment::with_prefix("STRIEM").separator("_"))
+            .add_source(env::Environment::new())
             .build()?;
 
         let config: StrIEMConfigOptions = builder.try_deserialize()?;
         Self::check(&config)?;
 
-        Ok(config.into())
+        Ok(StrIEMConfig {
+            files: vec![PathBuf::from(file)],
+            ..config.into()
+        })
     }
 
     pub fn from_multi_file(files: Vec<PathBuf>) -> Result<Self> {
+        Self::from_files_with_overrides(files, &[])
+    }
+
+    /// Read `files` in order, then the environment, then `overrides`, which
+    /// set dotted keys such as `api.address` and win over everything else
+    pub fn from_files_with_overrides(
+        files: Vec<PathBuf>,
+        overrides: &[(String, String)],
+    ) -> Result<Self> {
         let mut builder = Config::builder().add_source(config::File::from_str(
             serde_json::to_string(&StrIEMConfigOptions::default())?.as_str(),
             config::FileFormat::Json,
         ));
 
-        builder = builder.add_source(config::Environment::with_prefix("STRIEM").separator("_"));
-
+        let mut loaded = Vec::new();
         for file in files {
             if let Some(filename) = file.to_str() {
                 builder = builder.add_source(config::File::with_name(filename));
+                loaded.push(file);
             } else {
                 log::error!("Invalid config file path: {:?}", file);
             }
         }
 
-        let built = builder.build()?;
+        builder = builder.add_source(env::Environment::new());
+        for (key, value) in overrides {
+            builder = builder.set_override(key.as_str(), value.as_str())?;
+        }
 
-        let config: StrIEMConfigOptions = built.try_deserialize()?;
+        let config: StrIEMConfigOptions = builder.build()?.try_deserialize()?;
         Self::check(&config)?;
 
-        Ok(config.into())
+        Ok(StrIEMConfig {
+            files: loaded,
+            ..config.into()
+        })
     }
 
     pub fn from_yaml(s: &str) -> Result<Self> {
-        let builder = Config::builder()
-            .add_source(config::File::from_str(
-                serde_json::to_string(&StrIEMConfigOptions::default())?.as_str(),
-                config::FileFormat::Json,
-            ))
-            .add_source(config::File::from_str(s, config::FileFormat::Yaml))
-            .add_source(config::Environment::with_prefix("STRIEM").separator("_"))
-            .build()?;
-
-        let config: StrIEMConfigOptions = builder.try_deserialize()?;
-        Self::check(&config)?;
-
-        Ok(config.into())
+        Self::from_str_with_env(s, config::FileFormat::Yaml, env::Environment::new())
     }
 
     pub fn from_json(s: &str) -> Result<Self> {
-        let builder = Config::builder()
-            .add_source(config::File::from_str(
-                serde_json::to_string(&StrIEMConfigOptions::default())?.as_str(),
-                config::FileFormat::Json,
-            ))
-            .add_source(config::File::from_str(s, config::FileFormat::Json))
-            .add_source(config::Environment::with_prefix("STRIEM").separator("_"))
-            .build()?;
-
-        let config: StrIEMConfigOptions = builder.try_deserialize()?;
-        Self::check(&config)?;
-
-        Ok(config.into())
+        Self::from_str_with_env(s, config::FileFormat::Json, env::Environment::new())
     }
 
     pub fn from_toml(s: &str) -> Result<Self> {
+        Self::from_str_with_env(s, config::FileFormat::Toml, env::Environment::new())
+    }
+
+    /// Read `s` with `environment` in place of the process environment
+    pub fn from_str_with_env(
+        s: &str,
+        format: config::FileFormat,
+        environment: env::Environment,
+    ) -> Result<Self> {
         let builder = Config::builder()
             .add_source(config::File::from_str(
                 serde_json::to_string(&StrIEMConfigOptions::default())?.as_str(),
                 config::FileFormat::Json,
             ))
-            .add_source(config::File::from_str(s, config::FileFormat::Toml))
-            .add_source(config::Environment::with_prefix("STRIEM").separator("_"))
+            .add_source(config::File::from_str(s, format))
+            .add_source(environment)
             .build()?;
 
         let config: StrIEMConfigOptions = builder.try_deserialize()?;
@@ -313,12 +463,25 @@ impl StrIEMConfig {
     }
 
     fn check(config: &StrIEMConfigOptions) -> Result<()> {
+        if let Some(storage) = &config.storage {
+            storage.check_overrides().map_err(|e| anyhow!(e))?;
+            storage.check_sampling().map_err(|e| anyhow!(e))?;
+            storage
+                .compaction
+                .scheduled_minute()
+                .map_err(|e| anyhow!(e))?;
+        }
+        if let Some(mcp) = config.api.as_ref().and_then(|api| api.mcp.as_ref()) {
+            mcp.servers().map_err(|e| anyhow!(e))?;
+        }
         let api = if let Some(ref api) = config.api {
             api.enabled
         } else {
             false
         };
-        if !(config.output.is_some() || config.storage.is_some()) {
+        let outputs =
+            config.output.is_some() || config.outputs.as_ref().is_some_and(|o| !o.is_empty());
+        if !(outputs || config.storage.is_some()) {
             if !api {
                 Err(anyhow!(
                     "No output, storage, or API configured; StrIEM cannot run"
@@ -3,13 +3,47 @@
 //! Defines where StrIEM sends processed events and detection findings.
 //! Supports Vector (for downstream pipelines) and HTTP endpoints.
 
-use std::net::SocketAddr;
+use std::{collections::BTreeMap, net::SocketAddr, path::PathBuf};
 
 use serde::{Deserialize, Serialize};
 
-use striem_common::prelude::*;
+use striem_common::{prelude::*, severity::Severity};
 
-use crate::HostConfig;
+use crate::{AddressError, Compression, HostConfig};
+
+const MAX_RETRIES: fn() -> u32 = || DEFAULT_HTTP_OUTPUT_MAX_RETRIES;
+const CONCURRENCY: fn() -> usize = || DEFAULT_HTTP_OUTPUT_CONCURRENCY;
+const TIMEOUT_SECS: fn() -> u64 = || DEFAULT_HTTP_OUTPUT_TIMEOUT_SECS;
+const PROVISION_DEBOUNCE_MS: fn() -> u64 = || DEFAULT_VECTOR_PROVISION_DEBOUNCE_MS;
+
+/// Which events an output forwards.
+///
+/// Findings below `min_severity` (by OCSF `severity_id`), from rules not in
+/// `rules` when it's set, or from rules in `exclude_rules` aren't forwarded
+/// but are still stored. Changes apply on reload, except `raw_events`.
+///
+/// # Example
+/// ```yaml
+/// output:
+///   vector:
+///     url: http://pager-vector:9000
+///     filter:
+///       min_severity: high
+///       exclude_rules:
+///         - 0e0d5b38-5dcb-4a39-a9d2-1c4a5e7a86c2
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct OutputFilter {
+    pub min_severity: Option<Severity>,
+    /// Rule ids to forward findings of; all when empty
+    #[serde(default)]
+    pub rules: Vec<String>,
+    #[serde(default)]
+    pub exclude_rules: Vec<String>,
+    /// Forward received events as well as findings
+    #[serde(default)]
+    pub raw_events: bool,
+}
 
 /// Vector destination configuration
 ///
@@ -23,6 +57,11 @@ use crate::HostConfig;
 /// - `http`: HTTP listener configuration
 ///   - **Use Case**: Enables Vector's HTTP listener, for receiving events from webhooks
 ///
+/// # Provisioning
+/// With `api` and `config_path` set, the generated configuration is written
+/// to `config_path` whenever sources change, and Vector is sent SIGHUP via
+/// `pid_file`, or left to pick it up with `--watch-config`.
+///
 /// # Example
 /// ```yaml
 /// output:
@@ -33,12 +72,24 @@ use crate::HostConfig;
 #[derive(Debug, Serialize, Clone)]
 pub struct VectorDestinationConfig {
     /// Primary Vector gRPC endpoint configuration
+    #[serde(flatten)]
     pub cfg: HostConfig,
     /// Optional Splunk HEC endpoint for Vector to forward events
     pub hec: Option<HostConfig>,
     /// Optional HTTP endpoint for Vector to forward events
     pub http: Option<HostConfig>,
     pub api: Option<HostConfig>,
+    /// Where to write the generated configuration when sources change
+    pub config_path: Option<PathBuf>,
+    /// Vector's PID file, to send it SIGHUP after writing `config_path`
+    pub pid_file: Option<PathBuf>,
+    /// Changes this close together are written once
+    pub provision_debounce_ms: u64,
+    /// Forward only some findings; all by default
+    pub filter: Option<OutputFilter>,
+    /// Compress pushed batches, and accept responses compressed, with this
+    /// encoding; the downstream Vector must accept it
+    pub compression: Compression,
 }
 
 impl<'de> Deserialize<'de> for VectorDestinationConfig {
@@ -53,6 +104,13 @@ impl<'de> Deserialize<'de> for VectorDestinationConfig {
             hec: Option<HostConfig>,
             http: Option<HostConfig>,
             api: Option<HostConfig>,
+            config_path: Option<PathBuf>,
+            pid_file: Option<PathBuf>,
+            #[serde(default = "PROVISION_DEBOUNCE_MS")]
+            provision_debounce_ms: u64,
+            filter: Option<OutputFilter>,
+            #[serde(default)]
+            compression: Compression,
         }
 
         let mut helper = Helper::deserialize(deserializer)?;
@@ -80,10 +138,71 @@ impl<'de> Deserialize<'de> for VectorDestinationConfig {
             hec: helper.hec,
             http: helper.http,
             api: helper.api,
+            config_path: helper.config_path,
+            pid_file: helper.pid_file,
+            provision_debounce_ms: helper.provision_debounce_ms,
+            filter: helper.filter,
+            compression: helper.compression,
         })
     }
 }
 
+/// Body encoding for batches sent to an HTTP destination
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HttpFormat {
+    /// One JSON event per line (`application/x-ndjson`)
+    #[default]
+    Ndjson,
+    /// A JSON array of events (`application/json`)
+    Json,
+}
+
+/// HTTP destination configuration
+///
+/// Each batch of detection findings is POSTed to `url` as one request.
+/// Failed requests (connection errors, 429 and 5xx responses) are retried
+/// with exponential backoff up to `max_retries` times before the batch is
+/// dropped.
+///
+/// # Example
+/// ```yaml
+/// output:
+///   http:
+///     url: https://alerts.example.com/ingest
+///     format: ndjson
+///     token: s3cr3t
+///     headers:
+///       X-Source: striem
+///     gzip: true
+///     concurrency: 4
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct HttpDestinationConfig {
+    #[serde(flatten)]
+    pub cfg: HostConfig,
+    #[serde(default)]
+    pub format: HttpFormat,
+    /// Extra request headers
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Sent as `Authorization: Bearer <token>`
+    pub token: Option<String>,
+    /// Gzip request bodies (`Content-Encoding: gzip`)
+    #[serde(default)]
+    pub gzip: bool,
+    #[serde(default = "MAX_RETRIES")]
+    pub max_retries: u32,
+    /// Requests in flight at once; above 1, batches may arrive out of order
+    #[serde(default = "CONCURRENCY")]
+    pub concurrency: usize,
+    /// Timeout for each request
+    #[serde(default = "TIMEOUT_SECS")]
+    pub timeout_secs: u64,
+    /// Forward only some findings; all by default
+    pub filter: Option<OutputFilter>,
+}
+
 /// Output destination for processed events and detection findings.
 ///
 /// StrIEM can forward events to downstream systems for additional processing,
@@ -104,27 +223,48 @@ impl<'de> Deserialize<'de> for VectorDestinationConfig {
 /// output:
 ///   vector:
 ///     url: http://downstream-vector:9000
+///
+/// # Or several, each forwarded to independently
+/// outputs:
+///   - vector:
+///       url: http://downstream-vector:9000
+///   - http:
+///       url: https://alerts.example.com/ingest
 /// ```
+///
+/// See [`HttpDestinationConfig`] for HTTP options.
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum Destination {
     /// Forward events to a Vector instance via gRPC
     Vector(Box<VectorDestinationConfig>),
     /// Forward events to an HTTP endpoint
-    Http(Box<HostConfig>),
+    Http(Box<HttpDestinationConfig>),
 }
 
 impl Destination {
     pub fn url(&self) -> String {
         match self {
             Destination::Vector(vector) => vector.cfg.url(),
-            Destination::Http(cfg) => cfg.url(),
+            Destination::Http(http) => http.cfg.url(),
         }
     }
     pub fn address(&self) -> SocketAddr {
         match self {
             Destination::Vector(cfg) => cfg.cfg.address(),
-            Destination::Http(cfg) => cfg.address(),
+            Destination::Http(http) => http.cfg.address(),
+        }
+    }
+    pub fn try_address(&self) -> Result<SocketAddr, AddressError> {
+        match self {
+            Destination::Vector(cfg) => cfg.cfg.try_address(),
+            Destination::Http(http) => http.cfg.try_address(),
+        }
+    }
+    pub fn filter(&self) -> Option<&OutputFilter> {
+        match self {
+            Destination::Vector(vector) => vector.filter.as_ref(),
+            Destination::Http(http) => http.filter.as_ref(),
         }
     }
 }
@@ -0,0 +1,74 @@
+//! What changed between two configurations, and whether StrIEM can apply
+//! it while running.
+//!
+//! Keys are dotted paths into the configuration as it serializes, e.g.
+//! `storage.retention.interval_secs` or `input.vector.address`. Lists are
+//! compared whole, so a changed output is reported as `outputs`.
+//!
+//! Listener addresses are bound once at startup; changing them needs a
+//! restart (see [`requires_restart`]).
+
+use serde_json::Value;
+
+use crate::StrIEMConfig;
+
+/// Keys holding listener addresses, bound only at startup
+pub const LISTENER_KEYS: &[&str] = &[
+    "input.vector.address",
+    "input.vector.url",
+    "input.vector.port",
+    "input.http.address",
+    "input.http.url",
+    "input.http.port",
+    "api.address",
+    "api.url",
+    "api.port",
+];
+
+/// Whether a change to `key` only applies after a restart; this includes
+/// keys containing a listener address, such as `input.vector` when the
+/// input kind changes
+pub fn requires_restart(key: &str) -> bool {
+    LISTENER_KEYS.iter().any(|listener| {
+        *listener == key
+            || listener
+                .strip_prefix(key)
+                .is_some_and(|rest| rest.starts_with('.'))
+    })
+}
+
+fn diff(prefix: &str, old: &Value, new: &Value, changes: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                diff(
+                    &path,
+                    old.get(key).unwrap_or(&Value::Null),
+                    new.get(key).unwrap_or(&Value::Null),
+                    changes,
+                );
+            }
+        }
+        (old, new) if old != new => changes.push(prefix.to_string()),
+        _ => {}
+    }
+}
+
+impl StrIEMConfig {
+    /// Keys whose values differ in `other`, sorted
+    pub fn changes(&self, other: &StrIEMConfig) -> Result<Vec<String>, serde_json::Error> {
+        let old = serde_json::to_value(self)?;
+        let new = serde_json::to_value(other)?;
+        let mut changes = Vec::new();
+        diff("", &old, &new, &mut changes);
+        Ok(changes)
+    }
+}
@@ -1,9 +1,308 @@
-use std::path::PathBuf;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
 
 use serde::{Deserialize, Serialize};
+use striem_common::prelude::*;
+
+const RETENTION_INTERVAL_SECS: fn() -> u64 = || DEFAULT_RETENTION_INTERVAL_SECS;
+const QUEUE_CAPACITY: fn() -> usize = || DEFAULT_STORAGE_QUEUE_CAPACITY;
+const QUEUE_TIMEOUT_MS: fn() -> u64 = || DEFAULT_STORAGE_QUEUE_TIMEOUT_MS;
+const ROTATION_JITTER_SECS: fn() -> u64 = || DEFAULT_STORAGE_ROTATION_JITTER_SECS;
+const COMPACTION_MIN_AGE_SECS: fn() -> u64 = || DEFAULT_COMPACTION_MIN_AGE_SECS;
+
+/// Directory layout of Parquet files below `{category}/{class}/`
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Partitioning {
+    /// All files directly in the class directory
+    #[default]
+    None,
+    /// Hive-style `date=YYYY-MM-DD/` subdirectories
+    Daily,
+    /// Hive-style `date=YYYY-MM-DD/hour=HH/` subdirectories
+    Hourly,
+}
+
+/// What to do with a batch when its class's write queue is full
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QueuePolicy {
+    /// Wait up to `timeout_ms` for room, then drop
+    #[default]
+    Block,
+    /// Drop immediately
+    Drop,
+}
+
+/// Per-class write queues between event routing and the Parquet writers
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+pub struct QueueConfig {
+    /// Batches each class may have waiting
+    #[serde(default = "QUEUE_CAPACITY")]
+    pub capacity: usize,
+    #[serde(default)]
+    pub policy: QueuePolicy,
+    /// How long the `block` policy waits for room
+    #[serde(default = "QUEUE_TIMEOUT_MS")]
+    pub timeout_ms: u64,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        QueueConfig {
+            capacity: QUEUE_CAPACITY(),
+            policy: QueuePolicy::default(),
+            timeout_ms: QUEUE_TIMEOUT_MS(),
+        }
+    }
+}
+
+/// Age-based deletion of stored Parquet files
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RetentionConfig {
+    /// Days to keep files of any class; unset keeps them forever
+    pub days: Option<u64>,
+    /// Per-category overrides keyed by storage directory name (e.g. `iam`)
+    #[serde(default)]
+    pub categories: HashMap<String, u64>,
+    /// Days to keep detection findings, overriding the above
+    pub findings_days: Option<u64>,
+    /// Seconds between retention sweeps
+    #[serde(default = "RETENTION_INTERVAL_SECS")]
+    pub interval_secs: u64,
+    /// Log what would be deleted without deleting anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            days: None,
+            categories: HashMap::new(),
+            findings_days: None,
+            interval_secs: RETENTION_INTERVAL_SECS(),
+            dry_run: false,
+        }
+    }
+}
+
+impl RetentionConfig {
+    /// Days to keep files of `class` in `category`, or `None` to keep them
+    pub fn days_for(&self, category: &str, class: &str) -> Option<u64> {
+        self.findings_days
+            .filter(|_| category == "findings" && class == "detection_finding")
+            .or_else(|| self.categories.get(category).copied())
+            .or(self.days)
+    }
+
+    /// Whether any files are subject to deletion
+    pub fn enabled(&self) -> bool {
+        self.days.is_some() || !self.categories.is_empty() || self.findings_days.is_some()
+    }
+}
+
+/// Merging the small files of past days into one file per partition
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CompactionConfig {
+    /// UTC time of day, `HH:MM`, to compact every class at; unset only
+    /// compacts through the API
+    pub schedule: Option<String>,
+    /// Seconds a day must have been over before its files are compacted,
+    /// so files rotated late into it are already in place
+    #[serde(default = "COMPACTION_MIN_AGE_SECS")]
+    pub min_age_secs: u64,
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        CompactionConfig {
+            schedule: None,
+            min_age_secs: COMPACTION_MIN_AGE_SECS(),
+        }
+    }
+}
+
+impl CompactionConfig {
+    /// Minutes after midnight UTC of `schedule`, if set; an error if it
+    /// isn't `HH:MM`
+    pub fn scheduled_minute(&self) -> Result<Option<u32>, String> {
+        let Some(schedule) = &self.schedule else {
+            return Ok(None);
+        };
+        let parsed = schedule
+            .split_once(':')
+            .filter(|(h, m)| h.len() == 2 && m.len() == 2)
+            .and_then(|(h, m)| Some((h.parse::<u32>().ok()?, m.parse::<u32>().ok()?)))
+            .filter(|(h, m)| *h < 24 && *m < 60);
+        match parsed {
+            Some((h, m)) => Ok(Some(h * 60 + m)),
+            None => Err(format!(
+                "storage.compaction.schedule: {} is not a time of day as HH:MM",
+                schedule
+            )),
+        }
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct StorageConfig {
     pub schema: PathBuf,
     pub path: PathBuf,
+    /// Applies to newly rotated files; existing files stay where they are
+    #[serde(default)]
+    pub partitioning: Partitioning,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    /// Reject events with mistyped or out-of-range values to dead-letter
+    /// files instead of storing those values as null
+    #[serde(default)]
+    pub strict: bool,
+    #[serde(default)]
+    pub queue: QueueConfig,
+    /// Columns to sort each file by before it is written, e.g.
+    /// `[time, metadata.uid]`, so row group statistics prune well. Rows are
+    /// held in memory until the file rotates.
+    #[serde(default)]
+    pub sort_by: Vec<String>,
+    /// Columns to write bloom filters for, as dotted paths (e.g.
+    /// `src_endpoint.ip`)
+    #[serde(default)]
+    pub bloom_filter_columns: Vec<String>,
+    /// Store events without a known OCSF `class_uid` under `other/raw/`
+    /// instead of dropping them
+    #[serde(default)]
+    pub keep_unclassified: bool,
+    /// Object store location for finished files (e.g. `s3://bucket/prefix`);
+    /// `path` is then only used for local state
+    pub uri: Option<String>,
+    /// Object store settings such as `aws_region` or `aws_access_key_id`;
+    /// anything unset falls back to the provider's environment variables
+    #[serde(default)]
+    pub options: HashMap<String, String>,
+    /// Base paths replacing `path` for some categories or classes, keyed by
+    /// storage directory name (e.g. `findings` or `detection_finding`); a
+    /// class's entry wins over its category's. Applies to newly rotated
+    /// files, and not with `uri`.
+    #[serde(default)]
+    pub overrides: BTreeMap<String, PathBuf>,
+    #[serde(default)]
+    pub compaction: CompactionConfig,
+    /// Fraction of events to store, from 0.0 to 1.0, for some categories or
+    /// classes, keyed by storage directory name (e.g. `network` or
+    /// `network_activity`); a class's entry wins over its category's. Which
+    /// events are kept depends only on their uid. Detections still see every
+    /// event, and findings are always stored.
+    #[serde(default)]
+    pub sampling: BTreeMap<String, f64>,
+    /// Rotate files at wall-clock multiples of the rotation interval (e.g.
+    /// :00, :05, :10) rather than at intervals from startup; the first
+    /// file may be shorter
+    #[serde(default)]
+    pub rotate_aligned: bool,
+    /// Window over which classes' rotated files are finished, each class at
+    /// its own offset, so they aren't all written out at once; 0 finishes
+    /// them all at rotation
+    #[serde(default = "ROTATION_JITTER_SECS")]
+    pub rotation_jitter_secs: u64,
+    /// Directory files are written to until they rotate. By default it's
+    /// `.tmp` below each base path, so finished files are renamed into
+    /// place; elsewhere they're copied. With `uri`, the system's.
+    pub temp_dir: Option<PathBuf>,
+}
+
+impl StorageConfig {
+    /// Root of the stored `{category}/{class}/` tree: the object store uri
+    /// when configured, else the local path
+    pub fn root(&self) -> PathBuf {
+        self.uri
+            .as_ref()
+            .map(|u| PathBuf::from(u.trim_end_matches('/')))
+            .unwrap_or_else(|| self.path.clone())
+    }
+
+    /// Local base path of the `{category}/{class}/` tree for `class`
+    pub fn base_for(&self, category: &str, class: &str) -> &Path {
+        self.overrides
+            .get(class)
+            .or_else(|| self.overrides.get(category))
+            .unwrap_or(&self.path)
+    }
+
+    /// Where `class` is read from: its base path, or the object store
+    pub fn root_for(&self, category: &str, class: &str) -> PathBuf {
+        match self.uri {
+            Some(_) => self.root(),
+            None => self.base_for(category, class).to_path_buf(),
+        }
+    }
+
+    /// Every root stored files are read from, [`root`](Self::root) first
+    pub fn roots(&self) -> Vec<PathBuf> {
+        let mut roots = vec![self.root()];
+        if self.uri.is_none() {
+            for path in self.overrides.values() {
+                if !roots.contains(path) {
+                    roots.push(path.clone());
+                }
+            }
+        }
+        roots
+    }
+
+    /// Fraction of `class` events to store
+    pub fn sample_ratio(&self, category: &str, class: &str) -> f64 {
+        self.sampling
+            .get(class)
+            .or_else(|| self.sampling.get(category))
+            .copied()
+            .unwrap_or(1.0)
+    }
+
+    /// Reject sampling ratios outside 0.0 to 1.0
+    pub fn check_sampling(&self) -> Result<(), String> {
+        match self
+            .sampling
+            .iter()
+            .find(|(_, ratio)| !(0.0..=1.0).contains(*ratio))
+        {
+            Some((name, ratio)) => Err(format!(
+                "storage.sampling: {} for {} is not between 0.0 and 1.0",
+                ratio, name
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Reject overrides that can't be honored: with an object store, or
+    /// with base paths nested in one another, whose trees would overlap
+    pub fn check_overrides(&self) -> Result<(), String> {
+        if self.overrides.is_empty() {
+            return Ok(());
+        }
+        if self.uri.is_some() {
+            return Err("storage.overrides can't be combined with storage.uri".to_string());
+        }
+        let bases = std::iter::once(("path", &self.path))
+            .chain(self.overrides.iter().map(|(k, v)| (k.as_str(), v)))
+            .collect::<Vec<_>>();
+        for (i, (name, base)) in bases.iter().enumerate() {
+            for (other, other_base) in &bases[i + 1..] {
+                if base != other_base
+                    && (base.starts_with(other_base) || other_base.starts_with(base))
+                {
+                    return Err(format!(
+                        "storage paths for {} ({}) and {} ({}) are nested",
+                        name,
+                        base.display(),
+                        other,
+                        other_base.display()
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
 }
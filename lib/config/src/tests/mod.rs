@@ -20,22 +20,734 @@ fn test_read_config() {
     let config = StrIEMConfig::from_yaml(config).unwrap();
 
     assert_eq!(
-        config.detections,
+        config.detections.map(|d| d.paths),
         Some(StringOrList::List(vec![
             "/path/to/sigmarules".into(),
             "/path/to/more/rules".into()
         ]))
     );
 }
-/*
+
+#[test]
+fn test_outputs() {
+    use crate::output::{Destination, HttpFormat};
+
+    let single = r#"
+      output:
+        vector:
+          url: http://127.0.0.1:6000
+    "#;
+    let config = StrIEMConfig::from_yaml(single).unwrap();
+    assert_eq!(config.outputs.len(), 1);
+    assert!(matches!(config.outputs[0], Destination::Vector(_)));
+
+    let list = r#"
+      outputs:
+        - vector:
+            url: http://127.0.0.1:6000
+        - http:
+            url: https://alerts.example.com/ingest
+            format: json
+            token: s3cr3t
+        - http:
+            url: https://siem.example.com/hook
+            gzip: true
+    "#;
+    let config = StrIEMConfig::from_yaml(list).unwrap();
+    assert_eq!(config.outputs.len(), 3);
+    assert_eq!(config.outputs[0].url(), "http://127.0.0.1:6000/");
+    let Destination::Http(ref alerts) = config.outputs[1] else {
+        panic!("expected an http output");
+    };
+    assert_eq!(alerts.format, HttpFormat::Json);
+    assert_eq!(alerts.token.as_deref(), Some("s3cr3t"));
+    let Destination::Http(ref hook) = config.outputs[2] else {
+        panic!("expected an http output");
+    };
+    assert_eq!(hook.cfg.url(), "https://siem.example.com/hook");
+    assert!(hook.gzip && hook.format == HttpFormat::Ndjson);
+
+    // an empty list is no output at all
+    assert!(StrIEMConfig::from_yaml("outputs: []").is_err());
+}
+
+#[test]
+fn test_api_metrics_toggle() {
+    let config = StrIEMConfig::from_yaml(
+        r#"
+      api:
+        address: 127.0.0.1:8080
+        metrics:
+          enabled: false
+      storage:
+        schema: ocsf/schema
+        path: data/ocsf
+    "#,
+    )
+    .unwrap();
+    assert!(!config.api.metrics.enabled);
+    assert!(api::ApiConfig::default().metrics.enabled);
+}
+
+#[test]
+fn test_vector_server_options() {
+    use crate::input::{Listener, ServerOptions};
+
+    let config = r#"
+      input:
+        vector:
+          address: 0.0.0.0:50050
+          acknowledgements: true
+          compression: zstd
+          server:
+            channel_capacity: 1024
+            max_concurrent_streams: 32
+            tcp_nodelay: true
+      storage:
+        schema: ocsf/schema
+        path: data/ocsf
+    "#;
+    let config = StrIEMConfig::from_yaml(config).unwrap();
+    let Listener::Vector(vector) = config.input else {
+        panic!("expected a vector listener");
+    };
+    assert_eq!(vector.cfg.address().port(), 50050);
+    assert_eq!(vector.server.channel_capacity, 1024);
+    assert_eq!(vector.server.max_concurrent_streams, Some(32));
+    assert!(vector.server.tcp_nodelay);
+    assert_eq!(
+        vector.server.max_decoding_message_size,
+        ServerOptions::default().max_decoding_message_size
+    );
+    assert!(vector.acknowledgements);
+    assert_eq!(vector.ack_timeout_secs, 30);
+    assert_eq!(vector.compression, crate::Compression::Zstd);
+
+    let Listener::Vector(vector) = Listener::default() else {
+        panic!("expected a vector listener");
+    };
+    assert_eq!(vector.server, ServerOptions::default());
+    assert!(!vector.acknowledgements);
+    assert_eq!(vector.compression, crate::Compression::None);
+}
+
+#[test]
+fn test_read_detections_block() {
+    let config = r#"
+      detections:
+        paths: /path/to/sigmarules
+        dedup_window_secs: 60
+        dedup_fields:
+          - src_endpoint.ip
+        dedup_rules:
+          noisy-rule: 600
+        workers: 2
+      storage:
+        schema: ocsf/schema
+        path: data/ocsf
+    "#;
+    let config = StrIEMConfig::from_yaml(config).unwrap();
+    let detections = config.detections.unwrap();
+
+    assert_eq!(
+        detections.paths,
+        StringOrList::String("/path/to/sigmarules".into())
+    );
+    assert_eq!(detections.dedup_window_secs, Some(60));
+    assert_eq!(detections.dedup_fields, vec!["src_endpoint.ip".to_string()]);
+    assert_eq!(detections.dedup_rules.get("noisy-rule"), Some(&600));
+    assert_eq!(detections.dedup_max_entries, 10_000);
+    assert!(detections.dedup_enabled());
+    assert_eq!(detections.workers, 2);
+
+    let shorthand = detections::DetectionsConfig::from(StringOrList::String("rules".into()));
+    assert!((1..=4).contains(&shorthand.workers));
+}
 #[test]
 fn test_env() {
-    std::env::set_var("STRIEM_SOURCE_VECTOR_ADDRESS", "1.2.3.4:1234");
-    std::env::set_var("STRIEM_DETECTIONS", "/path/to/sigmarules");
-    std::env::set_var("STRIEM_OUTPUT_VECTOR_ADDRESS", "1.2.3.4:1234");
-    std::env::set_var("STRIEM_STORAGE_SCHEMA", "ocsf/schema");
-    std::env::set_var("STRIEM_STORAGE_PATH", "data/ocsf");
-    let cfg = StrIEMConfig::default();
-    println!("{:?}", cfg);
-}
-*/
+    use config::FileFormat;
+
+    use crate::{input::Listener, output::Destination};
+
+    let file = r#"
+      output:
+        vector:
+          url: http://127.0.0.1:6000
+      storage:
+        schema: ocsf/schema
+        path: data/ocsf
+    "#;
+    let read = |vars: &[(&str, &str)]| {
+        StrIEMConfig::from_str_with_env(
+            file,
+            FileFormat::Yaml,
+            env::Environment::with_vars(vars.iter().copied()),
+        )
+        .unwrap()
+    };
+
+    // nested keys, including ones with underscores in their names
+    let config = read(&[
+        ("STRIEM__OUTPUT__VECTOR__HEC__ADDRESS", "1.2.3.4:6600"),
+        ("STRIEM__STORAGE__RETENTION__INTERVAL_SECS", "60"),
+        ("STRIEM__API__VECTOR_INTERPOLATE", "true"),
+        ("STRIEM__INPUT__VECTOR__ADDRESS", "0.0.0.0:3000"),
+        ("STRIEM__INPUT__VECTOR__SERVER__CHANNEL_CAPACITY", "1024"),
+    ]);
+    let Destination::Vector(vector) = &config.outputs[0] else {
+        panic!("expected a vector output");
+    };
+    assert_eq!(vector.cfg.url(), "http://127.0.0.1:6000/");
+    assert_eq!(
+        vector.hec.as_ref().unwrap().address().to_string(),
+        "1.2.3.4:6600"
+    );
+    assert_eq!(config.storage.as_ref().unwrap().retention.interval_secs, 60);
+    assert!(config.api.vector_interpolate);
+    let Listener::Vector(listener) = &config.input else {
+        panic!("expected a vector listener");
+    };
+    assert_eq!(listener.cfg.address().to_string(), "0.0.0.0:3000");
+    assert_eq!(listener.server.channel_capacity, 1024);
+
+    // comma-separated lists
+    let config = read(&[
+        ("STRIEM__DETECTIONS", "/rules/a, /rules/b"),
+        ("STRIEM__STORAGE__SORT_BY", "time,class_uid"),
+    ]);
+    assert_eq!(
+        config.detections.unwrap().paths,
+        StringOrList::List(vec!["/rules/a".into(), "/rules/b".into()])
+    );
+    assert_eq!(
+        config.storage.unwrap().sort_by,
+        vec!["time".to_string(), "class_uid".to_string()]
+    );
+
+    // precedence: file < single-underscore alias < double-underscore
+    let path = |vars: &[(&str, &str)]| read(vars).storage.unwrap().path;
+    assert_eq!(path(&[]), PathBuf::from("data/ocsf"));
+    assert_eq!(
+        path(&[("STRIEM_STORAGE_PATH", "/alias")]),
+        PathBuf::from("/alias")
+    );
+    assert_eq!(
+        path(&[
+            ("STRIEM__STORAGE__PATH", "/nested"),
+            ("STRIEM_STORAGE_PATH", "/alias"),
+        ]),
+        PathBuf::from("/nested")
+    );
+
+    // variables that aren't configuration are left alone
+    let config = read(&[("STRIEM_REMAPS", "/remaps"), ("STRIEM__", "x")]);
+    assert_eq!(config.storage.unwrap().path, PathBuf::from("data/ocsf"));
+}
+
+#[test]
+fn test_storage_partitioning() {
+    use crate::storage::Partitioning;
+
+    let config = r#"
+      storage:
+        schema: ocsf/schema
+        path: data/ocsf
+    "#;
+    let config = StrIEMConfig::from_yaml(config).unwrap();
+    let storage = config.storage.unwrap();
+    assert_eq!(storage.partitioning, Partitioning::None);
+    assert!(!storage.strict);
+    assert!(!storage.keep_unclassified);
+    assert!(storage.sort_by.is_empty() && storage.bloom_filter_columns.is_empty());
+    assert_eq!(storage.queue.capacity, 64);
+    assert_eq!(storage.queue.policy, crate::storage::QueuePolicy::Block);
+
+    let config = r#"
+      storage:
+        schema: ocsf/schema
+        path: data/ocsf
+        partitioning: hourly
+    "#;
+    let config = StrIEMConfig::from_yaml(config).unwrap();
+    assert_eq!(config.storage.unwrap().partitioning, Partitioning::Hourly);
+}
+
+#[test]
+fn test_mcp_servers() {
+    let config = r#"
+      api:
+        address: 127.0.0.1:8080
+        mcp:
+          url: http://edr.local:8000/mcp
+    "#;
+    let config = StrIEMConfig::from_yaml(config).unwrap();
+    let mcp = config.api.mcp.unwrap();
+    assert_eq!(
+        mcp.servers().unwrap(),
+        vec![(
+            "edr.local".to_string(),
+            "http://edr.local:8000/mcp".to_string()
+        )]
+    );
+    assert_eq!(
+        mcp.timeout_secs,
+        striem_common::prelude::DEFAULT_MCP_TIMEOUT_SECS
+    );
+    assert_eq!(
+        mcp.retry_secs,
+        striem_common::prelude::DEFAULT_MCP_RETRY_SECS
+    );
+
+    let config = r#"
+      api:
+        address: 127.0.0.1:8080
+        mcp:
+          url:
+            - http://edr.local:8000/mcp
+            - alias: fw/main
+              url: http://10.0.0.1/mcp
+            - alias: edr-2
+              url: http://edr.local:9000/mcp
+    "#;
+    let config = StrIEMConfig::from_yaml(config).unwrap();
+    let aliases = config
+        .api
+        .mcp
+        .unwrap()
+        .servers()
+        .unwrap()
+        .into_iter()
+        .map(|(alias, _)| alias)
+        .collect::<Vec<_>>();
+    assert_eq!(aliases, vec!["edr.local", "fw-main", "edr-2"]);
+
+    // servers sharing an alias, derived or given, are refused
+    for urls in [
+        "[http://edr.local:8000/mcp, http://edr.local:9000/mcp]",
+        "[http://edr.local:8000/mcp, {alias: edr.local, url: http://10.0.0.1/mcp}]",
+        "[{alias: fw/main, url: http://10.0.0.1/mcp}, {alias: fw-main, url: http://10.0.0.2/mcp}]",
+    ] {
+        let config = format!(
+            "api:\n  address: 127.0.0.1:8080\n  mcp:\n    url: {}\n",
+            urls
+        );
+        let err = StrIEMConfig::from_yaml(&config).unwrap_err().to_string();
+        assert!(err.contains("share the alias"), "{}", err);
+    }
+}
+
+#[test]
+fn test_host_config_addresses() {
+    let host = |yaml: &str| serde_yaml::from_str::<HostConfig>(yaml).unwrap();
+
+    // (config, address, url)
+    let cases = [
+        (
+            "address: 0.0.0.0:9000",
+            "0.0.0.0:9000",
+            "http://localhost:9000",
+        ),
+        // port 0 is left for the OS to pick
+        ("address: 127.0.0.1:0", "127.0.0.1:0", "http://127.0.0.1:0"),
+        (
+            "{address: 10.0.0.1:0, port: 7000}",
+            "10.0.0.1:7000",
+            "http://10.0.0.1:7000",
+        ),
+        ("address: '[::]:9000'", "[::]:9000", "http://localhost:9000"),
+        ("address: '[::1]:9000'", "[::1]:9000", "http://[::1]:9000"),
+        (
+            "{address: 127.0.0.1:9000, scheme: https}",
+            "127.0.0.1:9000",
+            "https://127.0.0.1:9000",
+        ),
+        (
+            "{address: 127.0.0.1:6000, scheme: grpc}",
+            "127.0.0.1:6000",
+            "grpc://127.0.0.1:6000",
+        ),
+        (
+            "url: http://127.0.0.1:6000",
+            "127.0.0.1:6000",
+            "http://127.0.0.1:6000/",
+        ),
+        (
+            "url: 'http://[::1]:6000'",
+            "[::1]:6000",
+            "http://[::1]:6000/",
+        ),
+        (
+            "{url: 'http://127.0.0.1', port: 8080}",
+            "127.0.0.1:8080",
+            "http://127.0.0.1/",
+        ),
+        (
+            "url: http://localhost:6000",
+            "127.0.0.1:6000",
+            "http://localhost:6000/",
+        ),
+        // a configured URL keeps its own scheme
+        (
+            "{url: https://localhost:6000, scheme: grpc}",
+            "127.0.0.1:6000",
+            "https://localhost:6000/",
+        ),
+    ];
+    for (config, address, url) in cases {
+        let host = host(config);
+        assert_eq!(
+            host.try_address().unwrap().to_string(),
+            address,
+            "{}",
+            config
+        );
+        assert_eq!(host.address().to_string(), address, "{}", config);
+        assert_eq!(host.url(), url, "{}", config);
+    }
+
+    // A URL host is resolved, preferring IPv4 unless asked otherwise
+    let v4 = host("url: http://localhost:6000");
+    assert!(v4.try_address().unwrap().is_ipv4());
+    let v6 = host("{url: 'http://localhost:6000', prefer_ipv6: true}");
+    let resolved: Vec<_> = std::net::ToSocketAddrs::to_socket_addrs(&("localhost", 6000))
+        .unwrap()
+        .collect();
+    assert_eq!(
+        v6.try_address().unwrap().is_ipv6(),
+        resolved.iter().any(|a| a.is_ipv6())
+    );
+
+    // Hosts that can't be resolved are an error, not 0.0.0.0
+    let unresolvable = host("url: http://striem.invalid:6000");
+    assert!(matches!(
+        unresolvable.try_address(),
+        Err(AddressError::Resolve { .. } | AddressError::NoAddresses(_))
+    ));
+    assert_eq!(unresolvable.address().to_string(), "0.0.0.0:6000");
+    assert_eq!(unresolvable.url(), "http://striem.invalid:6000/");
+
+    assert!(matches!(
+        host("url: 'unix:/run/vector.sock'").try_address(),
+        Err(AddressError::NoHost(_))
+    ));
+
+    assert!(serde_yaml::from_str::<HostConfig>("port: 9000").is_err());
+    assert!(serde_yaml::from_str::<HostConfig>("{address: 127.0.0.1:1, scheme: ftp}").is_err());
+}
+
+#[test]
+fn test_validate_deep() {
+    use crate::validate::ValidateOptions;
+
+    let dir = std::env::temp_dir().join(format!("striem-validate-{}", std::process::id()));
+    let rules = dir.join("rules");
+    let schema = dir.join("schema");
+    std::fs::create_dir_all(rules.join("nested")).unwrap();
+    std::fs::create_dir_all(&schema).unwrap();
+    std::fs::write(rules.join("good.yml"), "title: ok\ndetection: {}\n").unwrap();
+    std::fs::write(rules.join("nested/bad.yaml"), "title: [unclosed\n").unwrap();
+    std::fs::write(rules.join("README.md"), "not a rule").unwrap();
+
+    let config = StrIEMConfig::from_yaml(&format!(
+        r#"
+      detections:
+        - {}
+        - {}
+      input:
+        vector:
+          address: 127.0.0.1:0
+      api:
+        enabled: false
+      output:
+        vector:
+          url: http://127.0.0.1:6000
+      storage:
+        schema: {}
+        path: {}
+    "#,
+        rules.display(),
+        dir.join("missing").display(),
+        schema.display(),
+        dir.join("storage").display(),
+    ))
+    .unwrap();
+
+    let report = config.validate_deep(ValidateOptions::default());
+    let errors: Vec<String> = report.errors.iter().map(|i| i.to_string()).collect();
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+    assert!(errors[0].starts_with("detections: ") && errors[0].contains("bad.yaml"));
+    assert!(errors[1].contains("missing does not exist"));
+    assert_eq!(report.rule_files.len(), 2);
+    assert!(report.warnings.iter().any(|i| i.section == "storage"));
+    assert_eq!(report.errors_in(&["storage", "outputs"]).count(), 0);
+
+    // A listener that's already taken can't be bound, unless binding is skipped
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let config = StrIEMConfig::from_yaml(&format!(
+        r#"
+      input:
+        vector:
+          address: {}
+      output:
+        http:
+          url: http://127.0.0.1:8088/ingest
+      api:
+        enabled: false
+    "#,
+        taken.local_addr().unwrap()
+    ))
+    .unwrap();
+    let report = config.validate_deep(ValidateOptions::default());
+    assert_eq!(report.errors_in(&["input"]).count(), 1);
+    let report = config.validate_deep(ValidateOptions { bind: false });
+    assert_eq!(report.errors_in(&["input"]).count(), 0);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_overrides() {
+    let dir = std::env::temp_dir().join(format!("striem-overrides-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("striem.yaml");
+    std::fs::write(
+        &file,
+        r#"
+      input:
+        vector:
+          address: 0.0.0.0:50050
+      api:
+        address: 127.0.0.1:8080
+      output:
+        http:
+          url: http://127.0.0.1:8088/ingest
+    "#,
+    )
+    .unwrap();
+
+    let config = StrIEMConfig::from_files_with_overrides(
+        vec![file.clone()],
+        &[
+            (
+                "input.vector.address".to_string(),
+                "0.0.0.0:7000".to_string(),
+            ),
+            ("api.address".to_string(), "127.0.0.1:9999".to_string()),
+        ],
+    )
+    .unwrap();
+    assert_eq!(config.input.address().port(), 7000);
+    assert_eq!(config.api.host.address().port(), 9999);
+    assert_eq!(config.files, vec![file]);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_config_changes() {
+    use crate::reload::requires_restart;
+
+    let base = r#"
+      input:
+        vector:
+          address: 0.0.0.0:50050
+      output:
+        http:
+          url: http://127.0.0.1:8088/ingest
+      storage:
+        schema: /data/schema
+        path: /data/storage
+    "#;
+    let current = StrIEMConfig::from_yaml(base).unwrap();
+    assert!(current.changes(&current.clone()).unwrap().is_empty());
+    assert!(!current.watch_config);
+
+    let updated =
+        StrIEMConfig::from_yaml(&format!("{}\n      watch_config: true\n", base)).unwrap();
+    assert_eq!(current.changes(&updated).unwrap(), vec!["watch_config"]);
+
+    assert_eq!(current.shutdown_grace_secs, 30);
+    let updated =
+        StrIEMConfig::from_yaml(&format!("{}\n      shutdown_grace_secs: 5\n", base)).unwrap();
+    assert_eq!(updated.shutdown_grace_secs, 5);
+    assert_eq!(
+        current.changes(&updated).unwrap(),
+        vec!["shutdown_grace_secs"]
+    );
+
+    let moved = StrIEMConfig::from_yaml(&base.replace("50050", "50051")).unwrap();
+    let changes = current.changes(&moved).unwrap();
+    assert!(
+        changes.iter().any(|key| requires_restart(key)),
+        "{:?}",
+        changes
+    );
+
+    assert!(requires_restart("api.address"));
+    assert!(requires_restart("input.http"));
+    assert!(!requires_restart("api.mcp.url"));
+    assert!(!requires_restart("storage.path"));
+    assert!(!requires_restart("input.vector.acknowledgements"));
+}
+
+#[test]
+fn test_detection_sources() {
+    use std::path::{Path, PathBuf};
+
+    use crate::detections::{DetectionSource, DetectionsConfig};
+
+    assert_eq!(
+        DetectionSource::parse("./rules"),
+        DetectionSource::Path(PathBuf::from("./rules"))
+    );
+    assert_eq!(
+        DetectionSource::parse(
+            "git+https://github.com/SigmaHQ/sigma.git@r2024-11-10#rules/windows"
+        ),
+        DetectionSource::Git {
+            url: "https://github.com/SigmaHQ/sigma.git".to_string(),
+            reference: Some("r2024-11-10".to_string()),
+            subdir: Some("rules/windows".to_string()),
+        }
+    );
+    // user info isn't a ref
+    let source = DetectionSource::parse("git+https://bot@git.example.com/rules.git");
+    assert_eq!(
+        source,
+        DetectionSource::Git {
+            url: "https://bot@git.example.com/rules.git".to_string(),
+            reference: None,
+            subdir: None,
+        }
+    );
+    let cache = source.cache_dir(Path::new("/data")).unwrap();
+    assert!(cache.starts_with("/data/rules-cache"));
+    assert_eq!(source.rules_dir(Path::new("/data")), cache);
+    assert_eq!(source.pack(), "rules");
+    assert_eq!(DetectionSource::parse("./data/custom/").pack(), "custom");
+
+    let config = DetectionsConfig::from(StringOrList::List(vec![
+        "./rules".to_string(),
+        "git+https://github.com/SigmaHQ/sigma.git#rules".to_string(),
+    ]));
+    assert_eq!(config.sources().len(), 2);
+    assert_eq!(config.upload_dir(), None);
+    let local = DetectionsConfig::from(StringOrList::String("./rules".to_string()));
+    assert_eq!(local.upload_dir(), Some(PathBuf::from("./rules")));
+}
+
+#[test]
+fn test_storage_overrides() {
+    use std::path::{Path, PathBuf};
+
+    let config = r#"
+      input:
+        vector:
+          address: 0.0.0.0:50050
+      storage:
+        schema: ocsf/schema
+        path: /data/striem
+        overrides:
+          findings: /secure/striem
+          authentication: /secure/auth
+    "#;
+    let config = StrIEMConfig::from_yaml(config).unwrap();
+    let storage = config.storage.as_ref().unwrap();
+    assert_eq!(
+        storage.base_for("findings", "detection_finding"),
+        Path::new("/secure/striem")
+    );
+    assert_eq!(
+        storage.base_for("iam", "authentication"),
+        Path::new("/secure/auth")
+    );
+    assert_eq!(
+        storage.base_for("network", "dns_activity"),
+        Path::new("/data/striem")
+    );
+    assert_eq!(
+        storage.roots(),
+        vec![
+            PathBuf::from("/data/striem"),
+            PathBuf::from("/secure/auth"),
+            PathBuf::from("/secure/striem"),
+        ]
+    );
+    assert!(storage.check_overrides().is_ok());
+
+    let mut nested = storage.clone();
+    nested
+        .overrides
+        .insert("network".to_string(), PathBuf::from("/data/striem/network"));
+    let err = nested.check_overrides().unwrap_err();
+    assert!(err.contains("nested"), "{}", err);
+
+    let mut remote = storage.clone();
+    remote.uri = Some("s3://bucket/striem".to_string());
+    assert!(remote.check_overrides().is_err());
+    assert_eq!(
+        remote.root_for("findings", "detection_finding"),
+        PathBuf::from("s3://bucket/striem")
+    );
+}
+
+#[test]
+fn test_storage_sampling() {
+    let config = |sampling: &str| {
+        StrIEMConfig::from_yaml(&format!(
+            r#"
+      input:
+        vector:
+          address: 0.0.0.0:50050
+      storage:
+        schema: ocsf/schema
+        path: /data/striem
+        sampling:
+          {}
+    "#,
+            sampling
+        ))
+    };
+
+    let sampled = config("{network: 0.1, dns_activity: 0.5}").unwrap();
+    let storage = sampled.storage.as_ref().unwrap();
+    assert_eq!(storage.sample_ratio("network", "network_activity"), 0.1);
+    assert_eq!(storage.sample_ratio("network", "dns_activity"), 0.5);
+    assert_eq!(storage.sample_ratio("iam", "authentication"), 1.0);
+
+    let err = config("{network: 1.5}").unwrap_err();
+    assert!(err.to_string().contains("storage.sampling"), "{}", err);
+    assert!(config("{network: -0.1}").is_err());
+}
+
+#[test]
+fn test_storage_compaction_schedule() {
+    let config = |compaction: &str| {
+        StrIEMConfig::from_yaml(&format!(
+            r#"
+      input:
+        vector:
+          address: 0.0.0.0:50050
+      storage:
+        schema: ocsf/schema
+        path: /data/striem
+        compaction: {}
+    "#,
+            compaction
+        ))
+    };
+
+    let unscheduled = config("{}").unwrap();
+    let compaction = &unscheduled.storage.as_ref().unwrap().compaction;
+    assert_eq!(compaction.scheduled_minute(), Ok(None));
+    assert_eq!(compaction.min_age_secs, 3600);
+
+    let scheduled = config("{schedule: \"02:30\", min_age_secs: 7200}").unwrap();
+    let compaction = &scheduled.storage.as_ref().unwrap().compaction;
+    assert_eq!(compaction.scheduled_minute(), Ok(Some(150)));
+    assert_eq!(compaction.min_age_secs, 7200);
+
+    let err = config("{schedule: \"2:30am\"}").unwrap_err();
+    assert!(err.to_string().contains("storage.compaction"), "{}", err);
+    assert!(config("{schedule: \"24:00\"}").is_err());
+}
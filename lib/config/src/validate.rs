@@ -0,0 +1,318 @@
+//! Validation beyond what deserializing a configuration checks.
+//!
+//! [`StrIEMConfig::validate_deep`] looks at what the configuration points
+//! to: that the storage schema and path are usable, that detection
+//! directories exist and hold rule files that parse as YAML, that the
+//! enrichment files are there and well-formed, that listener addresses can
+//! be bound, and that output URLs are well-formed. Problems
+//! are reported per configuration section, errors and warnings apart, so
+//! callers can decide which sections they care about.
+
+use std::{
+    fmt::Display,
+    net::{IpAddr, TcpListener},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use url::Url;
+
+use crate::{HostConfig, StrIEMConfig, detections::DetectionSource};
+
+/// What [`StrIEMConfig::validate_deep`] checks
+#[derive(Debug, Clone, Copy)]
+pub struct ValidateOptions {
+    /// Try binding the listener addresses; off when StrIEM already has them
+    pub bind: bool,
+}
+
+impl Default for ValidateOptions {
+    fn default() -> Self {
+        ValidateOptions { bind: true }
+    }
+}
+
+/// A problem found in one configuration section
+#[derive(Debug, Clone, Serialize)]
+pub struct Issue {
+    /// Top-level configuration key, e.g. `storage`
+    pub section: &'static str,
+    pub message: String,
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.section, self.message)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ValidationReport {
+    pub errors: Vec<Issue>,
+    pub warnings: Vec<Issue>,
+    /// Rule files found in the detection directories
+    pub rule_files: Vec<PathBuf>,
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn error(&mut self, section: &'static str, message: impl Into<String>) {
+        self.errors.push(Issue {
+            section,
+            message: message.into(),
+        });
+    }
+
+    pub fn warning(&mut self, section: &'static str, message: impl Into<String>) {
+        self.warnings.push(Issue {
+            section,
+            message: message.into(),
+        });
+    }
+
+    /// Errors in any of `sections`
+    pub fn errors_in<'a>(&'a self, sections: &'a [&str]) -> impl Iterator<Item = &'a Issue> {
+        self.errors
+            .iter()
+            .filter(|issue| sections.contains(&issue.section))
+    }
+}
+
+/// Whether `path` is a rule file
+fn is_rule_file(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("yml" | "yaml")
+    )
+}
+
+/// Check every document in `s` is YAML; rule files may hold several
+fn parse_yaml(s: &str) -> Result<(), serde_yaml::Error> {
+    use serde::Deserialize;
+    for document in serde_yaml::Deserializer::from_str(s) {
+        serde_yaml::Value::deserialize(document)?;
+    }
+    Ok(())
+}
+
+/// Rule files under `dir`, recursively
+fn rule_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            rule_files(&path, files)?;
+        } else if is_rule_file(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Check `path` is a directory that can be listed
+fn check_dir(report: &mut ValidationReport, section: &'static str, what: &str, path: &Path) {
+    if !path.exists() {
+        report.error(
+            section,
+            format!("{} {} does not exist", what, path.display()),
+        );
+    } else if !path.is_dir() {
+        report.error(
+            section,
+            format!("{} {} is not a directory", what, path.display()),
+        );
+    } else if let Err(e) = std::fs::read_dir(path) {
+        report.error(
+            section,
+            format!("{} {} is not readable: {}", what, path.display(), e),
+        );
+    }
+}
+
+/// Check a listener address resolves and, with `bind`, can be bound
+fn check_listener(
+    report: &mut ValidationReport,
+    section: &'static str,
+    host: &HostConfig,
+    bind: bool,
+) {
+    match host.try_address() {
+        Err(e) => report.error(section, e.to_string()),
+        Ok(address) if bind => {
+            if let Err(e) = TcpListener::bind(address) {
+                report.error(section, format!("cannot listen on {}: {}", address, e));
+            }
+        }
+        Ok(_) => {}
+    }
+}
+
+/// Check an output URL parses, has a host, and that the host resolves
+fn check_url(report: &mut ValidationReport, section: &'static str, host: &HostConfig) {
+    let url = host.url();
+    match Url::parse(&url) {
+        Err(e) => report.error(section, format!("invalid URL {}: {}", url, e)),
+        Ok(parsed) if parsed.host().is_none() => {
+            report.error(section, format!("URL {} has no host", url))
+        }
+        Ok(_) => {
+            if let Err(e) = host.try_address() {
+                report.warning(section, e.to_string());
+            }
+        }
+    }
+}
+
+/// Whether `s` is an address and prefix length, e.g. `10.0.0.0/8`
+fn is_cidr(s: &str) -> bool {
+    let Some((addr, len)) = s.split_once('/') else {
+        return false;
+    };
+    match (addr.parse::<IpAddr>(), len.parse::<u8>()) {
+        (Ok(IpAddr::V4(_)), Ok(len)) => len <= 32,
+        (Ok(IpAddr::V6(_)), Ok(len)) => len <= 128,
+        _ => false,
+    }
+}
+
+impl StrIEMConfig {
+    /// Check what the configuration refers to; see [`crate::validate`]
+    pub fn validate_deep(&self, options: ValidateOptions) -> ValidationReport {
+        let mut report = ValidationReport::default();
+
+        if let Some(storage) = &self.storage {
+            check_dir(&mut report, "storage", "schema", &storage.schema);
+            if storage.path.exists() {
+                check_dir(&mut report, "storage", "path", &storage.path);
+            } else {
+                report.warning(
+                    "storage",
+                    format!("path {} will be created", storage.path.display()),
+                );
+            }
+            for (name, path) in &storage.overrides {
+                if path.exists() {
+                    check_dir(&mut report, "storage", &format!("{} path", name), path);
+                } else {
+                    report.warning(
+                        "storage",
+                        format!("{} path {} will be created", name, path.display()),
+                    );
+                }
+            }
+            if let Some(uri) = &storage.uri
+                && let Err(e) = Url::parse(uri)
+            {
+                report.error("storage", format!("invalid uri {}: {}", uri, e));
+            }
+        }
+
+        if let Some(enrichment) = &self.enrichment {
+            // both fail open at runtime, but a broken assets file is a mistake
+            if let Some(geoip) = &enrichment.geoip
+                && !geoip.database.is_file()
+            {
+                report.warning(
+                    "enrichment",
+                    format!(
+                        "GeoIP database {} not found, GeoIP enrichment will be skipped",
+                        geoip.database.display()
+                    ),
+                );
+            }
+            if let Some(assets) = &enrichment.assets {
+                match assets.load() {
+                    Ok(ranges) => {
+                        for range in ranges.iter().filter(|r| !is_cidr(&r.cidr)) {
+                            report.error(
+                                "enrichment",
+                                format!("{}: invalid CIDR {}", assets.path.display(), range.cidr),
+                            );
+                        }
+                    }
+                    Err(e) => report.error("enrichment", format!("{:#}", e)),
+                }
+            }
+        }
+
+        if let Some(detections) = &self.detections {
+            let db = self.db.clone().unwrap_or_else(|| PathBuf::from("."));
+            let mut dirs = Vec::new();
+            for source in detections.sources() {
+                match &source {
+                    DetectionSource::Path(path) => dirs.push(path.clone()),
+                    DetectionSource::Git { url, .. } => match Url::parse(url) {
+                        Err(e) => report.error("detections", format!("invalid URL {}: {}", url, e)),
+                        // synced at startup; only a cached clone can be checked
+                        Ok(_) if source.rules_dir(&db).is_dir() => dirs.push(source.rules_dir(&db)),
+                        Ok(_) => {
+                            report.warning("detections", format!("{} has not been cloned yet", url))
+                        }
+                    },
+                }
+            }
+            for dir in dirs {
+                check_dir(&mut report, "detections", "rule directory", &dir);
+                let mut files = Vec::new();
+                if dir.is_dir()
+                    && let Err(e) = rule_files(&dir, &mut files)
+                {
+                    report.error(
+                        "detections",
+                        format!("could not list {}: {}", dir.display(), e),
+                    );
+                }
+                if dir.is_dir() && files.is_empty() {
+                    report.warning("detections", format!("no rule files in {}", dir.display()));
+                }
+                for file in &files {
+                    let parsed = std::fs::read_to_string(file)
+                        .map_err(|e| e.to_string())
+                        .and_then(|s| parse_yaml(&s).map_err(|e| e.to_string()));
+                    if let Err(e) = parsed {
+                        report.error("detections", format!("{}: {}", file.display(), e));
+                    }
+                }
+                report.rule_files.extend(files);
+            }
+        } else {
+            report.warning("detections", "no detection rules configured");
+        }
+
+        let input = match &self.input {
+            crate::input::Listener::Vector(vector) => &vector.cfg,
+            crate::input::Listener::Http(http) => &http.cfg,
+        };
+        check_listener(&mut report, "input", input, options.bind);
+        if self.api.enabled {
+            check_listener(&mut report, "api", &self.api.host, options.bind);
+            if let Some(tls) = &self.api.tls {
+                for file in [Some(&tls.cert), Some(&tls.key), tls.client_ca.as_ref()]
+                    .into_iter()
+                    .flatten()
+                {
+                    if !file.is_file() {
+                        report.error("api", format!("{} does not exist", file.display()));
+                    }
+                }
+            }
+            if let Some(auth) = &self.api.auth
+                && let Err(e) = auth.load()
+            {
+                report.error("api", e.to_string());
+            }
+        }
+
+        for output in &self.outputs {
+            let host = match output {
+                crate::output::Destination::Vector(vector) => &vector.cfg,
+                crate::output::Destination::Http(http) => &http.cfg,
+            };
+            check_url(&mut report, "outputs", host);
+        }
+
+        report
+    }
+}
@@ -20,6 +20,8 @@ fn main() {
     get_git_sha();
 
     let repo = format!("{}/ocsf-schema", std::env::var("OUT_DIR").unwrap());
+    let version = option_env!("OCSF_SCHEMA_VERSION").unwrap_or("1.4.0");
+    println!("cargo:rustc-env=OCSF_VERSION={}", version);
 
     // Clone or update the repository
     if !Path::new(&repo).exists() {
@@ -30,7 +32,7 @@ fn main() {
                 "--depth",
                 "1",
                 "--branch",
-                option_env!("OCSF_SCHEMA_VERSION").unwrap_or("1.4.0"),
+                version,
                 &repo,
             ])
             .status()
@@ -92,6 +94,7 @@ fn main() {
     output.push_str("#[repr(u32)]\n");
     output.push_str("pub enum Class {\n");
     output.push_str("    BaseEvent = 0,\n");
+    let mut variants = Vec::new();
     if let Some(attrs) = categories["attributes"].as_object() {
         for (cat_name, cat) in attrs {
             let cat_uid = cat["uid"].as_u64().unwrap();
@@ -116,6 +119,7 @@ fn main() {
                                 "    {} = {},\n",
                                 event_enum_name, combined_uid
                             ));
+                            variants.push(event_enum_name);
                         }
                     }
                 }
@@ -148,6 +152,7 @@ fn main() {
                                 "    {} = {},\n",
                                 event_enum_name, combined_uid
                             ));
+                            variants.push(event_enum_name);
                         }
                     }
                 }
@@ -156,6 +161,14 @@ fn main() {
     }
     output.push_str("}\n\n");
 
+    // Every class but BaseEvent, to check schema directories against
+    output.push_str("impl Class {\n");
+    output.push_str("    pub const ALL: &'static [Class] = &[\n");
+    for variant in &variants {
+        output.push_str(&format!("        Class::{},\n", variant));
+    }
+    output.push_str("    ];\n}\n\n");
+
     // Generate ToString implementation for Class
     output.push_str("impl ToString for Class {\n");
     output.push_str("    fn to_string(&self) -> String {\n");
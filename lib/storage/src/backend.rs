@@ -2,35 +2,120 @@
 //!
 //! Routes events to appropriate Parquet writers based on OCSF class_uid.
 //! Each OCSF class gets its own writer and directory structure:
-//! `{storage_path}/{category}/{class}/`
+//! `{storage_path}/{category}/{class}/`, optionally partitioned further into
+//! Hive-style `date=YYYY-MM-DD/[hour=HH/]` directories.
+//!
+//! With `storage.uri` set, finished files are uploaded to that object
+//! store in the same layout instead of being moved below the local path.
+//!
+//! Each writer is fed by its own queue and task (see [`queue`]), so a slow
+//! class doesn't hold up the others.
+//!
+//! In strict mode, events that don't fit their class schema are appended
+//! to dead-letter files instead (see [`dead_letter`]).
+//!
+//! Events that can't be routed to a class are dropped, or with
+//! `storage.keep_unclassified` kept under `other/raw/` (see [`raw`]).
+//!
+//! Switching partitioning only affects newly rotated files, so a class
+//! directory may hold both layouts; readers use recursive `**/*.parquet`
+//! globs and see both.
+//!
+//! Each writer has its own base path, `storage.path` or the class's entry in
+//! `storage.overrides`, so findings can live on a separate volume. A reload
+//! moves newly rotated files to the new base.
+//!
+//! Past days' files are merged by compaction, on `storage.compaction`'s
+//! schedule or on request (see [`compaction`]).
+//!
+//! Classes or categories in `storage.sampling` only have a share of their
+//! events stored (see [`sampling`]); detection findings are always stored.
 //!
 //! This organization enables efficient DuckDB queries by class/category
 //! and keeps related events together for better compression.
 
 use super::writer::Writer;
-use super::{ocsf, util::visit_dirs};
+use super::{
+    compaction, dead_letter, finding, ocsf,
+    queue::{Batch, ClassQueue},
+    raw,
+    remote::Remote,
+    retention, sampling,
+    util::class_schemas,
+};
 use anyhow::{Result, anyhow};
 use arc_swap::ArcSwap;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use parquet::arrow::parquet_to_arrow_schema;
 use serde_json::Value;
-use std::path::PathBuf;
-use std::{collections::HashMap, sync::Arc};
-use striem_common::SysMessage;
+use std::path::{Path, PathBuf};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use striem_common::event::Event;
-use striem_config::StrIEMConfig;
+use striem_common::{SysMessage, metrics};
+use striem_config::{
+    StrIEMConfig,
+    storage::{QueueConfig, StorageConfig},
+};
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::task::JoinHandle;
 
 /// Backend managing multiple Parquet writers, one per OCSF class.
 /// Writers are selected at runtime based on event's class_uid field.
 pub struct ParquetBackend {
     config: Arc<ArcSwap<StrIEMConfig>>,
-    path: Arc<ArcSwap<PathBuf>>,
+    /// Base path of each writer, by storage category and class name
+    bases: Vec<Base>,
+    /// Events rejected by strict conversion go to the dead-letter files
+    strict: bool,
+    queue: QueueConfig,
+    /// Writers until [`ParquetBackend::run`] hands them to their queues
     pub heap: HashMap<ocsf::Class, Writer>,
+    queues: HashMap<ocsf::Class, ClassQueue>,
+    /// Catch-all writer for unclassified events, if they are kept
+    unclassified: Option<Writer>,
+    unclassified_queue: Option<ClassQueue>,
+}
+
+/// A writer's base path, re-read from the configuration on reload
+struct Base {
+    category: String,
+    class: String,
+    path: Arc<ArcSwap<PathBuf>>,
+}
+
+impl Base {
+    fn new(storage: &StorageConfig, category: &str, class: &str) -> Self {
+        Base {
+            category: category.to_string(),
+            class: class.to_string(),
+            path: Arc::new(ArcSwap::from_pointee(
+                storage.base_for(category, class).to_path_buf(),
+            )),
+        }
+    }
+
+    fn reload(&self, storage: &StorageConfig) {
+        let path = storage.base_for(&self.category, &self.class);
+        if **self.path.load() != path {
+            info!(
+                "{}/{} files now go to {}",
+                self.category,
+                self.class,
+                path.display()
+            );
+            self.path.store(Arc::new(path.to_path_buf()));
+        }
+    }
 }
 
 impl std::fmt::Debug for ParquetBackend {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "ParquetBackend {{ heap: {:?} }}", self.heap.keys())
+        write!(
+            f,
+            "ParquetBackend {{ heap: {:?}, queues: {:?} }}",
+            self.heap.keys(),
+            self.queues.keys()
+        )
     }
 }
 
@@ -40,6 +125,9 @@ impl ParquetBackend {
     /// # Schema Discovery
     /// Recursively scans schema directory for `.parquet` schema files.
     /// Schema file name (minus extension) must match OCSF class name.
+    /// Every file that can't be read or parsed is listed in the one error;
+    /// a schema whose name isn't an OCSF class is skipped, and it and
+    /// classes without a schema are warned about.
     ///
     /// # Directory Structure
     /// Output path: `{out}/{category}/{class}/`
@@ -48,22 +136,31 @@ impl ParquetBackend {
     /// This structure is optimized for DuckDB's glob patterns:
     /// `SELECT * FROM './storage/iam/**/*.parquet'`
     pub fn new(config: &Arc<ArcSwap<StrIEMConfig>>) -> Result<Self> {
-        let (path, schemapath) = config
+        let storage = config
             .load()
             .storage
-            .as_ref()
-            .map(|c| (c.path.clone(), c.schema.clone()))
+            .clone()
             .ok_or_else(|| anyhow!("storage path not set"))?;
+        let (schemapath, partitioning) = (storage.schema.clone(), storage.partitioning);
 
-        let path = Arc::new(ArcSwap::from_pointee(path));
+        let remote = storage
+            .uri
+            .as_deref()
+            .map(|uri| Remote::new(uri, &storage.options).map(Arc::new))
+            .transpose()?;
 
         let mut heap = HashMap::new();
+        let mut bases = Vec::new();
 
-        for (schema, filepath) in visit_dirs(&schemapath)? {
+        for (class, schema, filepath) in class_schemas(&schemapath)? {
             // Convert Parquet schema to Arrow schema and enrich with metadata
             // Metadata is preserved in Parquet files for debugging and lineage tracking
             let arrow_schema = Arc::new(
-                parquet_to_arrow_schema(&schema, None)?.with_metadata(HashMap::from([
+                finding::with_finding_columns(
+                    schema.name(),
+                    parquet_to_arrow_schema(&schema, None)?,
+                )
+                .with_metadata(HashMap::from([
                     (
                         "created_by".to_string(),
                         format!(
@@ -85,50 +182,235 @@ impl ParquetBackend {
 
             // Derive category from class_uid using OCSF's numeric scheme:
             // class_uid 3002 -> category 3 (IAM), class 2 (Authentication)
-            let class: ocsf::Class = schema.name().parse().map_err(|e: String| anyhow!(e))?;
             let category = ocsf::Category::try_from((class as u32 % 10000) / 1000)?;
+            let stem = filepath
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.split('.').next());
+            if stem.is_some_and(|stem| stem != schema.name()) {
+                warn!(
+                    "schema {} is for class {}; name the file after its class",
+                    filepath.display(),
+                    schema.name()
+                );
+            }
 
             let subpath = PathBuf::from(category.to_string()).join(class.to_string());
-            let writer = Writer::new(path.clone(), subpath, arrow_schema)?;
+            let base = Base::new(&storage, &category.to_string(), &class.to_string());
+            let mut writer = Writer::new(base.path.clone(), subpath, arrow_schema)?
+                .with_partitioning(partitioning)
+                .with_strict(storage.strict)
+                .with_sort_by(storage.sort_by.clone())
+                .with_bloom_filters(storage.bloom_filter_columns.clone())
+                .with_temp_dir(storage.temp_dir.clone())
+                .with_aligned_rotation(storage.rotate_aligned)
+                .with_rotation_jitter(Duration::from_secs(storage.rotation_jitter_secs));
+            if let Some(remote) = &remote {
+                writer = writer.with_remote(remote.clone());
+            }
 
             heap.insert(class, writer);
+            bases.push(base);
+        }
+
+        let unclassified = storage
+            .keep_unclassified
+            .then(|| {
+                let base = Base::new(&storage, raw::CATEGORY, raw::CLASS);
+                let mut writer = Writer::new(base.path.clone(), raw::subpath(), raw::schema())?
+                    .with_partitioning(partitioning)
+                    .with_strict(storage.strict)
+                    .with_sort_by(storage.sort_by.clone())
+                    .with_bloom_filters(storage.bloom_filter_columns.clone())
+                    .with_temp_dir(storage.temp_dir.clone())
+                    .with_aligned_rotation(storage.rotate_aligned)
+                    .with_rotation_jitter(Duration::from_secs(storage.rotation_jitter_secs));
+                if let Some(remote) = &remote {
+                    writer = writer.with_remote(remote.clone());
+                }
+                bases.push(base);
+                Ok::<_, anyhow::Error>(writer)
+            })
+            .transpose()?;
+
+        let missing = ocsf::Class::ALL
+            .iter()
+            .filter(|class| !heap.contains_key(*class))
+            .map(|class| class.to_string())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            warn!(
+                "no schema in {} for {} OCSF {} classes, whose events will be {}: {}",
+                schemapath.display(),
+                missing.len(),
+                crate::OCSF_VERSION,
+                match storage.keep_unclassified {
+                    true => "kept unclassified",
+                    false => "rejected",
+                },
+                missing.join(", ")
+            );
+        }
+
+        for name in storage.overrides.keys() {
+            if !bases
+                .iter()
+                .any(|b| &b.category == name || &b.class == name)
+            {
+                warn!(
+                    "storage.overrides: {} is not a storage category or class",
+                    name
+                );
+            }
+        }
+        for name in storage.sampling.keys() {
+            if name == finding::CLASS {
+                warn!("storage.sampling: detection findings are never sampled");
+            } else if !bases
+                .iter()
+                .any(|b| &b.category == name || &b.class == name)
+            {
+                warn!(
+                    "storage.sampling: {} is not a storage category or class",
+                    name
+                );
+            }
         }
 
         Ok(Self {
             heap,
-            path,
+            bases,
+            strict: storage.strict,
+            queue: storage.queue,
+            queues: HashMap::new(),
+            unclassified,
+            unclassified_queue: None,
             config: config.clone(),
         })
     }
 
+    /// Writer for events of `class_uid`, taken out for a one-off job that
+    /// writes outside [`ParquetBackend::run`]. [`Writer::flush`] it before
+    /// writing, to open its first file, and after, to finish the last.
+    pub fn take_writer(&mut self, class_uid: u32) -> Option<Writer> {
+        let class = ocsf::Class::try_from(class_uid).ok()?;
+        self.heap.remove(&class)
+    }
+
     /// Route and write a JSON event to the appropriate Parquet writer.
     ///
     /// # Routing Logic
     /// Extracts `class_uid` field from event to determine OCSF class.
-    /// Fails if class_uid is missing or unknown (no matching schema loaded).
+    /// Fails if class_uid is missing or unknown (no matching schema loaded),
+    /// unless unclassified events are kept.
     ///
     /// # Error Handling
     /// Returns error rather than silently dropping events to surface
     /// schema mismatches early in development.
     pub async fn write(&self, value: &Value) -> Result<()> {
-        let writer = value
-            .get("class_uid")
-            .and_then(|v| v.as_u64())
-            .and_then(|v| ocsf::Class::try_from(v as u32).ok())
-            .and_then(|k| self.heap.get(&k))
-            .ok_or(anyhow::anyhow!("invalid OCSF"))?;
+        let routed = self
+            .class_of(value)
+            .and_then(|k| Some((k.to_string(), self.heap.get(&k)?)));
+        let (class, writer, value) = match (routed, &self.unclassified) {
+            (Some((class, writer)), _) => (class, writer, value.clone()),
+            (None, Some(writer)) => (raw::CLASS.to_string(), writer, raw::row(&value.into())),
+            (None, None) => return Err(anyhow!("invalid OCSF")),
+        };
 
-        writer.write(value).await?;
+        writer.write(&value).await?;
+        metrics::increment("striem_storage_events_total", &[("class", &class)], 1);
 
         Ok(())
     }
 
+    /// OCSF class of an event, if a schema for it is loaded
+    fn class_of(&self, value: &Value) -> Option<ocsf::Class> {
+        value
+            .get("class_uid")
+            .and_then(|v| v.as_u64())
+            .and_then(|v| ocsf::Class::try_from(v as u32).ok())
+            .filter(|k| self.heap.contains_key(k) || self.queues.contains_key(k))
+    }
+
+    /// Route a received batch to the class queues.
     async fn process(&self, events: Arc<Vec<Event>>) {
-        for event in &*events {
-            if let Err(e) = self.write(&event.data).await {
-                error!("Failed to write event: {}", e);
+        let mut classes: HashMap<ocsf::Class, Vec<usize>> = HashMap::new();
+        let mut unclassified = Vec::new();
+        let mut unrouted = Vec::new();
+        for (i, event) in events.iter().enumerate() {
+            match self.class_of(&event.data) {
+                Some(class) => classes.entry(class).or_default().push(i),
+                None if self.unclassified_queue.is_some() => unclassified.push(i),
+                None => {
+                    error!("Failed to write event: invalid OCSF");
+                    unrouted.push(i);
+                }
             }
         }
+        let config = self.config.load();
+        let mut sampled = Vec::new();
+        if let Some(storage) = config.storage.as_ref().filter(|s| !s.sampling.is_empty()) {
+            for (class, indexes) in classes.iter_mut() {
+                let name = class.to_string();
+                // detection findings are never sampled
+                if name == finding::CLASS {
+                    continue;
+                }
+                let category = ocsf::Category::try_from((*class as u32 % 10000) / 1000)
+                    .map(|c| c.to_string())
+                    .unwrap_or_default();
+                let ratio = storage.sample_ratio(&category, &name);
+                if ratio >= 1.0 {
+                    continue;
+                }
+                let before = indexes.len();
+                indexes.retain(|i| {
+                    let keep = sampling::keep(&sampling::uid(&events[*i]), ratio);
+                    if !keep {
+                        sampled.push(*i);
+                    }
+                    keep
+                });
+                metrics::increment(
+                    "striem_storage_sampled_events_total",
+                    &[("class", &name)],
+                    (before - indexes.len()) as u64,
+                );
+            }
+        }
+
+        // Resending these wouldn't make them storable, so don't hold up
+        // their batch; sampled out events are as good as stored
+        Batch {
+            events: events.clone(),
+            indexes: unrouted,
+        }
+        .acknowledge(true);
+        Batch {
+            events: events.clone(),
+            indexes: sampled,
+        }
+        .acknowledge(true);
+
+        // Enqueue every class at once, so a full queue waiting out its
+        // timeout doesn't hold up the others
+        let unclassified = self
+            .unclassified_queue
+            .as_ref()
+            .filter(|_| !unclassified.is_empty())
+            .map(|queue| (queue, unclassified));
+        let sends = classes
+            .into_iter()
+            .filter(|(_, indexes)| !indexes.is_empty())
+            .filter_map(|(class, indexes)| Some((self.queues.get(&class)?, indexes)))
+            .chain(unclassified)
+            .map(|(queue, indexes)| {
+                queue.send(Batch {
+                    events: events.clone(),
+                    indexes,
+                })
+            });
+        futures_util::future::join_all(sends).await;
     }
 
     /// Run the backend with dual event stream subscription.
@@ -141,35 +423,100 @@ impl ParquetBackend {
     /// Detection findings inherit metadata from original events but get new UIDs.
     ///
     /// # Lifecycle
-    /// Spawns rotation tasks for all writers, then processes events until
-    /// shutdown or both channels close. Writer Drop impls handle final flushes.
+    /// Spawns rotation tasks for all writers, a write task per class and the
+    /// retention sweeper, then routes events until shutdown or either channel
+    /// closes. Batches missed by lagging behind a channel are counted and
+    /// skipped.
+    ///
+    /// On shutdown the batches already sent on both channels are routed,
+    /// each write task drains its queue, and every writer's current file is
+    /// finished; the returned handle completes once that's done. If the
+    /// channels close instead, Writer Drop impls handle final flushes.
     pub async fn run(
         mut self,
         mut upstream_rx: tokio::sync::broadcast::Receiver<Arc<Vec<Event>>>,
         mut internal_rx: tokio::sync::broadcast::Receiver<Arc<Vec<Event>>>,
         mut sys: tokio::sync::broadcast::Receiver<SysMessage>,
-    ) {
+    ) -> JoinHandle<()> {
         // Start rotation timers for all writers before processing events
-        for w in self.heap.values_mut() {
+        for w in self.heap.values_mut().chain(self.unclassified.as_mut()) {
             w.run().await.expect("Failed to start writer");
         }
+        let mut writers = Vec::new();
+        let mut tasks = Vec::new();
+        for (class, writer) in std::mem::take(&mut self.heap) {
+            let path = writer.base();
+            let writer = Arc::new(writer);
+            writers.push(writer.clone());
+            let strict = self.strict;
+            let (queue, task) = ClassQueue::spawn(class.to_string(), self.queue, move |batch| {
+                let writer = writer.clone();
+                let path = path.clone();
+                async move {
+                    let values = batch.values();
+                    let stored = write_class(
+                        &writer,
+                        &class.to_string(),
+                        &values.iter().map(AsRef::as_ref).collect::<Vec<_>>(),
+                        &path.load(),
+                        strict,
+                    )
+                    .await;
+                    batch.acknowledge(stored);
+                }
+            });
+            self.queues.insert(class, queue);
+            tasks.push(task);
+        }
+        if let Some(writer) = self.unclassified.take() {
+            let path = writer.base();
+            let writer = Arc::new(writer);
+            writers.push(writer.clone());
+            let strict = self.strict;
+            let (queue, task) =
+                ClassQueue::spawn(raw::CLASS.to_string(), self.queue, move |batch| {
+                    let writer = writer.clone();
+                    let path = path.clone();
+                    async move {
+                        let rows = batch
+                            .indexes
+                            .iter()
+                            .map(|i| raw::row(&batch.events[*i]))
+                            .collect::<Vec<_>>();
+                        let stored = write_class(
+                            &writer,
+                            raw::CLASS,
+                            &rows.iter().collect::<Vec<_>>(),
+                            &path.load(),
+                            strict,
+                        )
+                        .await;
+                        batch.acknowledge(stored);
+                    }
+                });
+            self.unclassified_queue = Some(queue);
+            tasks.push(task);
+        }
+        tokio::spawn(retention::run(self.config.clone(), sys.resubscribe()));
+        tokio::spawn(compaction::run(self.config.clone(), sys.resubscribe()));
+
         let config = self.config.clone();
         tokio::spawn(async move {
             loop {
                 tokio::select! {
                     // File finalization is handled by Writer's Drop implementation
-                    result = upstream_rx.recv() => {
-                        if let Ok(events) = result {
-                            self.process(events).await;
-                        } else {
+                    result = upstream_rx.recv() => match result {
+                        Ok(events) => self.process(events).await,
+                        Err(RecvError::Lagged(n)) => lagged("storage_upstream", n),
+                        Err(RecvError::Closed) => {
                             debug!("Upstream channel closed, shutting down ParquetBackend");
                             break;
                         }
                     },
-                    result = internal_rx.recv() => {
-                        if let Ok(events) = result {
-                            self.process(events).await;
-                        } else {
+                    result = internal_rx.recv() => match result {
+                        Ok(events) => self.process(events).await,
+                        Err(RecvError::Lagged(n)) => lagged("storage_internal", n),
+                        Err(RecvError::Closed) => {
                             debug!("Internal channel closed, shutting down ParquetBackend");
                             break;
                         }
@@ -177,15 +524,29 @@ impl ParquetBackend {
                     msg = sys.recv() => {
                         match msg {
                             Ok(SysMessage::Shutdown) => {
-                            info!("shutting down Parquet writer...");
-                            return;
+                                info!("shutting down Parquet writer...");
+                                self.drain(&mut upstream_rx, &mut internal_rx).await;
+                                // without their senders, write tasks end once
+                                // their queues are empty
+                                self.queues.clear();
+                                self.unclassified_queue = None;
+                                for task in tasks {
+                                    task.await.ok();
+                                }
+                                for writer in writers {
+                                    if let Err(e) = writer.close().await {
+                                        error!("failed to finish Parquet file: {}", e);
+                                    }
+                                }
+                                info!("Parquet writer stopped");
+                                return;
                             }
                             Ok(SysMessage::Reload) => {
                                 info!("reloading Parquet writer config...");
-                                if let Ok(path) = config.load().storage.as_ref()
-                                .map(|c| c.path.clone())
-                                .ok_or_else(|| anyhow!("storage path not set")) {
-                                    self.path.store(Arc::new(path));
+                                if let Some(storage) = config.load().storage.as_ref() {
+                                    for base in &self.bases {
+                                        base.reload(storage);
+                                    }
                                     // Schema reload not implemented yet
                                     info!("Parquet writer config reloaded");
                                 } else {
@@ -202,6 +563,81 @@ impl ParquetBackend {
                     }
                 };
             }
-        });
+        })
+    }
+
+    /// Route the batches already sent on both channels
+    async fn drain(
+        &self,
+        upstream_rx: &mut tokio::sync::broadcast::Receiver<Arc<Vec<Event>>>,
+        internal_rx: &mut tokio::sync::broadcast::Receiver<Arc<Vec<Event>>>,
+    ) {
+        for (rx, subscriber) in [
+            (upstream_rx, "storage_upstream"),
+            (internal_rx, "storage_internal"),
+        ] {
+            loop {
+                match rx.try_recv() {
+                    Ok(events) => self.process(events).await,
+                    Err(TryRecvError::Lagged(n)) => lagged(subscriber, n),
+                    Err(_) => break,
+                }
+            }
+        }
+    }
+}
+
+/// Record batches a storage subscriber missed by falling behind its channel.
+/// The missed events are not written; the backend keeps receiving.
+fn lagged(subscriber: &str, n: u64) {
+    metrics::increment(
+        "striem_broadcast_lagged_total",
+        &[("subscriber", subscriber)],
+        n,
+    );
+    warn!("{} subscriber lagged, {} batches not stored", subscriber, n);
+}
+
+/// Write one class's events, counting the outcome and dead-lettering
+/// rejected events in strict mode.
+///
+/// Returns whether the events are dealt with: written, dead-lettered, or
+/// skipped as unconvertible (which retrying wouldn't change).
+async fn write_class(
+    writer: &Writer,
+    class: &str,
+    values: &[&Value],
+    base: &Path,
+    strict: bool,
+) -> bool {
+    let labels = [("class", class)];
+    let (written, stored) = match writer.write_batch(values).await {
+        Ok(rejected) => {
+            let mut stored = true;
+            if strict {
+                let rejected = rejected
+                    .iter()
+                    .map(|(i, e)| (values[*i], e.as_str()))
+                    .collect::<Vec<_>>();
+                if let Err(e) = dead_letter::write(base, class, &rejected).await {
+                    error!("Failed to dead-letter {} events: {}", class, e);
+                    stored = false;
+                }
+            }
+            (values.len() - rejected.len(), stored)
+        }
+        Err(e) => {
+            error!("Failed to write {} events: {}", class, e);
+            (0, false)
+        }
+    };
+    metrics::increment("striem_storage_events_total", &labels, written as u64);
+    if written < values.len() {
+        metrics::increment(
+            "striem_storage_conversion_failures_total",
+            &labels,
+            (values.len() - written) as u64,
+        );
     }
+    stored
 }
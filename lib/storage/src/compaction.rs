@@ -0,0 +1,549 @@
+//! Compaction of stored Parquet files.
+//!
+//! Five-minute rotation leaves hundreds of small files per class and day,
+//! and DuckDB slows down with the number of files it opens. Compaction
+//! merges the files of a partition, a directory's files of one day, into
+//! one file written with the writer's properties. A day is only compacted
+//! once it has been over for `storage.compaction.min_age_secs` (and at
+//! least two rotation intervals), so no writer still places files in it.
+//!
+//! Only writer-named (UUIDv7) files are merged, and only files with the same
+//! columns; the merged file is named with the time of the newest file it
+//! replaces, so retention keeps it as long as the newest of them.
+//!
+//! # Crash Safety
+//! The merged file is written to a temp file, its row count checked
+//! against the originals and synced. A journal naming it and the originals
+//! is then written before it's renamed into place and the originals are
+//! deleted. A journal found on the next compaction finishes the deletion if
+//! the merged file is in place, or drops the merged file otherwise, so the
+//! originals are never deleted before their replacement is complete. Between
+//! the rename and the deletion, queries may briefly see both.
+//!
+//! Only local storage is compacted; use the object store's own tooling for
+//! `storage.uri`.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+use arc_swap::ArcSwap;
+use arrow::{
+    array::RecordBatch,
+    datatypes::{Fields, Schema},
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use log::{debug, error, info, warn};
+use parquet::{
+    arrow::{ArrowWriter, arrow_reader::ParquetRecordBatchReaderBuilder},
+    file::reader::{FileReader, SerializedFileReader},
+};
+use serde::{Deserialize, Serialize};
+use striem_common::{
+    SysMessage,
+    prelude::{STORAGE_ROTATION_INTERVAL_SECS, STORAGE_TEMP_DIR},
+};
+use striem_config::{StrIEMConfig, storage::StorageConfig};
+use tempfile::NamedTempFile;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    manifest, reader,
+    retention::file_time,
+    stats::FileStats,
+    util::parquet_files,
+    writer::{arrow_writer_options, place, sync_dir},
+};
+
+/// Journal of the compaction in progress in a partition directory
+pub const JOURNAL_FILE: &str = "_compaction.json";
+
+/// How often the scheduled task checks for a schedule when none is set
+const UNSCHEDULED_RECHECK: Duration = Duration::from_secs(60);
+
+/// One compaction at a time per process, whether scheduled or requested
+static COMPACTING: Mutex<()> = Mutex::new(());
+
+/// What a compaction did, or has done so far
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Report {
+    /// Partitions with files to merge
+    pub partitions: u64,
+    /// Partitions done so far
+    pub partitions_done: u64,
+    /// Files replaced by merged files
+    pub files_merged: u64,
+    /// Merged files written
+    pub files_written: u64,
+    /// Size of the replaced files
+    pub bytes_before: u64,
+    /// Size of the merged files
+    pub bytes_after: u64,
+}
+
+/// Counters of a running compaction
+#[derive(Debug, Default)]
+pub struct Progress {
+    partitions: AtomicU64,
+    partitions_done: AtomicU64,
+    files_merged: AtomicU64,
+    files_written: AtomicU64,
+    bytes_before: AtomicU64,
+    bytes_after: AtomicU64,
+}
+
+impl Progress {
+    pub fn report(&self) -> Report {
+        Report {
+            partitions: self.partitions.load(Ordering::Relaxed),
+            partitions_done: self.partitions_done.load(Ordering::Relaxed),
+            files_merged: self.files_merged.load(Ordering::Relaxed),
+            files_written: self.files_written.load(Ordering::Relaxed),
+            bytes_before: self.bytes_before.load(Ordering::Relaxed),
+            bytes_after: self.bytes_after.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Files of a partition with their rotation times
+type Files = Vec<(PathBuf, DateTime<Utc>)>;
+
+/// Record of a merge, kept next to the merged file until the originals are
+/// gone
+#[derive(Debug, Serialize, Deserialize)]
+struct Journal {
+    /// Temp file the merged file is written to
+    tmp: PathBuf,
+    /// Merged file name
+    file: String,
+    rows: i64,
+    /// Names of the files it replaces
+    replaces: Vec<String>,
+}
+
+/// Day of the partition a file at `relative` (below the class directory)
+/// belongs to: its `date=` directory, else the day it was rotated
+fn partition_day(relative: &Path, time: DateTime<Utc>) -> NaiveDate {
+    relative
+        .components()
+        .filter_map(|c| c.as_os_str().to_str()?.strip_prefix("date="))
+        .find_map(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+        .unwrap_or_else(|| time.date_naive())
+}
+
+/// Whether the `day` partition can no longer receive files at `now`
+pub fn settled(day: NaiveDate, now: DateTime<Utc>, min_age_secs: u64) -> bool {
+    let min_age = min_age_secs.max(2 * STORAGE_ROTATION_INTERVAL_SECS);
+    let Some(end) = day.succ_opt().and_then(|d| d.and_hms_opt(0, 0, 0)) else {
+        return false;
+    };
+    now - end.and_utc() >= chrono::Duration::seconds(min_age as i64)
+}
+
+/// Writer-named files below the class directory `dir`, by partition
+/// directory and day, oldest first
+fn partitions(dir: &Path) -> Result<BTreeMap<(PathBuf, NaiveDate), Files>> {
+    let mut files = Vec::new();
+    parquet_files(dir, &mut files)?;
+    let mut partitions: BTreeMap<_, Files> = BTreeMap::new();
+    for path in files {
+        let Some(time) = file_time(&path) else {
+            continue;
+        };
+        let Some(parent) = path.parent().map(Path::to_path_buf) else {
+            continue;
+        };
+        let day = partition_day(path.strip_prefix(dir)?, time);
+        partitions
+            .entry((parent, day))
+            .or_default()
+            .push((path, time));
+    }
+    for files in partitions.values_mut() {
+        files.sort_by_key(|(_, time)| *time);
+    }
+    Ok(partitions)
+}
+
+/// Journals left below `dir` by interrupted compactions
+fn journals(dir: &Path, found: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            journals(&path, found)?;
+        } else if path.file_name().is_some_and(|n| n == JOURNAL_FILE) {
+            found.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Finish or undo the compaction the journal at `path` records
+fn recover(path: &Path) -> Result<()> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow!("journal without a directory"))?;
+    let journal: Journal = serde_json::from_slice(&std::fs::read(path)?)?;
+    let merged = dir.join(&journal.file);
+    let complete = File::open(&merged)
+        .ok()
+        .and_then(|file| SerializedFileReader::new(file).ok())
+        .is_some_and(|r| r.metadata().file_metadata().num_rows() == journal.rows);
+
+    if complete {
+        for name in &journal.replaces {
+            match std::fs::remove_file(dir.join(name)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        info!("compaction: finished merge into {}", merged.display());
+    } else {
+        std::fs::remove_file(&merged).ok();
+        warn!(
+            "compaction: undid incomplete merge into {}",
+            merged.display()
+        );
+    }
+    std::fs::remove_file(&journal.tmp).ok();
+    std::fs::remove_file(path)?;
+    sync_dir(dir)?;
+    Ok(())
+}
+
+/// Merge `files` of the class directory `dir` into one file in the same
+/// directory, returning its size. The files must have the same columns.
+fn merge(storage: &StorageConfig, dir: &Path, files: &[(PathBuf, DateTime<Utc>)]) -> Result<u64> {
+    let (newest, time) = files.last().ok_or_else(|| anyhow!("nothing to merge"))?;
+    let partition = newest
+        .parent()
+        .ok_or_else(|| anyhow!("file without a directory"))?;
+
+    // key-value metadata of the newest file describes the class
+    let newest = SerializedFileReader::new(File::open(newest)?)?;
+    let metadata = newest
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .into_iter()
+        .flatten()
+        .filter(|kv| kv.key == "description" || kv.key == "schema_file")
+        .filter_map(|kv| Some((kv.key.clone(), kv.value.clone()?)))
+        .collect();
+    let fields = ParquetRecordBatchReaderBuilder::try_new(File::open(&files[0].0)?)?
+        .schema()
+        .fields()
+        .clone();
+    let schema = Arc::new(Schema::new_with_metadata(fields, metadata));
+
+    let root = dir
+        .ancestors()
+        .nth(2)
+        .ok_or_else(|| anyhow!("{} is not a class directory", dir.display()))?;
+    let temp_dir = storage
+        .temp_dir
+        .clone()
+        .unwrap_or_else(|| root.join(STORAGE_TEMP_DIR));
+    std::fs::create_dir_all(&temp_dir)?;
+    let tempfile = NamedTempFile::new_in(&temp_dir)?;
+
+    let mut writer = ArrowWriter::try_new_with_options(
+        tempfile.reopen()?,
+        schema.clone(),
+        arrow_writer_options(&schema, &storage.bloom_filter_columns),
+    )?;
+    let mut expected = 0;
+    // the merged file's write statistics, while every file has them
+    let mut stats = Some(FileStats::default());
+    for (path, _) in files {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+        expected += builder.metadata().file_metadata().num_rows();
+        stats = stats
+            .zip(FileStats::from_metadata(builder.metadata().file_metadata()))
+            .map(|(merged, file)| merged.merged(&file));
+        for batch in builder.build()? {
+            let batch = batch?;
+            writer.write(&RecordBatch::try_new(
+                schema.clone(),
+                batch.columns().to_vec(),
+            )?)?;
+        }
+    }
+    for kv in stats.iter().flat_map(FileStats::key_values) {
+        writer.append_key_value_metadata(kv);
+    }
+    let written = writer.close()?.num_rows;
+    if written != expected {
+        return Err(anyhow!(
+            "merged {} rows from {} files holding {}",
+            written,
+            files.len(),
+            expected
+        ));
+    }
+
+    let name = format!("{}.parquet", crate::writer::file_id(*time));
+    let merged = partition.join(&name);
+    let (_, tmp) = tempfile.keep()?;
+    let bytes = std::fs::metadata(&tmp)?.len();
+
+    let journal = partition.join(JOURNAL_FILE);
+    let record = Journal {
+        tmp: tmp.clone(),
+        file: name,
+        rows: written,
+        replaces: files
+            .iter()
+            .filter_map(|(path, _)| Some(path.file_name()?.to_string_lossy().to_string()))
+            .collect(),
+    };
+    std::fs::write(&journal, serde_json::to_vec(&record)?)?;
+    File::open(&journal)?.sync_all()?;
+    sync_dir(partition)?;
+
+    if let Err(e) = place(&tmp, &merged) {
+        std::fs::remove_file(&tmp).ok();
+        std::fs::remove_file(&journal).ok();
+        return Err(e.into());
+    }
+
+    let removed = files
+        .iter()
+        .filter_map(|(path, _)| path.strip_prefix(dir).ok().map(Path::to_path_buf))
+        .collect::<Vec<_>>();
+    // the merged file is in place either way; a stale manifest only widens
+    // what readers open
+    manifest::replace(dir, &removed, &merged)
+        .inspect_err(|e| warn!("compaction: failed to update manifest: {}", e))
+        .ok();
+
+    for (path, _) in files {
+        std::fs::remove_file(path)?;
+    }
+    sync_dir(partition)?;
+    std::fs::remove_file(&journal)?;
+    sync_dir(partition)?;
+    Ok(bytes)
+}
+
+/// Column layout of the file at `path`
+fn columns(path: &Path) -> Result<Fields> {
+    Ok(ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?
+        .schema()
+        .fields()
+        .clone())
+}
+
+/// Compact the settled partitions of the class directory `dir`, or only
+/// those of `date`
+fn compact_class(
+    storage: &StorageConfig,
+    dir: &Path,
+    date: Option<NaiveDate>,
+    now: DateTime<Utc>,
+    progress: &Progress,
+) -> Result<()> {
+    let mut interrupted = Vec::new();
+    journals(dir, &mut interrupted)?;
+    for journal in &interrupted {
+        recover(journal)?;
+    }
+    // readers fall back to file names until the manifest is rebuilt
+    if !interrupted.is_empty() || manifest::read(dir).is_err() {
+        manifest::rebuild(dir)?;
+    }
+
+    let partitions = partitions(dir)?
+        .into_iter()
+        .filter(|((_, day), files)| {
+            files.len() > 1
+                && date.is_none_or(|date| date == *day)
+                && settled(*day, now, storage.compaction.min_age_secs)
+        })
+        .collect::<Vec<_>>();
+    progress
+        .partitions
+        .fetch_add(partitions.len() as u64, Ordering::Relaxed);
+
+    for ((partition, day), files) in partitions {
+        // files written under different schemas are merged separately
+        let mut layouts: Vec<(Fields, Files)> = Vec::new();
+        for (path, time) in files {
+            let fields = match columns(&path) {
+                Ok(fields) => fields,
+                Err(e) => {
+                    warn!("compaction: skipping {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            match layouts.iter_mut().find(|(f, _)| *f == fields) {
+                Some((_, files)) => files.push((path, time)),
+                None => layouts.push((fields, vec![(path, time)])),
+            }
+        }
+
+        for (_, files) in layouts.into_iter().filter(|(_, files)| files.len() > 1) {
+            let before = files
+                .iter()
+                .filter_map(|(path, _)| std::fs::metadata(path).ok())
+                .map(|m| m.len())
+                .sum::<u64>();
+            match merge(storage, dir, &files) {
+                Ok(after) => {
+                    debug!(
+                        "compaction: merged {} files of {} ({})",
+                        files.len(),
+                        partition.display(),
+                        day
+                    );
+                    progress
+                        .files_merged
+                        .fetch_add(files.len() as u64, Ordering::Relaxed);
+                    progress.files_written.fetch_add(1, Ordering::Relaxed);
+                    progress.bytes_before.fetch_add(before, Ordering::Relaxed);
+                    progress.bytes_after.fetch_add(after, Ordering::Relaxed);
+                }
+                Err(e) => error!(
+                    "compaction: failed to merge files of {} ({}): {}",
+                    partition.display(),
+                    day,
+                    e
+                ),
+            }
+        }
+        progress.partitions_done.fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Class directories below the storage roots
+fn class_dirs(storage: &StorageConfig) -> Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    let visible = |entry: &std::fs::DirEntry| {
+        entry.file_type().is_ok_and(|t| t.is_dir())
+            && !entry.file_name().to_string_lossy().starts_with('.')
+    };
+    for root in storage.roots().iter().filter(|root| root.is_dir()) {
+        for category in std::fs::read_dir(root)? {
+            let category = category?;
+            if !visible(&category) {
+                continue;
+            }
+            for class in std::fs::read_dir(category.path())? {
+                let class = class?;
+                if visible(&class) {
+                    dirs.push(class.path());
+                }
+            }
+        }
+    }
+    Ok(dirs)
+}
+
+/// Compact the partitions of `class` (every class when `None`) that are
+/// settled at `now`, or only those of `date`. A `date` that isn't settled
+/// yet is an error.
+pub fn compact(
+    storage: &StorageConfig,
+    class: Option<&str>,
+    date: Option<NaiveDate>,
+    now: DateTime<Utc>,
+    progress: &Progress,
+) -> Result<Report> {
+    if storage.uri.is_some() {
+        return Err(anyhow!(
+            "only local storage can be compacted, not storage.uri"
+        ));
+    }
+    if let Some(date) = date
+        && !settled(date, now, storage.compaction.min_age_secs)
+    {
+        return Err(anyhow!("files are still being written for {}", date));
+    }
+    let dirs = match class {
+        Some(class) => vec![reader::class_dir(storage, class)?],
+        None => class_dirs(storage)?,
+    };
+
+    let _compacting = COMPACTING.lock().unwrap_or_else(|e| e.into_inner());
+    for dir in dirs.iter().filter(|dir| dir.is_dir()) {
+        // one class that can't be compacted doesn't hold up the others
+        if let Err(e) = compact_class(storage, dir, date, now, progress) {
+            error!("compaction: skipping {}: {}", dir.display(), e);
+        }
+    }
+    Ok(progress.report())
+}
+
+/// When the compaction scheduled at `minute` past midnight next runs after
+/// `now`
+pub(crate) fn next_run(now: DateTime<Utc>, minute: u32) -> DateTime<Utc> {
+    let today = now
+        .date_naive()
+        .and_hms_opt(minute / 60, minute % 60, 0)
+        .map(|t| t.and_utc())
+        .unwrap_or(now);
+    if today > now {
+        today
+    } else {
+        today + chrono::Duration::days(1)
+    }
+}
+
+/// Compact every class daily at `storage.compaction.schedule` until
+/// shutdown. The schedule is read again on reload.
+pub(crate) async fn run(
+    config: Arc<ArcSwap<StrIEMConfig>>,
+    mut sys: broadcast::Receiver<SysMessage>,
+) {
+    loop {
+        let minute = config
+            .load()
+            .storage
+            .as_ref()
+            .filter(|s| s.uri.is_none())
+            .and_then(|s| s.compaction.scheduled_minute().ok().flatten());
+        let wait = match minute {
+            Some(minute) => {
+                let now = Utc::now();
+                (next_run(now, minute) - now).to_std().unwrap_or_default()
+            }
+            None => UNSCHEDULED_RECHECK,
+        };
+
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {},
+            message = sys.recv() => match message {
+                Ok(SysMessage::Shutdown) | Err(RecvError::Closed) => return,
+                // a new schedule applies from now
+                _ => continue,
+            },
+        }
+        let Some(storage) = config.load().storage.clone().filter(|_| minute.is_some()) else {
+            continue;
+        };
+
+        let result = tokio::task::spawn_blocking(move || {
+            compact(&storage, None, None, Utc::now(), &Progress::default())
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|r| r);
+        match result {
+            Ok(report) if report.files_written > 0 => info!(
+                "compaction: merged {} files into {} ({} to {} bytes)",
+                report.files_merged, report.files_written, report.bytes_before, report.bytes_after
+            ),
+            Ok(_) => debug!("compaction: nothing to merge"),
+            Err(e) => error!("compaction failed: {}", e),
+        }
+    }
+}
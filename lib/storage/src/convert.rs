@@ -1,30 +1,206 @@
 //! JSON to Arrow RecordBatch conversion.
 //!
 //! Converts OCSF JSON events to Arrow RecordBatches matching Parquet schemas.
-//! Handles type coercion, missing fields, and nested structures.
+//! Handles type coercion, missing fields, and nested structures. Arrays are
+//! built column-wise across all events of a batch, so the per-builder setup
+//! cost is paid once per column rather than once per event.
 //!
 //! # Error Handling Philosophy
 //! - Required fields: Hard error if missing
 //! - Optional fields: Insert null and log warning
 //! - Type mismatches: Insert null for nullable fields, error for required
 //!
+//! Timestamps may be epoch integers in the field's unit or RFC3339 strings.
+//!
+//! With `storage.strict` set, type mismatches fail the event for nullable
+//! fields too, so nothing is silently stored as null.
+//!
 //! This "graceful degradation" prevents one malformed field from
-//! dropping an entire event, while preserving data integrity.
+//! dropping an entire event, while preserving data integrity. Likewise an
+//! event that can't be converted at all is skipped rather than failing the
+//! rest of its batch.
 
-use std::sync::Arc;
+use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
+};
 
 use arrow::{
     array::{
-        Array, ArrayData, ArrayRef, BooleanBuilder, Float64Builder, Int32Builder, Int64Builder,
-        ListArray, StringBuilder, StructArray, TimestampMillisecondBuilder, new_null_array,
+        ArrayRef, BooleanArray, Date32Array, Date64Array, Decimal128Array, Float64Array,
+        Int32Array, Int64Array, ListArray, MapArray, StringArray, StructArray,
+        TimestampMicrosecondArray, TimestampMillisecondArray, TimestampNanosecondArray,
+        TimestampSecondArray, UInt8Array, UInt16Array, UInt32Array, UInt64Array, timezone::Tz,
     },
-    buffer::Buffer,
-    compute::concat,
+    buffer::{NullBuffer, OffsetBuffer, ScalarBuffer},
     datatypes::{DataType, Field, SchemaRef, TimeUnit},
     error::{ArrowError, Result},
-    record_batch::RecordBatch,
+    record_batch::{RecordBatch, RecordBatchOptions},
 };
+use chrono::{DateTime, NaiveDateTime, Utc};
+use log::warn;
 use serde_json::Value;
+use striem_common::metrics;
+
+/// Zone-less timestamp formats accepted besides RFC3339; these are read in
+/// the schema's timezone, or UTC if it has none
+const NAIVE_TIMESTAMP_FORMATS: &[&str] = &["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"];
+
+/// Minimum time between repeated warnings for the same class, field and
+/// kind of error; occurrences in between are only counted
+const WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+static CONVERT_ERRORS: LazyLock<Mutex<HashMap<ErrorKey, ErrorStats>>> =
+    LazyLock::new(Default::default);
+
+/// `(class, field, kind)`; the field is empty for whole skipped events
+type ErrorKey = (String, String, &'static str);
+
+#[derive(Default)]
+struct ErrorStats {
+    count: u64,
+    last_message: String,
+    last_logged: Option<Instant>,
+    suppressed: u64,
+}
+
+/// Conversion problems of one class, field and kind since startup
+#[derive(Debug, Clone)]
+pub struct ConvertError {
+    pub class: String,
+    pub field: String,
+    pub kind: &'static str,
+    pub count: u64,
+    pub last_message: String,
+}
+
+/// Counts of conversion problems since startup, most frequent first
+pub fn convert_errors() -> Vec<ConvertError> {
+    let mut errors = CONVERT_ERRORS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|((class, field, kind), stats)| ConvertError {
+            class: class.clone(),
+            field: field.clone(),
+            kind,
+            count: stats.count,
+            last_message: stats.last_message.clone(),
+        })
+        .collect::<Vec<_>>();
+    errors.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.field.cmp(&b.field)));
+    errors
+}
+
+/// Broad category of a conversion message, for grouping and rate limiting
+fn error_kind(message: &str) -> &'static str {
+    if message.starts_with("Expected") {
+        "type"
+    } else if message.contains("out of range") || message.contains("exceeds") {
+        "range"
+    } else {
+        "format"
+    }
+}
+
+/// Count a conversion problem, logging the first occurrence and then at
+/// most once per [`WARNING_INTERVAL`].
+fn record_error(class: &str, field: &str, kind: &'static str, message: &str) {
+    metrics::increment(
+        "striem_storage_conversion_errors_total",
+        &[("class", class), ("field", field), ("kind", kind)],
+        1,
+    );
+
+    let mut errors = CONVERT_ERRORS.lock().unwrap_or_else(|e| e.into_inner());
+    let stats = errors
+        .entry((class.to_string(), field.to_string(), kind))
+        .or_default();
+    stats.count += 1;
+    stats.last_message = message.to_string();
+    if stats
+        .last_logged
+        .is_some_and(|t| t.elapsed() < WARNING_INTERVAL)
+    {
+        stats.suppressed += 1;
+        return;
+    }
+    let suppressed = std::mem::take(&mut stats.suppressed);
+    stats.last_logged = Some(Instant::now());
+    drop(errors);
+
+    let repeats = if suppressed > 0 {
+        format!(" ({} similar suppressed)", suppressed)
+    } else {
+        String::new()
+    };
+    if field.is_empty() {
+        warn!("{}: skipping event: {}{}", class, message, repeats);
+    } else {
+        warn!(
+            "{}: {} for field '{}'; inserting null{}",
+            class, message, field, repeats
+        );
+    }
+}
+
+/// Class name recorded in the schema metadata, for logs and metrics
+pub(crate) fn class_name(schema: &SchemaRef) -> &str {
+    schema
+        .metadata
+        .get("description")
+        .map(String::as_str)
+        .unwrap_or("unknown")
+}
+
+/// Nullable-field problems met while converting, reported only once the
+/// conversion they belong to succeeds so retries don't count them twice.
+///
+/// In strict mode there are none: every mismatch fails its event.
+#[derive(Default)]
+struct Mismatches {
+    strict: bool,
+    found: RefCell<Vec<(String, String)>>,
+    /// Rows, indexes into the converted events, with a mismatch
+    rows: RefCell<BTreeSet<usize>>,
+}
+
+impl Mismatches {
+    fn new(strict: bool) -> Self {
+        Self {
+            strict,
+            ..Default::default()
+        }
+    }
+
+    /// Number of rows with a mistyped value stored as null
+    fn mismatched(&self) -> usize {
+        self.rows.borrow().len()
+    }
+
+    fn clear(&self) {
+        self.found.borrow_mut().clear();
+        self.rows.borrow_mut().clear();
+    }
+
+    fn report(&self, class: &str) {
+        for (field, message) in self.found.take() {
+            record_error(class, &field, error_kind(&message), &message);
+        }
+    }
+}
+
+/// Result of converting a batch of events
+#[derive(Debug)]
+pub struct Converted {
+    pub batch: RecordBatch,
+    /// Index into the input and reason for each event left out
+    pub rejected: Vec<(usize, String)>,
+    /// Events converted with at least one mistyped value stored as null
+    pub mismatched: usize,
+}
 
 /// Convert a JSON object to RecordBatch matching the provided schema.
 ///
@@ -33,266 +209,538 @@ use serde_json::Value;
 /// Fields present in JSON but not in schema are silently dropped.
 /// This allows events to carry extra metadata without breaking writes.
 pub fn convert_json(data: &Value, schema: &SchemaRef) -> Result<RecordBatch> {
-    let obj = data.as_object().ok_or_else(|| {
-        ArrowError::ParseError("Expected JSON object at the top level".to_string())
-    })?;
+    convert_event(data, schema).map(|c| c.batch)
+}
+
+/// Convert one event as [`convert_json`] does, counting whether it had a
+/// mistyped value stored as null
+pub(crate) fn convert_event(data: &Value, schema: &SchemaRef) -> Result<Converted> {
+    let mismatches = Mismatches::default();
+    let batch = convert_rows(&[data], schema, &mismatches)?;
+    let mismatched = mismatches.mismatched();
+    mismatches.report(class_name(schema));
+    Ok(Converted {
+        batch,
+        rejected: Vec::new(),
+        mismatched,
+    })
+}
+
+/// Convert events of a single class into one RecordBatch.
+///
+/// Events that fail conversion on their own (not a JSON object, a required
+/// field missing or mistyped) are left out with a warning; the remaining
+/// events are still converted together.
+pub fn convert_json_batch(data: &[&Value], schema: &SchemaRef) -> Result<RecordBatch> {
+    convert_events(data, schema, false).map(|c| c.batch)
+}
+
+/// Convert events of a single class, reporting which were left out.
+///
+/// With `strict`, a mistyped or out-of-range value fails its event even in
+/// a nullable field, instead of being stored as null.
+pub fn convert_events(data: &[&Value], schema: &SchemaRef, strict: bool) -> Result<Converted> {
+    let class = class_name(schema);
+    let mismatches = Mismatches::new(strict);
+    if let Ok(batch) = convert_rows(data, schema, &mismatches) {
+        let mismatched = mismatches.mismatched();
+        mismatches.report(class);
+        return Ok(Converted {
+            batch,
+            rejected: Vec::new(),
+            mismatched,
+        });
+    }
+
+    // Slow path: find the offending events by converting one at a time
+    let mut rejected = Vec::new();
+    let valid = data
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(
+            |(i, value)| match convert_rows(&[value], schema, &Mismatches::new(strict)) {
+                Ok(_) => true,
+                Err(e) => {
+                    record_error(class, "", "skipped", &e.to_string());
+                    rejected.push((*i, e.to_string()));
+                    false
+                }
+            },
+        )
+        .map(|(_, value)| value)
+        .collect::<Vec<_>>();
+    mismatches.clear();
+    let batch = convert_rows(&valid, schema, &mismatches)?;
+    let mismatched = mismatches.mismatched();
+    mismatches.report(class);
+    Ok(Converted {
+        batch,
+        rejected,
+        mismatched,
+    })
+}
+
+fn convert_rows(
+    data: &[&Value],
+    schema: &SchemaRef,
+    mismatches: &Mismatches,
+) -> Result<RecordBatch> {
+    let objects = data
+        .iter()
+        .map(|v| {
+            v.as_object().ok_or_else(|| {
+                ArrowError::ParseError("Expected JSON object at the top level".to_string())
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
 
+    let valid = vec![true; objects.len()];
+    let rows = (0..objects.len()).collect::<Vec<_>>();
     let arrays = schema
         .fields()
         .iter()
-        .map(|f| build_array(obj.get(f.name()), f))
+        .map(|f| {
+            let values = objects.iter().map(|o| o.get(f.name())).collect::<Vec<_>>();
+            build_array(&values, &valid, &rows, f, mismatches)
+        })
         .collect::<Result<Vec<_>>>()?;
 
-    RecordBatch::try_new(schema.clone(), arrays)
+    RecordBatch::try_new_with_options(
+        schema.clone(),
+        arrays,
+        &RecordBatchOptions::new().with_row_count(Some(objects.len())),
+    )
 }
 
-/// Build an Arrow array from a JSON value, handling nulls and type mismatches.
+/// A value is absent: fine for nullable fields and rows whose parent is
+/// already null, an error for required fields.
+fn absent(field: &Field, valid: bool) -> Result<()> {
+    if valid && !field.is_nullable() {
+        return Err(ArrowError::ParseError(format!(
+            "Missing required field '{}'",
+            field.name()
+        )));
+    }
+    Ok(())
+}
+
+/// A value has the wrong type or range.
 ///
 /// # Design Choice: Null vs Error
-/// For nullable fields with wrong types, inserts null and logs warning.
+/// For nullable fields with wrong types, inserts null and records a warning.
 /// This preserves as much data as possible while signaling schema issues.
 ///
 /// Required fields fail hard to catch integration problems early.
-fn build_array(value: Option<&Value>, field: &Field) -> Result<ArrayRef> {
-    match value {
-        None => {
-            if !field.is_nullable() {
-                return Err(ArrowError::ParseError(format!(
-                    "Missing required field '{}'",
-                    field.name()
-                )));
-            }
+fn mismatch(field: &Field, message: &str, row: usize, mismatches: &Mismatches) -> Result<()> {
+    if !field.is_nullable() || mismatches.strict {
+        return Err(ArrowError::ParseError(format!(
+            "{} for field '{}'",
+            message,
+            field.name()
+        )));
+    }
+    mismatches
+        .found
+        .borrow_mut()
+        .push((field.name().clone(), message.to_string()));
+    mismatches.rows.borrow_mut().insert(row);
+    Ok(())
+}
 
-            // Create appropriately-typed null value for schema
-            // List and Struct nulls require special handling to maintain child schema
-            match field.data_type() {
-                DataType::List(child_field) => {
-                    let data = arrow::array::ArrayData::builder(field.data_type().clone())
-                        .len(1)
-                        .add_buffer(Buffer::from_slice_ref([0i32, 0i32]))
-                        .add_child_data(new_null_array(child_field.data_type(), 0).to_data())
-                        .null_bit_buffer(Some(Buffer::from_slice_ref([0u8])))
-                        .build()?;
-                    Ok(Arc::new(arrow::array::ListArray::from(data)))
-                }
-                DataType::Struct(child_fields) => {
-                    let children: Vec<(Arc<Field>, ArrayRef)> = child_fields
-                        .iter()
-                        .map(|child| (child.clone(), new_null_array(child.data_type(), 1)))
-                        .collect();
-                    Ok(Arc::new(StructArray::from(children)))
-                }
-                _ => Ok(new_null_array(field.data_type(), 1)),
-            }
+/// Extract one scalar per row, applying the null/absent policy
+fn collect<T>(
+    values: &[Option<&Value>],
+    valid: &[bool],
+    rows: &[usize],
+    field: &Field,
+    mismatches: &Mismatches,
+    parse: impl Fn(&Value) -> std::result::Result<T, String>,
+) -> Result<Vec<Option<T>>> {
+    values
+        .iter()
+        .zip(valid)
+        .zip(rows)
+        .map(|((value, valid), row)| match value {
+            None | Some(Value::Null) => absent(field, *valid).map(|_| None),
+            Some(v) => match parse(v) {
+                Ok(x) => Ok(Some(x)),
+                Err(message) => mismatch(field, &message, *row, mismatches).map(|_| None),
+            },
+        })
+        .collect()
+}
+
+/// Unsigned integer of type `T`; negative or too-large values are out of range
+fn unsigned<T: TryFrom<u64>>(v: &Value) -> std::result::Result<T, String> {
+    match (v.as_u64(), v.as_i64()) {
+        (Some(n), _) => T::try_from(n).map_err(|_| format!("Integer {} out of range", n)),
+        (None, Some(n)) => Err(format!("Integer {} out of range", n)),
+        _ => Err("Expected unsigned integer".to_string()),
+    }
+}
+
+/// Days since the epoch from a `YYYY-MM-DD` string
+fn parse_date(s: &str) -> std::result::Result<i64, String> {
+    chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|d| (d - chrono::NaiveDate::default()).num_days())
+        .map_err(|_| format!("Invalid date '{}'", s))
+}
+
+/// Parse a decimal number into an integer scaled by `10^scale`.
+///
+/// Digits beyond `scale` must be zero, so no value is silently rounded.
+fn parse_decimal(s: &str, precision: u8, scale: i8) -> std::result::Result<i128, String> {
+    let invalid = || format!("Invalid decimal '{}'", s);
+    let (mantissa, exponent) = match s.split_once(['e', 'E']) {
+        Some((m, e)) => (m, e.parse::<i32>().map_err(|_| invalid())?),
+        None => (s, 0),
+    };
+    let (negative, mantissa) = match mantissa.strip_prefix('-') {
+        Some(m) => (true, m),
+        None => (false, mantissa.strip_prefix('+').unwrap_or(mantissa)),
+    };
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if int.is_empty() && frac.is_empty()
+        || !int.chars().chain(frac.chars()).all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+
+    // shift the digits so the decimal point sits `scale` places from the end
+    let digits = format!("{}{}", int, frac);
+    let shift = i32::try_from(frac.len())
+        .ok()
+        .and_then(|frac| (scale as i32).checked_add(exponent)?.checked_sub(frac))
+        .ok_or_else(invalid)?;
+    if shift > i32::from(precision) {
+        return Err(format!("Decimal '{}' exceeds precision {}", s, precision));
+    }
+    let digits = if shift >= 0 {
+        format!("{}{}", digits, "0".repeat(shift as usize))
+    } else {
+        let keep = digits.len().saturating_sub(shift.unsigned_abs() as usize);
+        if digits[keep..].chars().any(|c| c != '0') {
+            return Err(format!("Decimal '{}' exceeds scale {}", s, scale));
         }
-        Some(v) => match field.data_type() {
-            DataType::Int32 => {
-                let mut builder = Int32Builder::new();
-                if let Some(n) = v.as_i64() {
+        digits[..keep].to_string()
+    };
+    let digits = digits.trim_start_matches('0');
+    if digits.len() > precision as usize {
+        return Err(format!("Decimal '{}' exceeds precision {}", s, precision));
+    }
+    let value = if digits.is_empty() {
+        0
+    } else {
+        digits.parse::<i128>().map_err(|_| invalid())?
+    };
+    Ok(if negative { -value } else { value })
+}
+
+/// Timestamp in `unit` since the epoch.
+///
+/// Integers (and integer strings) are taken to already be in `unit`;
+/// other strings are parsed as RFC3339 or one of [`NAIVE_TIMESTAMP_FORMATS`].
+fn parse_timestamp(
+    v: &Value,
+    unit: TimeUnit,
+    zone: Option<Tz>,
+) -> std::result::Result<i64, String> {
+    if let Some(n) = v.as_i64() {
+        return Ok(n);
+    }
+    let Some(s) = v.as_str() else {
+        return Err("Expected timestamp".to_string());
+    };
+    if let Ok(n) = s.parse::<i64>() {
+        return Ok(n);
+    }
+
+    let time = DateTime::parse_from_rfc3339(s)
+        .map(|t| t.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            let naive = NAIVE_TIMESTAMP_FORMATS
+                .iter()
+                .find_map(|f| NaiveDateTime::parse_from_str(s, f).ok())?;
+            match zone {
+                Some(zone) => naive
+                    .and_local_timezone(zone)
+                    .single()
+                    .map(|t| t.with_timezone(&Utc)),
+                None => Some(naive.and_utc()),
+            }
+        })
+        .ok_or_else(|| format!("Invalid timestamp '{}'", s))?;
+
+    match unit {
+        TimeUnit::Second => Ok(time.timestamp()),
+        TimeUnit::Millisecond => Ok(time.timestamp_millis()),
+        TimeUnit::Microsecond => Ok(time.timestamp_micros()),
+        TimeUnit::Nanosecond => time
+            .timestamp_nanos_opt()
+            .ok_or_else(|| format!("Timestamp '{}' out of range", s)),
+    }
+}
+
+/// Null mask for a nested array; `None` when every row is present
+fn nulls(present: &[bool]) -> Option<NullBuffer> {
+    present
+        .iter()
+        .any(|p| !p)
+        .then(|| NullBuffer::from(present.to_vec()))
+}
+
+/// Build an Arrow array for `field` from one JSON value per row.
+///
+/// `valid[i]` is false where an enclosing struct or list is itself null;
+/// those rows are null here too and never trip required-field checks.
+/// `rows[i]` is the event the value belongs to, for counting mismatches.
+fn build_array(
+    values: &[Option<&Value>],
+    valid: &[bool],
+    rows: &[usize],
+    field: &Field,
+    mismatches: &Mismatches,
+) -> Result<ArrayRef> {
+    match field.data_type() {
+        DataType::Int32 => {
+            let values = collect(values, valid, rows, field, mismatches, |v| {
+                match v.as_i64() {
                     // Check for overflow: JSON numbers are i64, schema may be i32
                     // Insert null for nullable fields rather than truncating incorrectly
-                    if n < i32::MIN as i64 || n > i32::MAX as i64 {
-                        if field.is_nullable() {
-                            eprintln!(
-                                "Warning: integer {} out of range for field '{}'; inserting null",
-                                n,
-                                field.name()
-                            );
-                            builder.append_null();
-                        } else {
-                            return Err(ArrowError::ParseError(format!(
-                                "Integer {} out of range for field '{}'",
-                                n,
-                                field.name()
-                            )));
-                        }
-                    } else {
-                        builder.append_value(n as i32);
-                    }
-                } else if field.is_nullable() {
-                    eprintln!(
-                        "Warning: expected integer for field '{}'; inserting null",
-                        field.name()
-                    );
-                    builder.append_null();
-                } else {
-                    return Err(ArrowError::ParseError(format!(
-                        "Expected integer for field '{}'",
-                        field.name()
-                    )));
-                }
-                Ok(Arc::new(builder.finish()))
-            }
-            DataType::Int64 => {
-                let mut builder = Int64Builder::new();
-                if let Some(n) = v.as_i64() {
-                    builder.append_value(n);
-                } else if field.is_nullable() {
-                    eprintln!(
-                        "Warning: expected integer for field '{}'; inserting null",
-                        field.name()
-                    );
-                    builder.append_null();
-                } else {
-                    return Err(ArrowError::ParseError(format!(
-                        "Expected integer for field '{}'",
-                        field.name()
-                    )));
+                    Some(n) => i32::try_from(n).map_err(|_| format!("Integer {} out of range", n)),
+                    None => Err("Expected integer".to_string()),
                 }
-                Ok(Arc::new(builder.finish()))
-            }
-            DataType::Float64 => {
-                let mut builder = Float64Builder::new();
-                if let Some(f) = v.as_f64() {
-                    builder.append_value(f);
-                } else if let Some(n) = v.as_i64() {
-                    builder.append_value(n as f64);
-                } else if field.is_nullable() {
-                    eprintln!(
-                        "Warning: expected float for field '{}'; inserting null",
-                        field.name()
-                    );
-                    builder.append_null();
-                } else {
-                    return Err(ArrowError::ParseError(format!(
-                        "Expected float for field '{}'",
-                        field.name()
-                    )));
+            })?;
+            Ok(Arc::new(Int32Array::from(values)))
+        }
+        DataType::Int64 => {
+            let values = collect(values, valid, rows, field, mismatches, |v| {
+                v.as_i64().ok_or_else(|| "Expected integer".to_string())
+            })?;
+            Ok(Arc::new(Int64Array::from(values)))
+        }
+        DataType::UInt8 => {
+            let values = collect(values, valid, rows, field, mismatches, unsigned::<u8>)?;
+            Ok(Arc::new(UInt8Array::from(values)))
+        }
+        DataType::UInt16 => {
+            let values = collect(values, valid, rows, field, mismatches, unsigned::<u16>)?;
+            Ok(Arc::new(UInt16Array::from(values)))
+        }
+        DataType::UInt32 => {
+            let values = collect(values, valid, rows, field, mismatches, unsigned::<u32>)?;
+            Ok(Arc::new(UInt32Array::from(values)))
+        }
+        DataType::UInt64 => {
+            let values = collect(values, valid, rows, field, mismatches, unsigned::<u64>)?;
+            Ok(Arc::new(UInt64Array::from(values)))
+        }
+        DataType::Float64 => {
+            let values = collect(values, valid, rows, field, mismatches, |v| {
+                v.as_f64().ok_or_else(|| "Expected float".to_string())
+            })?;
+            Ok(Arc::new(Float64Array::from(values)))
+        }
+        DataType::Boolean => {
+            let values = collect(values, valid, rows, field, mismatches, |v| {
+                v.as_bool().ok_or_else(|| "Expected boolean".to_string())
+            })?;
+            Ok(Arc::new(BooleanArray::from(values)))
+        }
+        DataType::Utf8 | DataType::Binary => {
+            // Non-string values are kept as their JSON text
+            let values = collect(values, valid, rows, field, mismatches, |v| {
+                Ok(v.as_str()
+                    .map(str::to_string)
+                    .unwrap_or_else(|| v.to_string()))
+            })?;
+            Ok(Arc::new(StringArray::from(values)))
+        }
+        DataType::Timestamp(unit, tz) => {
+            let zone = tz.as_deref().map(str::parse::<Tz>).transpose()?;
+            let values = collect(values, valid, rows, field, mismatches, |v| {
+                parse_timestamp(v, *unit, zone)
+            })?;
+            let tz = tz.clone();
+            Ok(match unit {
+                TimeUnit::Second => {
+                    Arc::new(TimestampSecondArray::from(values).with_timezone_opt(tz))
                 }
-                Ok(Arc::new(builder.finish()))
-            }
-            DataType::Boolean => {
-                let mut builder = BooleanBuilder::new();
-                if let Some(b) = v.as_bool() {
-                    builder.append_value(b);
-                } else if field.is_nullable() {
-                    eprintln!(
-                        "Warning: expected boolean for field '{}'; inserting null",
-                        field.name()
-                    );
-                    builder.append_null();
-                } else {
-                    return Err(ArrowError::ParseError(format!(
-                        "Expected boolean for field '{}'",
-                        field.name()
-                    )));
+                TimeUnit::Millisecond => {
+                    Arc::new(TimestampMillisecondArray::from(values).with_timezone_opt(tz))
                 }
-                Ok(Arc::new(builder.finish()))
-            }
-            DataType::Utf8 | DataType::Binary => {
-                let mut builder = StringBuilder::new();
-                if let Some(s) = v.as_str() {
-                    builder.append_value(s);
-                } else if v.is_null() {
-                    if field.is_nullable() {
-                        eprintln!(
-                            "Warning: expected string for field '{}'; inserting null",
-                            field.name()
-                        );
-                        builder.append_null();
-                    } else {
-                        return Err(ArrowError::ParseError(format!(
-                            "Expected string for field '{}'",
-                            field.name()
-                        )));
-                    }
-                } else {
-                    builder.append_value(v.to_string());
+                TimeUnit::Microsecond => {
+                    Arc::new(TimestampMicrosecondArray::from(values).with_timezone_opt(tz))
                 }
-                Ok(Arc::new(builder.finish()))
-            }
-            DataType::Struct(children) => {
-                let obj = v.as_object().ok_or_else(|| {
-                    ArrowError::ParseError(format!(
-                        "Expected JSON object for struct field '{}'",
-                        field.name()
-                    ))
-                })?;
-
-                let child_array = children
-                    .iter()
-                    .map(|child| build_array(obj.get(child.name()), child))
-                    .collect::<Result<Vec<_>>>()?;
-
-                let data = children
-                    .iter()
-                    .cloned()
-                    .zip(child_array)
-                    .collect::<Vec<(Arc<Field>, ArrayRef)>>();
-
-                Ok(Arc::new(StructArray::from(data)))
-            }
-            DataType::List(child_field) => {
-                let json_array = v.as_array().ok_or_else(|| {
-                    ArrowError::ParseError(format!(
-                        "Expected JSON array for list field '{}'",
-                        field.name()
-                    ))
-                })?;
-
-                if json_array.is_empty() {
-                    let data = ArrayData::builder(field.data_type().clone())
-                        .len(1)
-                        .add_buffer(Buffer::from_slice_ref([0i32, 0i32]))
-                        .add_child_data(new_null_array(child_field.data_type(), 0).to_data())
-                        .build()?;
-
-                    return Ok(Arc::new(ListArray::from(data)));
+                TimeUnit::Nanosecond => {
+                    Arc::new(TimestampNanosecondArray::from(values).with_timezone_opt(tz))
                 }
+            })
+        }
+        DataType::Date32 => {
+            // Epoch days or YYYY-MM-DD
+            let values = collect(values, valid, rows, field, mismatches, |v| {
+                let days = match v {
+                    Value::Number(n) => n.as_i64().ok_or_else(|| "Expected date".to_string())?,
+                    Value::String(s) => parse_date(s)?,
+                    _ => return Err("Expected date".to_string()),
+                };
+                i32::try_from(days).map_err(|_| format!("Date {} out of range", days))
+            })?;
+            Ok(Arc::new(Date32Array::from(values)))
+        }
+        DataType::Date64 => {
+            // Epoch milliseconds or YYYY-MM-DD
+            let values = collect(values, valid, rows, field, mismatches, |v| match v {
+                Value::Number(n) => n.as_i64().ok_or_else(|| "Expected date".to_string()),
+                Value::String(s) => parse_date(s).map(|days| days * 86_400_000),
+                _ => Err("Expected date".to_string()),
+            })?;
+            Ok(Arc::new(Date64Array::from(values)))
+        }
+        DataType::Decimal128(precision, scale) => {
+            let values = collect(values, valid, rows, field, mismatches, |v| match v {
+                Value::Number(n) => parse_decimal(&n.to_string(), *precision, *scale),
+                Value::String(s) => parse_decimal(s.trim(), *precision, *scale),
+                _ => Err("Expected decimal".to_string()),
+            })?;
+            Ok(Arc::new(
+                Decimal128Array::from(values).with_precision_and_scale(*precision, *scale)?,
+            ))
+        }
+        DataType::Map(entries_field, sorted) => {
+            let DataType::Struct(entry_fields) = entries_field.data_type() else {
+                return Err(ArrowError::SchemaError(format!(
+                    "Map field '{}' has non-struct entries",
+                    field.name()
+                )));
+            };
+            let [key_field, value_field] = &entry_fields.iter().collect::<Vec<_>>()[..] else {
+                return Err(ArrowError::SchemaError(format!(
+                    "Map field '{}' entries must have a key and a value",
+                    field.name()
+                )));
+            };
 
-                let inner_arrays = json_array
-                    .iter()
-                    .map(|elem| build_array(Some(elem), child_field))
-                    .collect::<Result<Vec<_>>>()?;
+            let mut present = Vec::with_capacity(values.len());
+            let mut offsets = Vec::with_capacity(values.len() + 1);
+            let mut keys = Vec::new();
+            let mut items = Vec::new();
+            let mut entry_rows = Vec::new();
+            offsets.push(0i32);
+            for ((value, valid), row) in values.iter().zip(valid).zip(rows) {
+                present.push(match value {
+                    Some(Value::Object(map)) => {
+                        for (k, v) in map {
+                            keys.push(Value::String(k.clone()));
+                            items.push(Some(v));
+                            entry_rows.push(*row);
+                        }
+                        true
+                    }
+                    None | Some(Value::Null) => absent(field, *valid).map(|_| false)?,
+                    Some(_) => {
+                        mismatch(field, "Expected JSON object", *row, mismatches).map(|_| false)?
+                    }
+                });
+                offsets.push(i32::try_from(keys.len()).map_err(|_| {
+                    ArrowError::ParseError(format!("Map field '{}' too large", field.name()))
+                })?);
+            }
 
-                let inner = concat(&inner_arrays.iter().map(|a| a.as_ref()).collect::<Vec<_>>())?;
+            let all = vec![true; keys.len()];
+            let keys = build_array(
+                &keys.iter().map(Some).collect::<Vec<_>>(),
+                &all,
+                &entry_rows,
+                key_field,
+                mismatches,
+            )?;
+            let items = build_array(&items, &all, &entry_rows, value_field, mismatches)?;
+            let entries = StructArray::try_new(entry_fields.clone(), vec![keys, items], None)?;
+            Ok(Arc::new(MapArray::try_new(
+                entries_field.clone(),
+                OffsetBuffer::new(ScalarBuffer::from(offsets)),
+                entries,
+                nulls(&present),
+                *sorted,
+            )?))
+        }
+        DataType::Struct(children) => {
+            let mut present = Vec::with_capacity(values.len());
+            for ((value, valid), row) in values.iter().zip(valid).zip(rows) {
+                present.push(match value {
+                    Some(Value::Object(_)) => true,
+                    None | Some(Value::Null) => absent(field, *valid).map(|_| false)?,
+                    Some(_) => {
+                        mismatch(field, "Expected JSON object", *row, mismatches).map(|_| false)?
+                    }
+                });
+            }
 
-                let data = ArrayData::builder(field.data_type().clone())
-                    .len(1)
-                    .add_buffer(Buffer::from_slice_ref([0i32, inner.len() as i32]))
-                    .add_child_data(inner.to_data())
-                    .build()?;
+            let arrays = children
+                .iter()
+                .map(|child| {
+                    let values = values
+                        .iter()
+                        .zip(&present)
+                        .map(|(v, present)| {
+                            v.filter(|_| *present).and_then(|v| v.get(child.name()))
+                        })
+                        .collect::<Vec<_>>();
+                    build_array(&values, &present, rows, child, mismatches)
+                })
+                .collect::<Result<Vec<_>>>()?;
 
-                Ok(Arc::new(ListArray::from(data)))
-            }
-            DataType::Timestamp(TimeUnit::Millisecond, tz) => {
-                let mut builder = TimestampMillisecondBuilder::new();
-                if let Some(ts) = v.as_i64() {
-                    builder.append_value(ts);
-                } else if let Some(s) = v.as_str() {
-                    if let Ok(ts) = s.parse::<i64>() {
-                        builder.append_value(ts);
-                    } else if field.is_nullable() {
-                        eprintln!(
-                            "Warning: expected timestamp for field '{}'; inserting null",
-                            field.name()
-                        );
-                        builder.append_null();
-                    } else {
-                        return Err(ArrowError::ParseError(format!(
-                            "Expected timestamp for field '{}'",
-                            field.name()
-                        )));
+            Ok(Arc::new(StructArray::try_new(
+                children.clone(),
+                arrays,
+                nulls(&present),
+            )?))
+        }
+        DataType::List(child_field) => {
+            let mut present = Vec::with_capacity(values.len());
+            let mut offsets = Vec::with_capacity(values.len() + 1);
+            let mut elements = Vec::new();
+            let mut element_rows = Vec::new();
+            offsets.push(0i32);
+            for ((value, valid), row) in values.iter().zip(valid).zip(rows) {
+                present.push(match value {
+                    Some(Value::Array(items)) => {
+                        elements.extend(items.iter().map(Some));
+                        element_rows.extend(std::iter::repeat_n(*row, items.len()));
+                        true
                     }
-                } else if field.is_nullable() {
-                    eprintln!(
-                        "Warning: expected timestamp for field '{}'; inserting null",
-                        field.name()
-                    );
-                    builder.append_null();
-                } else {
-                    return Err(ArrowError::ParseError(format!(
-                        "Expected timestamp for field '{}'",
-                        field.name()
-                    )));
-                }
-                Ok(Arc::new(builder.finish().with_timezone_opt(tz.clone())))
+                    None | Some(Value::Null) => absent(field, *valid).map(|_| false)?,
+                    Some(_) => {
+                        mismatch(field, "Expected JSON array", *row, mismatches).map(|_| false)?
+                    }
+                });
+                offsets.push(i32::try_from(elements.len()).map_err(|_| {
+                    ArrowError::ParseError(format!("List field '{}' too large", field.name()))
+                })?);
             }
-            dt => Err(ArrowError::NotYetImplemented(format!(
-                "Data type {:?} not supported for field '{}'",
-                dt,
-                field.name()
-            ))),
-        },
+
+            let items = build_array(
+                &elements,
+                &vec![true; elements.len()],
+                &element_rows,
+                child_field,
+                mismatches,
+            )?;
+            Ok(Arc::new(ListArray::try_new(
+                child_field.clone(),
+                OffsetBuffer::new(ScalarBuffer::from(offsets)),
+                items,
+                nulls(&present),
+            )?))
+        }
+        dt => Err(ArrowError::NotYetImplemented(format!(
+            "Data type {:?} not supported for field '{}'",
+            dt,
+            field.name()
+        ))),
     }
 }
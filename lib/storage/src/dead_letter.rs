@@ -0,0 +1,50 @@
+//! Dead-letter files for events rejected in strict mode.
+//!
+//! Rejected events are appended as NDJSON to
+//! `{storage_path}/dead_letter/{class}/{YYYY-MM-DD}.ndjson`, one object per
+//! line with the rejection time, the reason and the original event, so they
+//! can be inspected and replayed once the schema mismatch is fixed. These
+//! files sit outside the Parquet globs and are never swept by retention.
+
+use std::path::Path;
+
+use anyhow::Result;
+use chrono::Utc;
+use serde_json::{Value, json};
+use tokio::io::AsyncWriteExt;
+
+/// Directory below the storage path holding dead-letter files
+pub(crate) const DEAD_LETTER_DIR: &str = "dead_letter";
+
+/// Append `events` with their rejection reasons to today's file for `class`.
+pub(crate) async fn write(base: &Path, class: &str, events: &[(&Value, &str)]) -> Result<()> {
+    if events.is_empty() {
+        return Ok(());
+    }
+
+    let now = Utc::now();
+    let dir = base.join(DEAD_LETTER_DIR).join(class);
+    tokio::fs::create_dir_all(&dir).await?;
+
+    let mut lines = Vec::new();
+    for (event, error) in events {
+        serde_json::to_writer(
+            &mut lines,
+            &json!({
+                "time": now.to_rfc3339(),
+                "error": error,
+                "event": event,
+            }),
+        )?;
+        lines.push(b'\n');
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(format!("{}.ndjson", now.format("%Y-%m-%d"))))
+        .await?;
+    file.write_all(&lines).await?;
+    file.flush().await?;
+    Ok(())
+}
@@ -0,0 +1,113 @@
+//! Columns StrIEM's own detection findings need, whatever the schema file.
+//!
+//! Findings carry the matching rule's first reference, identity and ATT&CK
+//! tags in `finding_info` and its false positives in `unmapped`. Columns a
+//! `detection_finding` schema file lacks are added, so these fields aren't
+//! dropped on conversion; columns the file has are kept as they are, so an
+//! `unmapped` stored as JSON text keeps false positives as JSON.
+
+use std::sync::Arc;
+
+use arrow::datatypes::{DataType, Field, FieldRef, Fields, Schema};
+
+/// Schema file (and class) name of detection findings
+pub(crate) const CLASS: &str = "detection_finding";
+
+fn strings(name: &str) -> Field {
+    Field::new(
+        name,
+        DataType::List(Arc::new(Field::new("element", DataType::Utf8, true))),
+        true,
+    )
+}
+
+fn object(name: &str, fields: Vec<Field>) -> Field {
+    Field::new(name, DataType::Struct(Fields::from(fields)), true)
+}
+
+fn uid_name(name: &str) -> Field {
+    object(
+        name,
+        vec![
+            Field::new("uid", DataType::Utf8, true),
+            Field::new("name", DataType::Utf8, true),
+        ],
+    )
+}
+
+/// Finding columns filled in from the matching rule
+fn finding_fields() -> Fields {
+    let attack = object(
+        "element",
+        vec![
+            uid_name("tactic"),
+            uid_name("technique"),
+            uid_name("sub_technique"),
+        ],
+    );
+    Fields::from(vec![
+        object(
+            "finding_info",
+            vec![
+                Field::new("src_url", DataType::Utf8, true),
+                object(
+                    "analytic",
+                    vec![
+                        Field::new("uid", DataType::Utf8, true),
+                        Field::new("name", DataType::Utf8, true),
+                        Field::new("version", DataType::Utf8, true),
+                        Field::new("type", DataType::Utf8, true),
+                        Field::new("type_id", DataType::Int32, true),
+                    ],
+                ),
+                Field::new("attacks", DataType::List(Arc::new(attack)), true),
+            ],
+        ),
+        object("unmapped", vec![strings("falsepositives")]),
+    ])
+}
+
+/// `have` with the members of `want` it lacks, recursing into structs and
+/// lists of structs
+fn merge_fields(have: &Fields, want: &Fields) -> Fields {
+    let mut fields = have
+        .iter()
+        .map(|field| match want.find(field.name()) {
+            Some((_, wanted)) => merge(field, wanted),
+            None => field.clone(),
+        })
+        .collect::<Vec<FieldRef>>();
+    fields.extend(
+        want.iter()
+            .filter(|wanted| have.find(wanted.name()).is_none())
+            .cloned(),
+    );
+    Fields::from(fields)
+}
+
+fn merge(have: &FieldRef, want: &FieldRef) -> FieldRef {
+    let data_type = match (have.data_type(), want.data_type()) {
+        (DataType::Struct(members), DataType::Struct(wanted)) => {
+            DataType::Struct(merge_fields(members, wanted))
+        }
+        (DataType::List(item), DataType::List(wanted)) => {
+            match (item.data_type(), wanted.data_type()) {
+                (DataType::Struct(_), DataType::Struct(_)) => DataType::List(merge(item, wanted)),
+                _ => return have.clone(),
+            }
+        }
+        // the schema file's own type wins
+        _ => return have.clone(),
+    };
+    Arc::new(have.as_ref().clone().with_data_type(data_type))
+}
+
+/// `schema` with the finding columns it lacks, if it's for detection
+/// findings
+pub(crate) fn with_finding_columns(name: &str, schema: Schema) -> Schema {
+    if name != CLASS {
+        return schema;
+    }
+    let fields = merge_fields(schema.fields(), &finding_fields());
+    Schema::new_with_metadata(fields, schema.metadata().clone())
+}
@@ -1,6 +1,18 @@
 //mod buffer;
 mod backend;
+pub mod compaction;
 mod convert;
+mod dead_letter;
+mod finding;
+pub mod manifest;
+mod queue;
+mod raw;
+pub mod reader;
+mod remote;
+mod retention;
+mod sampling;
+pub mod schema;
+pub mod stats;
 mod util;
 mod writer;
 
@@ -9,8 +21,15 @@ mod ocsf {
 }
 
 pub use crate::backend::ParquetBackend;
-pub use convert::convert_json;
+pub use convert::{
+    ConvertError, Converted, convert_errors, convert_events, convert_json, convert_json_batch,
+};
+pub use retention::file_time;
+pub use util::parquet_files;
 pub use writer::Writer;
 
+/// OCSF schema version the class and category names were generated from
+pub const OCSF_VERSION: &str = env!("OCSF_VERSION");
+
 #[cfg(test)]
 mod tests;
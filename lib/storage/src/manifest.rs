@@ -0,0 +1,254 @@
+//! Per-class file manifests.
+//!
+//! Each class directory holds a `_manifest.ndjson` with one line per
+//! finished Parquet file: its path relative to the class directory, the
+//! earliest and latest event `time` it contains, its row count and size.
+//! Readers use it to pass DuckDB an explicit file list for a time range
+//! instead of globbing every file of the class.
+//!
+//! The manifest is an index, not the source of truth. Files missing from it
+//! (written before it existed, or by a writer that crashed before appending)
+//! are always included and files deleted by retention are skipped. Without
+//! a readable manifest, readers prune files by the rotation time in their
+//! names instead; compaction rebuilds it from the Parquet footers.
+//!
+//! Files uploaded to an object store are not recorded.
+
+use std::{
+    collections::HashSet,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use arrow::{
+    array::{Array, RecordBatch, TimestampMillisecondArray},
+    compute::{cast, max, min},
+    datatypes::{DataType, TimeUnit},
+};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use parquet::{
+    arrow::{arrow_reader::statistics::StatisticsConverter, parquet_to_arrow_schema},
+    file::reader::{FileReader, SerializedFileReader},
+};
+use serde::{Deserialize, Serialize};
+use striem_common::prelude::STORAGE_ROTATION_INTERVAL_SECS;
+
+use crate::{retention::file_time, util::parquet_files};
+
+pub const MANIFEST_FILE: &str = "_manifest.ndjson";
+
+/// Files are named for the time they were rotated, so a file holds events
+/// from roughly one rotation interval before its name. Files named when
+/// they were finished rather than rotated are named up to the jitter window
+/// later, which is at most an interval.
+const NAME_PAD: chrono::Duration =
+    chrono::Duration::seconds(2 * STORAGE_ROTATION_INTERVAL_SECS as i64);
+
+/// Column holding the OCSF event time
+pub(crate) const TIME_COLUMN: &str = "time";
+
+/// One finished Parquet file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Path relative to the class directory
+    pub file: PathBuf,
+    /// Earliest event time, epoch milliseconds
+    pub min_time: Option<i64>,
+    /// Latest event time, epoch milliseconds
+    pub max_time: Option<i64>,
+    pub rows: u64,
+    pub bytes: u64,
+}
+
+impl Entry {
+    /// Whether the file may hold events between `start` and `end`; files
+    /// without recorded times always may
+    fn overlaps(&self, start: i64, end: i64) -> bool {
+        self.min_time.is_none_or(|t| t <= end) && self.max_time.is_none_or(|t| t >= start)
+    }
+}
+
+/// `(min, max)` of the `time` column of `batch` in epoch milliseconds
+pub(crate) fn time_range(batch: &RecordBatch) -> Option<(i64, i64)> {
+    let column = batch.column_by_name(TIME_COLUMN)?;
+    let millis = cast(column, &DataType::Timestamp(TimeUnit::Millisecond, None)).ok()?;
+    let millis = millis
+        .as_any()
+        .downcast_ref::<TimestampMillisecondArray>()?;
+    Some((min(millis)?, max(millis)?))
+}
+
+/// Widen `range` to include `other`
+pub(crate) fn merge_range(
+    range: Option<(i64, i64)>,
+    other: Option<(i64, i64)>,
+) -> Option<(i64, i64)> {
+    match (range, other) {
+        (Some((a, b)), Some((c, d))) => Some((a.min(c), b.max(d))),
+        (range, other) => range.or(other),
+    }
+}
+
+/// Append `entry` to the manifest of `dir` and flush it to disk.
+pub(crate) fn append(dir: &Path, entry: &Entry) -> Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(MANIFEST_FILE))?;
+    file.write_all(&line)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Entries of the manifest in `dir`; an error if it is missing or any line
+/// doesn't parse
+pub fn read(dir: &Path) -> Result<Vec<Entry>> {
+    std::fs::read_to_string(dir.join(MANIFEST_FILE))?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+/// Manifest entry for an existing Parquet file, from its footer
+fn entry_from_footer(dir: &Path, path: &Path) -> Result<Entry> {
+    let bytes = std::fs::metadata(path)?.len();
+    let reader = SerializedFileReader::new(std::fs::File::open(path)?)?;
+    let metadata = reader.metadata();
+    let rows = metadata.file_metadata().num_rows().max(0) as u64;
+
+    let parquet_schema = metadata.file_metadata().schema_descr();
+    let arrow_schema = parquet_to_arrow_schema(parquet_schema, None)?;
+    let range = StatisticsConverter::try_new(TIME_COLUMN, &arrow_schema, parquet_schema)
+        .ok()
+        .and_then(|converter| {
+            let groups = metadata.row_groups();
+            let to_millis = |array: arrow::array::ArrayRef| {
+                let array = cast(&array, &DataType::Timestamp(TimeUnit::Millisecond, None)).ok()?;
+                let array = array
+                    .as_any()
+                    .downcast_ref::<TimestampMillisecondArray>()?
+                    .clone();
+                Some(array)
+            };
+            let mins = to_millis(converter.row_group_mins(groups.iter()).ok()?)?;
+            let maxes = to_millis(converter.row_group_maxes(groups.iter()).ok()?)?;
+            // a row group without statistics could hold any time
+            if mins.null_count() > 0 || maxes.null_count() > 0 {
+                return None;
+            }
+            Some((min(&mins)?, max(&maxes)?))
+        });
+
+    Ok(Entry {
+        file: path.strip_prefix(dir)?.to_path_buf(),
+        min_time: range.map(|r| r.0),
+        max_time: range.map(|r| r.1),
+        rows,
+        bytes,
+    })
+}
+
+/// Replace the manifest of `dir` with `entries`, atomically so concurrent
+/// readers see the old or new manifest
+fn write(dir: &Path, entries: &[Entry]) -> Result<()> {
+    let mut contents = Vec::new();
+    for entry in entries {
+        serde_json::to_writer(&mut contents, entry)?;
+        contents.push(b'\n');
+    }
+    // a name of its own, so concurrent rebuilds don't write the same file
+    let mut tmp = tempfile::Builder::new()
+        .prefix(MANIFEST_FILE)
+        .suffix(".tmp")
+        .tempfile_in(dir)?;
+    tmp.write_all(&contents)?;
+    tmp.as_file().sync_all()?;
+    tmp.persist(dir.join(MANIFEST_FILE))?;
+    Ok(())
+}
+
+/// Record in the manifest of `dir` that the files `removed` (relative to
+/// `dir`) were replaced by the file at `added`. A manifest that can't be
+/// read is rebuilt instead.
+pub(crate) fn replace(dir: &Path, removed: &[PathBuf], added: &Path) -> Result<()> {
+    let Ok(mut entries) = read(dir) else {
+        return rebuild(dir).map(|_| ());
+    };
+    entries.retain(|e| !removed.contains(&e.file));
+    entries.push(entry_from_footer(dir, added)?);
+    write(dir, &entries)
+}
+
+/// Regenerate the manifest of `dir` from the footers of its Parquet files.
+///
+/// Files whose footer can't be read are left out, and so are always
+/// included by [`files_for_range`].
+pub fn rebuild(dir: &Path) -> Result<Vec<Entry>> {
+    let mut files = Vec::new();
+    parquet_files(dir, &mut files)?;
+    files.sort();
+
+    let entries = files
+        .iter()
+        .filter_map(|path| {
+            entry_from_footer(dir, path)
+                .inspect_err(|e| warn!("manifest: skipping {}: {}", path.display(), e))
+                .ok()
+        })
+        .collect::<Vec<_>>();
+
+    write(dir, &entries)?;
+
+    info!(
+        "manifest: rebuilt {} with {} files",
+        dir.display(),
+        entries.len()
+    );
+    Ok(entries)
+}
+
+/// Parquet files below the class directory `dir` that may hold events
+/// between `start` and `end`, sorted.
+///
+/// Without a readable manifest, files are kept by the rotation time in
+/// their names, padded for late events and the jitter delay; files whose
+/// name isn't a UUIDv7 are always kept. Returns `None` if `dir` can't be
+/// listed, in which case callers should fall back to globbing it.
+pub fn files_for_range(
+    dir: &Path,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Option<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    parquet_files(dir, &mut files)
+        .inspect_err(|e| warn!("failed to list {}: {}", dir.display(), e))
+        .ok()?;
+
+    match read(dir) {
+        Ok(entries) => {
+            let (start, end) = (start.timestamp_millis(), end.timestamp_millis());
+            let excluded = entries
+                .iter()
+                .filter(|e| !e.overlaps(start, end))
+                .map(|e| dir.join(&e.file))
+                .collect::<HashSet<_>>();
+            files.retain(|f| !excluded.contains(f));
+        }
+        Err(e) => {
+            warn!(
+                "manifest: {} unusable ({}), pruning by file name",
+                dir.display(),
+                e
+            );
+            let (lower, upper) = (start - NAME_PAD, end + NAME_PAD);
+            files.retain(|f| file_time(f).is_none_or(|t| t >= lower && t <= upper));
+        }
+    }
+    files.sort();
+    Some(files)
+}
@@ -0,0 +1,164 @@
+//! Per-class write queues.
+//!
+//! Each OCSF class gets a bounded queue drained by its own task, so a slow
+//! write (a stalled disk, a long rotation) only backs up that class. The
+//! backend's receive loop does nothing but route events and enqueue them,
+//! keeping it ahead of the broadcast channel it reads from.
+//!
+//! When a class queue is full the configured [`QueuePolicy`] applies:
+//! wait up to a timeout for room, or drop the batch at once. Dropped events
+//! are counted in `striem_storage_dropped_events_total`.
+
+use std::{borrow::Cow, future::Future, sync::Arc, time::Duration};
+
+use log::warn;
+use serde_json::{Value, json};
+use striem_common::{
+    event::{Ack, Event, add_logger, ingest_logger, label_tenant},
+    metrics,
+};
+use striem_config::storage::{QueueConfig, QueuePolicy};
+use tokio::{
+    sync::mpsc::{self, error::SendTimeoutError, error::TrySendError},
+    task::JoinHandle,
+};
+
+/// Events of one class from a received broadcast batch
+pub(crate) struct Batch {
+    pub events: Arc<Vec<Event>>,
+    pub indexes: Vec<usize>,
+}
+
+impl Batch {
+    /// Event data, with `time` filled in from [`Event::time`] where the
+    /// JSON lacks it, the source's tenant added to `metadata.labels` and the
+    /// Vector agent it came from to `metadata.loggers`
+    pub fn values(&self) -> Vec<Cow<'_, Value>> {
+        self.indexes
+            .iter()
+            .map(|i| {
+                let event = &self.events[*i];
+                let time = event
+                    .time
+                    .filter(|_| event.data.is_object() && event.data.get("time").is_none());
+                let tenant = event.metadata.get("tenant").and_then(|t| t.as_str());
+                let logger = ingest_logger(&event.metadata);
+                if time.is_none() && tenant.is_none() && logger.is_none() {
+                    return Cow::Borrowed(&event.data);
+                }
+                let mut data = event.data.clone();
+                if let Some(time) = time {
+                    data["time"] = json!(time.timestamp_millis());
+                }
+                if let Some(tenant) = tenant {
+                    label_tenant(&mut data, tenant);
+                }
+                if let Some(logger) = logger {
+                    add_logger(&mut data, logger);
+                }
+                Cow::Owned(data)
+            })
+            .collect()
+    }
+
+    /// Report the outcome to the senders waiting on these events, if any
+    pub fn acknowledge(&self, stored: bool) {
+        let mut acks: Vec<(&Arc<Ack>, usize)> = Vec::new();
+        for ack in self
+            .indexes
+            .iter()
+            .filter_map(|i| self.events[*i].ack.as_ref())
+        {
+            match acks.iter_mut().find(|(a, _)| Arc::ptr_eq(a, ack)) {
+                Some((_, n)) => *n += 1,
+                None => acks.push((ack, 1)),
+            }
+        }
+        for (ack, n) in acks {
+            if stored {
+                ack.complete(n);
+            } else {
+                ack.fail();
+            }
+        }
+    }
+}
+
+/// Sending half of a class queue
+pub(crate) struct ClassQueue {
+    class: String,
+    tx: mpsc::Sender<Batch>,
+    config: QueueConfig,
+}
+
+impl ClassQueue {
+    /// Create the queue for `class` and spawn a task passing each batch to
+    /// `handler` in order. The task ends once the queue is dropped and drained.
+    pub fn spawn<F, Fut>(
+        class: String,
+        config: QueueConfig,
+        mut handler: F,
+    ) -> (Self, JoinHandle<()>)
+    where
+        F: FnMut(Batch) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (tx, mut rx) = mpsc::channel::<Batch>(config.capacity.max(1));
+        let label = class.clone();
+        let task = tokio::spawn(async move {
+            while let Some(batch) = rx.recv().await {
+                metrics::set(
+                    "striem_storage_queue_depth",
+                    &[("class", &label)],
+                    rx.len() as f64,
+                );
+                handler(batch).await;
+            }
+        });
+        (Self { class, tx, config }, task)
+    }
+
+    /// Batches waiting to be written
+    pub fn depth(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+
+    /// Enqueue `batch`, applying the full-queue policy. Returns false if the
+    /// batch was dropped, failing its acknowledgements.
+    pub async fn send(&self, batch: Batch) -> bool {
+        let count = batch.indexes.len();
+        let result = match self.config.policy {
+            QueuePolicy::Block => self
+                .tx
+                .send_timeout(batch, Duration::from_millis(self.config.timeout_ms))
+                .await
+                .map_err(|e| match e {
+                    SendTimeoutError::Timeout(batch) => (batch, true),
+                    SendTimeoutError::Closed(batch) => (batch, false),
+                }),
+            QueuePolicy::Drop => self.tx.try_send(batch).map_err(|e| match e {
+                TrySendError::Full(batch) => (batch, true),
+                TrySendError::Closed(batch) => (batch, false),
+            }),
+        };
+
+        let labels = [("class", self.class.as_str())];
+        metrics::set("striem_storage_queue_depth", &labels, self.depth() as f64);
+        match result {
+            Ok(_) => true,
+            Err((batch, full)) => {
+                batch.acknowledge(false);
+                if full {
+                    warn!("{} write queue full, dropping {} events", self.class, count);
+                } else {
+                    warn!(
+                        "{} writer has stopped, dropping {} events",
+                        self.class, count
+                    );
+                }
+                metrics::increment("striem_storage_dropped_events_total", &labels, count as u64);
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Catch-all storage for events that can't be routed to an OCSF class.
+//!
+//! With `storage.keep_unclassified` set, events without a `class_uid`, or
+//! with one no schema is loaded for, are written under `other/raw/` with a
+//! generic schema instead of being dropped: the time they were stored,
+//! the Vector source they came from, the event itself as a JSON string and
+//! its metadata. They are queryable like any other class, e.g.
+//! `SELECT raw FROM './storage/other/raw/**/*.parquet'`.
+
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use arrow::datatypes::{DataType, Field, Fields, Schema, SchemaRef, TimeUnit};
+use chrono::Utc;
+use serde_json::{Map, Value, json};
+use striem_common::event::Event;
+
+/// Storage category directory of unclassified events
+pub(crate) const CATEGORY: &str = "other";
+
+/// Name used for the writer, metrics and dead-letter files
+pub(crate) const CLASS: &str = "raw";
+
+/// `other/raw`, below the storage root
+pub(crate) fn subpath() -> PathBuf {
+    PathBuf::from(CATEGORY).join(CLASS)
+}
+
+pub(crate) fn schema() -> SchemaRef {
+    let entries = Field::new(
+        "key_value",
+        DataType::Struct(Fields::from(vec![
+            Field::new("key", DataType::Utf8, false),
+            Field::new("value", DataType::Utf8, true),
+        ])),
+        false,
+    );
+    Arc::new(
+        Schema::new(vec![
+            Field::new(
+                "time",
+                DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+                false,
+            ),
+            Field::new("source_id", DataType::Utf8, true),
+            Field::new("source_type", DataType::Utf8, true),
+            Field::new("raw", DataType::Utf8, false),
+            Field::new("metadata", DataType::Map(Arc::new(entries), false), true),
+        ])
+        .with_metadata(HashMap::from([(
+            "description".to_string(),
+            CLASS.to_string(),
+        )])),
+    )
+}
+
+/// Row of the raw schema for `event`. `time` is when the event was stored:
+/// the event's own time field is unreliable if it failed normalization.
+pub(crate) fn row(event: &Event) -> Value {
+    let text = |v: &Value| match v {
+        Value::String(s) => Value::String(s.clone()),
+        v => Value::String(v.to_string()),
+    };
+
+    json!({
+        "time": Utc::now().timestamp_millis(),
+        "source_id": event.metadata.get("source_id").map(text),
+        "source_type": event.metadata.get("source_type").map(text),
+        "raw": event.data.to_string(),
+        "metadata": event
+            .metadata
+            .iter()
+            .map(|(k, v)| (k.clone(), text(v)))
+            .collect::<Map<_, _>>(),
+    })
+}
@@ -0,0 +1,125 @@
+//! Reading stored events back, e.g. to replay them through detection rules.
+//!
+//! Files are picked with the class manifest (see [`crate::manifest`]) and
+//! read with the Arrow Parquet reader, a file at a time. Rows become
+//! [`Event`]s with their OCSF fields as `data`, `time` in epoch
+//! milliseconds as received, and `ocsf` metadata so detection uses their
+//! `raw_data`; [`read_rows`] reads just some columns, as JSON. Only local
+//! storage can be read.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow};
+use arrow::{
+    array::{Array, TimestampMillisecondArray},
+    compute::cast,
+    datatypes::{DataType, TimeUnit},
+};
+use chrono::{DateTime, Utc};
+use parquet::arrow::{ProjectionMask, arrow_reader::ParquetRecordBatchReaderBuilder};
+use serde_json::{Map, Value, json};
+use striem_common::event::Event;
+use striem_config::storage::StorageConfig;
+
+use crate::{manifest, ocsf};
+
+/// Directory of the OCSF class named `class`, e.g. `authentication`
+pub fn class_dir(storage: &StorageConfig, class: &str) -> Result<PathBuf> {
+    if storage.uri.is_some() {
+        return Err(anyhow!("only local storage can be read, not storage.uri"));
+    }
+    let class: ocsf::Class = class.parse().map_err(|e: String| anyhow!(e))?;
+    let category = ocsf::Category::try_from((class as u32 % 10000) / 1000)?.to_string();
+    let class = class.to_string();
+    Ok(storage
+        .root_for(&category, &class)
+        .join(category)
+        .join(class))
+}
+
+/// Parquet files of the class named `class` that may hold events between
+/// `start` and `end`, oldest first; none if the class was never stored
+pub fn class_files(
+    storage: &StorageConfig,
+    class: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<PathBuf>> {
+    let dir = class_dir(storage, class)?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    manifest::files_for_range(&dir, start, end)
+        .ok_or_else(|| anyhow!("could not list {}", dir.display()))
+}
+
+/// Rows of the Parquet file at `path` with a time between `start` and
+/// `end`, as JSON objects with their time in epoch milliseconds; rows
+/// without a time are skipped. With `columns`, only those columns are read,
+/// named by dotted path (`metadata.uid`).
+pub fn read_rows(
+    path: &Path,
+    columns: Option<&[&str]>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<(i64, Map<String, Value>)>> {
+    let mut builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?;
+    if let Some(columns) = columns {
+        let columns = columns.iter().copied().chain([manifest::TIME_COLUMN]);
+        let mask = ProjectionMask::columns(builder.parquet_schema(), columns);
+        builder = builder.with_projection(mask);
+    }
+    let reader = builder.build()?;
+    let (start, end) = (start.timestamp_millis(), end.timestamp_millis());
+
+    let mut rows = Vec::new();
+    for batch in reader {
+        let batch = batch?;
+        if batch.num_rows() == 0 {
+            continue;
+        }
+        let Some(times) = batch.column_by_name(manifest::TIME_COLUMN) else {
+            continue;
+        };
+        let times = cast(times, &DataType::Timestamp(TimeUnit::Millisecond, None))?;
+        let Some(times) = times.as_any().downcast_ref::<TimestampMillisecondArray>() else {
+            continue;
+        };
+
+        let mut writer = arrow::json::ArrayWriter::new(Vec::new());
+        writer.write(&batch)?;
+        writer.finish()?;
+        let batch_rows: Vec<Map<String, Value>> = serde_json::from_slice(&writer.into_inner())?;
+
+        for (i, mut row) in batch_rows.into_iter().enumerate() {
+            if times.is_null(i) {
+                continue;
+            }
+            let time = times.value(i);
+            if time < start || time > end {
+                continue;
+            }
+            row.insert(manifest::TIME_COLUMN.to_string(), json!(time));
+            rows.push((time, row));
+        }
+    }
+    Ok(rows)
+}
+
+/// Events in the Parquet file at `path` with a time between `start` and
+/// `end`; rows without a time are skipped
+pub fn read_events(path: &Path, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Vec<Event>> {
+    Ok(read_rows(path, None, start, end)?
+        .into_iter()
+        .map(|(time, row)| Event {
+            time: DateTime::from_timestamp_millis(time),
+            data: Value::Object(row),
+            metadata: HashMap::from([("ocsf".to_string(), json!(true))]),
+            ..Default::default()
+        })
+        .collect())
+}
@@ -0,0 +1,103 @@
+//! Upload of finished Parquet files to object storage.
+//!
+//! When `storage.uri` is set, writers still buffer into local temp files
+//! and only hand complete files to the object store, keeping the same
+//! `{category}/{class}/[partition/]` layout below the uri's prefix.
+//! Small files go up in a single request; larger ones as a multipart
+//! upload so a failed part doesn't resend the whole file.
+
+use std::{collections::HashMap, path::Path, sync::Arc, time::Duration};
+
+use anyhow::{Result, anyhow};
+use log::{debug, warn};
+use object_store::{ObjectStore, WriteMultipart, path::Path as ObjectPath};
+use striem_common::prelude::*;
+use tokio::io::AsyncReadExt;
+
+/// Files at least this large are uploaded in parts
+const MULTIPART_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// Size of each part of a multipart upload
+const PART_SIZE: usize = 16 * 1024 * 1024;
+
+/// An object store and the prefix files are placed under
+#[derive(Debug)]
+pub(crate) struct Remote {
+    store: Arc<dyn ObjectStore>,
+    prefix: ObjectPath,
+}
+
+impl Remote {
+    /// Connect to the store named by `uri` (e.g. `s3://bucket/prefix`).
+    pub(crate) fn new(uri: &str, options: &HashMap<String, String>) -> Result<Self> {
+        let url = url::Url::parse(uri)?;
+        let (store, prefix) = object_store::parse_url_opts(&url, options)?;
+        Ok(Self::with_store(Arc::from(store), prefix))
+    }
+
+    pub(crate) fn with_store(store: Arc<dyn ObjectStore>, prefix: ObjectPath) -> Self {
+        Self { store, prefix }
+    }
+
+    /// Object location for `relative`, a path below the storage root
+    pub(crate) fn location(&self, relative: &Path) -> ObjectPath {
+        relative.iter().fold(self.prefix.clone(), |p, part| {
+            p.child(part.to_string_lossy().as_ref())
+        })
+    }
+
+    /// Upload the local `file` to `relative`, retrying with exponential backoff.
+    pub(crate) async fn upload(&self, file: &Path, relative: &Path) -> Result<()> {
+        let location = self.location(relative);
+        let mut delay = Duration::from_secs(STORAGE_UPLOAD_RETRY_BASE_SECS);
+        for attempt in 1..=STORAGE_UPLOAD_ATTEMPTS {
+            match self.put(file, &location).await {
+                Ok(_) => {
+                    debug!("uploaded {}", location);
+                    return Ok(());
+                }
+                Err(e) if attempt < STORAGE_UPLOAD_ATTEMPTS => {
+                    warn!(
+                        "upload of {} attempt {}/{} failed: {}",
+                        location, attempt, STORAGE_UPLOAD_ATTEMPTS, e
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(anyhow!("upload of {} was not attempted", location))
+    }
+
+    async fn put(&self, file: &Path, location: &ObjectPath) -> Result<()> {
+        let size = tokio::fs::metadata(file).await?.len();
+        if size < MULTIPART_THRESHOLD {
+            let data = tokio::fs::read(file).await?;
+            self.store.put(location, data.into()).await?;
+            return Ok(());
+        }
+
+        let upload = self.store.put_multipart(location).await?;
+        let mut writer = WriteMultipart::new_with_chunk_size(upload, PART_SIZE);
+        let mut reader = tokio::fs::File::open(file).await?;
+        let mut buf = vec![0u8; PART_SIZE];
+        loop {
+            let n = match reader.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) => {
+                    writer.abort().await.ok();
+                    return Err(e.into());
+                }
+            };
+            if let Err(e) = writer.wait_for_capacity(4).await {
+                writer.abort().await.ok();
+                return Err(e.into());
+            }
+            writer.write(&buf[..n]);
+        }
+        writer.finish().await?;
+        Ok(())
+    }
+}
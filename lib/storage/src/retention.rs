@@ -0,0 +1,165 @@
+//! Age-based cleanup of stored Parquet files.
+//!
+//! File age comes from the UUIDv7 the writer names each file with at
+//! rotation, so no file has to be opened or stat'ed for its timestamp.
+//! Only `.parquet` files with UUIDv7 names below `{category}/{class}/` are
+//! ever considered; writers keep in-progress data in temp files outside the
+//! storage path, and anything else in the tree is left alone. Files uploaded
+//! to an object store are not swept; use the store's lifecycle rules instead.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
+use log::{debug, error, info};
+use striem_common::SysMessage;
+use striem_config::{StrIEMConfig, storage::RetentionConfig};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Outcome of one retention sweep
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Sweep {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Rotation time encoded in a writer-generated file name
+pub fn file_time(path: &Path) -> Option<DateTime<Utc>> {
+    if path.extension().is_none_or(|e| e != "parquet") {
+        return None;
+    }
+    let id = uuid::Uuid::parse_str(&path.file_stem()?.to_string_lossy()).ok()?;
+    if id.get_version_num() != 7 {
+        return None;
+    }
+    let (secs, nanos) = id.get_timestamp()?.to_unix();
+    DateTime::<Utc>::from_timestamp(secs as i64, nanos)
+}
+
+/// Delete (or with `dry_run`, report) expired files below `dir`, removing
+/// partition directories left empty.
+fn sweep_dir(dir: &Path, cutoff: DateTime<Utc>, dry_run: bool, sweep: &mut Sweep) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            sweep_dir(&path, cutoff, dry_run, sweep)?;
+            // only Hive-style partition directories are ours to remove
+            let partition = path
+                .file_name()
+                .is_some_and(|n| n.to_string_lossy().contains('='));
+            if !dry_run && partition && std::fs::read_dir(&path)?.next().is_none() {
+                std::fs::remove_dir(&path)?;
+            }
+            continue;
+        }
+
+        if file_time(&path).is_none_or(|t| t >= cutoff) {
+            continue;
+        }
+        let bytes = entry.metadata()?.len();
+        if dry_run {
+            info!("retention: would delete {}", path.display());
+        } else {
+            std::fs::remove_file(&path)?;
+            debug!("retention: deleted {}", path.display());
+        }
+        sweep.files += 1;
+        sweep.bytes += bytes;
+    }
+    Ok(())
+}
+
+/// Apply `config` to the `{category}/{class}/` tree below `base`.
+pub(crate) fn sweep(base: &Path, config: &RetentionConfig, now: DateTime<Utc>) -> Result<Sweep> {
+    let mut sweep = Sweep::default();
+    for category in std::fs::read_dir(base)? {
+        let category = category?;
+        if !category.file_type()?.is_dir() {
+            continue;
+        }
+        for class in std::fs::read_dir(category.path())? {
+            let class = class?;
+            if !class.file_type()?.is_dir() {
+                continue;
+            }
+            let days = config.days_for(
+                &category.file_name().to_string_lossy(),
+                &class.file_name().to_string_lossy(),
+            );
+            if let Some(days) = days {
+                let cutoff = now - chrono::Duration::days(days as i64);
+                sweep_dir(&class.path(), cutoff, config.dry_run, &mut sweep)?;
+            }
+        }
+    }
+    Ok(sweep)
+}
+
+/// Sweep each storage base path on the configured interval until shutdown.
+///
+/// Settings are re-read before every sweep so reloads take effect.
+pub(crate) async fn run(
+    config: Arc<ArcSwap<StrIEMConfig>>,
+    mut shutdown: broadcast::Receiver<SysMessage>,
+) {
+    loop {
+        let (retention, bases) = config
+            .load()
+            .storage
+            .as_ref()
+            .filter(|s| s.uri.is_none())
+            .map(|s| (s.retention.clone(), s.roots()))
+            .unwrap_or_default();
+
+        if retention.enabled() {
+            let dry_run = retention.dry_run;
+            let result = tokio::task::spawn_blocking(move || {
+                // a base that hasn't been written to yet has nothing to sweep
+                bases.iter().filter(|base| base.is_dir()).try_fold(
+                    Sweep::default(),
+                    |mut total, base: &PathBuf| {
+                        let swept = sweep(base, &retention, Utc::now())?;
+                        total.files += swept.files;
+                        total.bytes += swept.bytes;
+                        Ok::<_, anyhow::Error>(total)
+                    },
+                )
+            })
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|r| r);
+            match result {
+                Ok(sweep) if sweep.files > 0 => info!(
+                    "retention: {} {} files ({} bytes)",
+                    if dry_run { "would free" } else { "freed" },
+                    sweep.files,
+                    sweep.bytes
+                ),
+                Ok(_) => debug!("retention: nothing to delete"),
+                Err(e) => error!("retention sweep failed: {}", e),
+            }
+        }
+
+        let interval = config
+            .load()
+            .storage
+            .as_ref()
+            .map(|s| s.retention.interval_secs)
+            .unwrap_or(striem_common::prelude::DEFAULT_RETENTION_INTERVAL_SECS);
+
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(interval.max(1))) => {},
+            msg = shutdown.recv() => {
+                if matches!(msg, Ok(SysMessage::Shutdown) | Err(RecvError::Closed)) {
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,34 @@
+//! Storage sampling (`storage.sampling`).
+//!
+//! Whether an event is stored depends only on its uid, so the same event
+//! always makes the same decision, whichever process or replay sees it.
+
+use striem_common::event::Event;
+
+/// The event's OCSF `metadata.uid`, else StrIEM's event id
+pub(crate) fn uid(event: &Event) -> String {
+    event
+        .data
+        .get("metadata")
+        .and_then(|m| m.get("uid"))
+        .and_then(|u| u.as_str())
+        .map(str::to_string)
+        .unwrap_or_else(|| event.id.to_string())
+}
+
+/// 64-bit FNV-1a with a final mix, stable across builds and platforms
+fn hash(bytes: &[u8]) -> u64 {
+    let hash = bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    });
+    let hash = (hash ^ (hash >> 33)).wrapping_mul(0xff51afd7ed558ccd);
+    hash ^ (hash >> 33)
+}
+
+/// Whether the event with `uid` is kept at `ratio`
+pub(crate) fn keep(uid: &str, ratio: f64) -> bool {
+    if ratio >= 1.0 {
+        return true;
+    }
+    (hash(uid.as_bytes()) as f64) < ratio * u64::MAX as f64
+}
@@ -0,0 +1,235 @@
+//! OCSF schema introspection.
+//!
+//! Describes the columns of every class the storage backend writes, read
+//! from the same schema directory [`crate::ParquetBackend::new`] scans, so
+//! callers never need to open data files to learn the table layout.
+//! [`check_schemas`] reports every schema file that can't be used.
+
+use anyhow::Result;
+use arrow::datatypes::{DataType, Field};
+use parquet::arrow::parquet_to_arrow_schema;
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    finding, ocsf,
+    util::{class_schemas, read_schemas},
+};
+
+/// A schema file that couldn't be read or parsed
+#[derive(Debug, Clone)]
+pub struct SchemaError {
+    pub path: PathBuf,
+    /// 1-based line and column of the problem, when it can be told
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl SchemaError {
+    pub(crate) fn new(path: &Path, message: impl Display) -> Self {
+        SchemaError {
+            path: path.to_path_buf(),
+            position: None,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.position {
+            Some((line, column)) => write!(
+                f,
+                "{}:{}:{}: {}",
+                self.path.display(),
+                line,
+                column,
+                self.message
+            ),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+/// Problems with the schema files in a schema directory
+#[derive(Debug, Clone, Default)]
+pub struct SchemaReport {
+    pub dir: PathBuf,
+    /// Files that couldn't be read or parsed; storage doesn't start with any
+    pub errors: Vec<SchemaError>,
+    /// Files whose message isn't named after a known OCSF class, with the
+    /// name; they're skipped
+    pub unknown: Vec<(PathBuf, String)>,
+}
+
+impl SchemaReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl Display for SchemaReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} schema file(s) in {} can't be used:",
+            self.errors.len(),
+            self.dir.display()
+        )?;
+        for error in &self.errors {
+            write!(f, "\n  {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SchemaReport {}
+
+/// Read every schema file under `schemapath`, as storage does at startup,
+/// and report the ones it would fail on or skip
+pub fn check_schemas(schemapath: &Path) -> SchemaReport {
+    let (schemas, errors) = read_schemas(schemapath);
+    SchemaReport {
+        dir: schemapath.to_path_buf(),
+        errors,
+        unknown: schemas
+            .into_iter()
+            .filter(|(schema, _)| schema.name().parse::<ocsf::Class>().is_err())
+            .map(|(schema, path)| (path, schema.name().to_string()))
+            .collect(),
+    }
+}
+
+/// A (possibly nested) column, named with dot notation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub data_type: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClassSchema {
+    /// Storage directory name of the OCSF category, e.g. `iam`
+    pub category: String,
+    /// Storage directory name of the OCSF class, e.g. `authentication`
+    pub class: String,
+    pub class_uid: u32,
+    pub columns: Vec<Column>,
+    /// Top-level columns with their complete types, e.g.
+    /// `STRUCT("name" VARCHAR)`, for declaring the class's table
+    pub fields: Vec<Column>,
+    /// Schema file the class was read from
+    pub path: PathBuf,
+}
+
+/// SQL type name as DuckDB reports it for Parquet-backed data
+fn type_name(data_type: &DataType) -> String {
+    match data_type {
+        DataType::Boolean => "BOOLEAN".to_string(),
+        DataType::Int32 => "INTEGER".to_string(),
+        DataType::Int64 => "BIGINT".to_string(),
+        DataType::Float32 => "FLOAT".to_string(),
+        DataType::Float64 => "DOUBLE".to_string(),
+        DataType::Utf8 | DataType::LargeUtf8 => "VARCHAR".to_string(),
+        DataType::Binary | DataType::LargeBinary => "BLOB".to_string(),
+        DataType::Timestamp(_, Some(_)) => "TIMESTAMP WITH TIME ZONE".to_string(),
+        DataType::Timestamp(_, None) => "TIMESTAMP".to_string(),
+        DataType::Struct(_) => "STRUCT".to_string(),
+        DataType::List(child) | DataType::LargeList(child) => {
+            format!("{}[]", type_name(child.data_type()))
+        }
+        other => other.to_string().to_uppercase(),
+    }
+}
+
+/// Complete SQL type of `data_type`, with struct members and list element
+/// types spelled out
+fn sql_type(data_type: &DataType) -> String {
+    match data_type {
+        DataType::Struct(children) => format!(
+            "STRUCT({})",
+            children
+                .iter()
+                .map(|c| format!(
+                    "\"{}\" {}",
+                    c.name().replace('"', "\"\""),
+                    sql_type(c.data_type())
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        DataType::List(child) | DataType::LargeList(child) => {
+            format!("{}[]", sql_type(child.data_type()))
+        }
+        other => type_name(other),
+    }
+}
+
+/// Append `field` and, for structs (including lists of structs), its children
+fn flatten(prefix: Option<&str>, field: &Field, columns: &mut Vec<Column>) {
+    let name = match prefix {
+        Some(prefix) => format!("{}.{}", prefix, field.name()),
+        None => field.name().to_string(),
+    };
+    columns.push(Column {
+        name: name.clone(),
+        data_type: type_name(field.data_type()),
+    });
+
+    let children = match field.data_type() {
+        DataType::Struct(children) => Some(children),
+        DataType::List(child) | DataType::LargeList(child) => match child.data_type() {
+            DataType::Struct(children) => Some(children),
+            _ => None,
+        },
+        _ => None,
+    };
+    for child in children.into_iter().flatten() {
+        flatten(Some(&name), child, columns);
+    }
+}
+
+/// Load column layouts for every class schema under `schemapath`
+pub fn load_schemas(schemapath: &PathBuf) -> Result<Vec<ClassSchema>> {
+    let mut classes = Vec::new();
+    for (class, schema, path) in class_schemas(schemapath)? {
+        let category = ocsf::Category::try_from((class as u32 % 10000) / 1000)?;
+
+        let arrow_schema =
+            finding::with_finding_columns(schema.name(), parquet_to_arrow_schema(&schema, None)?);
+        let mut columns = Vec::new();
+        for field in arrow_schema.fields() {
+            flatten(None, field, &mut columns);
+        }
+        let fields = arrow_schema
+            .fields()
+            .iter()
+            .map(|field| Column {
+                name: field.name().to_string(),
+                data_type: sql_type(field.data_type()),
+            })
+            .collect();
+
+        classes.push(ClassSchema {
+            category: category.to_string(),
+            class: class.to_string(),
+            class_uid: class as u32,
+            columns,
+            fields,
+            path,
+        });
+    }
+    Ok(classes)
+}
+
+/// Names of the OCSF classes none of `classes` is for; events of these
+/// classes can't be stored
+pub fn missing_classes(classes: &[ClassSchema]) -> Vec<String> {
+    ocsf::Class::ALL
+        .iter()
+        .filter(|class| !classes.iter().any(|c| c.class_uid == **class as u32))
+        .map(|class| class.to_string())
+        .collect()
+}
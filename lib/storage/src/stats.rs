@@ -0,0 +1,112 @@
+//! Write statistics kept in each Parquet file's footer.
+//!
+//! When a file is finished, the writer records in its key-value metadata
+//! how many events it holds, how many of those had a mistyped value stored
+//! as null (see [`crate::convert`]), and the earliest and latest event
+//! `time`, so a file describes how it was produced without the manifest.
+//! Compaction sums the statistics of the files it merges. Files written
+//! before these keys existed have none, nor do files merged from them.
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use parquet::file::{
+    metadata::{FileMetaData, KeyValue},
+    reader::{FileReader, SerializedFileReader},
+};
+use serde::Serialize;
+
+use crate::manifest;
+
+const EVENTS_KEY: &str = "striem.events";
+const MISMATCHED_KEY: &str = "striem.mismatched_events";
+const MIN_TIME_KEY: &str = "striem.min_time";
+const MAX_TIME_KEY: &str = "striem.max_time";
+
+/// What went into one Parquet file
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FileStats {
+    pub events: u64,
+    /// Events stored with at least one null in place of a mistyped value
+    pub mismatched_events: u64,
+    /// Earliest event time, epoch milliseconds
+    pub min_time: Option<i64>,
+    /// Latest event time, epoch milliseconds
+    pub max_time: Option<i64>,
+}
+
+impl FileStats {
+    /// Footer key-value metadata recording these statistics
+    pub(crate) fn key_values(&self) -> Vec<KeyValue> {
+        [
+            (EVENTS_KEY, Some(self.events as i64)),
+            (MISMATCHED_KEY, Some(self.mismatched_events as i64)),
+            (MIN_TIME_KEY, self.min_time),
+            (MAX_TIME_KEY, self.max_time),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some(KeyValue::new(key.to_string(), value?.to_string())))
+        .collect()
+    }
+
+    /// Statistics recorded in the footer `metadata`, if any were
+    pub(crate) fn from_metadata(metadata: &FileMetaData) -> Option<Self> {
+        Self::from_key_values(metadata.key_value_metadata()?)
+    }
+
+    /// Statistics of a file holding the events of both
+    pub(crate) fn merged(self, other: &Self) -> Self {
+        let range = |s: &Self| s.min_time.zip(s.max_time);
+        let range = manifest::merge_range(range(&self), range(other));
+        FileStats {
+            events: self.events + other.events,
+            mismatched_events: self.mismatched_events + other.mismatched_events,
+            min_time: range.map(|r| r.0),
+            max_time: range.map(|r| r.1),
+        }
+    }
+
+    /// Statistics recorded in `metadata`, if any were
+    fn from_key_values(metadata: &[KeyValue]) -> Option<Self> {
+        let get = |key: &str| {
+            metadata
+                .iter()
+                .find(|kv| kv.key == key)
+                .and_then(|kv| kv.value.as_deref()?.parse::<i64>().ok())
+        };
+        Some(FileStats {
+            events: get(EVENTS_KEY)?.max(0) as u64,
+            mismatched_events: get(MISMATCHED_KEY).unwrap_or_default().max(0) as u64,
+            min_time: get(MIN_TIME_KEY),
+            max_time: get(MAX_TIME_KEY),
+        })
+    }
+}
+
+/// A finished Parquet file and the statistics in its footer
+#[derive(Debug, Clone, Serialize)]
+pub struct FileInfo {
+    /// Path relative to the class directory
+    pub file: PathBuf,
+    pub bytes: u64,
+    pub rows: u64,
+    /// `None` for files written without statistics
+    pub stats: Option<FileStats>,
+}
+
+/// Size, row count and write statistics of the Parquet file at `path`
+/// below the class directory `dir`, read from its footer
+pub fn read(dir: &Path, path: &Path) -> Result<FileInfo> {
+    let bytes = std::fs::metadata(path)?.len();
+    let reader = SerializedFileReader::new(File::open(path)?)?;
+    let metadata = reader.metadata().file_metadata();
+    Ok(FileInfo {
+        file: path.strip_prefix(dir)?.to_path_buf(),
+        bytes,
+        rows: metadata.num_rows().max(0) as u64,
+        stats: FileStats::from_metadata(metadata),
+    })
+}
@@ -86,8 +86,8 @@ fn parquet_test() {
 use super::writer::Writer;
 #[tokio::test]
 async fn writer_test() {
-    let temp_path = format!("{}/{}", std::env::temp_dir().display(), std::process::id());
-    tokio::fs::create_dir_all(&temp_path).await.unwrap();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let temp_path = temp_dir.path().join("api_activity");
 
     let input = json!({
         "activity_id": 1,
@@ -106,11 +106,13 @@ async fn writer_test() {
     let parquet_schema = SchemaDescriptor::new(parse_message_type(SCHEMA).unwrap().into());
     let arrow_schema = Arc::new(parquet_to_arrow_schema(&parquet_schema, None).unwrap());
 
-    let record_batch = convert_json(&input, &arrow_schema).unwrap();
-
-    let writer = Writer::new(temp_path.clone(), arrow_schema).await.unwrap();
+    let base = Arc::new(arc_swap::ArcSwap::from_pointee(
+        temp_dir.path().to_path_buf(),
+    ));
+    let writer = Writer::new(base, "api_activity".into(), arrow_schema).unwrap();
+    writer.run().await.unwrap();
 
-    writer.write(&record_batch).await.unwrap();
+    writer.write_batch(&[&input, &input]).await.unwrap();
     drop(writer);
 
     tokio::time::sleep(std::time::Duration::from_millis(100)).await;
@@ -136,5 +138,1820 @@ async fn writer_test() {
         })
         .collect::<Vec<_>>();
 
+    assert_eq!(v.len(), 2);
     assert_eq!(v[0], input);
 }
+
+#[test]
+fn rotations_align_to_the_interval() {
+    use chrono::{TimeZone, Timelike, Utc};
+    use std::time::Duration;
+
+    let interval = Duration::from_secs(300);
+    let start = Utc.with_ymd_and_hms(2024, 5, 1, 12, 3, 17).unwrap();
+
+    // the first file is cut short, then every rotation is on a boundary
+    let mut rotations = vec![writer::next_rotation(start, interval, true)];
+    for _ in 0..3 {
+        let last = *rotations.last().unwrap();
+        rotations.push(writer::next_rotation(last, interval, true));
+    }
+    let minutes = rotations
+        .iter()
+        .map(|t| (t.hour(), t.minute(), t.second()))
+        .collect::<Vec<_>>();
+    assert_eq!(minutes, [(12, 5, 0), (12, 10, 0), (12, 15, 0), (12, 20, 0)]);
+    for pair in rotations.windows(2) {
+        assert_eq!((pair[1] - pair[0]).num_seconds(), 300);
+    }
+
+    let unaligned = writer::next_rotation(start, interval, false);
+    assert_eq!(
+        (unaligned.hour(), unaligned.minute(), unaligned.second()),
+        (12, 8, 17)
+    );
+
+    // each class gets its own delay, the same every time, within the window
+    let window = Duration::from_secs(30);
+    let delays = ["iam/authentication", "findings/detection_finding"]
+        .map(|class| writer::jitter(std::path::Path::new(class), window));
+    assert!(delays.iter().all(|d| *d < window));
+    assert_ne!(delays[0], delays[1]);
+    assert_eq!(
+        writer::jitter(std::path::Path::new("iam/authentication"), window),
+        delays[0]
+    );
+    assert_eq!(
+        writer::jitter(std::path::Path::new("iam/authentication"), Duration::ZERO),
+        Duration::ZERO
+    );
+
+    // a file is named for its rotation, however late it's finished
+    let name = format!("{}.parquet", writer::file_id(start));
+    assert_eq!(
+        retention::file_time(std::path::Path::new(&name)),
+        Some(start)
+    );
+}
+
+#[test]
+fn finished_files_are_renamed_or_copied_into_place() {
+    use std::io::{Error, ErrorKind};
+
+    let storage = tempfile::tempdir().unwrap();
+    let scratch = storage.path().join(".tmp");
+    std::fs::create_dir_all(&scratch).unwrap();
+
+    let tmp = scratch.join("renamed");
+    std::fs::write(&tmp, b"PAR1").unwrap();
+    let path = storage.path().join("iam/authentication/renamed.parquet");
+    writer::place(&tmp, &path).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"PAR1");
+    assert!(!tmp.exists());
+
+    // a rename across filesystems falls back to a copy under a skipped name
+    let tmp = scratch.join("copied");
+    std::fs::write(&tmp, b"PAR1").unwrap();
+    let path = storage.path().join("iam/authentication/copied.parquet");
+    let renames = std::cell::RefCell::new(Vec::new());
+    writer::place_with(&tmp, &path, |from, to| {
+        renames
+            .borrow_mut()
+            .push((from.to_path_buf(), to.to_path_buf()));
+        Err(Error::from(ErrorKind::CrossesDevices))
+    })
+    .unwrap();
+    assert_eq!(renames.borrow().len(), 1);
+    assert_eq!(std::fs::read(&path).unwrap(), b"PAR1");
+    assert!(!tmp.exists());
+
+    let mut files = Vec::new();
+    util::parquet_files(storage.path(), &mut files).unwrap();
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            storage.path().join("iam/authentication/copied.parquet"),
+            storage.path().join("iam/authentication/renamed.parquet"),
+        ]
+    );
+    // nothing but the two files is left behind
+    assert_eq!(
+        std::fs::read_dir(storage.path().join("iam/authentication"))
+            .unwrap()
+            .count(),
+        2
+    );
+
+    // other failures are returned, leaving the file where it was
+    let tmp = scratch.join("failed");
+    std::fs::write(&tmp, b"PAR1").unwrap();
+    let path = storage.path().join("iam/authentication/failed.parquet");
+    let failed = writer::place_with(&tmp, &path, |_, _| {
+        Err(Error::from(ErrorKind::PermissionDenied))
+    });
+    assert!(failed.is_err());
+    assert!(tmp.exists() && !path.exists());
+}
+
+#[test]
+fn schema_columns_flattened() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("api_activity.parquet.schema"), SCHEMA).unwrap();
+
+    let classes = schema::load_schemas(&dir.path().to_path_buf()).unwrap();
+    assert_eq!(classes.len(), 1);
+    assert_eq!(classes[0].class_uid, 6003);
+    assert_eq!(
+        classes[0].category,
+        ocsf::Category::try_from(6).unwrap().to_string()
+    );
+
+    let columns = classes[0]
+        .columns
+        .iter()
+        .map(|c| (c.name.as_str(), c.data_type.as_str()))
+        .collect::<Vec<_>>();
+    assert!(columns.contains(&("activity_id", "INTEGER")));
+    assert!(columns.contains(&("actor", "STRUCT")));
+    assert!(columns.contains(&("actor.app_name", "VARCHAR")));
+    assert!(columns.contains(&("authorizations", "STRUCT[]")));
+    assert!(columns.contains(&("authorizations.decision", "VARCHAR")));
+    assert!(columns.contains(&("authorizations.is_applied", "BOOLEAN")));
+}
+
+#[test]
+fn schema_reports_file_and_missing_classes() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("api_activity.parquet.schema");
+    std::fs::write(&file, SCHEMA).unwrap();
+
+    let classes = schema::load_schemas(&dir.path().to_path_buf()).unwrap();
+    assert_eq!(classes[0].path, file);
+
+    let missing = schema::missing_classes(&classes);
+    assert_eq!(missing.len(), ocsf::Class::ALL.len() - 1);
+    assert!(!missing.contains(&"api_activity".to_string()));
+    assert!(missing.contains(&"authentication".to_string()));
+}
+
+#[test]
+fn schema_directory_reports_every_bad_file() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("api_activity.parquet.schema"), SCHEMA).unwrap();
+    let bad = dir.path().join("authentication.parquet.schema");
+    std::fs::write(
+        &bad,
+        "message authentication {\n    optional INT32 activity_id;\n    optional group actor {\n}\n",
+    )
+    .unwrap();
+    let typo = dir.path().join("nested/file_activity.parquet.schema");
+    std::fs::create_dir(typo.parent().unwrap()).unwrap();
+    std::fs::write(
+        &typo,
+        "message file_activity {\n    optional INT65 size;\n}\n",
+    )
+    .unwrap();
+    let unknown = dir.path().join("not_a_class.parquet.schema");
+    std::fs::write(&unknown, SCHEMA.replace("api_activity", "not_a_class")).unwrap();
+
+    let report = schema::check_schemas(dir.path());
+    assert!(!report.is_ok());
+    let errors = report
+        .errors
+        .iter()
+        .map(|e| (e.path.clone(), e.position))
+        .collect::<Vec<_>>();
+    // the group's braces are unbalanced, and INT65 only appears once
+    assert_eq!(
+        errors,
+        [(bad.clone(), Some((1, 24))), (typo.clone(), Some((2, 14)))]
+    );
+    assert_eq!(report.unknown, [(unknown, "not_a_class".to_string())]);
+
+    // startup fails with every bad file in the one error
+    let err = schema::load_schemas(&dir.path().to_path_buf())
+        .unwrap_err()
+        .to_string();
+    assert!(err.starts_with(&format!("2 schema file(s) in {}", dir.path().display())));
+    assert!(err.contains(&format!("\n  {}:1:24: ", bad.display())));
+    assert!(err.contains(&format!("\n  {}:2:14: ", typo.display())));
+
+    // the backend fails with the same report
+    let config = striem_config::StrIEMConfig::from_json(
+        &json!({"storage": {"path": dir.path().join("data"), "schema": dir.path()}}).to_string(),
+    )
+    .unwrap();
+    let err = ParquetBackend::new(&Arc::new(arc_swap::ArcSwap::from_pointee(config)))
+        .err()
+        .unwrap();
+    let report = err.downcast_ref::<schema::SchemaReport>().unwrap();
+    assert_eq!(report.errors.len(), 2);
+
+    // a class the backend doesn't know is skipped
+    std::fs::remove_file(&bad).unwrap();
+    std::fs::remove_file(&typo).unwrap();
+    let classes = schema::load_schemas(&dir.path().to_path_buf()).unwrap();
+    assert_eq!(classes.len(), 1);
+    assert_eq!(classes[0].class, "api_activity");
+
+    let missing = schema::check_schemas(&dir.path().join("missing"));
+    assert_eq!(missing.errors.len(), 1);
+    assert_eq!(
+        missing.errors[0].to_string(),
+        format!("{}: does not exist", dir.path().join("missing").display())
+    );
+}
+
+const REQUIRED_SCHEMA: &str = r#"message test {
+    required INT64 id;
+    optional BYTE_ARRAY name (STRING);
+    optional group actor {
+        required BYTE_ARRAY app_name (STRING);
+    }
+    }"#;
+
+fn required_schema() -> arrow::datatypes::SchemaRef {
+    let parquet_schema = SchemaDescriptor::new(parse_message_type(REQUIRED_SCHEMA).unwrap().into());
+    Arc::new(parquet_to_arrow_schema(&parquet_schema, None).unwrap())
+}
+
+#[test]
+fn convert_batch_skips_malformed_events() {
+    use arrow::array::{Array, Int64Array, StringArray, StructArray};
+
+    let schema = required_schema();
+    let events = [
+        json!({"id": 1, "name": "a", "actor": {"app_name": "x"}}),
+        json!({"name": "missing id"}),
+        json!("not an object"),
+        json!({"id": 2, "name": 7}),
+        // a null parent struct doesn't require its children
+        json!({"id": 3, "actor": null}),
+        json!({"id": 4, "actor": {}}),
+    ];
+    let refs = events.iter().collect::<Vec<_>>();
+
+    assert!(convert_json(&events[1], &schema).is_err());
+
+    let batch = convert_json_batch(&refs, &schema).unwrap();
+    assert_eq!(batch.num_rows(), 3);
+
+    let ids = batch
+        .column(0)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap();
+    assert_eq!(&ids.values()[..], &[1, 2, 3]);
+
+    // mistyped nullable strings keep their JSON text
+    let names = batch
+        .column(1)
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .unwrap();
+    assert_eq!(names.value(1), "7");
+
+    let actor = batch
+        .column(2)
+        .as_any()
+        .downcast_ref::<StructArray>()
+        .unwrap();
+    assert!(actor.is_valid(0));
+    assert!(actor.is_null(2));
+}
+
+#[test]
+fn convert_batch_matches_single_rows() {
+    let parquet_schema = SchemaDescriptor::new(parse_message_type(SCHEMA).unwrap().into());
+    let schema = Arc::new(parquet_to_arrow_schema(&parquet_schema, None).unwrap());
+    let events = (0..10)
+        .map(|i| {
+            let actor = match i % 3 {
+                0 => json!(null),
+                _ => json!({"app_name": format!("app{}", i)}),
+            };
+            let authorizations = (0..i % 4)
+                .map(|j| json!({"decision": format!("d{}", j), "is_applied": j % 2 == 0}))
+                .collect::<Vec<_>>();
+            json!({"activity_id": i, "actor": actor, "authorizations": authorizations})
+        })
+        .collect::<Vec<_>>();
+
+    let batch = convert_json_batch(&events.iter().collect::<Vec<_>>(), &schema).unwrap();
+    let rows = events
+        .iter()
+        .map(|e| convert_json(e, &schema).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        batch,
+        arrow::compute::concat_batches(&schema, &rows).unwrap()
+    );
+}
+
+/// Run with `cargo test -p striem_storage --release -- --ignored --nocapture`
+#[test]
+#[ignore]
+fn convert_batch_throughput() {
+    let parquet_schema = SchemaDescriptor::new(parse_message_type(SCHEMA).unwrap().into());
+    let schema = Arc::new(parquet_to_arrow_schema(&parquet_schema, None).unwrap());
+    let events = (0..10_000)
+        .map(|i| {
+            json!({
+                "activity_id": i,
+                "activity_name": "test",
+                "actor": {"app_name": "test"},
+                "authorizations": [{"decision": "allow", "is_applied": true}],
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let start = std::time::Instant::now();
+    for event in &events {
+        convert_json(event, &schema).unwrap();
+    }
+    let per_event = start.elapsed();
+
+    let start = std::time::Instant::now();
+    convert_json_batch(&events.iter().collect::<Vec<_>>(), &schema).unwrap();
+    let batched = start.elapsed();
+
+    println!("per-event: {:?}, batched: {:?}", per_event, batched);
+    assert!(batched < per_event);
+}
+
+#[test]
+fn partition_dirs() {
+    use striem_config::storage::Partitioning;
+
+    use crate::writer::partition_dir;
+
+    let time = chrono::DateTime::parse_from_rfc3339("2025-03-04T05:06:07Z")
+        .unwrap()
+        .with_timezone(&chrono::Utc);
+    assert_eq!(
+        partition_dir(Partitioning::None, time),
+        std::path::PathBuf::new()
+    );
+    assert_eq!(
+        partition_dir(Partitioning::Daily, time),
+        std::path::PathBuf::from("date=2025-03-04")
+    );
+    assert_eq!(
+        partition_dir(Partitioning::Hourly, time),
+        std::path::PathBuf::from("date=2025-03-04/hour=05")
+    );
+}
+
+#[test]
+fn retention_sweep() {
+    use striem_config::storage::RetentionConfig;
+
+    let dir = tempfile::tempdir().unwrap();
+    let now = chrono::Utc::now();
+    let file = |subpath: &str, age_days: i64| {
+        let time = now - chrono::Duration::days(age_days);
+        let ts = uuid::Timestamp::from_unix(uuid::NoContext, time.timestamp() as u64, 0);
+        let path = dir.path().join(subpath);
+        std::fs::create_dir_all(&path).unwrap();
+        let path = path.join(format!("{}.parquet", uuid::Uuid::new_v7(ts)));
+        std::fs::write(&path, b"0123456789").unwrap();
+        path
+    };
+
+    let old_auth = file("iam/authentication/date=2020-01-01", 40);
+    let new_auth = file("iam/authentication", 1);
+    let old_network = file("network_activity/network_activity", 10);
+    let old_finding = file("findings/detection_finding", 40);
+    let legacy = dir.path().join("iam/authentication/legacy.parquet");
+    std::fs::write(&legacy, b"").unwrap();
+
+    let mut config = RetentionConfig {
+        days: Some(30),
+        categories: [("network_activity".to_string(), 7)].into(),
+        findings_days: Some(365),
+        dry_run: true,
+        ..Default::default()
+    };
+
+    let sweep = retention::sweep(dir.path(), &config, now).unwrap();
+    assert_eq!(sweep.files, 2);
+    assert_eq!(sweep.bytes, 20);
+    assert!(old_auth.exists() && old_network.exists());
+
+    config.dry_run = false;
+    retention::sweep(dir.path(), &config, now).unwrap();
+    assert!(!old_auth.exists() && !old_network.exists());
+    assert!(new_auth.exists() && old_finding.exists() && legacy.exists());
+    // emptied partition directories go too
+    assert!(
+        !dir.path()
+            .join("iam/authentication/date=2020-01-01")
+            .exists()
+    );
+}
+
+#[tokio::test]
+async fn remote_upload() {
+    use crate::remote::Remote;
+    use object_store::{ObjectStore, memory::InMemory, path::Path};
+
+    let store = Arc::new(InMemory::new());
+    let remote = Remote::with_store(store.clone(), Path::from("striem"));
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("upload.parquet");
+    std::fs::write(&file, b"PAR1").unwrap();
+
+    let relative = std::path::PathBuf::from("iam/authentication/date=2025-03-04/x.parquet");
+    remote.upload(&file, &relative).await.unwrap();
+
+    let location = Path::from("striem/iam/authentication/date=2025-03-04/x.parquet");
+    assert_eq!(remote.location(&relative), location);
+    let data = store.get(&location).await.unwrap().bytes().await.unwrap();
+    assert_eq!(&data[..], b"PAR1");
+}
+
+/// Schema with a single field `v` of type `data_type`
+fn single_field(
+    data_type: arrow::datatypes::DataType,
+    nullable: bool,
+) -> arrow::datatypes::SchemaRef {
+    Arc::new(arrow::datatypes::Schema::new(vec![
+        arrow::datatypes::Field::new("v", data_type, nullable),
+    ]))
+}
+
+/// Convert `values` as rows of `{"v": value}` and return the column
+fn convert_column(
+    values: &[serde_json::Value],
+    schema: &arrow::datatypes::SchemaRef,
+) -> arrow::array::ArrayRef {
+    let events = values.iter().map(|v| json!({ "v": v })).collect::<Vec<_>>();
+    let batch = convert_json_batch(&events.iter().collect::<Vec<_>>(), schema).unwrap();
+    batch.column(0).clone()
+}
+
+#[test]
+fn convert_unsigned_types() {
+    use arrow::array::{Array, UInt8Array, UInt64Array};
+    use arrow::datatypes::DataType;
+
+    let column = convert_column(
+        &[json!(255), json!(256), json!(-1), json!("7"), json!(null)],
+        &single_field(DataType::UInt8, true),
+    );
+    let column = column.as_any().downcast_ref::<UInt8Array>().unwrap();
+    assert_eq!(column.value(0), 255);
+    // overflow, negative and mistyped values become null
+    assert!(column.is_null(1) && column.is_null(2) && column.is_null(3) && column.is_null(4));
+
+    let column = convert_column(&[json!(u64::MAX)], &single_field(DataType::UInt64, true));
+    let column = column.as_any().downcast_ref::<UInt64Array>().unwrap();
+    assert_eq!(column.value(0), u64::MAX);
+
+    let required = single_field(DataType::UInt32, false);
+    assert!(convert_json(&json!({"v": 1}), &required).is_ok());
+    assert!(convert_json(&json!({"v": -1}), &required).is_err());
+    assert!(convert_json(&json!({"v": 1u64 << 32}), &required).is_err());
+    assert!(convert_json(&json!({"v": "1"}), &required).is_err());
+}
+
+#[test]
+fn convert_date_types() {
+    use arrow::array::{Array, Date32Array, Date64Array};
+    use arrow::datatypes::DataType;
+
+    let column = convert_column(
+        &[
+            json!(19000),
+            json!("2025-03-04"),
+            json!("03/04/2025"),
+            json!(true),
+        ],
+        &single_field(DataType::Date32, true),
+    );
+    let column = column.as_any().downcast_ref::<Date32Array>().unwrap();
+    assert_eq!(column.value(0), 19000);
+    assert_eq!(column.value(1), 20151);
+    assert!(column.is_null(2) && column.is_null(3));
+
+    let column = convert_column(
+        &[json!(1_741_046_400_000i64), json!("2025-03-04"), json!([])],
+        &single_field(DataType::Date64, true),
+    );
+    let column = column.as_any().downcast_ref::<Date64Array>().unwrap();
+    assert_eq!(column.value(0), 1_741_046_400_000);
+    assert_eq!(column.value(1), 1_741_046_400_000);
+    assert!(column.is_null(2));
+
+    let required = single_field(DataType::Date32, false);
+    assert!(convert_json(&json!({"v": "2025-03-04"}), &required).is_ok());
+    assert!(convert_json(&json!({"v": "2025-13-01"}), &required).is_err());
+    assert!(convert_json(&json!({"v": 1.5}), &required).is_err());
+}
+
+#[test]
+fn convert_decimal_type() {
+    use arrow::array::{Array, Decimal128Array};
+    use arrow::datatypes::DataType;
+
+    let column = convert_column(
+        &[
+            json!(12.5),
+            json!("-0.07"),
+            json!("1e3"),
+            json!(7),
+            json!("1.234"),
+            json!("123456789"),
+            json!("abc"),
+            json!(false),
+        ],
+        &single_field(DataType::Decimal128(8, 2), true),
+    );
+    let column = column.as_any().downcast_ref::<Decimal128Array>().unwrap();
+    assert_eq!(column.value(0), 1250);
+    assert_eq!(column.value(1), -7);
+    assert_eq!(column.value(2), 100_000);
+    assert_eq!(column.value(3), 700);
+    // excess scale, excess precision and non-numeric values become null
+    for i in 4..8 {
+        assert!(column.is_null(i), "row {}", i);
+    }
+    assert_eq!(column.precision(), 8);
+    assert_eq!(column.scale(), 2);
+
+    let required = single_field(DataType::Decimal128(8, 2), false);
+    assert!(convert_json(&json!({"v": "1.20"}), &required).is_ok());
+    assert!(convert_json(&json!({"v": "1.234"}), &required).is_err());
+    assert!(convert_json(&json!({"v": {}}), &required).is_err());
+    // exponents that would overflow the shift are invalid, not a panic
+    assert!(convert_json(&json!({"v": "1e2147483647"}), &required).is_err());
+    assert!(convert_json(&json!({"v": "1.5e-2147483648"}), &required).is_err());
+}
+
+#[test]
+fn convert_map_type() {
+    use arrow::array::{Array, Int64Array, MapArray, StringArray};
+    use arrow::datatypes::{DataType, Field, Fields};
+
+    let entries = Field::new(
+        "key_value",
+        DataType::Struct(Fields::from(vec![
+            Field::new("key", DataType::Utf8, false),
+            Field::new("value", DataType::Int64, true),
+        ])),
+        false,
+    );
+    let map_type = DataType::Map(Arc::new(entries), false);
+
+    let column = convert_column(
+        &[
+            json!({"a": 1, "b": "x"}),
+            json!({}),
+            json!(null),
+            json!([1, 2]),
+        ],
+        &single_field(map_type.clone(), true),
+    );
+    let column = column.as_any().downcast_ref::<MapArray>().unwrap();
+    assert_eq!(column.value_length(0), 2);
+    assert_eq!(column.value_length(1), 0);
+    assert!(column.is_null(2) && column.is_null(3));
+
+    let keys = column
+        .keys()
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .unwrap();
+    assert_eq!(keys.value(0), "a");
+    assert_eq!(keys.value(1), "b");
+    // values follow the value field's own null policy
+    let values = column
+        .values()
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap();
+    assert_eq!(values.value(0), 1);
+    assert!(values.is_null(1));
+
+    let required = single_field(map_type, false);
+    assert!(convert_json(&json!({"v": {"a": 1}}), &required).is_ok());
+    assert!(convert_json(&json!({"v": "a=1"}), &required).is_err());
+    assert!(convert_json(&json!({}), &required).is_err());
+}
+
+#[test]
+fn convert_timestamp_strings() {
+    use arrow::array::{
+        Array, TimestampMillisecondArray, TimestampNanosecondArray, TimestampSecondArray,
+    };
+    use arrow::datatypes::{DataType, TimeUnit};
+
+    let millis = single_field(DataType::Timestamp(TimeUnit::Millisecond, None), true);
+    let column = convert_column(
+        &[
+            json!("2024-06-01T12:34:56.789Z"),
+            json!("2024-06-01T14:34:56.789+02:00"),
+            json!("2024-06-01 12:34:56.789"),
+            json!("2024-06-01T12:34:56"),
+            json!(1717245296789i64),
+            json!("1717245296789"),
+            json!("yesterday"),
+        ],
+        &millis,
+    );
+    let column = column
+        .as_any()
+        .downcast_ref::<TimestampMillisecondArray>()
+        .unwrap();
+    for i in [0, 1, 2, 4, 5] {
+        assert_eq!(column.value(i), 1717245296789, "row {}", i);
+    }
+    assert_eq!(column.value(3), 1717245296000);
+    // unparseable strings are null in a nullable field
+    assert!(column.is_null(6));
+
+    // zone-less input is read in the schema's timezone
+    let offset = single_field(
+        DataType::Timestamp(TimeUnit::Second, Some("+02:00".into())),
+        true,
+    );
+    let column = convert_column(
+        &[json!("2024-06-01 14:34:56"), json!("2024-06-01T12:34:56Z")],
+        &offset,
+    );
+    let column = column
+        .as_any()
+        .downcast_ref::<TimestampSecondArray>()
+        .unwrap();
+    assert_eq!(column.value(0), 1717245296);
+    assert_eq!(column.value(1), 1717245296);
+
+    let nanos = single_field(DataType::Timestamp(TimeUnit::Nanosecond, None), true);
+    let column = convert_column(&[json!("2024-06-01T12:34:56.123456789Z")], &nanos);
+    let column = column
+        .as_any()
+        .downcast_ref::<TimestampNanosecondArray>()
+        .unwrap();
+    assert_eq!(column.value(0), 1717245296123456789);
+
+    let required = single_field(DataType::Timestamp(TimeUnit::Microsecond, None), false);
+    assert!(convert_json(&json!({"v": "2024-06-01T12:34:56.000001Z"}), &required).is_ok());
+    assert!(convert_json(&json!({"v": "yesterday"}), &required).is_err());
+}
+
+#[test]
+fn convert_errors_counted_once_per_value() {
+    use arrow::datatypes::{DataType, Field, Schema};
+
+    let schema = Arc::new(
+        Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("port", DataType::Int32, true),
+        ])
+        .with_metadata([("description".to_string(), "convert_errors_test".to_string())].into()),
+    );
+    let events = [
+        json!({"id": 1, "port": "http"}),
+        json!({"id": 2, "port": 1u64 << 40}),
+        json!({"port": 80}),
+        json!({"id": 3, "port": "https"}),
+    ];
+    let batch = convert_json_batch(&events.iter().collect::<Vec<_>>(), &schema).unwrap();
+    assert_eq!(batch.num_rows(), 3);
+
+    let errors = convert_errors()
+        .into_iter()
+        .filter(|e| e.class == "convert_errors_test")
+        .map(|e| ((e.field, e.kind), e.count))
+        .collect::<std::collections::HashMap<_, _>>();
+    // the slow-path retry doesn't double count the nullable mismatches
+    assert_eq!(errors[&("port".to_string(), "type")], 2);
+    assert_eq!(errors[&("port".to_string(), "range")], 1);
+    assert_eq!(errors[&(String::new(), "skipped")], 1);
+    assert_eq!(
+        striem_common::metrics::value(
+            "striem_storage_conversion_errors_total",
+            &[
+                ("class", "convert_errors_test"),
+                ("field", "port"),
+                ("kind", "type")
+            ]
+        ),
+        Some(2.0)
+    );
+}
+
+#[test]
+fn mismatches_counted_per_event() {
+    use arrow::datatypes::{DataType, Field, Fields, Schema};
+
+    let ports = Field::new_list("ports", Field::new("item", DataType::Int32, true), true);
+    let user = Field::new_struct(
+        "user",
+        Fields::from(vec![Field::new("uid", DataType::Int64, true)]),
+        true,
+    );
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, true),
+        ports,
+        user,
+    ]));
+    let events = [
+        json!({"id": 1, "ports": [443, "http"], "user": {"uid": "root"}}),
+        json!({"id": 2, "ports": [22]}),
+        json!({"id": 3, "ports": [80, 8080, "alt"]}),
+        json!({"id": "four", "user": "nobody"}),
+    ];
+    let refs = events.iter().collect::<Vec<_>>();
+
+    // two mismatches in the first event still count it once
+    let converted = convert_events(&refs, &schema, false).unwrap();
+    assert_eq!(converted.batch.num_rows(), 4);
+    assert_eq!(converted.mismatched, 3);
+    let converted = convert_events(&refs[1..2], &schema, false).unwrap();
+    assert_eq!(converted.mismatched, 0);
+}
+
+#[tokio::test]
+async fn strict_mode_rejects_mismatches() {
+    use arrow::array::{Array, Int32Array};
+    use arrow::datatypes::{DataType, Field, Schema};
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("port", DataType::Int32, true),
+    ]));
+    let events = [
+        json!({"id": 1, "port": 443}),
+        json!({"id": 2, "port": "http"}),
+        json!({"id": 3}),
+    ];
+    let refs = events.iter().collect::<Vec<_>>();
+
+    // lenient: the mistyped port is stored as null
+    let lenient = convert_events(&refs, &schema, false).unwrap();
+    assert_eq!(lenient.batch.num_rows(), 3);
+    assert!(lenient.rejected.is_empty());
+    let ports = lenient
+        .batch
+        .column(1)
+        .as_any()
+        .downcast_ref::<Int32Array>()
+        .unwrap();
+    assert!(ports.is_null(1));
+    assert_eq!(lenient.mismatched, 1);
+
+    // strict: the event is rejected; a merely absent field is still fine
+    let strict = convert_events(&refs, &schema, true).unwrap();
+    assert_eq!(strict.batch.num_rows(), 2);
+    assert_eq!(strict.rejected.len(), 1);
+    assert_eq!(strict.rejected[0].0, 1);
+    assert!(strict.rejected[0].1.contains("port"));
+
+    let dir = tempfile::tempdir().unwrap();
+    let rejected = [(&events[1], strict.rejected[0].1.as_str())];
+    dead_letter::write(dir.path(), "network_activity", &rejected)
+        .await
+        .unwrap();
+    dead_letter::write(dir.path(), "network_activity", &rejected)
+        .await
+        .unwrap();
+
+    let file = std::fs::read_dir(dir.path().join("dead_letter/network_activity"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let lines = std::fs::read_to_string(file).unwrap();
+    let lines = lines.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    let line: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(line["event"], events[1]);
+    assert!(line["error"].as_str().unwrap().contains("port"));
+}
+
+#[test]
+fn batch_values_fall_back_to_event_time() {
+    use crate::queue::Batch;
+    use chrono::{TimeZone, Utc};
+    use striem_common::event::Event;
+
+    let time = Utc.timestamp_millis_opt(1_700_000_000_000).unwrap();
+    let events = Arc::new(vec![
+        Event {
+            time: Some(time),
+            ..Event::from(json!({"class_uid": 1001}))
+        },
+        // the JSON's own time wins
+        Event {
+            time: Some(time),
+            ..Event::from(json!({"class_uid": 1001, "time": 5}))
+        },
+        Event::from(json!({"class_uid": 1001})),
+    ]);
+    let batch = Batch {
+        events,
+        indexes: vec![0, 1, 2],
+    };
+    let values = batch.values();
+    assert_eq!(values[0]["time"], json!(1_700_000_000_000_i64));
+    assert_eq!(values[1]["time"], json!(5));
+    assert!(values[2].get("time").is_none());
+}
+
+#[test]
+fn ingest_peer_and_agent_are_stored() {
+    use crate::{queue::Batch, raw};
+    use std::collections::HashMap;
+    use striem_common::event::Event;
+
+    let metadata = HashMap::from([
+        ("ingest_peer".to_string(), json!("10.0.0.7:50312")),
+        ("ingest_agent".to_string(), json!("edge-1")),
+    ]);
+    let events = Arc::new(vec![
+        Event::from((json!({"class_uid": 1001}), metadata.clone())),
+        Event::from(json!({"class_uid": 1001})),
+    ]);
+    let batch = Batch {
+        events: events.clone(),
+        indexes: vec![0, 1],
+    };
+    let values = batch.values();
+    assert_eq!(
+        values[0]["metadata"]["loggers"],
+        json!([{"name": "vector", "device": {"ip": "10.0.0.7"}, "uid": "edge-1"}])
+    );
+    assert!(values[1].get("metadata").is_none());
+
+    // unclassified events keep their metadata as is
+    let row = raw::row(&events[0]);
+    assert_eq!(row["metadata"]["ingest_peer"], json!("10.0.0.7:50312"));
+    assert_eq!(row["metadata"]["ingest_agent"], json!("edge-1"));
+}
+
+#[tokio::test]
+async fn stalled_class_queue_does_not_block_others() {
+    use crate::queue::{Batch, ClassQueue};
+    use striem_common::event::Event;
+    use striem_config::storage::{QueueConfig, QueuePolicy};
+    use tokio::sync::mpsc;
+
+    let events = Arc::new(vec![Event::from(json!({"class_uid": 4001}))]);
+    let batch = || Batch {
+        events: events.clone(),
+        indexes: vec![0],
+    };
+    let config = QueueConfig {
+        capacity: 1,
+        policy: QueuePolicy::Drop,
+        timeout_ms: 50,
+    };
+
+    // a class whose writes never finish
+    let (stalled, _) = ClassQueue::spawn("stalled_test".to_string(), config, |_| {
+        std::future::pending::<()>()
+    });
+    let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+    let (healthy, _) = ClassQueue::spawn("healthy_test".to_string(), config, move |b| {
+        let done_tx = done_tx.clone();
+        async move {
+            done_tx.send(b.values().len()).unwrap();
+        }
+    });
+
+    // the first batch is taken by the stalled task, the second fills the
+    // queue and the rest are dropped
+    assert!(stalled.send(batch()).await);
+    tokio::task::yield_now().await;
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    assert!(stalled.send(batch()).await);
+    for _ in 0..3 {
+        assert!(!stalled.send(batch()).await);
+    }
+    assert_eq!(stalled.depth(), 1);
+
+    let start = std::time::Instant::now();
+    for _ in 0..10 {
+        assert!(healthy.send(batch()).await);
+        let written = tokio::time::timeout(std::time::Duration::from_secs(1), done_rx.recv())
+            .await
+            .unwrap();
+        assert_eq!(written, Some(1));
+    }
+    assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+    assert_eq!(
+        striem_common::metrics::value(
+            "striem_storage_dropped_events_total",
+            &[("class", "stalled_test")]
+        ),
+        Some(3.0)
+    );
+    assert_eq!(
+        striem_common::metrics::value("striem_storage_queue_depth", &[("class", "stalled_test")]),
+        Some(1.0)
+    );
+
+    // blocking waits for the timeout before giving up
+    let blocking = ClassQueue::spawn(
+        "blocking_test".to_string(),
+        QueueConfig {
+            policy: QueuePolicy::Block,
+            ..config
+        },
+        |_| std::future::pending::<()>(),
+    )
+    .0;
+    assert!(blocking.send(batch()).await);
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    assert!(blocking.send(batch()).await);
+    let start = std::time::Instant::now();
+    assert!(!blocking.send(batch()).await);
+    assert!(start.elapsed() >= std::time::Duration::from_millis(50));
+}
+
+#[tokio::test]
+async fn manifest_records_and_prunes_files() {
+    use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+    use chrono::{TimeZone, Utc};
+
+    let dir = tempfile::tempdir().unwrap();
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new(
+            "time",
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            true,
+        ),
+    ]));
+    let base = Arc::new(arc_swap::ArcSwap::from_pointee(dir.path().to_path_buf()));
+    let writer = Writer::new(base, "findings/detection_finding".into(), schema).unwrap();
+    writer.run().await.unwrap();
+
+    let events = [
+        json!({"id": 1, "time": "2025-01-01T00:10:00Z"}),
+        json!({"id": 2, "time": "2025-01-01T00:05:00Z"}),
+        json!({"id": 3, "time": "2025-01-01T00:20:00Z"}),
+    ];
+    writer
+        .write_batch(&events.iter().collect::<Vec<_>>())
+        .await
+        .unwrap();
+    drop(writer);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let class_dir = dir.path().join("findings/detection_finding");
+    let entries = manifest::read(&class_dir).unwrap();
+    assert_eq!(entries.len(), 1);
+    let entry = &entries[0];
+    assert_eq!(entry.rows, 3);
+    assert_eq!(
+        entry.min_time,
+        Some(
+            Utc.with_ymd_and_hms(2025, 1, 1, 0, 5, 0)
+                .unwrap()
+                .timestamp_millis()
+        )
+    );
+    assert_eq!(
+        entry.max_time,
+        Some(
+            Utc.with_ymd_and_hms(2025, 1, 1, 0, 20, 0)
+                .unwrap()
+                .timestamp_millis()
+        )
+    );
+    let written = class_dir.join(&entry.file);
+    assert_eq!(entry.bytes, std::fs::metadata(&written).unwrap().len());
+
+    let at = |h, m| Utc.with_ymd_and_hms(2025, 1, 1, h, m, 0).unwrap();
+    assert_eq!(
+        manifest::files_for_range(&class_dir, at(0, 15), at(0, 30)).unwrap(),
+        vec![written.clone()]
+    );
+    assert!(
+        manifest::files_for_range(&class_dir, at(1, 0), at(2, 0))
+            .unwrap()
+            .is_empty()
+    );
+
+    // files the manifest doesn't know about are always included
+    let unknown = class_dir.join("unknown.parquet");
+    std::fs::copy(&written, &unknown).unwrap();
+    assert_eq!(
+        manifest::files_for_range(&class_dir, at(1, 0), at(2, 0)).unwrap(),
+        vec![unknown.clone()]
+    );
+
+    // a corrupt manifest falls back to pruning by name, the file written
+    // now falling outside 2025, and is left for compaction to rebuild
+    std::fs::write(class_dir.join(manifest::MANIFEST_FILE), "not json\n").unwrap();
+    assert_eq!(
+        manifest::files_for_range(&class_dir, at(1, 0), at(2, 0)).unwrap(),
+        vec![unknown.clone()]
+    );
+    assert!(manifest::read(&class_dir).is_err());
+    manifest::rebuild(&class_dir).unwrap();
+    let rebuilt = manifest::read(&class_dir).unwrap();
+    assert_eq!(rebuilt.len(), 2);
+    assert!(
+        rebuilt
+            .iter()
+            .all(|e| e.min_time == entry.min_time && e.max_time == entry.max_time && e.rows == 3)
+    );
+}
+
+#[tokio::test]
+async fn footer_records_write_stats() {
+    use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+    use chrono::{TimeZone, Utc};
+
+    let dir = tempfile::tempdir().unwrap();
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("count", DataType::Int64, true),
+        Field::new(
+            "time",
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            true,
+        ),
+    ]));
+    let base = Arc::new(arc_swap::ArcSwap::from_pointee(dir.path().to_path_buf()));
+    let writer = Writer::new(base, "iam/authentication".into(), schema).unwrap();
+    writer.flush().await.unwrap();
+
+    let events = [
+        json!({"count": 1, "time": "2025-01-01T00:10:00Z"}),
+        json!({"count": "many", "time": "2025-01-01T00:05:00Z"}),
+        json!({"count": 3, "time": "2025-01-01T00:20:00Z"}),
+    ];
+    writer
+        .write_batch(&events.iter().collect::<Vec<_>>())
+        .await
+        .unwrap();
+    writer
+        .write(&json!({"count": [4], "time": "2025-01-01T00:15:00Z"}))
+        .await
+        .unwrap();
+    writer.flush().await.unwrap();
+
+    let class_dir = dir.path().join("iam/authentication");
+    let mut files = Vec::new();
+    parquet_files(&class_dir, &mut files).unwrap();
+    assert_eq!(files.len(), 1);
+
+    let info = stats::read(&class_dir, &files[0]).unwrap();
+    assert_eq!(info.rows, 4);
+    let at = |m| Utc.with_ymd_and_hms(2025, 1, 1, 0, m, 0).unwrap();
+    assert_eq!(
+        info.stats,
+        Some(stats::FileStats {
+            events: 4,
+            mismatched_events: 2,
+            min_time: Some(at(5).timestamp_millis()),
+            max_time: Some(at(20).timestamp_millis()),
+        })
+    );
+}
+
+#[tokio::test]
+async fn backend_survives_lagging_channel() {
+    use striem_common::{SysMessage, event::Event, metrics};
+    use tokio::sync::broadcast;
+
+    let schemas = tempfile::tempdir().unwrap();
+    let storage = tempfile::tempdir().unwrap();
+    std::fs::write(
+        schemas.path().join("file_activity.parquet"),
+        "message file_activity { optional INT32 class_uid (INTEGER(32, true)); }",
+    )
+    .unwrap();
+    let config = striem_config::StrIEMConfig::from_json(
+        &json!({
+            "storage": {
+                "schema": schemas.path(),
+                "path": storage.path(),
+            }
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let backend = ParquetBackend::new(&Arc::new(arc_swap::ArcSwap::from_pointee(config))).unwrap();
+
+    let (upstream_tx, upstream_rx) = broadcast::channel(2);
+    let (_internal_tx, internal_rx) = broadcast::channel(2);
+    let (sys_tx, sys_rx) = broadcast::channel(1);
+    let batch = || Arc::new(vec![Event::from(json!({"class_uid": 1001}))]);
+
+    // overflow the upstream channel before the backend reads from it
+    for _ in 0..5 {
+        upstream_tx.send(batch()).unwrap();
+    }
+    backend.run(upstream_rx, internal_rx, sys_rx).await;
+
+    let written = || {
+        metrics::value("striem_storage_events_total", &[("class", "file_activity")])
+            .unwrap_or_default()
+    };
+    let wait_for = |n: f64| async move {
+        tokio::time::timeout(std::time::Duration::from_secs(1), async {
+            while written() < n {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap()
+    };
+
+    // the two retained batches are written, and so is one sent afterwards
+    wait_for(2.0).await;
+    upstream_tx.send(batch()).unwrap();
+    wait_for(3.0).await;
+
+    assert_eq!(
+        metrics::value(
+            "striem_broadcast_lagged_total",
+            &[("subscriber", "storage_upstream")]
+        ),
+        Some(3.0)
+    );
+    sys_tx.send(SysMessage::Shutdown).unwrap();
+}
+
+#[tokio::test]
+async fn unclassified_events_kept_as_raw() {
+    use std::collections::HashMap;
+
+    use arrow::array::{Array, MapArray, StringArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use striem_common::{SysMessage, event::Event};
+    use tokio::sync::broadcast;
+
+    let schemas = tempfile::tempdir().unwrap();
+    let storage = tempfile::tempdir().unwrap();
+    std::fs::write(
+        schemas.path().join("file_activity.parquet"),
+        "message file_activity { optional INT32 class_uid (INTEGER(32, true)); }",
+    )
+    .unwrap();
+    let config = striem_config::StrIEMConfig::from_json(
+        &json!({
+            "storage": {
+                "schema": schemas.path(),
+                "path": storage.path(),
+                "keep_unclassified": true,
+            }
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let backend = ParquetBackend::new(&Arc::new(arc_swap::ArcSwap::from_pointee(config))).unwrap();
+
+    let (upstream_tx, upstream_rx) = broadcast::channel(4);
+    let (_internal_tx, internal_rx) = broadcast::channel(1);
+    let (sys_tx, sys_rx) = broadcast::channel(1);
+    backend.run(upstream_rx, internal_rx, sys_rx).await;
+
+    let metadata = HashMap::from([
+        ("source_id".to_string(), json!("syslog_1")),
+        ("source_type".to_string(), json!("syslog")),
+        ("port".to_string(), json!(514)),
+    ]);
+    upstream_tx
+        .send(Arc::new(vec![
+            // no class_uid at all
+            Event::from((json!({"message": "unparsed"}), metadata)),
+            // a class without a loaded schema
+            Event::from(json!({"class_uid": 3002, "user": "alice"})),
+            Event::from(json!({"class_uid": 1001})),
+        ]))
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    sys_tx.send(SysMessage::Shutdown).unwrap();
+    // writers finalize their files once their queues are dropped
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let mut files = Vec::new();
+    crate::util::parquet_files(&storage.path().join("other/raw"), &mut files).unwrap();
+    assert_eq!(files.len(), 1);
+    let batch = ParquetRecordBatchReaderBuilder::try_new(File::open(&files[0]).unwrap())
+        .unwrap()
+        .build()
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(batch.num_rows(), 2);
+
+    let column = |name| {
+        batch
+            .column_by_name(name)
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap()
+            .clone()
+    };
+    let raw = column("raw");
+    let first: serde_json::Value = serde_json::from_str(raw.value(0)).unwrap();
+    let second: serde_json::Value = serde_json::from_str(raw.value(1)).unwrap();
+    assert_eq!(first, json!({"message": "unparsed"}));
+    assert_eq!(second, json!({"class_uid": 3002, "user": "alice"}));
+    assert_eq!(column("source_id").value(0), "syslog_1");
+    assert_eq!(column("source_type").value(0), "syslog");
+    assert!(column("source_id").is_null(1));
+
+    let metadata = batch
+        .column_by_name("metadata")
+        .unwrap()
+        .as_any()
+        .downcast_ref::<MapArray>()
+        .unwrap();
+    assert_eq!(metadata.value_length(0), 3);
+    assert_eq!(metadata.value_length(1), 0);
+    assert!(batch.column_by_name("time").unwrap().null_count() == 0);
+}
+
+#[tokio::test]
+async fn backend_acknowledges_stored_batches() {
+    use crate::queue::{Batch, ClassQueue};
+    use striem_common::{
+        SysMessage,
+        event::{Ack, Event},
+    };
+    use striem_config::storage::{QueueConfig, QueuePolicy};
+    use tokio::sync::broadcast;
+
+    let schemas = tempfile::tempdir().unwrap();
+    let storage = tempfile::tempdir().unwrap();
+    std::fs::write(
+        schemas.path().join("file_activity.parquet"),
+        "message file_activity { optional INT32 class_uid (INTEGER(32, true)); }",
+    )
+    .unwrap();
+    let config = striem_config::StrIEMConfig::from_json(
+        &json!({
+            "storage": {
+                "schema": schemas.path(),
+                "path": storage.path(),
+            }
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let backend = ParquetBackend::new(&Arc::new(arc_swap::ArcSwap::from_pointee(config))).unwrap();
+    let (upstream_tx, upstream_rx) = broadcast::channel(1);
+    let (_internal_tx, internal_rx) = broadcast::channel(1);
+    let (sys_tx, sys_rx) = broadcast::channel(1);
+    backend.run(upstream_rx, internal_rx, sys_rx).await;
+
+    let acked = |data: Vec<serde_json::Value>| {
+        let (ack, stored) = Ack::new(data.len());
+        let events = data
+            .into_iter()
+            .map(|d| Event {
+                ack: Some(ack.clone()),
+                ..Event::from(d)
+            })
+            .collect::<Vec<_>>();
+        (Arc::new(events), stored)
+    };
+    let outcome = |stored| async move {
+        tokio::time::timeout(std::time::Duration::from_secs(1), stored)
+            .await
+            .unwrap()
+            .unwrap()
+    };
+
+    // written and unroutable events both complete the batch
+    let (events, stored) = acked(vec![json!({"class_uid": 1001}), json!({"message": "x"})]);
+    upstream_tx.send(events).unwrap();
+    assert!(outcome(stored).await);
+    sys_tx.send(SysMessage::Shutdown).unwrap();
+
+    // a batch dropped by a full queue fails
+    let queue = ClassQueue::spawn(
+        "ack_test".to_string(),
+        QueueConfig {
+            capacity: 1,
+            policy: QueuePolicy::Drop,
+            timeout_ms: 50,
+        },
+        |_| std::future::pending::<()>(),
+    )
+    .0;
+    let mut outcomes = Vec::new();
+    for _ in 0..3 {
+        let (events, stored) = acked(vec![json!({"class_uid": 1001})]);
+        queue
+            .send(Batch {
+                events,
+                indexes: vec![0],
+            })
+            .await;
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        outcomes.push(stored);
+    }
+    assert!(!outcome(outcomes.pop().unwrap()).await);
+}
+
+/// `(min, max)` of an Int64 `column` in each row group of a Parquet file
+fn row_group_spans(bytes: Vec<u8>, column: usize) -> Vec<(i64, i64)> {
+    use parquet::file::statistics::Statistics;
+    use std::io::Write;
+
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(&bytes).unwrap();
+    let reader = SerializedFileReader::new(file).unwrap();
+    reader
+        .metadata()
+        .row_groups()
+        .iter()
+        .map(|g| match g.column(column).statistics() {
+            Some(Statistics::Int64(s)) => (*s.min_opt().unwrap(), *s.max_opt().unwrap()),
+            other => panic!("unexpected statistics {:?}", other),
+        })
+        .collect()
+}
+
+#[test]
+fn sorted_rows_tighten_row_group_ranges() {
+    use arrow::array::{StringArray, TimestampMillisecondArray};
+    use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+    use parquet::file::properties::WriterProperties;
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new(
+            "time",
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            false,
+        ),
+        Field::new("uid", DataType::Utf8, false),
+    ]));
+    // arrival order scatters times across the whole range
+    let times = (0..1000i64).map(|i| (i * 7919) % 1000).collect::<Vec<_>>();
+    let batch = arrow::array::RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(TimestampMillisecondArray::from(times.clone())),
+            Arc::new(StringArray::from_iter_values(
+                times.iter().map(|t| format!("uid-{}", 999 - t)),
+            )),
+        ],
+    )
+    .unwrap();
+
+    let write = |batch: &arrow::array::RecordBatch| {
+        let props = WriterProperties::builder()
+            .set_max_row_group_size(100)
+            .build();
+        let mut out = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut out, schema.clone(), Some(props)).unwrap();
+        writer.write(batch).unwrap();
+        writer.close().unwrap();
+        out
+    };
+
+    let unsorted = row_group_spans(write(&batch), 0);
+    let sorted = writer::sort_batch(&batch, &["time".to_string(), "uid".to_string()]).unwrap();
+    let sorted = row_group_spans(write(&sorted), 0);
+    assert_eq!(unsorted.len(), 10);
+    assert_eq!(sorted.len(), 10);
+
+    let total = |spans: &[(i64, i64)]| spans.iter().map(|(min, max)| max - min).sum::<i64>();
+    assert!(total(&unsorted) > 9000);
+    assert_eq!(total(&sorted), 10 * 99);
+    // sorted row groups don't overlap, so a range query reads only its own
+    assert!(sorted.windows(2).all(|w| w[0].1 < w[1].0));
+
+    // missing columns are ignored rather than failing the file
+    let same = writer::sort_batch(&batch, &["metadata.uid".to_string()]).unwrap();
+    assert_eq!(same, batch);
+}
+
+#[tokio::test]
+async fn writer_sorts_and_writes_bloom_filters() {
+    use arrow::array::{Array, StringArray, TimestampMillisecondArray};
+    use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+
+    let dir = tempfile::tempdir().unwrap();
+    let schema = Arc::new(Schema::new(vec![
+        Field::new(
+            "time",
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            true,
+        ),
+        Field::new("id", DataType::Utf8, true),
+    ]));
+    let base = Arc::new(arc_swap::ArcSwap::from_pointee(dir.path().to_path_buf()));
+    let writer = Writer::new(base, "sorted".into(), schema)
+        .unwrap()
+        .with_sort_by(vec!["time".to_string()])
+        .with_bloom_filters(vec!["id".to_string()]);
+    writer.run().await.unwrap();
+
+    for chunk in [[30, 10], [20, 40]] {
+        let events = chunk
+            .iter()
+            .map(|t| json!({"time": t, "id": format!("e{}", t)}))
+            .collect::<Vec<_>>();
+        writer
+            .write_batch(&events.iter().collect::<Vec<_>>())
+            .await
+            .unwrap();
+    }
+    drop(writer);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut files = Vec::new();
+    util::parquet_files(&dir.path().join("sorted"), &mut files).unwrap();
+    assert_eq!(files.len(), 1);
+
+    let reader = SerializedFileReader::new(File::open(&files[0]).unwrap()).unwrap();
+    let id = &reader.metadata().row_group(0).columns()[1];
+    assert!(id.bloom_filter_offset().is_some());
+    let time = &reader.metadata().row_group(0).columns()[0];
+    assert!(time.bloom_filter_offset().is_none());
+
+    let batch = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(
+        File::open(&files[0]).unwrap(),
+    )
+    .unwrap()
+    .build()
+    .unwrap()
+    .next()
+    .unwrap()
+    .unwrap();
+    let times = batch
+        .column(0)
+        .as_any()
+        .downcast_ref::<TimestampMillisecondArray>()
+        .unwrap();
+    assert_eq!(times.values().to_vec(), vec![10, 20, 30, 40]);
+    let ids = batch
+        .column(1)
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .unwrap();
+    assert_eq!(ids.value(0), "e10");
+}
+
+#[tokio::test]
+async fn reader_replays_stored_events() {
+    use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+    use chrono::{TimeZone, Utc};
+
+    let dir = tempfile::tempdir().unwrap();
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("user", DataType::Utf8, true),
+        Field::new(
+            "time",
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            true,
+        ),
+    ]));
+    let base = Arc::new(arc_swap::ArcSwap::from_pointee(dir.path().to_path_buf()));
+    let writer = Writer::new(base, "iam/authentication".into(), schema).unwrap();
+    writer.flush().await.unwrap();
+    let events = [
+        json!({"user": "alice", "time": "2025-01-01T00:10:00Z"}),
+        json!({"user": "bob", "time": "2025-01-01T00:40:00Z"}),
+        json!({"user": "carol"}),
+    ];
+    writer
+        .write_batch(&events.iter().collect::<Vec<_>>())
+        .await
+        .unwrap();
+    writer.flush().await.unwrap();
+
+    let config = striem_config::StrIEMConfig::from_json(
+        &json!({"storage": {"schema": dir.path(), "path": dir.path()}}).to_string(),
+    )
+    .unwrap();
+    let storage = config.storage.unwrap();
+    let at = |h, m| Utc.with_ymd_and_hms(2025, 1, 1, h, m, 0).unwrap();
+
+    assert!(reader::class_dir(&storage, "no_such_class").is_err());
+    assert!(
+        reader::class_files(&storage, "file_activity", at(0, 0), at(1, 0))
+            .unwrap()
+            .is_empty()
+    );
+    let files = reader::class_files(&storage, "authentication", at(0, 0), at(0, 30)).unwrap();
+    assert_eq!(files.len(), 1);
+
+    // rows outside the range, or without a time, are skipped
+    let events = reader::read_events(&files[0], at(0, 0), at(0, 30)).unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].data["user"], "alice");
+    assert_eq!(events[0].data["time"], at(0, 10).timestamp_millis());
+    assert_eq!(events[0].time, Some(at(0, 10)));
+    assert_eq!(events[0].metadata["ocsf"], true);
+
+    // a projection reads only the named columns, plus the time
+    let rows = reader::read_rows(&files[0], Some(&["time"][..]), at(0, 0), at(1, 0)).unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[1].0, at(0, 40).timestamp_millis());
+    assert!(rows.iter().all(|(_, row)| !row.contains_key("user")));
+}
+
+#[test]
+fn finding_columns_survive_storage() {
+    let parquet_schema = SchemaDescriptor::new(
+        parse_message_type(
+            r#"message detection_finding {
+    optional INT32 class_uid (INTEGER(32, true));
+    optional group finding_info {
+        optional BYTE_ARRAY title (STRING);
+        optional group analytic {
+            optional BYTE_ARRAY uid (STRING);
+        }
+    }
+    }"#,
+        )
+        .unwrap()
+        .into(),
+    );
+    let schema = Arc::new(finding::with_finding_columns(
+        "detection_finding",
+        parquet_to_arrow_schema(&parquet_schema, None).unwrap(),
+    ));
+    let finding = json!({
+        "class_uid": 2004,
+        "finding_info": {
+            "title": "Suspicious PowerShell",
+            "src_url": "https://attack.mitre.org/techniques/T1059/001/",
+            "analytic": {
+                "uid": "8d1b3c2a-0000-4000-8000-000000000001",
+                "name": "Suspicious PowerShell",
+                "version": "2025-01-02",
+                "type": "Rule",
+                "type_id": 1
+            },
+            "attacks": [{
+                "tactic": {"uid": "TA0002", "name": "Execution"},
+                "technique": {"uid": "T1059"},
+                "sub_technique": {"uid": "T1059.001"}
+            }]
+        },
+        "unmapped": {"falsepositives": ["Administrative scripts"]}
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("finding.parquet");
+    let mut writer =
+        ArrowWriter::try_new(File::create(&path).unwrap(), schema.clone(), None).unwrap();
+    writer
+        .write(&convert_json(&finding, &schema).unwrap())
+        .unwrap();
+    writer.close().unwrap();
+
+    let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+    let rows = reader
+        .get_row_iter(None)
+        .unwrap()
+        .map(|r| r.unwrap().to_json_value())
+        .collect::<Vec<_>>();
+    assert_eq!(rows.len(), 1);
+    let info = &rows[0]["finding_info"];
+    assert_eq!(info["title"], json!("Suspicious PowerShell"));
+    assert_eq!(info["src_url"], finding["finding_info"]["src_url"]);
+    assert_eq!(info["analytic"], finding["finding_info"]["analytic"]);
+    assert_eq!(info["attacks"][0]["tactic"]["uid"], json!("TA0002"));
+    assert_eq!(
+        info["attacks"][0]["sub_technique"]["uid"],
+        json!("T1059.001")
+    );
+    assert_eq!(
+        rows[0]["unmapped"]["falsepositives"],
+        json!(["Administrative scripts"])
+    );
+
+    // a column the schema file has keeps its type
+    let parquet_schema = SchemaDescriptor::new(
+        parse_message_type(
+            "message detection_finding {\n optional BYTE_ARRAY unmapped (STRING);\n}",
+        )
+        .unwrap()
+        .into(),
+    );
+    let schema = finding::with_finding_columns(
+        "detection_finding",
+        parquet_to_arrow_schema(&parquet_schema, None).unwrap(),
+    );
+    assert_eq!(
+        schema.field_with_name("unmapped").unwrap().data_type(),
+        &arrow::datatypes::DataType::Utf8
+    );
+    assert!(schema.field_with_name("finding_info").is_ok());
+
+    // other classes are left alone
+    let schema = parquet_to_arrow_schema(&parquet_schema, None).unwrap();
+    assert_eq!(
+        finding::with_finding_columns("authentication", schema.clone()),
+        schema
+    );
+}
+
+#[tokio::test]
+async fn sampling_stores_a_stable_share() {
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use striem_common::{SysMessage, event::Event};
+    use tokio::sync::broadcast;
+
+    let uids = (0..400).map(|i| format!("event-{}", i)).collect::<Vec<_>>();
+    let kept = uids.iter().filter(|u| sampling::keep(u, 0.25)).count();
+    assert!((50..150).contains(&kept), "{}", kept);
+    // decided by the uid alone
+    assert_eq!(
+        uids.iter().filter(|u| sampling::keep(u, 0.25)).count(),
+        kept
+    );
+    assert!(uids.iter().all(|u| sampling::keep(u, 1.0)));
+    assert!(!uids.iter().any(|u| sampling::keep(u, 0.0)));
+
+    let schemas = tempfile::tempdir().unwrap();
+    let storage = tempfile::tempdir().unwrap();
+    for class in ["network_activity", "detection_finding"] {
+        std::fs::write(
+            schemas.path().join(format!("{}.parquet", class)),
+            format!(
+                "message {} {{ optional INT32 class_uid (INTEGER(32, true)); }}",
+                class
+            ),
+        )
+        .unwrap();
+    }
+    let config = striem_config::StrIEMConfig::from_json(
+        &json!({
+            "storage": {
+                "schema": schemas.path(),
+                "path": storage.path(),
+                "sampling": {"network": 0.25, "detection_finding": 0.0},
+            }
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let backend = ParquetBackend::new(&Arc::new(arc_swap::ArcSwap::from_pointee(config))).unwrap();
+
+    let (upstream_tx, upstream_rx) = broadcast::channel(1);
+    let (internal_tx, internal_rx) = broadcast::channel(1);
+    let (sys_tx, sys_rx) = broadcast::channel(1);
+    backend.run(upstream_rx, internal_rx, sys_rx).await;
+
+    let events = |class_uid| {
+        Arc::new(
+            uids.iter()
+                .map(|uid| Event::from(json!({"class_uid": class_uid, "metadata": {"uid": uid}})))
+                .collect::<Vec<_>>(),
+        )
+    };
+    upstream_tx.send(events(4001)).unwrap();
+    internal_tx.send(events(2004)).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    sys_tx.send(SysMessage::Shutdown).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let rows = |class: &str| {
+        let mut files = Vec::new();
+        crate::util::parquet_files(&storage.path().join(class), &mut files).unwrap();
+        files
+            .iter()
+            .flat_map(|f| {
+                ParquetRecordBatchReaderBuilder::try_new(File::open(f).unwrap())
+                    .unwrap()
+                    .build()
+                    .unwrap()
+            })
+            .map(|b| b.unwrap().num_rows())
+            .sum::<usize>()
+    };
+    assert_eq!(rows("network/network_activity"), kept);
+    // findings are never sampled
+    assert_eq!(rows("findings/detection_finding"), uids.len());
+}
+
+#[test]
+fn compaction_merges_settled_partitions() {
+    use arrow::{
+        array::{Int64Array, RecordBatch},
+        datatypes::{DataType, Field, Schema},
+    };
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    let day = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+    let at = |d, h, m| Utc.with_ymd_and_hms(2025, 1, d, h, m, 0).unwrap();
+    assert!(!compaction::settled(day, at(1, 23, 0), 3600));
+    assert!(!compaction::settled(day, at(2, 0, 30), 3600));
+    assert!(compaction::settled(day, at(2, 1, 0), 3600));
+    // never sooner than two rotations after midnight
+    assert!(!compaction::settled(day, at(2, 0, 5), 0));
+    assert_eq!(compaction::next_run(at(1, 1, 0), 150), at(1, 2, 30));
+    assert_eq!(compaction::next_run(at(1, 2, 30), 150), at(2, 2, 30));
+
+    let storage_dir = tempfile::tempdir().unwrap();
+    let storage: striem_config::storage::StorageConfig = serde_json::from_value(json!({
+        "schema": storage_dir.path(),
+        "path": storage_dir.path(),
+    }))
+    .unwrap();
+    let class_dir = storage_dir.path().join("network/network_activity");
+    let partition = class_dir.join("date=2025-01-01");
+    std::fs::create_dir_all(&partition).unwrap();
+
+    let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, true)]));
+    let mut newest = None;
+    for (i, minute) in [0u64, 5, 10].iter().enumerate() {
+        let secs = at(1, 0, 0).timestamp() as u64 + minute * 60;
+        let id = uuid::Uuid::new_v7(uuid::Timestamp::from_unix(uuid::NoContext, secs, 0));
+        let path = partition.join(format!("{}.parquet", id));
+        let mut writer =
+            ArrowWriter::try_new(File::create(&path).unwrap(), schema.clone(), None).unwrap();
+        let ids = Int64Array::from(vec![i as i64 * 10, i as i64 * 10 + 1]);
+        writer
+            .write(&RecordBatch::try_new(schema.clone(), vec![Arc::new(ids)]).unwrap())
+            .unwrap();
+        writer.close().unwrap();
+        newest = retention::file_time(&path);
+    }
+    manifest::rebuild(&class_dir).unwrap();
+
+    // an unsettled day is refused
+    let progress = compaction::Progress::default();
+    assert!(
+        compaction::compact(
+            &storage,
+            Some("network_activity"),
+            Some(day),
+            at(1, 12, 0),
+            &progress
+        )
+        .is_err()
+    );
+
+    let report = compaction::compact(&storage, None, None, at(3, 0, 0), &progress).unwrap();
+    assert_eq!(report.partitions, 1);
+    assert_eq!(report.files_merged, 3);
+    assert_eq!(report.files_written, 1);
+
+    let mut files = Vec::new();
+    crate::util::parquet_files(&class_dir, &mut files).unwrap();
+    assert_eq!(files.len(), 1);
+    // kept by retention as long as the newest file it replaces
+    assert_eq!(retention::file_time(&files[0]), newest);
+    let reader = SerializedFileReader::new(File::open(&files[0]).unwrap()).unwrap();
+    assert_eq!(reader.metadata().file_metadata().num_rows(), 6);
+    let entries = manifest::read(&class_dir).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].rows, 6);
+    assert!(!partition.join(compaction::JOURNAL_FILE).exists());
+
+    // a journal whose merged file never made it is undone, keeping the
+    // originals
+    let original = files[0].file_name().unwrap().to_string_lossy().to_string();
+    std::fs::write(
+        partition.join(compaction::JOURNAL_FILE),
+        json!({
+            "tmp": storage_dir.path().join(".tmp/missing"),
+            "file": "0190a000-0000-7000-8000-000000000000.parquet",
+            "rows": 6,
+            "replaces": [original],
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let report =
+        compaction::compact(&storage, None, None, at(3, 0, 0), &Default::default()).unwrap();
+    assert_eq!(report.files_written, 0);
+    assert!(files[0].exists());
+    assert!(!partition.join(compaction::JOURNAL_FILE).exists());
+
+    // a class that can't be compacted doesn't stop the others
+    let broken = storage_dir
+        .path()
+        .join("iam/authentication/date=2025-01-01");
+    std::fs::create_dir_all(&broken).unwrap();
+    std::fs::write(broken.join(compaction::JOURNAL_FILE), "not json").unwrap();
+    for minute in [15u64, 20] {
+        let secs = at(1, 0, 0).timestamp() as u64 + minute * 60;
+        let id = uuid::Uuid::new_v7(uuid::Timestamp::from_unix(uuid::NoContext, secs, 0));
+        let path = partition.join(format!("{}.parquet", id));
+        let mut writer =
+            ArrowWriter::try_new(File::create(&path).unwrap(), schema.clone(), None).unwrap();
+        writer
+            .write(
+                &RecordBatch::try_new(schema.clone(), vec![Arc::new(Int64Array::from(vec![1]))])
+                    .unwrap(),
+            )
+            .unwrap();
+        writer.close().unwrap();
+    }
+    let report =
+        compaction::compact(&storage, None, None, at(3, 0, 0), &Default::default()).unwrap();
+    assert_eq!(report.files_written, 1);
+    assert!(broken.join(compaction::JOURNAL_FILE).exists());
+}
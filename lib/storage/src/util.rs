@@ -1,31 +1,167 @@
 use anyhow::Result;
+use log::warn;
 use parquet::schema::{parser::parse_message_type, types::SchemaDescriptor};
-use std::{fs, path::PathBuf};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
-pub fn visit_dirs(path: &PathBuf) -> Result<Vec<(SchemaDescriptor, PathBuf)>> {
+use crate::{
+    ocsf,
+    schema::{SchemaError, SchemaReport},
+};
+
+/// Parquet message types in the files below `path`, or in the file at
+/// `path`, in path order, and the files among them that couldn't be read
+/// or parsed
+pub(crate) fn read_schemas(path: &Path) -> (Vec<(SchemaDescriptor, PathBuf)>, Vec<SchemaError>) {
+    let mut schemas = Vec::new();
+    let mut errors = Vec::new();
+    if !path.exists() {
+        errors.push(SchemaError::new(path, "does not exist"));
+    } else {
+        visit(path, &mut schemas, &mut errors);
+    }
+    (schemas, errors)
+}
+
+fn visit(
+    path: &Path,
+    schemas: &mut Vec<(SchemaDescriptor, PathBuf)>,
+    errors: &mut Vec<SchemaError>,
+) {
     if path.is_dir() {
-        let mut schemas = Vec::new();
-        for entry in fs::read_dir(path)? {
-            let entry = entry?;
-            let path = entry.path();
-            let entries = match path.is_dir() {
-                true => visit_dirs(&path)?,
-                false => {
-                    let file = std::fs::read_to_string(&path)?;
-                    vec![(
-                        SchemaDescriptor::new(parse_message_type(file.as_str())?.into()),
-                        path,
-                    )]
+        let entries = fs::read_dir(path).and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<std::io::Result<Vec<_>>>()
+        });
+        match entries {
+            Ok(mut entries) => {
+                entries.sort();
+                for entry in entries {
+                    visit(&entry, schemas, errors);
                 }
-            };
-            schemas.extend(entries);
+            }
+            Err(e) => errors.push(SchemaError::new(path, e)),
+        }
+        return;
+    }
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            errors.push(SchemaError::new(path, e));
+            return;
+        }
+    };
+    match parse_message_type(&contents) {
+        Ok(schema) => schemas.push((SchemaDescriptor::new(schema.into()), path.to_path_buf())),
+        Err(e) => {
+            let message = e.to_string();
+            errors.push(SchemaError {
+                path: path.to_path_buf(),
+                position: position(&contents, &message),
+                message,
+            })
+        }
+    }
+}
+
+/// Schemas of the OCSF classes below `path`. Fails with a [`SchemaReport`]
+/// listing every file that couldn't be read or parsed; files whose message
+/// isn't named after a known class are skipped with a warning.
+pub(crate) fn class_schemas(path: &Path) -> Result<Vec<(ocsf::Class, SchemaDescriptor, PathBuf)>> {
+    let (schemas, errors) = read_schemas(path);
+    if !errors.is_empty() {
+        return Err(SchemaReport {
+            dir: path.to_path_buf(),
+            errors,
+            unknown: Vec::new(),
+        }
+        .into());
+    }
+    Ok(schemas
+        .into_iter()
+        .filter_map(|(schema, file)| match schema.name().parse() {
+            Ok(class) => Some((class, schema, file)),
+            Err(e) => {
+                warn!("skipping schema {}: {}", file.display(), e);
+                None
+            }
+        })
+        .collect())
+}
+
+/// Tokens of a message type as Parquet's parser splits it, with their
+/// 1-based line and column
+fn tokens(contents: &str) -> Vec<(&str, (usize, usize))> {
+    let mut tokens = Vec::new();
+    for (n, line) in contents.lines().enumerate() {
+        let mut start = None;
+        for (i, c) in line.char_indices().chain([(line.len(), ' ')]) {
+            let delimiter = matches!(c, ';' | '{' | '}' | '(' | ')' | '=' | ',');
+            if c.is_whitespace() || delimiter {
+                if let Some(s) = start.take() {
+                    tokens.push((&line[s..i], (n + 1, s + 1)));
+                }
+                if delimiter {
+                    tokens.push((&line[i..i + 1], (n + 1, i + 1)));
+                }
+            } else if start.is_none() {
+                start = Some(i);
+            }
+        }
+    }
+    tokens
+}
+
+/// Where in `contents` the parser's error `message` is, when it can be
+/// told: Parquet's parser doesn't say, so this is an unbalanced brace, or
+/// else the one place a token quoted in the message, or a word of it that
+/// isn't lower case like the keywords, appears
+fn position(contents: &str, message: &str) -> Option<(usize, usize)> {
+    let tokens = tokens(contents);
+
+    let mut open = Vec::new();
+    for (token, position) in &tokens {
+        match *token {
+            "{" => open.push(*position),
+            "}" if open.pop().is_none() => return Some(*position),
+            _ => {}
+        }
+    }
+    if let Some(position) = open.pop() {
+        return Some(position);
+    }
+
+    message
+        .split('\'')
+        .skip(1)
+        .step_by(2)
+        .chain(
+            message
+                .split(|c: char| c.is_whitespace() || matches!(c, ',' | ':' | '\''))
+                .filter(|word| word.chars().any(|c| !c.is_ascii_lowercase())),
+        )
+        .filter(|candidate| !candidate.is_empty())
+        .find_map(|candidate| {
+            let mut found = tokens.iter().filter(|(token, _)| *token == candidate);
+            match (found.next(), found.next()) {
+                (Some((_, position)), None) => Some(*position),
+                _ => None,
+            }
+        })
+}
+
+/// Recursively collect Parquet files below `dir`
+pub fn parquet_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            parquet_files(&path, files)?;
+        } else if path.extension().is_some_and(|e| e == "parquet") {
+            files.push(path);
         }
-        Ok(schemas)
-    } else {
-        let file = std::fs::read_to_string(path)?;
-        Ok(vec![(
-            SchemaDescriptor::new(parse_message_type(file.as_str())?.into()),
-            path.to_path_buf(),
-        )])
     }
+    Ok(())
 }
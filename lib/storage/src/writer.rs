@@ -1,30 +1,64 @@
 //! Parquet file writer with time-based rotation.
 //!
 //! # Rotation Strategy
-//! Files are rotated every 5 minutes (configurable) to bound file sizes
-//! and enable incremental queries. Empty files are written to temp locations
-//! and only moved to final location if non-empty.
+//! Files are rotated every 5 minutes to bound file sizes and enable
+//! incremental queries, optionally at wall-clock multiples of the interval.
+//! A rotated file is finished after a delay within the jitter window that's
+//! fixed per class, so classes don't all write their files out at once.
+//! Empty files are written to temp locations and only moved to final
+//! location if non-empty.
+//!
+//! # Finalization
+//! Files are written in `.tmp` below the base path (or `storage.temp_dir`),
+//! then synced and renamed into place with their parent directory synced,
+//! so a crash never leaves a partial `.parquet` file. Across filesystems the
+//! file is copied under a name not ending in `.parquet`, which readers skip,
+//! and renamed once complete.
 //!
 //! # Concurrency
 //! Uses ArcSwap for lock-free rotation, allowing writes to continue
 //! while old file is being finalized and moved.
+//!
+//! # Sorting
+//! With sort columns set, batches are held in memory until rotation and
+//! written sorted, so each row group covers a narrow `time` range and
+//! DuckDB can skip most of them. Held rows are lost if the process dies
+//! before the file rotates.
 
 use anyhow::Result;
 use arc_swap::ArcSwap;
-use arrow::{array::RecordBatch, datatypes::SchemaRef};
+use arrow::{
+    array::{Array, ArrayRef, RecordBatch, StructArray},
+    compute::{SortColumn, concat_batches, lexsort_to_indices, take_record_batch},
+    datatypes::SchemaRef,
+};
+use chrono::{DateTime, Utc};
 use log::{debug, error, trace};
 use parquet::arrow::{AsyncArrowWriter, arrow_writer::ArrowWriterOptions};
 use parquet::{
     basic::Compression,
     file::{
         metadata::KeyValue,
-        properties::{WriterProperties, WriterVersion},
+        properties::{EnabledStatistics, WriterProperties, WriterVersion},
     },
+    schema::types::ColumnPath,
 };
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    time::Duration,
+};
+use striem_common::{
+    metrics,
+    prelude::{STORAGE_ROTATION_INTERVAL_SECS, STORAGE_TEMP_DIR},
+};
+use striem_config::storage::Partitioning;
 use tempfile::NamedTempFile;
 use tokio::{fs::File, sync::Mutex};
+
+use crate::{convert::class_name, manifest, remote::Remote, stats::FileStats};
+
 type WriterInstanceMutex = Mutex<Option<WriterImpl>>;
 type WriterInstance = Arc<ArcSwap<WriterInstanceMutex>>;
 
@@ -33,18 +67,252 @@ type WriterInstance = Arc<ArcSwap<WriterInstanceMutex>>;
 struct WriterImpl {
     tempfile: NamedTempFile,
     inner: AsyncArrowWriter<File>,
+    /// Earliest and latest event time written, for the manifest
+    time_range: Option<(i64, i64)>,
+    /// Events written, and those with a mistyped value stored as null
+    events: u64,
+    mismatched: u64,
+    /// Batches held for sorting until the file is finished
+    pending: Vec<RecordBatch>,
+}
+
+/// Layout of the rows within each Parquet file
+#[derive(Clone, Default)]
+struct FileOptions {
+    /// Columns to sort by, as dotted paths; empty writes in arrival order
+    sort_by: Vec<String>,
+    /// Columns to write bloom filters for, as dotted paths
+    bloom_filter_columns: Vec<String>,
+    /// Directory for files being written, instead of the default
+    temp_dir: Option<PathBuf>,
+}
+
+/// Where finished files are placed: `{base}/{subpath}/[partition/]`
+/// locally, or the same layout below the object store prefix.
+#[derive(Clone)]
+struct Destination {
+    base: Arc<ArcSwap<PathBuf>>,
+    subpath: PathBuf,
+    partitioning: Partitioning,
+    remote: Option<Arc<Remote>>,
+}
+
+impl Destination {
+    /// Directory for files being written: the configured one, else `.tmp`
+    /// below the base path, or the system's when uploading
+    fn temp_dir(&self, options: &FileOptions) -> Option<PathBuf> {
+        options.temp_dir.clone().or_else(|| {
+            self.remote
+                .is_none()
+                .then(|| self.base.load().join(STORAGE_TEMP_DIR))
+        })
+    }
+}
+
+/// Sync `dir` so renames and deletions in it survive a crash
+pub(crate) fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    std::fs::File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Move the finished file at `tmp` to `path`; see [`place_with`]
+pub(crate) fn place(tmp: &Path, path: &Path) -> std::io::Result<()> {
+    place_with(tmp, path, |from, to| std::fs::rename(from, to))
+}
+
+/// Sync `tmp` and move it to `path` with `rename`, then sync the directory.
+/// When `rename` fails because `tmp` is on another filesystem, it's copied
+/// beside `path` under a name readers skip, synced and renamed from there.
+pub(crate) fn place_with(
+    tmp: &Path,
+    path: &Path,
+    rename: impl Fn(&Path, &Path) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    std::fs::create_dir_all(dir)?;
+    std::fs::File::open(tmp)?.sync_all()?;
+
+    match rename(tmp, path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let staging = dir.join(format!(".{}.tmp", name));
+            let copied = std::fs::copy(tmp, &staging)
+                .and_then(|_| std::fs::File::open(&staging)?.sync_all())
+                .and_then(|_| std::fs::rename(&staging, path));
+            if let Err(e) = copied {
+                std::fs::remove_file(&staging).ok();
+                return Err(e);
+            }
+            std::fs::remove_file(tmp)?;
+        }
+        Err(e) => return Err(e),
+    }
+    sync_dir(dir)
 }
 
 /// Manages Parquet file lifecycle: creation, buffering, rotation, finalization.
 /// Uses temporary files to avoid partial writes in final location.
 #[derive(Clone)]
 pub struct Writer {
-    base: Arc<ArcSwap<PathBuf>>,
-    subpath: PathBuf,
+    dest: Destination,
     schema: SchemaRef,
     inner: WriterInstance,
+    options: FileOptions,
+    /// The last rotated file while it waits out the jitter delay
+    retiring: WriterInstance,
     // TODO: Make rotation interval configurable per-class for different retention needs
-    rotation_interval: tokio::time::Duration,
+    rotation_interval: Duration,
+    /// Rotate at wall-clock multiples of `rotation_interval`
+    aligned: bool,
+    /// Delay between rotating a file and finishing it
+    jitter: Duration,
+    strict: bool,
+}
+
+/// When to rotate after `after`: the next wall-clock multiple of
+/// `interval` when `aligned`, else `interval` later
+pub(crate) fn next_rotation(
+    after: DateTime<Utc>,
+    interval: Duration,
+    aligned: bool,
+) -> DateTime<Utc> {
+    let step = chrono::Duration::from_std(interval).unwrap_or(chrono::Duration::MAX);
+    if !aligned || interval.as_millis() == 0 {
+        return after + step;
+    }
+    let step_ms = interval.as_millis() as i64;
+    let next = (after.timestamp_millis().div_euclid(step_ms) + 1) * step_ms;
+    DateTime::from_timestamp_millis(next).unwrap_or(after + step)
+}
+
+/// Name of a file rotated at `rotated_at`, so it carries the rotation time
+/// however long the jitter delay held it back
+pub(crate) fn file_id(rotated_at: DateTime<Utc>) -> uuid::Uuid {
+    let (secs, nanos) = (
+        rotated_at.timestamp().max(0) as u64,
+        rotated_at.timestamp_subsec_nanos(),
+    );
+    uuid::Uuid::new_v7(uuid::Timestamp::from_unix(uuid::NoContext, secs, nanos))
+}
+
+/// Delay within `window` for the writer of `subpath`, the same on every
+/// rotation and spread across classes
+pub(crate) fn jitter(subpath: &Path, window: Duration) -> Duration {
+    let window = window.as_millis() as u64;
+    if window == 0 {
+        return Duration::ZERO;
+    }
+    let mut hasher = DefaultHasher::new();
+    subpath.hash(&mut hasher);
+    Duration::from_millis(hasher.finish() % window)
+}
+
+/// Options every Parquet file of `schema` is written with: StrIEM's
+/// properties, the class description and schema file as key-value metadata,
+/// and bloom filters for `bloom_filter_columns`
+pub(crate) fn arrow_writer_options(
+    schema: &SchemaRef,
+    bloom_filter_columns: &[String],
+) -> ArrowWriterOptions {
+    let mut metadata = vec![KeyValue {
+        key: "created_by".to_string(),
+        value: Some(format!(
+            "StrIEM version {} (build {})",
+            env!("CARGO_PKG_VERSION"),
+            env!("CARGO_GIT_SHA")
+        )),
+    }];
+
+    if let Some(desc) = schema.metadata.get("description") {
+        metadata.push(KeyValue {
+            key: "description".to_string(),
+            value: Some(desc.to_string()),
+        });
+    }
+    if let Some(file) = schema.metadata.get("schema_file") {
+        metadata.push(KeyValue {
+            key: "schema_file".to_string(),
+            value: Some(file.to_string()),
+        });
+    }
+
+    let props = bloom_filter_columns.iter().fold(
+        WriterProperties::builder()
+            .set_writer_version(WriterVersion::PARQUET_2_0)
+            .set_compression(Compression::SNAPPY)
+            .set_statistics_enabled(EnabledStatistics::Page)
+            .set_key_value_metadata(Some(metadata)),
+        |props, column| {
+            let path = ColumnPath::new(column.split('.').map(str::to_string).collect());
+            props.set_column_bloom_filter_enabled(path, true)
+        },
+    );
+    let props = props.build();
+
+    ArrowWriterOptions::default()
+        .with_properties(props)
+        .with_skip_arrow_metadata(true)
+        .with_schema_root(
+            schema
+                .metadata
+                .get("description")
+                .cloned()
+                .unwrap_or_else(|| "arrow_schema".into()),
+        )
+}
+
+/// Hive-style partition directory for a file rotated at `time`
+pub(crate) fn partition_dir(partitioning: Partitioning, time: DateTime<Utc>) -> PathBuf {
+    match partitioning {
+        Partitioning::None => PathBuf::new(),
+        Partitioning::Daily => PathBuf::from(format!("date={}", time.format("%Y-%m-%d"))),
+        Partitioning::Hourly => PathBuf::from(format!("date={}", time.format("%Y-%m-%d")))
+            .join(format!("hour={}", time.format("%H"))),
+    }
+}
+
+/// Column of `batch` at the dotted `path`, descending into structs
+fn column_at(batch: &RecordBatch, path: &str) -> Option<ArrayRef> {
+    let mut parts = path.split('.');
+    let mut column = batch.column_by_name(parts.next()?)?.clone();
+    for part in parts {
+        column = column
+            .as_any()
+            .downcast_ref::<StructArray>()?
+            .column_by_name(part)?
+            .clone();
+    }
+    Some(column)
+}
+
+/// `batch` sorted ascending by the `sort_by` columns, nulls last
+pub(crate) fn sort_batch(batch: &RecordBatch, sort_by: &[String]) -> Result<RecordBatch> {
+    let columns = sort_by
+        .iter()
+        .filter_map(|path| {
+            let column = column_at(batch, path);
+            if column.is_none() {
+                debug!("sort column {} not found, ignoring it", path);
+            }
+            column
+        })
+        .map(|values| SortColumn {
+            values,
+            options: Some(arrow::compute::SortOptions {
+                descending: false,
+                nulls_first: false,
+            }),
+        })
+        .collect::<Vec<_>>();
+    if columns.is_empty() {
+        return Ok(batch.clone());
+    }
+    let indices = lexsort_to_indices(&columns, None)?;
+    Ok(take_record_batch(batch, &indices)?)
 }
 
 impl Writer {
@@ -58,35 +326,109 @@ impl Writer {
     pub fn new(base: Arc<ArcSwap<PathBuf>>, subpath: PathBuf, schema: SchemaRef) -> Result<Self> {
         let writer = Arc::new(ArcSwap::from_pointee(Mutex::new(None)));
         Ok(Self {
-            base,
-            subpath,
+            dest: Destination {
+                base,
+                subpath,
+                partitioning: Partitioning::None,
+                remote: None,
+            },
             schema: schema.clone(),
             inner: writer.clone(),
-            rotation_interval: tokio::time::Duration::from_secs(300),
+            options: FileOptions::default(),
+            retiring: Arc::new(ArcSwap::from_pointee(Mutex::new(None))),
+            rotation_interval: Duration::from_secs(STORAGE_ROTATION_INTERVAL_SECS),
+            aligned: false,
+            jitter: Duration::ZERO,
+            strict: false,
         })
     }
 
+    /// Place finished files in date (and hour) subdirectories.
+    pub fn with_partitioning(mut self, partitioning: Partitioning) -> Self {
+        self.dest.partitioning = partitioning;
+        self
+    }
+
+    /// Rotate at wall-clock multiples of the rotation interval.
+    pub fn with_aligned_rotation(mut self, aligned: bool) -> Self {
+        self.aligned = aligned;
+        self
+    }
+
+    /// Finish rotated files after this writer's delay within `window`,
+    /// which is capped at the rotation interval.
+    pub fn with_rotation_jitter(mut self, window: Duration) -> Self {
+        self.jitter = jitter(&self.dest.subpath, window.min(self.rotation_interval));
+        self
+    }
+
+    /// Reject events with mistyped values rather than storing nulls.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sort each file by `columns` (dotted paths, e.g. `metadata.uid`)
+    /// before writing it. Columns missing from the schema are ignored.
+    pub fn with_sort_by(mut self, columns: Vec<String>) -> Self {
+        self.options.sort_by = columns;
+        self
+    }
+
+    /// Write bloom filters for `columns` (dotted paths).
+    pub fn with_bloom_filters(mut self, columns: Vec<String>) -> Self {
+        self.options.bloom_filter_columns = columns;
+        self
+    }
+
+    /// Write files in `dir` until they rotate, rather than below the base
+    /// path.
+    pub fn with_temp_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.options.temp_dir = dir;
+        self
+    }
+
+    /// Base path finished files are placed under.
+    pub(crate) fn base(&self) -> Arc<ArcSwap<PathBuf>> {
+        self.dest.base.clone()
+    }
+
+    /// Upload finished files to object storage instead of the local path.
+    pub(crate) fn with_remote(mut self, remote: Arc<Remote>) -> Self {
+        self.dest.remote = Some(remote);
+        self
+    }
+
     /// Spawn background rotation task.
     ///
     /// # Rotation Timing
     /// Fixed 5-minute interval provides predictable file sizes and query patterns.
     /// High-volume classes may produce 100MB+ files; low-volume classes stay small.
+    /// The next rotation is scheduled from the last one rather than from when
+    /// its file was finished, so the jitter delay doesn't accumulate.
     pub async fn run(&self) -> Result<()> {
         tokio::spawn({
             let cloned = self.clone();
             async move {
-                if let Ok(writer) = Self::create_writer(&cloned.schema) {
+                let temp_dir = cloned.dest.temp_dir(&cloned.options);
+                if let Ok(writer) =
+                    Self::create_writer(&cloned.schema, &cloned.options, temp_dir.as_deref())
+                {
                     cloned.inner.store(Arc::new(writer));
                 } else {
                     error!("Failed to create initial Parquet writer");
                     return;
                 }
 
+                let mut target = Utc::now();
                 loop {
-                    tokio::time::sleep(cloned.rotation_interval).await;
-                    Self::rotate(&cloned.base, &cloned.subpath, &cloned.schema, &cloned.inner)
-                        .await
-                        .ok();
+                    target = next_rotation(target, cloned.rotation_interval, cloned.aligned);
+                    let now = Utc::now();
+                    if target <= now {
+                        target = next_rotation(now, cloned.rotation_interval, cloned.aligned);
+                    }
+                    tokio::time::sleep((target - now).to_std().unwrap_or_default()).await;
+                    cloned.rotate(target, cloned.jitter).await.ok();
                 }
             }
         });
@@ -99,9 +441,20 @@ impl Writer {
     /// Using temporary files prevents corrupt/partial files in storage directory
     /// if process crashes mid-write. Only non-empty, finalized files appear.
     ///
-    /// Trade-off: Extra disk I/O for atomic move, but negligible for 5min files.
-    fn create_writer(schema: &SchemaRef) -> Result<WriterInstanceMutex> {
-        let tempfile = NamedTempFile::new()?;
+    /// With `temp_dir` on the destination's filesystem, the finished file is
+    /// renamed into place rather than copied.
+    fn create_writer(
+        schema: &SchemaRef,
+        options: &FileOptions,
+        temp_dir: Option<&Path>,
+    ) -> Result<WriterInstanceMutex> {
+        let tempfile = match temp_dir {
+            Some(dir) => {
+                std::fs::create_dir_all(dir)?;
+                NamedTempFile::new_in(dir)?
+            }
+            None => NamedTempFile::new()?,
+        };
         trace!(
             "{} created temporary file: {}",
             schema
@@ -111,44 +464,7 @@ impl Writer {
             tempfile.path().display()
         );
 
-        let mut metadata = vec![KeyValue {
-            key: "created_by".to_string(),
-            value: Some(format!(
-                "StrIEM version {} (build {})",
-                env!("CARGO_PKG_VERSION"),
-                env!("CARGO_GIT_SHA")
-            )),
-        }];
-
-        if let Some(desc) = schema.metadata.get("description") {
-            metadata.push(KeyValue {
-                key: "description".to_string(),
-                value: Some(desc.to_string()),
-            });
-        }
-        if let Some(file) = schema.metadata.get("schema_file") {
-            metadata.push(KeyValue {
-                key: "schema_file".to_string(),
-                value: Some(file.to_string()),
-            });
-        }
-
-        let props = WriterProperties::builder()
-            .set_writer_version(WriterVersion::PARQUET_2_0)
-            .set_compression(Compression::SNAPPY)
-            .set_key_value_metadata(Some(metadata))
-            .build();
-
-        let options = ArrowWriterOptions::default()
-            .with_properties(props)
-            .with_skip_arrow_metadata(true)
-            .with_schema_root(
-                schema
-                    .metadata
-                    .get("description")
-                    .cloned()
-                    .unwrap_or_else(|| "arrow_schema".into()),
-            );
+        let options = arrow_writer_options(schema, &options.bloom_filter_columns);
 
         let writer = AsyncArrowWriter::try_new_with_options(
             File::from_std(tempfile.reopen()?),
@@ -159,6 +475,10 @@ impl Writer {
         Ok(Mutex::new(Some(WriterImpl {
             tempfile,
             inner: writer,
+            time_range: None,
+            events: 0,
+            mismatched: 0,
+            pending: Vec::new(),
         })))
     }
 
@@ -171,33 +491,71 @@ impl Writer {
     /// # File Naming
     /// UUIDv7 provides time-ordered, collision-free names. Sorts chronologically
     /// in filesystem listings and DuckDB queries (`ORDER BY filename`).
-    async fn rotate(
-        base: &Arc<ArcSwap<PathBuf>>,
-        path: &PathBuf,
-        schema: &SchemaRef,
-        inner: &WriterInstance,
-    ) -> Result<()> {
-        let new_writer = Self::create_writer(schema)?;
-        let old = inner.swap(Arc::new(new_writer));
-        let dir = base.load().join(path);
-        Self::finish(&old, schema, dir).await
+    ///
+    /// # Jitter
+    /// The old file is finished `delay` after the swap. Until then it's kept
+    /// in `retiring`, so dropping the writer still finishes it.
+    async fn rotate(&self, rotated_at: DateTime<Utc>, delay: Duration) -> Result<()> {
+        let temp_dir = self.dest.temp_dir(&self.options);
+        let new_writer = Self::create_writer(&self.schema, &self.options, temp_dir.as_deref())?;
+        let old = self.inner.swap(Arc::new(new_writer));
+        if !delay.is_zero() {
+            self.retiring.store(old.clone());
+            tokio::time::sleep(delay).await;
+        }
+
+        let started = std::time::Instant::now();
+        let result = Self::finish(&old, &self.schema, &self.dest, &self.options, rotated_at).await;
+        metrics::observe(
+            "striem_storage_rotation_duration_seconds",
+            &[("class", class_name(&self.schema))],
+            started.elapsed().as_secs_f64(),
+        );
+        result
     }
 
-    /// Finalize old writer: flush, close, and move temp file if non-empty.
+    /// Finalize old writer: write any rows held for sorting and its write
+    /// statistics (see [`crate::stats`]), flush, close, and move temp file
+    /// if non-empty (see [`place`]), then record it in the class manifest.
+    ///
+    /// With partitioning, the file lands in the partition of the rotation
+    /// time. A file that fails to upload is left in place as a temp file
+    /// rather than discarded.
     async fn finish(
         guard: &Arc<WriterInstanceMutex>,
         schema: &SchemaRef,
-        dir: PathBuf,
+        dest: &Destination,
+        options: &FileOptions,
+        rotated_at: DateTime<Utc>,
     ) -> Result<()> {
         let old = guard.lock().await.take();
         if let Some(mut meta) = old {
+            if !meta.pending.is_empty() {
+                let batch = concat_batches(schema, &meta.pending)?;
+                meta.pending.clear();
+                meta.inner
+                    .write(&sort_batch(&batch, &options.sort_by)?)
+                    .await?;
+            }
+            let stats = FileStats {
+                events: meta.events,
+                mismatched_events: meta.mismatched,
+                min_time: meta.time_range.map(|r| r.0),
+                max_time: meta.time_range.map(|r| r.1),
+            };
+            for kv in stats.key_values() {
+                meta.inner.append_key_value_metadata(kv);
+            }
             meta.inner.finish().await?;
             if !meta.inner.flushed_row_groups().is_empty()
                 && meta.inner.flushed_row_groups()[0].num_rows() != 0
             {
-                let path = dir
-                    .join(format!("{}", uuid::Uuid::now_v7()))
+                let relative = dest
+                    .subpath
+                    .join(partition_dir(dest.partitioning, rotated_at))
+                    .join(format!("{}", file_id(rotated_at)))
                     .with_extension("parquet");
+                let path = dest.base.load().join(&relative);
                 trace!(
                     "{} wrote new file: {}",
                     schema
@@ -207,18 +565,81 @@ impl Writer {
                     path.as_os_str().display()
                 );
                 let (_, tmppath) = meta.tempfile.keep()?;
+                let bytes = tokio::fs::metadata(&tmppath).await?.len();
+                let rows = meta
+                    .inner
+                    .flushed_row_groups()
+                    .iter()
+                    .map(|g| g.num_rows().max(0) as u64)
+                    .sum();
 
-                tokio::fs::create_dir_all(&dir).await?;
-                tokio::fs::copy(tmppath.clone(), path).await?;
-                tokio::fs::remove_file(tmppath).await?;
+                if let Some(remote) = &dest.remote {
+                    remote.upload(&tmppath, &relative).await.inspect_err(|e| {
+                        error!(
+                            "failed to upload {}, kept at {}: {}",
+                            relative.display(),
+                            tmppath.display(),
+                            e
+                        )
+                    })?;
+                    tokio::fs::remove_file(tmppath).await?;
+                } else {
+                    let (from, to) = (tmppath.clone(), path.clone());
+                    tokio::task::spawn_blocking(move || place(&from, &to))
+                        .await?
+                        .inspect_err(|e| {
+                            error!(
+                                "failed to move {} into place, kept at {}: {}",
+                                path.display(),
+                                tmppath.display(),
+                                e
+                            )
+                        })?;
+
+                    let dir = dest.base.load().join(&dest.subpath);
+                    let entry = manifest::Entry {
+                        file: path.strip_prefix(&dir)?.to_path_buf(),
+                        min_time: meta.time_range.map(|r| r.0),
+                        max_time: meta.time_range.map(|r| r.1),
+                        rows,
+                        bytes,
+                    };
+                    // the file is in place either way; a missing entry only
+                    // means readers include it for every range
+                    tokio::task::spawn_blocking(move || manifest::append(&dir, &entry))
+                        .await?
+                        .inspect_err(|e| error!("failed to update manifest: {}", e))
+                        .ok();
+                }
+
+                let labels = [("class", class_name(schema))];
+                metrics::increment("striem_storage_files_total", &labels, 1);
+                metrics::increment("striem_storage_bytes_total", &labels, bytes);
             }
         }
 
         Ok(())
     }
 
+    /// Finish the current file now rather than at the next rotation, e.g.
+    /// at the end of a one-off job.
+    pub async fn flush(&self) -> Result<()> {
+        self.rotate(Utc::now(), Duration::ZERO).await
+    }
+
+    /// Finish the current file, and one still retiring, without starting a
+    /// new one; for shutdown, once nothing is written anymore.
+    pub async fn close(&self) -> Result<()> {
+        let now = Utc::now();
+        let retiring = self.retiring.load_full();
+        let retired = Self::finish(&retiring, &self.schema, &self.dest, &self.options, now).await;
+        let current = self.inner.load_full();
+        Self::finish(&current, &self.schema, &self.dest, &self.options, now).await?;
+        retired
+    }
+
     pub async fn write(&self, event: &serde_json::Value) -> Result<()> {
-        let record_batch = crate::convert_json(event, &self.schema)?;
+        let converted = crate::convert::convert_event(event, &self.schema)?;
         trace!(
             "{} writing event",
             self.schema
@@ -226,20 +647,63 @@ impl Writer {
                 .get("description")
                 .unwrap_or(&"unknown".into())
         );
-        self.write_recordbatch(&record_batch).await
+        self.append(&converted.batch, converted.mismatched as u64)
+            .await
+    }
+
+    /// Convert and write a batch of events as a single RecordBatch,
+    /// returning the index and reason of every event left out.
+    ///
+    /// Events that can't be converted are skipped (see
+    /// [`crate::convert_events`]).
+    pub async fn write_batch(&self, events: &[&serde_json::Value]) -> Result<Vec<(usize, String)>> {
+        let crate::Converted {
+            batch: record_batch,
+            rejected,
+            mismatched,
+        } = crate::convert_events(events, &self.schema, self.strict)?;
+        if record_batch.num_rows() == 0 {
+            return Ok(rejected);
+        }
+        trace!(
+            "{} writing {} events",
+            self.schema
+                .metadata
+                .get("description")
+                .unwrap_or(&"unknown".into()),
+            record_batch.num_rows()
+        );
+        self.append(&record_batch, mismatched as u64).await?;
+        Ok(rejected)
     }
 
     pub async fn write_recordbatch(&self, batch: &RecordBatch) -> Result<()> {
+        self.append(batch, 0).await
+    }
+
+    /// Write `batch` to the current file, `mismatched` of whose events had a
+    /// mistyped value stored as null
+    async fn append(&self, batch: &RecordBatch, mismatched: u64) -> Result<()> {
         loop {
             // if we get None back, it's a race with rotate & we should try again
             // TODO: timeout
             let guard = self.inner.load();
             let mut writer = guard.lock().await;
             if let Some(meta) = writer.as_mut() {
-                meta.inner.write(batch).await?;
+                if self.options.sort_by.is_empty() {
+                    meta.inner.write(batch).await?;
+                } else {
+                    meta.pending.push(batch.clone());
+                }
+                meta.time_range =
+                    manifest::merge_range(meta.time_range, manifest::time_range(batch));
+                meta.events += batch.num_rows() as u64;
+                meta.mismatched += mismatched;
                 break;
             } else {
                 debug!("Writer is being rotated, retrying...");
+                drop(writer);
+                tokio::task::yield_now().await;
             }
         }
         Ok(())
@@ -249,11 +713,19 @@ impl Writer {
 impl Drop for Writer {
     fn drop(&mut self) {
         let guard = self.inner.load();
+        let retiring = self.retiring.load_full();
         let schema = self.schema.clone();
-        let dir = self.base.load().join(&self.subpath);
+        let dest = self.dest.clone();
+        let options = self.options.clone();
 
         tokio::spawn(async move {
-            Self::finish(&guard, &schema, dir).await.ok();
+            let now = Utc::now();
+            Self::finish(&retiring, &schema, &dest, &options, now)
+                .await
+                .ok();
+            Self::finish(&guard, &schema, &dest, &options, now)
+                .await
+                .ok();
         });
     }
 }
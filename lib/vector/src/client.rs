@@ -1,17 +1,64 @@
+//! Vector gRPC client forwarding events downstream.
+//!
+//! Batches are pushed in order. When a push fails because the downstream
+//! Vector is unreachable, the batch is kept and the client reconnects with
+//! exponential backoff, buffering batches that arrive meanwhile (up to
+//! [`VECTOR_CLIENT_BUFFER_BATCHES`], dropping the oldest beyond that), then
+//! resends them before carrying on. Batches Vector rejects outright are
+//! dropped rather than retried. Events the output's [`Filter`] doesn't
+//! forward are left out before batches are queued.
+//!
+//! [`Client::supervise`] keeps a client running for good: it connects with
+//! the same backoff and, should the client fail, starts over on a new
+//! connection and subscription. Batches sent in between aren't forwarded.
+//!
+//! With a [`Compression`] (`output.vector.compression`), pushed batches are
+//! compressed and compressed responses accepted; the downstream Vector has
+//! to accept the encoding. Batches are sent uncompressed by default.
+//!
+//! On shutdown the client takes the batches already sent on its channel and
+//! makes one attempt to push everything it holds before stopping; a
+//! downstream that is unreachable by then isn't waited for.
+
 use crate::{
     event::{EventWrapper, event_wrapper::Event as VectorEvent},
+    filter::{Filter, SharedFilter},
     vector::{self, vector_client::VectorClient},
 };
 use anyhow::Result;
-use log::info;
-use std::sync::Arc;
-use striem_common::{SysMessage, event::Event};
-use tokio::sync::broadcast;
+use log::{info, warn};
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use striem_common::{SysMessage, event::Event, metrics, prelude::*};
+use striem_config::Compression;
+use tokio::sync::broadcast::{self, error::RecvError};
 
 pub struct Client {
+    addr: String,
     client: VectorClient<tonic::transport::channel::Channel>,
     rx: broadcast::Receiver<Arc<Vec<Event>>>,
     sys: broadcast::Receiver<SysMessage>,
+    filter: SharedFilter,
+    compression: Compression,
+    /// Batches waiting to be pushed, oldest first
+    pending: VecDeque<Arc<Vec<Event>>>,
+    capacity: usize,
+    pub(crate) reconnect_base: Duration,
+    pub(crate) reconnect_max: Duration,
+}
+
+/// Whether a failed push should be retried on a new connection
+fn retryable(status: &tonic::Status) -> bool {
+    matches!(
+        status.code(),
+        tonic::Code::Unavailable
+            | tonic::Code::Unknown
+            | tonic::Code::Cancelled
+            | tonic::Code::DeadlineExceeded
+    )
 }
 
 impl Client {
@@ -20,44 +67,348 @@ impl Client {
         rx: broadcast::Receiver<Arc<Vec<Event>>>,
         sys: broadcast::Receiver<SysMessage>,
     ) -> Result<Self> {
+        let client = Self::connect(addr, Compression::None).await?;
+        Ok(Self {
+            addr: addr.to_string(),
+            client,
+            rx,
+            sys,
+            filter: Filter::shared(None),
+            compression: Compression::None,
+            pending: VecDeque::new(),
+            capacity: VECTOR_CLIENT_BUFFER_BATCHES,
+            reconnect_base: Duration::from_millis(VECTOR_CLIENT_RECONNECT_BASE_MS),
+            reconnect_max: Duration::from_secs(VECTOR_CLIENT_RECONNECT_MAX_SECS),
+        })
+    }
+
+    /// Forward only the events `filter` keeps
+    pub fn with_filter(mut self, filter: SharedFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Compress pushed batches with `compression`, and accept responses
+    /// compressed with it
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self.client = Self::compressed(self.client, compression);
+        self
+    }
+
+    /// Forward events sent on `events` to `addr` until shutdown or the
+    /// channel closes, connecting with exponential backoff and starting a
+    /// new client whenever one fails. The backoff resets once a client has
+    /// run for [`VECTOR_CLIENT_RECONNECT_MAX_SECS`].
+    pub async fn supervise(
+        addr: &str,
+        events: &broadcast::Sender<Arc<Vec<Event>>>,
+        filter: SharedFilter,
+        compression: Compression,
+        sys: broadcast::Receiver<SysMessage>,
+    ) {
+        Self::supervise_with(
+            addr,
+            events,
+            filter,
+            compression,
+            sys,
+            Duration::from_millis(VECTOR_CLIENT_RECONNECT_BASE_MS),
+            Duration::from_secs(VECTOR_CLIENT_RECONNECT_MAX_SECS),
+        )
+        .await
+    }
+
+    pub(crate) async fn supervise_with(
+        addr: &str,
+        events: &broadcast::Sender<Arc<Vec<Event>>>,
+        filter: SharedFilter,
+        compression: Compression,
+        mut sys: broadcast::Receiver<SysMessage>,
+        base: Duration,
+        max: Duration,
+    ) {
+        let mut delay = base;
+        loop {
+            let started = Instant::now();
+            match Self::new(addr, events.subscribe(), sys.resubscribe()).await {
+                // a shutdown sent while connecting went to `sys` only
+                Ok(_) if Self::shutting_down(&mut sys) => return,
+                Ok(client) => {
+                    info!("connected to downstream Vector at {}", addr);
+                    let mut client = client
+                        .with_filter(filter.clone())
+                        .with_compression(compression);
+                    client.reconnect_base = base;
+                    client.reconnect_max = max;
+                    match client.run().await {
+                        Ok(()) => return,
+                        Err(e) => warn!("Vector client for {} failed, restarting: {}", addr, e),
+                    }
+                }
+                Err(e) => warn!("Failed to connect to Vector at {}: {}", addr, e),
+            }
+            if started.elapsed() >= max {
+                delay = base;
+            }
+
+            let sleep = tokio::time::sleep(delay);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    msg = sys.recv() => {
+                        if Self::stopped(msg) {
+                            return;
+                        }
+                    }
+                }
+            }
+            delay = (delay * 2).min(max);
+        }
+    }
+
+    /// Whether a shutdown is waiting on `sys`
+    fn shutting_down(sys: &mut broadcast::Receiver<SysMessage>) -> bool {
+        loop {
+            match sys.try_recv() {
+                Ok(SysMessage::Shutdown) | Err(broadcast::error::TryRecvError::Closed) => {
+                    return true;
+                }
+                Ok(_) | Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                Err(broadcast::error::TryRecvError::Empty) => return false,
+            }
+        }
+    }
+
+    async fn connect(
+        addr: &str,
+        compression: Compression,
+    ) -> Result<VectorClient<tonic::transport::channel::Channel>> {
         let uri = tonic::transport::Uri::try_from(addr)?;
-        let client = VectorClient::connect(uri).await?;
-        Ok(Self { client, rx, sys })
+        Ok(Self::compressed(
+            VectorClient::connect(uri).await?,
+            compression,
+        ))
+    }
+
+    /// `client` sending and accepting `compression`
+    fn compressed(
+        client: VectorClient<tonic::transport::channel::Channel>,
+        compression: Compression,
+    ) -> VectorClient<tonic::transport::channel::Channel> {
+        match crate::encoding(compression) {
+            Some(encoding) => client.send_compressed(encoding).accept_compressed(encoding),
+            None => client,
+        }
+    }
+
+    /// Queue a batch for sending, dropping the oldest if the buffer is full
+    fn enqueue(&mut self, events: Arc<Vec<Event>>) {
+        if self.pending.len() >= self.capacity.max(1)
+            && let Some(dropped) = self.pending.pop_front()
+        {
+            warn!(
+                "Vector client buffer full, dropping {} events",
+                dropped.len()
+            );
+            metrics::increment("striem_vector_client_dropped_batches_total", &[], 1);
+        }
+        self.pending.push_back(events);
+    }
+
+    /// Receive from the event channel. Returns false once it closes.
+    fn received(&mut self, result: Result<Arc<Vec<Event>>, RecvError>) -> bool {
+        match result {
+            Ok(events) => {
+                if let Some(events) = self.filter.load().apply(events) {
+                    self.enqueue(events);
+                }
+            }
+            Err(RecvError::Lagged(n)) => {
+                metrics::increment(
+                    "striem_broadcast_lagged_total",
+                    &[("subscriber", "vector_client")],
+                    n,
+                );
+                warn!("Vector client lagged, {} batches not forwarded", n);
+            }
+            Err(RecvError::Closed) => {
+                info!("Vector client channel closed");
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Whether a system message ends the client
+    fn stopped(msg: Result<SysMessage, RecvError>) -> bool {
+        match msg {
+            Ok(SysMessage::Shutdown) => {
+                info!("Vector client received shutdown signal");
+                true
+            }
+            Err(_) => {
+                info!("Shutdown channel closed, exiting Vector client...");
+                true
+            }
+            Ok(_) => false,
+        }
+    }
+
+    async fn push(&mut self, events: &[Event]) -> Result<(), tonic::Status> {
+        let events: Vec<EventWrapper> = events
+            .iter()
+            .map(|e| EventWrapper {
+                event: Some(VectorEvent::Log(e.into())),
+            })
+            .collect();
+        let request = tonic::Request::new(vector::PushEventsRequest { events });
+        self.client.push_events(request).await.map(|_| ())
+    }
+
+    /// Reconnect to the stored address with exponential backoff, buffering
+    /// events that arrive meanwhile. Returns false if the client should stop.
+    async fn reconnect(&mut self) -> bool {
+        let mut delay = self.reconnect_base;
+        loop {
+            let sleep = tokio::time::sleep(delay);
+            tokio::pin!(sleep);
+            loop {
+                tokio::select! {
+                    _ = &mut sleep => break,
+                    result = self.rx.recv() => {
+                        if !self.received(result) {
+                            return false;
+                        }
+                    },
+                    msg = self.sys.recv() => {
+                        if Self::stopped(msg) {
+                            return false;
+                        }
+                    }
+                }
+            }
+
+            match Self::connect(&self.addr, self.compression).await {
+                Ok(client) => {
+                    info!("reconnected to downstream Vector at {}", self.addr);
+                    metrics::increment("striem_vector_client_reconnects_total", &[], 1);
+                    self.client = client;
+                    return true;
+                }
+                Err(e) => {
+                    warn!("Failed to reconnect to Vector at {}: {}", self.addr, e);
+                    delay = (delay * 2).min(self.reconnect_max);
+                }
+            }
+        }
     }
 
+    /// Take the batches waiting on the channel and push everything pending,
+    /// once, stopping at the first failure
+    async fn flush(&mut self) {
+        loop {
+            match self.rx.try_recv() {
+                Ok(events) => {
+                    self.received(Ok(events));
+                }
+                Err(broadcast::error::TryRecvError::Lagged(n)) => {
+                    self.received(Err(RecvError::Lagged(n)));
+                }
+                Err(_) => break,
+            }
+        }
+        while let Some(events) = self.pending.front().cloned() {
+            match self.push(&events).await {
+                Ok(_) => {
+                    metrics::increment(
+                        "striem_vector_client_events_total",
+                        &[],
+                        events.len() as u64,
+                    );
+                    self.pending.pop_front();
+                }
+                Err(status) => {
+                    warn!(
+                        "Failed to forward {} events to Vector at {} on shutdown: {}",
+                        events.len(),
+                        self.addr,
+                        status
+                    );
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Forward events until the channel closes or shutdown.
+    ///
+    /// Only the initial health check can fail; later push failures are
+    /// retried or dropped as described in the module docs.
     pub async fn run(&mut self) -> Result<()> {
         let request = tonic::Request::new(vector::HealthCheckRequest {});
 
         let _ = &self.client.health_check(request).await?;
 
         loop {
+            if let Some(events) = self.pending.front().cloned() {
+                match self.push(&events).await {
+                    Ok(_) => {
+                        metrics::increment(
+                            "striem_vector_client_events_total",
+                            &[],
+                            events.len() as u64,
+                        );
+                        self.pending.pop_front();
+                    }
+                    Err(status) if retryable(&status) => {
+                        warn!(
+                            "Failed to forward {} events to Vector at {}: {}",
+                            events.len(),
+                            self.addr,
+                            status
+                        );
+                        if !self.reconnect().await {
+                            break;
+                        }
+                    }
+                    Err(status) => {
+                        warn!(
+                            "Vector rejected {} events, dropping them: {}",
+                            events.len(),
+                            status
+                        );
+                        metrics::increment("striem_vector_client_dropped_batches_total", &[], 1);
+                        self.pending.pop_front();
+                    }
+                }
+                continue;
+            }
+
             tokio::select! {
                 result = self.rx.recv() => {
-                    if let Ok(events) = result {
-                        let events: Vec<EventWrapper> = events
-                            .iter()
-                            .map(|e| EventWrapper {
-                                event: Some(VectorEvent::Log(e.into())),
-                            })
-                            .collect();
-                        let request = tonic::Request::new(vector::PushEventsRequest { events });
-                        let _ = &self.client.push_events(request).await?;
-                    } else {
-                        log::info!("Vector client channel closed");
+                    if !self.received(result) {
                         break;
                     }
                 },
                 msg = self.sys.recv() => {
-                    if let Ok(SysMessage::Shutdown) = msg {
-                        info!("Vector client received shutdown signal");
-                        break;
-                    } else if msg.is_err() {
-                        info!("Shutdown channel closed, exiting Vector client...");
+                    let shutdown = matches!(msg, Ok(SysMessage::Shutdown));
+                    if Self::stopped(msg) {
+                        if shutdown {
+                            self.flush().await;
+                        }
                         break;
                     }
                 }
             }
         }
+        if !self.pending.is_empty() {
+            warn!(
+                "Vector client stopping with {} batches unsent",
+                self.pending.len()
+            );
+        }
         Ok(())
     }
 }
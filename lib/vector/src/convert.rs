@@ -1,4 +1,5 @@
-use serde_json::Value;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{Value, json};
 use std::collections::HashMap;
 use uuid::Uuid;
 
@@ -6,6 +7,20 @@ use crate::event as vector_event;
 
 use striem_common::event::Event;
 
+/// Carry `time` as Vector's `vector.ingest_timestamp`, which is read back
+/// into [`Event::time`] on the way in
+fn set_ingest_timestamp(metadata: &mut HashMap<String, Value>, time: DateTime<Utc>) {
+    let vector = metadata
+        .entry("vector".to_string())
+        .or_insert_with(|| json!({}));
+    if let Some(vector) = vector.as_object_mut() {
+        vector.insert(
+            "ingest_timestamp".to_string(),
+            json!(time.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+        );
+    }
+}
+
 impl From<vector_event::Log> for Event {
     fn from(mut event: vector_event::Log) -> Self {
         let data: Value = event
@@ -43,6 +58,7 @@ impl From<vector_event::Log> for Event {
             metadata.insert("source_id".to_string(), i.into());
         }
 
+        let mut time = None;
         if let Some(ts) = metadata
             .remove("vector")
             .and_then(|v| match v {
@@ -52,8 +68,12 @@ impl From<vector_event::Log> for Event {
             .flatten()
             .and_then(|v| v.as_str().map(|s| s.to_string()))
         {
+            time = DateTime::parse_from_rfc3339(&ts)
+                .ok()
+                .map(|t| t.with_timezone(&Utc));
             metadata.insert("timestamp".to_string(), ts.to_string().into());
         };
+        let time = time.or_else(|| Event::time_of(&data));
 
         let id: Uuid =
             match TryInto::<&[u8; 16]>::try_into(metadata_full.source_event_id.as_slice()) {
@@ -65,7 +85,13 @@ impl From<vector_event::Log> for Event {
             .entry("correlation_uid".to_string())
             .or_insert_with(|| id.to_string().into());
 
-        Event { id, data, metadata }
+        Event {
+            id,
+            data,
+            metadata,
+            time,
+            ack: None,
+        }
     }
 }
 
@@ -86,16 +112,15 @@ impl From<Event> for vector_event::Log {
             .entry("correlation_uid".to_string())
             .or_insert_with(|| val.id.to_string().into());
 
-        if let Some(ts) = val
-            .metadata
-            .get("timestamp")
-            .and_then(|v| v.as_str())
-            .and_then(|ts| serde_json::to_value(ts).ok())
-        {
-            val.metadata.entry("vector".to_string()).and_modify(|e| {
-                e.as_object_mut()
-                    .and_then(|o| o.insert("ingest_timestamp".to_string(), ts.clone()));
-            });
+        let time = val.time.or_else(|| {
+            val.metadata
+                .get("timestamp")
+                .and_then(|v| v.as_str())
+                .and_then(|ts| DateTime::parse_from_rfc3339(ts).ok())
+                .map(|t| t.with_timezone(&Utc))
+        });
+        if let Some(time) = time {
+            set_ingest_timestamp(&mut val.metadata, time);
         }
 
         let metadata_full = vector_event::Metadata {
@@ -133,9 +158,13 @@ impl From<&Event> for vector_event::Log {
             })
             .unwrap_or_default();
 
+        let mut metadata = val.metadata.clone();
+        if let Some(time) = val.time {
+            set_ingest_timestamp(&mut metadata, time);
+        }
         let metadata_full = vector_event::Metadata {
             source_event_id: val.id.as_bytes().to_vec(),
-            value: Some((&val.metadata).into()),
+            value: Some((&metadata).into()),
             ..Default::default()
         };
 
@@ -0,0 +1,91 @@
+//! Which events an output forwards (see [`OutputFilter`]).
+//!
+//! Findings, events with OCSF `class_uid` 2004, are kept by their
+//! `severity_id` and the rule id in `finding_info.analytic.uid`. A finding
+//! whose severity isn't one of informational through critical, or fatal,
+//! is kept from outputs with a `min_severity`. Other events are forwarded
+//! only with `raw_events`, or without a filter.
+
+use std::{collections::HashSet, sync::Arc};
+
+use arc_swap::ArcSwap;
+use striem_common::{event::Event, severity::Severity};
+use striem_config::output::OutputFilter;
+
+/// OCSF detection_finding
+const FINDING_CLASS_UID: u64 = 2004;
+/// OCSF `severity_id` of Fatal, above Critical
+const FATAL_SEVERITY_ID: u64 = 6;
+
+/// An output's filter, replaced on reload
+pub type SharedFilter = Arc<ArcSwap<Filter>>;
+
+#[derive(Debug, Clone)]
+pub struct Filter {
+    min_severity: Option<Severity>,
+    rules: HashSet<String>,
+    exclude_rules: HashSet<String>,
+    raw_events: bool,
+}
+
+impl Filter {
+    /// Filter for `config`; none forwards everything
+    pub fn new(config: Option<&OutputFilter>) -> Self {
+        let Some(config) = config else {
+            return Self {
+                min_severity: None,
+                rules: HashSet::new(),
+                exclude_rules: HashSet::new(),
+                raw_events: true,
+            };
+        };
+        Self {
+            min_severity: config.min_severity,
+            rules: config.rules.iter().cloned().collect(),
+            exclude_rules: config.exclude_rules.iter().cloned().collect(),
+            raw_events: config.raw_events,
+        }
+    }
+
+    pub fn shared(config: Option<&OutputFilter>) -> SharedFilter {
+        Arc::new(ArcSwap::from_pointee(Self::new(config)))
+    }
+
+    pub fn forwards(&self, event: &Event) -> bool {
+        if event.data.get("class_uid").and_then(|v| v.as_u64()) != Some(FINDING_CLASS_UID) {
+            return self.raw_events;
+        }
+        if let Some(min) = self.min_severity {
+            let severe = match event.data.get("severity_id").and_then(|v| v.as_u64()) {
+                Some(FATAL_SEVERITY_ID) => true,
+                Some(id) => Severity::from_id(id).is_some_and(|severity| severity >= min),
+                None => false,
+            };
+            if !severe {
+                return false;
+            }
+        }
+        if self.rules.is_empty() && self.exclude_rules.is_empty() {
+            return true;
+        }
+        let rule = event
+            .data
+            .pointer("/finding_info/analytic/uid")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default();
+        (self.rules.is_empty() || self.rules.contains(rule)) && !self.exclude_rules.contains(rule)
+    }
+
+    /// The events of `batch` to forward, or `None` if there are none
+    pub fn apply(&self, batch: Arc<Vec<Event>>) -> Option<Arc<Vec<Event>>> {
+        if batch.iter().all(|event| self.forwards(event)) {
+            return (!batch.is_empty()).then_some(batch);
+        }
+        let kept = batch
+            .iter()
+            .filter(|event| self.forwards(event))
+            .cloned()
+            .collect::<Vec<_>>();
+        (!kept.is_empty()).then(|| Arc::new(kept))
+    }
+}
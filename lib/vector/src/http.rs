@@ -0,0 +1,309 @@
+//! HTTP client forwarding detection findings to a webhook or collector.
+//!
+//! Each non-empty batch is POSTed as one request, encoded per
+//! [`HttpFormat`] and optionally gzipped. Up to `concurrency` requests are
+//! in flight at once. Connection errors, timeouts, 429 and 5xx responses are
+//! retried with exponential backoff up to `max_retries` times; other
+//! failures drop the batch. Events the output's [`Filter`] doesn't forward
+//! are left out of the request. Shutdown waits for the requests in flight.
+
+use std::{io::Write, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use flate2::{Compression, write::GzEncoder};
+use log::{info, warn};
+use reqwest::{
+    StatusCode,
+    header::{AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue},
+};
+use striem_common::{SysMessage, event::Event, metrics, prelude::*};
+use striem_config::output::{HttpDestinationConfig, HttpFormat};
+use tokio::{
+    sync::{
+        OwnedSemaphorePermit, Semaphore,
+        broadcast::{
+            self,
+            error::{RecvError, TryRecvError},
+        },
+    },
+    task::JoinSet,
+};
+
+use crate::filter::{Filter, SharedFilter};
+
+pub struct HttpClient {
+    url: String,
+    client: reqwest::Client,
+    format: HttpFormat,
+    gzip: bool,
+    max_retries: u32,
+    concurrency: usize,
+    rx: broadcast::Receiver<Arc<Vec<Event>>>,
+    sys: broadcast::Receiver<SysMessage>,
+    filter: SharedFilter,
+    pub(crate) retry_base: Duration,
+    pub(crate) retry_max: Duration,
+}
+
+/// Whether a failed request should be retried
+fn retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+impl HttpClient {
+    pub fn new(
+        config: &HttpDestinationConfig,
+        rx: broadcast::Receiver<Arc<Vec<Event>>>,
+        sys: broadcast::Receiver<SysMessage>,
+    ) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &config.headers {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(value)?,
+            );
+        }
+        if let Some(token) = &config.token {
+            let mut value = HeaderValue::from_str(&format!("Bearer {}", token))?;
+            value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, value);
+        }
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(match config.format {
+                HttpFormat::Ndjson => "application/x-ndjson",
+                HttpFormat::Json => "application/json",
+            }),
+        );
+        if config.gzip {
+            headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        }
+
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .build()?;
+
+        Ok(Self {
+            url: config.cfg.url(),
+            client,
+            format: config.format,
+            gzip: config.gzip,
+            max_retries: config.max_retries,
+            concurrency: config.concurrency.max(1),
+            rx,
+            sys,
+            filter: Filter::shared(config.filter.as_ref()),
+            retry_base: Duration::from_millis(HTTP_OUTPUT_RETRY_BASE_MS),
+            retry_max: Duration::from_secs(HTTP_OUTPUT_RETRY_MAX_SECS),
+        })
+    }
+
+    /// Forward only the events `filter` keeps
+    pub fn with_filter(mut self, filter: SharedFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Request body for a batch of events
+    fn encode(&self, events: &[Event]) -> Result<Vec<u8>> {
+        let body = match self.format {
+            HttpFormat::Ndjson => {
+                let mut body = Vec::new();
+                for event in events {
+                    serde_json::to_writer(&mut body, &event.data)?;
+                    body.push(b'\n');
+                }
+                body
+            }
+            HttpFormat::Json => {
+                serde_json::to_vec(&events.iter().map(|e| &e.data).collect::<Vec<_>>())?
+            }
+        };
+        if !self.gzip {
+            return Ok(body);
+        }
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&body)?;
+        Ok(encoder.finish()?)
+    }
+
+    /// Send one batch, waiting for a free request slot
+    fn dispatch(
+        &self,
+        events: Arc<Vec<Event>>,
+        permit: OwnedSemaphorePermit,
+        requests: &mut JoinSet<()>,
+    ) -> Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let Some(events) = self.filter.load().apply(events) else {
+            return Ok(());
+        };
+        let body = match self.encode(&events) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!(
+                    "Failed to encode {} events for {}: {}",
+                    events.len(),
+                    self.url,
+                    e
+                );
+                metrics::increment("striem_http_output_dropped_batches_total", &[], 1);
+                return Ok(());
+            }
+        };
+        let request = Request {
+            client: self.client.clone(),
+            url: self.url.clone(),
+            events: events.len(),
+            max_retries: self.max_retries,
+            retry_base: self.retry_base,
+            retry_max: self.retry_max,
+        };
+        requests.spawn(async move {
+            request.send(body).await;
+            drop(permit);
+        });
+        // reap finished requests
+        while requests.try_join_next().is_some() {}
+        Ok(())
+    }
+
+    fn lagged(&self, n: u64) {
+        metrics::increment(
+            "striem_broadcast_lagged_total",
+            &[("subscriber", "http_output")],
+            n,
+        );
+        warn!("HTTP output lagged, {} batches not forwarded", n);
+    }
+
+    /// Forward findings until the channel closes or shutdown.
+    ///
+    /// On shutdown the batches already sent on the channel are sent too, and
+    /// requests in flight, retries included, are waited for. If the channel
+    /// or the system channel closes instead, requests in flight are
+    /// abandoned.
+    pub async fn run(&mut self) -> Result<()> {
+        let permits = Arc::new(Semaphore::new(self.concurrency));
+        let mut requests = JoinSet::new();
+        let drain = loop {
+            // wait for room for another request, still answering shutdown
+            let permit = tokio::select! {
+                permit = permits.clone().acquire_owned() => permit?,
+                msg = self.sys.recv() => match stop(msg) {
+                    Some(drain) => break drain,
+                    None => continue,
+                },
+            };
+            tokio::select! {
+                result = self.rx.recv() => match result {
+                    Ok(events) => self.dispatch(events, permit, &mut requests)?,
+                    Err(RecvError::Lagged(n)) => self.lagged(n),
+                    Err(RecvError::Closed) => {
+                        info!("HTTP output channel closed");
+                        break false;
+                    }
+                },
+                msg = self.sys.recv() => if let Some(drain) = stop(msg) {
+                    break drain;
+                }
+            }
+        };
+        if drain {
+            loop {
+                match self.rx.try_recv() {
+                    Ok(events) => {
+                        let permit = permits.clone().acquire_owned().await?;
+                        self.dispatch(events, permit, &mut requests)?
+                    }
+                    Err(TryRecvError::Lagged(n)) => self.lagged(n),
+                    Err(_) => break,
+                }
+            }
+            while requests.join_next().await.is_some() {}
+            return Ok(());
+        }
+        if !requests.is_empty() {
+            warn!(
+                "HTTP output stopping with {} requests in flight",
+                requests.len()
+            );
+        }
+        requests.shutdown().await;
+        Ok(())
+    }
+}
+
+/// Whether a system message stops the output: `Some(true)` to drain what's
+/// already received first, `Some(false)` to stop at once
+fn stop(msg: Result<SysMessage, RecvError>) -> Option<bool> {
+    match msg {
+        Ok(SysMessage::Shutdown) => {
+            info!("HTTP output received shutdown signal");
+            Some(true)
+        }
+        Err(_) => {
+            info!("Shutdown channel closed, exiting HTTP output...");
+            Some(false)
+        }
+        Ok(_) => None,
+    }
+}
+
+/// One batch's delivery, retried independently of the others
+struct Request {
+    client: reqwest::Client,
+    url: String,
+    events: usize,
+    max_retries: u32,
+    retry_base: Duration,
+    retry_max: Duration,
+}
+
+impl Request {
+    async fn send(&self, body: Vec<u8>) {
+        let mut delay = self.retry_base;
+        let mut attempt = 0;
+        loop {
+            let error = match self.client.post(&self.url).body(body.clone()).send().await {
+                Ok(response) if response.status().is_success() => {
+                    metrics::increment("striem_http_output_events_total", &[], self.events as u64);
+                    return;
+                }
+                Ok(response) if !retryable(response.status()) => {
+                    warn!(
+                        "{} rejected {} events, dropping them: {}",
+                        self.url,
+                        self.events,
+                        response.status()
+                    );
+                    break;
+                }
+                Ok(response) => response.status().to_string(),
+                Err(e) => e.to_string(),
+            };
+            if attempt >= self.max_retries {
+                warn!(
+                    "Failed to forward {} events to {} after {} attempts, dropping them: {}",
+                    self.events,
+                    self.url,
+                    attempt + 1,
+                    error
+                );
+                break;
+            }
+            warn!(
+                "Failed to forward {} events to {}, retrying in {:?}: {}",
+                self.events, self.url, delay, error
+            );
+            metrics::increment("striem_http_output_retries_total", &[], 1);
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(self.retry_max);
+            attempt += 1;
+        }
+        metrics::increment("striem_http_output_dropped_batches_total", &[], 1);
+    }
+}
@@ -0,0 +1,281 @@
+//! HTTP listener ingesting events directly, for deployments without Vector.
+//!
+//! Accepts POSTs on any path:
+//! - `/services/collector/event` (and `/services/collector/event/1.0`):
+//!   Splunk HEC events, one or more concatenated JSON objects. Their `event`
+//!   field becomes the event; the other fields (`host`, `source`,
+//!   `sourcetype`, `time`, ...) are kept in the `splunk_hec` metadata.
+//! - anything else: a JSON object, a JSON array of them, or NDJSON.
+//!
+//! [`Event::time`] is the HEC `time`, else the event's own OCSF `time`, else
+//! when it was received.
+//!
+//! Bodies may be gzipped (`Content-Encoding: gzip`) and are capped at
+//! `max_body_size` both before and after decompression. Events are published
+//! on the Vector server's channel (see [`crate::Server::sender`]), so
+//! detection and storage handle them exactly like events from Vector.
+
+use std::{collections::HashMap, io::Read, net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::{ConnectInfo, DefaultBodyLimit, State},
+    http::{
+        HeaderMap, StatusCode, Uri,
+        header::{AUTHORIZATION, CONTENT_ENCODING},
+    },
+    routing::post,
+};
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use log::{error, info};
+use serde_json::{Map, Value, json};
+use striem_common::{SysMessage, constant_time_eq, event::Event, metrics};
+use striem_config::input::HttpListenerConfig;
+use tokio::sync::broadcast;
+
+type Rejection = (StatusCode, String);
+
+fn reject(status: StatusCode, message: impl Into<String>) -> Rejection {
+    metrics::increment(
+        "striem_http_ingest_rejected_requests_total",
+        &[("status", status.as_str())],
+        1,
+    );
+    (status, message.into())
+}
+
+struct Ingest {
+    tokens: Vec<String>,
+    max_body_size: usize,
+    channel: broadcast::Sender<Arc<Vec<Event>>>,
+}
+
+impl Ingest {
+    /// The request's token for `scheme`, if it is allowed in
+    fn authorize(&self, headers: &HeaderMap, scheme: &str) -> Result<Option<String>, Rejection> {
+        let token = headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix(scheme))
+            .map(|t| t.trim().to_string());
+        if self.tokens.is_empty() {
+            return Ok(token);
+        }
+        // every token is compared, so the time taken doesn't tell which matched
+        let valid = |token: &str| {
+            self.tokens.iter().fold(false, |found, t| {
+                found | constant_time_eq(t.as_bytes(), token.as_bytes())
+            })
+        };
+        match token {
+            Some(token) if valid(&token) => Ok(Some(token)),
+            Some(_) => Err(reject(StatusCode::UNAUTHORIZED, "invalid token")),
+            None => Err(reject(StatusCode::UNAUTHORIZED, "token required")),
+        }
+    }
+
+    /// Request body, decompressed if needed
+    fn decode(&self, headers: &HeaderMap, body: Bytes) -> Result<Vec<u8>, Rejection> {
+        let gzip = headers
+            .get(CONTENT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.eq_ignore_ascii_case("gzip"));
+        if !gzip {
+            return Ok(body.to_vec());
+        }
+        let mut decoded = Vec::new();
+        GzDecoder::new(&body[..])
+            .take(self.max_body_size as u64 + 1)
+            .read_to_end(&mut decoded)
+            .map_err(|e| reject(StatusCode::BAD_REQUEST, format!("invalid gzip body: {}", e)))?;
+        if decoded.len() > self.max_body_size {
+            return Err(reject(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "decompressed body too large",
+            ));
+        }
+        Ok(decoded)
+    }
+
+    fn publish(&self, events: Vec<Event>) -> Result<(), Rejection> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let count = events.len();
+        self.channel
+            .send(Arc::new(events))
+            .map_err(|_| reject(StatusCode::SERVICE_UNAVAILABLE, "no event subscribers"))?;
+        metrics::increment("striem_http_ingest_events_total", &[], count as u64);
+        metrics::set_now("striem_last_event_timestamp_seconds", &[]);
+        Ok(())
+    }
+}
+
+/// Source metadata common to every ingested event
+fn metadata(source_type: &str, remote: SocketAddr, uri: &Uri) -> HashMap<String, Value> {
+    HashMap::from([
+        ("source_type".to_string(), json!(source_type)),
+        ("remote_addr".to_string(), json!(remote.to_string())),
+        ("path".to_string(), json!(uri.path())),
+    ])
+}
+
+/// HEC's `time`: epoch seconds, possibly fractional or quoted
+fn hec_time(fields: &Map<String, Value>) -> Option<DateTime<Utc>> {
+    let secs = match fields.get("time")? {
+        Value::Number(n) => n.as_f64()?,
+        Value::String(s) => s.parse().ok()?,
+        _ => return None,
+    };
+    DateTime::from_timestamp_millis((secs * 1000.0) as i64)
+}
+
+/// A JSON object, an array of them, or NDJSON
+fn parse_json(body: &[u8]) -> Result<Vec<Value>, Rejection> {
+    match serde_json::from_slice::<Value>(body) {
+        Ok(Value::Array(values)) => Ok(values),
+        Ok(value) => Ok(vec![value]),
+        Err(_) => body
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.trim_ascii().is_empty())
+            .map(|line| {
+                serde_json::from_slice(line)
+                    .map_err(|e| reject(StatusCode::BAD_REQUEST, format!("invalid JSON: {}", e)))
+            })
+            .collect(),
+    }
+}
+
+/// Concatenated HEC events, split into each `event` and its other fields
+fn parse_hec(body: &[u8]) -> Result<Vec<(Value, Map<String, Value>)>, Rejection> {
+    serde_json::Deserializer::from_slice(body)
+        .into_iter::<Value>()
+        .map(|value| {
+            let value = value
+                .map_err(|e| reject(StatusCode::BAD_REQUEST, format!("invalid JSON: {}", e)))?;
+            let Value::Object(mut fields) = value else {
+                return Err(reject(
+                    StatusCode::BAD_REQUEST,
+                    "HEC events must be objects",
+                ));
+            };
+            let event = fields
+                .remove("event")
+                .ok_or_else(|| reject(StatusCode::BAD_REQUEST, "missing 'event' field"))?;
+            // string events often carry JSON
+            let event = match event {
+                Value::String(s) => serde_json::from_str(&s).unwrap_or(Value::String(s)),
+                v => v,
+            };
+            Ok((event, fields))
+        })
+        .collect()
+}
+
+async fn hec(
+    State(ingest): State<Arc<Ingest>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<Value>, Rejection> {
+    let token = ingest.authorize(&headers, "Splunk ")?;
+    let body = ingest.decode(&headers, body)?;
+    let received = Utc::now();
+    let events = parse_hec(&body)?
+        .into_iter()
+        .map(|(data, fields)| {
+            let time = hec_time(&fields);
+            let mut metadata = metadata("splunk_hec", remote, &uri);
+            if let Some(token) = &token {
+                metadata.insert("splunk_hec_token".to_string(), json!(token));
+            }
+            metadata.insert("splunk_hec".to_string(), Value::Object(fields));
+            let mut event = Event::from((data, metadata));
+            event.time = time.or(event.time).or(Some(received));
+            event
+        })
+        .collect();
+    ingest.publish(events)?;
+    Ok(Json(json!({"text": "Success", "code": 0})))
+}
+
+async fn ingest_json(
+    State(ingest): State<Arc<Ingest>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, Rejection> {
+    ingest.authorize(&headers, "Bearer ")?;
+    let body = ingest.decode(&headers, body)?;
+    let received = Utc::now();
+    let events = parse_json(&body)?
+        .into_iter()
+        .map(|data| {
+            let mut event = Event::from((data, metadata("http_server", remote, &uri)));
+            event.time = event.time.or(Some(received));
+            event
+        })
+        .collect();
+    ingest.publish(events)?;
+    Ok(StatusCode::OK)
+}
+
+/// HTTP ingest server publishing onto an existing event channel
+pub struct HttpServer {
+    ingest: Arc<Ingest>,
+}
+
+impl HttpServer {
+    pub fn new(config: &HttpListenerConfig, channel: broadcast::Sender<Arc<Vec<Event>>>) -> Self {
+        Self {
+            ingest: Arc::new(Ingest {
+                tokens: config.tokens.clone(),
+                max_body_size: config.max_body_size,
+                channel,
+            }),
+        }
+    }
+
+    fn router(&self) -> Router {
+        Router::new()
+            .route("/services/collector/event", post(hec))
+            .route("/services/collector/event/1.0", post(hec))
+            .route("/", post(ingest_json))
+            .route("/{*path}", post(ingest_json))
+            .layer(DefaultBodyLimit::max(self.ingest.max_body_size))
+            .with_state(self.ingest.clone())
+    }
+
+    pub async fn serve(
+        &self,
+        addr: &SocketAddr,
+        mut shutdown: broadcast::Receiver<SysMessage>,
+    ) -> Result<()> {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        axum::serve(
+            listener,
+            self.router()
+                .into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(async move {
+            loop {
+                match shutdown.recv().await {
+                    Ok(SysMessage::Shutdown) => break,
+                    Ok(_) => continue,
+                    Err(_) => {
+                        error!("system broadcast channel closed unexpectedly");
+                        break;
+                    }
+                }
+            }
+            info!("HTTP listener shutting down...");
+        })
+        .await?;
+        Ok(())
+    }
+}
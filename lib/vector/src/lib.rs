@@ -2,6 +2,11 @@ mod convert;
 //mod proto;
 
 mod client;
+mod filter;
+mod http;
+mod ingest;
+mod limit;
+mod metric;
 mod server;
 
 #[allow(unused)]
@@ -15,4 +20,23 @@ pub mod vector {
 }
 
 pub use client::Client;
-pub use server::Server;
+pub use filter::{Filter, SharedFilter};
+pub use http::HttpClient;
+pub use ingest::HttpServer;
+pub use metric::{MetricEvent, MetricKind};
+pub use server::{Server, ServerMonitor, ServerStats};
+
+use striem_config::Compression;
+use tonic::codec::CompressionEncoding;
+
+/// The tonic encoding for `compression`, if any
+pub(crate) fn encoding(compression: Compression) -> Option<CompressionEncoding> {
+    match compression {
+        Compression::None => None,
+        Compression::Gzip => Some(CompressionEncoding::Gzip),
+        Compression::Zstd => Some(CompressionEncoding::Zstd),
+    }
+}
+
+#[cfg(test)]
+mod tests;
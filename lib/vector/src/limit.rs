@@ -0,0 +1,136 @@
+//! Event rate limits for the gRPC server (`input.vector.limits`).
+//!
+//! Each limit is a token bucket refilled at its rate and holding at most one
+//! second's worth. A batch's log events are taken from the global bucket and
+//! from the bucket of each capped source in it. Every bucket is checked
+//! before any is charged, so rejected events cost nothing; a delayed batch
+//! is charged up front and waits for the buckets to refill, which queues
+//! later batches behind it. A batch larger than a full bucket is let
+//! through, leaving the bucket in debt, rather than never fitting.
+//!
+//! A batch with any source over its cap is rejected whole, uncharged: Vector
+//! only retries or backs off on a refused batch, so events dropped from an
+//! accepted one would be lost.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use striem_config::input::{LimitPolicy, LimitsConfig};
+
+/// What to do with a batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Admission {
+    Now,
+    Delay(Duration),
+    Reject,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64, now: Instant) -> Self {
+        Bucket {
+            rate: rate as f64,
+            tokens: rate as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.updated = now;
+    }
+
+    /// How long until `n` events fit
+    fn wait(&self, n: u64) -> Duration {
+        let short = n as f64 - self.tokens;
+        if short <= 0.0 || self.tokens >= self.rate {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(short / self.rate)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    global: Option<Bucket>,
+    sources: HashMap<String, Bucket>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Limiter {
+    policy: LimitPolicy,
+    max_delay: Duration,
+    buckets: Mutex<Buckets>,
+}
+
+impl Limiter {
+    pub(crate) fn new(config: &LimitsConfig) -> Self {
+        let now = Instant::now();
+        // a zero rate would never refill
+        let bucket = |rate: u64| (rate > 0).then(|| Bucket::new(rate, now));
+        Limiter {
+            policy: config.policy,
+            max_delay: Duration::from_millis(config.max_delay_ms),
+            buckets: Mutex::new(Buckets {
+                global: config.events_per_sec.and_then(bucket),
+                sources: config
+                    .sources
+                    .iter()
+                    .filter_map(|(source, rate)| Some((source.clone(), bucket(*rate)?)))
+                    .collect(),
+            }),
+        }
+    }
+
+    /// Admit a batch of `counts` log events by `source_id`, charging the
+    /// buckets unless it's rejected
+    pub(crate) fn admit(&self, counts: &HashMap<String, u64>) -> Admission {
+        let now = Instant::now();
+        let total: u64 = counts.values().sum();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let Buckets { global, sources } = &mut *buckets;
+
+        if let Some(bucket) = global.as_mut() {
+            bucket.refill(now);
+        }
+        let mut capped: Vec<(&mut Bucket, u64)> = Vec::new();
+        for (source, bucket) in sources.iter_mut() {
+            if let Some(n) = counts.get(source) {
+                bucket.refill(now);
+                capped.push((bucket, *n));
+            }
+        }
+        let wait = capped
+            .iter()
+            .map(|(bucket, n)| bucket.wait(*n))
+            .chain(global.as_ref().map(|bucket| bucket.wait(total)))
+            .max()
+            .unwrap_or_default();
+
+        let admission = if wait.is_zero() {
+            Admission::Now
+        } else if self.policy == LimitPolicy::Delay && wait <= self.max_delay {
+            Admission::Delay(wait)
+        } else {
+            return Admission::Reject;
+        };
+        if let Some(bucket) = global.as_mut() {
+            bucket.tokens -= total as f64;
+        }
+        for (bucket, n) in capped {
+            bucket.tokens -= n as f64;
+        }
+        admission
+    }
+}
@@ -1,27 +1,16 @@
 use std::net::SocketAddr;
 
-mod convert;
-mod server;
-
-#[allow(unused)]
-pub mod event {
-    include!(concat!(env!("OUT_DIR"), "/proto/event.rs"));
-}
-
-#[allow(unused)]
-pub mod vector {
-    include!(concat!(env!("OUT_DIR"), "/proto/vector.rs"));
-}
-
-use striem_common::SysMessage;
+use striem_common::{SysMessage, logging};
 use tokio::main;
 
-use crate::server::Server;
+use striem_vector::Server;
 
 #[main]
 async fn main() -> anyhow::Result<()> {
+    logging::init(&Default::default(), None).map_err(|e| anyhow::anyhow!("{}", e))?;
+
     let addr: SocketAddr = "0.0.0.0:50051".parse()?;
-    let mut server = Server::new();
+    let mut server = Server::default();
     let mut rx = server.subscribe().await?;
 
     tokio::spawn(async move {
@@ -0,0 +1,64 @@
+//! Internal representation of metric events received from Vector.
+//!
+//! Only counters and gauges carry a value; other metric types (sets,
+//! distributions, histograms, summaries, sketches) keep their name and tags
+//! with `value: None`.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::event::{self as vector_event, metric};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricKind {
+    /// A change since the last sample
+    Incremental,
+    /// The current value
+    Absolute,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetricEvent {
+    pub name: String,
+    pub namespace: Option<String>,
+    pub tags: BTreeMap<String, String>,
+    /// Epoch milliseconds
+    pub timestamp: Option<i64>,
+    pub kind: MetricKind,
+    pub value: Option<f64>,
+}
+
+impl From<vector_event::Metric> for MetricEvent {
+    fn from(metric: vector_event::Metric) -> Self {
+        let kind = match metric::Kind::try_from(metric.kind) {
+            Ok(metric::Kind::Absolute) => MetricKind::Absolute,
+            _ => MetricKind::Incremental,
+        };
+        let value = match metric.value {
+            Some(metric::Value::Counter(counter)) => Some(counter.value),
+            Some(metric::Value::Gauge(gauge)) => Some(gauge.value),
+            _ => None,
+        };
+        // newer Vector versions send multi-valued tags; keep the last value
+        let mut tags = metric.tags_v1.into_iter().collect::<BTreeMap<_, _>>();
+        tags.extend(metric.tags_v2.into_iter().filter_map(|(k, v)| {
+            v.values
+                .into_iter()
+                .next_back()
+                .and_then(|t| t.value)
+                .map(|v| (k, v))
+        }));
+        MetricEvent {
+            name: metric.name,
+            namespace: Some(metric.namespace).filter(|n| !n.is_empty()),
+            tags,
+            timestamp: metric
+                .timestamp
+                .map(|t| t.seconds * 1000 + i64::from(t.nanos) / 1_000_000),
+            kind,
+            value,
+        }
+    }
+}
@@ -1,21 +1,72 @@
 //! Vector gRPC server implementation.
 //!
 //! Implements Vector's protocol for receiving events via gRPC.
-//! Only supports log events; metric and trace events are rejected.
+//! Log events are broadcast to subscribers. Metric events are dropped, or
+//! with `accept_metrics` set, broadcast on a separate channel; trace events
+//! are dropped.
 //!
 //! # Protocol
 //! Vector sends PushEventsRequest with batches of events.
 //! Server broadcasts to subscribers (detection handler, storage backend).
+//!
+//! Channel capacity and transport limits come from [`ServerOptions`]
+//! (`input.vector.server`).
+//!
+//! # Acknowledgements
+//! By default a batch is acknowledged as soon as it is broadcast. With
+//! acknowledgements enabled, each log event carries the batch's
+//! [`Ack`] and the response waits until storage has written every event;
+//! a failed write or a timeout returns UNAVAILABLE so Vector retries the
+//! batch. Detection doesn't take part.
+//!
+//! # Rate limits
+//! With [`LimitsConfig`] (`input.vector.limits`), batches over the global or
+//! a source's events/sec are held briefly or rejected with
+//! RESOURCE_EXHAUSTED; see [`crate::limit`]. Limits are read at startup.
+//!
+//! # Shutdown
+//! Once shutdown starts, pushes still arriving on open connections are
+//! refused with UNAVAILABLE, so Vector keeps those batches and retries them
+//! after the restart; batches already accepted are finished first.
+//!
+//! # Compression
+//! Gzip-compressed requests are always accepted. With `compression`
+//! (`input.vector.compression`) set, requests in that encoding are accepted
+//! too, and responses are compressed with it for clients that accept it.
+//!
+//! # Peers
+//! Each log event's metadata records the address of the connection it came
+//! in on as `ingest_peer`, and with [`AgentIdConfig`] (`input.vector.agent_id`)
+//! the agent id header as `ingest_agent`. Batches without the header are
+//! refused with UNAUTHENTICATED if it's required.
 
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Weak,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
 
 use anyhow::{Result, anyhow};
-use log::{debug, error, info};
-use striem_common::{SysMessage, event::Event};
+use log::{debug, error, info, warn};
+use serde_json::json;
+use striem_common::{
+    SysMessage,
+    event::{Ack, Event},
+    metrics,
+};
+use striem_config::{
+    Compression,
+    input::{AgentIdConfig, LimitsConfig, ServerOptions},
+};
 use tokio::sync::broadcast;
 
 use crate::{
     event::event_wrapper::Event as VectorEventWrapper,
+    limit::{Admission, Limiter},
+    metric::MetricEvent,
     vector::{
         self,
         vector_server::{Vector, VectorServer},
@@ -23,7 +74,72 @@ use crate::{
 };
 
 struct VectorService {
-    channel: broadcast::Sender<Arc<Vec<Event>>>,
+    channel: Arc<broadcast::Sender<Arc<Vec<Event>>>>,
+    /// Metric events, if they are accepted
+    metrics: Option<broadcast::Sender<Arc<Vec<MetricEvent>>>>,
+    /// How long to wait for log events to be stored, if acknowledgements
+    /// are enabled
+    ack_timeout: Option<Duration>,
+    limiter: Option<Limiter>,
+    agent_id: Option<AgentIdConfig>,
+    /// Set once shutdown starts; pushes are refused from then on
+    stopping: Arc<AtomicBool>,
+}
+
+impl VectorService {
+    /// The agent id sent with `request`, if one is configured; refuses the
+    /// request if it's required and missing
+    fn agent<T>(&self, request: &tonic::Request<T>) -> Result<Option<String>, tonic::Status> {
+        let Some(config) = &self.agent_id else {
+            return Ok(None);
+        };
+        let agent = request
+            .metadata()
+            .get(config.header.as_str())
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(String::from);
+        if agent.is_none() && config.required {
+            return Err(tonic::Status::unauthenticated(format!(
+                "missing {} header",
+                config.header
+            )));
+        }
+        Ok(agent)
+    }
+
+    /// Hold a batch of `sources` log events over its limits, or refuse it
+    /// whole so Vector keeps its events and backs off
+    async fn limit(&self, sources: &HashMap<String, u64>) -> Result<(), tonic::Status> {
+        let Some(limiter) = &self.limiter else {
+            return Ok(());
+        };
+        let (action, delay) = match limiter.admit(sources) {
+            Admission::Now => return Ok(()),
+            Admission::Delay(delay) => {
+                debug!("delaying a batch by {:?} for rate limits", delay);
+                ("delayed", Some(delay))
+            }
+            Admission::Reject => ("rejected", None),
+        };
+        for (source, n) in sources {
+            metrics::increment(
+                "striem_vector_server_limited_events_total",
+                &[("source_id", source), ("action", action)],
+                *n,
+            );
+        }
+        match delay {
+            Some(delay) => {
+                tokio::time::sleep(delay).await;
+                Ok(())
+            }
+            None => Err(tonic::Status::resource_exhausted(
+                "event rate limit exceeded",
+            )),
+        }
+    }
 }
 
 #[tonic::async_trait]
@@ -31,8 +147,10 @@ impl Vector for VectorService {
     /// Receive and broadcast log events to subscribers.
     ///
     /// # Event Type Filtering
-    /// Only log events are supported. Metrics and traces are rejected
-    /// with UNIMPLEMENTED status to fail fast rather than silently drop.
+    /// Log events are broadcast; metric events go to the metrics channel if
+    /// enabled. Everything else is counted and dropped, so one misrouted
+    /// event doesn't fail the logs around it. A batch with nothing
+    /// processable is rejected with UNIMPLEMENTED.
     ///
     /// # Broadcasting
     /// Events are Arc-wrapped before sending to minimize cloning overhead
@@ -41,32 +159,132 @@ impl Vector for VectorService {
         &self,
         request: tonic::Request<vector::PushEventsRequest>,
     ) -> Result<tonic::Response<vector::PushEventsResponse>, tonic::Status> {
-        let events = request
-            .into_inner()
-            .events
-            .iter_mut()
-            .map(|wrapped| {
-                let event = wrapped
-                    .event
-                    .take()
-                    .ok_or_else(|| tonic::Status::invalid_argument("missing event"))?;
-                match event {
-                    VectorEventWrapper::Log(e) => {
-                        debug!("received log event: {:?}", e);
-                        Ok(e.into())
+        if self.stopping.load(Ordering::Acquire) {
+            return Err(tonic::Status::unavailable("StrIEM is shutting down"));
+        }
+        let agent = self.agent(&request)?;
+        let peer = request.remote_addr();
+        let wrapped = request.into_inner().events;
+        let received = wrapped.len();
+        let mut logs: Vec<Event> = Vec::new();
+        let mut metric_events = Vec::new();
+        let mut sources: HashMap<String, u64> = HashMap::new();
+        for event in wrapped.into_iter().map(|w| w.event) {
+            let kind = match event {
+                Some(VectorEventWrapper::Log(e)) => {
+                    debug!("received log event: {:?}", e);
+                    let mut event: Event = e.into();
+                    if let Some(peer) = peer {
+                        event
+                            .metadata
+                            .insert("ingest_peer".to_string(), json!(peer.to_string()));
                     }
-                    _ => Err(tonic::Status::unimplemented(
-                        "only log events are supported by this server",
-                    )),
+                    if let Some(agent) = &agent {
+                        event
+                            .metadata
+                            .insert("ingest_agent".to_string(), json!(agent));
+                    }
+                    let source = event
+                        .metadata
+                        .get("source_id")
+                        .and_then(|s| s.as_str())
+                        .unwrap_or("unknown");
+                    *sources.entry(source.to_string()).or_default() += 1;
+                    logs.push(event);
+                    "log"
                 }
-            })
-            .collect::<Result<Vec<Event>, tonic::Status>>()?;
+                Some(VectorEventWrapper::Metric(m)) => {
+                    if self.metrics.is_some() {
+                        metric_events.push(MetricEvent::from(m));
+                    } else {
+                        metrics::increment(
+                            "striem_vector_server_dropped_events_total",
+                            &[("type", "metric")],
+                            1,
+                        );
+                    }
+                    "metric"
+                }
+                Some(_) => {
+                    metrics::increment(
+                        "striem_vector_server_dropped_events_total",
+                        &[("type", "trace")],
+                        1,
+                    );
+                    "trace"
+                }
+                None => {
+                    metrics::increment(
+                        "striem_vector_server_dropped_events_total",
+                        &[("type", "missing")],
+                        1,
+                    );
+                    continue;
+                }
+            };
+            metrics::increment("striem_vector_server_events_total", &[("type", kind)], 1);
+        }
 
-        let events = Arc::new(events);
+        for (source, n) in &sources {
+            metrics::increment(
+                "striem_vector_server_source_events_total",
+                &[("source_id", source)],
+                *n,
+            );
+        }
+        if !sources.is_empty() {
+            metrics::set_now("striem_last_event_timestamp_seconds", &[]);
+        }
 
-        self.channel
-            .send(events)
-            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+        if received > 0 && logs.is_empty() && metric_events.is_empty() {
+            return Err(tonic::Status::unimplemented(
+                "batch contains no log events (or metric events, if accepted)",
+            ));
+        }
+        if logs.len() + metric_events.len() < received {
+            warn!(
+                "dropped {} unsupported events from a batch of {}",
+                received - logs.len() - metric_events.len(),
+                received
+            );
+        }
+
+        if !sources.is_empty() {
+            self.limit(&sources).await?;
+        }
+
+        if let Some(channel) = &self.metrics
+            && !metric_events.is_empty()
+        {
+            // nothing may be subscribed to metrics yet
+            channel.send(Arc::new(metric_events)).ok();
+        }
+        if !logs.is_empty() {
+            let stored = self.ack_timeout.map(|timeout| {
+                let (ack, stored) = Ack::new(logs.len());
+                for event in logs.iter_mut() {
+                    event.ack = Some(ack.clone());
+                }
+                (timeout, stored)
+            });
+            self.channel
+                .send(Arc::new(logs))
+                .map_err(|e| tonic::Status::internal(e.to_string()))?;
+
+            if let Some((timeout, stored)) = stored {
+                match tokio::time::timeout(timeout, stored).await {
+                    Ok(Ok(true)) => {}
+                    Ok(_) => {
+                        return Err(tonic::Status::unavailable("events could not be stored"));
+                    }
+                    Err(_) => {
+                        return Err(tonic::Status::unavailable(
+                            "timed out waiting for events to be stored",
+                        ));
+                    }
+                }
+            }
+        }
 
         Ok(tonic::Response::new(vector::PushEventsResponse {}))
     }
@@ -85,29 +303,120 @@ impl Vector for VectorService {
 /// Channel is created at construction but not started until serve() is called.
 pub struct Server {
     service: Option<VectorService>,
+    options: ServerOptions,
+    compression: Compression,
+    /// Observes the channel without keeping it open once the service stops
+    channel: Weak<broadcast::Sender<Arc<Vec<Event>>>>,
 }
 
 impl Default for Server {
     fn default() -> Self {
-        Self::new()
+        Self::new(&ServerOptions::default())
+    }
+}
+
+/// Point-in-time state of the server's event channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerStats {
+    /// Subscribed receivers (detection, storage, ...)
+    pub subscribers: usize,
+    /// Batches not yet received by the slowest subscriber; once this
+    /// reaches `capacity`, that subscriber starts missing batches
+    pub queued: usize,
+    pub capacity: usize,
+}
+
+/// Cloneable handle for reading [`ServerStats`] while the server runs
+#[derive(Clone)]
+pub struct ServerMonitor {
+    channel: Weak<broadcast::Sender<Arc<Vec<Event>>>>,
+    capacity: usize,
+}
+
+impl ServerMonitor {
+    /// Current channel state, or `None` once the server has stopped
+    pub fn stats(&self) -> Option<ServerStats> {
+        let channel = self.channel.upgrade()?;
+        Some(ServerStats {
+            subscribers: channel.receiver_count(),
+            queued: channel.len(),
+            capacity: self.capacity,
+        })
     }
 }
 
 impl Server {
-    /// Create server with 256-event buffer capacity.
+    /// Create server with the configured channel capacity.
     ///
     /// # Buffer Sizing
-    /// 256 provides backpressure for slow subscribers without excessive memory.
-    /// Vector batches events, so this represents ~10-50 batches depending on
-    /// Vector's batch settings.
-    pub fn new() -> Self {
+    /// The default of 256 provides backpressure for slow subscribers without
+    /// excessive memory. Vector batches events, so this represents ~10-50
+    /// batches depending on Vector's batch settings.
+    pub fn new(options: &ServerOptions) -> Self {
+        let capacity = options.channel_capacity.max(1);
+        let channel = Arc::new(broadcast::channel(capacity).0);
+        let metrics = options
+            .accept_metrics
+            .then(|| broadcast::channel(capacity).0);
         Self {
+            options: *options,
+            compression: Compression::None,
+            channel: Arc::downgrade(&channel),
             service: Some(VectorService {
-                channel: broadcast::channel(256).0,
+                channel,
+                metrics,
+                ack_timeout: None,
+                limiter: None,
+                agent_id: None,
+                stopping: Arc::new(AtomicBool::new(false)),
             }),
         }
     }
 
+    /// Wait up to `timeout` for storage to write each batch before
+    /// acknowledging it. Only enable this when a storage backend subscribes,
+    /// or every batch times out.
+    pub fn with_acknowledgements(mut self, timeout: Duration) -> Self {
+        if let Some(service) = self.service.as_mut() {
+            service.ack_timeout = Some(timeout);
+        }
+        self
+    }
+
+    /// Limit the rate of received log events
+    pub fn with_limits(mut self, limits: &LimitsConfig) -> Self {
+        if let Some(service) = self.service.as_mut() {
+            service.limiter = Some(Limiter::new(limits));
+        }
+        self
+    }
+
+    /// Record the agent id header of each batch, refusing batches without it
+    /// if it's required
+    pub fn with_agent_id(mut self, agent_id: &AgentIdConfig) -> Self {
+        if let Some(service) = self.service.as_mut() {
+            service.agent_id = Some(AgentIdConfig {
+                header: agent_id.header.to_lowercase(),
+                ..agent_id.clone()
+            });
+        }
+        self
+    }
+
+    /// Accept requests compressed with `compression` besides gzip, and
+    /// compress responses with it
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn monitor(&self) -> ServerMonitor {
+        ServerMonitor {
+            channel: self.channel.clone(),
+            capacity: self.options.channel_capacity.max(1),
+        }
+    }
+
     pub async fn serve(
         &mut self,
         addr: &std::net::SocketAddr,
@@ -119,12 +428,21 @@ impl Server {
             .service
             .take()
             .ok_or_else(|| anyhow!("service already running"))?;
+        let stopping = service.stopping.clone();
+
+        let mut service = VectorServer::new(service)
+            .accept_compressed(tonic::codec::CompressionEncoding::Gzip)
+            .max_decoding_message_size(self.options.max_decoding_message_size);
+        if let Some(encoding) = crate::encoding(self.compression) {
+            service = service
+                .accept_compressed(encoding)
+                .send_compressed(encoding);
+        }
 
         tonic::transport::Server::builder()
-            .add_service(
-                VectorServer::new(service)
-                    .accept_compressed(tonic::codec::CompressionEncoding::Gzip),
-            )
+            .tcp_nodelay(self.options.tcp_nodelay)
+            .max_concurrent_streams(self.options.max_concurrent_streams)
+            .add_service(service)
             .serve_with_shutdown(*addr, async {
                 loop {
                     match shutdown.recv().await {
@@ -136,6 +454,7 @@ impl Server {
                         }
                     }
                 }
+                stopping.store(true, Ordering::Release);
                 info!("Vector listener shutting down...");
             })
             .await?;
@@ -149,4 +468,26 @@ impl Server {
             .ok_or_else(|| anyhow!("service not running"))?;
         Ok(service.channel.subscribe())
     }
+
+    /// Sender for the event channel, for other listeners to publish on
+    /// alongside Vector
+    pub fn sender(&self) -> Result<broadcast::Sender<Arc<Vec<Event>>>> {
+        let service = self
+            .service
+            .as_ref()
+            .ok_or_else(|| anyhow!("service not running"))?;
+        Ok((*service.channel).clone())
+    }
+
+    /// Metric events received from Vector, or `None` unless
+    /// `accept_metrics` is set
+    pub async fn subscribe_metrics(
+        &self,
+    ) -> Result<Option<broadcast::Receiver<Arc<Vec<MetricEvent>>>>> {
+        let service = self
+            .service
+            .as_ref()
+            .ok_or_else(|| anyhow!("service not running"))?;
+        Ok(service.metrics.as_ref().map(|m| m.subscribe()))
+    }
 }
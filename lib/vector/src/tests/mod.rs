@@ -0,0 +1,993 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use serde_json::json;
+use striem_common::{SysMessage, event::Event, metrics};
+use tokio::{sync::broadcast, task::JoinHandle};
+
+use crate::{Client, Server};
+
+type Received = broadcast::Receiver<Arc<Vec<Event>>>;
+
+fn free_addr() -> SocketAddr {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// Start a Vector server on `addr`, returning its events and a handle to
+/// stop it
+async fn start_server(
+    addr: SocketAddr,
+) -> (Received, broadcast::Sender<SysMessage>, JoinHandle<()>) {
+    start_server_with(addr, Server::default()).await
+}
+
+async fn start_server_with(
+    addr: SocketAddr,
+    mut server: Server,
+) -> (Received, broadcast::Sender<SysMessage>, JoinHandle<()>) {
+    let events = server.subscribe().await.unwrap();
+    let (stop, shutdown) = broadcast::channel(1);
+    let task = tokio::spawn(async move {
+        server.serve(&addr, shutdown).await.unwrap();
+    });
+    // wait until it accepts connections
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(addr).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    (events, stop, task)
+}
+
+async fn next(events: &mut Received) -> Vec<Event> {
+    tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("timed out waiting for events")
+        .unwrap()
+        .to_vec()
+}
+
+#[tokio::test]
+async fn client_reconnects_and_resends_after_restart() {
+    let addr = free_addr();
+    let (mut received, stop, server) = start_server(addr).await;
+
+    let (tx, rx) = broadcast::channel(16);
+    let (sys, sys_rx) = broadcast::channel(1);
+    let mut client = Client::new(&format!("http://{}", addr), rx, sys_rx)
+        .await
+        .unwrap();
+    client.reconnect_base = Duration::from_millis(50);
+    client.reconnect_max = Duration::from_millis(200);
+    let client = tokio::spawn(async move { client.run().await });
+
+    let batch = |n: u64| Arc::new(vec![Event::from(json!({"n": n}))]);
+    let first = batch(1);
+    tx.send(first.clone()).unwrap();
+    assert_eq!(next(&mut received).await[0].id, first[0].id);
+
+    // kill the downstream Vector, then send while it is away
+    stop.send(SysMessage::Shutdown).unwrap();
+    server.await.unwrap();
+    let (second, third) = (batch(2), batch(3));
+    tx.send(second.clone()).unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    tx.send(third.clone()).unwrap();
+
+    // once it is back, both batches arrive in order
+    let (mut received, stop, server) = start_server(addr).await;
+    assert_eq!(next(&mut received).await[0].id, second[0].id);
+    assert_eq!(next(&mut received).await[0].id, third[0].id);
+
+    let fourth = batch(4);
+    tx.send(fourth.clone()).unwrap();
+    assert_eq!(next(&mut received).await[0].id, fourth[0].id);
+
+    assert!(
+        metrics::value("striem_vector_client_reconnects_total", &[]).unwrap_or_default() >= 1.0
+    );
+    assert_eq!(
+        metrics::value("striem_vector_client_dropped_batches_total", &[]),
+        None
+    );
+
+    sys.send(SysMessage::Shutdown).unwrap();
+    tokio::time::timeout(Duration::from_secs(1), client)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    stop.send(SysMessage::Shutdown).unwrap();
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn compressed_batches_both_ways() {
+    use striem_config::Compression;
+
+    // gzip requests are accepted whatever the server sends
+    let settings = [
+        (Compression::None, Compression::None),
+        (Compression::Gzip, Compression::Gzip),
+        (Compression::Zstd, Compression::Zstd),
+        (Compression::None, Compression::Gzip),
+    ];
+    for (server, client) in settings {
+        let addr = free_addr();
+        let (mut received, stop, task) =
+            start_server_with(addr, Server::default().with_compression(server)).await;
+
+        let (tx, rx) = broadcast::channel(16);
+        let (sys, sys_rx) = broadcast::channel(1);
+        let mut sender = Client::new(&format!("http://{}", addr), rx, sys_rx)
+            .await
+            .unwrap()
+            .with_compression(client);
+        let sender = tokio::spawn(async move { sender.run().await });
+
+        let padding = "x".repeat(512);
+        let batch = Arc::new(
+            (0..2000)
+                .map(|n| Event::from(json!({"n": n, "padding": padding})))
+                .collect::<Vec<_>>(),
+        );
+        tx.send(batch.clone()).unwrap();
+        let events = next(&mut received).await;
+        assert_eq!(events.len(), batch.len(), "{:?} -> {:?}", client, server);
+        assert_eq!(events[1999].id, batch[1999].id);
+
+        sys.send(SysMessage::Shutdown).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), sender)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        stop.send(SysMessage::Shutdown).unwrap();
+        task.await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn supervised_client_restarts_after_failing() {
+    use crate::vector::{
+        HealthCheckRequest, HealthCheckResponse, PushEventsRequest, PushEventsResponse,
+        vector_server::{Vector, VectorServer},
+    };
+
+    /// Accepts connections but fails every health check, like a Vector
+    /// going down
+    struct Failing;
+
+    #[tonic::async_trait]
+    impl Vector for Failing {
+        async fn push_events(
+            &self,
+            _: tonic::Request<PushEventsRequest>,
+        ) -> Result<tonic::Response<PushEventsResponse>, tonic::Status> {
+            Ok(tonic::Response::new(PushEventsResponse {}))
+        }
+
+        async fn health_check(
+            &self,
+            _: tonic::Request<HealthCheckRequest>,
+        ) -> Result<tonic::Response<HealthCheckResponse>, tonic::Status> {
+            Err(tonic::Status::unavailable("shutting down"))
+        }
+    }
+
+    let addr = free_addr();
+    let (stop_failing, mut failing_shutdown) = broadcast::channel::<()>(1);
+    let failing = tokio::spawn(async move {
+        tonic::transport::Server::builder()
+            .add_service(VectorServer::new(Failing))
+            .serve_with_shutdown(addr, async move {
+                failing_shutdown.recv().await.ok();
+            })
+            .await
+            .unwrap();
+    });
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(addr).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let (tx, _) = broadcast::channel(16);
+    let (sys, sys_rx) = broadcast::channel(1);
+    let supervisor = tokio::spawn({
+        let tx = tx.clone();
+        async move {
+            Client::supervise_with(
+                &format!("http://{}", addr),
+                &tx,
+                crate::Filter::shared(None),
+                striem_config::Compression::None,
+                sys_rx,
+                Duration::from_millis(50),
+                Duration::from_millis(200),
+            )
+            .await
+        }
+    });
+
+    // the client fails against it and keeps being restarted
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    stop_failing.send(()).unwrap();
+    failing.await.unwrap();
+
+    // once a working Vector is back, forwarding resumes
+    let (mut received, stop, server) = start_server(addr).await;
+    let forwarded = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let batch = Arc::new(vec![Event::from(json!({"n": 1}))]);
+            // batches sent before the new client subscribes aren't forwarded
+            tx.send(batch.clone()).ok();
+            if let Ok(Ok(events)) =
+                tokio::time::timeout(Duration::from_millis(100), received.recv()).await
+            {
+                return events;
+            }
+        }
+    })
+    .await
+    .expect("forwarding did not resume");
+    assert_eq!(forwarded[0].data["n"], json!(1));
+
+    sys.send(SysMessage::Shutdown).unwrap();
+    tokio::time::timeout(Duration::from_secs(1), supervisor)
+        .await
+        .unwrap()
+        .unwrap();
+    stop.send(SysMessage::Shutdown).unwrap();
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn server_applies_options_and_reports_stats() {
+    use crate::{
+        event::{EventWrapper, event_wrapper::Event as VectorEvent},
+        vector::{PushEventsRequest, vector_client::VectorClient},
+    };
+    use striem_config::input::ServerOptions;
+
+    let options = ServerOptions {
+        channel_capacity: 2,
+        max_decoding_message_size: 4096,
+        ..Default::default()
+    };
+    let server = Server::new(&options);
+    let monitor = server.monitor();
+    let addr = free_addr();
+    let (received, stop, task) = start_server_with(addr, server).await;
+
+    let stats = monitor.stats().unwrap();
+    assert_eq!((stats.subscribers, stats.queued, stats.capacity), (1, 0, 2));
+
+    let mut client = VectorClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+    let request = |size: usize| PushEventsRequest {
+        events: vec![EventWrapper {
+            event: Some(VectorEvent::Log(
+                (&Event::from(json!({"message": "x".repeat(size)}))).into(),
+            )),
+        }],
+    };
+
+    // requests over the limit are refused
+    let status = client.push_events(request(8192)).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::OutOfRange);
+
+    for _ in 0..3 {
+        client.push_events(request(16)).await.unwrap();
+    }
+    // the unread subscriber holds the channel at capacity
+    let stats = monitor.stats().unwrap();
+    assert_eq!((stats.subscribers, stats.queued), (1, 2));
+    drop(received);
+    assert_eq!(monitor.stats().unwrap().subscribers, 0);
+
+    drop(client);
+    stop.send(SysMessage::Shutdown).unwrap();
+    task.await.unwrap();
+    // the channel goes away with the server
+    tokio::time::timeout(Duration::from_secs(1), async {
+        while monitor.stats().is_some() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn mixed_batches_keep_logs_flowing() {
+    use crate::{
+        MetricEvent, MetricKind,
+        event::{self as vector_event, EventWrapper, event_wrapper::Event as VectorEvent, metric},
+        vector::{PushEventsRequest, vector_client::VectorClient},
+    };
+    use striem_config::input::ServerOptions;
+
+    let log = |n: u64| VectorEvent::Log((&Event::from(json!({"n": n}))).into());
+    let gauge = || {
+        VectorEvent::Metric(vector_event::Metric {
+            name: "queue_size".to_string(),
+            kind: metric::Kind::Absolute as i32,
+            value: Some(metric::Value::Gauge(vector_event::Gauge { value: 3.0 })),
+            tags_v1: [("host".to_string(), "a".to_string())].into(),
+            ..Default::default()
+        })
+    };
+    let trace = || VectorEvent::Trace(vector_event::Trace::default());
+    let request = |events: Vec<VectorEvent>| PushEventsRequest {
+        events: events
+            .into_iter()
+            .map(|e| EventWrapper { event: Some(e) })
+            .collect(),
+    };
+
+    // metrics not accepted: they are dropped, the logs still arrive
+    let addr = free_addr();
+    let (mut received, stop, task) = start_server(addr).await;
+    let mut client = VectorClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+    let dropped = || {
+        metrics::value(
+            "striem_vector_server_dropped_events_total",
+            &[("type", "metric")],
+        )
+        .unwrap_or_default()
+    };
+    let before = dropped();
+    client
+        .push_events(request(vec![log(1), gauge(), trace(), log(2)]))
+        .await
+        .unwrap();
+    let logs = next(&mut received).await;
+    assert_eq!(logs.len(), 2);
+    assert_eq!(logs[1].data["n"], json!(2));
+    assert!(dropped() > before);
+
+    // nothing processable is still an error
+    let status = client
+        .push_events(request(vec![gauge(), trace()]))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unimplemented);
+    drop(client);
+    stop.send(SysMessage::Shutdown).unwrap();
+    task.await.unwrap();
+
+    // metrics accepted: they arrive on their own channel
+    let server = Server::new(&ServerOptions {
+        accept_metrics: true,
+        ..Default::default()
+    });
+    let mut metric_events = server.subscribe_metrics().await.unwrap().unwrap();
+    let addr = free_addr();
+    let (mut received, stop, task) = start_server_with(addr, server).await;
+    let mut client = VectorClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+    client
+        .push_events(request(vec![gauge(), log(3)]))
+        .await
+        .unwrap();
+    assert_eq!(next(&mut received).await.len(), 1);
+    let metrics = tokio::time::timeout(Duration::from_secs(5), metric_events.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        metrics.as_slice(),
+        &[MetricEvent {
+            name: "queue_size".to_string(),
+            namespace: None,
+            tags: [("host".to_string(), "a".to_string())].into(),
+            timestamp: None,
+            kind: MetricKind::Absolute,
+            value: Some(3.0),
+        }]
+    );
+
+    // a metrics-only batch is processable now
+    client.push_events(request(vec![gauge()])).await.unwrap();
+    drop(client);
+    stop.send(SysMessage::Shutdown).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn acknowledgements_wait_for_storage() {
+    use crate::{
+        event::{EventWrapper, event_wrapper::Event as VectorEvent},
+        vector::{PushEventsRequest, vector_client::VectorClient},
+    };
+
+    let server = Server::default().with_acknowledgements(Duration::from_millis(200));
+    let addr = free_addr();
+    let (mut received, stop, task) = start_server_with(addr, server).await;
+    let mut client = VectorClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+    let request = || PushEventsRequest {
+        events: (0..2)
+            .map(|n| EventWrapper {
+                event: Some(VectorEvent::Log((&Event::from(json!({"n": n}))).into())),
+            })
+            .collect(),
+    };
+
+    // stands in for storage: stores the first batch, fails the second and
+    // never gets to the third
+    let storage = tokio::spawn(async move {
+        let events = next(&mut received).await;
+        let ack = events[0].ack.clone().unwrap();
+        assert!(Arc::ptr_eq(&ack, events[1].ack.as_ref().unwrap()));
+        ack.complete(1);
+        ack.complete(1);
+        next(&mut received).await[0].ack.as_ref().unwrap().fail();
+        next(&mut received).await
+    });
+
+    client.push_events(request()).await.unwrap();
+    let status = client.push_events(request()).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+    let status = client.push_events(request()).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+    assert!(status.message().contains("timed out"));
+
+    storage.await.unwrap();
+    drop(client);
+    stop.send(SysMessage::Shutdown).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn events_record_peer_and_agent() {
+    use striem_config::input::AgentIdConfig;
+
+    use crate::{
+        event::{EventWrapper, event_wrapper::Event as VectorEvent},
+        vector::{PushEventsRequest, vector_client::VectorClient},
+    };
+
+    let server = Server::default().with_agent_id(&AgentIdConfig {
+        header: "X-StrIEM-Agent-Id".to_string(),
+        required: true,
+    });
+    let addr = free_addr();
+    let (mut received, stop, task) = start_server_with(addr, server).await;
+    let mut client = VectorClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+    let request = || {
+        tonic::Request::new(PushEventsRequest {
+            events: vec![EventWrapper {
+                event: Some(VectorEvent::Log((&Event::from(json!({"n": 1}))).into())),
+            }],
+        })
+    };
+
+    let status = client.push_events(request()).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+
+    let mut with_agent = request();
+    with_agent
+        .metadata_mut()
+        .insert("x-striem-agent-id", "edge-1".parse().unwrap());
+    client.push_events(with_agent).await.unwrap();
+    let events = next(&mut received).await;
+    assert_eq!(events[0].metadata["ingest_agent"], json!("edge-1"));
+    let peer: SocketAddr = events[0].metadata["ingest_peer"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(peer.ip().is_loopback());
+
+    drop(client);
+    stop.send(SysMessage::Shutdown).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn capped_sources_dont_hold_up_others() {
+    use std::time::Instant;
+
+    use crate::{
+        event::{EventWrapper, event_wrapper::Event as VectorEvent},
+        vector::{PushEventsRequest, vector_client::VectorClient},
+    };
+    use striem_config::input::{LimitPolicy, LimitsConfig};
+
+    let request = |source: &str, n: usize| PushEventsRequest {
+        events: (0..n)
+            .map(|n| {
+                let mut event = Event::from(json!({"n": n}));
+                event
+                    .metadata
+                    .insert("source_id".to_string(), json!(source));
+                EventWrapper {
+                    event: Some(VectorEvent::Log((&event).into())),
+                }
+            })
+            .collect(),
+    };
+    let limited = |source: &str, action: &str| {
+        metrics::value(
+            "striem_vector_server_limited_events_total",
+            &[("source_id", source), ("action", action)],
+        )
+        .unwrap_or_default()
+    };
+    let limits = |policy: LimitPolicy| LimitsConfig {
+        events_per_sec: None,
+        sources: [("limit-noisy".to_string(), 10)].into(),
+        policy,
+        max_delay_ms: 2000,
+    };
+
+    // rejected: the noisy source's second batch is refused, the quiet one
+    // goes through
+    let server = Server::default().with_limits(&limits(LimitPolicy::Reject));
+    let addr = free_addr();
+    let (mut received, stop, task) = start_server_with(addr, server).await;
+    let mut client = VectorClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+    client
+        .push_events(request("limit-noisy", 10))
+        .await
+        .unwrap();
+    let status = client
+        .push_events(request("limit-noisy", 10))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    assert_eq!(limited("limit-noisy", "rejected"), 10.0);
+    client
+        .push_events(request("limit-quiet", 100))
+        .await
+        .unwrap();
+    assert_eq!(limited("limit-quiet", "rejected"), 0.0);
+    // a batch of both is refused whole, so Vector keeps the quiet events too
+    let mut mixed = request("limit-noisy", 10);
+    mixed.events.extend(request("limit-quiet", 5).events);
+    let status = client.push_events(mixed).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    assert_eq!(limited("limit-noisy", "rejected"), 20.0);
+    assert_eq!(limited("limit-quiet", "rejected"), 5.0);
+    assert_eq!(next(&mut received).await.len(), 10);
+    assert_eq!(next(&mut received).await.len(), 100);
+    assert!(received.try_recv().is_err());
+    // refused batches aren't charged, so a second's refill fits the next one
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    client
+        .push_events(request("limit-noisy", 10))
+        .await
+        .unwrap();
+    assert_eq!(next(&mut received).await.len(), 10);
+    drop(client);
+    stop.send(SysMessage::Shutdown).unwrap();
+    task.await.unwrap();
+
+    // delayed: the noisy source waits for its bucket while the quiet one
+    // is answered at once
+    let server = Server::default().with_limits(&limits(LimitPolicy::Delay));
+    let addr = free_addr();
+    let (_received, stop, task) = start_server_with(addr, server).await;
+    let mut client = VectorClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+    client
+        .push_events(request("limit-noisy", 10))
+        .await
+        .unwrap();
+    let start = Instant::now();
+    let noisy = {
+        let mut client = client.clone();
+        let request = request("limit-noisy", 10);
+        tokio::spawn(async move {
+            client.push_events(request).await.unwrap();
+            start.elapsed()
+        })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    client
+        .push_events(request("limit-quiet", 100))
+        .await
+        .unwrap();
+    let quiet = start.elapsed();
+    let noisy = noisy.await.unwrap();
+    assert!(quiet < Duration::from_millis(500), "{:?}", quiet);
+    assert!(noisy >= Duration::from_millis(800), "{:?}", noisy);
+    assert_eq!(limited("limit-noisy", "delayed"), 10.0);
+    drop(client);
+    stop.send(SysMessage::Shutdown).unwrap();
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn http_output_batches_and_retries() {
+    use std::{io::Read, sync::Mutex};
+
+    use axum::{
+        Router,
+        body::Bytes,
+        extract::State,
+        http::{HeaderMap, StatusCode},
+        routing::post,
+    };
+    use flate2::read::GzDecoder;
+    use striem_config::output::HttpDestinationConfig;
+
+    use crate::HttpClient;
+
+    /// Requests received, and the statuses to answer them with (200 once
+    /// they run out)
+    #[derive(Clone, Default)]
+    struct Receiver {
+        requests: Arc<Mutex<Vec<(HeaderMap, Bytes)>>>,
+        statuses: Arc<Mutex<Vec<StatusCode>>>,
+    }
+    async fn ingest(
+        State(receiver): State<Receiver>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> StatusCode {
+        receiver.requests.lock().unwrap().push((headers, body));
+        receiver
+            .statuses
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or(StatusCode::OK)
+    }
+    let serve = |statuses: Vec<StatusCode>| async move {
+        let receiver = Receiver {
+            statuses: Arc::new(Mutex::new(statuses)),
+            ..Default::default()
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new()
+            .route("/ingest", post(ingest))
+            .with_state(receiver.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}/ingest", addr), receiver)
+    };
+    let start = |config: serde_json::Value| {
+        let config: HttpDestinationConfig = serde_json::from_value(config).unwrap();
+        let (tx, rx) = broadcast::channel(4);
+        let (sys, sys_rx) = broadcast::channel(1);
+        let mut client = HttpClient::new(&config, rx, sys_rx).unwrap();
+        client.retry_base = Duration::from_millis(10);
+        let task = tokio::spawn(async move { client.run().await.unwrap() });
+        (tx, sys, task)
+    };
+    let wait_for = |receiver: &Receiver, n: usize| {
+        let requests = receiver.requests.clone();
+        async move {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while requests.lock().unwrap().len() < n {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .expect("timed out waiting for requests")
+        }
+    };
+
+    // NDJSON with gzip and auth, retried past a 503
+    let (url, receiver) = serve(vec![StatusCode::SERVICE_UNAVAILABLE]).await;
+    let (tx, sys, task) = start(json!({
+        "url": url,
+        "token": "s3cr3t",
+        "headers": {"X-Source": "striem"},
+        "gzip": true,
+    }));
+    tx.send(Arc::new(vec![])).unwrap();
+    tx.send(Arc::new(vec![
+        Event::from(json!({"n": 1})),
+        Event::from(json!({"n": 2})),
+    ]))
+    .unwrap();
+    wait_for(&receiver, 2).await;
+    {
+        let requests = receiver.requests.lock().unwrap();
+        // the empty batch was skipped; the retry resent the same body
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].1, requests[1].1);
+        let (headers, body) = &requests[1];
+        assert_eq!(headers["authorization"], "Bearer s3cr3t");
+        assert_eq!(headers["x-source"], "striem");
+        assert_eq!(headers["content-type"], "application/x-ndjson");
+        assert_eq!(headers["content-encoding"], "gzip");
+        let mut text = String::new();
+        GzDecoder::new(&body[..]).read_to_string(&mut text).unwrap();
+        assert_eq!(text, "{\"n\":1}\n{\"n\":2}\n");
+    }
+    assert!(metrics::value("striem_http_output_retries_total", &[]).unwrap_or_default() >= 1.0);
+    sys.send(SysMessage::Shutdown).unwrap();
+    task.await.unwrap();
+
+    // a JSON array, dropped without retrying when rejected
+    let (url, receiver) = serve(vec![StatusCode::BAD_REQUEST]).await;
+    let (tx, sys, task) = start(json!({"url": url, "format": "json"}));
+    tx.send(Arc::new(vec![Event::from(json!({"n": 3}))]))
+        .unwrap();
+    wait_for(&receiver, 1).await;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while metrics::value("striem_http_output_dropped_batches_total", &[]).is_none() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    {
+        let requests = receiver.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].0["content-type"], "application/json");
+        let body: serde_json::Value = serde_json::from_slice(&requests[0].1).unwrap();
+        assert_eq!(body, json!([{"n": 3}]));
+    }
+    sys.send(SysMessage::Shutdown).unwrap();
+    task.await.unwrap();
+
+    // stopping isn't held up waiting for a request slot: the one request
+    // allowed never gets an answer, and the second batch waits for it
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let hung = tokio::spawn(async move {
+        let mut connections = Vec::new();
+        loop {
+            connections.push(listener.accept().await.unwrap());
+        }
+    });
+    let (tx, sys, task) = start(json!({
+        "url": format!("http://{}/ingest", addr),
+        "concurrency": 1,
+        "timeout_secs": 60,
+    }));
+    tx.send(Arc::new(vec![Event::from(json!({"n": 4}))]))
+        .unwrap();
+    tx.send(Arc::new(vec![Event::from(json!({"n": 5}))]))
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    drop(sys);
+    tokio::time::timeout(Duration::from_secs(5), task)
+        .await
+        .expect("HTTP output didn't stop")
+        .unwrap();
+    hung.abort();
+}
+
+#[tokio::test]
+async fn http_listener_ingests_json_and_hec() {
+    use std::io::Write;
+
+    use flate2::{Compression, write::GzEncoder};
+    use reqwest::StatusCode;
+    use striem_config::input::HttpListenerConfig;
+
+    use crate::HttpServer;
+
+    let config: HttpListenerConfig = serde_json::from_value(json!({
+        "address": "127.0.0.1:0",
+        "tokens": ["t0k3n"],
+        "max_body_size": 1024,
+    }))
+    .unwrap();
+    let (channel, mut received) = broadcast::channel(4);
+    let addr = free_addr();
+    let (stop, shutdown) = broadcast::channel(1);
+    let task = tokio::spawn(async move {
+        HttpServer::new(&config, channel)
+            .serve(&addr, shutdown)
+            .await
+            .unwrap();
+    });
+    for _ in 0..100 {
+        if tokio::net::TcpStream::connect(addr).await.is_ok() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let client = reqwest::Client::new();
+    let post = |path: &str, auth: &str, body: Vec<u8>| {
+        client
+            .post(format!("http://{}{}", addr, path))
+            .header("authorization", auth)
+            .body(body)
+    };
+    let gzip = |body: &[u8]| {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        encoder.finish().unwrap()
+    };
+
+    // gzipped HEC, with one event carrying JSON as a string
+    let hec = br#"{"event": "{\"user\": \"alice\"}", "sourcetype": "okta"}{"event": {"n": 2}, "host": "web-1"}"#;
+    let response = post("/services/collector/event", "Splunk t0k3n", gzip(hec))
+        .header("content-encoding", "gzip")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let events = next(&mut received).await;
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].data, json!({"user": "alice"}));
+    assert_eq!(events[0].metadata["source_type"], json!("splunk_hec"));
+    assert_eq!(
+        events[0].metadata["splunk_hec"]["sourcetype"],
+        json!("okta")
+    );
+    assert_eq!(events[0].metadata["splunk_hec_token"], json!("t0k3n"));
+    assert_eq!(events[1].metadata["splunk_hec"]["host"], json!("web-1"));
+
+    // NDJSON and a JSON array on any other path
+    let response = post(
+        "/webhook",
+        "Bearer t0k3n",
+        b"{\"n\": 1}\n{\"n\": 2}\n".to_vec(),
+    )
+    .send()
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let events = next(&mut received).await;
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[1].data, json!({"n": 2}));
+    assert_eq!(events[0].metadata["path"], json!("/webhook"));
+    assert_eq!(events[0].metadata["source_type"], json!("http_server"));
+    assert!(events[0].metadata.contains_key("remote_addr"));
+    post(
+        "/",
+        "Bearer t0k3n",
+        b"[{\"n\": 3}, {\"n\": 4}, {\"n\": 5}]".to_vec(),
+    )
+    .send()
+    .await
+    .unwrap();
+    assert_eq!(next(&mut received).await.len(), 3);
+
+    // refused: a wrong token, a large body and one that only grows large
+    // once decompressed
+    let status = |response: reqwest::Response| response.status();
+    assert_eq!(
+        status(
+            post("/", "Bearer nope", b"{}".to_vec())
+                .send()
+                .await
+                .unwrap()
+        ),
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        status(
+            post("/", "Bearer t0k3n", vec![b' '; 2048])
+                .send()
+                .await
+                .unwrap()
+        ),
+        StatusCode::PAYLOAD_TOO_LARGE
+    );
+    let bomb = gzip(format!("{{\"pad\": \"{}\"}}", "x".repeat(4096)).as_bytes());
+    assert!(bomb.len() < 1024);
+    let response = post("/", "Bearer t0k3n", bomb)
+        .header("content-encoding", "gzip")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(received.is_empty());
+
+    stop.send(SysMessage::Shutdown).unwrap();
+    task.await.unwrap();
+}
+
+#[test]
+fn event_time_round_trips_through_vector() {
+    use chrono::{TimeZone, Utc};
+
+    use crate::event as vector_event;
+
+    let time = Utc.timestamp_millis_opt(1_700_000_000_123).unwrap();
+    let event = Event {
+        time: Some(time),
+        ..Event::from(json!({"message": "hello"}))
+    };
+    let back = Event::from(vector_event::Log::from(&event));
+    assert_eq!(back.time, Some(time));
+    assert_eq!(back.id, event.id);
+    let back = Event::from(vector_event::Log::from(event.clone()));
+    assert_eq!(back.time, Some(time));
+
+    // without an ingest timestamp, the event's own time is used
+    let event = Event::from(json!({"time": 1_600_000_000_000_i64}));
+    assert_eq!(
+        event.time,
+        Some(Utc.timestamp_millis_opt(1_600_000_000_000).unwrap())
+    );
+    let log = vector_event::Log {
+        fields: [("time".to_string(), json!(1_600_000_000_000_i64).into())].into(),
+        ..Default::default()
+    };
+    assert_eq!(Event::from(log).time, event.time);
+    assert_eq!(Event::from(json!({"time": "soon"})).time, None);
+}
+
+#[test]
+fn filter_keeps_findings_by_severity_and_rule() {
+    use striem_common::severity::Severity;
+    use striem_config::output::OutputFilter;
+
+    use crate::Filter;
+
+    let finding = |severity_id: u64, rule: &str| {
+        Event::from(json!({
+            "class_uid": 2004,
+            "severity_id": severity_id,
+            "finding_info": {"analytic": {"uid": rule}},
+        }))
+    };
+    let raw = Event::from(json!({"class_uid": 3002}));
+
+    // no filter forwards everything
+    let all = Filter::new(None);
+    assert!(all.forwards(&finding(1, "a")) && all.forwards(&raw));
+
+    // informational < low < medium < high < critical, by OCSF severity_id
+    let severities = [
+        Severity::Informational,
+        Severity::Low,
+        Severity::Medium,
+        Severity::High,
+        Severity::Critical,
+    ];
+    for (i, min) in severities.iter().enumerate() {
+        let filter = Filter::new(Some(&OutputFilter {
+            min_severity: Some(*min),
+            ..Default::default()
+        }));
+        for (j, severity) in severities.iter().enumerate() {
+            assert_eq!(
+                filter.forwards(&finding(severity.id() as u64, "a")),
+                j >= i,
+                "{} with min_severity {}",
+                severity,
+                min
+            );
+        }
+        // fatal is above critical; unknown and other aren't ranked
+        assert!(filter.forwards(&finding(6, "a")));
+        assert!(!filter.forwards(&finding(0, "a")));
+        assert!(!filter.forwards(&finding(99, "a")));
+        assert!(!filter.forwards(&raw));
+    }
+
+    let filter = Filter::new(Some(&OutputFilter {
+        min_severity: Some(Severity::High),
+        rules: vec!["a".to_string(), "b".to_string()],
+        exclude_rules: vec!["b".to_string()],
+        raw_events: true,
+    }));
+    assert!(filter.forwards(&finding(4, "a")));
+    assert!(!filter.forwards(&finding(4, "b")));
+    assert!(!filter.forwards(&finding(4, "c")));
+    assert!(!filter.forwards(&finding(3, "a")));
+    assert!(filter.forwards(&raw));
+
+    let batch = Arc::new(vec![finding(5, "a"), finding(2, "a"), raw.clone()]);
+    let kept = filter.apply(batch).unwrap();
+    assert_eq!(kept.len(), 2);
+    assert_eq!(kept[0].data["severity_id"], json!(5));
+    assert!(filter.apply(Arc::new(vec![finding(1, "a")])).is_none());
+}
@@ -1,38 +1,101 @@
 //! Core application orchestration module.
 //!
 //! The App struct coordinates all StrIEM subsystems:
-//! - Vector gRPC server for receiving events from Vector pipeline
+//! - Vector gRPC server for receiving events from Vector pipeline, or an
+//!   HTTP/HEC listener receiving them directly
 //! - Detection engine for evaluating Sigma rules on streaming data
 //! - Parquet storage backend for persisting OCSF-normalized events
-//! - Vector client for forwarding detection findings downstream
+//! - Vector or HTTP client for forwarding detection findings downstream
 //! - API server for management interface
 //!
 //! Event flow:
-//! Vector Pipeline → VectorServer → broadcast → [DetectionHandler, ParquetBackend]
+//! Vector Pipeline → VectorServer → broadcast → [enrichment →] [DetectionHandler, ParquetBackend]
 //!                                              ↓
-//!                                    detection findings → VectorClient → downstream
-
-use std::sync::Arc;
+//!                                    detection findings → VectorClient / HttpClient → downstream
+//!
+//! # Shutdown
+//! On `SysMessage::Shutdown` the listener stops first, refusing further
+//! pushes. The subsystems downstream are then stopped one [`Stage`] at a
+//! time, in the order events flow: each is told to shut down only once the
+//! ones before it have finished, and passes on everything already sent to
+//! it before it returns, so nothing accepted is dropped on the way. Storage
+//! finishes its open Parquet files; outputs send their pending batches. The
+//! whole drain is bounded by `shutdown_grace_secs`, after which whatever is
+//! still in flight is abandoned.
+
+use std::{sync::Arc, time::Duration};
 
 use anyhow::{Result, anyhow};
 use arc_swap::ArcSwap;
-use backoff::{ExponentialBackoff, future::retry};
-use log::{debug, error, info, warn};
+use log::{error, info, warn};
 use serde_json::{Map, Value};
-use tokio::sync::{RwLock, broadcast};
+use tokio::{
+    sync::{
+        RwLock,
+        broadcast::{
+            self,
+            error::{RecvError, TryRecvError},
+        },
+    },
+    task::JoinHandle,
+};
 
 use sigmars::{MemBackend, SigmaCollection};
 
-use striem_common::{SysMessage, event::Event};
-use striem_config::{
-    self as config, StrIEMConfig, StringOrList, input::Listener, output::Destination,
+use striem_common::{
+    SysMessage,
+    detection::{OcsfRules, ocsf_rules},
+    event::Event,
+    lists::ReferenceLists,
+    metrics,
+    prelude::DEFAULT_VECTOR_SERVER_CHANNEL_CAPACITY,
+    severity::LevelOverrides,
 };
+use striem_config::{StrIEMConfig, input::Listener, output::Destination};
 
 use striem_api as api;
 use striem_storage as storage;
-use striem_vector::{Client as VectorClient, Server as VectorServer};
+use striem_vector::{
+    Client as VectorClient, Filter, HttpClient, HttpServer, Server as VectorServer, SharedFilter,
+};
+
+use crate::{dedup::Dedup, detection::DetectionHandler};
+
+/// Subsystems stopped together at shutdown, after the stages before them
+struct Stage {
+    name: &'static str,
+    /// System messages for the stage's tasks, relayed from the system
+    /// channel except for shutdown, which is sent when the stage's turn comes
+    sys: broadcast::Sender<SysMessage>,
+    /// Awaited at shutdown
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Stage {
+    fn new(name: &'static str) -> Self {
+        Stage {
+            name,
+            sys: broadcast::channel(1).0,
+            tasks: Vec::new(),
+        }
+    }
 
-use crate::detection::DetectionHandler;
+    /// Relay everything but shutdown from `sys` to the stage's tasks
+    fn relay(&self, mut sys: broadcast::Receiver<SysMessage>) {
+        let stage = self.sys.clone();
+        tokio::spawn(async move {
+            loop {
+                match sys.recv().await {
+                    Ok(SysMessage::Shutdown) | Err(RecvError::Closed) => return,
+                    Ok(msg) => {
+                        stage.send(msg).ok();
+                    }
+                    Err(RecvError::Lagged(_)) => continue,
+                }
+            }
+        });
+    }
+}
 
 /// Main application struct coordinating all StrIEM subsystems.
 /// Uses Arc<RwLock<>> for detections to allow concurrent rule evaluation
@@ -40,15 +103,37 @@ use crate::detection::DetectionHandler;
 pub struct App {
     /// Sigma detection rules with thread-safe access for concurrent evaluation and API updates
     pub detections: Arc<RwLock<SigmaCollection>>,
+    /// Per-rule severity overrides, managed via the API
+    pub levels: LevelOverrides,
+    /// Ids of the rules written against OCSF fields
+    pub ocsf: OcsfRules,
+    /// File and pack each rule was loaded from
+    pub origins: api::RuleOrigins,
+    /// Reference lists for rules, managed via the API
+    pub lists: ReferenceLists,
     pub config: Arc<ArcSwap<StrIEMConfig>>,
     /// gRPC server accepting events from Vector pipeline
     server: VectorServer,
+    /// Parquet storage, until [`App::run`] starts it
+    storage: Option<storage::ParquetBackend>,
     /// Internal broadcast channel for detection findings (separate from upstream Vector events)
     events: broadcast::Sender<Arc<Vec<Event>>>,
+    /// Received events after enrichment, when `enrichment` is configured
+    enriched: Option<broadcast::Sender<Arc<Vec<Event>>>>,
     /// etc
     sys: broadcast::Sender<SysMessage>,
+    /// Enrichment, detection, storage, raw event relays and outputs, in the
+    /// order they are stopped
+    stages: [Stage; 5],
 }
 
+/// Indexes into [`App::stages`]
+const ENRICHMENT: usize = 0;
+const DETECTION: usize = 1;
+const STORAGE: usize = 2;
+const RELAYS: usize = 3;
+const OUTPUTS: usize = 4;
+
 impl App {
     /// Initialize the application with configuration.
     ///
@@ -60,107 +145,268 @@ impl App {
         let broadcast = broadcast::channel::<SysMessage>(1).0;
         // Internal channel capacity tuned for detection findings (typically lower volume than raw events)
         let events = broadcast::channel::<Arc<Vec<Event>>>(64).0;
-        let server = VectorServer::new();
+        let server = match &config.input {
+            // Only storage acknowledges batches, so without it every push would time out
+            Listener::Vector(vector) if vector.acknowledgements && config.storage.is_none() => {
+                warn!("input.vector.acknowledgements requires storage, ignoring");
+                VectorServer::new(&vector.server)
+            }
+            Listener::Vector(vector) if vector.acknowledgements => {
+                VectorServer::new(&vector.server)
+                    .with_acknowledgements(Duration::from_secs(vector.ack_timeout_secs))
+            }
+            Listener::Vector(vector) => VectorServer::new(&vector.server),
+            Listener::Http(_) => VectorServer::default(),
+        };
+        let server = match &config.input {
+            Listener::Vector(vector) => match &vector.limits {
+                Some(limits) => server.with_limits(limits),
+                None => server,
+            },
+            Listener::Http(_) => server,
+        };
+        let server = match &config.input {
+            Listener::Vector(vector) => match &vector.agent_id {
+                Some(agent_id) => server.with_agent_id(agent_id),
+                None => server,
+            },
+            Listener::Http(_) => server,
+        };
+        let server = match &config.input {
+            Listener::Vector(vector) => server.with_compression(vector.compression),
+            Listener::Http(_) => server,
+        };
+        let enriched = config
+            .enrichment
+            .as_ref()
+            .filter(|e| !e.is_empty())
+            .map(|_| {
+                let capacity = match &config.input {
+                    Listener::Vector(vector) => vector.server.channel_capacity,
+                    Listener::Http(_) => DEFAULT_VECTOR_SERVER_CHANNEL_CAPACITY,
+                };
+                broadcast::channel(capacity.max(1)).0
+            });
+        // Sampled on each metrics scrape
+        let monitor = server.monitor();
+        metrics::register_collector(move || {
+            if let Some(stats) = monitor.stats() {
+                metrics::set(
+                    "striem_vector_server_subscribers",
+                    &[],
+                    stats.subscribers as f64,
+                );
+                metrics::set(
+                    "striem_vector_server_queued_batches",
+                    &[],
+                    stats.queued as f64,
+                );
+            }
+        });
 
         let mut detections = SigmaCollection::default();
         let config = Arc::new(ArcSwap::from_pointee(config));
 
-        if let Some(StringOrList::String(path)) = &config.load().detections {
-            debug!("... loading Sigma detection rules from {}", path);
-        } else {
-            debug!("... loading detection rules");
-        }
-        // Support both single directory and multiple directories for detection rules
-        // This enables organizing rules by severity, product, or team ownership
-        let count = match &config.load().detections {
-            Some(config::StringOrList::String(path)) => detections
-                .load_from_dir(path)
-                .map_err(|e| anyhow!(e.to_string())),
-            Some(config::StringOrList::List(paths)) => paths
-                .iter()
-                .map(|path| {
-                    detections
-                        .load_from_dir(path)
-                        .map_err(|e| anyhow!(e.to_string()))
-                })
-                .collect::<Result<Vec<_>>>()
-                .map(|r| r.iter().sum()),
-            None => {
-                warn!("No detection rules loaded");
-                Ok(0)
-            }
-        }?;
+        // fails on unusable schema files, all of them, before anything starts
+        let storage = match config.load().storage {
+            Some(_) => Some(
+                storage::ParquetBackend::new(&config)
+                    .map_err(|e| anyhow!("Failed to create Parquet backend: {}", e))?,
+            ),
+            None => None,
+        };
+
+        // Rule directories may be organized by severity, product or team,
+        // or come from git repositories
+        let (count, origins) = api::load_rules(&config.load(), &mut detections).await?;
+        let origins = Arc::new(ArcSwap::from_pointee(origins));
 
         // MemBackend is required by sigmars for rule compilation and indexing
         // Rules are pre-compiled at startup to avoid runtime compilation overhead
         let mut backend = MemBackend::new().await;
         detections.init(&mut backend).await;
+        let ocsf = Arc::new(ArcSwap::from_pointee(ocsf_rules(&detections)));
 
         let detections = Arc::new(RwLock::new(detections));
 
         info!("... loaded {} Sigma detections", count);
         Ok(App {
             detections,
+            levels: LevelOverrides::default(),
+            ocsf,
+            origins,
+            lists: ReferenceLists::default(),
             config,
             server,
+            storage,
             sys: broadcast,
             events,
+            enriched,
+            stages: [
+                Stage::new("enrichment"),
+                Stage::new("detection"),
+                Stage::new("storage"),
+                Stage::new("output relays"),
+                Stage::new("outputs"),
+            ],
         })
     }
 
     pub async fn run(&mut self) -> Result<()> {
         self.config_watch().await;
+        for stage in &self.stages {
+            stage.relay(self.sys.subscribe());
+        }
+
+        if let Some(enriched) = &self.enriched {
+            info!("... initializing event enrichment");
+            let task = tokio::spawn(crate::enrich::run(
+                self.config.clone(),
+                self.server.subscribe().await?,
+                enriched.clone(),
+                self.stages[ENRICHMENT].sys.subscribe(),
+            ));
+            self.stages[ENRICHMENT].tasks.push(task);
+        }
 
         let config = self.config.load();
-        if let Some(_) = self.config.load().storage {
+        if let Some(writer) = self.storage.take() {
             info!("... initializing Parquet storage handler");
-            self.run_parquet().await?;
+            let task = self.run_parquet(writer).await?;
+            self.stages[STORAGE].tasks.push(task);
         }
 
         // Only spawn detection handler if rules are configured
         // Allows running as a pure data pipeline without detection overhead
         if config.detections.is_some() && self.detections.read().await.len() > 0 {
             info!("... initializing detection handler");
-            let src = self.server.subscribe().await?;
+            let src = self.received().await?;
             let dest = self.events.clone();
-            let mut detection_handler =
-                DetectionHandler::new(src, dest, self.detections.clone(), self.sys.subscribe());
-
-            tokio::spawn(async move {
+            let dedup = config.detections.as_ref().and_then(Dedup::new);
+            let mut detection_handler = DetectionHandler::new(
+                src,
+                dest,
+                self.detections.clone(),
+                self.levels.clone(),
+                dedup,
+                self.stages[DETECTION].sys.subscribe(),
+            )
+            .with_workers(config.detections.as_ref().map_or(1, |d| d.workers))
+            .with_lists(self.lists.clone())
+            .with_ocsf_rules(self.ocsf.clone())
+            .with_config(self.config.clone());
+
+            let task = tokio::spawn(async move {
                 detection_handler.run().await;
             });
+            self.stages[DETECTION].tasks.push(task);
         }
 
         if config.api.enabled {
             info!("... initializing API server and Vector configuration");
             let broadcast = self.sys.clone();
-            let detections = self.detections.clone();
+            let shared = api::Shared {
+                detections: self.detections.clone(),
+                ocsf: self.ocsf.clone(),
+                levels: self.levels.clone(),
+                origins: self.origins.clone(),
+                lists: self.lists.clone(),
+                findings: Some(self.events.clone()),
+                received: self.server.sender().ok(),
+            };
             let config = self.config.clone();
             tokio::spawn(async move {
-                api::serve(&config, detections, broadcast)
+                api::serve(&config, shared, broadcast)
                     .await
                     .expect("API server failed");
             });
         }
 
-        if let Some(Destination::Vector(ref vector)) = config.output {
-            info!("... initializing Vector output to {}", vector.cfg.url());
-            self.run_vector(vector).await?;
+        // Each output subscribes separately, so a slow or failing one
+        // doesn't hold up the others
+        for (index, output) in config.outputs.iter().enumerate() {
+            let filter = self.output_filter(index);
+            let raw = output.filter().is_some_and(|f| f.raw_events);
+            let task = match output {
+                Destination::Vector(vector) => {
+                    info!("... initializing Vector output to {}", vector.cfg.url());
+                    self.run_vector(vector, filter, raw).await?
+                }
+                Destination::Http(http) => {
+                    info!("... initializing HTTP output to {}", http.cfg.url());
+                    self.run_http(http, filter, raw).await?
+                }
+            };
+            self.stages[OUTPUTS].tasks.push(task);
         }
 
         let shutdown = self.sys.subscribe();
-        if let Listener::Vector(ref vector) = config.input {
-            info!("... listening for Vector events on {}", vector.url());
-            self.server.serve(&vector.address(), shutdown).await?;
+        match config.input {
+            Listener::Vector(ref vector) => {
+                info!("... listening for Vector events on {}", vector.cfg.url());
+                self.server
+                    .serve(&vector.cfg.try_address()?, shutdown)
+                    .await?;
+            }
+            // Published on the Vector server's channel, which detection and
+            // storage are already subscribed to
+            Listener::Http(ref http) => {
+                info!("... listening for HTTP events on {}", http.cfg.url());
+                HttpServer::new(http, self.server.sender()?)
+                    .serve(&http.cfg.try_address()?, shutdown)
+                    .await?;
+            }
         }
 
+        self.drain().await;
         Ok(())
     }
 
+    /// Stop the stages in order, each once the ones before it are done,
+    /// within `shutdown_grace_secs` overall
+    async fn drain(&mut self) {
+        let grace = self.config.load().shutdown_grace_secs;
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(grace);
+        let mut expired = false;
+        for stage in self.stages.iter_mut() {
+            let tasks = std::mem::take(&mut stage.tasks);
+            if tasks.is_empty() {
+                continue;
+            }
+            info!("... stopping {}", stage.name);
+            stage.sys.send(SysMessage::Shutdown).ok();
+            // later stages are still told, but no longer waited for
+            if expired {
+                continue;
+            }
+            let stopped = tokio::time::timeout_at(deadline, futures::future::join_all(tasks));
+            if stopped.await.is_err() {
+                warn!(
+                    "{} did not stop within shutdown_grace_secs ({}s), events in flight may be lost",
+                    stage.name, grace
+                );
+                expired = true;
+            }
+        }
+    }
+
+    /// Received events, enriched when `enrichment` is configured
+    async fn received(&self) -> Result<broadcast::Receiver<Arc<Vec<Event>>>> {
+        match &self.enriched {
+            Some(enriched) => Ok(enriched.subscribe()),
+            None => self.server.subscribe().await,
+        }
+    }
+
     pub fn update_channel(&self) -> broadcast::Sender<SysMessage> {
         self.sys.clone()
     }
 
+    /// The running configuration, replaced on each reload
+    pub fn config(&self) -> Arc<ArcSwap<StrIEMConfig>> {
+        self.config.clone()
+    }
+
     /// Initialize Parquet storage backend with dual subscription model.
     ///
     /// # Channel Architecture
@@ -169,53 +415,154 @@ impl App {
     ///
     /// Both streams are written to Parquet, but routed to different files based on class_uid.
     /// This allows querying raw data and detections independently via DuckDB.
-    async fn run_parquet(&self) -> Result<()> {
-        let writer =
-            storage::ParquetBackend::new(&self.config).expect("Failed to create Parquet backend");
-
-        let server_rx = self.server.subscribe().await?;
+    ///
+    /// Returns the backend's task, which finishes the open files at shutdown.
+    async fn run_parquet(&self, writer: storage::ParquetBackend) -> Result<JoinHandle<()>> {
+        let server_rx = self.received().await?;
         let event_rx = self.events.subscribe();
-        let shutdown = self.sys.subscribe();
-        tokio::spawn(async move {
-            writer.run(server_rx, event_rx, shutdown).await;
-        });
-        Ok(())
+        let shutdown = self.stages[STORAGE].sys.subscribe();
+        Ok(writer.run(server_rx, event_rx, shutdown).await)
     }
     /// Initialize Vector client for forwarding detection findings downstream.
     ///
     /// # Retry Strategy
-    /// Uses exponential backoff to handle transient network failures or Vector restarts.
-    /// Only subscribes to internal channel (detection findings), not raw upstream events.
-    /// This creates a detection-only output stream for downstream analysis or alerting.
+    /// The client is supervised (see [`VectorClient::supervise`]): it connects
+    /// with exponential backoff, reconnects and resends on its own once
+    /// running, and is started over on a fresh subscription if it fails,
+    /// until shutdown.
+    /// Subscribes to detection findings only, unless `raw` adds received events.
     async fn run_vector(
-        &self,
+        &mut self,
         vector: &striem_config::output::VectorDestinationConfig,
-    ) -> Result<()> {
+        filter: SharedFilter,
+        raw: bool,
+    ) -> Result<JoinHandle<()>> {
         let url = vector.cfg.url();
-        let rx = self.events.subscribe();
-        let shutdown = self.sys.subscribe();
-        tokio::spawn(async move {
-            // Retry indefinitely with exponential backoff until connection succeeds
-            // This is critical for resilience during Vector restarts or network issues
-            let mut sink = retry(ExponentialBackoff::default(), || async {
-                VectorClient::new(&url, rx.resubscribe(), shutdown.resubscribe())
-                    .await
-                    .map_err(|e| {
-                        warn!("Failed to connect to Vector at {}: {}", url, e);
-                        e.into()
-                    })
-            })
-            .await
-            .expect("Failed to connect to Vector client");
+        let compression = vector.compression;
+        let events = self.output_events(raw).await?;
+        let shutdown = self.stages[OUTPUTS].sys.subscribe();
+        Ok(tokio::spawn(async move {
+            VectorClient::supervise(&url, &events, filter, compression, shutdown).await;
+            info!("Vector output to {} stopped", url);
+        }))
+    }
 
-            info!("... connected to downstream Vector at {}", url);
+    /// Initialize the HTTP output for detection findings.
+    ///
+    /// Unlike the Vector output there is no connection to establish up front;
+    /// each batch is retried on its own (see [`HttpClient`]).
+    async fn run_http(
+        &mut self,
+        http: &striem_config::output::HttpDestinationConfig,
+        filter: SharedFilter,
+        raw: bool,
+    ) -> Result<JoinHandle<()>> {
+        let events = self.output_events(raw).await?;
+        let shutdown = self.stages[OUTPUTS].sys.subscribe();
+        let url = http.cfg.url();
+        let mut sink = HttpClient::new(http, events.subscribe(), shutdown)?.with_filter(filter);
+        Ok(tokio::spawn(async move {
+            if let Err(e) = sink.run().await {
+                error!("HTTP output to {} failed: {}", url, e);
+            }
+        }))
+    }
 
-            sink.run().await.expect("Vector client failed");
+    /// Filter of the output at `index`, rebuilt from the configuration on
+    /// each reload
+    fn output_filter(&self, index: usize) -> SharedFilter {
+        let filter = Filter::shared(
+            self.config
+                .load()
+                .outputs
+                .get(index)
+                .and_then(|o| o.filter()),
+        );
+        let config = self.config.clone();
+        let mut sys = self.sys.subscribe();
+        tokio::spawn({
+            let filter = filter.clone();
+            async move {
+                loop {
+                    match sys.recv().await {
+                        Ok(SysMessage::Shutdown) | Err(RecvError::Closed) => return,
+                        Ok(SysMessage::Reload) => {
+                            if let Some(output) = config.load().outputs.get(index) {
+                                filter.store(Arc::new(Filter::new(output.filter())));
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
         });
-        Ok(())
+        filter
     }
 
+    /// Events for an output: detection findings, and with `raw` received
+    /// events too, relayed onto a channel of its own
+    async fn output_events(&mut self, raw: bool) -> Result<broadcast::Sender<Arc<Vec<Event>>>> {
+        if !raw {
+            return Ok(self.events.clone());
+        }
+        let relay = broadcast::channel(DEFAULT_VECTOR_SERVER_CHANNEL_CAPACITY).0;
+        let mut findings = self.events.subscribe();
+        let mut received = self.received().await?;
+        let mut sys = self.stages[RELAYS].sys.subscribe();
+        let task = tokio::spawn({
+            let relay = relay.clone();
+            async move {
+                loop {
+                    let batch = tokio::select! {
+                        batch = findings.recv() => batch,
+                        batch = received.recv() => batch,
+                        msg = sys.recv() => match msg {
+                            Ok(SysMessage::Shutdown) => {
+                                // pass on what the stages before have sent
+                                for rx in [&mut findings, &mut received] {
+                                    loop {
+                                        match rx.try_recv() {
+                                            Ok(batch) => {
+                                                relay.send(batch).ok();
+                                            }
+                                            Err(TryRecvError::Lagged(_)) => continue,
+                                            Err(_) => break,
+                                        }
+                                    }
+                                }
+                                return;
+                            }
+                            Err(RecvError::Closed) => return,
+                            _ => continue,
+                        },
+                    };
+                    match batch {
+                        // nothing may be subscribed between client restarts
+                        Ok(batch) => {
+                            relay.send(batch).ok();
+                        }
+                        Err(RecvError::Lagged(n)) => {
+                            metrics::increment(
+                                "striem_broadcast_lagged_total",
+                                &[("subscriber", "output_relay")],
+                                n,
+                            );
+                        }
+                        Err(RecvError::Closed) => return,
+                    }
+                }
+            }
+        });
+        self.stages[RELAYS].tasks.push(task);
+        Ok(relay)
+    }
+
+    /// Apply configuration updates from the API and, with `watch_config`,
+    /// from the configuration files (see [`crate::watch`])
     async fn config_watch(&self) {
+        if self.config.load().watch_config {
+            tokio::spawn(crate::watch::run(self.config.clone(), self.sys.clone()));
+        }
         let mut rx = self.sys.subscribe();
         let tx = self.sys.clone();
         let locked = self.config.clone();
@@ -228,11 +575,13 @@ impl App {
                     }
                     Ok(SysMessage::Update(updated)) => {
                         info!("updating configuration...");
-                        // Apply updates to local config file and in-memory config
-                        let mut current = Self::get_local_config().await;
-                        for (k, v) in updated.iter() {
-                            current.insert(k.clone(), v.clone());
-                        }
+                        // Apply updates to local config file and in-memory config,
+                        // keeping saved keys the update doesn't mention
+                        let mut current = Value::Object(Self::get_local_config().await);
+                        api::merge(&mut current, Value::Object(*updated));
+                        let Value::Object(current) = current else {
+                            continue;
+                        };
                         if Self::set_local_config(&current)
                             .await
                             .inspect_err(|e| {
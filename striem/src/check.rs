@@ -0,0 +1,80 @@
+//! Configuration check for CI: `striem check [-c FILE]...`.
+//!
+//! Loads the configuration as the daemon would, runs
+//! [`StrIEMConfig::validate_deep`], parses every rule file as a Sigma
+//! rule, and reads the storage schema directory as storage would at
+//! startup (see [`striem_storage::schema::check_schemas`]). Errors,
+//! warnings and a rule count go to stdout; the process exits non-zero if
+//! there were any errors.
+
+use serde::Deserialize;
+use sigmars::SigmaRule;
+use striem_config::{StrIEMConfig, validate::ValidateOptions};
+
+/// Check `config`, printing a report; `true` when there are no errors
+pub(crate) fn run(config: &StrIEMConfig) -> bool {
+    let mut report = config.validate_deep(ValidateOptions::default());
+
+    let mut parsed = 0;
+    for file in &report.rule_files.clone() {
+        let Ok(contents) = std::fs::read_to_string(file) else {
+            // already reported by validate_deep
+            continue;
+        };
+        let rules: Result<Vec<SigmaRule>, _> = serde_yaml::Deserializer::from_str(&contents)
+            .map(SigmaRule::deserialize)
+            .collect();
+        match rules {
+            Ok(_) => parsed += 1,
+            Err(e) => {
+                // files that aren't YAML are already reported
+                let prefix = format!("{}:", file.display());
+                if !report.errors.iter().any(|i| i.message.starts_with(&prefix)) {
+                    report.error("detections", format!("{} not a Sigma rule: {}", prefix, e));
+                }
+            }
+        }
+    }
+
+    if let Some(storage) = &config.storage
+        && storage.schema.exists()
+    {
+        // a missing directory is already reported
+        let schemas = striem_storage::schema::check_schemas(&storage.schema);
+        for error in &schemas.errors {
+            report.error("storage", format!("invalid schema {}", error));
+        }
+        for (path, name) in &schemas.unknown {
+            report.warning(
+                "storage",
+                format!(
+                    "schema {} is for '{}', not an OCSF class; it's skipped",
+                    path.display(),
+                    name
+                ),
+            );
+        }
+    }
+
+    for file in &config.files {
+        println!("config: {}", file.display());
+    }
+    for issue in &report.errors {
+        println!("error: {}", issue);
+    }
+    for issue in &report.warnings {
+        println!("warning: {}", issue);
+    }
+    println!(
+        "rule files: {} parsed, {} failed",
+        parsed,
+        report.rule_files.len() - parsed
+    );
+
+    if report.is_ok() {
+        println!("configuration OK");
+    } else {
+        println!("configuration has {} error(s)", report.errors.len());
+    }
+    report.is_ok()
+}
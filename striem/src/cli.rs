@@ -0,0 +1,145 @@
+//! Command line.
+//!
+//! - `striem run [-c FILE]... [--listen ADDR] [--api-port N] [--log-level L]`
+//! - `striem check [-c FILE]...` - Validate the configuration; see [`crate::check`]
+//! - `striem export-vector-config [-c FILE]...` - Print the generated Vector
+//!   configuration as TOML
+//! - `striem replay [-c FILE]... --class CLASS --start TIME [--end TIME]
+//!   [--rule ID]... [--dry-run]` - Re-run detection rules over stored events;
+//!   see [`striem_api::replay`]
+//! - `striem version`
+//!
+//! Configuration files are read in order, then `striem.json` from
+//! `STRIEM_APPDATA` or the working directory, then `STRIEM` environment
+//! variables. `--listen` and `--api-port` are applied last and win over all
+//! of them.
+//!
+//! `striem config.yaml`, config files without a subcommand, still runs
+//! StrIEM with a warning; it will be removed in the next release.
+
+use std::{net::SocketAddr, path::PathBuf, sync::OnceLock};
+
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand};
+use striem_config::{StrIEMConfig, input::Listener};
+
+static ARGS: OnceLock<RunArgs> = OnceLock::new();
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "striem",
+    version,
+    about = "Streaming Intelligence and Event Management",
+    args_conflicts_with_subcommands = true
+)]
+pub(crate) struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Configuration files; deprecated, use `striem run -c FILE`
+    #[arg(hide = true)]
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum Command {
+    /// Run StrIEM
+    Run(RunArgs),
+    /// Validate the configuration and exit
+    Check(ConfigArgs),
+    /// Print the version
+    Version,
+    /// Print the generated Vector configuration as TOML
+    ExportVectorConfig(ConfigArgs),
+    /// Run detection rules over stored events of one class
+    Replay(ReplayArgs),
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub(crate) struct ConfigArgs {
+    /// Configuration file; later files override earlier ones
+    #[arg(short = 'c', long = "config", value_name = "FILE")]
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub(crate) struct RunArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    /// Address to receive events on, replacing the `input` address
+    #[arg(long, value_name = "ADDR")]
+    pub listen: Option<SocketAddr>,
+    /// Port for the API, replacing the `api` port
+    #[arg(long, value_name = "N")]
+    pub api_port: Option<u16>,
+    /// Log filter, e.g. `debug` or `info,striem=debug`; replaces RUST_LOG
+    /// and `logging.level`
+    #[arg(long, value_name = "L")]
+    pub log_level: Option<String>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub(crate) struct ReplayArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    /// OCSF class name, e.g. `authentication`
+    #[arg(long)]
+    pub class: String,
+    /// RFC 3339 time to replay from
+    #[arg(long, value_name = "TIME")]
+    pub start: DateTime<Utc>,
+    /// RFC 3339 time to replay to; defaults to now
+    #[arg(long, value_name = "TIME")]
+    pub end: Option<DateTime<Utc>>,
+    /// Rule id to evaluate; every rule when not given
+    #[arg(long = "rule", value_name = "ID")]
+    pub rules: Vec<String>,
+    /// Count findings per rule instead of storing them
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+impl Cli {
+    /// Whether StrIEM was started the old way, with bare config files
+    pub fn is_legacy(&self) -> bool {
+        self.command.is_none() && !self.files.is_empty()
+    }
+
+    /// The command to run; no subcommand runs StrIEM
+    pub fn command(self) -> Command {
+        self.command.unwrap_or_else(|| {
+            Command::Run(RunArgs {
+                config: ConfigArgs { files: self.files },
+                ..Default::default()
+            })
+        })
+    }
+}
+
+/// Keep `args` for [`crate::config`], including reloads
+pub(crate) fn set_args(args: RunArgs) {
+    let _ = ARGS.set(args);
+}
+
+pub(crate) fn args() -> &'static RunArgs {
+    ARGS.get_or_init(Default::default)
+}
+
+/// Configuration keys set by `args`, given the configuration they apply to
+pub(crate) fn overrides(args: &RunArgs, config: &StrIEMConfig) -> Vec<(String, String)> {
+    let mut overrides = Vec::new();
+    // a configured `port` wins over the address's, so it's set as well
+    if let Some(listen) = args.listen {
+        let key = match config.input {
+            Listener::Vector(_) => "input.vector",
+            Listener::Http(_) => "input.http",
+        };
+        overrides.push((format!("{}.address", key), listen.to_string()));
+        overrides.push((format!("{}.port", key), listen.port().to_string()));
+    }
+    if let Some(port) = args.api_port {
+        let address = SocketAddr::new(config.api.host.address().ip(), port);
+        overrides.push(("api.address".to_string(), address.to_string()));
+        overrides.push(("api.port".to_string(), port.to_string()));
+    }
+    overrides
+}
@@ -0,0 +1,213 @@
+//! Detection findings deduplication.
+//!
+//! Collapses repeated matches of the same rule within a time window.
+//! The first finding for a key is emitted immediately; further matches
+//! only bump a counter. When the window closes, a single roll-up finding
+//! is emitted carrying the OCSF `count`, `start_time`, and `end_time`
+//! attributes (only if there were repeats).
+//!
+//! Keys are the rule id plus a hash of the configured observable fields
+//! from the matched event, so e.g. the same rule firing for two different
+//! source IPs is not collapsed when `src_endpoint.ip` is a dedup field.
+
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{Value, json};
+use striem_common::event::Event;
+use striem_config::detections::DetectionsConfig;
+
+struct Entry {
+    opened: Instant,
+    window: Duration,
+    count: u64,
+    start_time: i64,
+    end_time: i64,
+    finding: Event,
+}
+
+pub(crate) struct Dedup {
+    default_window: Option<Duration>,
+    rules: HashMap<String, Duration>,
+    fields: Vec<String>,
+    max_entries: usize,
+    entries: HashMap<(String, u64), Entry>,
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+impl Dedup {
+    /// Build the dedup stage from config, or `None` if no window is configured
+    pub(crate) fn new(config: &DetectionsConfig) -> Option<Self> {
+        if !config.dedup_enabled() {
+            return None;
+        }
+        Some(Self {
+            default_window: config
+                .dedup_window_secs
+                .filter(|w| *w > 0)
+                .map(Duration::from_secs),
+            rules: config
+                .dedup_rules
+                .iter()
+                .map(|(id, w)| (id.clone(), Duration::from_secs(*w)))
+                .collect(),
+            fields: config.dedup_fields.clone(),
+            max_entries: config.dedup_max_entries.max(1),
+            entries: HashMap::new(),
+        })
+    }
+
+    /// Apply new settings from `config`. Open windows keep the length they
+    /// were opened with; new windows use the new settings.
+    pub(crate) fn reconfigure(&mut self, config: &DetectionsConfig) {
+        if let Some(updated) = Self::new(config) {
+            *self = Self {
+                entries: std::mem::take(&mut self.entries),
+                ..updated
+            };
+        }
+    }
+
+    /// Close every open window, returning roll-up findings for those that
+    /// saw repeats
+    pub(crate) fn drain(&mut self) -> Vec<Event> {
+        self.entries
+            .drain()
+            .filter_map(|(_, entry)| Self::rollup(entry))
+            .collect()
+    }
+
+    fn window(&self, rule_id: &str) -> Option<Duration> {
+        match self.rules.get(rule_id) {
+            Some(w) if w.is_zero() => None,
+            Some(w) => Some(*w),
+            None => self.default_window,
+        }
+    }
+
+    fn key(&self, rule_id: &str, event: &Event) -> (String, u64) {
+        let mut hasher = DefaultHasher::new();
+        for field in &self.fields {
+            field
+                .split('.')
+                .try_fold(&event.data, |v, k| v.get(k))
+                .map(|v| v.to_string())
+                .hash(&mut hasher);
+        }
+        (rule_id.to_string(), hasher.finish())
+    }
+
+    /// Record a finding for `rule_id` raised by `event`.
+    ///
+    /// Returns the finding if it should be emitted now (`None` if it was
+    /// folded into an open window), along with any roll-ups for windows
+    /// that had to be closed early to make room.
+    pub(crate) fn observe(
+        &mut self,
+        rule_id: &str,
+        event: &Event,
+        finding: Event,
+        now: Instant,
+    ) -> (Option<Event>, Vec<Event>) {
+        let Some(window) = self.window(rule_id) else {
+            return (Some(finding), Vec::new());
+        };
+
+        let key = self.key(rule_id, event);
+        let time = now_millis();
+
+        if let Some(entry) = self.entries.get_mut(&key)
+            && now.duration_since(entry.opened) < entry.window
+        {
+            entry.count += 1;
+            entry.end_time = time;
+            return (None, Vec::new());
+        }
+
+        // A stale entry for this key is closed out before opening a new window
+        let mut evicted = self
+            .entries
+            .remove(&key)
+            .and_then(Self::rollup)
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        if self.entries.len() >= self.max_entries
+            && let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.opened)
+                .map(|(k, _)| k.clone())
+        {
+            evicted.extend(self.entries.remove(&oldest).and_then(Self::rollup));
+        }
+
+        self.entries.insert(
+            key,
+            Entry {
+                opened: now,
+                window,
+                count: 1,
+                start_time: time,
+                end_time: time,
+                finding: finding.clone(),
+            },
+        );
+        (Some(finding), evicted)
+    }
+
+    /// Close all windows that have elapsed, returning roll-up findings for
+    /// those that saw repeats.
+    pub(crate) fn expire(&mut self, now: Instant) -> Vec<Event> {
+        let expired = self
+            .entries
+            .iter()
+            .filter(|(_, e)| now.duration_since(e.opened) >= e.window)
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+
+        expired
+            .into_iter()
+            .filter_map(|k| self.entries.remove(&k))
+            .filter_map(Self::rollup)
+            .collect()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Build the roll-up finding for a closed window; single hits were
+    /// already emitted in full and produce nothing.
+    fn rollup(entry: Entry) -> Option<Event> {
+        if entry.count <= 1 {
+            return None;
+        }
+        let mut rollup = Event {
+            data: entry.finding.data,
+            metadata: entry.finding.metadata,
+            time: entry.finding.time,
+            ..Default::default()
+        };
+        if rollup.data.is_object() {
+            rollup.data["metadata"]["uid"] = json!(rollup.id.to_string());
+            rollup.data["count"] = json!(entry.count);
+            rollup.data["start_time"] = json!(entry.start_time);
+            rollup.data["end_time"] = json!(entry.end_time);
+        }
+        rollup
+            .metadata
+            .insert("dedup".to_string(), Value::Bool(true));
+        Some(rollup)
+    }
+}
@@ -6,27 +6,72 @@
 //! # Event Processing
 //! 1. Receive batched events from Vector server
 //! 2. Extract logsource metadata for rule filtering
-//! 3. Use raw_data field if available (pre-normalization log)
-//! 4. Evaluate against matching Sigma rules
+//! 3. Use raw_data field if available (pre-normalization log) for vendor
+//!    rules, and the normalized event for OCSF rules
+//! 4. Tag it with the reference lists its values are in (see
+//!    [`striem_common::lists`]) and evaluate against matching Sigma rules
 //! 5. Generate detection finding with correlation to original event
+//! 6. Optionally collapse repeated findings (see [`crate::dedup`])
+//!
+//! Steps 2-5 can run on a pool of workers (`detections.workers`); step 6 and
+//! emitting findings always happen in the handler.
+//!
+//! On `SysMessage::Reload` the handler re-reads `detections.workers` and the
+//! dedup settings; in-flight events are finished first. Only
+//! `SysMessage::Shutdown` (or the system channel closing) stops it. On
+//! shutdown the batches already received are evaluated, the workers
+//! finish theirs and open dedup windows are emitted before it returns.
 
 use anyhow::Result;
 
-use log::{error, info, trace};
-use serde_json::{Value, json};
+use arc_swap::ArcSwap;
+use log::{error, info, trace, warn};
 use sigmars::SigmaCollection;
-use striem_common::{SysMessage, event::Event};
+use striem_common::{
+    SysMessage,
+    detection::{self, Matches, OcsfRules},
+    event::Event,
+    lists::ReferenceLists,
+    metrics,
+    severity::LevelOverrides,
+};
+use striem_config::StrIEMConfig;
 
+use std::ops::Range;
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tokio::sync::broadcast;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{
+    self,
+    error::{RecvError, TryRecvError},
+};
+use tokio::sync::{Mutex, RwLock, mpsc};
+
+use crate::dedup::Dedup;
+
+/// A slice of a source batch for a worker to evaluate
+struct Job {
+    events: Arc<Vec<Event>>,
+    range: Range<usize>,
+}
+
+/// A worker's findings for `events[index]`
+type Evaluated = (Arc<Vec<Event>>, usize, Result<Matches>);
+
+/// Job queue and results channel of a running worker pool
+type Pool = (mpsc::Sender<Job>, mpsc::UnboundedReceiver<Evaluated>);
 
 /// Background task processing events through the Sigma detection engine.
 pub(crate) struct DetectionHandler {
     src: broadcast::Receiver<Arc<Vec<Event>>>,
     dest: broadcast::Sender<Arc<Vec<Event>>>,
     rules: Arc<RwLock<SigmaCollection>>,
+    levels: LevelOverrides,
+    ocsf: OcsfRules,
+    lists: ReferenceLists,
+    dedup: Option<Dedup>,
     shutdown: broadcast::Receiver<SysMessage>,
+    workers: usize,
+    config: Option<Arc<ArcSwap<StrIEMConfig>>>,
 }
 
 impl DetectionHandler {
@@ -34,14 +79,81 @@ impl DetectionHandler {
         src: broadcast::Receiver<Arc<Vec<Event>>>,
         dest: broadcast::Sender<Arc<Vec<Event>>>,
         rules: Arc<RwLock<SigmaCollection>>,
+        levels: LevelOverrides,
+        dedup: Option<Dedup>,
         shutdown: broadcast::Receiver<SysMessage>,
     ) -> Self {
         Self {
             src,
             dest,
             rules,
+            levels,
+            ocsf: Default::default(),
+            lists: Default::default(),
+            dedup,
             shutdown,
+            workers: 1,
+            config: None,
+        }
+    }
+
+    /// Evaluate rules on `workers` tasks instead of in the handler.
+    ///
+    /// Findings still leave through the handler (and deduplication), so
+    /// each event's findings are unchanged, but findings of different
+    /// events may be emitted out of order.
+    pub(crate) fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Reference lists to tag events with before evaluating them
+    pub(crate) fn with_lists(mut self, lists: ReferenceLists) -> Self {
+        self.lists = lists;
+        self
+    }
+
+    /// Ids of the rules to evaluate against normalized OCSF events
+    pub(crate) fn with_ocsf_rules(mut self, ocsf: OcsfRules) -> Self {
+        self.ocsf = ocsf;
+        self
+    }
+
+    /// Configuration to re-read detection settings from on reload
+    pub(crate) fn with_config(mut self, config: Arc<ArcSwap<StrIEMConfig>>) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Spawn the worker pool, returning the job queue and the results
+    /// channel. Workers exit once the job queue is dropped.
+    fn spawn_workers(&self) -> Pool {
+        let (jobs_tx, jobs_rx) = mpsc::channel::<Job>(self.workers * 2);
+        let (results_tx, results_rx) = mpsc::unbounded_channel();
+        let jobs_rx = Arc::new(Mutex::new(jobs_rx));
+        for _ in 0..self.workers {
+            let jobs = jobs_rx.clone();
+            let results = results_tx.clone();
+            let rules = self.rules.clone();
+            let levels = self.levels.clone();
+            let ocsf = self.ocsf.clone();
+            let lists = self.lists.clone();
+            tokio::spawn(async move {
+                loop {
+                    let Some(job) = jobs.lock().await.recv().await else {
+                        return;
+                    };
+                    for index in job.range {
+                        let matches =
+                            evaluate(&rules, &ocsf, &levels, &lists, &job.events[index]).await;
+                        if results.send((job.events.clone(), index, matches)).is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
         }
+        (jobs_tx, results_rx)
     }
 
     /// Main event processing loop with graceful shutdown support.
@@ -49,123 +161,257 @@ impl DetectionHandler {
     /// # Error Handling
     /// Individual event processing errors are logged but don't halt the loop.
     /// This ensures one malformed event doesn't stop detection for all events.
+    /// Likewise, batches missed by lagging behind the source channel are
+    /// counted in `striem_broadcast_lagged_total` and skipped.
+    ///
+    /// # Workers
+    /// With more than one worker, each batch is split into one slice per
+    /// worker and evaluated in parallel; see [`DetectionHandler::with_workers`].
     pub(crate) async fn run(&mut self) {
+        let mut pool = (self.workers > 1).then(|| self.spawn_workers());
+        // Closes elapsed dedup windows; ticks are no-ops when dedup is disabled
+        let mut flush = tokio::time::interval(Duration::from_secs(1));
         loop {
             tokio::select! {
-                msg = self.shutdown.recv() => {
-                    if let Ok(SysMessage::Shutdown) = msg {
-                            info!("Detection worker shutting down...");
-                            return;
-                    } else if msg.is_err() {
+                msg = self.shutdown.recv() => match msg {
+                    Ok(SysMessage::Shutdown) => {
+                        info!("Detection worker shutting down...");
+                        self.drain(pool).await;
+                        return;
+                    }
+                    Ok(SysMessage::Reload) => self.reload(&mut pool).await,
+                    // Updates are applied by the config watcher, which then
+                    // broadcasts a reload
+                    Ok(SysMessage::Update(_)) => continue,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => {
                         info!("Shutdown channel closed, exiting detection worker...");
                         return;
                     }
                 },
-                result = self.src.recv() => {
-                    if let Ok(events) = result {
-                        // Process each event independently to isolate failures
-                        for event in events.iter() {
-                            if let Err(e) = self.apply(event).await {
-                                error!("error applying detection rules: {}", e);
-                            }
+                result = self.src.recv() => match result {
+                    Ok(events) => {
+                        if !self.process(&pool, events).await {
+                            return;
                         }
-                    } else {
+                    }
+                    // Falling behind skips the missed batches; detection carries on
+                    Err(RecvError::Lagged(n)) => lagged(n),
+                    Err(RecvError::Closed) => {
                         info!("source channel closed");
                         return;
                     }
+                },
+                Some((events, index, matches)) = async {
+                    match pool.as_mut() {
+                        Some((_, results)) => results.recv().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    self.emit(&events[index], matches);
+                },
+                _ = flush.tick() => {
+                    if let Some(dedup) = self.dedup.as_mut() {
+                        let rollups = dedup.expire(Instant::now());
+                        if !rollups.is_empty() {
+                            trace!("emitting {} deduplicated findings", rollups.len());
+                            self.send(rollups);
+                        }
+                    }
                 }
             }
         }
     }
 
-    /// Evaluate event against Sigma rules and emit detection findings.
-    ///
-    /// # Raw Data Handling
-    /// If event is OCSF-normalized (metadata.ocsf = true) with raw_data field,
-    /// rules are evaluated against the original vendor log format.
-    /// This allows Sigma rules written for vendor formats to work with normalized data.
-    ///
-    /// # Performance Consideration
-    /// Only acquires read lock on rules collection, allowing concurrent detection
-    /// across multiple events. Lock is explicitly dropped after matching to avoid
-    /// holding during detection finding generation.
-    async fn apply(&self, event: &Event) -> Result<()> {
-        // Extract logsource for rule filtering (e.g., windows/sysmon, aws/cloudtrail)
-        let filter = event
-            .metadata
-            .get("logsource")
-            .map(|v| sigmars::event::LogSource::from(v.clone()))
-            .unwrap_or_default();
-
-        // For OCSF events, prefer raw_data field for rule evaluation
-        // This allows vendor-specific Sigma rules to work post-normalization
-        let raw_data = event
-            .metadata
-            .get("ocsf")
-            .and_then(|_| match event.data.get("raw_data") {
-                Some(Value::String(raw_data)) => serde_json::from_str::<Value>(raw_data).ok(),
-                _ => None,
-            });
+    /// Evaluate a batch, on the workers if there are any. Returns false if
+    /// they have stopped.
+    async fn process(&mut self, pool: &Option<Pool>, events: Arc<Vec<Event>>) -> bool {
+        match pool {
+            Some((jobs, _)) => {
+                let chunk = events.len().div_ceil(self.workers).max(1);
+                for start in (0..events.len()).step_by(chunk) {
+                    let job = Job {
+                        events: events.clone(),
+                        range: start..(start + chunk).min(events.len()),
+                    };
+                    if jobs.send(job).await.is_err() {
+                        error!("detection workers stopped");
+                        return false;
+                    }
+                }
+            }
+            None => {
+                // Process each event independently to isolate failures
+                for event in events.iter() {
+                    let matches =
+                        evaluate(&self.rules, &self.ocsf, &self.levels, &self.lists, event).await;
+                    self.emit(event, matches);
+                }
+            }
+        }
+        true
+    }
 
-        let data = match raw_data {
-            Some(ref d) => d,
-            None => &event.data,
-        };
+    /// Evaluate the batches already received, wait for the workers to
+    /// finish and emit the roll-ups of open dedup windows
+    async fn drain(&mut self, pool: Option<Pool>) {
+        loop {
+            let events = match self.src.try_recv() {
+                Ok(events) => events,
+                Err(TryRecvError::Lagged(n)) => {
+                    lagged(n);
+                    continue;
+                }
+                Err(_) => break,
+            };
+            if !self.process(&pool, events).await {
+                break;
+            }
+        }
+        if let Some((jobs, mut results)) = pool {
+            drop(jobs);
+            while let Some((events, index, matches)) = results.recv().await {
+                self.emit(&events[index], matches);
+            }
+        }
+        if let Some(dedup) = self.dedup.as_mut() {
+            let rollups = dedup.drain();
+            if !rollups.is_empty() {
+                self.send(rollups);
+            }
+        }
+    }
 
-        let sigma_event = sigmars::event::RefEvent {
-            data,
-            metadata: &event.metadata,
-            logsource: filter,
+    /// Re-read the worker count and dedup settings.
+    ///
+    /// Changing the worker count drains the current pool, emitting its
+    /// findings, before starting the new one. Disabling dedup emits the
+    /// roll-ups of its open windows.
+    async fn reload(&mut self, pool: &mut Option<Pool>) {
+        let Some(config) = self.config.as_ref().map(|c| c.load_full()) else {
+            return;
+        };
+        let Some(detections) = config.detections.as_ref() else {
+            warn!("detections no longer configured; keeping current detection settings");
+            return;
         };
+        info!("reloading detection settings...");
 
-        let rules = self.rules.read().await;
-
-        // Get matching rules and convert to OCSF detection_finding events
-        let detections = rules
-            .get_matches_from_ref(&sigma_event)
-            .await
-            .map_err(|e| anyhow::anyhow!("error applying rules: {}", e))?
-            .iter()
-            .filter_map(|d| rules.get(d))
-            .filter_map(|d| {
-                // Establish correlation between detection and original event
-                // Uses OCSF metadata.uid if present, falls back to StrIEM's event ID
-                let correlation_uid = event
-                    .data
-                    .as_object()
-                    .and_then(|v| v.get("metadata"))
-                    .and_then(|v| v.as_object())
-                    .and_then(|v| v.get("uid"))
-                    .and_then(|v| v.as_str())
-                    .map(|v| v.to_string())
-                    .unwrap_or_else(|| event.id.to_string());
-
-                let mut ocsf = Event::default();
-
-                // Convert Sigma detection to OCSF detection_finding (class_uid 2004)
-                let mut data: Value = d.into();
-                data["metadata"]["uid"] = json!(event.id.to_string());
-                data["metadata"]["correlation_uid"] = json!(correlation_uid);
-                data["metadata"]["product"] = json!({
-                    "vendor_name": "StrIEM",
-                    "product_name": "StrIEM"
-                });
-                ocsf.data = data;
-                ocsf.metadata
-                    .extend(event.metadata.iter().map(|(k, v)| (k.clone(), v.clone())));
-                ocsf.metadata.extend([
-                    ("ocsf".to_string(), json!(true)),
-                    ("striem".to_string(), json!(true)),
-                ]);
-                Some(ocsf)
-            })
-            .collect::<Vec<_>>();
-        drop(rules);
+        match (self.dedup.as_mut(), detections.dedup_enabled()) {
+            (Some(dedup), true) => dedup.reconfigure(detections),
+            (Some(dedup), false) => {
+                let rollups = dedup.drain();
+                if !rollups.is_empty() {
+                    self.send(rollups);
+                }
+                self.dedup = None;
+            }
+            (None, true) => self.dedup = Dedup::new(detections),
+            (None, false) => {}
+        }
+
+        let workers = detections.workers.max(1);
+        if workers != self.workers {
+            if let Some((jobs, mut results)) = pool.take() {
+                // Workers finish the queued jobs, then close the results channel
+                drop(jobs);
+                while let Some((events, index, matches)) = results.recv().await {
+                    self.emit(&events[index], matches);
+                }
+            }
+            info!("detection workers: {} -> {}", self.workers, workers);
+            self.workers = workers;
+            *pool = (workers > 1).then(|| self.spawn_workers());
+        }
+    }
+
+    /// Deduplicate and send the findings for `event`
+    fn emit(&mut self, event: &Event, matches: Result<Matches>) {
+        let detections = match matches {
+            Ok(detections) => detections,
+            Err(e) => {
+                error!("error applying detection rules: {}", e);
+                return;
+            }
+        };
+        let detections = match self.dedup.as_mut() {
+            Some(dedup) => {
+                let now = Instant::now();
+                detections
+                    .into_iter()
+                    .flat_map(|(id, finding)| {
+                        let (finding, rollups) = dedup.observe(&id, event, finding, now);
+                        finding.into_iter().chain(rollups)
+                    })
+                    .collect::<Vec<_>>()
+            }
+            None => detections
+                .into_iter()
+                .map(|(_, finding)| finding)
+                .collect::<Vec<_>>(),
+        };
 
         if !detections.is_empty() {
             trace!("event {} matched {} detections", event.id, detections.len());
         }
-        let _ = self.dest.send(Arc::new(detections));
-        Ok(())
+        self.send(detections);
+    }
+
+    /// Send findings downstream; an empty set still marks the event as done
+    fn send(&self, findings: Vec<Event>) {
+        metrics::increment(
+            "striem_detection_findings_total",
+            &[],
+            findings.len() as u64,
+        );
+        let _ = self.dest.send(Arc::new(findings));
     }
 }
+
+/// Count batches missed by falling behind the source channel
+fn lagged(n: u64) {
+    metrics::increment(
+        "striem_broadcast_lagged_total",
+        &[("subscriber", "detection")],
+        n,
+    );
+    warn!("detection worker lagged, {} batches not evaluated", n);
+}
+
+/// Evaluate event against Sigma rules, building a detection finding for each
+/// match (see [`detection::findings`]).
+///
+/// # Performance Consideration
+/// Only acquires read lock on rules collection, allowing concurrent detection
+/// across multiple events.
+async fn evaluate(
+    rules: &RwLock<SigmaCollection>,
+    ocsf: &OcsfRules,
+    levels: &LevelOverrides,
+    lists: &ReferenceLists,
+    event: &Event,
+) -> Result<Matches> {
+    let start = Instant::now();
+    let matches = evaluate_event(rules, ocsf, levels, lists, event).await;
+    metrics::increment("striem_detection_events_total", &[], 1);
+    metrics::observe(
+        "striem_detection_evaluation_duration_seconds",
+        &[],
+        start.elapsed().as_secs_f64(),
+    );
+    matches
+}
+
+async fn evaluate_event(
+    rules: &RwLock<SigmaCollection>,
+    ocsf: &OcsfRules,
+    levels: &LevelOverrides,
+    lists: &ReferenceLists,
+    event: &Event,
+) -> Result<Matches> {
+    let rules = rules.read().await;
+    let (ocsf, levels, lists) = (ocsf.load(), levels.load(), lists.load());
+    detection::findings(&rules, &ocsf, &levels, &lists, event)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))
+}
@@ -0,0 +1,503 @@
+//! Event enrichment between the input and its consumers.
+//!
+//! With `enrichment` configured, [`run`] takes each batch the Vector server
+//! (or HTTP listener) receives, adds GeoIP locations and asset tags (see
+//! [`striem_config::enrichment`]), and passes it on to detection and storage
+//! on a channel of its own. Without it, they read the server's channel
+//! directly; turning enrichment on or off needs a restart.
+//!
+//! Enrichment fails open: a missing GeoIP database or a bad assets file is
+//! logged once, that enricher is skipped and events go on as they came. The
+//! GeoIP database is checked every [`GEOIP_RELOAD_CHECK_SECS`] and reopened
+//! when it changes; the assets file and field lists are read again on
+//! `SysMessage::Reload`.
+
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use arc_swap::ArcSwap;
+use glob::Pattern;
+use ipnet::IpNet;
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+use tokio::sync::broadcast::{
+    self,
+    error::{RecvError, TryRecvError},
+};
+
+use striem_common::{SysMessage, event::Event, metrics, prelude::*};
+use striem_config::{
+    StrIEMConfig,
+    enrichment::{AssetsConfig, EnrichmentConfig},
+};
+
+/// Field patterns, one [`Pattern`] per dotted segment
+type Fields = Vec<Vec<Pattern>>;
+
+fn fields(patterns: &[String]) -> Fields {
+    patterns
+        .iter()
+        .filter_map(|field| {
+            field
+                .split('.')
+                .map(Pattern::new)
+                .collect::<Result<Vec<_>, _>>()
+                .inspect_err(|e| warn!("enrichment: invalid field {}: {}", field, e))
+                .ok()
+        })
+        .collect()
+}
+
+/// Call `f` with the object holding each field matching `segments`, and
+/// the field's name; arrays along the way are searched element by element
+fn visit(
+    value: &mut Value,
+    segments: &[Pattern],
+    f: &mut impl FnMut(&mut Map<String, Value>, &str),
+) {
+    let Some((segment, rest)) = segments.split_first() else {
+        return;
+    };
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                visit(item, segments, f);
+            }
+        }
+        Value::Object(object) => {
+            let keys: Vec<String> = object
+                .keys()
+                .filter(|key| segment.matches(key))
+                .cloned()
+                .collect();
+            for key in keys {
+                if rest.is_empty() {
+                    f(object, &key);
+                } else if let Some(child) = object.get_mut(&key) {
+                    visit(child, rest, f);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Call `f` with the object holding each address in `fields`, and the
+/// address
+fn each_address(
+    data: &mut Value,
+    fields: &Fields,
+    mut f: impl FnMut(&mut Map<String, Value>, IpAddr),
+) {
+    for segments in fields {
+        visit(data, segments, &mut |object, key| {
+            if let Some(ip) = object
+                .get(key)
+                .and_then(|v| v.as_str())
+                .and_then(|s| s.parse::<IpAddr>().ok())
+            {
+                f(object, ip);
+            }
+        });
+    }
+}
+
+/// The parts of a GeoLite2/GeoIP2 City record that are used
+#[derive(Debug, Deserialize)]
+struct CityRecord {
+    city: Option<Named>,
+    continent: Option<Named>,
+    country: Option<Coded>,
+    location: Option<Coordinates>,
+    postal: Option<Postal>,
+    subdivisions: Option<Vec<Coded>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Named {
+    names: Option<BTreeMap<String, String>>,
+}
+
+impl Named {
+    fn english(&self) -> Option<&str> {
+        self.names.as_ref()?.get("en").map(|s| s.as_str())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Coded {
+    iso_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Coordinates {
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Postal {
+    code: Option<String>,
+}
+
+impl CityRecord {
+    /// As an OCSF `location` object, or `None` if it has nothing to say
+    fn location(&self) -> Option<Value> {
+        let coordinates = self.location.as_ref();
+        let location = [
+            (
+                "city",
+                self.city
+                    .as_ref()
+                    .and_then(|c| c.english())
+                    .map(|s| json!(s)),
+            ),
+            (
+                "continent",
+                self.continent
+                    .as_ref()
+                    .and_then(|c| c.english())
+                    .map(|s| json!(s)),
+            ),
+            (
+                "country",
+                self.country
+                    .as_ref()
+                    .and_then(|c| c.iso_code.as_ref())
+                    .map(|s| json!(s)),
+            ),
+            (
+                "region",
+                self.subdivisions
+                    .as_ref()
+                    .and_then(|s| s.first())
+                    .and_then(|s| s.iso_code.as_ref())
+                    .map(|s| json!(s)),
+            ),
+            (
+                "postal_code",
+                self.postal
+                    .as_ref()
+                    .and_then(|p| p.code.as_ref())
+                    .map(|s| json!(s)),
+            ),
+            (
+                "lat",
+                coordinates.and_then(|c| c.latitude).map(|v| json!(v)),
+            ),
+            (
+                "long",
+                coordinates.and_then(|c| c.longitude).map(|v| json!(v)),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.to_string(), value?)))
+        .collect::<Map<_, _>>();
+        (!location.is_empty()).then_some(Value::Object(location))
+    }
+}
+
+/// What a look at the GeoIP database file found
+enum Found {
+    Unchanged,
+    Missing(std::io::Error),
+    /// Modified since it was last read, and the file opened again
+    Changed(
+        SystemTime,
+        Result<maxminddb::Reader<Vec<u8>>, maxminddb::MaxMindDbError>,
+    ),
+}
+
+impl Found {
+    /// Stat `path`, and read it if it was modified since `modified`
+    fn check(path: &Path, modified: Option<SystemTime>) -> Self {
+        match std::fs::metadata(path).and_then(|m| m.modified()) {
+            Ok(changed) if Some(changed) == modified => Found::Unchanged,
+            Ok(changed) => Found::Changed(changed, maxminddb::Reader::open_readfile(path)),
+            Err(e) => Found::Missing(e),
+        }
+    }
+}
+
+/// A MaxMind City database, reopened when the file changes
+pub(crate) struct GeoIp {
+    path: PathBuf,
+    reader: Option<maxminddb::Reader<Vec<u8>>>,
+    /// Modification time of the file `reader` was read from
+    modified: Option<SystemTime>,
+    checked: Instant,
+    /// Whether the missing file has been reported
+    missing: bool,
+}
+
+impl GeoIp {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        let mut geoip = GeoIp {
+            path,
+            reader: None,
+            modified: None,
+            checked: Instant::now(),
+            missing: false,
+        };
+        geoip.reload_if_changed();
+        geoip
+    }
+
+    /// Open the database again if its modification time changed; a
+    /// database that disappears keeps being used. Reads the file in place,
+    /// see [`Enricher::refresh`] for the async loop.
+    pub(crate) fn reload_if_changed(&mut self) {
+        self.checked = Instant::now();
+        let found = Found::check(&self.path, self.modified);
+        self.apply(found);
+    }
+
+    fn apply(&mut self, found: Found) {
+        let (modified, opened) = match found {
+            Found::Unchanged => return,
+            Found::Missing(e) => {
+                if !self.missing {
+                    warn!(
+                        "GeoIP database {} not readable, skipping GeoIP enrichment: {}",
+                        self.path.display(),
+                        e
+                    );
+                    self.missing = true;
+                }
+                return;
+            }
+            Found::Changed(modified, opened) => (modified, opened),
+        };
+        self.missing = false;
+        // a file that won't open isn't retried until it changes again
+        self.modified = Some(modified);
+        match opened {
+            Ok(reader) => {
+                info!(
+                    "... loaded GeoIP database {} ({})",
+                    self.path.display(),
+                    reader.metadata.database_type
+                );
+                self.reader = Some(reader);
+            }
+            Err(e) => warn!(
+                "could not open GeoIP database {}, skipping GeoIP enrichment: {}",
+                self.path.display(),
+                e
+            ),
+        }
+    }
+
+    fn is_due(&self) -> bool {
+        self.checked.elapsed() >= Duration::from_secs(GEOIP_RELOAD_CHECK_SECS)
+    }
+
+    /// OCSF `location` of `ip`, if the database knows it
+    pub(crate) fn location(&self, ip: IpAddr) -> Option<Value> {
+        let record: CityRecord = self.reader.as_ref()?.lookup(ip).ok()??;
+        record.location()
+    }
+}
+
+/// Asset tags by CIDR range
+pub(crate) struct Assets {
+    /// Least specific first, so more specific ranges override their tags
+    ranges: Vec<(IpNet, Map<String, Value>)>,
+}
+
+impl Assets {
+    pub(crate) fn load(config: &AssetsConfig) -> anyhow::Result<Self> {
+        let mut ranges = config
+            .load()?
+            .into_iter()
+            .map(|range| {
+                let cidr = range
+                    .cidr
+                    .parse::<IpNet>()
+                    .map_err(|e| anyhow::anyhow!("invalid CIDR {}: {}", range.cidr, e))?;
+                Ok((cidr, range.tags.into_iter().collect()))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        ranges.sort_by_key(|(cidr, _)| cidr.prefix_len());
+        Ok(Assets { ranges })
+    }
+
+    /// Tags of every range holding `ip`
+    pub(crate) fn tags(&self, ip: IpAddr) -> Map<String, Value> {
+        let mut tags = Map::new();
+        for (_, range) in self.ranges.iter().filter(|(cidr, _)| cidr.contains(&ip)) {
+            tags.extend(range.clone());
+        }
+        tags
+    }
+}
+
+/// The configured enrichers
+#[derive(Default)]
+pub(crate) struct Enricher {
+    geoip: Option<(GeoIp, Fields)>,
+    assets: Option<(Assets, Fields)>,
+}
+
+impl Enricher {
+    pub(crate) fn new(config: &EnrichmentConfig) -> Self {
+        let geoip = config
+            .geoip
+            .as_ref()
+            .map(|geoip| (GeoIp::new(geoip.database.clone()), fields(&geoip.fields)));
+        let assets = config.assets.as_ref().and_then(|assets| {
+            Assets::load(assets)
+                .inspect_err(|e| warn!("skipping asset enrichment: {:#}", e))
+                .ok()
+                .map(|loaded| (loaded, fields(&assets.fields)))
+        });
+        Enricher { geoip, assets }
+    }
+
+    /// Re-read `config`, keeping the GeoIP database if it's the same file
+    fn reload(&mut self, config: &EnrichmentConfig) {
+        let geoip = match (self.geoip.take(), &config.geoip) {
+            (Some((geoip, _)), Some(config)) if geoip.path == config.database => {
+                Some((geoip, fields(&config.fields)))
+            }
+            (_, config) => config
+                .as_ref()
+                .map(|c| (GeoIp::new(c.database.clone()), fields(&c.fields))),
+        };
+        *self = Enricher {
+            geoip,
+            ..Enricher::new(&EnrichmentConfig {
+                geoip: None,
+                assets: config.assets.clone(),
+            })
+        };
+    }
+
+    /// Check the GeoIP database for changes, if it's time to or `force`,
+    /// reading the file on a blocking thread
+    pub(crate) async fn refresh(&mut self, force: bool) {
+        let Some((geoip, _)) = self.geoip.as_mut() else {
+            return;
+        };
+        if !force && !geoip.is_due() {
+            return;
+        }
+        geoip.checked = Instant::now();
+        let (path, modified) = (geoip.path.clone(), geoip.modified);
+        match tokio::task::spawn_blocking(move || Found::check(&path, modified)).await {
+            Ok(found) => geoip.apply(found),
+            Err(e) => warn!(
+                "could not check GeoIP database {}: {}",
+                geoip.path.display(),
+                e
+            ),
+        }
+    }
+
+    /// Add locations and tags next to the addresses in `event`
+    pub(crate) fn enrich(&self, event: &mut Event) {
+        if let Some((geoip, fields)) = &self.geoip
+            && geoip.reader.is_some()
+        {
+            each_address(&mut event.data, fields, |object, ip| {
+                if !object.contains_key("location")
+                    && let Some(location) = geoip.location(ip)
+                {
+                    object.insert("location".to_string(), location);
+                }
+            });
+        }
+        if let Some((assets, fields)) = &self.assets {
+            each_address(&mut event.data, fields, |object, ip| {
+                for (tag, value) in assets.tags(ip) {
+                    object.entry(tag).or_insert(value);
+                }
+            });
+        }
+    }
+}
+
+/// Enrich batches from `src` onto `dest` until shutdown; the batches
+/// already received are passed on before it returns
+pub(crate) async fn run(
+    config: Arc<ArcSwap<StrIEMConfig>>,
+    mut src: broadcast::Receiver<Arc<Vec<Event>>>,
+    dest: broadcast::Sender<Arc<Vec<Event>>>,
+    mut shutdown: broadcast::Receiver<SysMessage>,
+) {
+    let enrichment = |config: &StrIEMConfig| config.enrichment.clone().unwrap_or_default();
+    // the GeoIP database and assets file are read off the async threads
+    let initial = enrichment(&config.load());
+    let mut enricher = tokio::task::spawn_blocking(move || Enricher::new(&initial))
+        .await
+        .unwrap_or_default();
+    let pass_on = async |enricher: &mut Enricher, batch: Arc<Vec<Event>>| {
+        enricher.refresh(false).await;
+        let enriched = batch
+            .iter()
+            .cloned()
+            .map(|mut event| {
+                enricher.enrich(&mut event);
+                event
+            })
+            .collect();
+        // nothing may be subscribed yet
+        dest.send(Arc::new(enriched)).ok();
+    };
+    let lagged = |n| {
+        metrics::increment(
+            "striem_broadcast_lagged_total",
+            &[("subscriber", "enrichment")],
+            n,
+        );
+        warn!("enrichment lagged, {} batches not passed on", n);
+    };
+    loop {
+        tokio::select! {
+            msg = shutdown.recv() => match msg {
+                Ok(SysMessage::Shutdown) => {
+                    info!("enrichment shutting down...");
+                    loop {
+                        match src.try_recv() {
+                            Ok(batch) => pass_on(&mut enricher, batch).await,
+                            Err(TryRecvError::Lagged(n)) => lagged(n),
+                            Err(_) => return,
+                        }
+                    }
+                }
+                Err(RecvError::Closed) => {
+                    info!("enrichment shutting down...");
+                    return;
+                }
+                Ok(SysMessage::Reload) => {
+                    let (mut reloaded, settings) =
+                        (std::mem::take(&mut enricher), enrichment(&config.load()));
+                    match tokio::task::spawn_blocking(move || {
+                        reloaded.reload(&settings);
+                        reloaded
+                    })
+                    .await
+                    {
+                        Ok(reloaded) => enricher = reloaded,
+                        Err(e) => warn!("could not reload enrichment: {}", e),
+                    }
+                }
+                _ => {}
+            },
+            batch = src.recv() => match batch {
+                Ok(batch) => pass_on(&mut enricher, batch).await,
+                Err(RecvError::Lagged(n)) => lagged(n),
+                Err(RecvError::Closed) => {
+                    info!("source channel closed");
+                    return;
+                }
+            },
+        }
+    }
+}
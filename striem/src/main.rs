@@ -4,22 +4,140 @@
 //! - Loading configuration from file or environment variables
 //! - Initializing the application with detection rules and storage
 //! - Handling graceful shutdown via SIGINT/SIGTERM
+//! - Reloading the configuration on SIGHUP (unix only)
+//!
+//! Subcommands check the configuration, print the Vector configuration or
+//! replay stored events instead; see [`cli`].
 
-use std::path;
+use std::{path, sync::Arc};
 
-use anyhow::Result;
-use striem_common::SysMessage;
+use anyhow::{Result, anyhow};
+use arc_swap::ArcSwap;
+use chrono::Utc;
+use clap::Parser;
+use striem_common::{
+    SysMessage,
+    logging::{self, LoggingConfig},
+};
 use striem_config::StrIEMConfig;
 mod app;
+mod check;
+mod cli;
+mod dedup;
 mod detection;
+mod enrich;
+mod watch;
 use app::App;
-use log::info;
+use cli::{Cli, Command};
+use log::{info, warn};
+
+#[cfg(test)]
+mod tests;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::init();
+    let cli = Cli::parse();
+    let legacy = cli.is_legacy();
+    let command = cli.command();
+
+    let log_level = match &command {
+        Command::Run(args) => args.log_level.clone(),
+        _ => None,
+    };
+
+    match command {
+        Command::Version => {
+            println!("striem {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Command::Check(config) => {
+            cli::set_args(cli::RunArgs {
+                config,
+                ..Default::default()
+            });
+            let loaded = crate::config().await;
+            init_logging(loaded.as_ref().ok(), None)?;
+            let ok = match loaded {
+                Ok(config) => check::run(&config),
+                Err(e) => {
+                    eprintln!("error: configuration could not be loaded: {:#}", e);
+                    false
+                }
+            };
+            std::process::exit(if ok { 0 } else { 1 });
+        }
+        Command::ExportVectorConfig(config) => {
+            cli::set_args(cli::RunArgs {
+                config,
+                ..Default::default()
+            });
+            let config = crate::config().await;
+            init_logging(config.as_ref().ok(), None)?;
+            print!("{}", striem_api::export_vector_config(config?).await?);
+            return Ok(());
+        }
+        Command::Replay(args) => {
+            cli::set_args(cli::RunArgs {
+                config: args.config.clone(),
+                ..Default::default()
+            });
+            let config = crate::config().await;
+            init_logging(config.as_ref().ok(), None)?;
+            let config = config?;
+            // the severity overrides and lists detection runs with
+            let (levels, lists) = match striem_api::saved_overrides(&config) {
+                Ok(saved) => saved,
+                Err(e) => {
+                    eprintln!("error: saved rule settings could not be read: {:#}", e);
+                    std::process::exit(1);
+                }
+            };
+            let config = Arc::new(ArcSwap::from_pointee(config));
+            let request = striem_api::ReplayRequest {
+                class: args.class,
+                start: args.start,
+                end: args.end.unwrap_or_else(Utc::now),
+                rules: args.rules,
+                dry_run: args.dry_run,
+            };
+            let progress = striem_api::ReplayProgress::default();
+            let report =
+                match striem_api::replay(&config, &request, &levels, &lists, &progress).await {
+                    Ok(report) => report,
+                    Err(e) => {
+                        eprintln!("error: replay failed: {:#}", e);
+                        std::process::exit(1);
+                    }
+                };
+            println!(
+                "{} events in {} files, {} findings{}",
+                report.events,
+                report.files,
+                report.findings,
+                match request.dry_run {
+                    true => " (dry run)".to_string(),
+                    false => format!(", {} stored", report.written),
+                }
+            );
+            for rule in report.rules {
+                println!(
+                    "{:>8}  {}  {}",
+                    rule.findings,
+                    rule.id,
+                    rule.title.unwrap_or_default()
+                );
+            }
+            return Ok(());
+        }
+        Command::Run(args) => cli::set_args(args),
+    }
 
-    let config = config().await?;
+    let config = crate::config().await;
+    init_logging(config.as_ref().ok(), log_level.as_deref())?;
+    if legacy {
+        warn!("`striem <config files>` is deprecated; use `striem run -c <file>`");
+    }
+    let config = config?;
 
     let mut app = App::new(config).await?;
     let update = app.update_channel();
@@ -32,6 +150,9 @@ async fn main() -> Result<()> {
         update.send(SysMessage::Shutdown).unwrap();
     });
 
+    #[cfg(unix)]
+    tokio::spawn(watch::hangup(app.config(), app.update_channel()));
+
     println!(".:: Starting StrIEM ::.");
     app.run().await?;
     println!(".:: StrIEM Stopped. Goodbye ::.");
@@ -39,11 +160,19 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Log as configured, or with the defaults when the configuration didn't
+/// load; `level` is `--log-level`
+fn init_logging(config: Option<&StrIEMConfig>, level: Option<&str>) -> Result<()> {
+    let default = LoggingConfig::default();
+    let logging = config.map_or(&default, |config| &config.logging);
+    logging::init(logging, level).map_err(|e| anyhow!("could not initialize logging: {}", e))
+}
+
+/// The configuration from the command line's files, `striem.json`, the
+/// environment and the command line's overrides, in increasing precedence
 pub(crate) async fn config() -> Result<StrIEMConfig> {
-    let mut cfgfiles = std::env::args()
-        .skip(1)
-        .map(|arg| path::PathBuf::from(arg))
-        .collect::<Vec<_>>();
+    let args = cli::args();
+    let mut cfgfiles = args.config.files.clone();
 
     if let Some(dir) = std::env::var_os("STRIEM_APPDATA") {
         let cfg = path::PathBuf::from(dir).join("striem.json");
@@ -57,10 +186,14 @@ pub(crate) async fn config() -> Result<StrIEMConfig> {
         }
     };
 
-    // Load configuration from file if provided, otherwise use defaults/environment variables
-    // This allows both "striem" and "striem config.yaml" invocations
-    match cfgfiles.len() {
-        0 => Ok(StrIEMConfig::new()?),
-        _ => Ok(StrIEMConfig::from_multi_file(cfgfiles)?),
+    // Overrides depend on the configuration they apply to (the input kind,
+    // the API host), so it's read once without them first
+    let config = StrIEMConfig::from_multi_file(cfgfiles.clone())?;
+    let overrides = cli::overrides(args, &config);
+    if overrides.is_empty() {
+        return Ok(config);
     }
+    Ok(StrIEMConfig::from_files_with_overrides(
+        cfgfiles, &overrides,
+    )?)
 }
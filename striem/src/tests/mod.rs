@@ -0,0 +1,648 @@
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+use striem_common::event::Event;
+use striem_config::{StringOrList, detections::DetectionsConfig};
+
+use crate::dedup::Dedup;
+
+fn dedup_config(window: u64) -> DetectionsConfig {
+    DetectionsConfig {
+        dedup_window_secs: Some(window),
+        dedup_fields: vec!["src_endpoint.ip".to_string()],
+        dedup_max_entries: 4,
+        ..DetectionsConfig::from(StringOrList::String("rules".to_string()))
+    }
+}
+
+fn finding() -> Event {
+    Event::from(json!({"class_uid": 2004, "metadata": {"uid": "x"}}))
+}
+
+fn event(ip: &str) -> Event {
+    Event::from(json!({"src_endpoint": {"ip": ip}}))
+}
+
+#[test]
+fn dedup_disabled_without_window() {
+    let config = DetectionsConfig::from(StringOrList::String("rules".to_string()));
+    assert!(Dedup::new(&config).is_none());
+}
+
+#[test]
+fn dedup_burst_emits_first_then_rollup() {
+    let mut dedup = Dedup::new(&dedup_config(60)).unwrap();
+    let start = Instant::now();
+    let source = event("10.0.0.1");
+
+    let mut emitted = 0;
+    for i in 0..1000 {
+        let (f, rollups) = dedup.observe(
+            "rule-a",
+            &source,
+            finding(),
+            start + Duration::from_millis(i),
+        );
+        emitted += f.iter().count() + rollups.len();
+    }
+    // only the first occurrence goes out immediately
+    assert_eq!(emitted, 1);
+    assert!(dedup.expire(start + Duration::from_secs(30)).is_empty());
+
+    let rollups = dedup.expire(start + Duration::from_secs(61));
+    assert_eq!(rollups.len(), 1);
+    assert_eq!(rollups[0].data["count"], json!(1000));
+    assert!(rollups[0].data["end_time"].as_i64() >= rollups[0].data["start_time"].as_i64());
+    assert_ne!(rollups[0].data["metadata"]["uid"], json!("x"));
+    assert_eq!(dedup.len(), 0);
+}
+
+#[test]
+fn dedup_keys_on_observables() {
+    let mut dedup = Dedup::new(&dedup_config(60)).unwrap();
+    let now = Instant::now();
+
+    let (a, _) = dedup.observe("rule-a", &event("10.0.0.1"), finding(), now);
+    let (b, _) = dedup.observe("rule-a", &event("10.0.0.2"), finding(), now);
+    let (c, _) = dedup.observe("rule-b", &event("10.0.0.1"), finding(), now);
+    assert!(a.is_some() && b.is_some() && c.is_some());
+
+    // single hits produce no roll-up when the window closes
+    assert!(dedup.expire(now + Duration::from_secs(61)).is_empty());
+}
+
+#[test]
+fn dedup_evicts_oldest_when_full() {
+    let mut dedup = Dedup::new(&dedup_config(60)).unwrap();
+    let now = Instant::now();
+
+    // two hits on the first key so its eviction yields a roll-up
+    dedup.observe("rule-a", &event("10.0.0.0"), finding(), now);
+    dedup.observe("rule-a", &event("10.0.0.0"), finding(), now);
+
+    let mut evicted = Vec::new();
+    for i in 1..=4 {
+        let (f, rollups) = dedup.observe(
+            "rule-a",
+            &event(&format!("10.0.0.{}", i)),
+            finding(),
+            now + Duration::from_millis(i),
+        );
+        assert!(f.is_some());
+        evicted.extend(rollups);
+    }
+    assert_eq!(dedup.len(), 4);
+    assert_eq!(evicted.len(), 1);
+    assert_eq!(evicted[0].data["count"], json!(2));
+}
+
+#[test]
+fn dedup_per_rule_window_overrides_default() {
+    let mut config = dedup_config(60);
+    config.dedup_rules.insert("rule-off".to_string(), 0);
+    let mut dedup = Dedup::new(&config).unwrap();
+    let now = Instant::now();
+
+    for _ in 0..10 {
+        let (f, _) = dedup.observe("rule-off", &event("10.0.0.1"), finding(), now);
+        assert!(f.is_some());
+    }
+}
+
+#[tokio::test]
+async fn detection_survives_lagging_source() {
+    use std::sync::Arc;
+
+    use striem_common::{SysMessage, metrics};
+    use tokio::sync::{RwLock, broadcast};
+
+    use crate::detection::DetectionHandler;
+
+    let (src_tx, src_rx) = broadcast::channel(2);
+    let (dest_tx, mut dest_rx) = broadcast::channel(16);
+    let (sys_tx, sys_rx) = broadcast::channel::<SysMessage>(1);
+
+    // overflow the source before the handler reads from it
+    for _ in 0..6 {
+        src_tx.send(Arc::new(vec![event("10.0.0.1")])).unwrap();
+    }
+
+    let mut handler = DetectionHandler::new(
+        src_rx,
+        dest_tx,
+        Arc::new(RwLock::new(Default::default())),
+        Default::default(),
+        None,
+        sys_rx,
+    );
+    let task = tokio::spawn(async move { handler.run().await });
+
+    // the two retained batches are evaluated, and so is one sent afterwards
+    for _ in 0..2 {
+        tokio::time::timeout(Duration::from_secs(1), dest_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+    src_tx.send(Arc::new(vec![event("10.0.0.2")])).unwrap();
+    tokio::time::timeout(Duration::from_secs(1), dest_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!task.is_finished());
+
+    assert_eq!(
+        metrics::value(
+            "striem_broadcast_lagged_total",
+            &[("subscriber", "detection")]
+        ),
+        Some(4.0)
+    );
+
+    sys_tx.send(SysMessage::Shutdown).unwrap();
+    tokio::time::timeout(Duration::from_secs(1), task)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+/// `count` rules, rule `i` matching events whose `user` is `user{i}`
+async fn numbered_rules(count: usize) -> sigmars::SigmaCollection {
+    let mut rules = sigmars::SigmaCollection::default();
+    for i in 0..count {
+        let rule: sigmars::SigmaRule = serde_yaml::from_str(&format!(
+            r#"
+title: rule {i}
+id: 00000000-0000-4000-8000-{i:012}
+logsource:
+  product: test
+detection:
+  selection:
+    user: user{i}
+  condition: selection
+level: high
+"#
+        ))
+        .unwrap();
+        rules.add(rule).unwrap();
+    }
+    rules.init(&mut sigmars::MemBackend::new().await).await;
+    rules
+}
+
+/// Events for [`numbered_rules`]; every tenth matches no rule
+fn numbered_events(count: usize, rules: usize) -> Vec<Event> {
+    (0..count)
+        .map(|i| {
+            let user = match i % 10 {
+                0 => "nobody".to_string(),
+                _ => format!("user{}", i % rules),
+            };
+            Event::from((
+                json!({"user": user}),
+                [("logsource".to_string(), json!({"product": "test"}))].into(),
+            ))
+        })
+        .collect()
+}
+
+/// Run `events` through a handler with `workers`, in batches of `batch`,
+/// returning the rule ids matched for each event id and the time taken
+async fn detect(
+    rules: std::sync::Arc<tokio::sync::RwLock<sigmars::SigmaCollection>>,
+    events: &[Event],
+    workers: usize,
+    batch: usize,
+) -> (std::collections::HashMap<String, Vec<String>>, Duration) {
+    use std::sync::Arc;
+
+    use striem_common::SysMessage;
+    use tokio::sync::broadcast;
+
+    use crate::detection::DetectionHandler;
+
+    let batches = events.chunks(batch).collect::<Vec<_>>();
+    let (src_tx, src_rx) = broadcast::channel(batches.len() + 1);
+    let (dest_tx, mut dest_rx) = broadcast::channel(events.len() + 1);
+    let (sys_tx, sys_rx) = broadcast::channel::<SysMessage>(1);
+    let mut handler =
+        DetectionHandler::new(src_rx, dest_tx, rules, Default::default(), None, sys_rx)
+            .with_workers(workers);
+
+    let start = Instant::now();
+    for batch in batches {
+        src_tx.send(Arc::new(batch.to_vec())).unwrap();
+    }
+    let task = tokio::spawn(async move { handler.run().await });
+
+    // one (possibly empty) set of findings per event
+    let mut matched = std::collections::HashMap::<String, Vec<String>>::new();
+    for _ in 0..events.len() {
+        let findings = tokio::time::timeout(Duration::from_secs(30), dest_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        for finding in findings.iter() {
+            let event = finding.data["metadata"]["uid"]
+                .as_str()
+                .unwrap()
+                .to_string();
+            let rule = finding.data["finding_info"]["analytic"]["uid"]
+                .as_str()
+                .unwrap()
+                .to_string();
+            matched.entry(event).or_default().push(rule);
+        }
+    }
+    let elapsed = start.elapsed();
+
+    sys_tx.send(SysMessage::Shutdown).unwrap();
+    task.await.unwrap();
+    (matched, elapsed)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn detection_workers_match_like_a_single_handler() {
+    use std::sync::Arc;
+
+    let rules = Arc::new(tokio::sync::RwLock::new(numbered_rules(20).await));
+    let events = numbered_events(200, 20);
+
+    let (single, _) = detect(rules.clone(), &events, 1, 16).await;
+    let (pooled, _) = detect(rules, &events, 4, 16).await;
+
+    assert_eq!(single.len(), 180);
+    assert_eq!(pooled, single);
+    for (i, event) in events.iter().enumerate() {
+        let expected = match i % 10 {
+            0 => None,
+            _ => Some(vec![format!("00000000-0000-4000-8000-{:012}", i % 20)]),
+        };
+        assert_eq!(pooled.get(&event.id.to_string()), expected.as_ref());
+    }
+}
+
+/// Run with `cargo test -p striem --release -- --ignored --nocapture`
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+#[ignore]
+async fn detection_workers_throughput() {
+    use std::sync::Arc;
+
+    let rules = Arc::new(tokio::sync::RwLock::new(numbered_rules(2_000).await));
+    let events = numbered_events(20_000, 2_000);
+
+    let (single, one) = detect(rules.clone(), &events, 1, 256).await;
+    let (pooled, four) = detect(rules, &events, 4, 256).await;
+
+    println!("1 worker: {:?}, 4 workers: {:?}", one, four);
+    assert_eq!(pooled, single);
+    assert!(four < one);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn detection_handler_stops_only_on_shutdown() {
+    use std::sync::Arc;
+
+    use arc_swap::ArcSwap;
+    use striem_common::SysMessage;
+    use striem_config::StrIEMConfig;
+    use tokio::sync::{RwLock, broadcast};
+
+    use crate::detection::DetectionHandler;
+
+    const CONFIG: &str = r#"
+      detections:
+        paths: rules
+        workers: 1
+      storage:
+        schema: ocsf/schema
+        path: data/ocsf
+    "#;
+    let config = Arc::new(ArcSwap::from_pointee(
+        StrIEMConfig::from_yaml(CONFIG).unwrap(),
+    ));
+    let rules = Arc::new(RwLock::new(numbered_rules(4).await));
+    let events = numbered_events(20, 4);
+
+    let (src_tx, src_rx) = broadcast::channel(8);
+    let (dest_tx, mut dest_rx) = broadcast::channel(256);
+    let (sys_tx, sys_rx) = broadcast::channel(8);
+    let mut handler =
+        DetectionHandler::new(src_rx, dest_tx, rules, Default::default(), None, sys_rx)
+            .with_config(config.clone());
+    let task = tokio::spawn(async move { handler.run().await });
+
+    // number of findings for one batch of `events`
+    let mut detect = async || {
+        src_tx.send(Arc::new(events.clone())).unwrap();
+        let mut findings = 0;
+        for _ in 0..events.len() {
+            findings += tokio::time::timeout(Duration::from_secs(5), dest_rx.recv())
+                .await
+                .unwrap()
+                .unwrap()
+                .len();
+        }
+        findings
+    };
+    assert_eq!(detect().await, 18);
+
+    sys_tx
+        .send(SysMessage::Update(Box::new(serde_json::Map::new())))
+        .unwrap();
+    assert_eq!(detect().await, 18);
+
+    // enable dedup and the worker pool
+    config.store(Arc::new(
+        StrIEMConfig::from_yaml(
+            &CONFIG.replace("workers: 1", "workers: 3\n        dedup_window_secs: 60"),
+        )
+        .unwrap(),
+    ));
+    sys_tx.send(SysMessage::Reload).unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!task.is_finished());
+    // first sighting per rule passes, the rest are folded into its window
+    assert_eq!(detect().await, 4);
+    assert_eq!(detect().await, 0);
+
+    sys_tx.send(SysMessage::Shutdown).unwrap();
+    tokio::time::timeout(Duration::from_secs(5), task)
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[test]
+fn cli_commands() {
+    use clap::Parser;
+
+    use crate::cli::{Cli, Command};
+
+    let cli = Cli::try_parse_from([
+        "striem",
+        "run",
+        "-c",
+        "a.yaml",
+        "--config",
+        "b.yaml",
+        "--listen",
+        "0.0.0.0:7000",
+        "--api-port",
+        "9999",
+    ])
+    .unwrap();
+    assert!(!cli.is_legacy());
+    let Command::Run(args) = cli.command() else {
+        panic!("expected run");
+    };
+    assert_eq!(args.config.files.len(), 2);
+    assert_eq!(args.listen.unwrap().port(), 7000);
+    assert_eq!(args.api_port, Some(9999));
+
+    // bare config files still run StrIEM
+    let cli = Cli::try_parse_from(["striem", "config.yaml"]).unwrap();
+    assert!(cli.is_legacy());
+    assert!(matches!(cli.command(), Command::Run(args) if args.config.files.len() == 1));
+
+    let cli = Cli::try_parse_from(["striem"]).unwrap();
+    assert!(!cli.is_legacy());
+    assert!(matches!(cli.command(), Command::Run(_)));
+
+    let cli = Cli::try_parse_from(["striem", "export-vector-config", "-c", "a.yaml"]).unwrap();
+    assert!(matches!(cli.command(), Command::ExportVectorConfig(_)));
+    assert!(Cli::try_parse_from(["striem", "run", "--api-port", "x"]).is_err());
+
+    let cli = Cli::try_parse_from([
+        "striem",
+        "replay",
+        "--class",
+        "authentication",
+        "--start",
+        "2025-01-01T00:00:00Z",
+        "--rule",
+        "a",
+        "--rule",
+        "b",
+        "--dry-run",
+    ])
+    .unwrap();
+    let Command::Replay(args) = cli.command() else {
+        panic!("expected replay");
+    };
+    assert_eq!(args.class, "authentication");
+    assert_eq!(args.rules, ["a", "b"]);
+    assert!(args.end.is_none() && args.dry_run);
+    assert!(Cli::try_parse_from(["striem", "replay", "--class", "authentication"]).is_err());
+}
+
+#[test]
+fn cli_flags_win_over_configured_ports() {
+    use clap::Parser;
+    use striem_config::{StrIEMConfig, input::Listener};
+
+    use crate::cli::{Cli, Command, overrides};
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("striem.yaml");
+    std::fs::write(
+        &file,
+        r#"
+      input:
+        vector:
+          address: 0.0.0.0:3000
+          port: 3001
+      api:
+        address: 127.0.0.1:8080
+        port: 8081
+      output:
+        vector:
+          url: http://127.0.0.1:6000
+    "#,
+    )
+    .unwrap();
+
+    let cli = Cli::try_parse_from([
+        "striem",
+        "run",
+        "--listen",
+        "0.0.0.0:7000",
+        "--api-port",
+        "9999",
+    ])
+    .unwrap();
+    let Command::Run(args) = cli.command() else {
+        panic!("expected run");
+    };
+    let config = StrIEMConfig::from_multi_file(vec![file.clone()]).unwrap();
+    let config =
+        StrIEMConfig::from_files_with_overrides(vec![file], &overrides(&args, &config)).unwrap();
+    assert_eq!(config.api.host.address().to_string(), "127.0.0.1:9999");
+    let Listener::Vector(listener) = &config.input else {
+        panic!("expected a vector listener");
+    };
+    assert_eq!(listener.cfg.address().to_string(), "0.0.0.0:7000");
+}
+
+#[tokio::test]
+async fn enrichment_adds_locations_and_asset_tags() {
+    use std::path::Path;
+
+    use striem_config::enrichment::{AssetsConfig, EnrichmentConfig, GeoIpConfig};
+
+    use crate::enrich::Enricher;
+
+    // two City records: 81.2.69.0/24 (London) and 216.160.83.0/24 (Milton, WA)
+    let fixture =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("src/tests/fixtures/GeoLite2-City-Test.mmdb");
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("GeoLite2-City.mmdb");
+    let assets = dir.path().join("assets.yaml");
+    std::fs::write(
+        &assets,
+        r#"
+- cidr: 10.0.0.0/8
+  tags:
+    network_zone: internal
+- cidr: 10.20.0.0/16
+  tags:
+    network_zone: pci
+    asset_criticality: high
+"#,
+    )
+    .unwrap();
+    let fields = vec!["*_endpoint.ip".to_string()];
+    let config = EnrichmentConfig {
+        geoip: Some(GeoIpConfig {
+            database: database.clone(),
+            fields: fields.clone(),
+        }),
+        assets: Some(AssetsConfig {
+            path: assets,
+            fields,
+        }),
+    };
+    let event = || {
+        Event::from(json!({
+            "src_endpoint": {"ip": "81.2.69.160"},
+            "dst_endpoint": {"ip": "10.20.1.5", "network_zone": "lab"},
+            "device": {"ip": "10.1.1.1"},
+        }))
+    };
+
+    // no database yet: GeoIP is skipped, the assets still apply
+    let mut enricher = Enricher::new(&config);
+    let mut enriched = event();
+    enricher.enrich(&mut enriched);
+    assert!(enriched.data["src_endpoint"].get("location").is_none());
+    assert_eq!(enriched.data["dst_endpoint"]["asset_criticality"], "high");
+    // set fields are left alone, and other fields aren't looked at
+    assert_eq!(enriched.data["dst_endpoint"]["network_zone"], "lab");
+    assert!(enriched.data["device"].get("network_zone").is_none());
+
+    // the database appears and is picked up on the next check
+    std::fs::copy(&fixture, &database).unwrap();
+    enricher.refresh(true).await;
+    let mut enriched = event();
+    enricher.enrich(&mut enriched);
+    assert_eq!(
+        enriched.data["src_endpoint"]["location"],
+        json!({
+            "city": "London",
+            "country": "GB",
+            "region": "ENG",
+            "lat": 51.5142,
+            "long": -0.0931,
+        })
+    );
+    assert!(enriched.data["dst_endpoint"].get("location").is_none());
+
+    let mut milton = Event::from(json!({"src_endpoint": {"ip": "216.160.83.56"}}));
+    enricher.enrich(&mut milton);
+    assert_eq!(milton.data["src_endpoint"]["location"]["region"], "WA");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn shutdown_stores_events_received_just_before() {
+    use striem_common::SysMessage;
+    use striem_config::StrIEMConfig;
+    use striem_storage::{manifest, reader};
+    use striem_vector::{
+        event::{EventWrapper, event_wrapper::Event as VectorEvent},
+        vector::{PushEventsRequest, vector_client::VectorClient},
+    };
+
+    use crate::app::App;
+
+    const BATCHES: usize = 50;
+    const EVENTS: usize = 20;
+
+    let schemas = tempfile::tempdir().unwrap();
+    let storage = tempfile::tempdir().unwrap();
+    std::fs::write(
+        schemas.path().join("file_activity.parquet"),
+        "message file_activity { optional INT32 class_uid (INTEGER(32, true)); }",
+    )
+    .unwrap();
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let config = StrIEMConfig::from_json(
+        &json!({
+            "db": storage.path(),
+            "input": {"vector": {"address": addr.to_string()}},
+            "api": {"enabled": false},
+            "storage": {"schema": schemas.path(), "path": storage.path()},
+            "shutdown_grace_secs": 10,
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let class_dir = reader::class_dir(config.storage.as_ref().unwrap(), "file_activity").unwrap();
+
+    let mut app = App::new(config).await.unwrap();
+    let sys = app.update_channel();
+    let task = tokio::spawn(async move { app.run().await });
+
+    let mut client = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match VectorClient::connect(format!("http://{}", addr)).await {
+                Ok(client) => return client,
+                Err(_) => tokio::time::sleep(Duration::from_millis(20)).await,
+            }
+        }
+    })
+    .await
+    .unwrap();
+    let batch = || PushEventsRequest {
+        events: (0..EVENTS)
+            .map(|_| EventWrapper {
+                event: Some(VectorEvent::Log(
+                    (&Event::from(json!({"class_uid": 1001}))).into(),
+                )),
+            })
+            .collect(),
+    };
+    for _ in 0..BATCHES {
+        client.push_events(batch()).await.unwrap();
+    }
+
+    // right after the last push is accepted
+    sys.send(SysMessage::Shutdown).unwrap();
+    tokio::time::timeout(Duration::from_secs(15), task)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+
+    // every accepted event is in a finished file
+    let rows = manifest::read(&class_dir)
+        .unwrap()
+        .iter()
+        .map(|entry| entry.rows)
+        .sum::<u64>();
+    assert_eq!(rows, (BATCHES * EVENTS) as u64);
+    // and further pushes are refused
+    assert!(client.push_events(batch()).await.is_err());
+}
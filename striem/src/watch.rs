@@ -0,0 +1,199 @@
+//! Configuration reload when the configuration files change on disk.
+//!
+//! With `watch_config`, the directories holding the loaded configuration
+//! files are watched, so files replaced by a rename or a Kubernetes
+//! ConfigMap update (which swaps a `..data` symlink) are seen too. Changes
+//! less than [`CONFIG_WATCH_DEBOUNCE_MS`] apart are read once. The
+//! configuration is then loaded as at startup and compared with the running
+//! one: changes are applied and announced with [`SysMessage::Reload`], unless
+//! one of them needs a restart, in which case none are.
+//!
+//! On unix, SIGHUP reloads the same way whether or not `watch_config` is
+//! set, and announces the reload even when the configuration is unchanged,
+//! so rules and schemas are read again from disk.
+
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use log::{debug, error, info, warn};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::{broadcast, mpsc};
+
+use striem_common::{SysMessage, prelude::*};
+use striem_config::{StrIEMConfig, reload::requires_restart};
+
+/// Whether `event` touches one of the watched files
+fn is_relevant(event: &notify::Event, names: &HashSet<OsString>) -> bool {
+    if event.kind.is_access() {
+        return false;
+    }
+    event.paths.iter().any(|path| {
+        path.file_name()
+            .is_some_and(|name| names.contains(name) || name.to_string_lossy().starts_with(".."))
+    })
+}
+
+/// Load the configuration again and apply it if it can be; with `always`,
+/// announce the reload even if nothing changed
+pub(crate) async fn reload(
+    config: &ArcSwap<StrIEMConfig>,
+    sys: &broadcast::Sender<SysMessage>,
+    always: bool,
+) {
+    let updated = match crate::config().await {
+        Ok(updated) => updated,
+        Err(e) => {
+            error!(
+                "configuration change not applied, it could not be loaded: {:#}",
+                e
+            );
+            return;
+        }
+    };
+    let changes = match config.load().changes(&updated) {
+        Ok(changes) => changes,
+        Err(e) => {
+            error!("configuration change not applied: {}", e);
+            return;
+        }
+    };
+    if changes.is_empty() && !always {
+        debug!("configuration files changed, configuration didn't");
+        return;
+    }
+
+    let restart: Vec<&String> = changes.iter().filter(|key| requires_restart(key)).collect();
+    if !restart.is_empty() {
+        warn!(
+            "configuration change not applied, these need a restart: {}",
+            restart
+                .iter()
+                .map(|key| key.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        return;
+    }
+
+    if changes.is_empty() {
+        info!("configuration unchanged, reloading");
+    } else {
+        info!("configuration changed: {}", changes.join(", "));
+        config.store(Arc::new(updated));
+    }
+    sys.send(SysMessage::Reload)
+        .inspect_err(|e| error!("failed to broadcast config reload: {}", e))
+        .ok();
+}
+
+/// Watch the directories holding `files`, sending their events to `tx`
+fn watch(
+    files: &[PathBuf],
+    tx: mpsc::UnboundedSender<notify::Event>,
+) -> Result<RecommendedWatcher> {
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) => {
+                tx.send(event).ok();
+            }
+            Err(e) => warn!("config watcher error: {}", e),
+        })?;
+    let dirs: HashSet<&Path> = files
+        .iter()
+        .map(|file| {
+            file.parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(Path::new("."))
+        })
+        .collect();
+    for dir in dirs {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        info!("... watching {} for configuration changes", dir.display());
+    }
+    Ok(watcher)
+}
+
+/// Reload the configuration on each SIGHUP, until shutdown
+#[cfg(unix)]
+pub(crate) async fn hangup(config: Arc<ArcSwap<StrIEMConfig>>, sys: broadcast::Sender<SysMessage>) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            error!("could not handle SIGHUP: {}", e);
+            return;
+        }
+    };
+    let mut shutdown = sys.subscribe();
+    loop {
+        tokio::select! {
+            msg = shutdown.recv() => match msg {
+                Ok(SysMessage::Shutdown) | Err(broadcast::error::RecvError::Closed) => return,
+                _ => continue,
+            },
+            _ = hangups.recv() => {
+                info!("SIGHUP received, reloading configuration");
+                reload(&config, &sys, true).await;
+            }
+        }
+    }
+}
+
+/// Reload the configuration when its files change, until shutdown
+pub(crate) async fn run(config: Arc<ArcSwap<StrIEMConfig>>, sys: broadcast::Sender<SysMessage>) {
+    let files = config.load().files.clone();
+    if files.is_empty() {
+        warn!("watch_config is set, but no configuration files were loaded");
+        return;
+    }
+    let names: HashSet<OsString> = files
+        .iter()
+        .filter_map(|file| file.file_name().map(OsString::from))
+        .collect();
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    // dropping the watcher stops it, so it's held for the life of the task
+    let _watcher = match watch(&files, tx) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            error!("could not watch configuration files: {}", e);
+            return;
+        }
+    };
+
+    let debounce = Duration::from_millis(CONFIG_WATCH_DEBOUNCE_MS);
+    let mut shutdown = sys.subscribe();
+    loop {
+        tokio::select! {
+            msg = shutdown.recv() => match msg {
+                Ok(SysMessage::Shutdown) | Err(broadcast::error::RecvError::Closed) => {
+                    info!("shutting down config file watcher...");
+                    return;
+                }
+                _ => continue,
+            },
+            event = rx.recv() => match event {
+                Some(event) if is_relevant(&event, &names) => {}
+                Some(_) => continue,
+                None => return,
+            },
+        }
+
+        // wait for the burst of events an editor or ConfigMap update makes
+        loop {
+            tokio::select! {
+                event = rx.recv() => if event.is_none() { return },
+                _ = tokio::time::sleep(debounce) => break,
+            }
+        }
+        reload(&config, &sys, false).await;
+    }
+}